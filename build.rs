@@ -18,7 +18,9 @@ fn main() {
 
     generate_varlink_code();
 
-    package_charm_template();
+    package_dir("charm_template", "charm_template.zip");
+    package_dir("charm_templates", "charm_templates.zip");
+    package_dir("charm_examples", "charm_examples.zip");
 }
 
 fn generate_varlink_code() {
@@ -48,25 +50,22 @@ fn generate_varlink_code() {
     output_file.write_all(file_contents.as_bytes()).unwrap();
 }
 
-fn package_charm_template() {
+/// Package a directory in the repo into a ZIP in the `OUT_DIR` for inclusion into the binary
+fn package_dir(dir_name: &str, zip_name: &str) {
     let cargo_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
     let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
 
-    // Package charm template ZIP for inclusion into the binary
-    let charm_template_dir = cargo_dir.join("charm_template");
-    println!(
-        "cargo:rerun-if-changed={}",
-        charm_template_dir.to_str().unwrap()
-    );
-    let charm_template_zip_path = out_dir.join("charm_template.zip");
-    let prefix = &charm_template_dir;
+    let source_dir = cargo_dir.join(dir_name);
+    println!("cargo:rerun-if-changed={}", source_dir.to_str().unwrap());
+    let zip_path = out_dir.join(zip_name);
+    let prefix = &source_dir;
 
-    let file_writer = File::create(&charm_template_zip_path).unwrap();
+    let file_writer = File::create(&zip_path).unwrap();
     let mut zip = zip::ZipWriter::new(file_writer);
     let options =
         zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Bzip2);
 
-    let dir_iter = WalkDir::new(&charm_template_dir).into_iter();
+    let dir_iter = WalkDir::new(&source_dir).into_iter();
 
     let mut buffer = Vec::new();
     for entry in dir_iter {
@@ -76,6 +75,13 @@ fn package_charm_template() {
         let name = path.strip_prefix(Path::new(&prefix)).unwrap();
 
         if path.is_file() {
+            // Keep the file permissions so that scripts stay executable
+            #[cfg(unix)]
+            let options = {
+                use std::os::unix::fs::PermissionsExt;
+                options.unix_permissions(entry.metadata().unwrap().permissions().mode())
+            };
+
             zip.start_file_from_path(name, options).unwrap();
             let mut f = File::open(path).unwrap();
 
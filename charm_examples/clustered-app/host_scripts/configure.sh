@@ -0,0 +1,30 @@
+#!/bin/bash
+
+set -e
+
+lucky set-status maintenance "Configuring Redis"
+
+lucky container image set "$(lucky get-config image)"
+lucky container port remove --all
+lucky container port add 6379:6379
+lucky port open 6379
+
+if [ "$(lucky leader is-leader)" = "true" ]; then
+    # The leader runs the primary and tells the other units where to find it
+    lucky peer set "primary=$(lucky private-address)"
+    lucky container set-command -- redis-server
+
+    lucky set-status active "Primary"
+else
+    primary="$(lucky peer get primary)"
+
+    # Wait for the leader to publish its address
+    if [ "$primary" = "" ]; then
+        lucky set-status waiting "Waiting for primary"
+        exit 0
+    fi
+
+    lucky container set-command -- redis-server --replicaof "$primary" 6379
+
+    lucky set-status active "Replica of $primary"
+fi
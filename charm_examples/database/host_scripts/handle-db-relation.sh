@@ -0,0 +1,14 @@
+#!/bin/bash
+
+set -e
+
+lucky set-status maintenance "Providing database connection"
+
+# Give the related app everything it needs to connect to the database
+lucky relation set \
+    "host=$(lucky private-address)" \
+    "port=5432" \
+    "user=postgres" \
+    "password=$(lucky secret unit get postgres-password)"
+
+lucky set-status active
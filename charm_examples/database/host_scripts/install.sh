@@ -0,0 +1,21 @@
+#!/bin/bash
+
+set -e
+
+lucky set-status maintenance "Configuring PostgreSQL"
+
+# Generate the admin password the first time this runs. Later runs will get the same password.
+password="$(lucky secret unit generate postgres-password)"
+
+lucky container image set "$(lucky get-config image)"
+lucky container env set "POSTGRES_PASSWORD=$password"
+
+# Keep the database files when the container is re-created
+lucky container volume add pgdata /var/lib/postgresql/data
+
+# Make the database reachable by related apps
+lucky container port remove --all
+lucky container port add 5432:5432
+lucky port open 5432
+
+lucky set-status active
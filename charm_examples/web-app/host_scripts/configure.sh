@@ -0,0 +1,20 @@
+#!/bin/bash
+
+set -e
+
+lucky set-status maintenance "Configuring web app"
+
+port="$(lucky get-config port)"
+
+# Update the image in case it was changed in the config
+lucky container image set "$(lucky get-config image)"
+
+# Bind the configured host port to Nginx's port in the container
+lucky container port remove --all
+lucky container port add "$port:80"
+
+# Open the configured port on the firewall
+lucky port close --all
+lucky port open "$port"
+
+lucky set-status active
@@ -0,0 +1,10 @@
+#!/bin/bash
+
+set -e
+
+lucky set-status maintenance "Installing web app"
+
+# Setting the image will cause Lucky to run the container when this script exits
+lucky container image set "$(lucky get-config image)"
+
+lucky set-status active
@@ -2,16 +2,19 @@
 
 set -e
 
+# Load the shell helpers, which wrap the `lucky` commands in functions that quote their arguments
+. "$LUCKY_ENV"
+
 # This gets the current value of one of the configs from config.yaml
-name="$(lucky get-config name)"
+name="$(lucky::config-get name)"
 
-lucky set-status maintenance "Config has been changed! Name: $name"
+lucky::set-status maintenance "Config has been changed! Name: $name"
 
 # Set a container environment variable based on the config.
 # This will cause the container to be stopped, removed, and re-created after this script exits
 # if the container config is not the same as was when the script was started.
-lucky container env set "NAME=$name"
+lucky::container-env NAME "$name"
 
 sleep 5
 
-lucky set-status active
+lucky::set-status active
@@ -0,0 +1,25 @@
+#!/bin/bash
+
+set -e
+
+lucky set-status maintenance "Configuring web app"
+
+port="$(lucky get-config port)"
+
+# Setting the image will cause Lucky to run the container when this script exits
+lucky container image set "$(lucky get-config image)"
+
+# Pass the database connection to the app. The template is rendered every time the container config
+# is applied, so the container is re-created when the database connection changes.
+lucky container env set --template \
+    'DATABASE_URL=postgres://{{relation "db" "user"}}:{{relation "db" "password"}}@{{relation "db" "host"}}:{{relation "db" "port"}}/{{relation "db" "database"}}'
+
+# Bind the configured host port to the app's port in the container
+lucky container port remove --all
+lucky container port add "$port:$(lucky get-config container-port)"
+
+# Open the configured port on the firewall
+lucky port close --all
+lucky port open "$port"
+
+"$(dirname "$0")/update-status.sh"
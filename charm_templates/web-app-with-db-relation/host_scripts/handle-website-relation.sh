@@ -0,0 +1,18 @@
+#!/bin/bash
+
+set -e
+
+if [ "$1" = "join" ]; then
+    # Tell the newly related app where to find us
+    lucky relation set \
+        "hostname=$(lucky private-address)" \
+        "port=$(lucky get-config port)"
+
+elif [ "$1" = "update" ]; then
+    # Update every app related over the website relation
+    for relation_id in $(lucky relation list-ids --relation-name website); do
+        lucky relation set --relation-id "$relation_id" \
+            "hostname=$(lucky private-address)" \
+            "port=$(lucky get-config port)"
+    done
+fi
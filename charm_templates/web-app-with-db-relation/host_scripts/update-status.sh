@@ -0,0 +1,16 @@
+#!/bin/bash
+
+set -e
+
+# The app can't start without a database
+if [ "$LUCKY_HOOK" = "db-relation-broken" ] || [ -z "$(lucky relation list-ids --relation-name db)" ]; then
+    lucky set-status blocked "Waiting for a database relation"
+    exit 0
+fi
+
+# The database sends its connection details once it is ready
+if [ -z "$(echo '{{relation "db" "host"}}' | lucky render-template -)" ]; then
+    lucky set-status waiting "Waiting for the database to be ready"
+else
+    lucky set-status active
+fi
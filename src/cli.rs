@@ -13,8 +13,10 @@ mod util;
 // Subcommands
 mod charm;
 mod client;
+mod completions;
 #[cfg(feature = "daemon")]
 mod daemon;
+mod dev;
 
 /// Run the CLI
 pub fn run() {
@@ -22,6 +24,10 @@ pub fn run() {
 }
 
 fn run_cli() -> anyhow::Result<()> {
+    // Use the settings in the `lucky.toml` files as defaults for the environment variables
+    crate::config::LuckyConfig::load()?.apply_to_env();
+    crate::log::reload_log_filter();
+
     let cli: Box<dyn CliCommand>;
 
     // If there is a specified Lucky context
@@ -40,8 +46,20 @@ fn run_cli() -> anyhow::Result<()> {
         cli = Box::new(LuckyCli);
     }
 
-    // Show doc page if applicable
     let args: Vec<String> = std::env::args().collect();
+
+    // Disable colors and set the log level before anything is printed. The flags are checked
+    // before the args are parsed so that they also apply to the doc pages and to clap's help and
+    // errors.
+    let global_args: Vec<&String> = args.iter().take_while(|x| x.as_str() != "--").collect();
+    if global_args.iter().any(|x| *x == "--no-color") {
+        crate::log::disable_color();
+    }
+    if let Some(level) = get_verbosity(&global_args) {
+        crate::log::set_verbosity(level);
+    }
+
+    // Show doc page if applicable
     let mut args_iter = args.iter();
     args_iter.next(); // Skip first arg, which is the binary name
     cli.handle_doc_flags(args_iter)?;
@@ -54,6 +72,33 @@ fn run_cli() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Get the log level set by the `-v`, `-vv`, and `-q` flags, if any
+fn get_verbosity(args: &[&String]) -> Option<log::LevelFilter> {
+    let mut verbose = 0;
+    let mut quiet = false;
+    for arg in args {
+        if *arg == "--verbose" {
+            verbose += 1;
+        } else if *arg == "--quiet" || *arg == "-q" {
+            quiet = true;
+        // Count the `v`s in short flags such as `-vv`
+        } else if arg.len() > 1
+            && arg.starts_with('-')
+            && !arg.starts_with("--")
+            && arg.chars().skip(1).all(|c| c == 'v')
+        {
+            verbose += arg.len() - 1;
+        }
+    }
+
+    match (quiet, verbose) {
+        (true, _) => Some(log::LevelFilter::Error),
+        (false, 0) => None,
+        (false, 1) => Some(log::LevelFilter::Debug),
+        (false, _) => Some(log::LevelFilter::Trace),
+    }
+}
+
 pub(crate) struct LuckyCli;
 
 impl<'a> CliCommand<'a> for LuckyCli {
@@ -72,6 +117,9 @@ impl<'a> CliCommand<'a> for LuckyCli {
         vec![
             Box::new(charm::CharmSubcommand),
             Box::new(client::ClientSubcommand),
+            Box::new(completions::CompletionsSubcommand),
+            Box::new(dev::DevSubcommand),
+            Box::new(doc::DocSubcommand),
         ]
     }
 
@@ -94,7 +142,7 @@ pub fn doc_gen() {
 
 /// Generate CLI documentation
 fn run_doc_gen() -> anyhow::Result<()> {
-    println!("Starting doc gen");
+    log::info!("Starting doc gen");
 
     let cli = LuckyCli;
     doc::mdbook::generate_docs(
@@ -108,7 +156,7 @@ fn run_doc_gen() -> anyhow::Result<()> {
         .as_ref(),
     )?;
 
-    println!("Doc gen finished");
+    log::info!("Doc gen finished");
 
     Ok(())
 }
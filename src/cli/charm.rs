@@ -2,6 +2,16 @@ use clap::{App, ArgMatches};
 
 mod build;
 mod create;
+mod examples;
+mod fetch_deps;
+mod functest;
+mod import_compose;
+mod lint;
+#[cfg(feature = "daemon")]
+mod run_hook;
+#[cfg(feature = "daemon")]
+mod test;
+mod validate;
 
 use crate::cli::*;
 
@@ -17,10 +27,23 @@ impl<'a> CliCommand<'a> for CharmSubcommand {
     }
 
     fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
-        vec![
+        let mut subcommands: Vec<Box<dyn CliCommand<'a>>> = vec![
             Box::new(build::BuildSubcommand),
             Box::new(create::CreateSubcommand),
-        ]
+            Box::new(examples::ExamplesSubcommand),
+            Box::new(fetch_deps::FetchDepsSubcommand),
+            Box::new(functest::FunctestSubcommand),
+            Box::new(import_compose::ImportComposeSubcommand),
+            Box::new(lint::LintSubcommand),
+            Box::new(validate::ValidateSubcommand),
+        ];
+        // Testing charms requires the daemon
+        #[cfg(feature = "daemon")]
+        subcommands.push(Box::new(run_hook::RunHookSubcommand));
+        #[cfg(feature = "daemon")]
+        subcommands.push(Box::new(test::TestSubcommand));
+
+        subcommands
     }
 
     fn get_doc(&self) -> Option<CliDoc> {
@@ -1,17 +1,31 @@
 use anyhow::Context;
 use clap::{App, Arg, ArgMatches};
+use subprocess::{Exec, Redirection};
 use walkdir::WalkDir;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
 
-use std::fs;
-use std::path::Path;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 
+use super::validate::{validate_charm, Problem, Severity};
 use crate::cli::*;
 use crate::config::load_yaml;
 use crate::types::{
-    juju::{CharmMetadata, JUJU_NORMAL_HOOKS, JUJU_RELATION_HOOKS, JUJU_STORAGE_HOOKS},
+    juju::{
+        CharmMetadata, JujuFeature, JUJU_NORMAL_HOOKS, JUJU_PEBBLE_HOOKS, JUJU_RELATION_HOOKS,
+        JUJU_STORAGE_HOOKS,
+    },
     LuckyMetadata,
 };
 
+/// The directory in the built charm that the hook shims used by `dispatch` are put in
+const DISPATCH_SHIM_DIR: &str = "lucky-hooks";
+/// The URL of the Lucky release archive for a CPU architecture, with `{version}` and `{arch}`
+/// placeholders
+const LUCKY_RELEASE_URL: &str =
+    "https://github.com/katharostech/lucky/releases/download/{version}/lucky-linux-{arch}.tgz";
+
 pub(super) struct BuildSubcommand;
 
 impl<'a> CliCommand<'a> for BuildSubcommand {
@@ -36,7 +50,8 @@ impl<'a> CliCommand<'a> for BuildSubcommand {
                              mostly useful during development. See \"Building With Local Lucky\" in \
                              the doc page.")
                 .long("use-local-lucky")
-                .short('l'));
+                .short('l')
+                .conflicts_with("arch"));
 
         app.arg(Arg::with_name("log_level")
                 .help("The log level to build the charm with")
@@ -48,6 +63,39 @@ impl<'a> CliCommand<'a> for BuildSubcommand {
                 .possible_values(&["trace", "debug", "info", "warn", "error"])
                 .case_insensitive(true)
                 .default_value("debug"))
+            .arg(Arg::with_name("hook_mode")
+                .help("How Juju should run the charm's hooks")
+                .long_help("How Juju should run the charm's hooks. The \"dispatch\" mode only \
+                              works on Juju 2.8 and newer, while the \"legacy\" mode uses a \
+                              hooks directory that works on all Juju versions. The \"both\" mode \
+                              includes both so that Juju will use dispatch when it is supported. \
+                              See \"Hook Modes\" in the doc page.")
+                .long("hook-mode")
+                .short('H')
+                .possible_values(&["both", "dispatch", "legacy"])
+                .default_value("both"))
+            .arg(Arg::with_name("arch")
+                .help("Bundle the Lucky release for this CPU architecture, such as \"x86_64\"")
+                .long_help("Download the Lucky release for this CPU architecture, such as \
+                              \"x86_64\" or \"aarch64\", and bundle it into the charm so that \
+                              Lucky doesn't have to be downloaded when the charm is installed. \
+                              See \"Bundling Lucky\" in the doc page.")
+                .long("arch")
+                .short('a')
+                .takes_value(true))
+            .arg(Arg::with_name("offline")
+                .help("Only use locally cached downloads when building")
+                .long_help("Don't download anything while building. The Lucky release bundled \
+                              with `--arch` is taken from the download cache, which can be filled \
+                              ahead of time with `lucky charm fetch-deps`. See \"Offline Builds\" \
+                              in the doc page.")
+                .long("offline"))
+            .arg(Arg::with_name("package")
+                .help("Also package the built charm into a `.charm` file")
+                .long_help("Also package the built charm into a `charm_name.charm` zip file in the \
+                              build dir that can be deployed with `juju deploy`.")
+                .long("package")
+                .short('p'))
             .arg(Arg::with_name("build_dir")
                 .help("The directory to put the built charm in. Defaults to the \"build\" \
                          directory in the charm_dir.")
@@ -100,14 +148,18 @@ impl<'a> CliCommand<'a> for BuildSubcommand {
         let charm_name = &charm_metadata.name;
         // Get build target dir
         let target_dir = build_dir.join(charm_name);
-        // Load lucky metadata and validate the lucky.yaml file
-        let lucky_metadata = load_yaml::<LuckyMetadata>(&charm_path, "lucky")?;
-        // Validate chron schedules
-        for (schedule, _) in lucky_metadata.cron_jobs {
-            schedule
-                .parse::<cron::Schedule>()
-                .map_err(|_| format_err!("Could not parse cron schedule: {}", schedule))?;
+        // Validate the lucky.yaml file
+        let problems = validate_charm(&charm_path)?;
+        for problem in &problems {
+            match problem.severity {
+                Severity::Error => log::error!("{}", problem),
+                Severity::Warning => log::warn!("{}", problem),
+            }
         }
+        if problems.iter().any(Problem::is_error) {
+            anyhow::bail!("The lucky.yaml is not valid");
+        }
+        let lucky_metadata = load_yaml::<LuckyMetadata>(&charm_path, "lucky")?;
 
         // Clear the target directory
         if target_dir.exists() {
@@ -186,18 +238,29 @@ impl<'a> CliCommand<'a> for BuildSubcommand {
             create_dir_all(&bin_dir)?;
         }
 
-        // Create hook dir
-        let hook_dir = target_dir.join("hooks");
-        if !hook_dir.exists() {
-            create_dir_all(&hook_dir)?;
-        }
-
         // If we are using the local build of Lucky ( only works on linux )
         if args.is_present("use_local_lucky") {
             // Copy in the Lucky executable
             let lucky_path = bin_dir.join("lucky");
             let executable_path = std::env::current_exe()?;
             fs::copy(&executable_path, &lucky_path)?;
+
+        // If we are bundling a Lucky release
+        } else if let Some(arch) = args.value_of("arch") {
+            let release = get_lucky_release(arch, args.is_present("offline"))?;
+            let lucky_path = bin_dir.join("lucky");
+            fs::copy(&release, &lucky_path).context(format!(
+                "Could not copy Lucky executable {:?} to {:?}",
+                release, lucky_path
+            ))?;
+            set_file_mode(&lucky_path, 0o755)?;
+
+        // The charm will need internet access to download Lucky when it is installed
+        } else if args.is_present("offline") {
+            log::warn!(
+                "Lucky is not bundled into the charm, so it will be downloaded when the charm is \
+                installed. Use --arch to bundle it."
+            );
         }
 
         // Add the LXD profile
@@ -208,109 +271,105 @@ impl<'a> CliCommand<'a> for BuildSubcommand {
             include_str!("build/lxd-profile.yaml"),
         )?;
 
-        // Create stop hook
-        let stop_hook_path = hook_dir.join("stop");
-        write_file(
-            &stop_hook_path,
-            &format!(
-                include_str!("build/stop-hook-template.sh"),
-                log_level = log_level
-            ),
-        )?;
-        set_file_mode(&stop_hook_path, 0o755)?;
+        // Collect the names of all of the hooks that the charm can receive
+        let mut hook_names: Vec<String> = JUJU_NORMAL_HOOKS.iter().map(|&x| x.into()).collect();
+        for relation_names in vec![
+            charm_metadata.provides,
+            charm_metadata.requires,
+            charm_metadata.peers,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            for relation_name in relation_names.keys() {
+                for hook_name_template in JUJU_RELATION_HOOKS {
+                    hook_names.push(hook_name_template.replace("{}", relation_name));
+                }
+            }
+        }
+        if let Some(storage_data) = charm_metadata.storage {
+            for storage_name in storage_data.keys() {
+                for hook_name_template in JUJU_STORAGE_HOOKS {
+                    hook_names.push(hook_name_template.replace("{}", storage_name));
+                }
+            }
+        }
 
-        // Create install hook
-        let install_hook_path = hook_dir.join("install");
-        write_file(
-            &install_hook_path,
-            &format!(
-                include_str!("build/install-hook-template.sh"),
-                log_level = log_level,
-                lucky_version = env!("LUCKY_VERSION"),
-            ),
-        )?;
-        set_file_mode(&install_hook_path, 0o755)?;
+        let hook_mode = args
+            .value_of("hook_mode")
+            .expect("Missing required arg `hook_mode`");
+
+        // Kubernetes sidecar charms get hooks for their workload containers, and are always run
+        // with dispatch
+        if let Some(containers) = charm_metadata.containers {
+            if hook_mode == "legacy" {
+                anyhow::bail!(
+                    "Charms with workload containers in their metadata.yaml are Kubernetes \
+                    sidecar charms, which can't be built with the legacy hook mode"
+                );
+            }
 
-        // Create upgrade-charm hook
-        let upgrade_charm_hook_path = hook_dir.join("upgrade-charm");
-        write_file(
-            &upgrade_charm_hook_path,
-            &format!(
-                include_str!("build/upgrade-charm-hook-template.sh"),
-                log_level = log_level
-            ),
-        )?;
-        set_file_mode(&upgrade_charm_hook_path, 0o755)?;
+            for container_name in containers.keys() {
+                for hook_name_template in JUJU_PEBBLE_HOOKS {
+                    hook_names.push(hook_name_template.replace("{}", container_name));
+                }
+            }
+        }
+
+        // Create the legacy hooks dir with a shim for every hook
+        if hook_mode != "dispatch" {
+            let hook_dir = target_dir.join("hooks");
+            create_dir_all(&hook_dir)?;
 
-        // Create normal Juju hooks ( those not specific to a relation or storage )
-        for &hook in JUJU_NORMAL_HOOKS {
-            // Skip the stop and install hooks because we have already created them
-            if hook == "stop" || hook == "install" || hook == "upgrade-charm" {
-                continue;
+            for hook_name in &hook_names {
+                write_hook_shim(&hook_dir, hook_name, log_level)?;
             }
-            let new_hook_path = hook_dir.join(hook);
 
-            // Create hook from template
-            write_file(
-                &new_hook_path,
-                &format!(
-                    include_str!("build/hook-template.sh"),
-                    log_level = log_level,
-                    hook_name = hook
-                ),
-            )?;
-            set_file_mode(&new_hook_path, 0o755)?;
+            // Create the legacy actions dir with a shim for every action
+            if !lucky_metadata.actions.is_empty() {
+                let action_dir = target_dir.join("actions");
+                create_dir_all(&action_dir)?;
+
+                for action_name in lucky_metadata.actions.keys() {
+                    write_action_shim(&action_dir.join(action_name), log_level)?;
+                }
+            }
         }
 
-        // Create relation hooks
-        let create_relation_hook = |relation_name: &String| -> anyhow::Result<()> {
-            for hook_name_template in JUJU_RELATION_HOOKS {
-                let hook_name = hook_name_template.replace("{}", relation_name);
-                let new_hook_path = hook_dir.join(&hook_name);
-
-                // Create hook from template
-                write_file(
-                    &new_hook_path,
-                    &format!(
-                        include_str!("build/hook-template.sh"),
-                        log_level = log_level,
-                        hook_name = hook_name
-                    ),
-                )?;
-                set_file_mode(&new_hook_path, 0o755)?;
+        // Create the dispatch script and the lifecycle hook shims that it runs. Hooks without a
+        // lifecycle shim are run with the generic hook shim.
+        if hook_mode != "legacy" {
+            if hook_mode == "dispatch" {
+                log::warn!(
+                    "Charms built with the dispatch hook mode require Juju {} or newer",
+                    JujuFeature::Dispatch.min_version()
+                );
             }
 
-            Ok(())
-        };
-        if let Some(relation_names) = charm_metadata.provides {
-            relation_names.keys().try_for_each(create_relation_hook)?;
-        }
-        if let Some(relation_names) = charm_metadata.requires {
-            relation_names.keys().try_for_each(create_relation_hook)?;
-        }
-        if let Some(relation_names) = charm_metadata.peers {
-            relation_names.keys().try_for_each(create_relation_hook)?;
-        }
+            let shim_dir = target_dir.join(DISPATCH_SHIM_DIR);
+            create_dir_all(&shim_dir)?;
 
-        // Create hooks for defined storages
-        if let Some(storage_data) = charm_metadata.storage {
-            for storage_name in storage_data.keys() {
-                for hook_name_template in JUJU_STORAGE_HOOKS {
-                    let hook_name = hook_name_template.replace("{}", storage_name);
-                    let new_hook_path = hook_dir.join(&hook_name);
-
-                    // Create hook from template
-                    write_file(
-                        &new_hook_path,
-                        &format!(
-                            include_str!("build/hook-template.sh"),
-                            log_level = log_level,
-                            hook_name = hook_name
-                        ),
-                    )?;
-                    set_file_mode(&new_hook_path, 0o755)?;
-                }
+            for hook_name in &["install", "upgrade-charm", "stop", "hook"] {
+                write_hook_shim(&shim_dir, hook_name, log_level)?;
             }
+            write_action_shim(&shim_dir.join("action"), log_level)?;
+
+            let dispatch_path = target_dir.join("dispatch");
+            write_file(
+                &dispatch_path,
+                &format!(
+                    include_str!("build/dispatch-template.sh"),
+                    shim_dir = DISPATCH_SHIM_DIR
+                ),
+            )?;
+            set_file_mode(&dispatch_path, 0o755)?;
+        }
+
+        // Package the charm for deployment
+        if args.is_present("package") {
+            let charm_file = build_dir.join(format!("{}.charm", charm_name));
+            package_charm(&target_dir, &charm_file)?;
+            log::info!("Packaged charm: {:?}", charm_file);
         }
 
         Ok(data)
@@ -321,6 +380,187 @@ impl<'a> CliCommand<'a> for BuildSubcommand {
 // Helpers
 //
 
+/// Write the hook shim for the given hook into the hook dir
+///
+/// The `install`, `upgrade-charm`, and `stop` hooks get shims that also install, upgrade, and
+/// remove Lucky. All other hooks get the generic hook shim, which gets the hook name from
+/// `JUJU_DISPATCH_PATH` or from its own file name.
+fn write_hook_shim(hook_dir: &Path, hook_name: &str, log_level: &str) -> anyhow::Result<()> {
+    let content = match hook_name {
+        "install" => format!(
+            include_str!("build/install-hook-template.sh"),
+            log_level = log_level,
+            lucky_version = env!("LUCKY_VERSION"),
+        ),
+        "upgrade-charm" => format!(
+            include_str!("build/upgrade-charm-hook-template.sh"),
+            log_level = log_level,
+            lucky_version = env!("LUCKY_VERSION"),
+        ),
+        "stop" => format!(
+            include_str!("build/stop-hook-template.sh"),
+            log_level = log_level
+        ),
+        _ => format!(
+            include_str!("build/hook-template.sh"),
+            log_level = log_level
+        ),
+    };
+
+    let hook_path = hook_dir.join(hook_name);
+    write_file(&hook_path, &content)?;
+    set_file_mode(&hook_path, 0o755)?;
+
+    Ok(())
+}
+
+/// Write the action shim to the given path
+///
+/// The action shim gets the action name from `JUJU_DISPATCH_PATH` or from its own file name.
+fn write_action_shim(path: &Path, log_level: &str) -> anyhow::Result<()> {
+    write_file(
+        path,
+        &format!(
+            include_str!("build/action-template.sh"),
+            log_level = log_level
+        ),
+    )?;
+    set_file_mode(path, 0o755)?;
+
+    Ok(())
+}
+
+/// Get the dir that downloads made when building charms are cached in
+///
+/// This is `LUCKY_CACHE_DIR` if it is set, or the `lucky` dir in the user's cache dir.
+fn get_cache_dir() -> anyhow::Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("LUCKY_CACHE_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+
+    dirs::cache_dir().map(|x| x.join("lucky")).ok_or_else(|| {
+        anyhow::format_err!(
+            "Could not find the user's cache dir. Set LUCKY_CACHE_DIR to the dir to cache \
+            downloads in."
+        )
+    })
+}
+
+/// Get the path to the cached Lucky executable for the given CPU architecture, whether or not it
+/// has been downloaded yet
+pub(super) fn cached_lucky_release_path(arch: &str) -> anyhow::Result<PathBuf> {
+    Ok(get_cache_dir()?
+        .join("releases")
+        .join(env!("LUCKY_VERSION"))
+        .join(arch)
+        .join("lucky"))
+}
+
+/// Get the path to the Lucky executable for the given CPU architecture from the download cache,
+/// downloading it into the cache first if it isn't there and `offline` is `false`
+fn get_lucky_release(arch: &str, offline: bool) -> anyhow::Result<PathBuf> {
+    let path = cached_lucky_release_path(arch)?;
+    if path.exists() {
+        log::info!("Using cached Lucky for {}: {:?}", arch, path);
+        return Ok(path);
+    }
+
+    if offline {
+        anyhow::bail!(
+            "Lucky for the {} architecture is not in the download cache. Run `lucky charm \
+            fetch-deps --arch {}` while online to download it.",
+            arch,
+            arch
+        );
+    }
+
+    download_lucky_release(arch, &path)?;
+    Ok(path)
+}
+
+/// Download the Lucky release for the given CPU architecture and write the executable to the given
+/// path
+pub(super) fn download_lucky_release(arch: &str, path: &Path) -> anyhow::Result<()> {
+    let url = LUCKY_RELEASE_URL
+        .replace("{version}", env!("LUCKY_VERSION"))
+        .replace("{arch}", arch);
+    log::info!("Downloading Lucky for {}: {}", arch, url);
+
+    let capture = (Exec::cmd("curl").args(&["-fsSL", &url]) | Exec::cmd("tar").arg("-xzO"))
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .context("Could not run curl")?;
+    if !capture.success() || capture.stdout.is_empty() {
+        anyhow::bail!(
+            "Could not download Lucky for the {} architecture from {}: {}",
+            arch,
+            url,
+            capture.stdout_str().trim()
+        );
+    }
+
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    // Write to a temporary file first so that an interrupted write isn't mistaken for a download
+    let partial_path = path.with_extension("partial");
+    fs::write(&partial_path, &capture.stdout).context(format!(
+        "Could not write Lucky executable: {:?}",
+        partial_path
+    ))?;
+    set_file_mode(&partial_path, 0o755)?;
+    fs::rename(&partial_path, path)
+        .context(format!("Could not write Lucky executable: {:?}", path))?;
+
+    Ok(())
+}
+
+/// Zip up the built charm so that it can be deployed with `juju deploy`
+///
+/// The files are stored without compression because Juju can't read the bzip2 compression that
+/// Lucky is built with.
+fn package_charm(charm_dir: &Path, charm_file: &Path) -> anyhow::Result<()> {
+    let file = File::create(charm_file)
+        .context(format!("Could not create charm file: {:?}", charm_file))?;
+    let mut zip = ZipWriter::new(file);
+    let zip_error_message = format!("Could not write charm file: {:?}", charm_file);
+
+    for entry in WalkDir::new(charm_dir)
+        .min_depth(1)
+        .sort_by(|a, b| a.path().cmp(b.path()))
+    {
+        let entry = entry?;
+        let relative_path = entry
+            .path()
+            .strip_prefix(charm_dir)
+            .expect("Internal error parsing build paths");
+        // Zip paths always use forward slashes
+        let name = relative_path
+            .components()
+            .map(|x| x.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .unix_permissions(file_mode(entry.path())?);
+
+        if entry.file_type().is_dir() {
+            zip.add_directory(name, options)
+                .context(zip_error_message.clone())?;
+        } else {
+            zip.start_file(name, options)
+                .context(zip_error_message.clone())?;
+            let content = fs::read(entry.path())
+                .context(format!("Could not read file: {:?}", entry.path()))?;
+            zip.write_all(&content).context(zip_error_message.clone())?;
+        }
+    }
+    zip.finish().context(zip_error_message)?;
+
+    Ok(())
+}
+
 /// `fs::write` with extra error context
 fn write_file(path: &Path, content: &str) -> anyhow::Result<()> {
     fs::write(&path, content).context(format!("Could not write file: {:?}", &path))?;
@@ -333,6 +573,23 @@ fn create_dir_all(path: &Path) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Gets the file permission mode on Unix, or a default mode on other systems
+fn file_mode(path: &Path) -> anyhow::Result<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata =
+            fs::metadata(path).context(format!("Could not read file metadata: {:?}", path))?;
+        Ok(metadata.permissions().mode() & 0o777)
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(0o644)
+    }
+}
+
 /// Sets file permission mode on Unix with extra error context
 fn set_file_mode(path: &Path, mode: u32) -> anyhow::Result<()> {
     #[cfg(unix)]
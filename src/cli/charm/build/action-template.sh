@@ -0,0 +1,29 @@
+#!/bin/bash
+set -e # Exit immediately if a command fails
+
+# Set the lucky log level
+export LUCKY_LOG_LEVEL={log_level}
+
+# If log level is set to "trace"
+if [ "$(echo $LUCKY_LOG_LEVEL | awk '{{print tolower($0)}}')" = "trace" ]; then
+    set -x # Print out bash commands as they are executed
+fi
+
+# Get the action name from the dispatch path when run by the `dispatch` script, or from the name of
+# this script when run from the actions dir
+action_name="$(basename "${{JUJU_DISPATCH_PATH:-$0}}")"
+
+# Replace "/" with "_" in unit name
+unit_name=$(echo $JUJU_UNIT_NAME | sed 's/\//_/' )
+log_dir="/var/log/lucky"
+mkdir -p $log_dir
+lucky_data_dir="/var/lib/lucky/$unit_name"
+
+# The lucky executable
+lucky="$lucky_data_dir/bin/lucky"
+
+# Start the Lucky daemon
+LUCKY_CONTEXT=daemon $lucky start --ignore-already-running --log-file "$log_dir/$unit_name.log"
+
+# Trigger the action. We exec the trigger so that Juju can cancel the action by killing it.
+LUCKY_CONTEXT=daemon exec $lucky trigger-action "$action_name"
\ No newline at end of file
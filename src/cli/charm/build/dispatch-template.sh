@@ -0,0 +1,24 @@
+#!/bin/bash
+set -e # Exit immediately if a command fails
+
+# Juju 2.8 and newer run this script for every hook instead of the scripts in the hooks dir. The
+# hook that is being run is passed in `JUJU_DISPATCH_PATH`, such as `hooks/install`.
+shim_dir="$(dirname "$0")/{shim_dir}"
+case "$JUJU_DISPATCH_PATH" in
+    # Lucky tears down the workload and removes itself in the `stop` hook, which Juju runs right
+    # before `remove`, so there is nothing left to do
+    hooks/remove) exit 0 ;;
+    hooks/*) ;;
+    # Actions are all run with the action shim
+    actions/*) exec "$shim_dir/action" ;;
+    # Lucky doesn't handle anything else
+    *) exit 0 ;;
+esac
+hook_name="${{JUJU_DISPATCH_PATH#hooks/}}"
+
+# Run the shim for the hook if it has one, otherwise run the generic hook shim
+if [ -x "$shim_dir/$hook_name" ]; then
+    exec "$shim_dir/$hook_name"
+else
+    exec "$shim_dir/hook"
+fi
\ No newline at end of file
@@ -9,6 +9,10 @@ if [ "$(echo $LUCKY_LOG_LEVEL | awk '{{print tolower($0)}}')" = "trace" ]; then
     set -x # Print out bash commands as they are executed
 fi
 
+# Get the hook name from the dispatch path when run by the `dispatch` script, or from the name of
+# this script when run from the hooks dir
+hook_name="$(basename "${{JUJU_DISPATCH_PATH:-$0}}")"
+
 # Replace "/" with "_" in unit name
 unit_name=$(echo $JUJU_UNIT_NAME | sed 's/\//_/' )
 log_dir="/var/log/lucky"
@@ -21,5 +25,5 @@ lucky="$lucky_data_dir/bin/lucky"
 # Start the Lucky daemon
 LUCKY_CONTEXT=daemon $lucky start --ignore-already-running --log-file "$log_dir/$unit_name.log"
 
-# Trigger the `{hook_name}` hook
-LUCKY_CONTEXT=daemon $lucky trigger-hook {hook_name}
\ No newline at end of file
+# Trigger the hook
+LUCKY_CONTEXT=daemon $lucky trigger-hook "$hook_name"
\ No newline at end of file
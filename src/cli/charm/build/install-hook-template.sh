@@ -22,13 +22,11 @@ lucky=$bin_dir/lucky
 # If Lucky was not bundled
 if [ ! -f ./bin/lucky ]; then
     lucky="$bin_dir/lucky"
-    # Install the latest Lucky pre-release
-    # TODO: Allow specifying a specific version of Lucky to install
-    # TODO: Add checks for CPU architecture when downloading
+    # Install the version of Lucky that the charm was built with
     if [ ! -f $lucky ]; then
         mkdir -p $bin_dir
         curl -L \
-            https://github.com/katharostech/lucky/releases/download/{lucky_version}/lucky-linux-x86_64.tgz \
+            https://github.com/katharostech/lucky/releases/download/{lucky_version}/lucky-linux-$(uname -m).tgz \
             | tar -xzO > $lucky
     fi
     chmod +x $lucky
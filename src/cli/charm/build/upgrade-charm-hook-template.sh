@@ -27,12 +27,10 @@ if [ ! -f ./bin/lucky ]; then
     # Remove previous version of Lucky
     rm -f $lucky
 
-    # Install the latest Lucky pre-release
-    # TODO: Allow specifying a specific version of Lucky to install
-    # TODO: Add checks for CPU architecture when downloading
+    # Install the version of Lucky that the charm was built with
     mkdir -p $bin_dir
     curl -L \
-        https://github.com/katharostech/lucky/releases/download/pre-release/lucky-linux-x86_64.tgz \
+        https://github.com/katharostech/lucky/releases/download/{lucky_version}/lucky-linux-$(uname -m).tgz \
         | tar -xzO > $lucky
     chmod +x $lucky
 
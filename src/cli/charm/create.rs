@@ -1,18 +1,79 @@
 use std::ffi::OsStr;
 use std::fs;
-use std::io;
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 
-use anyhow::Context;
+use anyhow::{format_err, Context};
 use clap::{App, Arg, ArgMatches};
 use handlebars::Handlebars;
 use rprompt::prompt_reply_stdout;
 use serde::Serialize;
+use subprocess::{Exec, Redirection};
+use walkdir::WalkDir;
 
 /// Zip archive data for the charm template
 pub(crate) const CHARM_TEMPLATE_ARCHIVE: &[u8] =
     include_bytes!(concat!(env!("OUT_DIR"), "/charm_template.zip"));
 
+/// Zip archive data for the other built-in charm templates, with one directory per template
+const CHARM_TEMPLATES_ARCHIVE: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/charm_templates.zip"));
+
+/// The template that is used when no template is given
+const DEFAULT_TEMPLATE: &str = "default";
+
+/// A charm template that is bundled into the Lucky binary
+struct BuiltinTemplate {
+    /// The name used to select the template
+    name: &'static str,
+    /// A short description of the template
+    description: &'static str,
+    /// The zip archive that the template is in
+    archive: &'static [u8],
+    /// The directory of the template in the archive, or an empty string if the template is the
+    /// whole archive
+    dir: &'static str,
+}
+
+/// The built-in charm templates
+const TEMPLATES: &[BuiltinTemplate] = &[
+    BuiltinTemplate {
+        name: DEFAULT_TEMPLATE,
+        description: "A starter charm with every lucky.yaml option documented in comments",
+        archive: CHARM_TEMPLATE_ARCHIVE,
+        dir: "",
+    },
+    BuiltinTemplate {
+        name: "simple-container",
+        description: "A charm that runs one container configured from the charm config",
+        archive: CHARM_TEMPLATES_ARCHIVE,
+        dir: "simple-container",
+    },
+    BuiltinTemplate {
+        name: "web-app-with-db-relation",
+        description: "A web app container that gets its database from a pgsql relation",
+        archive: CHARM_TEMPLATES_ARCHIVE,
+        dir: "web-app-with-db-relation",
+    },
+    BuiltinTemplate {
+        name: "k8s-sidecar",
+        description: "A Kubernetes sidecar charm that runs its app with Pebble",
+        archive: CHARM_TEMPLATES_ARCHIVE,
+        dir: "k8s-sidecar",
+    },
+];
+
+/// Where to get the charm template from
+enum TemplateSource<'a> {
+    Builtin(&'a BuiltinTemplate),
+    Git {
+        /// The URL of the git repository
+        repository: &'a str,
+        /// The path to the template inside of the repository
+        path: Option<&'a str>,
+    },
+}
+
 #[derive(Serialize)]
 /// The input data to the charm template
 struct TemplateData {
@@ -20,6 +81,16 @@ struct TemplateData {
     pub charm_name: String,
     pub charm_summary: String,
     pub charm_maintainer: String,
+    /// The container image that the charm runs
+    pub container_image: String,
+    /// The port that the app listens on
+    pub port: u16,
+    /// Other ports that the app listens on, which are exposed on the same port of the host
+    pub extra_ports: Vec<u16>,
+    /// The relations that the charm provides
+    pub provides: Vec<TemplateRelation>,
+    /// The relations that the charm requires
+    pub requires: Vec<TemplateRelation>,
 }
 
 impl Default for TemplateData {
@@ -29,12 +100,26 @@ impl Default for TemplateData {
             charm_name: String::from("my_app"),
             charm_summary: String::from("A short summary of my app."),
             charm_maintainer: String::from("John Doe <johndoe@emailprovider.com>"),
+            container_image: String::from("nginx:1.19-alpine"),
+            port: 80,
+            extra_ports: vec![],
+            provides: vec![],
+            requires: vec![],
         }
     }
 }
 
+#[derive(Serialize)]
+/// A relation in the charm metadata
+struct TemplateRelation {
+    pub name: String,
+    pub interface: String,
+}
+
 use crate::cli::*;
 
+mod wizard;
+
 pub(super) struct CreateSubcommand;
 
 impl<'a> CliCommand<'a> for CreateSubcommand {
@@ -45,9 +130,24 @@ impl<'a> CliCommand<'a> for CreateSubcommand {
     #[rustfmt::skip]
     fn get_app(&self) -> App<'a> {
         self.get_base_app()
+            // Running without args on a terminal starts the interactive wizard
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
             .about("Create a new lucky charm")
             .arg(Arg::with_name("target_dir")
                 .help("The directory to create the charm in"))
+            .arg(Arg::with_name("template")
+                .long("template")
+                .short('t')
+                .help("The template to use: a built-in template name or a git repository URL")
+                .long_help(concat!(
+                    "The built-in template to use, or the URL of a git repository to get the ",
+                    "template from. A path to the template in the repository can be added to the ",
+                    "URL after a `#`. Use `--list-templates` to see the built-in templates."
+                ))
+                .default_value(DEFAULT_TEMPLATE))
+            .arg(Arg::with_name("list_templates")
+                .long("list-templates")
+                .help("List the built-in templates and exit"))
             .arg(Arg::with_name("use_defaults")
                 .long("use-defaults")
                 .short('D')
@@ -86,19 +186,72 @@ impl<'a> CliCommand<'a> for CreateSubcommand {
     }
 
     fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        if args.is_present("list_templates") {
+            let name_width = TEMPLATES.iter().map(|x| x.name.len()).max().unwrap_or(0);
+            for template in TEMPLATES {
+                writeln!(
+                    io::stdout(),
+                    "{:width$}  {}",
+                    template.name,
+                    template.description,
+                    width = name_width
+                )?;
+            }
+            return Ok(data);
+        }
+
+        // Create handlebars tempate engine
+        let mut handlebars = Handlebars::new();
+        // Clear the escape handler
+        handlebars.register_escape_fn(handlebars::no_escape);
+
+        // Walk the user through creating the charm if no args were given on a terminal
+        let no_args = args.occurrences_of("template") == 0
+            && [
+                "target_dir",
+                "use_defaults",
+                "charm_name",
+                "display_name",
+                "charm_summary",
+                "charm_maintainer",
+            ]
+            .iter()
+            .all(|x| !args.is_present(x));
+        if no_args && atty::is(atty::Stream::Stdin) && atty::is(atty::Stream::Stdout) {
+            wizard::run(&handlebars)?;
+            return Ok(data);
+        }
+
         // Make sure target directory doesn't already exist
         let target_dir = Path::new(
             args.value_of("target_dir")
-                .expect("Missing required argument: target_dir"),
+                .ok_or_else(|| format_err!("The target_dir argument is required"))?,
         );
         if target_dir.exists() {
             anyhow::bail!("Target directory already exists");
         }
 
-        // Create handlebars tempate engine
-        let mut handlebars = Handlebars::new();
-        // Clear the escape handler
-        handlebars.register_escape_fn(handlebars::no_escape);
+        // Find the template before prompting for the template settings
+        let template = args
+            .value_of("template")
+            .expect("Missing required argument: template");
+        let template_source = if is_git_url(template) {
+            let mut parts = template.splitn(2, '#');
+            TemplateSource::Git {
+                repository: parts.next().unwrap_or(template),
+                path: parts.next().filter(|x| !x.is_empty()),
+            }
+        } else {
+            TemplateSource::Builtin(TEMPLATES.iter().find(|x| x.name == template).ok_or_else(
+                || {
+                    format_err!(
+                        "Unknown template {:?}. Run `lucky charm create --list-templates` to see \
+                        the built-in templates.",
+                        template
+                    )
+                },
+            )?)
+        };
 
         // Initialize template settings
         let mut template_settings = TemplateData::default();
@@ -198,88 +351,243 @@ impl<'a> CliCommand<'a> for CreateSubcommand {
             }
         }
 
-        // Create the zip reader from the embeded charm template archive
-        let zip_reader = std::io::Cursor::new(CHARM_TEMPLATE_ARCHIVE);
-        let zip_error_message = "Internal error: problem reading embedded charm template zip";
-        let mut zip = zip::ZipArchive::new(zip_reader).context(zip_error_message)?;
-
-        // Iterate through the items in the zip
-        for i in 0..zip.len() {
-            let mut file = zip.by_index(i).context(zip_error_message)?;
-            let mut outpath = PathBuf::from(
-                args.value_of("target_dir")
-                    .expect("missing required argument `target_dir`"),
-            );
-            outpath.push(file.sanitized_name());
-
-            // If file entry is a directory
-            if file.name().ends_with('/') {
-                // Create a directory
+        match template_source {
+            TemplateSource::Builtin(template) => {
+                create_from_builtin(template, target_dir, &handlebars, &template_settings)?
+            }
+            TemplateSource::Git { repository, path } => create_from_git(
+                repository,
+                path,
+                target_dir,
+                &handlebars,
+                &template_settings,
+            )?,
+        }
+
+        Ok(data)
+    }
+}
+
+/// Whether or not a template argument is the URL of a git repository instead of a template name
+fn is_git_url(template: &str) -> bool {
+    template.contains("://") || template.starts_with("git@") || template.ends_with(".git")
+}
+
+/// Create a charm from one of the templates bundled into the Lucky binary
+fn create_from_builtin(
+    template: &BuiltinTemplate,
+    target_dir: &Path,
+    handlebars: &Handlebars,
+    template_settings: &TemplateData,
+) -> anyhow::Result<()> {
+    // Create the zip reader from the embeded charm template archive
+    let zip_reader = io::Cursor::new(template.archive);
+    let zip_error_message = "Internal error: problem reading embedded charm template zip";
+    let mut zip = zip::ZipArchive::new(zip_reader).context(zip_error_message)?;
+
+    // Iterate through the items in the zip
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i).context(zip_error_message)?;
+
+        // Skip files that are not a part of this template
+        let sanitized_name = file.sanitized_name();
+        let relative_path = match sanitized_name.strip_prefix(template.dir) {
+            Ok(path) => path.to_path_buf(),
+            Err(_) => continue,
+        };
+
+        // If file entry is a directory
+        if file.name().ends_with('/') {
+            let outpath =
+                target_dir.join(render_path(handlebars, template_settings, &relative_path)?);
+            fs::create_dir_all(&outpath)
+                .context(format!("Could not create directory: {:?}", outpath))?;
+
+        // If it is a file
+        } else {
+            let mode = file.unix_mode();
+            write_template_file(
+                handlebars,
+                template_settings,
+                target_dir,
+                &relative_path,
+                &mut file,
+                mode,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Create a charm from a template in a git repository
+fn create_from_git(
+    repository: &str,
+    path: Option<&str>,
+    target_dir: &Path,
+    handlebars: &Handlebars,
+    template_settings: &TemplateData,
+) -> anyhow::Result<()> {
+    let clone_dir =
+        std::env::temp_dir().join(format!("lucky-charm-template-{}", std::process::id()));
+
+    writeln!(
+        io::stderr(),
+        "Downloading charm template from {}",
+        repository
+    )?;
+    let clone_result = Exec::cmd("git")
+        .args(&["clone", "--depth", "1", "--quiet", repository])
+        .arg(&clone_dir)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .context("Could not run `git`. Make sure git is installed to use a template repository")?;
+    if !clone_result.exit_status.success() {
+        fs::remove_dir_all(&clone_dir).ok();
+        anyhow::bail!(
+            "Could not clone {}:\n{}",
+            repository,
+            clone_result.stdout_str()
+        );
+    }
+
+    let template_dir = match path {
+        Some(path) => clone_dir.join(path),
+        None => clone_dir.clone(),
+    };
+    let result = (|| -> anyhow::Result<()> {
+        if !template_dir.is_dir() {
+            anyhow::bail!("Template not found in repository: {}", path.unwrap_or(""));
+        }
+
+        for entry in WalkDir::new(&template_dir)
+            .into_iter()
+            .filter_entry(|e| e.file_name() != ".git")
+        {
+            let entry = entry?;
+            let relative_path = entry.path().strip_prefix(&template_dir)?;
+
+            if entry.file_type().is_dir() {
+                let outpath =
+                    target_dir.join(render_path(handlebars, template_settings, relative_path)?);
                 fs::create_dir_all(&outpath)
                     .context(format!("Could not create directory: {:?}", outpath))?;
-
-            // If it is a file
             } else {
-                // If the file has a parent
-                if let Some(p) = outpath.parent() {
-                    // If the parent doesn't exist yet
-                    if !p.exists() {
-                        // Create the parent directories
-                        fs::create_dir_all(&p)
-                            .context(format!("Could not create directory: {:?}", p))?;
-                    }
-                }
-
-                // If the file is a handlebars template
-                if file.name().ends_with(".hbs") {
-                    // Strip the `.hbs` extension from the output file path
-                    let panic_message = "Internal error parsing embedded template filename";
-                    outpath = PathBuf::from(
-                        &outpath
-                            .to_str()
-                            .expect(panic_message)
-                            .rsplitn(2, ".hbs")
-                            .nth(1)
-                            .expect(panic_message),
-                    );
-
-                    // Render the template to the output file
-                    let mut outfile = fs::File::create(&outpath).context(format!(
-                        "Could not create file for charm template: {:?}",
-                        outpath
-                    ))?;
-                    handlebars
-                        .render_template_source_to_write(
-                            &mut file,
-                            &template_settings,
-                            &mut outfile,
-                        )
-                        .context(format!("Could not render template to file: {:?}", outfile))?;
-
-                // If it is a normal file
-                } else {
-                    // Create file and write contents
-                    let mut outfile = fs::File::create(&outpath)
-                        .context(format!("Could not create file: {:?}", outpath))?;
-                    io::copy(&mut file, &mut outfile)
-                        .context(format!("Could not write to file: {:?}", outpath))?;
-                }
+                let mut file = fs::File::open(entry.path())
+                    .context(format!("Could not open file: {:?}", entry.path()))?;
+                write_template_file(
+                    handlebars,
+                    template_settings,
+                    target_dir,
+                    relative_path,
+                    &mut file,
+                    file_mode(&entry)?,
+                )?;
             }
+        }
 
-            // If we are on a unix system
-            #[cfg(unix)]
-            {
-                use std::os::unix::fs::PermissionsExt;
-                // If there is a mode set for the file in the zip
-                if let Some(mode) = file.unix_mode() {
-                    // Set ther permissions on the created file
-                    fs::set_permissions(&outpath, fs::Permissions::from_mode(mode)).context(
-                        format!("Could not set permissions on created file: {:?}", &outpath),
-                    )?;
-                }
-            }
+        Ok(())
+    })();
+
+    // Clean up the clone
+    fs::remove_dir_all(&clone_dir).ok();
+
+    result
+}
+
+/// Write a file from a charm template into the new charm, rendering it if it is a Handlebars
+/// template
+fn write_template_file(
+    handlebars: &Handlebars,
+    template_settings: &TemplateData,
+    target_dir: &Path,
+    relative_path: &Path,
+    file: &mut impl Read,
+    mode: Option<u32>,
+) -> anyhow::Result<()> {
+    let mut outpath = target_dir.join(render_path(handlebars, template_settings, relative_path)?);
+
+    // If the file has a parent
+    if let Some(p) = outpath.parent() {
+        // If the parent doesn't exist yet
+        if !p.exists() {
+            // Create the parent directories
+            fs::create_dir_all(&p).context(format!("Could not create directory: {:?}", p))?;
         }
+    }
 
-        Ok(data)
+    // If the file is a handlebars template
+    if outpath.extension() == Some(OsStr::new("hbs")) {
+        // Strip the `.hbs` extension from the output file path
+        outpath.set_extension("");
+
+        // Render the template to the output file
+        let mut outfile = fs::File::create(&outpath).context(format!(
+            "Could not create file for charm template: {:?}",
+            outpath
+        ))?;
+        handlebars
+            .render_template_source_to_write(file, template_settings, &mut outfile)
+            .context(format!("Could not render template to file: {:?}", outfile))?;
+
+    // If it is a normal file
+    } else {
+        // Create file and write contents
+        let mut outfile =
+            fs::File::create(&outpath).context(format!("Could not create file: {:?}", outpath))?;
+        io::copy(file, &mut outfile).context(format!("Could not write to file: {:?}", outpath))?;
+    }
+
+    // If we are on a unix system
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        // If there is a mode set for the file
+        if let Some(mode) = mode {
+            // Set ther permissions on the created file
+            fs::set_permissions(&outpath, fs::Permissions::from_mode(mode)).context(format!(
+                "Could not set permissions on created file: {:?}",
+                &outpath
+            ))?;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+
+    Ok(())
+}
+
+/// Render the template settings into a path from a charm template, so that files can be named
+/// after the charm, such as `{{charm_name}}.service`
+fn render_path(
+    handlebars: &Handlebars,
+    template_settings: &TemplateData,
+    path: &Path,
+) -> anyhow::Result<PathBuf> {
+    let path = path.to_string_lossy();
+    if !path.contains("{{") {
+        return Ok(PathBuf::from(path.as_ref()));
+    }
+
+    Ok(PathBuf::from(
+        handlebars
+            .render_template(&path, template_settings)
+            .context(format!("Could not render template file name: {:?}", path))?,
+    ))
+}
+
+/// Get the permission mode of a file in a template repository on Unix
+fn file_mode(entry: &walkdir::DirEntry) -> anyhow::Result<Option<u32>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Ok(Some(entry.metadata()?.permissions().mode()))
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = entry;
+        Ok(None)
     }
 }
@@ -0,0 +1,241 @@
+//! The interactive wizard that `lucky charm create` runs when it is given no args on a terminal
+
+use anyhow::Context;
+use handlebars::Handlebars;
+use rprompt::prompt_reply_stdout;
+use walkdir::WalkDir;
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::{create_from_builtin, TemplateData, TemplateRelation, TEMPLATES};
+
+/// The template that the wizard creates the charm from
+const WIZARD_TEMPLATE: &str = "simple-container";
+
+/// The files that are shown in full in the preview of the charm
+const PREVIEW_FILES: &[&str] = &["metadata.yaml", "config.yaml"];
+
+/// Prompt for the charm settings, show a preview of the generated files, and create the charm if
+/// the user accepts it
+pub(super) fn run(handlebars: &Handlebars) -> anyhow::Result<()> {
+    let template = TEMPLATES
+        .iter()
+        .find(|x| x.name == WIZARD_TEMPLATE)
+        .expect("Missing charm template for the wizard");
+
+    writeln!(
+        io::stdout(),
+        "Create a new Lucky charm that runs a container. Press Enter to use the value in brackets.\n"
+    )?;
+
+    let mut settings = TemplateData::default();
+    settings.charm_display_name = prompt("Display name", &settings.charm_display_name, |_| Ok(()))?;
+    settings.charm_name = prompt(
+        "Charm name",
+        &settings.charm_display_name.replace(' ', "-").to_lowercase(),
+        validate_name,
+    )?;
+    settings.charm_summary = prompt("Charm summary", &settings.charm_summary, |_| Ok(()))?;
+    settings.charm_maintainer = prompt("Charm maintainer", &settings.charm_maintainer, |_| Ok(()))?;
+    settings.container_image =
+        prompt("Container image", &settings.container_image, validate_image)?;
+
+    let ports = parse_ports(&prompt(
+        "Ports that the app listens on, separated by commas",
+        &settings.port.to_string(),
+        |x| parse_ports(x).map(|_| ()),
+    )?)
+    .map_err(|e| anyhow::format_err!(e))?;
+    let mut ports = ports.into_iter();
+    settings.port = ports.next().unwrap_or(settings.port);
+    settings.extra_ports = ports.collect();
+
+    settings.provides = parse_relations(&prompt(
+        "Relations that the charm provides, as name:interface pairs separated by commas",
+        "",
+        |x| parse_relations(x).map(|_| ()),
+    )?)
+    .map_err(|e| anyhow::format_err!(e))?;
+    settings.requires = parse_relations(&prompt(
+        "Relations that the charm requires, as name:interface pairs separated by commas",
+        "",
+        |x| parse_relations(x).map(|_| ()),
+    )?)
+    .map_err(|e| anyhow::format_err!(e))?;
+
+    let target_dir = PathBuf::from(prompt(
+        "Directory to create the charm in",
+        &settings.charm_name,
+        |x| {
+            if Path::new(x).exists() {
+                Err(format!("{} already exists", x))
+            } else {
+                Ok(())
+            }
+        },
+    )?);
+
+    // Render the charm into a temporary dir to show the preview
+    let preview_dir =
+        std::env::temp_dir().join(format!("lucky-charm-preview-{}", std::process::id()));
+    let result = (|| -> anyhow::Result<bool> {
+        create_from_builtin(template, &preview_dir, handlebars, &settings)?;
+        show_preview(&preview_dir, &target_dir)?;
+
+        let response = prompt_reply_stdout("\nCreate the charm? [Y/n]: ")
+            .context("Could not prompt for confirmation")?;
+        Ok(["", "y", "yes"].contains(&response.trim().to_lowercase().as_str()))
+    })();
+
+    // Clean up the preview
+    fs::remove_dir_all(&preview_dir).ok();
+
+    if result? {
+        create_from_builtin(template, &target_dir, handlebars, &settings)?;
+        writeln!(io::stdout(), "Created charm in {}", target_dir.display())?;
+    } else {
+        writeln!(io::stdout(), "Charm not created")?;
+    }
+
+    Ok(())
+}
+
+/// Prompt for a value until the user gives a valid one, using the default if the reply is empty
+fn prompt(
+    question: &str,
+    default: &str,
+    validate: impl Fn(&str) -> Result<(), String>,
+) -> anyhow::Result<String> {
+    let message = if default.is_empty() {
+        format!("{}: ", question)
+    } else {
+        format!("{} [{}]: ", question, default)
+    };
+
+    loop {
+        let response =
+            prompt_reply_stdout(&message).context(format!("Could not prompt for: {}", question))?;
+        let value = match response.trim() {
+            "" => default,
+            value => value,
+        };
+
+        match validate(value) {
+            Ok(()) => return Ok(value.into()),
+            Err(e) => writeln!(io::stdout(), "  {}", e)?,
+        }
+    }
+}
+
+/// Check that a charm, relation, or interface name is lowercase letters, numbers, and dashes,
+/// starting with a letter
+fn validate_name(name: &str) -> Result<(), String> {
+    let valid = name.starts_with(|c: char| c.is_ascii_lowercase())
+        && !name.ends_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid name \"{}\": names must be lowercase letters, numbers, and dashes, starting \
+            with a letter",
+            name
+        ))
+    }
+}
+
+/// Check that a container image name isn't empty and doesn't contain spaces
+fn validate_image(image: &str) -> Result<(), String> {
+    if image.is_empty() || image.contains(char::is_whitespace) {
+        Err(format!("Invalid container image \"{}\"", image))
+    } else {
+        Ok(())
+    }
+}
+
+/// Parse a comma separated list of at least one port
+fn parse_ports(ports: &str) -> Result<Vec<u16>, String> {
+    let ports = ports
+        .split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|x| match x.parse::<u16>() {
+            Ok(port) if port > 0 => Ok(port),
+            _ => Err(format!("Invalid port \"{}\"", x)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if ports.is_empty() {
+        Err("At least one port is required".into())
+    } else {
+        Ok(ports)
+    }
+}
+
+/// Parse a comma separated list of `name:interface` relations
+fn parse_relations(relations: &str) -> Result<Vec<TemplateRelation>, String> {
+    relations
+        .split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|relation| {
+            let mut parts = relation.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(interface)) => {
+                    validate_name(name)?;
+                    validate_name(interface)?;
+                    Ok(TemplateRelation {
+                        name: name.into(),
+                        interface: interface.into(),
+                    })
+                }
+                _ => Err(format!(
+                    "Invalid relation \"{}\": relations are given as name:interface, such as \
+                    db:pgsql",
+                    relation
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Print the files of the rendered charm and the contents of the most important ones
+fn show_preview(preview_dir: &Path, target_dir: &Path) -> anyhow::Result<()> {
+    let mut stdout = io::stdout();
+
+    let mut files: Vec<PathBuf> = WalkDir::new(preview_dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|x| x.file_type().is_file())
+        .filter_map(|x| {
+            x.path()
+                .strip_prefix(preview_dir)
+                .ok()
+                .map(Path::to_path_buf)
+        })
+        .collect();
+    files.sort();
+
+    writeln!(
+        stdout,
+        "\nThese files will be created in {}:\n",
+        target_dir.display()
+    )?;
+    for file in &files {
+        writeln!(stdout, "  {}", file.display())?;
+    }
+
+    for name in PREVIEW_FILES {
+        let path = preview_dir.join(name);
+        let content =
+            fs::read_to_string(&path).context(format!("Could not read file: {:?}", path))?;
+        writeln!(stdout, "\n--- {} ---\n{}", name, content.trim_end())?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,319 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{format_err, Context};
+use clap::{App, Arg, ArgMatches};
+use subprocess::{Exec, Redirection};
+use walkdir::WalkDir;
+
+use crate::cli::*;
+
+/// Zip archive data for the bundled charm examples
+const CHARM_EXAMPLES_ARCHIVE: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/charm_examples.zip"));
+
+/// An example charm that can be fetched with `lucky charm examples fetch`
+struct CharmExample {
+    /// The name used to fetch the example
+    name: &'static str,
+    /// A short description of what the example demonstrates
+    description: &'static str,
+    /// Where to get the example from
+    source: ExampleSource,
+}
+
+/// The location of an example charm
+enum ExampleSource {
+    /// The example is bundled into the Lucky binary and can be fetched offline
+    Bundled,
+    /// The example is downloaded from a directory in a git repository
+    Git {
+        /// The URL of the git repository
+        repository: &'static str,
+        /// The path to the charm inside of the repository
+        path: &'static str,
+    },
+}
+
+/// The curated list of example charms
+const EXAMPLES: &[CharmExample] = &[
+    CharmExample {
+        name: "web-app",
+        description: "A static website served by Nginx",
+        source: ExampleSource::Bundled,
+    },
+    CharmExample {
+        name: "database",
+        description: "A PostgreSQL database provided over a relation",
+        source: ExampleSource::Bundled,
+    },
+    CharmExample {
+        name: "clustered-app",
+        description: "A Redis primary and replicas coordinated with the peer store",
+        source: ExampleSource::Bundled,
+    },
+    CharmExample {
+        name: "codimd",
+        description: "The CodiMD charm from the Lucky getting started guide",
+        source: ExampleSource::Git {
+            repository: "https://github.com/katharostech/lucky.git",
+            path: "docs/book/src/codimd-example-charm",
+        },
+    },
+];
+
+pub(super) struct ExamplesSubcommand;
+
+impl<'a> CliCommand<'a> for ExamplesSubcommand {
+    fn get_name(&self) -> &'static str {
+        "examples"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Browse and fetch example charms")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![Box::new(ListSubcommand), Box::new(FetchSubcommand)]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_charm_examples",
+            content: include_str!("examples/examples.md"),
+        })
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        Ok(data)
+    }
+}
+
+struct ListSubcommand;
+
+impl<'a> CliCommand<'a> for ListSubcommand {
+    fn get_name(&self) -> &'static str {
+        "list"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("List the available example charms")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        let name_width = EXAMPLES.iter().map(|x| x.name.len()).max().unwrap_or(0);
+
+        for example in EXAMPLES {
+            let source = match example.source {
+                ExampleSource::Bundled => "bundled",
+                ExampleSource::Git { .. } => "download",
+            };
+
+            writeln!(
+                io::stdout(),
+                "{:width$}  {:8}  {}",
+                example.name,
+                source,
+                example.description,
+                width = name_width
+            )?;
+        }
+
+        Ok(data)
+    }
+}
+
+struct FetchSubcommand;
+
+impl<'a> CliCommand<'a> for FetchSubcommand {
+    fn get_name(&self) -> &'static str {
+        "fetch"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Fetch an example charm into a local directory")
+            .arg(Arg::with_name("name")
+                .help("The name of the example to fetch")
+                .required(true))
+            .arg(Arg::with_name("target_dir")
+                .help("The directory to put the example in. Defaults to the example name"))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        let name = args
+            .value_of("name")
+            .expect("Missing required argument: name");
+        let target_dir = PathBuf::from(args.value_of("target_dir").unwrap_or(name));
+
+        let example = EXAMPLES.iter().find(|x| x.name == name).ok_or_else(|| {
+            format_err!(
+                "Unknown example {:?}. Run `lucky charm examples list` to see the examples.",
+                name
+            )
+        })?;
+
+        // Make sure target directory doesn't already exist
+        if target_dir.exists() {
+            anyhow::bail!("Target directory already exists: {:?}", target_dir);
+        }
+
+        match example.source {
+            ExampleSource::Bundled => extract_bundled_example(name, &target_dir)?,
+            ExampleSource::Git { repository, path } => {
+                download_example(name, repository, path, &target_dir)?
+            }
+        }
+
+        writeln!(
+            io::stderr(),
+            "Fetched the {} example into {:?}",
+            name,
+            target_dir
+        )?;
+
+        Ok(data)
+    }
+}
+
+/// Extract a bundled example from the embedded examples archive
+fn extract_bundled_example(name: &str, target_dir: &Path) -> anyhow::Result<()> {
+    let zip_reader = io::Cursor::new(CHARM_EXAMPLES_ARCHIVE);
+    let zip_error_message = "Internal error: problem reading embedded charm examples zip";
+    let mut zip = zip::ZipArchive::new(zip_reader).context(zip_error_message)?;
+
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i).context(zip_error_message)?;
+
+        // Skip files that are not a part of this example
+        let sanitized_name = file.sanitized_name();
+        let relative_path = match sanitized_name.strip_prefix(name) {
+            Ok(path) => path,
+            Err(_) => continue,
+        };
+        let outpath = target_dir.join(relative_path);
+
+        // If file entry is a directory
+        if file.name().ends_with('/') {
+            fs::create_dir_all(&outpath)
+                .context(format!("Could not create directory: {:?}", outpath))?;
+
+        // If it is a file
+        } else {
+            if let Some(p) = outpath.parent() {
+                fs::create_dir_all(&p).context(format!("Could not create directory: {:?}", p))?;
+            }
+
+            let mut outfile = fs::File::create(&outpath)
+                .context(format!("Could not create file: {:?}", outpath))?;
+            io::copy(&mut file, &mut outfile)
+                .context(format!("Could not write to file: {:?}", outpath))?;
+        }
+
+        // If we are on a unix system
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            // If there is a mode set for the file in the zip
+            if let Some(mode) = file.unix_mode() {
+                fs::set_permissions(&outpath, fs::Permissions::from_mode(mode)).context(
+                    format!("Could not set permissions on created file: {:?}", &outpath),
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Download an example by cloning its git repository and copying the charm out of it
+fn download_example(
+    name: &str,
+    repository: &str,
+    path: &str,
+    target_dir: &Path,
+) -> anyhow::Result<()> {
+    let clone_dir =
+        std::env::temp_dir().join(format!("lucky-example-{}-{}", name, std::process::id()));
+
+    writeln!(
+        io::stderr(),
+        "Downloading {} example from {}",
+        name,
+        repository
+    )?;
+    let clone_result = Exec::cmd("git")
+        .args(&["clone", "--depth", "1", "--quiet", repository])
+        .arg(&clone_dir)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .context("Could not run `git`. Make sure git is installed to download this example")?;
+    if !clone_result.exit_status.success() {
+        fs::remove_dir_all(&clone_dir).ok();
+        anyhow::bail!(
+            "Could not clone {}:\n{}",
+            repository,
+            clone_result.stdout_str()
+        );
+    }
+
+    let result = copy_dir(&clone_dir.join(path), target_dir);
+
+    // Clean up the clone
+    fs::remove_dir_all(&clone_dir).ok();
+
+    result
+}
+
+/// Recursively copy a directory, skipping any `.git` directories
+fn copy_dir(source: &Path, target: &Path) -> anyhow::Result<()> {
+    if !source.is_dir() {
+        anyhow::bail!("Example not found in repository: {:?}", source);
+    }
+
+    for entry in WalkDir::new(source)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".git")
+    {
+        let entry = entry?;
+        let target_path = target.join(entry.path().strip_prefix(source)?);
+
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target_path)
+                .context(format!("Could not create directory: {:?}", target_path))?;
+        } else {
+            fs::copy(entry.path(), &target_path).context(format!(
+                "Could not copy file {:?} to {:?}",
+                entry.path(),
+                target_path
+            ))?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use clap::{App, Arg, ArgMatches};
+
+use std::io::{self, Write};
+
+use super::build::{cached_lucky_release_path, download_lucky_release};
+use crate::cli::*;
+
+pub(super) struct FetchDepsSubcommand;
+
+impl<'a> CliCommand<'a> for FetchDepsSubcommand {
+    fn get_name(&self) -> &'static str {
+        "fetch-deps"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("Download what `lucky charm build` needs into the cache for offline builds")
+            .arg(Arg::with_name("arch")
+                .help("The CPU architecture to download the Lucky release for, such as \"x86_64\"")
+                .long_help("The CPU architecture to download the Lucky release for, such as \
+                              \"x86_64\" or \"aarch64\". Can be given more than once. Defaults to \
+                              the architecture of this machine.")
+                .long("arch")
+                .short('a')
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1))
+            .arg(Arg::with_name("force")
+                .help("Download the releases again even if they are already cached")
+                .long("force")
+                .short('f'))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_charm_fetch-deps",
+            content: include_str!("fetch_deps/fetch_deps.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        let arches: Vec<&str> = match args.values_of("arch") {
+            Some(arches) => arches.collect(),
+            None => vec![std::env::consts::ARCH],
+        };
+
+        for arch in arches {
+            let path = cached_lucky_release_path(arch)?;
+            if path.exists() && !args.is_present("force") {
+                log::info!("Lucky for {} is already cached", arch);
+            } else {
+                download_lucky_release(arch, &path)?;
+            }
+            writeln!(io::stdout(), "{}", path.display())?;
+        }
+
+        Ok(data)
+    }
+}
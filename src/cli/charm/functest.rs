@@ -0,0 +1,587 @@
+use anyhow::{format_err, Context};
+use clap::{App, Arg, ArgMatches};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use subprocess::{Exec, Redirection};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::cli::util::{juju, juju_stdout};
+use crate::cli::*;
+use crate::config::load_yaml;
+use crate::types::juju::CharmMetadata;
+
+/// The file in the charm that the functional test is loaded from by default
+const FUNCTEST_FILE: &str = "functest.yaml";
+/// How often to check the status of the model while waiting for it to change
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// How long to wait for the applications to be removed when tearing down the test
+const TEARDOWN_TIMEOUT: Duration = Duration::from_secs(600);
+
+pub(super) struct FunctestSubcommand;
+
+impl<'a> CliCommand<'a> for FunctestSubcommand {
+    fn get_name(&self) -> &'static str {
+        "functest"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Deploy a built charm to a Juju model and run its functional tests")
+            .arg(Arg::with_name("model")
+                .help("The Juju model to deploy the charm to")
+                .long("model")
+                .short('m')
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("file")
+                .help("The functional test file. Defaults to the functest.yaml in the charm dir.")
+                .long("file")
+                .short('f')
+                .takes_value(true))
+            .arg(Arg::with_name("build_dir")
+                .help("The directory that the charm was built in. Defaults to the \"build\" \
+                       directory in the charm dir.")
+                .long("build-dir")
+                .short('b')
+                .takes_value(true))
+            .arg(Arg::with_name("format")
+                .help("The format to print the results in")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .default_value("text"))
+            .arg(Arg::with_name("keep")
+                .help("Leave the applications deployed after the test instead of removing them")
+                .long("keep"))
+            .arg(Arg::with_name("charm_dir")
+                .help("The path to the charm you want to test")
+                .required(false)
+                .default_value("."))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_charm_functest",
+            content: include_str!("functest/functest.md"),
+        })
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        let model = args.value_of("model").expect("Missing required arg: model");
+        let charm_path = Path::new(
+            args.value_of("charm_dir")
+                .expect("Missing required argument: charm_dir"),
+        )
+        .canonicalize()
+        .context("Could not find charm dir")?;
+
+        // Load the test
+        let test_file = match args.value_of("file") {
+            Some(file) => PathBuf::from(file),
+            None => charm_path.join(FUNCTEST_FILE),
+        };
+        let test: FuncTest = serde_yaml::from_str(
+            &fs::read_to_string(&test_file)
+                .context(format!("Could not read file: {:?}", test_file))?,
+        )
+        .context(format!("Could not parse functional test: {:?}", test_file))?;
+
+        // Find the built charm
+        let charm_metadata: CharmMetadata = load_yaml(&charm_path, "metadata")?;
+        let build_dir = match args.value_of("build_dir") {
+            Some(build_dir) => PathBuf::from(build_dir),
+            None => charm_path.join("build"),
+        };
+        let built_charm = build_dir.join(&charm_metadata.name);
+        if !built_charm.join("metadata.yaml").is_file() {
+            anyhow::bail!(
+                "Could not find the built charm in {:?}. Run `lucky charm build` first.",
+                built_charm
+            );
+        }
+        let application = test
+            .application
+            .clone()
+            .unwrap_or_else(|| charm_metadata.name.clone());
+
+        let mut result = FuncTestResult {
+            model: model.into(),
+            application: application.clone(),
+            passed: false,
+            error: None,
+            assertions: vec![],
+        };
+
+        // Deploy the charm and run the assertions, keeping track of what was deployed so that it
+        // can be removed even if the test fails part of the way through
+        let mut deployed = Vec::new();
+        let run_result = (|| -> anyhow::Result<()> {
+            deploy(
+                model,
+                &charm_path,
+                &built_charm,
+                &application,
+                &test,
+                &mut deployed,
+            )?;
+            let units = wait_for_units(model, &application, &test)?;
+
+            let env = assertion_env(model, &application, &units);
+            for assertion in &test.assertions {
+                log::info!("Running assertion: {}", assertion.name);
+                result
+                    .assertions
+                    .push(run_assertion(&charm_path, &env, assertion));
+            }
+
+            Ok(())
+        })();
+        if let Err(e) = run_result {
+            result.error = Some(format!("{:?}", e));
+        }
+
+        if args.is_present("keep") {
+            log::info!("Leaving the applications deployed: {}", deployed.join(", "));
+        } else if let Err(e) = tear_down(model, &deployed) {
+            if result.error.is_none() {
+                result.error = Some(format!("{:?}", e));
+            } else {
+                log::error!("{:?}", e);
+            }
+        }
+
+        result.passed = result.error.is_none() && result.assertions.iter().all(|x| x.passed);
+
+        // Print the results
+        let mut stdout = io::stdout();
+        if args.value_of("format") == Some("json") {
+            writeln!(stdout, "{}", serde_json::to_string_pretty(&result)?)?;
+        } else {
+            for assertion in &result.assertions {
+                if assertion.passed {
+                    writeln!(stdout, "PASS {}", assertion.name)?;
+                } else {
+                    writeln!(stdout, "FAIL {}", assertion.name)?;
+                    for line in assertion.output.lines() {
+                        writeln!(stdout, "    {}", line)?;
+                    }
+                }
+            }
+            if let Some(error) = &result.error {
+                writeln!(stdout, "ERROR {}", error)?;
+            }
+        }
+
+        if !result.passed {
+            let failed = result.assertions.iter().filter(|x| !x.passed).count();
+            anyhow::bail!(
+                "The functional test failed: {} of {} assertion(s) failed{}",
+                failed,
+                test.assertions.len(),
+                if result.error.is_some() {
+                    " and the test had an error"
+                } else {
+                    ""
+                }
+            );
+        }
+
+        Ok(data)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// A functional test: what to deploy, what the charm's units should look like once they have
+/// settled, and the assertions to run against them
+struct FuncTest {
+    /// The name to deploy the charm as. Defaults to the charm name.
+    #[serde(default)]
+    application: Option<String>,
+    /// The number of units of the charm to deploy
+    #[serde(default = "default_num_units")]
+    num_units: usize,
+    /// The charm config
+    #[serde(default)]
+    config: HashMap<String, JsonValue>,
+    /// The paths to the charm's resources, relative to the charm dir, keyed by resource name
+    #[serde(default)]
+    resources: HashMap<String, String>,
+    /// The other applications to deploy, keyed by application name
+    #[serde(default)]
+    deploy: HashMap<String, OtherApplication>,
+    /// The relations to add, each given as a pair of endpoints such as `my-app:db`
+    #[serde(default)]
+    relate: Vec<(String, String)>,
+    /// The status to wait for before running the assertions
+    #[serde(default)]
+    wait: WaitSpec,
+    /// The assertions to run once the charm's units have settled
+    #[serde(default)]
+    assertions: Vec<Assertion>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// An application deployed alongside the charm being tested
+struct OtherApplication {
+    /// The charm to deploy, such as `postgresql`
+    charm: String,
+    #[serde(default)]
+    channel: Option<String>,
+    #[serde(default = "default_num_units")]
+    num_units: usize,
+    #[serde(default)]
+    config: HashMap<String, JsonValue>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// The status that every unit of the charm has to be in before the assertions are run
+struct WaitSpec {
+    /// The workload status, such as `active`
+    #[serde(default = "default_wait_status")]
+    status: String,
+    /// Text that the status message has to contain
+    #[serde(default)]
+    message: Option<String>,
+    /// How long to wait, in seconds
+    #[serde(default = "default_wait_timeout")]
+    timeout: u64,
+}
+
+impl Default for WaitSpec {
+    fn default() -> Self {
+        WaitSpec {
+            status: default_wait_status(),
+            message: None,
+            timeout: default_wait_timeout(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// A shell command that passes if it exits zero
+struct Assertion {
+    name: String,
+    /// The bash command to run, from the charm dir
+    run: String,
+    /// How many more times to run the command if it fails
+    #[serde(default)]
+    retries: u32,
+    /// How long to wait between retries, in seconds
+    #[serde(default = "default_retry_delay")]
+    retry_delay: u64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+/// The result of a functional test, as printed with `--format json`
+struct FuncTestResult {
+    model: String,
+    application: String,
+    passed: bool,
+    /// The error that stopped the test, such as a failed deployment or a unit in an error state
+    error: Option<String>,
+    assertions: Vec<AssertionResult>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+/// The result of an assertion
+struct AssertionResult {
+    name: String,
+    passed: bool,
+    /// The number of times that the command was run
+    attempts: u32,
+    /// The output of the last run of the command
+    output: String,
+    /// How long the assertion took, including retries, in seconds
+    duration: f64,
+}
+
+/// A unit of the charm once it has settled
+struct UnitInfo {
+    name: String,
+    address: Option<String>,
+}
+
+/// Deploy the charm and the other applications in the test and add the relations between them,
+/// adding each application to `deployed` once it has been deployed
+fn deploy(
+    model: &str,
+    charm_dir: &Path,
+    built_charm: &Path,
+    application: &str,
+    test: &FuncTest,
+    deployed: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    log::info!("Deploying {} to model {}", application, model);
+    let mut args: Vec<String> = vec![
+        "deploy".into(),
+        "-m".into(),
+        model.into(),
+        built_charm.to_string_lossy().into(),
+        application.into(),
+        "-n".into(),
+        test.num_units.to_string(),
+    ];
+    for (name, path) in &test.resources {
+        args.push("--resource".into());
+        args.push(format!("{}={}", name, charm_dir.join(path).display()));
+    }
+    deploy_with_config(&mut args, application, &test.config)?;
+    deployed.push(application.into());
+
+    for (name, other) in &test.deploy {
+        log::info!("Deploying {} to model {}", name, model);
+        let mut args: Vec<String> = vec![
+            "deploy".into(),
+            "-m".into(),
+            model.into(),
+            other.charm.clone(),
+            name.clone(),
+            "-n".into(),
+            other.num_units.to_string(),
+        ];
+        if let Some(channel) = &other.channel {
+            args.push("--channel".into());
+            args.push(channel.clone());
+        }
+        deploy_with_config(&mut args, name, &other.config)?;
+        deployed.push(name.clone());
+    }
+
+    for (a, b) in &test.relate {
+        log::info!("Relating {} and {}", a, b);
+        juju(&["add-relation", "-m", model, a.as_str(), b.as_str()])?;
+    }
+
+    Ok(())
+}
+
+/// Run a `juju deploy` command, adding the application config to it if there is any
+fn deploy_with_config(
+    args: &mut Vec<String>,
+    application: &str,
+    config: &HashMap<String, JsonValue>,
+) -> anyhow::Result<()> {
+    // Juju reads the config from a YAML file keyed by application name
+    let config_file = std::env::temp_dir().join(format!(
+        "lucky-functest-{}-{}.yaml",
+        std::process::id(),
+        application
+    ));
+    if !config.is_empty() {
+        let mut file_content = HashMap::new();
+        file_content.insert(application, config);
+        fs::write(&config_file, serde_yaml::to_string(&file_content)?)
+            .context(format!("Could not write file: {:?}", config_file))?;
+        args.push("--config".into());
+        args.push(config_file.to_string_lossy().into());
+    }
+
+    let result = juju(&args.iter().map(String::as_str).collect::<Vec<_>>());
+    fs::remove_file(&config_file).ok();
+    result?;
+
+    Ok(())
+}
+
+/// Wait for every unit of the application to be in the expected status with an idle agent,
+/// failing if a unit goes into an error state
+fn wait_for_units(
+    model: &str,
+    application: &str,
+    test: &FuncTest,
+) -> anyhow::Result<Vec<UnitInfo>> {
+    let wait = &test.wait;
+    log::info!(
+        "Waiting for the units of {} to be {}",
+        application,
+        wait.status
+    );
+    let start = Instant::now();
+    loop {
+        let status: JsonValue =
+            serde_json::from_str(&juju_stdout(&["status", "-m", model, "--format", "json"])?)
+                .context("Could not parse juju status")?;
+        let empty = serde_json::Map::new();
+        let units = status
+            .pointer(&format!("/applications/{}/units", application))
+            .and_then(JsonValue::as_object)
+            .unwrap_or(&empty);
+
+        let mut settled = units.len() >= test.num_units;
+        let mut infos = Vec::new();
+        for (name, unit) in units {
+            let get = |pointer| unit.pointer(pointer).and_then(JsonValue::as_str);
+            let workload_status = get("/workload-status/current").unwrap_or("");
+            let message = get("/workload-status/message").unwrap_or("");
+            if workload_status == "error" {
+                anyhow::bail!("Unit {} is in an error state: {}", name, message);
+            }
+
+            let message_matches = wait
+                .message
+                .as_ref()
+                .map_or(true, |x| message.contains(x.as_str()));
+            if workload_status != wait.status
+                || get("/juju-status/current") != Some("idle")
+                || !message_matches
+            {
+                settled = false;
+            }
+
+            infos.push(UnitInfo {
+                name: name.clone(),
+                address: get("/public-address").map(Into::into),
+            });
+        }
+        if settled {
+            infos.sort_by(|a, b| a.name.cmp(&b.name));
+            return Ok(infos);
+        }
+
+        if start.elapsed() > Duration::from_secs(wait.timeout) {
+            return Err(format_err!(
+                "Timed out after {} seconds waiting for the units of {} to be {}",
+                wait.timeout,
+                application,
+                wait.status
+            ));
+        }
+        sleep(POLL_INTERVAL);
+    }
+}
+
+/// Get the environment variables that the assertions are run with
+fn assertion_env(model: &str, application: &str, units: &[UnitInfo]) -> Vec<(String, String)> {
+    let mut env = vec![
+        ("JUJU_MODEL".into(), model.into()),
+        ("APPLICATION".into(), application.into()),
+        (
+            "UNITS".into(),
+            units
+                .iter()
+                .map(|x| x.name.as_str())
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+    ];
+    if let Some(unit) = units.first() {
+        env.push(("UNIT".into(), unit.name.clone()));
+        if let Some(address) = &unit.address {
+            env.push(("UNIT_ADDRESS".into(), address.clone()));
+        }
+    }
+
+    env
+}
+
+/// Run an assertion, retrying it until it passes or it runs out of retries
+fn run_assertion(
+    charm_dir: &Path,
+    env: &[(String, String)],
+    assertion: &Assertion,
+) -> AssertionResult {
+    let start = Instant::now();
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let mut command = Exec::cmd("bash")
+            .args(&["-c", assertion.run.as_str()])
+            .cwd(charm_dir)
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Merge);
+        for (key, value) in env {
+            command = command.env(key, value);
+        }
+        let (passed, output) = match command.capture() {
+            Ok(capture) => (capture.success(), capture.stdout_str()),
+            Err(e) => (false, format!("Could not run assertion: {}", e)),
+        };
+
+        if passed || attempts > assertion.retries {
+            return AssertionResult {
+                name: assertion.name.clone(),
+                passed,
+                attempts,
+                output: output.trim_end().into(),
+                duration: start.elapsed().as_secs_f64(),
+            };
+        }
+        sleep(Duration::from_secs(assertion.retry_delay));
+    }
+}
+
+/// Remove the deployed applications and wait for them to be gone from the model
+fn tear_down(model: &str, applications: &[String]) -> anyhow::Result<()> {
+    if applications.is_empty() {
+        return Ok(());
+    }
+
+    log::info!("Removing applications: {}", applications.join(", "));
+    for application in applications {
+        juju(&["remove-application", "-m", model, application.as_str()])?;
+    }
+
+    let start = Instant::now();
+    loop {
+        let status: JsonValue =
+            serde_json::from_str(&juju_stdout(&["status", "-m", model, "--format", "json"])?)
+                .context("Could not parse juju status")?;
+        let remaining = applications
+            .iter()
+            .filter(|x| status.pointer(&format!("/applications/{}", x)).is_some())
+            .count();
+        if remaining == 0 {
+            return Ok(());
+        }
+
+        if start.elapsed() > TEARDOWN_TIMEOUT {
+            anyhow::bail!(
+                "Timed out waiting for the applications to be removed: {}",
+                applications.join(", ")
+            );
+        }
+        sleep(POLL_INTERVAL);
+    }
+}
+
+//
+// Helpers
+//
+
+fn default_num_units() -> usize {
+    1
+}
+
+fn default_wait_status() -> String {
+    "active".into()
+}
+
+fn default_wait_timeout() -> u64 {
+    900
+}
+
+fn default_retry_delay() -> u64 {
+    5
+}
@@ -0,0 +1,678 @@
+use anyhow::{bail, format_err, Context};
+use clap::{App, Arg, ArgMatches};
+use indexmap::IndexMap;
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::cli::*;
+use crate::types::{ContainerSpec, HealthCheckSpec, PortSpec, DEFAULT_CONTAINER_NAME};
+
+/// The service options that are converted to container definitions. Any other options are ignored
+/// with a warning.
+const SUPPORTED_OPTIONS: &[&str] = &[
+    "image",
+    "entrypoint",
+    "command",
+    "environment",
+    "ports",
+    "volumes",
+    "network_mode",
+    "networks",
+    "dns",
+    "extra_hosts",
+    "devices",
+    "gpus",
+    "healthcheck",
+    "restart",
+    "depends_on",
+];
+
+#[derive(Serialize)]
+/// The `containers` section of a `lucky.yaml` file
+struct ImportedContainers {
+    containers: IndexMap<String, ContainerSpec>,
+}
+
+pub(super) struct ImportComposeSubcommand;
+
+impl<'a> CliCommand<'a> for ImportComposeSubcommand {
+    fn get_name(&self) -> &'static str {
+        "import-compose"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Convert a docker-compose file to Lucky container definitions")
+            .arg(Arg::with_name("compose_file")
+                .help("The docker-compose file to import")
+                .required(true))
+            .arg(Arg::with_name("default")
+                .long("default")
+                .short('d')
+                .help("The service to use as the default container. Defaults to the only service \
+                       if the compose file has only one")
+                .takes_value(true))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_charm_import-compose",
+            content: include_str!("import_compose/import_compose.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        let compose_file = Path::new(
+            args.value_of("compose_file")
+                .expect("Missing required argument: compose_file"),
+        );
+
+        let compose: Value = serde_yaml::from_str(
+            &fs::read_to_string(compose_file)
+                .context(format!("Could not read file: {:?}", compose_file))?,
+        )
+        .context(format!("Could not parse compose file: {:?}", compose_file))?;
+
+        let containers = convert_compose(&compose, args.value_of("default"))?;
+
+        write!(
+            io::stdout(),
+            "{}",
+            serde_yaml::to_string(&ImportedContainers { containers })?.trim_start_matches("---\n")
+        )?;
+
+        Ok(data)
+    }
+}
+
+/// Convert the services in a compose file to container definitions, keyed by container name
+fn convert_compose(
+    compose: &Value,
+    default_service: Option<&str>,
+) -> anyhow::Result<IndexMap<String, ContainerSpec>> {
+    let services = compose
+        .get("services")
+        .and_then(Value::as_mapping)
+        .ok_or_else(|| format_err!("Compose file does not have any services"))?;
+
+    // Use the only service as the default container if a default isn't given
+    let default_service = match default_service {
+        Some(name) => {
+            if services.get(&Value::from(name)).is_none() {
+                bail!("Service {:?} not found in the compose file", name);
+            }
+            Some(name.to_string())
+        }
+        None if services.len() == 1 => services.iter().next().and_then(|(k, _)| value_str(k)),
+        None => None,
+    };
+
+    if compose.get("networks").is_some() {
+        log::warn!(
+            "Networks in the compose file are not created by Lucky. Make sure that they exist \
+            before the containers are started."
+        );
+    }
+
+    let mut containers = IndexMap::new();
+    for (name, service) in services {
+        let name = value_str(name).ok_or_else(|| format_err!("Invalid service name"))?;
+        let service = service
+            .as_mapping()
+            .ok_or_else(|| format_err!("Service {} is not a mapping", name))?;
+
+        let spec = match convert_service(&name, service)? {
+            Some(spec) => spec,
+            None => continue,
+        };
+
+        let container_name = if default_service.as_ref() == Some(&name) {
+            DEFAULT_CONTAINER_NAME.to_string()
+        } else {
+            name
+        };
+        containers.insert(container_name, spec);
+    }
+
+    // Point the dependencies at the renamed default container and drop the dependencies on
+    // services that were skipped
+    let names: Vec<String> = containers.keys().cloned().collect();
+    for (name, spec) in &mut containers {
+        let mut depends_on = Vec::new();
+        for dependency in spec.depends_on.drain(..) {
+            let dependency = if default_service.as_ref() == Some(&dependency) {
+                DEFAULT_CONTAINER_NAME.to_string()
+            } else {
+                dependency
+            };
+            if names.contains(&dependency) {
+                depends_on.push(dependency);
+            } else {
+                log::warn!(
+                    "Ignoring dependency of container {} on skipped service {}",
+                    name,
+                    dependency
+                );
+            }
+        }
+        spec.depends_on = depends_on;
+    }
+
+    // Compose services can reach each other by name on the default network, so put the
+    // containers that don't set their own network on Lucky's shared network
+    if containers.len() > 1 {
+        for spec in containers.values_mut() {
+            if spec.network.is_none() {
+                spec.shared_network = true;
+            }
+        }
+    }
+
+    Ok(containers)
+}
+
+/// Convert a compose service to a container definition, or `None` if the service can't be run by
+/// Lucky
+#[allow(clippy::too_many_lines)]
+fn convert_service(name: &str, service: &Mapping) -> anyhow::Result<Option<ContainerSpec>> {
+    let get = |key: &str| service.get(&Value::from(key));
+
+    for key in service.iter().filter_map(|(key, _)| value_str(key)) {
+        if !SUPPORTED_OPTIONS.contains(&key.as_str()) {
+            log::warn!("Ignoring unsupported option `{}` of service {}", key, name);
+        }
+    }
+
+    let image = match get("image").and_then(value_str) {
+        Some(image) => image,
+        None => {
+            log::warn!(
+                "Skipping service {}: it doesn't have an `image`. Build the image and push it to \
+                a registry to use it with Lucky.",
+                name
+            );
+            return Ok(None);
+        }
+    };
+    let mut spec = ContainerSpec {
+        image,
+        ..ContainerSpec::default()
+    };
+
+    // Entrypoint and command
+    let command = get("command").map(command_list).transpose()?;
+    if let Some(entrypoint) = get("entrypoint").map(command_list).transpose()? {
+        let mut entrypoint = entrypoint.into_iter();
+        spec.entrypoint = entrypoint.next();
+
+        // Lucky entrypoints are a single executable, so extra entrypoint args go in the command
+        let entrypoint_args: Vec<String> = entrypoint.collect();
+        if !entrypoint_args.is_empty() {
+            if command.is_none() {
+                log::warn!(
+                    "The entrypoint arguments of service {} replace the image's command",
+                    name
+                );
+            }
+            let mut full_command = entrypoint_args;
+            full_command.extend(command.clone().unwrap_or_default());
+            spec.command = Some(full_command);
+        }
+    }
+    if spec.command.is_none() {
+        spec.command = command;
+    }
+
+    // Environment
+    match get("environment") {
+        Some(Value::Mapping(env)) => {
+            for (var, value) in env {
+                let var = value_str(var).ok_or_else(|| format_err!("Invalid variable name"))?;
+                match value_str(value) {
+                    Some(value) => {
+                        spec.env.insert(var, value);
+                    }
+                    None => log::warn!(
+                        "Skipping variable {} of service {}: it doesn't have a value",
+                        var,
+                        name
+                    ),
+                }
+            }
+        }
+        Some(Value::Sequence(env)) => {
+            for var in env.iter().filter_map(value_str) {
+                let mut parts = var.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(var), Some(value)) => {
+                        spec.env.insert(var.into(), value.into());
+                    }
+                    _ => log::warn!(
+                        "Skipping variable {} of service {}: it doesn't have a value",
+                        var,
+                        name
+                    ),
+                }
+            }
+        }
+        Some(_) => bail!("Invalid environment for service {}", name),
+        None => (),
+    }
+
+    // Ports
+    for port in get("ports")
+        .and_then(Value::as_sequence)
+        .into_iter()
+        .flatten()
+    {
+        match convert_port(port) {
+            Ok(port) => spec.ports.push(PortSpec::Binding(port)),
+            Err(e) => log::warn!("Skipping port of service {}: {}", name, e),
+        }
+    }
+
+    // Volumes
+    for volume in get("volumes")
+        .and_then(Value::as_sequence)
+        .into_iter()
+        .flatten()
+    {
+        match convert_volume(name, volume) {
+            Ok(volume) => spec.volumes.push(volume),
+            Err(e) => log::warn!("Skipping volume of service {}: {}", name, e),
+        }
+    }
+
+    // Network
+    if let Some(network_mode) = get("network_mode").and_then(value_str) {
+        spec.network = Some(network_mode);
+    } else if let Some(networks) = get("networks") {
+        let networks: Vec<String> = match networks {
+            Value::Sequence(networks) => networks.iter().filter_map(value_str).collect(),
+            Value::Mapping(networks) => networks.iter().filter_map(|(k, _)| value_str(k)).collect(),
+            _ => bail!("Invalid networks for service {}", name),
+        };
+        if networks.len() > 1 {
+            log::warn!(
+                "Service {} is attached to more than one network, only {} will be used",
+                name,
+                networks.first().map_or("", String::as_str)
+            );
+        }
+        spec.network = networks.into_iter().next();
+    }
+
+    // DNS servers
+    match get("dns") {
+        Some(Value::Sequence(servers)) => spec.dns = servers.iter().filter_map(value_str).collect(),
+        Some(server) => spec.dns.extend(value_str(server)),
+        None => (),
+    }
+
+    // Extra hosts
+    match get("extra_hosts") {
+        Some(Value::Sequence(hosts)) => {
+            for host in hosts.iter().filter_map(value_str) {
+                // Hosts are written as `hostname:address` or `hostname=address`
+                match host.find(|c| c == ':' || c == '=') {
+                    Some(i) => {
+                        let (hostname, address) = host.split_at(i);
+                        spec.extra_hosts
+                            .insert(hostname.into(), address.get(1..).unwrap_or("").into());
+                    }
+                    None => log::warn!(
+                        "Skipping extra host {} of service {}: it doesn't have an address",
+                        host,
+                        name
+                    ),
+                }
+            }
+        }
+        Some(Value::Mapping(hosts)) => {
+            for (hostname, address) in hosts {
+                if let (Some(hostname), Some(address)) = (value_str(hostname), value_str(address)) {
+                    spec.extra_hosts.insert(hostname, address);
+                }
+            }
+        }
+        Some(_) => bail!("Invalid extra_hosts for service {}", name),
+        None => (),
+    }
+
+    // Devices and GPUs
+    spec.devices = get("devices")
+        .and_then(Value::as_sequence)
+        .into_iter()
+        .flatten()
+        .filter_map(value_str)
+        .collect();
+    if let Some(gpus) = get("gpus") {
+        spec.gpus = value_str(gpus);
+        if spec.gpus.is_none() {
+            log::warn!(
+                "Skipping GPUs of service {}: only `all` or a number of GPUs can be imported",
+                name
+            );
+        }
+    }
+
+    // Dependencies
+    match get("depends_on") {
+        Some(Value::Sequence(services)) => {
+            spec.depends_on = services.iter().filter_map(value_str).collect();
+        }
+        // Lucky only waits for the dependencies to be started, so the conditions are ignored
+        Some(Value::Mapping(services)) => {
+            spec.depends_on = services.iter().filter_map(|(k, _)| value_str(k)).collect();
+        }
+        Some(_) => bail!("Invalid depends_on for service {}", name),
+        None => (),
+    }
+
+    // Restart policy
+    if let Some(restart) = get("restart").and_then(value_str) {
+        match restart.parse() {
+            Ok(restart) => spec.restart = Some(restart),
+            Err(_) => log::warn!(
+                "Ignoring unsupported restart policy `{}` of service {}",
+                restart,
+                name
+            ),
+        }
+    }
+
+    // Health check
+    if let Some(healthcheck) = get("healthcheck").and_then(Value::as_mapping) {
+        spec.healthcheck = convert_healthcheck(name, healthcheck)?;
+    }
+
+    Ok(Some(spec))
+}
+
+/// Convert a compose port to the `host_port:container_port/protocol` format
+fn convert_port(port: &Value) -> anyhow::Result<String> {
+    let (host_port, container_port, protocol) = match port {
+        // Long syntax
+        Value::Mapping(port) => {
+            let get = |key: &str| port.get(&Value::from(key)).and_then(value_str);
+            let container_port =
+                get("target").ok_or_else(|| format_err!("Port doesn't have a target"))?;
+            (
+                get("published"),
+                container_port,
+                get("protocol").unwrap_or_else(|| "tcp".into()),
+            )
+        }
+        // Short syntax, such as `3000`, `8080:80`, `127.0.0.1:8080:80`, or `53:53/udp`
+        _ => {
+            let port = value_str(port).ok_or_else(|| format_err!("Invalid port"))?;
+            let mut parts = port.splitn(2, '/');
+            let ports = parts.next().unwrap_or("");
+            let protocol = parts.next().unwrap_or("tcp").to_string();
+            let mut ports = ports.rsplitn(3, ':');
+            let container_port = ports.next().unwrap_or("").to_string();
+            let host_port = ports.next().map(String::from);
+            if ports.next().is_some() {
+                log::warn!(
+                    "Port {} will be bound to all host addresses: binding to one address is not \
+                    supported",
+                    port
+                );
+            }
+            (host_port, container_port, protocol)
+        }
+    };
+
+    if container_port.contains('-') || host_port.as_deref().unwrap_or("").contains('-') {
+        bail!("port ranges are not supported");
+    }
+    let host_port = match host_port.filter(|x| !x.is_empty()) {
+        Some(host_port) => host_port,
+        None => {
+            log::warn!(
+                "Port {} doesn't have a host port, so it will be bound to the same port on the \
+                host",
+                container_port
+            );
+            container_port.clone()
+        }
+    };
+
+    let binding = format!("{}:{}/{}", host_port, container_port, protocol);
+    binding
+        .parse::<crate::docker::PortBinding>()
+        .map_err(|_| format_err!("invalid port {}", binding))?;
+
+    Ok(binding)
+}
+
+/// Convert a compose volume to the `source:target[:ro]` format
+fn convert_volume(service_name: &str, volume: &Value) -> anyhow::Result<String> {
+    let (source, target, read_only) = match volume {
+        // Long syntax
+        Value::Mapping(volume) => {
+            let get = |key: &str| volume.get(&Value::from(key)).and_then(value_str);
+            if get("type").as_deref() == Some("tmpfs") {
+                bail!("tmpfs volumes are not supported");
+            }
+            (
+                get("source"),
+                get("target").ok_or_else(|| format_err!("Volume doesn't have a target"))?,
+                volume
+                    .get(&Value::from("read_only"))
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false),
+            )
+        }
+        // Short syntax, such as `/data`, `data:/data`, or `./data:/data:ro`
+        _ => {
+            let volume = value_str(volume).ok_or_else(|| format_err!("Invalid volume"))?;
+            let parts: Vec<&str> = volume.split(':').collect();
+            match parts.as_slice() {
+                [target] => (None, (*target).to_string(), false),
+                [source, target] => (Some((*source).to_string()), (*target).to_string(), false),
+                [source, target, mode] => {
+                    let read_only = mode.split(',').any(|x| x == "ro");
+                    if mode.split(',').any(|x| x != "ro" && x != "rw") {
+                        log::warn!(
+                            "Ignoring mode `{}` of volume {}: only `ro` and `rw` are supported",
+                            mode,
+                            volume
+                        );
+                    }
+                    (
+                        Some((*source).to_string()),
+                        (*target).to_string(),
+                        read_only,
+                    )
+                }
+                _ => bail!("invalid volume {}", volume),
+            }
+        }
+    };
+
+    let source = match source {
+        // Absolute host paths are mounted as-is
+        Some(source) if source.starts_with('/') => source,
+        // Relative host paths become volumes in the Lucky data directory
+        Some(source) if source.starts_with('.') || source.starts_with('~') => {
+            let name = source
+                .trim_start_matches(|c: char| c == '.' || c == '~' || c == '/')
+                .replace('/', "-");
+            log::warn!(
+                "Host directory {} of service {} will be the Lucky volume {}: its contents will \
+                not be copied",
+                source,
+                service_name,
+                name
+            );
+            name
+        }
+        // Named volumes are kept in the Lucky data directory
+        Some(source) => source,
+        // Anonymous volumes get named after the service and target
+        None => format!("{}{}", service_name, target.replace('/', "-")),
+    };
+
+    Ok(format!(
+        "{}:{}{}",
+        source,
+        target,
+        if read_only { ":ro" } else { "" }
+    ))
+}
+
+/// Convert a compose health check to a container health check, or `None` if it is disabled
+fn convert_healthcheck(
+    service_name: &str,
+    healthcheck: &Mapping,
+) -> anyhow::Result<Option<HealthCheckSpec>> {
+    let get = |key: &str| healthcheck.get(&Value::from(key));
+
+    if get("disable").and_then(Value::as_bool) == Some(true) {
+        return Ok(None);
+    }
+
+    // The test is either `["CMD", args...]`, `["CMD-SHELL", command]`, or a shell command
+    let exec = match get("test") {
+        Some(Value::Sequence(test)) => {
+            let test: Vec<String> = test.iter().filter_map(value_str).collect();
+            match test.split_first() {
+                Some((kind, args)) if kind == "CMD" => args.to_vec(),
+                Some((kind, args)) if kind == "CMD-SHELL" => {
+                    vec!["sh".into(), "-c".into(), args.join(" ")]
+                }
+                Some((kind, _)) if kind == "NONE" => return Ok(None),
+                _ => bail!("Invalid health check test for service {}", service_name),
+            }
+        }
+        Some(test) => {
+            let test = value_str(test)
+                .ok_or_else(|| format_err!("Invalid health check for service {}", service_name))?;
+            vec!["sh".into(), "-c".into(), test]
+        }
+        None => {
+            log::warn!(
+                "Skipping health check of service {}: it doesn't have a test",
+                service_name
+            );
+            return Ok(None);
+        }
+    };
+
+    let mut spec = HealthCheckSpec {
+        exec: Some(exec),
+        ..HealthCheckSpec::default()
+    };
+    if let Some(interval) = get("interval").and_then(value_str) {
+        spec.interval = parse_duration(&interval)
+            .ok_or_else(|| format_err!("Invalid health check interval: {}", interval))?;
+    }
+    if let Some(retries) = get("retries").and_then(Value::as_u64) {
+        spec.retries = u32::try_from(retries)
+            .map_err(|_| format_err!("Invalid health check retries: {}", retries))?;
+    }
+
+    Ok(Some(spec))
+}
+
+/// Split a compose command into a list of arguments
+///
+/// Commands can be a list or a string. Strings are split on whitespace, keeping quoted strings
+/// together.
+fn command_list(command: &Value) -> anyhow::Result<Vec<String>> {
+    match command {
+        Value::Sequence(command) => Ok(command.iter().filter_map(value_str).collect()),
+        Value::String(command) => {
+            let mut args = vec![];
+            let mut current: Option<String> = None;
+            let mut quote: Option<char> = None;
+            let mut chars = command.chars();
+            while let Some(c) = chars.next() {
+                match (quote, c) {
+                    (Some(q), c) if c == q => quote = None,
+                    (None, '"') | (None, '\'') => {
+                        quote = Some(c);
+                        current.get_or_insert_with(String::new);
+                    }
+                    (q, '\\') if q != Some('\'') => {
+                        if let Some(c) = chars.next() {
+                            current.get_or_insert_with(String::new).push(c);
+                        }
+                    }
+                    (None, c) if c.is_whitespace() => {
+                        if let Some(arg) = current.take() {
+                            args.push(arg);
+                        }
+                    }
+                    (_, c) => current.get_or_insert_with(String::new).push(c),
+                }
+            }
+            if quote.is_some() {
+                bail!("Unclosed quote in command: {}", command);
+            }
+            args.extend(current);
+
+            Ok(args)
+        }
+        _ => bail!("Invalid command: {:?}", command),
+    }
+}
+
+/// Parse a compose duration, such as `1m30s`, to whole seconds
+fn parse_duration(duration: &str) -> Option<u64> {
+    let mut seconds = 0.0;
+    let mut number = String::new();
+    let mut chars = duration.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+
+        let mut unit = c.to_string();
+        while let Some(&c) = chars.peek().filter(|x| x.is_ascii_alphabetic()) {
+            unit.push(c);
+            chars.next();
+        }
+        let value: f64 = number.parse().ok()?;
+        number.clear();
+        seconds += value
+            * match unit.as_str() {
+                "h" => 3600.0,
+                "m" => 60.0,
+                "s" => 1.0,
+                "ms" => 0.001,
+                "us" => 0.000_001,
+                _ => return None,
+            };
+    }
+
+    // A plain number is a number of seconds
+    if !number.is_empty() {
+        seconds += number.parse::<f64>().ok()?;
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    Some(seconds.ceil().max(1.0) as u64)
+}
+
+/// Get a YAML scalar as a string
+fn value_str(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
@@ -0,0 +1,389 @@
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::validate::{find_line, validate_charm, Problem, Severity};
+use crate::cli::*;
+use crate::config::load_yaml;
+use crate::types::{juju::CharmMetadata, CharmScript, CharmScriptType, LuckyMetadata};
+
+/// The lint rules, with a description of each
+const RULES: &[(&str, &str)] = &[
+    (
+        "unhandled-relation",
+        "A relation in the metadata.yaml has no hooks in the lucky.yaml",
+    ),
+    (
+        "script-not-executable",
+        "A script in host_scripts or container_scripts is not executable",
+    ),
+    (
+        "status-not-cleared",
+        "A script sets a maintenance, waiting, or blocked status but never sets it back to active",
+    ),
+    (
+        "missing-healthcheck",
+        "A container in the lucky.yaml has no healthcheck",
+    ),
+    (
+        "unpinned-image",
+        "A container image has no tag or uses the `latest` tag",
+    ),
+];
+
+lazy_static! {
+    /// Matches a `lucky set-status` command in a script, capturing its arguments
+    static ref SET_STATUS_REGEX: Regex =
+        Regex::new(r"\blucky\s+(?:client\s+)?set-status\b([^;&|\n]*)").unwrap();
+    /// Matches a `lucky container image set` command in a script, capturing its arguments
+    static ref IMAGE_SET_REGEX: Regex =
+        Regex::new(r"\blucky\s+(?:client\s+)?container\s+image\s+set\b([^;&|\n]*)").unwrap();
+}
+
+pub(super) struct LintSubcommand;
+
+impl<'a> CliCommand<'a> for LintSubcommand {
+    fn get_name(&self) -> &'static str {
+        "lint"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Check a charm for common mistakes")
+            .arg(Arg::with_name("allow")
+                .help("A lint rule to skip. Can be given more than once.")
+                .long("allow")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1))
+            .arg(Arg::with_name("list_rules")
+                .help("List the lint rules and exit")
+                .long("list-rules"))
+            .arg(Arg::with_name("charm_dir")
+                .help("The path to the charm you want to lint")
+                .required(false)
+                .default_value("."))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_charm_lint",
+            content: include_str!("lint/lint.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        let mut stdout = io::stdout();
+
+        if args.is_present("list_rules") {
+            let name_width = RULES.iter().map(|x| x.0.len()).max().unwrap_or(0);
+            for (name, description) in RULES {
+                writeln!(
+                    stdout,
+                    "{:width$}  {}",
+                    name,
+                    description,
+                    width = name_width
+                )?;
+            }
+            return Ok(data);
+        }
+
+        let charm_path = Path::new(
+            args.value_of("charm_dir")
+                .expect("Missing required argument: charm_dir"),
+        );
+        let allowed: Vec<&str> = args.values_of("allow").into_iter().flatten().collect();
+        if let Some(rule) = allowed.iter().find(|x| !RULES.iter().any(|r| r.0 == **x)) {
+            anyhow::bail!(
+                "Unknown lint rule: {}. Run with `--list-rules` to see the rules.",
+                rule
+            );
+        }
+
+        // The lints can only be run on a valid charm
+        let mut problems = validate_charm(charm_path)?;
+        if !problems.iter().any(Problem::is_error) {
+            problems.extend(
+                lint_charm(charm_path)?
+                    .into_iter()
+                    .filter(|(rule, _)| !allowed.contains(rule))
+                    .map(|(rule, mut problem)| {
+                        problem.message = format!("{} [{}]", problem.message, rule);
+                        problem
+                    }),
+            );
+        }
+        problems.sort_by(|a, b| (&a.file, a.line).cmp(&(&b.file, b.line)));
+
+        for problem in &problems {
+            writeln!(stdout, "{}", problem)?;
+        }
+        if !problems.is_empty() {
+            anyhow::bail!("Found {} problem(s) in the charm", problems.len());
+        }
+        writeln!(stdout, "No problems found")?;
+
+        Ok(data)
+    }
+}
+
+/// Run the lint rules on a charm with a valid `lucky.yaml`, returning the problems found along with
+/// the rule that found them
+#[allow(clippy::too_many_lines)]
+fn lint_charm(charm_dir: &Path) -> anyhow::Result<Vec<(&'static str, Problem)>> {
+    let lucky_yaml_path = yaml_path(charm_dir, "lucky");
+    let lucky_yaml = fs::read_to_string(&lucky_yaml_path)
+        .context(format!("Could not read file: {:?}", lucky_yaml_path))?;
+    let metadata_path = yaml_path(charm_dir, "metadata");
+    let metadata_yaml = fs::read_to_string(&metadata_path)
+        .context(format!("Could not read file: {:?}", metadata_path))?;
+    let lucky_metadata: LuckyMetadata = load_yaml(charm_dir, "lucky")?;
+    let charm_metadata: CharmMetadata = load_yaml(charm_dir, "metadata")?;
+
+    let mut problems = Vec::new();
+    let mut warn = |rule, file: &Path, line, message: String| {
+        problems.push((
+            rule,
+            Problem {
+                severity: Severity::Warning,
+                file: file.to_path_buf(),
+                line,
+                column: None,
+                message,
+            },
+        ))
+    };
+
+    // Relations that don't run any scripts. Peer relations are skipped because they are used by the
+    // peer store, and `docker-registry` relations are handled by Lucky itself.
+    for relations in vec![&charm_metadata.provides, &charm_metadata.requires]
+        .into_iter()
+        .flatten()
+    {
+        for (name, relation) in relations {
+            let handled = relation.interface == "docker-registry"
+                || lucky_metadata.relation_kv.contains_key(name)
+                || ["joined", "changed", "departed", "broken", "created"]
+                    .iter()
+                    .map(|x| format!("{}-relation-{}", name, x))
+                    .any(|hook| !lucky_metadata.matching_hooks(&hook).is_empty());
+            if !handled {
+                warn(
+                    "unhandled-relation",
+                    &metadata_path,
+                    find_line(&metadata_yaml, name, ""),
+                    format!(
+                        "Relation {} is declared in the metadata.yaml, but none of its hooks \
+                        are handled in the lucky.yaml",
+                        name
+                    ),
+                );
+            }
+        }
+    }
+
+    // Get the content of every script, keeping track of the scripts that have been checked so
+    // that scripts used by more than one hook are only checked once
+    let mut checked_files = Vec::new();
+    let mut scripts: Vec<(PathBuf, Option<usize>, String)> = Vec::new();
+    for script in all_scripts(&lucky_metadata) {
+        match &script.script_type {
+            CharmScriptType::Host { .. } | CharmScriptType::Container { .. } => {
+                let path = charm_dir.join(script.name());
+                if checked_files.contains(&path) || !path.is_file() {
+                    continue;
+                }
+                checked_files.push(path.clone());
+
+                if !is_executable(&path)? {
+                    warn(
+                        "script-not-executable",
+                        &path,
+                        None,
+                        "Script is not executable. Run `chmod +x` on it.".into(),
+                    );
+                }
+
+                let content = fs::read_to_string(&path)
+                    .context(format!("Could not read file: {:?}", path))?;
+                scripts.push((path, None, content));
+            }
+            CharmScriptType::InlineHost {
+                inline_host_script: content,
+                ..
+            }
+            | CharmScriptType::InlineContainer {
+                inline_container_script: content,
+                ..
+            } => {
+                let first_line = content.lines().next().unwrap_or("");
+                scripts.push((
+                    lucky_yaml_path.clone(),
+                    find_line(&lucky_yaml, "", first_line.trim()),
+                    content.clone(),
+                ));
+            }
+        }
+    }
+
+    for (path, line, content) in &scripts {
+        // Statuses belong to the script that sets them, so a script has to clear its own status
+        let mut sets_status = None;
+        let mut clears_status = false;
+        for (i, status_line) in content.lines().enumerate() {
+            let status_args = match SET_STATUS_REGEX
+                .captures(status_line)
+                .and_then(|x| x.get(1))
+            {
+                Some(args) => args.as_str(),
+                None => continue,
+            };
+            let words: Vec<&str> = status_args
+                .split_whitespace()
+                .map(|x| x.trim_matches(|c| c == '"' || c == '\''))
+                .collect();
+            // Named statuses can be cleared by any script
+            if words.iter().any(|x| *x == "--name" || *x == "-n") {
+                continue;
+            }
+            match words.first().map(|x| x.to_lowercase()).as_deref() {
+                Some("maintenance") | Some("waiting") | Some("blocked") => {
+                    sets_status = sets_status.or(Some(i))
+                }
+                _ => clears_status = true,
+            }
+        }
+        if let (Some(status_line), false) = (sets_status, clears_status) {
+            warn(
+                "status-not-cleared",
+                path,
+                Some(line.map_or(status_line + 1, |x| x + status_line)),
+                "Script sets a status but never sets it back to active, so the status will stay \
+                until the script runs again"
+                    .into(),
+            );
+        }
+
+        // Images set by the script, skipping images that come from variables
+        for (i, image_line) in content.lines().enumerate() {
+            let image = IMAGE_SET_REGEX
+                .captures(image_line)
+                .and_then(|x| x.get(1))
+                .and_then(|x| x.as_str().split_whitespace().find(|x| !x.starts_with('-')))
+                .map(|x| x.trim_matches(|c| c == '"' || c == '\''));
+            if let Some(image) = image.filter(|x| !x.contains('$') && is_unpinned(x)) {
+                warn(
+                    "unpinned-image",
+                    path,
+                    Some(line.map_or(i + 1, |x| x + i)),
+                    format!(
+                        "Image {} is not pinned to a version, so it may change whenever it is \
+                        pulled",
+                        image
+                    ),
+                );
+            }
+        }
+    }
+
+    // Declared containers
+    for (name, container) in &lucky_metadata.containers {
+        if container.healthcheck.is_none() {
+            warn(
+                "missing-healthcheck",
+                &lucky_yaml_path,
+                find_line(&lucky_yaml, name, ""),
+                format!(
+                    "Container {} has no healthcheck, so the unit status won't show when it is \
+                    unhealthy",
+                    name
+                ),
+            );
+        }
+        // Images from resources are pinned by the resource revision
+        if container.image_resource.is_none() && is_unpinned(&container.image) {
+            warn(
+                "unpinned-image",
+                &lucky_yaml_path,
+                find_line(&lucky_yaml, "image", &container.image),
+                format!(
+                    "Image {} of container {} is not pinned to a version, so it may change \
+                    whenever it is pulled",
+                    container.image, name
+                ),
+            );
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Get the path to a YAML file in the charm, which may have either a `.yaml` or `.yml` extension
+fn yaml_path(charm_dir: &Path, name: &str) -> PathBuf {
+    let path = charm_dir.join(format!("{}.yaml", name));
+    if path.exists() {
+        path
+    } else {
+        charm_dir.join(format!("{}.yml", name))
+    }
+}
+
+/// Get all of the scripts in the `lucky.yaml`
+fn all_scripts(metadata: &LuckyMetadata) -> impl Iterator<Item = &CharmScript> {
+    metadata
+        .hooks
+        .values()
+        .chain(std::iter::once(&metadata.pre_hook))
+        .chain(std::iter::once(&metadata.post_hook))
+        .chain(metadata.cron_jobs.values())
+        .chain(metadata.actions.values().map(|x| &x.scripts))
+        .chain(metadata.events.values())
+        .chain(metadata.kv_watches.values())
+        .chain(metadata.migrations.values())
+        .flatten()
+        .flat_map(CharmScript::with_on_failure)
+}
+
+/// Whether or not an image reference has no tag or uses the `latest` tag. Images pinned to a
+/// digest are never unpinned.
+fn is_unpinned(image: &str) -> bool {
+    if image.contains('@') {
+        return false;
+    }
+
+    // The tag is after the last `:` of the last path segment, because registries can have ports
+    let name = image.rsplit('/').next().unwrap_or(image);
+    match name.rsplitn(2, ':').collect::<Vec<_>>().as_slice() {
+        [tag, _] => *tag == "latest",
+        _ => true,
+    }
+}
+
+/// Whether or not a file can be executed by its owner. This is always `true` on systems other
+/// than Unix.
+fn is_executable(path: &Path) -> anyhow::Result<bool> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata =
+            fs::metadata(path).context(format!("Could not read file metadata: {:?}", path))?;
+        Ok(metadata.permissions().mode() & 0o100 != 0)
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(true)
+    }
+}
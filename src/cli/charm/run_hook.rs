@@ -0,0 +1,351 @@
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches};
+use crossterm::style::Color;
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use super::test::{mock_relation, MockDaemon};
+use crate::cli::util::{color_stdout, write_field_diff, FieldChange};
+use crate::cli::*;
+use crate::config::load_yaml;
+use crate::juju::MockJujuState;
+use crate::rpc::VarlinkClientInterface;
+
+pub(super) struct RunHookSubcommand;
+
+impl<'a> CliCommand<'a> for RunHookSubcommand {
+    fn get_name(&self) -> &'static str {
+        "run-hook"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Dry-run one of a charm's hooks locally and show what it would change")
+            .arg(Arg::with_name("hook")
+                .help("The name of the hook to run, such as `config-changed`")
+                .required(true))
+            .arg(Arg::with_name("charm_dir")
+                .help("The path to the charm")
+                .required(false)
+                .default_value("."))
+            .arg(Arg::with_name("config")
+                .help("A YAML file with the charm config to run the hook with")
+                .long_help("A YAML file with the charm config to run the hook with. The config \
+                            is merged over the defaults in the charm's config.yaml.")
+                .long("config")
+                .short('c')
+                .takes_value(true))
+            .arg(Arg::with_name("relation")
+                .help("A YAML file with the relation to run the hook in the context of")
+                .long("relation")
+                .short('r')
+                .takes_value(true))
+            .arg(Arg::with_name("not_leader")
+                .help("Run the hook on a unit that isn't the leader")
+                .long("not-leader"))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_charm_run-hook",
+            content: include_str!("run_hook/run_hook.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        let hook_name = args.value_of("hook").expect("Missing required arg: hook");
+        let charm_path = Path::new(
+            args.value_of("charm_dir")
+                .expect("Missing required argument: charm_dir"),
+        )
+        .canonicalize()
+        .context("Could not find charm dir")?;
+
+        // Get the charm config
+        let mut config = load_config_defaults(&charm_path)?;
+        if let Some(file) = args.value_of("config") {
+            let values: HashMap<String, JsonValue> = serde_yaml::from_str(
+                &fs::read_to_string(file).context(format!("Could not read file: {:?}", file))?,
+            )
+            .context(format!("Could not parse config file: {:?}", file))?;
+            config.extend(values);
+        }
+
+        // Get the relation that the hook runs for
+        let relation: Option<RelationFile> = match args.value_of("relation") {
+            Some(file) => Some(
+                serde_yaml::from_str(
+                    &fs::read_to_string(file)
+                        .context(format!("Could not read file: {:?}", file))?,
+                )
+                .context(format!("Could not parse relation file: {:?}", file))?,
+            ),
+            None => None,
+        };
+        let mut relations = HashMap::new();
+        let mut relation_id = None;
+        let mut remote_unit = None;
+        if let Some(relation) = relation {
+            remote_unit = relation
+                .remote_unit
+                .clone()
+                .or_else(|| relation.remote_units.keys().min().cloned());
+            relations.insert(
+                relation.id.clone(),
+                mock_relation(
+                    &relation.id,
+                    relation.remote_units,
+                    relation.remote_app_data,
+                ),
+            );
+            relation_id = Some(relation.id);
+        }
+
+        let work_dir = std::env::temp_dir().join(format!("lucky-run-hook-{}", std::process::id()));
+        let result = (|| -> anyhow::Result<()> {
+            let mut daemon = MockDaemon::start(
+                &charm_path,
+                &work_dir,
+                MockJujuState {
+                    config,
+                    is_leader: !args.is_present("not_leader"),
+                    relations,
+                    ..Default::default()
+                },
+            )?;
+
+            let before = ModelSnapshot::take(&daemon.juju.state());
+            let hook_result =
+                daemon.trigger_hook(hook_name, relation_id.as_deref(), remote_unit.as_deref());
+            let after = ModelSnapshot::take(&daemon.juju.state());
+            let container_changes = daemon.client.container_apply_dry_run().call();
+            daemon.stop()?;
+            hook_result?;
+
+            print_changes(hook_name, &before, &after, &container_changes?.changes)?;
+
+            Ok(())
+        })();
+        fs::remove_dir_all(&work_dir).ok();
+        result?;
+
+        Ok(data)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// The relation file given to `run-hook`
+struct RelationFile {
+    /// The relation ID, such as `db:1`
+    id: String,
+    /// The remote unit that the hook runs for. Defaults to the first remote unit.
+    #[serde(default)]
+    remote_unit: Option<String>,
+    /// The relation data of each of the remote units, keyed by unit name
+    #[serde(default)]
+    remote_units: HashMap<String, HashMap<String, String>>,
+    /// The remote application's relation data
+    #[serde(default)]
+    remote_app_data: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+/// The charm's `config.yaml`
+struct CharmConfig {
+    #[serde(default)]
+    options: HashMap<String, CharmConfigOption>,
+}
+
+#[derive(Deserialize, Debug)]
+/// A config option in the charm's `config.yaml`
+struct CharmConfigOption {
+    #[serde(default)]
+    default: Option<JsonValue>,
+}
+
+/// Get the default charm config from the charm's `config.yaml`, if it has one
+fn load_config_defaults(charm_dir: &Path) -> anyhow::Result<HashMap<String, JsonValue>> {
+    let has_config =
+        charm_dir.join("config.yaml").exists() || charm_dir.join("config.yml").exists();
+    if !has_config {
+        return Ok(HashMap::new());
+    }
+
+    let config: CharmConfig = load_yaml(charm_dir, "config")?;
+    Ok(config
+        .options
+        .into_iter()
+        .filter_map(|(name, option)| Some((name, option.default?)))
+        .collect())
+}
+
+/// The parts of the mock Juju model that a hook can change
+struct ModelSnapshot {
+    status: Option<String>,
+    opened_ports: HashSet<String>,
+    /// The relation data set by the unit, keyed by relation ID
+    relation_data: HashMap<String, HashMap<String, String>>,
+    /// The application relation data set by the unit, keyed by relation ID
+    relation_app_data: HashMap<String, HashMap<String, String>>,
+    leader_data: HashMap<String, String>,
+}
+
+impl ModelSnapshot {
+    fn take(state: &MockJujuState) -> Self {
+        ModelSnapshot {
+            status: state.status.as_ref().map(ToString::to_string),
+            opened_ports: state.opened_ports.clone(),
+            relation_data: state
+                .relations
+                .iter()
+                .map(|(id, relation)| (id.clone(), relation.local_unit_data.clone()))
+                .collect(),
+            relation_app_data: state
+                .relations
+                .iter()
+                .map(|(id, relation)| (id.clone(), relation.local_app_data.clone()))
+                .collect(),
+            leader_data: state.leader_data.clone(),
+        }
+    }
+}
+
+/// Print the changes that the hook made to the model and the container changes that it would
+/// have applied
+fn print_changes(
+    hook_name: &str,
+    before: &ModelSnapshot,
+    after: &ModelSnapshot,
+    container_changes: &[crate::rpc::ContainerChange],
+) -> anyhow::Result<()> {
+    let mut stdout = io::stdout();
+    writeln!(stdout, "Ran hook {}", hook_name)?;
+
+    if before.status != after.status {
+        writeln!(stdout, "\nStatus:")?;
+        write_field_diff(
+            &mut stdout,
+            2,
+            &[FieldChange {
+                field: "status",
+                old: before.status.as_deref(),
+                new: after.status.as_deref(),
+            }],
+        )?;
+    }
+
+    let mut ports: Vec<&String> = before
+        .opened_ports
+        .symmetric_difference(&after.opened_ports)
+        .collect();
+    if !ports.is_empty() {
+        ports.sort();
+        writeln!(stdout, "\nOpened ports:")?;
+        let changes: Vec<FieldChange> = ports
+            .into_iter()
+            .map(|port| {
+                let opened = after.opened_ports.contains(port);
+                FieldChange {
+                    field: port,
+                    old: Some("open").filter(|_| !opened),
+                    new: Some("open").filter(|_| opened),
+                }
+            })
+            .collect();
+        write_field_diff(&mut stdout, 2, &changes)?;
+    }
+
+    let empty = HashMap::new();
+    let mut relation_ids: Vec<&String> = after.relation_data.keys().collect();
+    relation_ids.sort();
+    for relation_id in relation_ids {
+        let changes = data_changes(
+            before.relation_data.get(relation_id).unwrap_or(&empty),
+            after.relation_data.get(relation_id).unwrap_or(&empty),
+        );
+        if !changes.is_empty() {
+            writeln!(stdout, "\nRelation data ({}):", relation_id)?;
+            write_field_diff(&mut stdout, 2, &changes)?;
+        }
+
+        let changes = data_changes(
+            before.relation_app_data.get(relation_id).unwrap_or(&empty),
+            after.relation_app_data.get(relation_id).unwrap_or(&empty),
+        );
+        if !changes.is_empty() {
+            writeln!(stdout, "\nApplication relation data ({}):", relation_id)?;
+            write_field_diff(&mut stdout, 2, &changes)?;
+        }
+    }
+
+    let changes = data_changes(&before.leader_data, &after.leader_data);
+    if !changes.is_empty() {
+        writeln!(stdout, "\nLeader data:")?;
+        write_field_diff(&mut stdout, 2, &changes)?;
+    }
+
+    if container_changes.is_empty() {
+        writeln!(stdout, "\nNo pending container updates")?;
+    } else {
+        writeln!(stdout, "\nContainers:")?;
+    }
+    for change in container_changes {
+        let color = match change.action.as_str() {
+            "create" => Color::Green,
+            "remove" => Color::Red,
+            _ => Color::Yellow,
+        };
+        writeln!(
+            stdout,
+            "  {} container {}",
+            color_stdout(&change.action, color),
+            change
+                .container_name
+                .as_deref()
+                .unwrap_or(crate::types::DEFAULT_CONTAINER_NAME)
+        )?;
+        let fields: Vec<FieldChange> = change
+            .fields
+            .iter()
+            .map(|x| FieldChange {
+                field: &x.field,
+                old: x.old.as_deref(),
+                new: x.new.as_deref(),
+            })
+            .collect();
+        write_field_diff(&mut stdout, 4, &fields)?;
+    }
+
+    Ok(())
+}
+
+/// Get the changes between two sets of key-value data, sorted by key
+fn data_changes<'a>(
+    before: &'a HashMap<String, String>,
+    after: &'a HashMap<String, String>,
+) -> Vec<FieldChange<'a>> {
+    let mut keys: Vec<&String> = before.keys().chain(after.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter(|key| before.get(*key) != after.get(*key))
+        .map(|key| FieldChange {
+            field: key,
+            old: before.get(key).map(String::as_str),
+            new: after.get(key).map(String::as_str),
+        })
+        .collect()
+}
@@ -0,0 +1,533 @@
+use anyhow::{format_err, Context};
+use clap::{App, Arg, ArgMatches};
+use serde::Deserialize;
+use serde_json::Value as JsonValue;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::thread;
+
+use crate::cli::daemon::get_daemon_client;
+use crate::cli::*;
+use crate::config::load_yaml;
+use crate::daemon::LuckyDaemonOptions;
+use crate::juju::{MockJujuBackend, MockJujuState, MockRelation};
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+use crate::types::{juju::CharmMetadata, LuckyMetadata, Platform};
+
+/// The directory in the charm that the test scenarios are loaded from by default
+const SCENARIO_DIR: &str = "tests";
+/// The number of RPC requests that the test daemon handles at the same time
+const RPC_THREADS: usize = 8;
+
+pub(super) struct TestSubcommand;
+
+impl<'a> CliCommand<'a> for TestSubcommand {
+    fn get_name(&self) -> &'static str {
+        "test"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Run a charm's hooks against a simulated Juju")
+            .arg(Arg::with_name("scenario")
+                .help("A test scenario file to run. Defaults to every scenario in the `tests` dir")
+                .long("scenario")
+                .short('s')
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1))
+            .arg(Arg::with_name("charm_dir")
+                .help("The path to the charm you want to test")
+                .required(false)
+                .default_value("."))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_charm_test",
+            content: include_str!("test/test.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        let charm_path = Path::new(
+            args.value_of("charm_dir")
+                .expect("Missing required argument: charm_dir"),
+        )
+        .canonicalize()
+        .context("Could not find charm dir")?;
+
+        // Get the scenario files
+        let scenario_files: Vec<PathBuf> = match args.values_of("scenario") {
+            Some(files) => files.map(PathBuf::from).collect(),
+            None => find_scenarios(&charm_path.join(SCENARIO_DIR))?,
+        };
+        if scenario_files.is_empty() {
+            anyhow::bail!(
+                "No test scenarios found in {:?}",
+                charm_path.join(SCENARIO_DIR)
+            );
+        }
+
+        let mut stdout = io::stdout();
+        let mut failed = 0;
+        for (i, file) in scenario_files.iter().enumerate() {
+            let scenario: TestScenario = serde_yaml::from_str(
+                &fs::read_to_string(file).context(format!("Could not read file: {:?}", file))?,
+            )
+            .context(format!("Could not parse test scenario: {:?}", file))?;
+            let name = scenario
+                .name
+                .clone()
+                .unwrap_or_else(|| file.to_string_lossy().into());
+
+            let work_dir =
+                std::env::temp_dir().join(format!("lucky-test-{}-{}", std::process::id(), i));
+            let result = run_scenario(&charm_path, &work_dir, &scenario);
+            fs::remove_dir_all(&work_dir).ok();
+
+            match result {
+                Ok(failures) if failures.is_empty() => writeln!(stdout, "PASS {}", name)?,
+                Ok(failures) => {
+                    failed += 1;
+                    writeln!(stdout, "FAIL {}", name)?;
+                    for failure in failures {
+                        writeln!(stdout, "    {}", failure)?;
+                    }
+                }
+                Err(e) => {
+                    failed += 1;
+                    writeln!(stdout, "FAIL {}", name)?;
+                    writeln!(stdout, "    {:?}", e)?;
+                }
+            }
+        }
+
+        if failed > 0 {
+            anyhow::bail!("{} of {} test(s) failed", failed, scenario_files.len());
+        }
+
+        Ok(data)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// A test scenario: the state of the simulated Juju model, the hooks to run in it, and what the
+/// model should look like afterwards
+struct TestScenario {
+    /// The name of the test. Defaults to the scenario file name.
+    #[serde(default)]
+    name: Option<String>,
+    /// The charm config
+    #[serde(default)]
+    config: HashMap<String, JsonValue>,
+    /// Whether or not the unit is the leader
+    #[serde(default = "default_true")]
+    leader: bool,
+    /// The leader data
+    #[serde(default)]
+    leader_data: HashMap<String, String>,
+    /// The relations, keyed by relation ID such as `db:1`
+    #[serde(default)]
+    relations: HashMap<String, ScenarioRelation>,
+    /// The paths to the resources, keyed by resource name
+    #[serde(default)]
+    resources: HashMap<String, String>,
+    /// The hooks to run, in order
+    hooks: Vec<ScenarioHook>,
+    /// What the model should look like after the hooks have run
+    #[serde(default)]
+    expect: ScenarioExpectations,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// A relation in a test scenario
+struct ScenarioRelation {
+    /// The relation data of each of the remote units, keyed by unit name
+    #[serde(default)]
+    remote_units: HashMap<String, HashMap<String, String>>,
+    /// The remote application's relation data
+    #[serde(default)]
+    remote_app_data: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+/// A hook to run in a test scenario
+enum ScenarioHook {
+    /// Just the name of the hook
+    Name(String),
+    /// A hook with the context that it runs in
+    #[serde(rename_all = "kebab-case")]
+    Detailed {
+        hook: String,
+        /// The relation ID for relation hooks
+        #[serde(default)]
+        relation: Option<String>,
+        /// The remote unit for relation hooks
+        #[serde(default)]
+        remote_unit: Option<String>,
+        /// Charm config to change before the hook is run
+        #[serde(default)]
+        config: HashMap<String, JsonValue>,
+    },
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// What the model should look like after the hooks in a test scenario have run. Only the values
+/// that are given are checked.
+struct ScenarioExpectations {
+    /// The unit status: `active`, `waiting`, `maintenance`, or `blocked`
+    #[serde(default)]
+    status: Option<String>,
+    /// The unit status message
+    #[serde(default)]
+    status_message: Option<String>,
+    /// The ports that should be open, such as `80/tcp`
+    #[serde(default)]
+    opened_ports: Option<Vec<String>>,
+    /// Relation data that this unit should have set, keyed by relation ID
+    #[serde(default)]
+    relation_data: HashMap<String, HashMap<String, String>>,
+    /// Leader data that should have been set
+    #[serde(default)]
+    leader_data: HashMap<String, String>,
+    /// Values that should be in the global namespace of the unit key-value store
+    #[serde(default)]
+    kv: HashMap<String, String>,
+}
+
+/// Get the scenario files in a directory
+fn find_scenarios(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).context(format!("Could not read dir: {:?}", dir))? {
+        let path = entry?.path();
+        let is_yaml = path
+            .extension()
+            .map_or(false, |x| x == "yaml" || x == "yml");
+        if path.is_file() && is_yaml {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+/// A Lucky daemon running in this process with a mock Juju backend
+///
+/// Containers are not run: the daemon is run with container support disabled, but the changes to
+/// the container configuration are still recorded.
+pub(super) struct MockDaemon {
+    /// The mock Juju model that the daemon runs in
+    pub juju: Arc<MockJujuBackend>,
+    /// The client connected to the daemon
+    pub client: VarlinkClient,
+    server_thread: thread::JoinHandle<varlink::Result<()>>,
+}
+
+impl MockDaemon {
+    /// Start a daemon for the charm in the given mock Juju model, keeping its data and socket in
+    /// `work_dir`
+    pub fn start(
+        charm_dir: &Path,
+        work_dir: &Path,
+        juju_state: MockJujuState,
+    ) -> anyhow::Result<Self> {
+        let data_dir = work_dir.join("data");
+        fs::create_dir_all(&data_dir).context(format!("Could not create dir: {:?}", data_dir))?;
+        let socket_path = work_dir.join("lucky.sock").to_string_lossy().to_string();
+
+        let charm_metadata: CharmMetadata = load_yaml(charm_dir, "metadata")?;
+        let unit_name = format!("{}/0", charm_metadata.name);
+        let juju = Arc::new(MockJujuBackend::new(juju_state));
+
+        // Load the lucky.yaml without container support
+        let mut lucky_metadata: LuckyMetadata = load_yaml(charm_dir, "lucky")?;
+        lucky_metadata.use_docker = false;
+        lucky_metadata.platform = Platform::Machine;
+
+        // The daemon and the charm's scripts find the unit and the daemon in the environment
+        std::env::set_var("JUJU_UNIT_NAME", &unit_name);
+        std::env::set_var("JUJU_CHARM_DIR", charm_dir);
+        std::env::set_var("LUCKY_DAEMON_SOCKET", &socket_path);
+
+        let stop_listening = Arc::new(AtomicBool::new(false));
+        let service = crate::daemon::get_service(LuckyDaemonOptions {
+            lucky_metadata,
+            charm_dir: charm_dir.to_path_buf(),
+            data_dir,
+            socket_path: PathBuf::from(&socket_path),
+            stop_listening: stop_listening.clone(),
+            juju: juju.clone(),
+        });
+        let listen_address = format!("unix:{}", socket_path);
+        let server_thread = thread::spawn(move || {
+            varlink::listen(
+                service,
+                &listen_address,
+                &varlink::ListenConfig {
+                    max_worker_threads: RPC_THREADS,
+                    stop_listening: Some(stop_listening),
+                    ..Default::default()
+                },
+            )
+        });
+        let client = get_daemon_client(&socket_path)?;
+
+        Ok(MockDaemon {
+            juju,
+            client,
+            server_thread,
+        })
+    }
+
+    /// Run a hook, in the context of a relation if `relation_id` is given
+    pub fn trigger_hook(
+        &mut self,
+        hook_name: &str,
+        relation_id: Option<&str>,
+        remote_unit: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let mut environment = HashMap::new();
+        if let Some(relation_id) = relation_id {
+            let relation_name = self
+                .juju
+                .state()
+                .relations
+                .get(relation_id)
+                .map(|x| x.name.clone())
+                .ok_or_else(|| format_err!("Relation {} does not exist", relation_id))?;
+            environment.insert("JUJU_RELATION".to_string(), relation_name);
+            environment.insert("JUJU_RELATION_ID".to_string(), relation_id.into());
+        }
+        if let Some(remote_unit) = remote_unit {
+            environment.insert("JUJU_REMOTE_UNIT".to_string(), remote_unit.into());
+            environment.insert(
+                "JUJU_REMOTE_APP".to_string(),
+                remote_unit.split('/').next().unwrap_or("").into(),
+            );
+        }
+
+        log::info!("Running hook: {}", hook_name);
+        self.client
+            .trigger_hook(hook_name.into(), environment)
+            .call()
+            .map_err(|e| format_err!("{}", e).context(format!("Hook {} failed", hook_name)))?;
+
+        Ok(())
+    }
+
+    /// Stop the daemon and wait for it to exit
+    pub fn stop(mut self) -> anyhow::Result<()> {
+        self.client.stop_daemon().call()?;
+        self.server_thread
+            .join()
+            .expect("Could not join to server thread")?;
+
+        Ok(())
+    }
+}
+
+/// Get the mock relation for a relation ID, such as `db:1`, where the relation name is the part
+/// of the ID before the `:`
+pub(super) fn mock_relation(
+    relation_id: &str,
+    remote_units: HashMap<String, HashMap<String, String>>,
+    remote_app_data: HashMap<String, String>,
+) -> MockRelation {
+    MockRelation {
+        name: relation_id.split(':').next().unwrap_or(relation_id).into(),
+        remote_units,
+        remote_app_data,
+        ..Default::default()
+    }
+}
+
+/// Run the hooks of a test scenario against a daemon that uses a mock Juju backend and return the
+/// expectations that weren't met
+fn run_scenario(
+    charm_dir: &Path,
+    work_dir: &Path,
+    scenario: &TestScenario,
+) -> anyhow::Result<Vec<String>> {
+    let mut daemon = MockDaemon::start(
+        charm_dir,
+        work_dir,
+        MockJujuState {
+            config: scenario.config.clone(),
+            is_leader: scenario.leader,
+            leader_data: scenario.leader_data.clone(),
+            resources: scenario.resources.clone(),
+            relations: scenario
+                .relations
+                .iter()
+                .map(|(id, relation)| {
+                    (
+                        id.clone(),
+                        mock_relation(
+                            id,
+                            relation.remote_units.clone(),
+                            relation.remote_app_data.clone(),
+                        ),
+                    )
+                })
+                .collect(),
+            ..Default::default()
+        },
+    )?;
+
+    let result = run_hooks(&mut daemon, scenario);
+    let juju = daemon.juju.clone();
+    daemon.stop()?;
+
+    let mut failures = result?;
+    check_expectations(&juju.state(), &scenario.expect, &mut failures);
+
+    Ok(failures)
+}
+
+/// Run the hooks of a test scenario on the daemon and return the expectations of the key-value
+/// store that weren't met
+fn run_hooks(daemon: &mut MockDaemon, scenario: &TestScenario) -> anyhow::Result<Vec<String>> {
+    for hook in &scenario.hooks {
+        match hook {
+            ScenarioHook::Name(name) => daemon.trigger_hook(name, None, None)?,
+            ScenarioHook::Detailed {
+                hook,
+                relation,
+                remote_unit,
+                config,
+            } => {
+                daemon.juju.state().config.extend(config.clone());
+                daemon.trigger_hook(hook, relation.as_deref(), remote_unit.as_deref())?;
+            }
+        }
+    }
+
+    // The key-value store is checked while the daemon is still running
+    let mut failures = Vec::new();
+    let mut expected_kv: Vec<(&String, &String)> = scenario.expect.kv.iter().collect();
+    expected_kv.sort();
+    for (key, expected) in expected_kv {
+        let value = daemon.client.unit_kv_get(key.clone(), None).call()?.value;
+        if value.as_ref() != Some(expected) {
+            failures.push(format!(
+                "Expected key-value {} to be {:?}, but it was {:?}",
+                key, expected, value
+            ));
+        }
+    }
+
+    Ok(failures)
+}
+
+/// Compare the mock Juju model to the expectations of a test scenario, adding a message to
+/// `failures` for each expectation that isn't met
+fn check_expectations(
+    state: &MockJujuState,
+    expect: &ScenarioExpectations,
+    failures: &mut Vec<String>,
+) {
+    if let Some(expected) = &expect.status {
+        let actual = state.status.as_ref().map(|x| x.state.as_ref().to_string());
+        if actual.as_ref() != Some(expected) {
+            failures.push(format!(
+                "Expected status {:?}, but it was {:?}",
+                expected, actual
+            ));
+        }
+    }
+
+    if let Some(expected) = &expect.status_message {
+        let actual = state.status.as_ref().and_then(|x| x.message.clone());
+        if actual.as_ref() != Some(expected) {
+            failures.push(format!(
+                "Expected status message {:?}, but it was {:?}",
+                expected, actual
+            ));
+        }
+    }
+
+    if let Some(expected) = &expect.opened_ports {
+        let mut expected: Vec<String> = expected
+            .iter()
+            .map(|x| crate::juju::normalize_port(x))
+            .collect();
+        expected.sort();
+        let mut actual: Vec<String> = state.opened_ports.iter().cloned().collect();
+        actual.sort();
+        if actual != expected {
+            failures.push(format!(
+                "Expected opened ports {:?}, but they were {:?}",
+                expected, actual
+            ));
+        }
+    }
+
+    for (relation_id, expected) in &expect.relation_data {
+        let actual = state
+            .relations
+            .get(relation_id)
+            .map(|x| x.local_unit_data.clone())
+            .unwrap_or_default();
+        check_data(
+            &format!("relation {} data", relation_id),
+            expected,
+            &actual,
+            failures,
+        );
+    }
+
+    check_data(
+        "leader data",
+        &expect.leader_data,
+        &state.leader_data,
+        failures,
+    );
+}
+
+/// Check that the `actual` data has every key-value pair in the `expected` data
+fn check_data(
+    description: &str,
+    expected: &HashMap<String, String>,
+    actual: &HashMap<String, String>,
+    failures: &mut Vec<String>,
+) {
+    let mut keys: Vec<&String> = expected.keys().collect();
+    keys.sort();
+    for key in keys {
+        let expected = expected.get(key);
+        let actual = actual.get(key);
+        if actual != expected {
+            failures.push(format!(
+                "Expected {} {} to be {:?}, but it was {:?}",
+                description, key, expected, actual
+            ));
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
@@ -0,0 +1,512 @@
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches};
+use serde_yaml::Value;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cli::*;
+use crate::config::load_yaml;
+use crate::types::{
+    condition::Condition, script_dependencies, CharmScript, CharmScriptType, JobSchedule,
+    LuckyMetadata, ScriptDependencyError, LUCKY_YAML_SCHEMA_VERSION,
+};
+
+/// The JSON Schema of the latest version of the `lucky.yaml` file
+const LUCKY_YAML_SCHEMA: &str = include_str!("validate/lucky-yaml.schema.json");
+
+pub(super) struct ValidateSubcommand;
+
+impl<'a> CliCommand<'a> for ValidateSubcommand {
+    fn get_name(&self) -> &'static str {
+        "validate"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Check a charm's lucky.yaml for problems")
+            .arg(Arg::with_name("schema")
+                .help("Print the JSON Schema of the lucky.yaml instead of validating a charm")
+                .long("schema"))
+            .arg(Arg::with_name("charm_dir")
+                .help("The path to the charm you want to validate")
+                .required(false)
+                .default_value("."))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_charm_validate",
+            content: include_str!("validate/validate.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        let mut stdout = io::stdout();
+
+        if args.is_present("schema") {
+            writeln!(stdout, "{}", LUCKY_YAML_SCHEMA)?;
+            return Ok(data);
+        }
+
+        let charm_path = Path::new(
+            args.value_of("charm_dir")
+                .expect("Missing required argument: charm_dir"),
+        );
+
+        let problems = validate_charm(charm_path)?;
+        for problem in &problems {
+            writeln!(stdout, "{}", problem)?;
+        }
+
+        let error_count = problems.iter().filter(|x| x.is_error()).count();
+        if error_count > 0 {
+            anyhow::bail!("Found {} error(s) in the lucky.yaml", error_count);
+        }
+        writeln!(stdout, "The lucky.yaml is valid")?;
+
+        Ok(data)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// How bad a problem in the `lucky.yaml` is
+pub(super) enum Severity {
+    /// The charm will not work
+    Error,
+    /// The charm will work, but probably not like it was meant to
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+/// A problem found in a charm's `lucky.yaml`
+pub(super) struct Problem {
+    pub severity: Severity,
+    /// The file that the problem is in
+    pub file: PathBuf,
+    /// The line of the file that the problem is on, starting at 1, if it is known
+    pub line: Option<usize>,
+    /// The column of the line that the problem is at, starting at 1, if it is known
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl Problem {
+    /// Whether or not the problem is an error
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+            if let Some(column) = self.column {
+                write!(f, ":{}", column)?;
+            }
+        }
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, ": {}: {}", severity, self.message)
+    }
+}
+
+/// Check the `lucky.yaml` of a charm and return the problems found in it, ordered by line
+///
+/// This reports the unknown keys and type errors in the file, as well as references to things that
+/// don't exist in the charm, such as scripts that are run by hooks but are not in the
+/// `host_scripts` dir.
+#[allow(clippy::too_many_lines)]
+pub(super) fn validate_charm(charm_dir: &Path) -> anyhow::Result<Vec<Problem>> {
+    let file = if charm_dir.join("lucky.yaml").exists() {
+        charm_dir.join("lucky.yaml")
+    } else {
+        charm_dir.join("lucky.yml")
+    };
+    if !file.exists() {
+        anyhow::bail!(
+            "Could not locate a lucky.yaml file in the directory: {:?}",
+            charm_dir
+        );
+    }
+    let content = fs::read_to_string(&file).context(format!("Could not read file: {:?}", file))?;
+
+    let mut problems = Vec::new();
+    let mut add_problem = |severity, line: Option<usize>, message: String| {
+        problems.push(Problem {
+            severity,
+            file: file.clone(),
+            line,
+            column: None,
+            message,
+        })
+    };
+
+    // Unknown keys and type errors fail the parsing, so only the first one can be reported
+    let metadata: LuckyMetadata = match serde_yaml::from_str(&content) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            let location = e.location();
+            return Ok(vec![Problem {
+                severity: Severity::Error,
+                file: file.clone(),
+                line: location.as_ref().map(serde_yaml::Location::line),
+                column: location.as_ref().map(|x| x.column() + 1),
+                message: e.to_string(),
+            }]);
+        }
+    };
+
+    // Check the schema version
+    if let Some(version) = metadata
+        .version
+        .filter(|x| *x == 0 || *x > LUCKY_YAML_SCHEMA_VERSION)
+    {
+        add_problem(
+            Severity::Error,
+            find_line(&content, "version", &version.to_string()),
+            format!(
+                "Unsupported lucky.yaml version {}. This version of Lucky supports up to \
+                version {}.",
+                version, LUCKY_YAML_SCHEMA_VERSION
+            ),
+        );
+    }
+
+    // Migrations start after version 0, which is the version of units that have never migrated
+    if metadata.migrations.contains_key(&0) {
+        add_problem(
+            Severity::Error,
+            find_line(&content, "0", ""),
+            "Migration versions must start at 1".into(),
+        );
+    }
+
+    // Check the cron schedules
+    for schedule in metadata.cron_jobs.keys() {
+        if let Err(e) = schedule.parse::<JobSchedule>() {
+            add_problem(
+                Severity::Error,
+                find_line(&content, "", schedule),
+                e.to_string(),
+            );
+        }
+    }
+
+    // Check the scripts
+    let mut script_lists: Vec<(String, &Vec<CharmScript>)> = vec![
+        ("pre-hook".into(), &metadata.pre_hook),
+        ("post-hook".into(), &metadata.post_hook),
+    ];
+    script_lists.extend(
+        metadata
+            .hooks
+            .iter()
+            .map(|(name, scripts)| (format!("hook {}", name), scripts)),
+    );
+    script_lists.extend(
+        metadata
+            .cron_jobs
+            .iter()
+            .map(|(schedule, scripts)| (format!("cron job {:?}", schedule), scripts)),
+    );
+    script_lists.extend(
+        metadata
+            .actions
+            .iter()
+            .map(|(name, action)| (format!("action {}", name), &action.scripts)),
+    );
+    script_lists.extend(
+        metadata
+            .events
+            .iter()
+            .map(|(name, scripts)| (format!("event {}", name), scripts)),
+    );
+    script_lists.extend(
+        metadata
+            .kv_watches
+            .iter()
+            .map(|(key, scripts)| (format!("kv watch {:?}", key), scripts)),
+    );
+    script_lists.extend(
+        metadata
+            .migrations
+            .iter()
+            .map(|(version, scripts)| (format!("migration {}", version), scripts)),
+    );
+    for (owner, scripts) in script_lists {
+        for script in scripts.iter().flat_map(CharmScript::with_on_failure) {
+            // Container scripts can't be interrupted
+            let in_container = match &script.script_type {
+                CharmScriptType::Container { .. } | CharmScriptType::InlineContainer { .. } => true,
+                CharmScriptType::Host { .. } | CharmScriptType::InlineHost { .. } => false,
+            };
+            if let (Some(timeout), true) = (script.timeout, in_container) {
+                add_problem(
+                    Severity::Warning,
+                    find_line(&content, "timeout", &timeout.to_string()),
+                    format!(
+                        "Container script for {} has a timeout, but only host scripts can time out",
+                        owner
+                    ),
+                );
+            }
+            if let (Some(interpreter), true) = (&script.interpreter, in_container) {
+                add_problem(
+                    Severity::Warning,
+                    find_line(&content, "interpreter", interpreter),
+                    format!(
+                        "Container script for {} has an interpreter, but only host scripts are run \
+                        with an interpreter",
+                        owner
+                    ),
+                );
+            }
+
+            if let (Some(user), true) = (&script.run_as, in_container) {
+                add_problem(
+                    Severity::Warning,
+                    find_line(&content, "run-as", user),
+                    format!(
+                        "Container script for {} has a `run-as` user, but only host scripts are \
+                        run as another user",
+                        owner
+                    ),
+                );
+            }
+            if let (Some(_), true) = (&script.limits, in_container) {
+                add_problem(
+                    Severity::Warning,
+                    find_line(&content, "limits", ""),
+                    format!(
+                        "Container script for {} has resource limits, but only host scripts can \
+                        be limited",
+                        owner
+                    ),
+                );
+            }
+
+            if let Some(when) = &script.when {
+                if let Err(e) = when.parse::<Condition>() {
+                    add_problem(
+                        Severity::Error,
+                        find_line(&content, "when", when),
+                        format!("Script for {} has an invalid condition: {}", owner, e),
+                    );
+                }
+            }
+
+            for (var, template) in &script.env {
+                if let Err(e) = handlebars::Template::compile(template) {
+                    add_problem(
+                        Severity::Error,
+                        find_line(&content, var, ""),
+                        format!(
+                            "Script for {} has an invalid template for env var {}: {}",
+                            owner, var, e
+                        ),
+                    );
+                }
+            }
+
+            let (key, dir, name, container_name) = match &script.script_type {
+                CharmScriptType::Host { host_script, .. } => {
+                    ("host-script", "host_scripts", host_script, None)
+                }
+                CharmScriptType::Container {
+                    container_script,
+                    container_name,
+                    ..
+                } => (
+                    "container-script",
+                    "container_scripts",
+                    container_script,
+                    container_name.as_ref(),
+                ),
+                CharmScriptType::InlineContainer { container_name, .. } => {
+                    if let Some(container_name) = container_name {
+                        check_script_container(
+                            &metadata,
+                            &content,
+                            &owner,
+                            container_name,
+                            &mut add_problem,
+                        );
+                    }
+                    continue;
+                }
+                CharmScriptType::InlineHost { .. } => continue,
+            };
+
+            if !charm_dir.join(dir).join(name).is_file() {
+                add_problem(
+                    Severity::Error,
+                    find_line(&content, key, name),
+                    format!("Script for {} does not exist: {}", owner, script.name()),
+                );
+            }
+            if let Some(container_name) = container_name {
+                check_script_container(
+                    &metadata,
+                    &content,
+                    &owner,
+                    container_name,
+                    &mut add_problem,
+                );
+            }
+        }
+
+        if let Err(e) = script_dependencies(scripts) {
+            let line = match &e {
+                ScriptDependencyError::UnknownDependency { dependency, .. } => {
+                    find_line(&content, "after", dependency)
+                        .or_else(|| find_line(&content, "requires", dependency))
+                }
+                ScriptDependencyError::DuplicateId(id) => find_line(&content, "id", id),
+                ScriptDependencyError::Cycle(ids) => {
+                    ids.first().and_then(|x| find_line(&content, "id", x))
+                }
+            };
+            add_problem(
+                Severity::Error,
+                line,
+                format!("Invalid script dependencies for {}: {}", owner, e),
+            );
+        }
+    }
+
+    // Check the containers
+    for (name, container) in &metadata.containers {
+        for dependency in &container.depends_on {
+            if !metadata.containers.contains_key(dependency) {
+                add_problem(
+                    Severity::Error,
+                    find_line(&content, "depends-on", dependency),
+                    format!(
+                        "Container {} depends on container {}, which is not declared",
+                        name, dependency
+                    ),
+                );
+            }
+        }
+        for file in &container.files {
+            if !charm_dir.join(&file.template).is_file() {
+                add_problem(
+                    Severity::Error,
+                    find_line(&content, "template", &file.template),
+                    format!(
+                        "Template for container {} does not exist: {}",
+                        name, file.template
+                    ),
+                );
+            }
+        }
+    }
+
+    // Check the service scripts
+    for (name, service) in &metadata.service_scripts {
+        if !charm_dir
+            .join("host_scripts")
+            .join(&service.host_script)
+            .is_file()
+        {
+            add_problem(
+                Severity::Error,
+                find_line(&content, "host-script", &service.host_script),
+                format!(
+                    "Script for service script {} does not exist: {}",
+                    name, service.host_script
+                ),
+            );
+        }
+    }
+
+    // Juju will only run the actions that are defined in the actions.yaml
+    if !metadata.actions.is_empty() {
+        match load_yaml::<HashMap<String, Value>>(charm_dir, "actions") {
+            Ok(actions) => {
+                for name in metadata.actions.keys() {
+                    if !actions.contains_key(name) {
+                        add_problem(
+                            Severity::Warning,
+                            find_line(&content, name, ""),
+                            format!(
+                                "Action {} is not defined in the actions.yaml, so Juju will not \
+                                run it",
+                                name
+                            ),
+                        );
+                    }
+                }
+            }
+            Err(_) => add_problem(
+                Severity::Warning,
+                find_line(&content, "actions", ""),
+                "The lucky.yaml defines actions, but the charm does not have an actions.yaml file"
+                    .into(),
+            ),
+        }
+    }
+
+    problems.sort_by_key(|x| x.line);
+
+    Ok(problems)
+}
+
+/// Warn about a script that runs in a container that isn't declared in the `lucky.yaml`
+///
+/// This is only a warning because containers can also be created by the charm's scripts.
+fn check_script_container(
+    metadata: &LuckyMetadata,
+    content: &str,
+    owner: &str,
+    container_name: &str,
+    add_problem: &mut impl FnMut(Severity, Option<usize>, String),
+) {
+    if metadata.containers.is_empty() || metadata.containers.contains_key(container_name) {
+        return;
+    }
+
+    add_problem(
+        Severity::Warning,
+        find_line(content, "container-name", container_name),
+        format!(
+            "Script for {} runs in container {}, which is not declared",
+            owner, container_name
+        ),
+    );
+}
+
+/// Find the first line, starting at 1, that sets the given key to a value containing `value`
+///
+/// The key may be empty to find the first line that starts with `value`, such as the key of a
+/// mapping.
+pub(super) fn find_line(content: &str, key: &str, value: &str) -> Option<usize> {
+    content
+        .lines()
+        .position(|line| {
+            let line = line.trim_start();
+            let line = line.strip_prefix("- ").unwrap_or(line);
+            let line = line.trim_start_matches(|c| c == '"' || c == '\'');
+            if key.is_empty() {
+                return line.starts_with(value);
+            }
+
+            match line.strip_prefix(key) {
+                Some(rest) => rest.starts_with(':') && rest.contains(value),
+                None => false,
+            }
+        })
+        .map(|x| x + 1)
+}
@@ -1,19 +1,30 @@
-use clap::{App, AppSettings, ArgMatches};
+use clap::{App, AppSettings, Arg, ArgMatches};
 
 use std::collections::HashMap;
 
 // Subcommands
 mod container;
+mod docker;
+mod emit;
+mod fetch;
 mod get_config;
 mod get_resource;
+mod host_service;
 mod kv;
 mod leader;
+mod lock;
+mod model;
+mod peer;
 mod port;
 mod private_address;
 mod public_address;
 mod random;
 mod relation;
+mod render_template;
+mod script;
+mod secret;
 mod set_status;
+mod status;
 
 #[cfg(feature = "daemon")]
 use crate::cli::daemon::get_daemon_client;
@@ -21,6 +32,43 @@ use crate::cli::daemon::get_daemon_client;
 use crate::cli::daemon::{get_daemon_connection_args, get_daemon_socket_path};
 use crate::cli::*;
 
+/// The environment variable that sets the default of the "format" argument
+const OUTPUT_FORMAT_ENV_VAR: &str = "LUCKY_OUTPUT_FORMAT";
+
+/// Return the "format" argument for use in subcommands that print data
+///
+/// The default format is taken from the `LUCKY_OUTPUT_FORMAT` environment variable so that scripts
+/// can get JSON output from every command by setting it once.
+fn format_arg<'a>() -> Arg<'a> {
+    Arg::with_name("format")
+        .help("The format to print the output in")
+        .long_help(concat!(
+            "The format to print the output in: `text` for the human-readable format or `json` ",
+            "for a format that scripts can parse reliably. The default can be set with the ",
+            "`LUCKY_OUTPUT_FORMAT` environment variable."
+        ))
+        .long("format")
+        .short('f')
+        .takes_value(true)
+        .possible_values(&["text", "json"])
+        .env(OUTPUT_FORMAT_ENV_VAR)
+        .default_value("text")
+}
+
+/// Get whether JSON output was requested with the "format" argument
+fn is_json_format(args: &ArgMatches) -> bool {
+    args.value_of("format") == Some("json")
+}
+
+/// Get the depth of the custom event or kv watch that the current script was run for, if any
+///
+/// The daemon uses it to keep events and kv watches that trigger each other from looping forever.
+fn get_event_depth() -> Option<i64> {
+    std::env::var("LUCKY_EVENT_DEPTH")
+        .ok()
+        .and_then(|x| x.parse().ok())
+}
+
 pub(super) struct ClientSubcommand;
 
 impl<'a> CliCommand<'a> for ClientSubcommand {
@@ -45,14 +93,25 @@ impl<'a> CliCommand<'a> for ClientSubcommand {
             Box::new(set_status::SetStatusSubcommand),
             Box::new(kv::KvSubcommand),
             Box::new(container::ContainerSubcommand),
+            Box::new(docker::DockerSubcommand),
+            Box::new(host_service::HostServiceSubcommand),
+            Box::new(emit::EmitSubcommand),
             Box::new(public_address::PublicAddressSubcommand),
             Box::new(private_address::PrivateAddressSubcommand),
             Box::new(get_config::GetConfigSubcommand),
             Box::new(port::PortSubcommand),
             Box::new(relation::RelationSubcommand),
             Box::new(leader::LeaderSubcommand),
+            Box::new(peer::PeerSubcommand),
             Box::new(random::RandomSubcommand),
             Box::new(get_resource::GetResourceSubcommand),
+            Box::new(fetch::FetchSubcommand),
+            Box::new(render_template::RenderTemplateSubcommand),
+            Box::new(secret::SecretSubcommand),
+            Box::new(model::ModelSubcommand),
+            Box::new(script::ScriptSubcommand),
+            Box::new(status::StatusSubcommand),
+            Box::new(lock::LockSubcommand),
         ]
     }
 
@@ -70,9 +129,14 @@ impl<'a> CliCommand<'a> for ClientSubcommand {
 
     #[cfg(feature = "daemon")]
     fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
-        // Skip creation of client data if the matched subcommand was "random", which doesn't need
-        // the client connection.
-        if args.subcommand_matches("random").is_some() {
+        // Skip creation of client data if the matched subcommand was "random" or
+        // "container env exec", which don't need the client connection.
+        let is_env_exec = args
+            .subcommand_matches("container")
+            .and_then(|x| x.subcommand_matches("env"))
+            .and_then(|x| x.subcommand_matches("exec"))
+            .is_some();
+        if args.subcommand_matches("random").is_some() || is_env_exec {
             return Ok(data);
         }
 
@@ -3,13 +3,27 @@ use clap::{App, Arg, ArgMatches};
 use crate::cli::*;
 
 mod apply_updates;
+mod backup;
+mod check_images;
 mod delete;
 mod env;
 mod image;
+mod logs;
 mod port;
+mod restart;
+mod restore;
 mod set_command;
+mod set_devices;
+mod set_dns;
 mod set_entrypoint;
+mod set_extra_host;
+mod set_gpus;
 mod set_network;
+mod set_restart_policy;
+mod set_update_strategy;
+mod start;
+mod stop;
+mod update_hook;
 mod volume;
 
 pub(super) struct ContainerSubcommand;
@@ -30,13 +44,27 @@ impl<'a> CliCommand<'a> for ContainerSubcommand {
         vec![
             Box::new(image::ImageSubcommand),
             Box::new(apply_updates::ApplyUpdatesSubcommand),
+            Box::new(check_images::CheckImagesSubcommand),
             Box::new(env::EnvSubcommand),
             Box::new(set_entrypoint::SetEntrypointSubcommand),
             Box::new(set_command::SetCommandSubcommand),
             Box::new(volume::VolumeSubcommand),
             Box::new(delete::DeleteSubcommand),
+            Box::new(backup::BackupSubcommand),
+            Box::new(restore::RestoreSubcommand),
+            Box::new(start::StartSubcommand),
+            Box::new(stop::StopSubcommand),
+            Box::new(restart::RestartSubcommand),
+            Box::new(logs::LogsSubcommand),
             Box::new(port::PortSubcommand),
             Box::new(set_network::SetNetworkSubcommand),
+            Box::new(set_dns::SetDnsSubcommand),
+            Box::new(set_extra_host::SetExtraHostSubcommand),
+            Box::new(set_devices::SetDevicesSubcommand),
+            Box::new(set_gpus::SetGpusSubcommand),
+            Box::new(set_restart_policy::SetRestartPolicySubcommand),
+            Box::new(set_update_strategy::SetUpdateStrategySubcommand),
+            Box::new(update_hook::UpdateHookSubcommand),
         ]
     }
 
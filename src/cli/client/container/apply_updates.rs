@@ -1,5 +1,9 @@
-use clap::{App, ArgMatches};
+use clap::{App, Arg, ArgMatches};
+use crossterm::style::Color;
 
+use std::io::Write;
+
+use crate::cli::util::{color_stdout, write_field_diff, FieldChange};
 use crate::cli::*;
 use crate::rpc::{VarlinkClient, VarlinkClientInterface};
 
@@ -15,6 +19,19 @@ impl<'a> CliCommand<'a> for ApplyUpdatesSubcommand {
         self.get_base_app()
             .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
             .about("Apply pending container configuration updates")
+            .alias("apply")
+            .arg(Arg::with_name("now")
+                .help("Apply the pending updates now instead of at the end of the hook")
+                .long_help(concat!(
+                    "Apply the pending updates now instead of at the end of the hook. The ",
+                    "updates are always applied immediately when this command is run, so this ",
+                    "only makes the intent explicit, as in `lucky container apply --now`."
+                ))
+                .long("now")
+                .conflicts_with("dry_run"))
+            .arg(Arg::with_name("dry_run")
+                .help("Show the pending updates instead of applying them")
+                .long("dry-run"))
     }
 
     fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
@@ -28,7 +45,7 @@ impl<'a> CliCommand<'a> for ApplyUpdatesSubcommand {
         })
     }
 
-    fn execute_command(&self, _args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
         // Get client connection
         let mut client: Box<VarlinkClient> = data
             .remove("client")
@@ -36,6 +53,48 @@ impl<'a> CliCommand<'a> for ApplyUpdatesSubcommand {
             .downcast()
             .expect("Invalid type");
 
+        if args.is_present("dry_run") {
+            let changes = client.container_apply_dry_run().call()?.changes;
+            let mut stdout = std::io::stdout();
+
+            if changes.is_empty() {
+                writeln!(stdout, "No pending container updates")?;
+            }
+
+            for change in changes {
+                let color = match change.action.as_str() {
+                    "create" => Color::Green,
+                    "remove" => Color::Red,
+                    _ => Color::Yellow,
+                };
+                writeln!(
+                    stdout,
+                    "{} container {}",
+                    color_stdout(&change.action, color),
+                    change
+                        .container_name
+                        .as_deref()
+                        .unwrap_or(crate::types::DEFAULT_CONTAINER_NAME)
+                )?;
+
+                if change.fields.is_empty() {
+                    writeln!(stdout, "  (no configuration changes)")?;
+                }
+                let fields: Vec<FieldChange> = change
+                    .fields
+                    .iter()
+                    .map(|x| FieldChange {
+                        field: &x.field,
+                        old: x.old.as_deref(),
+                        new: x.new.as_deref(),
+                    })
+                    .collect();
+                write_field_diff(&mut stdout, 2, &fields)?;
+            }
+
+            return Ok(data);
+        }
+
         // Apply the container configuration
         client.container_apply().call()?;
 
@@ -0,0 +1,69 @@
+use clap::{App, Arg, ArgMatches};
+
+use std::io::Write;
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct BackupSubcommand;
+
+impl<'a> CliCommand<'a> for BackupSubcommand {
+    fn get_name(&self) -> &'static str {
+        "backup"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("Back up the container's volumes and print the path to the backup")
+            .arg(Arg::with_name("storage")
+                .help("Write the backup to this Juju storage")
+                .long("storage")
+                .short('s')
+                .value_name("storage_name")
+                .takes_value(true)
+                .conflicts_with("dir"))
+            .arg(Arg::with_name("dir")
+                .help("Write the backup to this directory")
+                .long("dir")
+                .short('d')
+                .takes_value(true))
+            .arg(super::container_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_container_backup",
+            content: include_str!("cli_help/backup.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let container = args.value_of("container");
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let path = client
+            .container_backup(
+                container.map(Into::into),
+                args.value_of("storage").map(Into::into),
+                args.value_of("dir").map(Into::into),
+            )
+            .call()?
+            .path;
+
+        writeln!(std::io::stdout(), "{}", path)?;
+
+        Ok(data)
+    }
+}
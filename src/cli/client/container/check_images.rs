@@ -0,0 +1,106 @@
+use clap::{App, Arg, ArgMatches};
+use crossterm::style::Color;
+
+use std::io::Write;
+
+use crate::cli::util::color_stdout;
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct CheckImagesSubcommand;
+
+impl<'a> CliCommand<'a> for CheckImagesSubcommand {
+    fn get_name(&self) -> &'static str {
+        "check-images"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("Check that the containers are running their pinned image digests")
+            .arg(Arg::with_name("pull")
+                .help("Pull the image tags again to check whether they have changed in the registry")
+                .long("pull"))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_container_check-images",
+            content: include_str!("cli_help/check_images.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let checks = client
+            .container_check_images(args.is_present("pull"))
+            .call()?
+            .checks;
+        let mut stdout = std::io::stdout();
+
+        if checks.is_empty() {
+            writeln!(stdout, "No containers have been created")?;
+        }
+
+        let mut mismatched = 0;
+        for check in checks {
+            let container_name = check
+                .container_name
+                .as_deref()
+                .unwrap_or(crate::types::DEFAULT_CONTAINER_NAME);
+
+            let (status, color) = match &check.pinned_digest {
+                Some(digest) if check.running_digests.contains(digest) => ("ok", Color::Green),
+                Some(_) => {
+                    mismatched += 1;
+                    ("mismatch", Color::Red)
+                }
+                None => ("unpinned", Color::Yellow),
+            };
+            writeln!(
+                stdout,
+                "{} container {} ( {} )",
+                color_stdout(status, color),
+                container_name,
+                check.image
+            )?;
+
+            if let Some(digest) = &check.pinned_digest {
+                writeln!(stdout, "  pinned:  {}", digest)?;
+            }
+            for digest in &check.running_digests {
+                writeln!(stdout, "  running: {}", digest)?;
+            }
+            if let Some(remote_digest) = &check.remote_digest {
+                if check.pinned_digest.as_ref() != Some(remote_digest) {
+                    writeln!(
+                        stdout,
+                        "  {}: the tag now resolves to {}",
+                        color_stdout("drifted", Color::Yellow),
+                        remote_digest
+                    )?;
+                }
+            }
+        }
+
+        if mismatched > 0 {
+            anyhow::bail!(
+                "{} container(s) are not running their pinned image digest",
+                mismatched
+            );
+        }
+
+        Ok(data)
+    }
+}
@@ -1,6 +1,8 @@
+use anyhow::Context;
 use clap::{App, Arg, ArgMatches};
 
 use std::io::Write;
+use std::os::unix::process::CommandExt;
 
 use crate::cli::*;
 use crate::rpc::{VarlinkClient, VarlinkClientInterface};
@@ -19,7 +21,12 @@ impl<'a> CliCommand<'a> for EnvSubcommand {
     }
 
     fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
-        vec![Box::new(GetSubcommand), Box::new(SetSubcommand)]
+        vec![
+            Box::new(GetSubcommand),
+            Box::new(SetSubcommand),
+            Box::new(ModeSubcommand),
+            Box::new(ExecSubcommand),
+        ]
     }
 
     fn get_doc(&self) -> Option<CliDoc> {
@@ -122,6 +129,14 @@ impl<'a> CliCommand<'a> for SetSubcommand {
                             var.")
                 .required(true)
                 .multiple(true))
+            .arg(Arg::with_name("template")
+                .help("Treat the values as templates that reference the charm config and \
+                       relation data")
+                .long_help("Treat the values as templates that reference the charm config and \
+                            relation data. The templates are rendered every time the container \
+                            config is applied. See the doc page for the template syntax.")
+                .long("template")
+                .short('t'))
             .arg(super::container_arg())
     }
 
@@ -148,10 +163,138 @@ impl<'a> CliCommand<'a> for SetSubcommand {
             .expect("Invalid type");
 
         // Set the environment value. If value was not provided the environment var will be deleted.
-        client
-            .container_env_set(env_vars, container.map(Into::into))
-            .call()?;
+        if args.is_present("template") {
+            client
+                .container_env_set_template(env_vars, container.map(Into::into))
+                .call()?;
+        } else {
+            client
+                .container_env_set(env_vars, container.map(Into::into))
+                .call()?;
+        }
+
+        Ok(data)
+    }
+}
+
+struct ModeSubcommand;
+
+impl<'a> CliCommand<'a> for ModeSubcommand {
+    fn get_name(&self) -> &'static str {
+        "mode"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("Get or set how environment variables are passed to the container")
+            .long_about(concat!(
+                "Get or set how environment variables are passed to the container. The `inline` ",
+                "mode sets the variables in the container config, while the `file` mode renders ",
+                "them to an env file that is loaded when the container starts so that they are ",
+                "not visible in `docker inspect`. If you leave `mode` unspecified, the current ",
+                "mode will be printed."
+            ))
+            .arg(Arg::with_name("mode")
+                .help("The env mode to set")
+                .possible_values(&["inline", "file"]))
+            .arg(super::container_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let container = args.value_of("container");
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        // If a mode was given
+        if let Some(mode) = args.value_of("mode") {
+            client
+                .container_env_mode_set(mode.into(), container.map(Into::into))
+                .call()?;
+
+        // If no mode was given
+        } else {
+            let response = client
+                .container_env_mode_get(container.map(Into::into))
+                .call()?;
+
+            writeln!(
+                std::io::stdout(),
+                "{}",
+                response.mode.unwrap_or_else(|| "".into())
+            )?;
+        }
 
         Ok(data)
     }
 }
+
+/// Loads the container's env file and runs the container's command. This is used as the
+/// container entrypoint when the container's env mode is `file`.
+struct ExecSubcommand;
+
+impl<'a> CliCommand<'a> for ExecSubcommand {
+    fn get_name(&self) -> &'static str {
+        "exec"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .setting(clap::AppSettings::Hidden)
+            .setting(clap::AppSettings::TrailingVarArg)
+            .about("Load the container env file and run a command")
+            .arg(Arg::with_name("command")
+                .help("The command to run")
+                .required(true)
+                .multiple(true))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        let command: Vec<&str> = args
+            .values_of("command")
+            .expect("Missing required arg: command")
+            .collect();
+        let (program, program_args) = command
+            .split_first()
+            .expect("Missing required arg: command");
+
+        // Load the env file
+        let env_file_path =
+            std::env::var("LUCKY_ENV_FILE").context("LUCKY_ENV_FILE env var not set")?;
+        let env_file = std::fs::read_to_string(&env_file_path)
+            .context(format!("Could not read env file: {}", env_file_path))?;
+        let vars = crate::types::env_file::parse(&env_file)?;
+
+        // Replace this process with the command. `exec` only returns if there was an error.
+        let error = std::process::Command::new(program)
+            .args(program_args)
+            .envs(vars)
+            .env_remove("LUCKY_ENV_FILE")
+            .exec();
+
+        Err(anyhow::Error::new(error).context(format!("Could not run command: {}", program)))
+    }
+}
@@ -3,7 +3,7 @@ use clap::{App, Arg, ArgMatches};
 use std::io::Write;
 
 use crate::cli::*;
-use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+use crate::rpc::{RegistryAuth, VarlinkClient, VarlinkClientInterface};
 
 pub(super) struct ImageSubcommand;
 
@@ -19,7 +19,11 @@ impl<'a> CliCommand<'a> for ImageSubcommand {
     }
 
     fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
-        vec![Box::new(GetSubcommand), Box::new(SetSubcommand)]
+        vec![
+            Box::new(GetSubcommand),
+            Box::new(SetSubcommand),
+            Box::new(AuthSubcommand),
+        ]
     }
 
     fn get_doc(&self) -> Option<CliDoc> {
@@ -136,3 +140,99 @@ impl<'a> CliCommand<'a> for SetSubcommand {
         Ok(data)
     }
 }
+
+struct AuthSubcommand;
+
+impl<'a> CliCommand<'a> for AuthSubcommand {
+    fn get_name(&self) -> &'static str {
+        "auth"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("Set where to get the credentials for pulling the image from a private registry")
+            .arg(Arg::with_name("username_key")
+                .help("The config or relation data key that the registry username is stored in")
+                .long("username-key")
+                .value_name("key")
+                .default_value("registry-username"))
+            .arg(Arg::with_name("password_key")
+                .help("The config or relation data key that the registry password is stored in")
+                .long("password-key")
+                .value_name("key")
+                .default_value("registry-password"))
+            .arg(Arg::with_name("relation_id")
+                .help("Read the credentials from this relation instead of the charm config")
+                .long("relation-id")
+                .short('r')
+                .value_name("id")
+                .requires("remote_unit"))
+            .arg(Arg::with_name("remote_unit")
+                .help("The remote unit to read the relation data of")
+                .long("unit")
+                .short('U')
+                .value_name("unit")
+                .requires("relation_id"))
+            .arg(Arg::with_name("app")
+                .help("Read the remote application's relation data instead of the remote unit's")
+                .long("app")
+                .requires("relation_id"))
+            .arg(Arg::with_name("server")
+                .help("The address of the registry. Defaults to the registry in the image name")
+                .long("server")
+                .short('s')
+                .value_name("address"))
+            .arg(Arg::with_name("unset")
+                .help("Pull the image without credentials")
+                .long("unset")
+                .short('u')
+                .conflicts_with_all(&["relation_id", "server"]))
+            .arg(super::container_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let container = args.value_of("container");
+        let auth = if args.is_present("unset") {
+            None
+        } else {
+            Some(RegistryAuth {
+                username_key: args
+                    .value_of("username_key")
+                    .expect("Missing default arg: username_key")
+                    .into(),
+                password_key: args
+                    .value_of("password_key")
+                    .expect("Missing default arg: password_key")
+                    .into(),
+                relation_id: args.value_of("relation_id").map(Into::into),
+                remote_unit: args.value_of("remote_unit").map(Into::into),
+                app: args.is_present("app"),
+                server_address: args.value_of("server").map(Into::into),
+            })
+        };
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        // Set the registry auth for the specified container
+        client
+            .container_image_set_auth(auth, container.map(Into::into))
+            .call()?;
+
+        Ok(data)
+    }
+}
@@ -0,0 +1,80 @@
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches};
+
+use std::io::Write;
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct LogsSubcommand;
+
+impl<'a> CliCommand<'a> for LogsSubcommand {
+    fn get_name(&self) -> &'static str {
+        "logs"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Show the logs of the docker container")
+            .unset_setting(AppSettings::ArgRequiredElseHelp)
+            .arg(super::container_arg())
+            .arg(Arg::with_name("follow")
+                .help("Keep showing new log lines until the command is interrupted")
+                .short('f')
+                .long("follow"))
+            .arg(Arg::with_name("tail")
+                .help("The number of lines to show from the end of the logs")
+                .short('n')
+                .long("tail")
+                .value_name("lines")
+                .takes_value(true))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_container_logs",
+            content: include_str!("cli_help/logs.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let container = args.value_of("container");
+        let follow = args.is_present("follow");
+
+        // Parse the tail
+        let tail: Option<i64> = args
+            .value_of("tail")
+            .map(|x| x.parse().context("Invalid tail"))
+            .transpose()?;
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        // Print each log line to the stream that the container wrote it to
+        let stdout = std::io::stdout();
+        let stderr = std::io::stderr();
+        for reply in client
+            .container_logs(follow, tail, container.map(Into::into))
+            .more()?
+        {
+            if let Some(log_line) = reply?.line {
+                if log_line.stream == "stderr" {
+                    writeln!(stderr.lock(), "{}", log_line.line)?;
+                } else {
+                    writeln!(stdout.lock(), "{}", log_line.line)?;
+                }
+            }
+        }
+
+        Ok(data)
+    }
+}
@@ -59,6 +59,9 @@ impl<'a> CliCommand<'a> for AddSubcommand {
                     "The port binding to add in the format: `host_port:container_port/proto`. ",
                     "the `/proto` suffix is optional and defaults to `/tcp`."
                 )))
+            .arg(Arg::with_name("no_open")
+                .help("Don't open the host port in Juju")
+                .long("no-open"))
             .arg(super::container_arg())
     }
 
@@ -98,6 +101,7 @@ impl<'a> CliCommand<'a> for AddSubcommand {
                 port_binding.host_port.into(),
                 port_binding.container_port.into(),
                 port_binding.protocol,
+                args.is_present("no_open"),
                 container.map(Into::into),
             )
             .call()?;
@@ -194,6 +198,7 @@ impl<'a> CliCommand<'a> for ListSubcommand {
         self.get_base_app()
             .about("Get a list of the container's port bindings")
             .arg(super::container_arg())
+            .arg(crate::cli::client::format_arg())
     }
 
     fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
@@ -220,11 +225,32 @@ impl<'a> CliCommand<'a> for ListSubcommand {
             .downcast()
             .expect("Invalid type");
 
-        for port_binding in client
+        let ports = client
             .container_port_get_all(container.map(Into::into))
             .call()?
-            .ports
-        {
+            .ports;
+
+        // Print the bindings as an array of objects
+        if crate::cli::client::is_json_format(args) {
+            let ports: Vec<serde_json::Value> = ports
+                .into_iter()
+                .map(|x| {
+                    serde_json::json!({
+                        "host-port": x.host_port,
+                        "container-port": x.container_port,
+                        "protocol": x.protocol,
+                    })
+                })
+                .collect();
+            writeln!(
+                std::io::stdout(),
+                "{}",
+                serde_json::to_string_pretty(&ports)?
+            )?;
+            return Ok(data);
+        }
+
+        for port_binding in ports {
             writeln!(
                 std::io::stdout(),
                 "{}:{}/{}",
@@ -0,0 +1,64 @@
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches};
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct RestartSubcommand;
+
+impl<'a> CliCommand<'a> for RestartSubcommand {
+    fn get_name(&self) -> &'static str {
+        "restart"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Restart the docker container")
+            .unset_setting(AppSettings::ArgRequiredElseHelp)
+            .arg(super::container_arg())
+            .arg(Arg::with_name("timeout")
+                .help("The number of seconds to wait for the container to stop before killing it")
+                .short('t')
+                .long("timeout")
+                .value_name("seconds")
+                .takes_value(true)
+                .default_value("10"))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_container_restart",
+            content: include_str!("cli_help/restart.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let container = args.value_of("container");
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        // Parse the timeout
+        let timeout: i64 = args
+            .value_of("timeout")
+            .expect("Missing required arg: timeout")
+            .parse()
+            .context("Invalid timeout")?;
+
+        // Restart the specified container
+        client
+            .container_restart(Some(timeout), container.map(Into::into))
+            .call()?;
+
+        Ok(data)
+    }
+}
@@ -0,0 +1,53 @@
+use clap::{App, Arg, ArgMatches};
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct RestoreSubcommand;
+
+impl<'a> CliCommand<'a> for RestoreSubcommand {
+    fn get_name(&self) -> &'static str {
+        "restore"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Restore the container's volumes from a backup")
+            .arg(Arg::with_name("path")
+                .help("The path to a backup made with `lucky container backup`")
+                .required(true))
+            .arg(super::container_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_container_restore",
+            content: include_str!("cli_help/restore.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let path = args
+            .value_of("path")
+            .expect("Missing required argument: path");
+        let container = args.value_of("container");
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client
+            .container_restore(path.into(), container.map(Into::into))
+            .call()?;
+
+        Ok(data)
+    }
+}
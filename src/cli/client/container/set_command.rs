@@ -23,11 +23,21 @@ impl<'a> CliCommand<'a> for SetCommandSubcommand {
                 ))
                 .long("unset")
                 .short('u')
-                .required_unless("command"))
+                .required_unless_one(&["command", "reset"]))
+            .arg(Arg::with_name("reset")
+                .help("Reset the command to the one in the `lucky.yaml`")
+                .long_help(concat!(
+                    "Reset the command to the one in the container's `lucky.yaml` declaration. ",
+                    "Containers that aren't declared in the `lucky.yaml` will use the default ",
+                    "command."
+                ))
+                .long("reset")
+                .short('r')
+                .conflicts_with("unset"))
             .arg(Arg::with_name("command")
                 .help("The command for the container")
                 .multiple(true)
-                .required_unless("unset"))
+                .required_unless_one(&["unset", "reset"]))
             .arg(super::container_arg())
     }
 
@@ -52,7 +62,12 @@ impl<'a> CliCommand<'a> for SetCommandSubcommand {
             .downcast()
             .expect("Invalid type");
 
-        if args.is_present("unset") {
+        if args.is_present("reset") {
+            // Reset the command
+            client
+                .container_reset_command(container.map(Into::into))
+                .call()?;
+        } else if args.is_present("unset") {
             // Unset the command
             client
                 .container_set_command(None, container.map(Into::into))
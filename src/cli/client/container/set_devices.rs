@@ -0,0 +1,64 @@
+use clap::{App, Arg, ArgMatches};
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct SetDevicesSubcommand;
+
+impl<'a> CliCommand<'a> for SetDevicesSubcommand {
+    fn get_name(&self) -> &'static str {
+        "set-devices"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Set the host devices that are added to the container")
+            .arg(Arg::with_name("unset")
+                .help("Remove all of the devices from the container instead of setting them")
+                .long("unset")
+                .short('u')
+                .required_unless("devices"))
+            .arg(Arg::with_name("devices")
+                .help("The devices in the `host_path[:container_path][:permissions]` format")
+                .multiple(true)
+                .required_unless("unset"))
+            .arg(super::container_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_container_set-devices",
+            content: include_str!("cli_help/set_devices.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let container = args.value_of("container");
+        let devices = if args.is_present("unset") {
+            vec![]
+        } else {
+            args.values_of("devices")
+                .expect("Missing required argument: devices")
+                .map(ToOwned::to_owned)
+                .collect()
+        };
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client
+            .container_devices_set(devices, container.map(Into::into))
+            .call()?;
+
+        Ok(data)
+    }
+}
@@ -0,0 +1,68 @@
+use clap::{App, Arg, ArgMatches};
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct SetDnsSubcommand;
+
+impl<'a> CliCommand<'a> for SetDnsSubcommand {
+    fn get_name(&self) -> &'static str {
+        "set-dns"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Set the DNS servers of the container")
+            .arg(Arg::with_name("unset")
+                .help("Unset the DNS servers instead of setting them")
+                .long_help(concat!(
+                    "Unset the DNS servers instead of setting them. The container will use the ",
+                    "default DNS servers of the container engine."
+                ))
+                .long("unset")
+                .short('u')
+                .required_unless("servers"))
+            .arg(Arg::with_name("servers")
+                .help("The addresses of the DNS servers for the container to use")
+                .multiple(true)
+                .required_unless("unset"))
+            .arg(super::container_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_container_set-dns",
+            content: include_str!("cli_help/set_dns.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let container = args.value_of("container");
+        let servers = if args.is_present("unset") {
+            vec![]
+        } else {
+            args.values_of("servers")
+                .expect("Missing required argument: servers")
+                .map(ToOwned::to_owned)
+                .collect()
+        };
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client
+            .container_dns_set(servers, container.map(Into::into))
+            .call()?;
+
+        Ok(data)
+    }
+}
@@ -16,7 +16,7 @@ impl<'a> CliCommand<'a> for SetEntrypointSubcommand {
             .about("Set the docker entrypoint")
             .arg(Arg::with_name("entrypoint")
                 .help("The entrypoint for the container")
-                .required_unless("unset"))
+                .required_unless_one(&["unset", "reset"]))
             .arg(Arg::with_name("unset")
                 .help("Unset the entrypoint instead of setting it")
                 .long_help(concat!(
@@ -25,7 +25,17 @@ impl<'a> CliCommand<'a> for SetEntrypointSubcommand {
                 ))
                 .long("unset")
                 .short('u')
-                .required_unless("entrypoint"))
+                .required_unless_one(&["entrypoint", "reset"]))
+            .arg(Arg::with_name("reset")
+                .help("Reset the entrypoint to the one in the `lucky.yaml`")
+                .long_help(concat!(
+                    "Reset the entrypoint to the one in the container's `lucky.yaml` declaration. ",
+                    "Containers that aren't declared in the `lucky.yaml` will use the default ",
+                    "entrypoint."
+                ))
+                .long("reset")
+                .short('r')
+                .conflicts_with("unset"))
             .arg(super::container_arg())
     }
 
@@ -50,7 +60,12 @@ impl<'a> CliCommand<'a> for SetEntrypointSubcommand {
             .downcast()
             .expect("Invalid type");
 
-        if args.is_present("unset") {
+        if args.is_present("reset") {
+            // Reset the entrypoint
+            client
+                .container_reset_entrypoint(container.map(Into::into))
+                .call()?;
+        } else if args.is_present("unset") {
             // Unset the entrypoint
             client
                 .container_set_entrypoint(None, container.map(Into::into))
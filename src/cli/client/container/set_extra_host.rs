@@ -0,0 +1,69 @@
+use clap::{App, Arg, ArgMatches};
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct SetExtraHostSubcommand;
+
+impl<'a> CliCommand<'a> for SetExtraHostSubcommand {
+    fn get_name(&self) -> &'static str {
+        "set-extra-host"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Add a host to the container's `/etc/hosts` file")
+            .arg(Arg::with_name("unset")
+                .help("Remove the host instead of adding it")
+                .long("unset")
+                .short('u'))
+            .arg(Arg::with_name("hostname")
+                .help("The hostname to add")
+                .required(true))
+            .arg(Arg::with_name("address")
+                .help("The IP address that the hostname resolves to")
+                .required_unless("unset"))
+            .arg(super::container_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_container_set-extra-host",
+            content: include_str!("cli_help/set_extra_host.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let container = args.value_of("container");
+        let hostname = args
+            .value_of("hostname")
+            .expect("Missing required argument: hostname");
+        let address = if args.is_present("unset") {
+            None
+        } else {
+            Some(
+                args.value_of("address")
+                    .expect("Missing required argument: address")
+                    .into(),
+            )
+        };
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client
+            .container_extra_host_set(hostname.into(), address, container.map(Into::into))
+            .call()?;
+
+        Ok(data)
+    }
+}
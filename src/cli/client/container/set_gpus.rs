@@ -0,0 +1,68 @@
+use clap::{App, Arg, ArgMatches};
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct SetGpusSubcommand;
+
+impl<'a> CliCommand<'a> for SetGpusSubcommand {
+    fn get_name(&self) -> &'static str {
+        "set-gpus"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Set the GPUs that are added to the container")
+            .arg(Arg::with_name("unset")
+                .help("Remove the GPUs from the container instead of setting them")
+                .long("unset")
+                .short('u')
+                .required_unless("gpus"))
+            .arg(Arg::with_name("gpus")
+                .help("`all`, a number of GPUs, or `device=` followed by a list of GPUs")
+                .long_help(concat!(
+                    "The GPUs to add to the container: `all`, a number of GPUs, or `device=` ",
+                    "followed by a comma separated list of GPU indexes or UUIDs."
+                ))
+                .required_unless("unset"))
+            .arg(super::container_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_container_set-gpus",
+            content: include_str!("cli_help/set_gpus.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let container = args.value_of("container");
+        let gpus = if args.is_present("unset") {
+            None
+        } else {
+            Some(
+                args.value_of("gpus")
+                    .expect("Missing required argument: gpus")
+                    .into(),
+            )
+        };
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client
+            .container_gpus_set(gpus, container.map(Into::into))
+            .call()?;
+
+        Ok(data)
+    }
+}
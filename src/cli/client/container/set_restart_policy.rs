@@ -0,0 +1,55 @@
+use clap::{App, Arg, ArgMatches};
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct SetRestartPolicySubcommand;
+
+impl<'a> CliCommand<'a> for SetRestartPolicySubcommand {
+    fn get_name(&self) -> &'static str {
+        "set-restart-policy"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Set when the container is restarted after it exits")
+            .arg(Arg::with_name("policy")
+                .help("The restart policy for the container")
+                .required(true)
+                .possible_values(&["no", "on-failure", "always", "unless-stopped"]))
+            .arg(super::container_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_container_set-restart-policy",
+            content: include_str!("cli_help/set_restart_policy.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let container = args.value_of("container");
+        let policy = args
+            .value_of("policy")
+            .expect("Missing required argument: policy");
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        // Set the restart policy
+        client
+            .container_restart_policy_set(policy.into(), container.map(Into::into))
+            .call()?;
+
+        Ok(data)
+    }
+}
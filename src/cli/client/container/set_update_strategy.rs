@@ -0,0 +1,55 @@
+use clap::{App, Arg, ArgMatches};
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct SetUpdateStrategySubcommand;
+
+impl<'a> CliCommand<'a> for SetUpdateStrategySubcommand {
+    fn get_name(&self) -> &'static str {
+        "set-update-strategy"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Set how the container is replaced when its configuration changes")
+            .arg(Arg::with_name("strategy")
+                .help("The update strategy for the container")
+                .required(true)
+                .possible_values(&["recreate", "stop-start", "blue-green"]))
+            .arg(super::container_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_container_set-update-strategy",
+            content: include_str!("cli_help/set_update_strategy.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let container = args.value_of("container");
+        let strategy = args
+            .value_of("strategy")
+            .expect("Missing required argument: strategy");
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        // Set the update strategy
+        client
+            .container_update_strategy_set(strategy.into(), container.map(Into::into))
+            .call()?;
+
+        Ok(data)
+    }
+}
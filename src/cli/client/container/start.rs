@@ -0,0 +1,47 @@
+use clap::{App, ArgMatches};
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct StartSubcommand;
+
+impl<'a> CliCommand<'a> for StartSubcommand {
+    fn get_name(&self) -> &'static str {
+        "start"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Start the docker container")
+            .unset_setting(AppSettings::ArgRequiredElseHelp)
+            .arg(super::container_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_container_start",
+            content: include_str!("cli_help/start.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let container = args.value_of("container");
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        // Start the specified container
+        client.container_start(container.map(Into::into)).call()?;
+
+        Ok(data)
+    }
+}
@@ -0,0 +1,164 @@
+use clap::{App, AppSettings, Arg, ArgMatches};
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct UpdateHookSubcommand;
+
+impl<'a> CliCommand<'a> for UpdateHookSubcommand {
+    fn get_name(&self) -> &'static str {
+        "update-hook"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Add and remove scripts that are run when the container is replaced")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![Box::new(AddSubcommand), Box::new(RemoveSubcommand)]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_container_update-hook",
+            content: include_str!("cli_help/update_hook.md"),
+        })
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        Ok(data)
+    }
+}
+
+/// Return the "stage" argument for use in subcommands
+fn stage_arg<'a>() -> Arg<'a> {
+    Arg::with_name("stage")
+        .help("When the script is run")
+        .required(true)
+        .possible_values(&["pre-update", "post-update"])
+}
+
+struct AddSubcommand;
+
+impl<'a> CliCommand<'a> for AddSubcommand {
+    fn get_name(&self) -> &'static str {
+        "add"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Add a script that is run before or after the container is replaced")
+            .setting(AppSettings::TrailingVarArg)
+            .arg(stage_arg())
+            .arg(Arg::with_name("script")
+                .help("The name of the script in the `host_scripts` dir")
+                .required(true))
+            .arg(Arg::with_name("args")
+                .help("Arguments to pass to the script")
+                .multiple(true))
+            .arg(Arg::with_name("in_container")
+                .help("Run the script from the `container_scripts` dir in the container")
+                .long_help(concat!(
+                    "Run the script from the `container_scripts` dir in the container. ",
+                    "`pre-update` scripts run in the old container and `post-update` scripts run ",
+                    "in the new one."
+                ))
+                .long("in-container"))
+            .arg(super::container_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let stage = args
+            .value_of("stage")
+            .expect("Missing required argument: stage");
+        let script = args
+            .value_of("script")
+            .expect("Missing required argument: script");
+        let script_args = args
+            .values_of("args")
+            .map(|x| x.map(ToOwned::to_owned).collect())
+            .unwrap_or_default();
+        let container = args.value_of("container");
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client
+            .container_update_hook_add(
+                stage.into(),
+                script.into(),
+                script_args,
+                args.is_present("in_container"),
+                container.map(Into::into),
+            )
+            .call()?;
+
+        Ok(data)
+    }
+}
+
+struct RemoveSubcommand;
+
+impl<'a> CliCommand<'a> for RemoveSubcommand {
+    fn get_name(&self) -> &'static str {
+        "remove"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Remove a script that was added with `lucky container update-hook add`")
+            .arg(stage_arg())
+            .arg(Arg::with_name("script")
+                .help("The name of the script to remove")
+                .required(true))
+            .arg(super::container_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let stage = args
+            .value_of("stage")
+            .expect("Missing required argument: stage");
+        let script = args
+            .value_of("script")
+            .expect("Missing required argument: script");
+        let container = args.value_of("container");
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client
+            .container_update_hook_remove(stage.into(), script.into(), container.map(Into::into))
+            .call()?;
+
+        Ok(data)
+    }
+}
@@ -0,0 +1,74 @@
+use clap::{App, AppSettings, ArgMatches};
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct DockerSubcommand;
+
+impl<'a> CliCommand<'a> for DockerSubcommand {
+    fn get_name(&self) -> &'static str {
+        "docker"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Manage the installation of the container engine")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![Box::new(EnsureSubcommand)]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_docker",
+            content: include_str!("cli_help/docker.md"),
+        })
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        Ok(data)
+    }
+}
+
+struct EnsureSubcommand;
+
+impl<'a> CliCommand<'a> for EnsureSubcommand {
+    fn get_name(&self) -> &'static str {
+        "ensure"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("Make sure the container engine is installed and running")
+            .long_about(concat!(
+                "Make sure the container engine is installed and running, installing it if it ",
+                "isn't. The progress is shown in the unit status."
+            ))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client.docker_ensure().call()?;
+
+        Ok(data)
+    }
+}
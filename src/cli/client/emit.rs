@@ -0,0 +1,63 @@
+use clap::{App, Arg, ArgMatches};
+
+use std::io::Read;
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct EmitSubcommand;
+
+impl<'a> CliCommand<'a> for EmitSubcommand {
+    fn get_name(&self) -> &'static str {
+        "emit"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Emit a custom event to run the scripts subscribed to it")
+            .arg(Arg::with_name("event_name")
+                .help("The name of the event to emit")
+                .required(true))
+            .arg(Arg::with_name("payload")
+                .help("Data to pass to the subscribed scripts. Use `-` to read it from stdin"))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_emit",
+            content: include_str!("cli_help/emit.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let event_name = args
+            .value_of("event_name")
+            .expect("Missing required argument: event_name");
+        let payload = match args.value_of("payload") {
+            Some("-") => {
+                let mut payload = String::new();
+                std::io::stdin().read_to_string(&mut payload)?;
+                Some(payload)
+            }
+            payload => payload.map(ToOwned::to_owned),
+        };
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client
+            .emit(event_name.into(), payload, get_event_depth())
+            .call()?;
+
+        Ok(data)
+    }
+}
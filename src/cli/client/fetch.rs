@@ -0,0 +1,82 @@
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches};
+
+use std::io::Write;
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct FetchSubcommand;
+
+impl<'a> CliCommand<'a> for FetchSubcommand {
+    fn get_name(&self) -> &'static str {
+        "fetch"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Download a file to the shared download cache")
+            .long_about(concat!(
+                "Download a file to the shared download cache and print the path to it. NOTE: ",
+                "This path is the path to the file on the host and will not be accessible if ",
+                "called from a container."
+            ))
+            .arg(Arg::with_name("url")
+                .help("The http or https URL to download")
+                .required(true))
+            .arg(Arg::with_name("sha256")
+                .help("The expected SHA-256 hash of the file")
+                .long("sha256")
+                .value_name("hash"))
+            .arg(Arg::with_name("output")
+                .help("Copy the downloaded file to this path instead of printing the cache path")
+                .long("output")
+                .short('o')
+                .value_name("path"))
+            .arg(Arg::with_name("print_hash")
+                .help("Print the SHA-256 hash of the file instead of its path")
+                .long("print-hash"))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_fetch",
+            content: include_str!("cli_help/fetch.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let url = args
+            .value_of("url")
+            .expect("Missing required argument: url");
+        let sha256 = args.value_of("sha256").map(ToOwned::to_owned);
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let response = client.fetch(url.into(), sha256).call()?;
+
+        // Copy the file out of the cache if requested
+        if let Some(output) = args.value_of("output") {
+            std::fs::copy(&response.path, output)
+                .context(format!("Could not copy {} to {}", response.path, output))?;
+        }
+
+        if args.is_present("print_hash") {
+            writeln!(std::io::stdout(), "{}", response.sha256)?;
+        } else if !args.is_present("output") {
+            writeln!(std::io::stdout(), "{}", response.path)?;
+        }
+
+        Ok(data)
+    }
+}
@@ -0,0 +1,457 @@
+use clap::{App, Arg, ArgMatches};
+
+use std::io::Write;
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+/// Return the host service name argument for use in subcommands
+fn service_arg<'a>() -> Arg<'a> {
+    Arg::with_name("service")
+        .help("The name of the host service")
+        .required(true)
+}
+
+pub(super) struct HostServiceSubcommand;
+
+impl<'a> CliCommand<'a> for HostServiceSubcommand {
+    fn get_name(&self) -> &'static str {
+        "host-service"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Manipulate the charm's systemd services on the host")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![
+            Box::new(SetCommandSubcommand),
+            Box::new(SetTemplateSubcommand),
+            Box::new(SetRestartPolicySubcommand),
+            Box::new(EnvSubcommand),
+            Box::new(DeleteSubcommand),
+            Box::new(ListSubcommand),
+            Box::new(ApplyUpdatesSubcommand),
+        ]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_host-service",
+            content: include_str!("cli_help/host_service.md"),
+        })
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        Ok(data)
+    }
+}
+
+struct SetCommandSubcommand;
+
+impl<'a> CliCommand<'a> for SetCommandSubcommand {
+    fn get_name(&self) -> &'static str {
+        "set-command"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Set the command for a host service, creating the service if it doesn't exist")
+            .setting(AppSettings::TrailingVarArg)
+            .arg(service_arg())
+            .arg(Arg::with_name("command")
+                .help("The command to run, starting with the path to the program")
+                .multiple(true)
+                .required(true))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let service = args
+            .value_of("service")
+            .expect("Missing required arg: service");
+        let command = args
+            .values_of("command")
+            .expect("Missing required arg: command")
+            .map(ToOwned::to_owned)
+            .collect();
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client
+            .host_service_set_command(service.into(), command)
+            .call()?;
+
+        Ok(data)
+    }
+}
+
+struct SetTemplateSubcommand;
+
+impl<'a> CliCommand<'a> for SetTemplateSubcommand {
+    fn get_name(&self) -> &'static str {
+        "set-template"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Set the systemd unit file template for a host service")
+            .arg(service_arg())
+            .arg(Arg::with_name("unset")
+                .help("Use the default unit file instead of a template")
+                .long("unset")
+                .short('u')
+                .required_unless("template"))
+            .arg(Arg::with_name("template")
+                .help("The path to the unit file template, relative to the charm directory")
+                .required_unless("unset"))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let service = args
+            .value_of("service")
+            .expect("Missing required arg: service");
+        let template = if args.is_present("unset") {
+            None
+        } else {
+            args.value_of("template").map(ToOwned::to_owned)
+        };
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client
+            .host_service_set_template(service.into(), template)
+            .call()?;
+
+        Ok(data)
+    }
+}
+
+struct SetRestartPolicySubcommand;
+
+impl<'a> CliCommand<'a> for SetRestartPolicySubcommand {
+    fn get_name(&self) -> &'static str {
+        "set-restart-policy"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Set when systemd should restart a host service")
+            .arg(service_arg())
+            .arg(Arg::with_name("policy")
+                .help("The restart policy")
+                .possible_values(&["no", "on-failure", "always"])
+                .required(true))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let service = args
+            .value_of("service")
+            .expect("Missing required arg: service");
+        let policy = args
+            .value_of("policy")
+            .expect("Missing required arg: policy");
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client
+            .host_service_set_restart_policy(service.into(), policy.into())
+            .call()?;
+
+        Ok(data)
+    }
+}
+
+struct EnvSubcommand;
+
+impl<'a> CliCommand<'a> for EnvSubcommand {
+    fn get_name(&self) -> &'static str {
+        "env"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Get and set the environment variables of a host service")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![Box::new(EnvSetSubcommand), Box::new(EnvListSubcommand)]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        Ok(data)
+    }
+}
+
+struct EnvSetSubcommand;
+
+impl<'a> CliCommand<'a> for EnvSetSubcommand {
+    fn get_name(&self) -> &'static str {
+        "set"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Set environment variables")
+            .arg(service_arg())
+            .arg(Arg::with_name("vars")
+                .help("The vars to set as `key=value` pairs separated by spaces")
+                .long_help("The vars to set as `key=value` pairs separated by spaces. Setting \
+                            values to nothing will remove the environment var.")
+                .required(true)
+                .multiple(true))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let service = args
+            .value_of("service")
+            .expect("Missing required arg: service");
+        let raw_env_vars = args.values_of("vars").expect("Missing required arg: vars");
+
+        // Parse key-value pairs
+        let env_vars = util::parse_kv_pairs(raw_env_vars)?;
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client
+            .host_service_env_set(service.into(), env_vars)
+            .call()?;
+
+        Ok(data)
+    }
+}
+
+struct EnvListSubcommand;
+
+impl<'a> CliCommand<'a> for EnvListSubcommand {
+    fn get_name(&self) -> &'static str {
+        "list"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("List the environment variables")
+            .arg(service_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let service = args
+            .value_of("service")
+            .expect("Missing required arg: service");
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        // Print out the pairs sorted by key
+        let mut pairs = client
+            .host_service_env_get_all(service.into())
+            .call()?
+            .pairs;
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        for pair in pairs {
+            writeln!(std::io::stdout(), "{}={}", pair.key, pair.value)?;
+        }
+
+        Ok(data)
+    }
+}
+
+struct DeleteSubcommand;
+
+impl<'a> CliCommand<'a> for DeleteSubcommand {
+    fn get_name(&self) -> &'static str {
+        "delete"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Delete a host service")
+            .arg(service_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let service = args
+            .value_of("service")
+            .expect("Missing required arg: service");
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client.host_service_delete(service.into()).call()?;
+
+        Ok(data)
+    }
+}
+
+struct ListSubcommand;
+
+impl<'a> CliCommand<'a> for ListSubcommand {
+    fn get_name(&self) -> &'static str {
+        "list"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(AppSettings::ArgRequiredElseHelp)
+            .about("List the host services")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        for service in client.host_service_list().call()?.services {
+            writeln!(
+                std::io::stdout(),
+                "{} [restart: {}{}]: {}",
+                service.name,
+                service.restart_policy,
+                service
+                    .template
+                    .map_or_else(String::new, |x| format!(", template: {}", x)),
+                service.command.join(" ")
+            )?;
+        }
+
+        Ok(data)
+    }
+}
+
+struct ApplyUpdatesSubcommand;
+
+impl<'a> CliCommand<'a> for ApplyUpdatesSubcommand {
+    fn get_name(&self) -> &'static str {
+        "apply-updates"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(AppSettings::ArgRequiredElseHelp)
+            .about("Apply any host service configuration changes immediately")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client.host_service_apply().call()?;
+
+        Ok(data)
+    }
+}
@@ -1,9 +1,12 @@
+use anyhow::{bail, Context};
 use clap::{App, Arg, ArgMatches};
 
-use std::io::Write;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Write};
 
 use crate::cli::*;
 use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+use crate::types::{kv_key_matches, GLOBAL_KV_NAMESPACE};
 
 pub(super) struct KvSubcommand;
 
@@ -20,7 +23,14 @@ impl<'a> CliCommand<'a> for KvSubcommand {
     }
 
     fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
-        vec![Box::new(GetSubcommand), Box::new(SetSubcommand)]
+        vec![
+            Box::new(GetSubcommand),
+            Box::new(SetSubcommand),
+            Box::new(DeleteSubcommand),
+            Box::new(ListSubcommand),
+            Box::new(ExportSubcommand),
+            Box::new(ImportSubcommand),
+        ]
     }
 
     fn get_doc(&self) -> Option<CliDoc> {
@@ -35,6 +45,102 @@ impl<'a> CliCommand<'a> for KvSubcommand {
     }
 }
 
+/// Return the "namespace" argument for use in subcommands
+fn namespace_arg<'a>() -> Arg<'a> {
+    Arg::with_name("namespace")
+        .help("The namespace of the keys: defaults to the ID of the current script")
+        .long_help(concat!(
+            "The namespace of the keys: defaults to the ID of the current script. Each script ",
+            "gets its own keys so that scripts can't accidentally overwrite each other's values. ",
+            "Use the `global` namespace for keys that are shared by all scripts."
+        ))
+        .long("namespace")
+        .short('n')
+        .takes_value(true)
+        .env("LUCKY_SCRIPT_ID")
+}
+
+/// Get the namespace to send to the daemon from the "namespace" argument, which is `None` for the
+/// global namespace
+fn get_namespace(args: &ArgMatches) -> Option<String> {
+    args.value_of("namespace")
+        .filter(|x| *x != GLOBAL_KV_NAMESPACE)
+        .map(Into::into)
+}
+
+/// Return the "scope" argument for use in subcommands
+fn scope_arg<'a>() -> Arg<'a> {
+    Arg::with_name("scope")
+        .help("Whether to use the unit's store or the application's leader data")
+        .long_help(concat!(
+            "Whether to use the unit's store or the application's leader data. Values in the ",
+            "`app` scope are shared by every unit of the application, but only the leader unit ",
+            "may set them."
+        ))
+        .long("scope")
+        .short('s')
+        .takes_value(true)
+        .possible_values(&["unit", "app"])
+        .default_value("unit")
+}
+
+/// Check whether or not the "scope" argument selects the application's leader data
+fn is_app_scope(args: &ArgMatches) -> bool {
+    args.value_of("scope") == Some("app")
+}
+
+/// Get the key-value pairs of a namespace in the application scope from the leader data
+///
+/// Keys in namespaces other than the global namespace are stored in the leader data as
+/// `namespace/key`, so they are left out of the global namespace.
+fn get_app_pairs(
+    client: &mut VarlinkClient,
+    namespace: Option<&str>,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let data = client.leader_get().call()?.data.into_iter();
+
+    Ok(match namespace {
+        Some(namespace) => {
+            let prefix = format!("{}/", namespace);
+            data.filter_map(|(k, v)| Some((k.strip_prefix(&prefix)?.to_owned(), v)))
+                .collect()
+        }
+        None => data.filter(|(k, _)| !k.contains('/')).collect(),
+    })
+}
+
+/// Set key-value pairs of a namespace in the application scope in the leader data. Setting a value
+/// to `None` deletes it.
+fn set_app_pairs(
+    client: &mut VarlinkClient,
+    namespace: Option<&str>,
+    data: HashMap<String, Option<String>>,
+) -> anyhow::Result<()> {
+    if !client.leader_is_leader().call()?.is_leader {
+        bail!("Only the leader unit may set values in the `app` scope");
+    }
+
+    // Keys with a `/` would be mistaken for keys in another namespace
+    if let Some(key) = data.keys().find(|x| x.contains('/')) {
+        bail!("Keys in the `app` scope cannot contain a `/`: {}", key);
+    }
+
+    // Map `None`s to empty strings, which will erase the values
+    let data = data
+        .into_iter()
+        .map(|(key, value)| {
+            let key = match namespace {
+                Some(namespace) => format!("{}/{}", namespace, key),
+                None => key,
+            };
+            (key, value.unwrap_or_default())
+        })
+        .collect();
+    client.leader_set(data).call()?;
+
+    Ok(())
+}
+
 struct GetSubcommand;
 
 impl<'a> CliCommand<'a> for GetSubcommand {
@@ -54,6 +160,8 @@ impl<'a> CliCommand<'a> for GetSubcommand {
             ))
             .arg(Arg::with_name("key")
                 .help("The key to get from the store"))
+            .arg(namespace_arg())
+            .arg(scope_arg())
     }
 
     fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
@@ -69,6 +177,7 @@ impl<'a> CliCommand<'a> for GetSubcommand {
 
     fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
         let key = args.value_of("key");
+        let namespace = get_namespace(args);
 
         // Get client connection
         let mut client: Box<VarlinkClient> = data
@@ -77,10 +186,25 @@ impl<'a> CliCommand<'a> for GetSubcommand {
             .downcast()
             .expect("Invalid type");
 
+        // Get the values from the leader data in the app scope
+        if is_app_scope(args) {
+            let mut pairs = get_app_pairs(&mut client, namespace.as_deref())?;
+            if let Some(key) = key {
+                writeln!(
+                    std::io::stdout(),
+                    "{}",
+                    pairs.remove(key).unwrap_or_default()
+                )?;
+            } else {
+                for (key, value) in pairs {
+                    writeln!(std::io::stdout(), "{}={}", key, value)?;
+                }
+            }
+
         // If a specific key was given
-        if let Some(key) = key {
+        } else if let Some(key) = key {
             // Print out the requested value
-            let response = client.unit_kv_get(key.into()).call()?;
+            let response = client.unit_kv_get(key.into(), namespace).call()?;
 
             writeln!(
                 std::io::stdout(),
@@ -91,7 +215,7 @@ impl<'a> CliCommand<'a> for GetSubcommand {
         // If no key was given
         } else {
             // Return all of the key-value pairs
-            for pair in client.unit_kv_get_all().call()?.pairs {
+            for pair in client.unit_kv_get_all(namespace).call()?.pairs {
                 // Print out key-value pair
                 writeln!(std::io::stdout(), "{}={}", pair.key, pair.value)?;
             }
@@ -116,6 +240,25 @@ impl<'a> CliCommand<'a> for SetSubcommand {
                 .help("The data to set on the relation as `key=value` pairs separated by spaces")
                 .required(true)
                 .multiple(true))
+            .arg(Arg::with_name("ttl")
+                .help("The number of seconds after which the values expire")
+                .long_help(concat!(
+                    "The number of seconds after which the values expire. Expired values are ",
+                    "deleted from the store. If this isn't set the values never expire, even if ",
+                    "they were set with a TTL before."
+                ))
+                .long("ttl")
+                .takes_value(true))
+            .arg(Arg::with_name("secret")
+                .help("Encrypt the values at rest and keep them out of the logs")
+                .long_help(concat!(
+                    "Encrypt the values at rest and keep them out of the logs. Secret values are ",
+                    "only returned by `lucky kv get <key>`: `lucky kv get` without a key prints ",
+                    "them as `[REDACTED]` and `lucky kv export` leaves them out."
+                ))
+                .long("secret"))
+            .arg(namespace_arg())
+            .arg(scope_arg())
     }
 
     fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
@@ -135,6 +278,14 @@ impl<'a> CliCommand<'a> for SetSubcommand {
         // Parse key-value pairs
         let kv_data = util::parse_kv_pairs(raw_kv_pairs)?;
 
+        // Parse the TTL
+        let ttl: Option<i64> = args
+            .value_of("ttl")
+            .map(str::parse)
+            .transpose()
+            .context("Invalid TTL")?;
+        let secret = args.is_present("secret");
+
         // Get client connection
         let mut client: Box<VarlinkClient> = data
             .remove("client")
@@ -142,8 +293,296 @@ impl<'a> CliCommand<'a> for SetSubcommand {
             .downcast()
             .expect("Invalid type");
 
+        // Set the values in the leader data in the app scope
+        if is_app_scope(args) {
+            if ttl.is_some() || secret {
+                bail!("The `--ttl` and `--secret` options can only be used in the `unit` scope");
+            }
+            set_app_pairs(&mut client, get_namespace(args).as_deref(), kv_data)?;
+
+            return Ok(data);
+        }
+
         // Set the key-value data
-        client.unit_kv_set(kv_data).call()?;
+        client
+            .unit_kv_set(kv_data, get_namespace(args), ttl, secret, get_event_depth())
+            .call()?;
+
+        Ok(data)
+    }
+}
+
+struct DeleteSubcommand;
+
+impl<'a> CliCommand<'a> for DeleteSubcommand {
+    fn get_name(&self) -> &'static str {
+        "delete"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Delete values")
+            .arg(Arg::with_name("keys")
+                .help("The keys to delete from the store")
+                .required(true)
+                .multiple(true))
+            .arg(namespace_arg())
+            .arg(scope_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let keys: Vec<String> = args
+            .values_of("keys")
+            .expect("Missing required arg: keys")
+            .map(ToOwned::to_owned)
+            .collect();
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        if is_app_scope(args) {
+            set_app_pairs(
+                &mut client,
+                get_namespace(args).as_deref(),
+                keys.into_iter().map(|key| (key, None)).collect(),
+            )?;
+        } else {
+            client
+                .unit_kv_delete(keys, get_namespace(args), get_event_depth())
+                .call()?;
+        }
+
+        Ok(data)
+    }
+}
+
+struct ListSubcommand;
+
+impl<'a> CliCommand<'a> for ListSubcommand {
+    fn get_name(&self) -> &'static str {
+        "list"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("List the keys in the store")
+            .arg(Arg::with_name("pattern")
+                .help("Only list the keys that match this pattern, such as `db-*`")
+                .long_help(concat!(
+                    "Only list the keys that match this pattern, where `*` matches any number of ",
+                    "characters. Use `prefix*` to list the keys that start with `prefix`."
+                )))
+            .arg(Arg::with_name("format")
+                .help("How to print the keys")
+                .long_help(concat!(
+                    "How to print the keys: `keys` prints one key per line, `pairs` prints ",
+                    "`key=value` lines, `json` prints a JSON object, and `shell` prints ",
+                    "`export KEY='value'` lines that can be evaluated by a shell, with the keys ",
+                    "upper-cased and other characters than letters and numbers replaced with ",
+                    "underscores."
+                ))
+                .long("format")
+                .short('f')
+                .takes_value(true)
+                .possible_values(&["keys", "pairs", "json", "shell"])
+                .default_value("keys"))
+            .arg(namespace_arg())
+            .arg(scope_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        // Get the pairs sorted by key
+        let mut pairs: BTreeMap<String, String> = if is_app_scope(args) {
+            get_app_pairs(&mut client, get_namespace(args).as_deref())?
+        } else {
+            client
+                .unit_kv_get_all(get_namespace(args))
+                .call()?
+                .pairs
+                .into_iter()
+                .map(|pair| (pair.key, pair.value))
+                .collect()
+        };
+
+        // Only keep the keys that match the pattern
+        if let Some(pattern) = args.value_of("pattern") {
+            pairs.retain(|key, _| kv_key_matches(pattern, key));
+        }
+
+        // Use JSON if it is the default output format and no other format was given
+        let format = match args.value_of("format") {
+            Some("keys")
+                if args.occurrences_of("format") == 0
+                    && std::env::var(super::OUTPUT_FORMAT_ENV_VAR).ok().as_deref()
+                        == Some("json") =>
+            {
+                Some("json")
+            }
+            format => format,
+        };
+
+        let mut stdout = std::io::stdout();
+        match format {
+            Some("pairs") => {
+                for (key, value) in pairs {
+                    writeln!(stdout, "{}={}", key, value)?;
+                }
+            }
+            Some("json") => writeln!(stdout, "{}", serde_json::to_string_pretty(&pairs)?)?,
+            Some("shell") => {
+                for (key, value) in pairs {
+                    // Turn the key into a valid variable name
+                    let name: String = key
+                        .to_uppercase()
+                        .chars()
+                        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                        .collect();
+                    writeln!(stdout, "export {}='{}'", name, value.replace('\'', r"'\''"))?;
+                }
+            }
+            _ => {
+                for key in pairs.keys() {
+                    writeln!(stdout, "{}", key)?;
+                }
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+struct ExportSubcommand;
+
+impl<'a> CliCommand<'a> for ExportSubcommand {
+    fn get_name(&self) -> &'static str {
+        "export"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("Print the whole store, including every namespace, as JSON")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        // Sort the namespaces and keys so that exports are easy to compare
+        let namespaces: BTreeMap<String, BTreeMap<String, String>> = client
+            .unit_kv_export()
+            .call()?
+            .namespaces
+            .into_iter()
+            .map(|(namespace, pairs)| (namespace, pairs.into_iter().collect()))
+            .collect();
+
+        writeln!(
+            std::io::stdout(),
+            "{}",
+            serde_json::to_string_pretty(&namespaces)?
+        )?;
+
+        Ok(data)
+    }
+}
+
+struct ImportSubcommand;
+
+impl<'a> CliCommand<'a> for ImportSubcommand {
+    fn get_name(&self) -> &'static str {
+        "import"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("Load values into the store from JSON printed by `lucky kv export`")
+            .arg(Arg::with_name("file")
+                .help("The JSON file to import. Use `-` or leave it unspecified to read stdin"))
+            .arg(Arg::with_name("replace")
+                .help("Delete the keys that are not in the imported JSON")
+                .long("replace"))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let json = match args.value_of("file") {
+            None | Some("-") => {
+                let mut json = String::new();
+                std::io::stdin().read_to_string(&mut json)?;
+                json
+            }
+            Some(file) => std::fs::read_to_string(file)
+                .context(format!("Could not read import file: {}", file))?,
+        };
+        let namespaces: HashMap<String, HashMap<String, String>> = serde_json::from_str(&json)
+            .context(concat!(
+            "Could not parse import: expected a JSON object of namespaces, each with an object ",
+            "of string values"
+        ))?;
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client
+            .unit_kv_import(namespaces, args.is_present("replace"), get_event_depth())
+            .call()?;
 
         Ok(data)
     }
@@ -0,0 +1,152 @@
+use anyhow::{bail, Context};
+use clap::{App, Arg, ArgMatches};
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct LockSubcommand;
+
+impl<'a> CliCommand<'a> for LockSubcommand {
+    fn get_name(&self) -> &'static str {
+        "lock"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Acquire and release named locks shared by the charm's scripts")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![Box::new(AcquireSubcommand), Box::new(ReleaseSubcommand)]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_lock",
+            content: include_str!("cli_help/lock.md"),
+        })
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        Ok(data)
+    }
+}
+
+struct AcquireSubcommand;
+
+impl<'a> CliCommand<'a> for AcquireSubcommand {
+    fn get_name(&self) -> &'static str {
+        "acquire"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Acquire a lock")
+            .long_about(concat!(
+                "Acquire a lock. Fails if another script holds the lock, unless `--wait` is given ",
+                "to wait for the lock to be released."
+            ))
+            .arg(Arg::with_name("name")
+                .help("The name of the lock")
+                .required(true))
+            .arg(Arg::with_name("wait")
+                .help("The number of seconds to wait for the lock if another script holds it")
+                .long("wait")
+                .short('w')
+                .takes_value(true)
+                .value_name("seconds"))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let name = args
+            .value_of("name")
+            .expect("Missing required argument: name");
+        let wait = args
+            .value_of("wait")
+            .map(|x| {
+                x.parse::<i64>()
+                    .context(format!("Invalid wait time: {}", x))
+            })
+            .transpose()?;
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let acquired = client
+            .lock_acquire(name.into(), get_holder()?, wait)
+            .call()?
+            .acquired;
+        if !acquired {
+            if let Some(wait) = wait {
+                bail!("Timed out after {} seconds waiting for lock {}", wait, name);
+            } else {
+                bail!("Lock {} is held by another script", name);
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+struct ReleaseSubcommand;
+
+impl<'a> CliCommand<'a> for ReleaseSubcommand {
+    fn get_name(&self) -> &'static str {
+        "release"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Release a lock held by this script")
+            .arg(Arg::with_name("name")
+                .help("The name of the lock")
+                .required(true))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let name = args
+            .value_of("name")
+            .expect("Missing required argument: name");
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        client.lock_release(name.into(), get_holder()?).call()?;
+
+        Ok(data)
+    }
+}
+
+/// Get the ID of the current run of the script, which holds the locks that it acquires
+fn get_holder() -> anyhow::Result<String> {
+    std::env::var("LUCKY_SCRIPT_RUN_ID")
+        .context("Locks can only be used in a script run by Lucky: LUCKY_SCRIPT_RUN_ID is not set")
+}
@@ -0,0 +1,165 @@
+use clap::{App, ArgMatches};
+
+use std::io::Write;
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct ModelSubcommand;
+
+impl<'a> CliCommand<'a> for ModelSubcommand {
+    fn get_name(&self) -> &'static str {
+        "model"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Get information about the Juju model from the controller API")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![
+            Box::new(InfoSubcommand),
+            Box::new(ApplicationsSubcommand),
+            Box::new(OffersSubcommand),
+        ]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_model",
+            content: include_str!("cli_help/model.md"),
+        })
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        Ok(data)
+    }
+}
+
+struct InfoSubcommand;
+
+impl<'a> CliCommand<'a> for InfoSubcommand {
+    fn get_name(&self) -> &'static str {
+        "info"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("Get the name and UUID of the model")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let info = client.model_info().call()?;
+
+        writeln!(std::io::stdout(), "name={}", info.name)?;
+        writeln!(std::io::stdout(), "uuid={}", info.uuid)?;
+
+        Ok(data)
+    }
+}
+
+struct ApplicationsSubcommand;
+
+impl<'a> CliCommand<'a> for ApplicationsSubcommand {
+    fn get_name(&self) -> &'static str {
+        "applications"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("List the applications in the model")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        for application in client.model_applications().call()?.applications {
+            writeln!(std::io::stdout(), "{}", application)?;
+        }
+
+        Ok(data)
+    }
+}
+
+struct OffersSubcommand;
+
+impl<'a> CliCommand<'a> for OffersSubcommand {
+    fn get_name(&self) -> &'static str {
+        "offers"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("List the cross-model relation offers made from the model")
+            .long_about(concat!(
+                "List the cross-model relation offers made from the model, one per line, in the ",
+                "format `offer-name application endpoint1,endpoint2`."
+            ))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        for offer in client.model_offers().call()?.offers {
+            writeln!(
+                std::io::stdout(),
+                "{} {} {}",
+                offer.name,
+                offer.application,
+                offer.endpoints.join(",")
+            )?;
+        }
+
+        Ok(data)
+    }
+}
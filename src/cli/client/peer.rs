@@ -0,0 +1,194 @@
+use clap::{App, Arg, ArgMatches};
+
+use std::io::Write;
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+/// Return the "--relation" argument for use in subcommands
+fn relation_arg<'a>() -> Arg<'a> {
+    Arg::with_name("relation_name")
+        .help(concat!(
+            "The name of the peer relation to use. If not specified the first peer relation in ",
+            "the charm's metadata.yaml will be used"
+        ))
+        .short('r')
+        .long("relation")
+        .value_name("name")
+        .takes_value(true)
+}
+
+pub(super) struct PeerSubcommand;
+
+impl<'a> CliCommand<'a> for PeerSubcommand {
+    fn get_name(&self) -> &'static str {
+        "peer"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Share application data with peer units")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![
+            Box::new(GetSubcommand),
+            Box::new(SetSubcommand),
+            Box::new(ListSubcommand),
+        ]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_peer",
+            content: include_str!("cli_help/peer.md"),
+        })
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        Ok(data)
+    }
+}
+
+struct GetSubcommand;
+
+impl<'a> CliCommand<'a> for GetSubcommand {
+    fn get_name(&self) -> &'static str {
+        "get"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Get a value from the peer store")
+            .arg(Arg::with_name("key")
+                .help("The key to get from the peer store")
+                .required(true))
+            .arg(relation_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let key = args.value_of("key").expect("Missing required arg: key");
+        let relation_name = args.value_of("relation_name").map(ToOwned::to_owned);
+
+        let value = client.peer_get(key.into(), relation_name).call()?.value;
+
+        writeln!(std::io::stdout(), "{}", value.unwrap_or_else(|| "".into()))?;
+
+        Ok(data)
+    }
+}
+
+struct SetSubcommand;
+
+impl<'a> CliCommand<'a> for SetSubcommand {
+    fn get_name(&self) -> &'static str {
+        "set"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Set values in the peer store ( leader only )")
+            .arg(Arg::with_name("data")
+                .help("The data to set as `key=value` pairs separated by spaces")
+                .long_help(concat!(
+                    "The data to set as `key=value` pairs separated by spaces. Setting a key to ",
+                    "an empty value, such as `key=`, will remove it from the peer store."
+                ))
+                .required(true)
+                .multiple(true))
+            .arg(relation_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let raw_kv_pairs = args.values_of("data").expect("Missing required arg: data");
+        let relation_name = args.value_of("relation_name").map(ToOwned::to_owned);
+
+        // Parse key-value pairs
+        let peer_data = util::parse_kv_pairs(raw_kv_pairs)?;
+
+        // Set peer data
+        client.peer_set(peer_data, relation_name).call()?;
+
+        Ok(data)
+    }
+}
+
+struct ListSubcommand;
+
+impl<'a> CliCommand<'a> for ListSubcommand {
+    fn get_name(&self) -> &'static str {
+        "list"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("List all of the values in the peer store")
+            .arg(relation_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let relation_name = args.value_of("relation_name").map(ToOwned::to_owned);
+
+        let peer_data = client.peer_get_all(relation_name).call()?.data;
+
+        // Print out the pairs sorted by key
+        let mut pairs: Vec<_> = peer_data.iter().collect();
+        pairs.sort();
+        for (k, v) in pairs {
+            writeln!(std::io::stdout(), "{}={}", k, v)?;
+        }
+
+        Ok(data)
+    }
+}
@@ -1,4 +1,4 @@
-use clap::{App, ArgMatches};
+use clap::{App, Arg, ArgMatches};
 
 use std::io::Write;
 
@@ -17,6 +17,16 @@ impl<'a> CliCommand<'a> for PrivateAddressSubcommand {
         self.get_base_app()
             .about("Get the private IP address of the unit")
             .unset_setting(AppSettings::ArgRequiredElseHelp)
+            .arg(Arg::with_name("binding")
+                .help("Get the address to bind to for this endpoint binding instead")
+                .long_help(concat!(
+                    "Get the address that the unit should bind to for this endpoint binding, ",
+                    "such as a relation name, instead of the unit's private address. This ",
+                    "requires Juju 2.1 or newer."
+                ))
+                .long("binding")
+                .short('b')
+                .takes_value(true))
     }
 
     fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
@@ -30,7 +40,7 @@ impl<'a> CliCommand<'a> for PrivateAddressSubcommand {
         })
     }
 
-    fn execute_command(&self, _args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
         // Get client connection
         let mut client: Box<VarlinkClient> = data
             .remove("client")
@@ -38,11 +48,12 @@ impl<'a> CliCommand<'a> for PrivateAddressSubcommand {
             .downcast()
             .expect("Invalid type");
 
-        // Set script status
+        let binding = args.value_of("binding").map(Into::into);
+
         writeln!(
             std::io::stdout(),
             "{}",
-            client.get_private_address().call()?.address
+            client.get_private_address(binding).call()?.address
         )?;
 
         Ok(data)
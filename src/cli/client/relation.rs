@@ -71,6 +71,7 @@ impl<'a> CliCommand<'a> for GetSubcommand {
             .arg(Arg::with_name("key")
                 .help("Optional key to get from the data")
                 .required(false))
+            .arg(super::format_arg())
     }
 
     fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
@@ -113,8 +114,19 @@ impl<'a> CliCommand<'a> for GetSubcommand {
             relation_data = client.relation_get(None, app).call()?.data;
         }
 
+        // Print the value of the key, or `null` if it isn't set, or an object with all of the data
+        if super::is_json_format(args) {
+            let json = match args.value_of("key") {
+                Some(key) => serde_json::to_string(&relation_data.get(key))?,
+                None => serde_json::to_string_pretty(
+                    &relation_data
+                        .iter()
+                        .collect::<std::collections::BTreeMap<_, _>>(),
+                )?,
+            };
+            writeln!(std::io::stdout(), "{}", json)?;
         // If a specific key was requested
-        if let Some(key) = args.value_of("key") {
+        } else if let Some(key) = args.value_of("key") {
             writeln!(
                 std::io::stdout(),
                 "{}",
@@ -0,0 +1,76 @@
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches};
+
+use std::io::{Read, Write};
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct RenderTemplateSubcommand;
+
+impl<'a> CliCommand<'a> for RenderTemplateSubcommand {
+    fn get_name(&self) -> &'static str {
+        "render-template"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Render a template with the charm config, relation data, and key-value store")
+            .arg(Arg::with_name("template")
+                .help("The path to the template file, or `-` to read the template from stdin")
+                .required(true))
+            .arg(Arg::with_name("output")
+                .help("Write the rendered template to this file instead of printing it")
+                .long("output")
+                .short('o')
+                .value_name("path"))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_render-template",
+            content: include_str!("cli_help/render_template.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let template_path = args
+            .value_of("template")
+            .expect("Missing required argument: template");
+
+        // The template is read by the client so that templates in containers can be rendered
+        let template = if template_path == "-" {
+            let mut template = String::new();
+            std::io::stdin()
+                .read_to_string(&mut template)
+                .context("Could not read template from stdin")?;
+            template
+        } else {
+            std::fs::read_to_string(template_path)
+                .context(format!("Could not read template: {:?}", template_path))?
+        };
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let rendered = client.render_template(template).call()?.rendered;
+
+        if let Some(output) = args.value_of("output") {
+            std::fs::write(output, rendered)
+                .context(format!("Could not write file: {:?}", output))?;
+        } else {
+            write!(std::io::stdout(), "{}", rendered)?;
+        }
+
+        Ok(data)
+    }
+}
@@ -0,0 +1,109 @@
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches};
+
+use std::io::Write;
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct ScriptSubcommand;
+
+impl<'a> CliCommand<'a> for ScriptSubcommand {
+    fn get_name(&self) -> &'static str {
+        "script"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Get information about the charm's scripts")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![Box::new(LogsSubcommand)]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_script",
+            content: include_str!("cli_help/script.md"),
+        })
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        Ok(data)
+    }
+}
+
+struct LogsSubcommand;
+
+impl<'a> CliCommand<'a> for LogsSubcommand {
+    fn get_name(&self) -> &'static str {
+        "logs"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Get the output of a run of a script")
+            .long_about(concat!(
+                "Get the output of a run of a script. Gets the output of the latest run unless ",
+                "`--run` is given."
+            ))
+            .arg(Arg::with_name("script_id")
+                .help("The ID of the script, such as `install_0`")
+                .required(true))
+            .arg(Arg::with_name("run")
+                .help("The number of the run to get the output of")
+                .long("run")
+                .short('r')
+                .takes_value(true)
+                .value_name("N"))
+            .arg(Arg::with_name("list")
+                .help("List the numbers of the runs that have logs instead of printing a log")
+                .long("list")
+                .short('l')
+                .conflicts_with("run"))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let script_id = args
+            .value_of("script_id")
+            .expect("Missing required argument: script_id");
+        let run = args
+            .value_of("run")
+            .map(|x| {
+                x.parse::<i64>()
+                    .context(format!("Invalid run number: {}", x))
+            })
+            .transpose()?;
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let response = client.script_logs(script_id.into(), run).call()?;
+
+        if args.is_present("list") {
+            for run in response.runs {
+                writeln!(std::io::stdout(), "{}", run)?;
+            }
+        } else {
+            write!(std::io::stdout(), "{}", response.log)?;
+        }
+
+        Ok(data)
+    }
+}
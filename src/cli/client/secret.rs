@@ -0,0 +1,360 @@
+use anyhow::format_err;
+use clap::{App, Arg, ArgMatches};
+
+use std::io::Write;
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+
+pub(super) struct SecretSubcommand;
+
+impl<'a> CliCommand<'a> for SecretSubcommand {
+    fn get_name(&self) -> &'static str {
+        "secret"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Get and set Juju secrets and store secrets for the unit")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![
+            Box::new(GetSubcommand),
+            Box::new(SetSubcommand),
+            Box::new(UnitSubcommand),
+        ]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_secret",
+            content: include_str!("cli_help/secret.md"),
+        })
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        Ok(data)
+    }
+}
+
+struct GetSubcommand;
+
+impl<'a> CliCommand<'a> for GetSubcommand {
+    fn get_name(&self) -> &'static str {
+        "get"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Get the content of a Juju secret")
+            .long_about(concat!(
+                "Get the content of a Juju secret. If you leave `key` unspecified, all key-value ",
+                "pairs in the secret will be printed out, one per line, in the format `key=value`."
+            ))
+            .arg(Arg::with_name("secret_id")
+                .help("The ID of the secret to get")
+                .required_unless("label"))
+            .arg(Arg::with_name("key")
+                .help("Optional key to get from the secret")
+                .long("key")
+                .short('k')
+                .takes_value(true))
+            .arg(Arg::with_name("label")
+                .help("The label of the secret to get")
+                .long("label")
+                .short('l')
+                .takes_value(true))
+            .arg(Arg::with_name("refresh")
+                .help("Fetch and start tracking the latest revision of the secret")
+                .long_help(concat!(
+                    "Fetch and start tracking the latest revision of the secret. Without this ",
+                    "flag the daemon will return its cached copy of the secret if it has one."
+                ))
+                .long("refresh")
+                .short('r'))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let secret_data = client
+            .secret_get(
+                args.value_of("secret_id").map(Into::into),
+                args.value_of("label").map(Into::into),
+                args.is_present("refresh"),
+            )
+            .call()?
+            .data;
+
+        // If a specific key was requested
+        if let Some(key) = args.value_of("key") {
+            writeln!(
+                std::io::stdout(),
+                "{}",
+                secret_data.get(key).unwrap_or(&"".to_string()),
+            )?;
+        // Print all key-value pairs
+        } else {
+            for (k, v) in &secret_data {
+                writeln!(std::io::stdout(), "{}={}", k, v)?;
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+struct SetSubcommand;
+
+impl<'a> CliCommand<'a> for SetSubcommand {
+    fn get_name(&self) -> &'static str {
+        "set"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Set the content of a Juju secret owned by this charm")
+            .arg(Arg::with_name("secret_id")
+                .help("The ID of the secret to set")
+                .required(true))
+            .arg(Arg::with_name("data")
+                .help("The data to set in the secret as `key=value` pairs separated by spaces")
+                .required(true)
+                .multiple(true))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let secret_id = args
+            .value_of("secret_id")
+            .expect("Missing required arg: secret_id");
+        let raw_kv_pairs = args.values_of("data").expect("Missing required arg: data");
+
+        // Parse key-value pairs
+        let mut secret_data = util::parse_kv_pairs(raw_kv_pairs)?;
+        // Map `None`s to null strings
+        let secret_data = secret_data
+            .drain()
+            .map(|(k, v)| (k, v.unwrap_or_else(|| "".into())))
+            .collect();
+
+        // Set secret data
+        client.secret_set(secret_id.into(), secret_data).call()?;
+
+        Ok(data)
+    }
+}
+
+struct UnitSubcommand;
+
+impl<'a> CliCommand<'a> for UnitSubcommand {
+    fn get_name(&self) -> &'static str {
+        "unit"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Get and set values in the unit's secret store")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![
+            Box::new(UnitGetSubcommand),
+            Box::new(UnitSetSubcommand),
+            Box::new(UnitGenerateSubcommand),
+        ]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        Ok(data)
+    }
+}
+
+struct UnitGetSubcommand;
+
+impl<'a> CliCommand<'a> for UnitGetSubcommand {
+    fn get_name(&self) -> &'static str {
+        "get"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Get a value from the unit's secret store")
+            .arg(Arg::with_name("key")
+                .help("The key to get")
+                .required(true))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let key = args.value_of("key").expect("Missing required arg: key");
+
+        let value = client.unit_secret_get(key.into()).call()?.value;
+
+        writeln!(std::io::stdout(), "{}", value.unwrap_or_else(|| "".into()))?;
+
+        Ok(data)
+    }
+}
+
+struct UnitSetSubcommand;
+
+impl<'a> CliCommand<'a> for UnitSetSubcommand {
+    fn get_name(&self) -> &'static str {
+        "set"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Set values in the unit's secret store")
+            .arg(Arg::with_name("data")
+                .help("The data to set as `key=value` pairs separated by spaces")
+                .long_help(concat!(
+                    "The data to set as `key=value` pairs separated by spaces. Setting a key to ",
+                    "an empty value, such as `key=`, will remove it from the secret store."
+                ))
+                .required(true)
+                .multiple(true))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let raw_kv_pairs = args.values_of("data").expect("Missing required arg: data");
+
+        // Parse key-value pairs
+        let secret_data = util::parse_kv_pairs(raw_kv_pairs)?;
+
+        // Set secret data
+        client.unit_secret_set(secret_data).call()?;
+
+        Ok(data)
+    }
+}
+
+struct UnitGenerateSubcommand;
+
+impl<'a> CliCommand<'a> for UnitGenerateSubcommand {
+    fn get_name(&self) -> &'static str {
+        "generate"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Generate a random secret if it does not already exist and print it")
+            .long_about(concat!(
+                "Generate a random secret and store it in the unit's secret store. If the key is ",
+                "already set, the existing value will be printed instead of generating a new one."
+            ))
+            .arg(Arg::with_name("key")
+                .help("The key to store the generated secret under")
+                .required(true))
+            .arg(Arg::with_name("length")
+                .help("Set the length of the generated secret")
+                .short('l')
+                .long("length")
+                .takes_value(true)
+                .default_value("24"))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let key = args.value_of("key").expect("Missing required arg: key");
+        let length: i64 = args
+            .value_of("length")
+            .expect("Missing required arg: length")
+            .parse()
+            .map_err(|_| format_err!("Could not parse length as a number"))?;
+
+        let value = client
+            .unit_secret_generate(key.into(), length)
+            .call()?
+            .value;
+
+        writeln!(std::io::stdout(), "{}", value)?;
+
+        Ok(data)
+    }
+}
@@ -1,3 +1,4 @@
+use anyhow::Context;
 use clap::{App, Arg, ArgMatches, ArgSettings};
 
 use crate::cli::*;
@@ -29,6 +30,15 @@ impl<'a> CliCommand<'a> for SetStatusSubcommand {
                 )
                 .env("LUCKY_SCRIPT_ID")
                 .required(true))
+            .arg(Arg::with_name("transient")
+                .long("transient")
+                .short('t')
+                .help("Clear the status when the current script exits successfully")
+                .long_help(
+                    "Clear the status when the current script exits successfully. This is useful \
+                    for statuses such as `maintenance \"Installing packages\"` that only apply \
+                    while the script is running. The status is left in place if the script fails."
+                ))
             .arg(Arg::with_name("state")
                 .help("The enumerated state of the service")
                 .possible_values(&ScriptState::variants())
@@ -62,6 +72,16 @@ impl<'a> CliCommand<'a> for SetStatusSubcommand {
             .value_of("status_name")
             .expect("Missing required argument: status-name");
 
+        // Transient statuses are cleared when the script that is running exits
+        let transient_for = if args.is_present("transient") {
+            Some(std::env::var("LUCKY_SCRIPT_ID").context(
+                "The --transient flag can only be used in a script run by Lucky: \
+                LUCKY_SCRIPT_ID is not set",
+            )?)
+        } else {
+            None
+        };
+
         // Get client connection
         let mut client: Box<VarlinkClient> = data
             .remove("client")
@@ -71,7 +91,7 @@ impl<'a> CliCommand<'a> for SetStatusSubcommand {
 
         // Set script status
         client
-            .set_status(status_name.into(), status.into())
+            .set_status(status_name.into(), status.into(), transient_for)
             .call()?;
 
         Ok(data)
@@ -0,0 +1,141 @@
+use chrono::{Local, TimeZone};
+use clap::{App, Arg, ArgMatches};
+
+use std::io::Write;
+
+use crate::cli::*;
+use crate::rpc::{VarlinkClient, VarlinkClientInterface};
+use crate::types::ScriptStatus;
+
+pub(super) struct StatusSubcommand;
+
+impl<'a> CliCommand<'a> for StatusSubcommand {
+    fn get_name(&self) -> &'static str {
+        "status"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Get information about the script statuses")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![Box::new(HistorySubcommand)]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_client_status",
+            content: include_str!("cli_help/status.md"),
+        })
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        Ok(data)
+    }
+}
+
+struct HistorySubcommand;
+
+impl<'a> CliCommand<'a> for HistorySubcommand {
+    fn get_name(&self) -> &'static str {
+        "history"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("Show the recent changes of the script statuses")
+            .long_about(concat!(
+                "Show the recent changes of the script statuses, oldest first. Shows the changes ",
+                "of every script unless `script_id` is given."
+            ))
+            .arg(Arg::with_name("script_id")
+                .help("The ID of the script to show the status changes of, such as `install_0`"))
+            .arg(super::format_arg())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, mut data: CliData) -> anyhow::Result<CliData> {
+        let script_id = args.value_of("script_id");
+
+        // Get client connection
+        let mut client: Box<VarlinkClient> = data
+            .remove("client")
+            .expect("Missing client data")
+            .downcast()
+            .expect("Invalid type");
+
+        let transitions = client
+            .status_history(script_id.map(Into::into))
+            .call()?
+            .transitions;
+
+        // Print the changes as an array of objects
+        if super::is_json_format(args) {
+            let transitions: Vec<serde_json::Value> = transitions
+                .into_iter()
+                .map(|transition| {
+                    serde_json::json!({
+                        "script-id": transition.script_id,
+                        "time": Local.timestamp_millis(transition.time).to_rfc3339(),
+                        "old": transition.old.map(ScriptStatus::from),
+                        "new": transition.new.map(ScriptStatus::from),
+                    })
+                })
+                .collect();
+            writeln!(
+                std::io::stdout(),
+                "{}",
+                serde_json::to_string_pretty(&transitions)?
+            )?;
+            return Ok(data);
+        }
+
+        // Line up the statuses when the changes of several scripts are shown
+        let id_width = if script_id.is_some() {
+            0
+        } else {
+            transitions
+                .iter()
+                .map(|x| x.script_id.len())
+                .max()
+                .unwrap_or(0)
+        };
+
+        let describe = |status: Option<crate::rpc::ScriptStatus>| {
+            status.map_or_else(
+                || "(unset)".to_string(),
+                |x| ScriptStatus::from(x).to_string(),
+            )
+        };
+        for transition in transitions {
+            let time = Local.timestamp_millis(transition.time);
+            let id_column = if script_id.is_some() {
+                String::new()
+            } else {
+                format!("{:width$}  ", transition.script_id, width = id_width)
+            };
+            writeln!(
+                std::io::stdout(),
+                "{}  {}{} -> {}",
+                time.format("%Y-%m-%d %H:%M:%S%.3f"),
+                id_column,
+                describe(transition.old),
+                describe(transition.new)
+            )?;
+        }
+
+        Ok(data)
+    }
+}
@@ -0,0 +1,53 @@
+use clap::{App, Arg, ArgMatches};
+use clap_generate::generate;
+use clap_generate::generators::{Bash, Fish, PowerShell, Zsh};
+
+use crate::cli::*;
+
+pub(super) struct CompletionsSubcommand;
+
+impl<'a> CliCommand<'a> for CompletionsSubcommand {
+    fn get_name(&self) -> &'static str {
+        "completions"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Generate shell completions for the Lucky CLI")
+            .arg(Arg::with_name("shell")
+                .help("The shell to generate the completion script for")
+                .possible_values(&["bash", "zsh", "fish", "powershell"])
+                .required(true))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_completions",
+            content: include_str!("completions/completions.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        let shell = args
+            .value_of("shell")
+            .expect("Missing required argument: shell");
+
+        // Generate the completions from the full command tree
+        let mut app = LuckyCli.get_cli();
+        let mut out = std::io::stdout();
+        match shell {
+            "bash" => generate::<Bash, _>(&mut app, "lucky", &mut out),
+            "zsh" => generate::<Zsh, _>(&mut app, "lucky", &mut out),
+            "fish" => generate::<Fish, _>(&mut app, "lucky", &mut out),
+            "powershell" => generate::<PowerShell, _>(&mut app, "lucky", &mut out),
+            other => anyhow::bail!("Unsupported shell: {}", other),
+        }
+
+        Ok(data)
+    }
+}
@@ -5,8 +5,10 @@ use std::sync::{Arc, RwLock};
 
 mod cron_tick;
 mod exit_code_helper;
+mod reset;
 mod start;
 mod stop;
+mod trigger_action;
 mod trigger_hook;
 
 use crate::cli::*;
@@ -27,7 +29,9 @@ impl<'a> CliCommand<'a> for DaemonSubcommand {
         vec![
             Box::new(start::StartSubcommand),
             Box::new(stop::StopSubcommand),
+            Box::new(reset::ResetSubcommand),
             Box::new(trigger_hook::TriggerHookSubcommand),
+            Box::new(trigger_action::TriggerActionSubcommand),
             Box::new(exit_code_helper::ExitCodeHelperSubcommand),
             Box::new(cron_tick::CronTickSubcommand),
         ]
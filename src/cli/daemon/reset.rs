@@ -0,0 +1,88 @@
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches};
+use rprompt::prompt_reply_stdout;
+
+use crate::cli::daemon::{get_daemon_client, get_daemon_connection_args, get_daemon_socket_path};
+use crate::cli::*;
+use crate::rpc::VarlinkClientInterface;
+
+pub(super) struct ResetSubcommand;
+
+impl<'a> CliCommand<'a> for ResetSubcommand {
+    fn get_name(&self) -> &'static str {
+        "reset"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Clear selected parts of the daemon state")
+            .long_about(concat!(
+                "Clear selected parts of the daemon state. This is useful for recovering a unit ",
+                "that keeps failing because of stale internal state. Every reset is recorded in ",
+                "the `audit.log` file in the unit's data directory."
+            ))
+            .arg(Arg::with_name("statuses")
+                .long("statuses")
+                .help("Clear all of the script statuses")
+                .required_unless_one(&["queue", "containers"]))
+            .arg(Arg::with_name("queue")
+                .long("queue")
+                .help("Drop any cron jobs that are pending execution"))
+            .arg(Arg::with_name("containers")
+                .long("containers")
+                .help("Forget all container configuration")
+                .long_help(concat!(
+                    "Forget all container configuration. Note that this will not stop or remove ",
+                    "any containers that are already running."
+                )))
+            .arg(Arg::with_name("yes")
+                .long("yes")
+                .short('y')
+                .help("Don't prompt for confirmation"))
+            .args(&get_daemon_connection_args())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        let socket_path = get_daemon_socket_path(args);
+        let statuses = args.is_present("statuses");
+        let queue = args.is_present("queue");
+        let containers = args.is_present("containers");
+
+        // Confirm the reset with the user
+        if !args.is_present("yes") {
+            // Refuse to reset without confirmation if we can't prompt for it
+            if !atty::is(atty::Stream::Stdin) {
+                anyhow::bail!("Refusing to reset daemon state without confirmation: use --yes");
+            }
+
+            let response = prompt_reply_stdout(
+                "This will clear the selected daemon state and cannot be undone. Continue? [y/N]: ",
+            )
+            .context("Could not prompt for confirmation")?;
+
+            if !response.trim().eq_ignore_ascii_case("y") {
+                log::info!("Reset cancelled");
+                return Ok(data);
+            }
+        }
+
+        // Connect to lucky daemon
+        let mut client = get_daemon_client(&socket_path)?;
+
+        // Reset the daemon state
+        client.daemon_reset(statuses, queue, containers).call()?;
+
+        log::info!("Daemon state reset");
+
+        Ok(data)
+    }
+}
@@ -18,8 +18,14 @@ use crate::cli::daemon::{
 use crate::cli::*;
 use crate::config;
 use crate::daemon::LuckyDaemonOptions;
+use crate::juju::ExecJujuBackend;
 use crate::log::{set_log_mode, LogMode::Daemon};
-use crate::types::LuckyMetadata;
+use crate::types::{JobSchedule, LuckyMetadata};
+
+/// How often the daemon writes out the logs of containers that forward their logs to Juju
+const LOG_FORWARDING_INTERVAL: Duration = Duration::from_secs(30);
+/// How often the daemon checks the containers declared in the `lucky.yaml` for crash loops
+const CRASH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
 
 pub(super) struct StartSubcommand;
 
@@ -59,6 +65,30 @@ impl<'a> CliCommand<'a> for StartSubcommand {
                 .takes_value(true)
                 .help("File to write daemon logs to")
                 .env("LUCKY_LOG_FILE"))
+            .arg(Arg::with_name("rpc_threads")
+                .long("rpc-threads")
+                .takes_value(true)
+                .default_value("8")
+                .help("The maximum number of RPC requests to handle at the same time")
+                .long_help(concat!(
+                    "The maximum number of RPC requests to handle at the same time. Read-only ",
+                    "requests, such as getting a key-value, can be handled concurrently when ",
+                    "multiple scripts are running at once. Requests that make changes are always ",
+                    "handled one at a time regardless of this setting."
+                ))
+                .env("LUCKY_RPC_THREADS"))
+            .arg(Arg::with_name("otlp_endpoint")
+                .long("otlp-endpoint")
+                .takes_value(true)
+                .help("An OpenTelemetry collector to export traces to")
+                .long_help(concat!(
+                    "An OpenTelemetry collector to export traces to, such as ",
+                    "`http://localhost:4318`. Traces are sent using the OTLP/HTTP protocol. If ",
+                    "this is not specified, the standard `OTEL_EXPORTER_OTLP_ENDPOINT` ",
+                    "environment variable will be used if it is set. The service name can be ",
+                    "set with the `OTEL_SERVICE_NAME` environment variable."
+                ))
+                .env("LUCKY_OTLP_ENDPOINT"))
             .args(&get_daemon_connection_args())
     }
 
@@ -87,6 +117,21 @@ impl<'a> CliCommand<'a> for StartSubcommand {
 
         let socket_path = get_daemon_socket_path(args);
 
+        let rpc_threads: usize = args
+            .value_of("rpc_threads")
+            .expect("Missing required arg: rpc_threads")
+            .parse()
+            .context("Could not parse --rpc-threads as a number")?;
+        if rpc_threads == 0 {
+            anyhow::bail!("--rpc-threads must be at least 1");
+        }
+
+        // Get the OpenTelemetry collector endpoint
+        let otlp_endpoint = args
+            .value_of("otlp_endpoint")
+            .map(String::from)
+            .or_else(|| std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok());
+
         let listen_address = format!("unix:{};mode=700", socket_path);
 
         // Make sure a daemon is not already running
@@ -110,6 +155,14 @@ impl<'a> CliCommand<'a> for StartSubcommand {
             set_log_mode(Daemon);
             log::info!("Starting daemon in foreground");
 
+            // Enable trace export
+            if let Some(endpoint) = &otlp_endpoint {
+                let service_name =
+                    std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "lucky".into());
+                crate::trace::init(endpoint, &service_name)
+                    .context("Could not enable trace export")?;
+            }
+
             // The stop_listening flag is used to shutdown the server by setting it to `false`
             let stop_listening = Arc::new(AtomicBool::new(false));
 
@@ -124,12 +177,30 @@ impl<'a> CliCommand<'a> for StartSubcommand {
             let lucky_metadata: LuckyMetadata = config::load_yaml(&charm_dir, "lucky")?;
 
             // Collect cron schedules ( for scheduling cron tick )
-            let cron_schedules: Vec<cron::Schedule> = lucky_metadata
+            let cron_schedules: Vec<JobSchedule> = lucky_metadata
                 .cron_jobs
                 .keys()
                 .map(|x| x.parse())
                 .collect::<Result<_, _>>()
-                .map_err(|e| format_err!("Could not parse cron job: {}", e))?;
+                .context("Could not parse cron job")?;
+
+            // Get the longest time to wait between cron ticks so that container health checks
+            // are run, forwarded container logs are written out, and crash loops are reported on
+            // time
+            let tick_interval = lucky_metadata
+                .containers
+                .values()
+                .filter_map(|x| x.healthcheck.as_ref())
+                .map(|x| Duration::from_secs(x.interval.max(1)))
+                .chain(
+                    lucky_metadata
+                        .containers
+                        .values()
+                        .filter(|x| x.forward_logs)
+                        .map(|_| LOG_FORWARDING_INTERVAL),
+                )
+                .chain(Some(CRASH_CHECK_INTERVAL).filter(|_| !lucky_metadata.containers.is_empty()))
+                .min();
 
             log::trace!("loaded lucky.yml: {:#?}", lucky_metadata);
 
@@ -140,6 +211,7 @@ impl<'a> CliCommand<'a> for StartSubcommand {
                 data_dir,
                 stop_listening: stop_listening.clone(),
                 socket_path: PathBuf::from(&socket_path),
+                juju: Arc::new(ExecJujuBackend),
             });
 
             // Set signal handler for SIGINT/SIGTERM
@@ -158,9 +230,9 @@ impl<'a> CliCommand<'a> for StartSubcommand {
                     service,
                     &listen_address,
                     &varlink::ListenConfig {
-                        // We only need one thread because Juju only allows one charm context at a
-                        // time anyway.
-                        max_worker_threads: 1,
+                        // Read-only requests may be handled concurrently, but the daemon
+                        // serializes any requests that make changes.
+                        max_worker_threads: rpc_threads,
                         stop_listening: Some(stop_listening_),
                         ..Default::default()
                     },
@@ -175,7 +247,14 @@ impl<'a> CliCommand<'a> for StartSubcommand {
             let unit_name_ = unit_name.to_string();
             let cron_thread = thread::Builder::new()
                 .name("cron-tick".into())
-                .spawn(move || cron_tick(&unit_name_, cron_schedules.as_slice(), &stop_listening))
+                .spawn(move || {
+                    cron_tick(
+                        &unit_name_,
+                        cron_schedules.as_slice(),
+                        tick_interval,
+                        &stop_listening,
+                    )
+                })
                 .context("Could not spawn cron-tick thread")?;
 
             // Get the server thread result
@@ -204,10 +283,19 @@ impl<'a> CliCommand<'a> for StartSubcommand {
                 .env("LUCKY_DATA_DIR", &*data_dir)
                 .env("JUJU_UNIT_NAME", &unit_name)
                 .env("LUCKY_DAEMON_SOCKET", &socket_path)
+                .env("LUCKY_RPC_THREADS", rpc_threads.to_string())
                 .args(&["start", "-F"]);
             if let Some(log_file) = args.value_of("log_file") {
                 cmd.env("LUCKY_LOG_FILE", log_file);
             }
+            if let Some(endpoint) = &otlp_endpoint {
+                cmd.env("LUCKY_OTLP_ENDPOINT", endpoint);
+            }
+            // Pass on the log filter so that the verbosity flags apply to the daemon too
+            let log_filter = crate::log::log_filter_spec();
+            if !log_filter.is_empty() {
+                cmd.env("LUCKY_LOG", log_filter);
+            }
 
             // Spawn process and stream output
             cmd.spawn().context("Could not start lucky daemon")?;
@@ -241,7 +329,13 @@ impl<'a> CliCommand<'a> for StartSubcommand {
     }
 }
 
-fn cron_tick(unit_name: &str, cron_schedules: &[cron::Schedule], stop: &Arc<AtomicBool>) {
+/// Run the cron tick whenever a cron job is scheduled or a health check is due
+fn cron_tick(
+    unit_name: &str,
+    cron_schedules: &[JobSchedule],
+    tick_interval: Option<Duration>,
+    stop: &Arc<AtomicBool>,
+) {
     // Lucky exe path
     let lucky_exe = match std::env::current_exe() {
         Ok(exe) => exe,
@@ -289,7 +383,7 @@ fn cron_tick(unit_name: &str, cron_schedules: &[cron::Schedule], stop: &Arc<Atom
         // Find closest next cron job time
         for schedule in cron_schedules {
             // If this schedule has an upcomming date
-            if let Some(time) = schedule.upcoming(chrono::Local).next() {
+            if let Some(time) = schedule.after(&chrono::Local::now()) {
                 // If we already have a next_time
                 if let Some(nt) = next_time {
                     // If this time is before the next time
@@ -305,6 +399,15 @@ fn cron_tick(unit_name: &str, cron_schedules: &[cron::Schedule], stop: &Arc<Atom
             }
         }
 
+        // Tick again in time for the next health check or log flush if it is before the next job
+        if let Some(interval) = tick_interval {
+            let interval_time = chrono::Local::now()
+                + chrono::Duration::from_std(interval).unwrap_or_else(|_| chrono::Duration::zero());
+            if next_time.map_or(true, |nt| interval_time < nt) {
+                next_time = Some(interval_time);
+            }
+        }
+
         // If we found a next job time
         if let Some(time) = next_time {
             // Get the time between now and the next job
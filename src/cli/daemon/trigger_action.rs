@@ -0,0 +1,92 @@
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches};
+
+use std::collections::HashMap;
+
+use crate::cli::daemon::{get_daemon_client, get_daemon_connection_args, get_daemon_socket_path};
+use crate::cli::*;
+use crate::rpc::VarlinkClientInterface;
+
+pub(super) struct TriggerActionSubcommand;
+
+impl<'a> CliCommand<'a> for TriggerActionSubcommand {
+    fn get_name(&self) -> &'static str {
+        "trigger-action"
+    }
+
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Run an action through the Lucky daemon")
+            .arg(Arg::with_name("action_name").help(concat!(
+                "The name of the action to trigger. Defaults to the action in the ",
+                "`JUJU_ACTION_NAME` environment variable"
+            )))
+            .args(&get_daemon_connection_args())
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        let socket_path = get_daemon_socket_path(args);
+
+        // Get the action name from the args or from the environment set by Juju
+        let action_name = match args.value_of("action_name") {
+            Some(action_name) => action_name.to_string(),
+            None => std::env::var("JUJU_ACTION_NAME")
+                .ok()
+                .or_else(|| {
+                    std::env::var("JUJU_DISPATCH_PATH")
+                        .ok()
+                        .and_then(|path| path.strip_prefix("actions/").map(ToOwned::to_owned))
+                })
+                .ok_or_else(|| {
+                    format_err!("No action name given and JUJU_ACTION_NAME is not set")
+                })?,
+        };
+
+        // Populate environment variables the Lucky daemon may need for executing the action
+        let mut environment: HashMap<String, String> = HashMap::new();
+        for &var in &["JUJU_CONTEXT_ID", "JUJU_ACTION_NAME", "JUJU_ACTION_UUID"] {
+            if let Ok(value) = std::env::var(var) {
+                environment.insert(var.into(), value);
+            }
+        }
+
+        // Connect to lucky daemon
+        let mut client = get_daemon_client(&socket_path)?;
+
+        // Cancel the action in the daemon if we are terminated before it finishes
+        if let Some(action_id) = environment.get("JUJU_ACTION_UUID").cloned() {
+            let socket_path = socket_path.clone();
+            ctrlc::set_handler(move || {
+                log::warn!("Cancelling action {}", action_id);
+                if let Ok(mut client) = get_daemon_client(&socket_path) {
+                    client.cancel_action(action_id.clone()).call().ok();
+                }
+                std::process::exit(1);
+            })
+            .context("Error setting signal handler for SIGINT/SIGTERM")?;
+        }
+
+        log::info!(r#"Triggering action "{}""#, &action_name);
+
+        // Trigger the action and wait for it to finish
+        client
+            .trigger_action(
+                action_name.clone(),
+                environment,
+                Some(i64::from(std::process::id())),
+            )
+            .call()?;
+
+        log::info!(r#"Done running action "{}""#, &action_name);
+
+        Ok(data)
+    }
+}
@@ -16,7 +16,10 @@ impl<'a> CliCommand<'a> for TriggerHookSubcommand {
     fn get_app(&self) -> App<'a> {
         self.get_base_app()
             .about("Run a hook through the Lucky daemon")
-            .arg(Arg::with_name("hook_name").help("The name of the hook to trigger"))
+            .arg(Arg::with_name("hook_name").help(concat!(
+                "The name of the hook to trigger. Defaults to the hook in the ",
+                "`JUJU_DISPATCH_PATH` environment variable"
+            )))
             .args(&get_daemon_connection_args())
     }
 
@@ -31,10 +34,16 @@ impl<'a> CliCommand<'a> for TriggerHookSubcommand {
     fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
         let socket_path = get_daemon_socket_path(args);
 
-        let hook_name = args
-            .value_of("hook_name")
-            .expect("Missing required argument: hook_name")
-            .to_string();
+        // Get the hook name from the args or from the dispatch path set by Juju
+        let hook_name = match args.value_of("hook_name") {
+            Some(hook_name) => hook_name.to_string(),
+            None => std::env::var("JUJU_DISPATCH_PATH")
+                .ok()
+                .and_then(|path| path.strip_prefix("hooks/").map(ToOwned::to_owned))
+                .ok_or_else(|| {
+                    format_err!("No hook name given and JUJU_DISPATCH_PATH is not set to a hook")
+                })?,
+        };
 
         // Populate environment variables the Lucky daemon may need for executing the hook
         let mut environment: HashMap<String, String> = HashMap::new();
@@ -0,0 +1,33 @@
+use clap::{App, ArgMatches};
+
+mod watch;
+
+use crate::cli::*;
+
+pub(super) struct DevSubcommand;
+
+impl<'a> CliCommand<'a> for DevSubcommand {
+    fn get_name(&self) -> &'static str {
+        "dev"
+    }
+
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Tools for developing charms against a live deployment")
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![Box::new(watch::WatchSubcommand)]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_dev",
+            content: include_str!("dev/dev.md"),
+        })
+    }
+
+    fn execute_command(&self, _args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        Ok(data)
+    }
+}
@@ -0,0 +1,331 @@
+use anyhow::Context;
+use clap::{App, Arg, ArgMatches};
+use serde_yaml::Value;
+use walkdir::WalkDir;
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime};
+
+use crate::cli::util::{juju, juju_stdout};
+use crate::cli::*;
+
+/// The directory on the unit that changed files are copied to before they are moved into the
+/// charm directory, which is only writable by root
+const REMOTE_STAGING_DIR: &str = "/tmp/lucky-dev-watch";
+/// The directories in the charm source that are not synced to the unit
+const IGNORED_DIRS: &[&str] = &["build", ".git"];
+
+pub(super) struct WatchSubcommand;
+
+impl<'a> CliCommand<'a> for WatchSubcommand {
+    fn get_name(&self) -> &'static str {
+        "watch"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Sync changes to a charm into a deployed unit as they are made")
+            .arg(Arg::with_name("unit")
+                .help("The unit to sync the charm to, such as `my-app/0`")
+                .required(true))
+            .arg(Arg::with_name("charm_dir")
+                .help("The path to the charm source")
+                .required(false)
+                .default_value("."))
+            .arg(Arg::with_name("interval")
+                .help("How often to check for changes, in seconds")
+                .long("interval")
+                .short('i')
+                .default_value("1"))
+            .arg(Arg::with_name("rerun")
+                .help("Re-run the last hook that the unit ran after syncing changes")
+                .long("rerun")
+                .short('r'))
+            .arg(Arg::with_name("hook")
+                .help("Re-run this hook after syncing changes instead of the last hook")
+                .long("hook")
+                .takes_value(true))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_dev_watch",
+            content: include_str!("watch/watch.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, _data: CliData) -> anyhow::Result<CliData> {
+        let unit = args.value_of("unit").expect("Missing required arg: unit");
+        let charm_path = Path::new(
+            args.value_of("charm_dir")
+                .expect("Missing required argument: charm_dir"),
+        );
+        let interval: u64 = args
+            .value_of("interval")
+            .expect("Missing required arg: interval")
+            .parse()
+            .context("Invalid interval")?;
+        let hook = args.value_of("hook");
+        let rerun = args.is_present("rerun") || hook.is_some();
+
+        let unit_charm_dir = get_unit_charm_dir(unit)?;
+
+        log::info!(
+            "Watching {:?} for changes to sync to {}. Press Ctrl-C to stop.",
+            charm_path,
+            unit
+        );
+        let mut files = scan_files(charm_path)?;
+        loop {
+            sleep(Duration::from_secs(interval));
+
+            let new_files = scan_files(charm_path)?;
+            let changed: Vec<PathBuf> = new_files
+                .iter()
+                .filter(|(path, modified)| files.get(*path) != Some(*modified))
+                .map(|(path, _)| path.clone())
+                .collect();
+            let removed: Vec<PathBuf> = files
+                .keys()
+                .filter(|path| !new_files.contains_key(*path))
+                .cloned()
+                .collect();
+            files = new_files;
+
+            if changed.is_empty() && removed.is_empty() {
+                continue;
+            }
+
+            // Keep watching when syncing fails so that the next change can fix it
+            if let Err(e) = sync_changes(unit, &unit_charm_dir, charm_path, &changed, &removed) {
+                log::error!("{:?}", e);
+                continue;
+            }
+
+            if rerun {
+                if let Err(e) = rerun_hook(unit, hook) {
+                    log::error!("{:?}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Get the charm directory of a deployed unit, such as `/var/lib/juju/agents/unit-my-app-0/charm`
+/// for `my-app/0`
+fn get_unit_charm_dir(unit: &str) -> anyhow::Result<String> {
+    let mut parts = unit.splitn(2, '/');
+    match (parts.next(), parts.next()) {
+        (Some(app), Some(number)) if !app.is_empty() && number.parse::<u32>().is_ok() => Ok(
+            format!("/var/lib/juju/agents/unit-{}-{}/charm", app, number),
+        ),
+        _ => anyhow::bail!(
+            "Invalid unit name, expected a name like `my-app/0`: {}",
+            unit
+        ),
+    }
+}
+
+/// Get the modification times of the files in the charm source, keyed by their path relative to
+/// the charm directory
+fn scan_files(charm_dir: &Path) -> anyhow::Result<HashMap<PathBuf, SystemTime>> {
+    let mut files = HashMap::new();
+
+    for entry in WalkDir::new(charm_dir).into_iter().filter_entry(|e| {
+        // Skip the ignored dirs at the top of the charm
+        !(e.depth() == 1
+            && e.file_type().is_dir()
+            && IGNORED_DIRS.contains(&e.file_name().to_string_lossy().as_ref()))
+    }) {
+        let entry = entry?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        let relative_path = entry
+            .path()
+            .strip_prefix(charm_dir)
+            .expect("Internal error parsing charm paths");
+        let modified = entry
+            .metadata()?
+            .modified()
+            .context(format!("Could not read file metadata: {:?}", entry.path()))?;
+        files.insert(relative_path.to_path_buf(), modified);
+    }
+
+    Ok(files)
+}
+
+/// Copy the changed files into the unit's charm directory and delete the removed files from it
+fn sync_changes(
+    unit: &str,
+    unit_charm_dir: &str,
+    charm_dir: &Path,
+    changed: &[PathBuf],
+    removed: &[PathBuf],
+) -> anyhow::Result<()> {
+    juju(&["ssh", unit, &format!("mkdir -p {}", REMOTE_STAGING_DIR)])?;
+
+    // Juju can only copy files to where the `ubuntu` user can write, so the files are staged and
+    // then moved into the charm dir as root
+    let mut script = String::from("set -e\n");
+    for (i, path) in changed.iter().enumerate() {
+        let local_path = charm_dir.join(path);
+        let staged_path = format!("{}/{}", REMOTE_STAGING_DIR, i);
+        juju(&[
+            "scp",
+            &local_path.to_string_lossy(),
+            &format!("{}:{}", unit, staged_path),
+        ])?;
+
+        script.push_str(&format!(
+            "install -D -m {:o} {} {}\n",
+            file_mode(&local_path)?,
+            staged_path,
+            shell_quote(&remote_path(unit_charm_dir, path)),
+        ));
+    }
+    for path in removed {
+        script.push_str(&format!(
+            "rm -f {}\n",
+            shell_quote(&remote_path(unit_charm_dir, path))
+        ));
+    }
+    script.push_str(&format!("rm -rf {}\n", REMOTE_STAGING_DIR));
+    juju(&[
+        "ssh",
+        unit,
+        &format!("sudo bash -c {}", shell_quote(&script)),
+    ])?;
+
+    for path in changed {
+        log::info!("Synced {:?}", path);
+    }
+    for path in removed {
+        log::info!("Removed {:?}", path);
+    }
+
+    // The daemon only reads the lucky.yaml when it starts
+    let lucky_yaml_changed = changed
+        .iter()
+        .chain(removed)
+        .any(|x| x == Path::new("lucky.yaml") || x == Path::new("lucky.yml"));
+    if lucky_yaml_changed {
+        log::info!("Restarting the Lucky daemon to load the new lucky.yaml");
+        let unit_name = unit.replace('/', "_");
+        let lucky = format!("/var/lib/lucky/{}/bin/lucky", unit_name);
+        juju(&[
+            "run",
+            "--unit",
+            unit,
+            &format!(
+                "LUCKY_CONTEXT=daemon {lucky} stop --ignore-already-stopped && \
+                LUCKY_CONTEXT=daemon {lucky} start --ignore-already-running \
+                --log-file /var/log/lucky/{unit_name}.log",
+                lucky = lucky,
+                unit_name = unit_name
+            ),
+        ])?;
+    }
+
+    Ok(())
+}
+
+/// Re-run a hook on the unit, or the last hook that the unit ran if `hook` is `None`
+fn rerun_hook(unit: &str, hook: Option<&str>) -> anyhow::Result<()> {
+    let hook = match hook {
+        Some(hook) => hook.to_string(),
+        None => match get_last_hook(unit)? {
+            Some(hook) => hook,
+            None => {
+                log::warn!("Not re-running a hook: the unit hasn't run any hooks yet");
+                return Ok(());
+            }
+        },
+    };
+
+    // Juju only provides the context of relation and storage hooks when it runs them itself
+    if hook.contains("-relation-") || hook.contains("-storage-") {
+        log::warn!(
+            "Not re-running hook {}: relation and storage hooks can only be run by Juju",
+            hook
+        );
+        return Ok(());
+    }
+
+    log::info!("Re-running hook: {}", hook);
+    let output = juju(&[
+        "run",
+        "--unit",
+        unit,
+        &format!(
+            "if [ -x ./dispatch ]; then JUJU_DISPATCH_PATH=hooks/{hook} ./dispatch; \
+            else ./hooks/{hook}; fi",
+            hook = hook
+        ),
+    ])?;
+    write!(io::stdout(), "{}", output)?;
+
+    Ok(())
+}
+
+/// Get the last hook that the unit ran from the Lucky daemon state
+fn get_last_hook(unit: &str) -> anyhow::Result<Option<String>> {
+    let state_file = format!("/var/lib/lucky/{}/state.yaml", unit.replace('/', "_"));
+    let state: Value = serde_yaml::from_str(&juju_stdout(&[
+        "ssh",
+        unit,
+        &format!("sudo cat {}", state_file),
+    ])?)
+    .context(format!("Could not parse daemon state on unit {}", unit))?;
+
+    Ok(state
+        .get("last_hook")
+        .and_then(Value::as_str)
+        .map(Into::into))
+}
+
+//
+// Helpers
+//
+
+/// Get the path of a file in the charm directory of the unit
+fn remote_path(unit_charm_dir: &str, path: &Path) -> String {
+    // Remote paths always use forward slashes
+    let relative_path = path
+        .components()
+        .map(|x| x.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/");
+    format!("{}/{}", unit_charm_dir, relative_path)
+}
+
+/// Quote a string for a shell
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Gets the file permission mode on Unix, or a default mode on other systems
+fn file_mode(path: &Path) -> anyhow::Result<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata =
+            std::fs::metadata(path).context(format!("Could not read file metadata: {:?}", path))?;
+        Ok(metadata.permissions().mode() & 0o777)
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        Ok(0o644)
+    }
+}
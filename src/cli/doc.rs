@@ -1,4 +1,270 @@
-//! Handles printing doc pages for both the commandline pager and the mdbook site
+//! Handles printing doc pages for the commandline pager, and generating the mdbook site, the static
+//! doc site, and the man pages
+
+use clap::{App, Arg, ArgMatches};
+use regex::Regex;
+
+use std::io::Write;
+
+use crate::cli::doc::cmdln_pager::{show_doc_page, show_guide};
+use crate::cli::*;
 
 pub(crate) mod cmdln_pager;
+mod generate;
+pub(crate) mod man;
 pub(crate) mod mdbook;
+pub(crate) mod site;
+
+/// A standalone guide that can be opened with `lucky doc`
+struct Guide {
+    /// The topic that opens the guide
+    topic: &'static str,
+    /// A short description of the guide
+    description: &'static str,
+    /// The content of the guide
+    doc: CliDoc,
+}
+
+/// The files of the example charm that the getting started guide includes, keyed by the path that
+/// the guide includes them with
+const GETTING_STARTED_INCLUDES: &[(&str, &str)] = &[
+    (
+        "./codimd-example-charm/config.yaml",
+        include_str!("../../docs/book/src/codimd-example-charm/config.yaml"),
+    ),
+    (
+        "./codimd-example-charm/lucky.yaml",
+        include_str!("../../docs/book/src/codimd-example-charm/lucky.yaml"),
+    ),
+    (
+        "./codimd-example-charm/host_scripts/install.sh",
+        include_str!("../../docs/book/src/codimd-example-charm/host_scripts/install.sh"),
+    ),
+    (
+        "./codimd-example-charm/host_scripts/configure.sh",
+        include_str!("../../docs/book/src/codimd-example-charm/host_scripts/configure.sh"),
+    ),
+    (
+        "./codimd-example-charm/host_scripts/handle-database-relation.sh",
+        include_str!(
+            "../../docs/book/src/codimd-example-charm/host_scripts/handle-database-relation.sh"
+        ),
+    ),
+    (
+        "./codimd-example-charm/host_scripts/handle-website-relation.sh",
+        include_str!(
+            "../../docs/book/src/codimd-example-charm/host_scripts/handle-website-relation.sh"
+        ),
+    ),
+];
+
+lazy_static::lazy_static! {
+    /// Matches an mdbook `{{#include path:start:end}}` directive
+    static ref BOOK_INCLUDE: Regex =
+        Regex::new(r"\{\{#include (?P<path>[^:}]+)(?::(?P<start>\d*))?(?::(?P<end>\d*))?\}\}")
+            .expect("Could not compile regex");
+
+    /// The getting started guide from the book, with its includes expanded
+    static ref GETTING_STARTED: String = expand_book_includes(
+        include_str!("../../docs/book/src/getting-started.md"),
+        GETTING_STARTED_INCLUDES
+    );
+
+    /// The standalone guides
+    static ref GUIDES: Vec<Guide> = vec![
+        Guide {
+            topic: "getting-started",
+            description: "A walk through creating a charm for CodiMD",
+            doc: CliDoc {
+                name: "guide_getting_started",
+                content: GETTING_STARTED.as_str(),
+            },
+        },
+        Guide {
+            topic: "lucky-yaml",
+            description: "Every option of the lucky.yaml file",
+            doc: CliDoc {
+                name: "guide_lucky_yaml",
+                content: concat!(
+                    "# lucky.yaml Reference\n\n",
+                    "The `lucky.yaml` file tells Lucky which scripts to run for the charm's hooks, ",
+                    "actions, and cron jobs, and which containers to run. This is the `lucky.yaml` ",
+                    "from the charm template, which documents every option in comments.\n\n",
+                    "```yaml\n",
+                    include_str!("../../charm_template/lucky.yaml"),
+                    "\n```"
+                ),
+            },
+        },
+    ];
+}
+
+/// Replace the mdbook `{{#include}}` directives of a book page with the contents of the included
+/// files, which are given as pairs of the included path and the file contents
+///
+/// Line ranges are handled the same way as mdbook: `:2` includes only line 2, `:2:` includes line
+/// 2 to the end, `::5` includes the first 5 lines, and `:2:5` includes lines 2 to 5. Includes of
+/// files that aren't given are left as they are.
+fn expand_book_includes(content: &str, files: &[(&str, &str)]) -> String {
+    BOOK_INCLUDE
+        .replace_all(content, |captures: &regex::Captures| {
+            let directive = captures.get(0).map_or("", |x| x.as_str()).to_string();
+            let path = captures.name("path").map_or("", |x| x.as_str()).trim();
+            let file = match files.iter().find(|(x, _)| *x == path) {
+                Some((_, file)) => file,
+                None => return directive,
+            };
+
+            let line_number = |name| {
+                captures
+                    .name(name)
+                    .and_then(|x| x.as_str().parse::<usize>().ok())
+            };
+            let start = line_number("start").unwrap_or(1).max(1);
+            let end = match (captures.name("start"), captures.name("end")) {
+                // A range such as `:2:5` or `:2:`
+                (_, Some(_)) => line_number("end"),
+                // A single line such as `:2`
+                (Some(x), None) if !x.as_str().is_empty() => Some(start),
+                // The whole file
+                _ => None,
+            };
+
+            file.lines()
+                .skip(start - 1)
+                .take(end.map_or(usize::MAX, |end| (end + 1).saturating_sub(start)))
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .into_owned()
+}
+
+pub(super) struct DocSubcommand;
+
+impl<'a> CliCommand<'a> for DocSubcommand {
+    fn get_name(&self) -> &'static str {
+        "doc"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .unset_setting(clap::AppSettings::ArgRequiredElseHelp)
+            .about("Browse and generate the documentation of the Lucky CLI")
+            .long_about(concat!(
+                "Browse and generate the documentation of the Lucky CLI. Lists the doc pages when ",
+                "no topic is given."
+            ))
+            .arg(Arg::with_name("topic")
+                .help("The doc page to open, such as `getting-started` or `charm create`")
+                .multiple(true))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![Box::new(generate::GenerateSubcommand)]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        None
+    }
+
+    fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        // Let the subcommand run if one was given
+        if args.subcommand_name().is_some() {
+            return Ok(data);
+        }
+
+        let topic: Vec<&str> = args
+            .values_of("topic")
+            .map_or_else(Vec::new, Iterator::collect);
+
+        // List the topics if none was given
+        if topic.is_empty() {
+            list_topics()?;
+            return Ok(data);
+        }
+
+        // Open the guide if the topic is one
+        let joined_topic = topic.join(" ");
+        if let Some(guide) = GUIDES.iter().find(|x| x.topic == joined_topic) {
+            show_guide(guide.doc, None)?;
+            return Ok(data);
+        }
+
+        // Open the doc page of the command, allowing the path to start with `lucky`
+        let path: Vec<&str> = topic.into_iter().skip_while(|&x| x == "lucky").collect();
+        match find_command(Box::new(LuckyCli), &path) {
+            Some(command) => show_doc_page(command.as_ref(), None)?,
+            None => anyhow::bail!(
+                "Unknown doc topic: {}. Run `lucky doc` to list the topics",
+                joined_topic
+            ),
+        }
+
+        Ok(data)
+    }
+}
+
+/// Print the guides and the commands that have doc pages
+fn list_topics() -> anyhow::Result<()> {
+    let mut stdout = std::io::stdout();
+
+    writeln!(stdout, "Guides:\n")?;
+    let topic_width = GUIDES.iter().map(|x| x.topic.len()).max().unwrap_or(0);
+    for guide in GUIDES.iter() {
+        writeln!(
+            stdout,
+            "  {:width$}  {}",
+            guide.topic,
+            guide.description,
+            width = topic_width
+        )?;
+    }
+
+    writeln!(stdout, "\nCommands:\n")?;
+    let mut topics = vec![];
+    collect_command_topics(&LuckyCli, "", &mut topics);
+    for topic in topics {
+        writeln!(stdout, "  {}", topic)?;
+    }
+
+    writeln!(stdout, "\nOpen a doc page with `lucky doc <topic>`")?;
+
+    Ok(())
+}
+
+/// Collect the paths of the commands below the given command that have doc pages, such as
+/// `charm create`
+fn collect_command_topics<'a>(
+    command: &dyn CliCommand<'a>,
+    prefix: &str,
+    topics: &mut Vec<String>,
+) {
+    for subcommand in command.get_subcommands() {
+        let path = if prefix.is_empty() {
+            subcommand.get_name().to_string()
+        } else {
+            format!("{} {}", prefix, subcommand.get_name())
+        };
+
+        if subcommand.get_doc().is_some() {
+            topics.push(path.clone());
+        }
+        collect_command_topics(subcommand.as_ref(), &path, topics);
+    }
+}
+
+/// Find the command at the path of subcommand names below the given command
+fn find_command<'a>(
+    command: Box<dyn CliCommand<'a>>,
+    path: &[&str],
+) -> Option<Box<dyn CliCommand<'a>>> {
+    match path.split_first() {
+        None => Some(command),
+        Some((name, rest)) => command
+            .get_subcommands()
+            .into_iter()
+            .find(|x| x.get_name() == *name)
+            .and_then(|x| find_command(x, rest)),
+    }
+}
@@ -5,44 +5,178 @@ use anyhow::Context;
 use crossterm::{
     cursor::{Hide, MoveTo, Show},
     event::{self, Event, KeyCode::*, KeyEvent},
-    style::{style, Attribute::*, Color::*, ResetColor, SetBackgroundColor, SetForegroundColor},
-    terminal::{self, size, Clear, ClearType::All, EnterAlternateScreen, LeaveAlternateScreen},
+    style::{
+        style, Attribute::*, Color::*, ResetColor, SetAttribute, SetBackgroundColor,
+        SetForegroundColor,
+    },
+    terminal::{
+        self, size, Clear,
+        ClearType::{All, CurrentLine},
+        EnterAlternateScreen, LeaveAlternateScreen,
+    },
     QueueableCommand,
 };
 use lazy_static::lazy_static;
-use minimad::TextTemplate;
+use minimad::{Composite, CompositeStyle, Line, Text, TextTemplate};
 use regex::Regex;
 use termimad::*;
 
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::fs::OpenOptions;
 use std::io::{stdout, Read, Seek, SeekFrom, Write};
+use std::process::{Command, Stdio};
 
 use crate::cli::{CliCommand, CliDoc};
 
+mod theme;
+
+/// The smallest terminal width that the pager can render the docs in
+const MIN_PAGER_WIDTH: u16 = 20;
+/// The smallest terminal height that the pager can render the docs in, including the help bar
+const MIN_PAGER_HEIGHT: u16 = 5;
+
+/// The environment variable that sets the pager to use when `--pager` isn't given
+const PAGER_ENV_VAR: &str = "LUCKY_PAGER";
+/// The command used for the external pager when `$PAGER` isn't set
+const DEFAULT_EXTERNAL_PAGER: &str = "less -R";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+/// The pager that doc pages are shown in
+pub(crate) enum PagerKind {
+    /// The built-in pager
+    Builtin,
+    /// The user's `$PAGER`, which is given the doc rendered to ANSI text
+    External,
+}
+
+impl std::str::FromStr for PagerKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "builtin" => Ok(Self::Builtin),
+            "external" => Ok(Self::External),
+            other => anyhow::bail!(
+                "Unrecognized pager \"{}\": expected \"builtin\" or \"external\"",
+                other
+            ),
+        }
+    }
+}
+
 lazy_static! {
     /// Creates a colored `USAGE: ` + args template for use in the doc pages
     static ref USAGE_TEMPLATE: String = {
-        let usage_header = style("USAGE:").with(DarkYellow);
-        format!("{} {{usage}}\n\n{{all-args}}", usage_header)
+        if crate::log::color_enabled(atty::Stream::Stdout) {
+            let usage_header = style("USAGE:").with(DarkYellow);
+            format!("{} {{usage}}\n\n{{all-args}}", usage_header)
+        } else {
+            "USAGE: {usage}\n\n{all-args}".into()
+        }
     };
+}
 
-    /// The markdown renderer skin
-    static ref MD_SKIN: MadSkin = {
-        let mut skin = MadSkin::default();
-        skin.headers[0].set_fg(DarkYellow);
-        skin.set_headers_fg(DarkYellow);
-        skin.bold.set_fg(Magenta);
-        skin.italic.add_attr(Underlined);
+/// Get the markdown renderer skin, with the colors of the user's pager theme applied
+///
+/// If color is disabled the skin has no colors, and search matches are shown in reverse video.
+fn get_markdown_skin() -> anyhow::Result<MadSkin> {
+    if !crate::log::color_enabled(atty::Stream::Stdout) {
+        let mut skin = MadSkin::no_style();
+        skin.strikeout = CompoundStyle::new(None, None, vec![Reverse]);
+        return Ok(skin);
+    }
 
-        skin
-    };
+    let mut skin = MadSkin::default();
+    skin.headers[0].set_fg(DarkYellow);
+    skin.set_headers_fg(DarkYellow);
+    skin.bold.set_fg(Magenta);
+    skin.italic.add_attr(Underlined);
+    // Strikeout isn't used in the docs, so it is used to highlight search matches
+    skin.strikeout = CompoundStyle::with_fgbg(Black, Yellow);
+
+    theme::PagerTheme::load()?.apply(&mut skin);
+
+    Ok(skin)
+}
+
+/// A page that can be shown in the pager
+enum PagerPage<'c, 'a> {
+    /// The doc page of a command, which includes the command's help message
+    Command(&'c dyn CliCommand<'a>),
+    /// A standalone guide
+    Guide(CliDoc),
+}
+
+impl<'c, 'a> PagerPage<'c, 'a> {
+    /// Get the doc of the page, if it has one
+    fn get_doc(&self) -> Option<CliDoc> {
+        match self {
+            PagerPage::Command(command) => command.get_doc(),
+            PagerPage::Guide(guide) => Some(*guide),
+        }
+    }
+
+    /// Get the clap help message of the command for use in its doc page, or an empty string for
+    /// guides
+    fn get_help_message(&self) -> String {
+        let command = match self {
+            PagerPage::Command(command) => command,
+            PagerPage::Guide(_) => return String::new(),
+        };
+
+        // Hide the help, doc, and version flags in the command help message.
+        let mut cli = command
+            .get_cli()
+            .mut_arg("help", |arg| arg.hidden_long_help(true))
+            .mut_arg("doc", |arg| arg.hidden_long_help(true))
+            .mut_arg("pager", |arg| arg.hidden_long_help(true))
+            .mut_arg("version", |arg| arg.hidden_long_help(true));
+
+        // Set the help message template
+        cli.template = Some(&USAGE_TEMPLATE);
+
+        // Get clap help message
+        let mut help_message = vec![];
+        cli.write_long_help(&mut help_message)
+            .expect("Could not write to internal string buffer");
+        String::from_utf8(help_message).expect("Could not parse command help as utf8")
+    }
+
+    /// Get the markdown template of the page, which may have a `${help_message}` placeholder for
+    /// the help message
+    fn get_doc_template(&self) -> String {
+        match self {
+            PagerPage::Command(command) => match command.get_doc() {
+                // If there is a help document for this command
+                Some(cli_doc) => preprocess_markdown(cli_doc.content),
+                // If there is no help document, create a template just to print the help message
+                None => format!("# {}\n\n${{help_message}}", command.get_name()),
+            },
+            PagerPage::Guide(guide) => preprocess_markdown(guide.content),
+        }
+    }
 }
 
 /// Show the commandline pager with documentation for the given command
-pub(crate) fn show_doc_page<'a>(command: &impl CliCommand<'a>) -> anyhow::Result<()> {
-    // Hide the help, doc, and version flags in the command help message.
-    let cli_doc = command.get_doc();
+///
+/// If `pager` is `None`, the pager set by the `LUCKY_PAGER` environment variable is used, or the
+/// built-in pager if it isn't set.
+pub(crate) fn show_doc_page<'a>(
+    command: &dyn CliCommand<'a>,
+    pager: Option<PagerKind>,
+) -> anyhow::Result<()> {
+    show_page(&PagerPage::Command(command), pager)
+}
+
+/// Show a standalone guide in the commandline pager
+pub(crate) fn show_guide(guide: CliDoc, pager: Option<PagerKind>) -> anyhow::Result<()> {
+    show_page(&PagerPage::Guide(guide), pager)
+}
+
+/// Show a page in the commandline pager
+fn show_page(page: &PagerPage, pager: Option<PagerKind>) -> anyhow::Result<()> {
+    let cli_doc = page.get_doc();
 
     // Get stdout writer
     let mut w = stdout();
@@ -53,6 +187,24 @@ pub(crate) fn show_doc_page<'a>(command: &impl CliCommand<'a>) -> anyhow::Result
         return Ok(());
     }
 
+    // Load the skin before switching screens so that errors in the user's theme are visible
+    let skin = get_markdown_skin()?;
+
+    // Hand the doc over to the user's pager if they prefer it
+    let pager = match pager {
+        Some(pager) => pager,
+        None => match std::env::var(PAGER_ENV_VAR) {
+            Ok(pager) => pager
+                .parse()
+                .context(format!("Invalid {} environment variable", PAGER_ENV_VAR))?,
+            Err(_) => PagerKind::Builtin,
+        },
+    };
+    if pager == PagerKind::External {
+        show_external_pager(page, &skin)?;
+        return Err(CliError::Exit(0).into());
+    }
+
     // Load the last position the user was scrolled to on this doc
     let mut scrolled_positions: HashMap<String, i32> = HashMap::new();
     let mut config_file: Option<std::fs::File> = None;
@@ -96,48 +248,38 @@ pub(crate) fn show_doc_page<'a>(command: &impl CliCommand<'a>) -> anyhow::Result
     let mut scroll = 0;
     let mut first_view = true;
 
+    // The search query being typed, if the user is typing one
+    let mut search_input: Option<String> = None;
+    // The last search query
+    let mut search: Option<String> = None;
+    // The index of the search match that was last jumped to
+    let mut current_match: Option<usize> = None;
+
+    // The number of lines that the doc was wrapped to the last time it was rendered, so that the
+    // scroll position can be kept when the doc is reflowed after a resize
+    let mut line_count: Option<usize> = None;
+
     // Listen for events and redraw screen
     loop {
-        // Reload CLI in case the screen size changed and help message needs re-printing
-        let mut cli = command
-            .get_cli()
-            .mut_arg("help", |arg| arg.hidden_long_help(true))
-            .mut_arg("doc", |arg| arg.hidden_long_help(true))
-            .mut_arg("version", |arg| arg.hidden_long_help(true));
+        // Wait for the terminal to be big enough to render the doc in
+        if !wait_for_usable_size(&mut w)? {
+            break;
+        }
 
-        // Set the help message template
-        cli.template = Some(&USAGE_TEMPLATE);
+        // Reload help message in case the screen size changed and it needs re-printing
+        let help_message = page.get_help_message();
 
-        // Get clap help message
-        let mut help_message = vec![];
-        cli.write_long_help(&mut help_message)
-            .expect("Could not write to internal string buffer");
-        let help_message =
-            String::from_utf8(help_message).expect("Could not parse command help as utf8");
-
-        let content;
-        let doc = match &cli_doc {
-            // If there is a help document for this command
-            Some(cli_doc) => {
-                // Create text template from cli doc
-                content = preprocess_markdown(cli_doc.content);
-                let doc_template = TextTemplate::from(content.as_ref());
-                let mut doc_expander = doc_template.expander();
-                doc_expander.set_lines("help_message", &help_message);
-
-                // Expand document template
-                doc_expander.expand()
-            }
-            // If there is no help document
-            None => {
-                // Create a template just to print the help message
-                content = format!("# {}\n\n${{help_message}}", command.get_name());
-                let doc_template = TextTemplate::from(content.as_ref());
-                let mut doc_expander = doc_template.expander();
-                doc_expander.set_lines("help_message", &help_message);
-                doc_expander.expand()
-            }
-        };
+        // Expand document template
+        let content = page.get_doc_template();
+        let doc_template = TextTemplate::from(content.as_ref());
+        let mut doc_expander = doc_template.expander();
+        doc_expander.set_lines("help_message", &help_message);
+        let mut doc = doc_expander.expand();
+
+        // Highlight the matches of the last search
+        if let Some(query) = &search {
+            highlight_matches(&mut doc, query);
+        }
 
         // Prepare and write to scroll area
         let mut area = Area::full_screen();
@@ -147,9 +289,15 @@ pub(crate) fn show_doc_page<'a>(command: &impl CliCommand<'a>) -> anyhow::Result
         area.height -= 1;
 
         // Create text view
-        let fmt_text = FmtText::from_text(&MD_SKIN, doc.clone(), Some((area.width - 1) as usize));
+        let fmt_text = FmtText::from_text(&skin, doc.clone(), Some((area.width - 1) as usize));
         let mut view = TextView::from(&area, &fmt_text);
 
+        // Keep the same part of the doc in view if it was reflowed to a different number of lines
+        if let Some(old_count) = line_count {
+            scroll = reflow_scroll(scroll, old_count, fmt_text.lines.len());
+        }
+        line_count = Some(fmt_text.lines.len());
+
         // Scroll to the last viewed position if this command has a doc page
         if let Some(cli_doc) = &cli_doc {
             if first_view {
@@ -159,25 +307,75 @@ pub(crate) fn show_doc_page<'a>(command: &impl CliCommand<'a>) -> anyhow::Result
                 }
                 first_view = false;
             } else {
-                view.scroll = scroll;
+                scroll_to_line(&mut view, scroll);
             }
         } else {
-            view.scroll = scroll;
+            scroll_to_line(&mut view, scroll);
+        }
+
+        // Jump to the first match below the top of the view if a search was just made
+        let matches = match_lines(&fmt_text);
+        if search.is_some() && current_match.is_none() && !matches.is_empty() {
+            let index = matches.iter().position(|&x| x >= scroll).unwrap_or(0);
+            scroll_to_line(&mut view, matches.get(index).copied().unwrap_or(0));
+            current_match = Some(index);
         }
 
         // Write out the document view
         view.write_on(&mut w)?;
 
         // Write out help bar
-        write_help_bar(&mut w, r#" Type "h" for help "#)?;
+        let help_bar = match (&search_input, &search) {
+            (Some(input), _) => format!("/{}", input),
+            (None, Some(query)) if matches.is_empty() => format!(" Pattern not found: {} ", query),
+            (None, Some(_)) => format!(
+                r#" Match {} of {}, type "n" or "N" for the next or previous match "#,
+                current_match.unwrap_or(0) % matches.len() + 1,
+                matches.len()
+            ),
+            (None, None) => r#" Type "h" for help "#.into(),
+        };
+        write_help_bar(&mut w, &help_bar)?;
 
         // Flush output
         w.flush()?;
 
         // Respond to keyboard events
         match event::read() {
+            // Edit the search query while it is being typed
+            Ok(Event::Key(KeyEvent { code, .. })) if search_input.is_some() => {
+                let input = search_input.get_or_insert_with(String::new);
+                match code {
+                    Char(c) => input.push(c),
+                    Backspace => {
+                        input.pop();
+                    }
+                    Enter => {
+                        if !input.is_empty() {
+                            search = Some(input.clone());
+                            current_match = None;
+                        }
+                        search_input = None;
+                    }
+                    Esc => search_input = None,
+                    _ => (),
+                }
+            }
             Ok(Event::Key(KeyEvent { code, .. })) => {
                 match code {
+                    Char('/') => {
+                        search_input = Some(String::new());
+                    }
+                    Char('n') | Char('N') if !matches.is_empty() => {
+                        let current = current_match.unwrap_or(0) % matches.len();
+                        let index = if code == Char('n') {
+                            (current + 1) % matches.len()
+                        } else {
+                            (current + matches.len() - 1) % matches.len()
+                        };
+                        scroll_to_line(&mut view, matches.get(index).copied().unwrap_or(0));
+                        current_match = Some(index);
+                    }
                     Home | Char('g') => {
                         view.scroll = 0;
                     }
@@ -199,9 +397,17 @@ pub(crate) fn show_doc_page<'a>(command: &impl CliCommand<'a>) -> anyhow::Result
                         view.try_scroll_pages(1);
                     }
                     Char('h') | Char('?') => {
-                        show_pager_help(&mut w)?;
+                        show_pager_help(&mut w, &skin)?;
                         continue;
                     }
+                    Char('t') => {
+                        let headings = doc_headings(&fmt_text);
+                        if !headings.is_empty() {
+                            if let Some(line) = show_table_of_contents(&mut w, &skin, &headings)? {
+                                scroll_to_line(&mut view, line);
+                            }
+                        }
+                    }
                     Esc | Enter | Char('q') => break,
                     _ => (),
                 }
@@ -242,19 +448,319 @@ pub(crate) fn show_doc_page<'a>(command: &impl CliCommand<'a>) -> anyhow::Result
     Err(CliError::Exit(0).into())
 }
 
+/// Show the page in the user's `$PAGER`, or `less -R` if it isn't set
+fn show_external_pager(page: &PagerPage, skin: &MadSkin) -> anyhow::Result<()> {
+    let help_message = page.get_help_message();
+    let content = page.get_doc_template();
+    let doc_template = TextTemplate::from(content.as_ref());
+    let mut doc_expander = doc_template.expander();
+    doc_expander.set_lines("help_message", &help_message);
+
+    // Render the doc to ANSI text one column narrower than the terminal so that full lines don't
+    // wrap in the pager
+    let width = size().map(|x| x.0).unwrap_or(80).saturating_sub(1);
+    let rendered =
+        FmtText::from_text(skin, doc_expander.expand(), Some(usize::from(width))).to_string();
+
+    let pager = std::env::var("PAGER")
+        .ok()
+        .filter(|x| !x.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EXTERNAL_PAGER.into());
+    let mut pager_args = pager.split_whitespace();
+    let program = pager_args.next().unwrap_or("less");
+
+    let mut child = Command::new(program)
+        .args(pager_args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .context(format!("Couldn't run pager: {}", pager))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager closes its input if the user quits before the whole doc has been written
+        match stdin.write_all(rendered.as_bytes()) {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => (),
+            result => result?,
+        }
+    }
+    child
+        .wait()
+        .context(format!("Couldn't wait for pager: {}", pager))?;
+
+    Ok(())
+}
+
 /// Add a bar to the bottom of the terminal with the given message
 fn write_help_bar(w: &mut impl Write, message: &str) -> anyhow::Result<()> {
     let screen_size = size()?;
 
     w.queue(MoveTo(0, screen_size.1))?;
-    w.queue(SetBackgroundColor(Grey))?;
-    w.queue(SetForegroundColor(Black))?;
+    w.queue(Clear(CurrentLine))?;
+    if crate::log::color_enabled(atty::Stream::Stdout) {
+        w.queue(SetBackgroundColor(Grey))?;
+        w.queue(SetForegroundColor(Black))?;
+    } else {
+        w.queue(SetAttribute(Reverse))?;
+    }
     write!(w, "{}", message)?;
+    w.queue(SetAttribute(crossterm::style::Attribute::Reset))?;
     w.queue(ResetColor)?;
 
     Ok(())
 }
 
+/// Mark the parts of the doc that match the search query as strikeout, which the skin renders as
+/// highlighted
+///
+/// The search ignores case. Matches that span text with different styles, such as a word that is
+/// partly bold, aren't found.
+fn highlight_matches(text: &mut Text, query: &str) {
+    let query = query.to_ascii_lowercase();
+    for line in &mut text.lines {
+        match line {
+            Line::Normal(composite) => highlight_composite(composite, &query),
+            Line::TableRow(row) => {
+                for cell in &mut row.cells {
+                    highlight_composite(cell, &query);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Split the compounds of a composite so that the matches of the lowercase query are compounds of
+/// their own, and mark the matches as strikeout
+fn highlight_composite(composite: &mut Composite, query: &str) {
+    let mut compounds = Vec::with_capacity(composite.compounds.len());
+    for compound in composite.compounds.drain(..) {
+        // Only ASCII characters are lowercased so that the byte offsets stay the same
+        let lowercase = compound.src.to_ascii_lowercase();
+        let mut start = 0;
+        for (index, _) in lowercase.match_indices(query) {
+            if index > start {
+                compounds.push(compound.sub(start, index));
+            }
+            let mut hit = compound.sub(index, index + query.len());
+            hit.strikeout = true;
+            compounds.push(hit);
+            start = index + query.len();
+        }
+        if start < compound.src.len() {
+            compounds.push(compound.sub(start, compound.src.len()));
+        }
+    }
+    composite.compounds = compounds;
+}
+
+/// Get the indexes of the formatted lines that contain a search match
+fn match_lines(fmt_text: &FmtText) -> Vec<i32> {
+    let has_match = |composite: &Composite| composite.compounds.iter().any(|x| x.strikeout);
+    fmt_text
+        .lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| match line {
+            FmtLine::Normal(fc) => has_match(&fc.composite),
+            FmtLine::TableRow(row) => row.cells.iter().any(|fc| has_match(&fc.composite)),
+            _ => false,
+        })
+        .filter_map(|(i, _)| i32::try_from(i).ok())
+        .collect()
+}
+
+/// A section heading of a doc
+struct Heading {
+    /// The text of the heading
+    title: String,
+    /// The level of the heading, starting at 1 for `#` headings
+    level: usize,
+    /// The index of the formatted line that the heading starts on
+    line: i32,
+}
+
+/// Get the section headings of a formatted doc
+fn doc_headings(fmt_text: &FmtText) -> Vec<Heading> {
+    let mut headings: Vec<Heading> = Vec::new();
+    let mut in_heading = false;
+    for (i, line) in fmt_text.lines.iter().enumerate() {
+        let level = match line {
+            FmtLine::Normal(fc) => match fc.composite.style {
+                CompositeStyle::Header(level) => Some(level),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        if let (Some(level), FmtLine::Normal(fc)) = (level, line) {
+            let text: String = fc.composite.compounds.iter().map(|x| x.src).collect();
+            let text = text.trim();
+            match headings.last_mut() {
+                // Join the lines of a heading that was wrapped
+                Some(heading) if in_heading => {
+                    heading.title.push(' ');
+                    heading.title.push_str(text);
+                }
+                _ => {
+                    if let Ok(line) = i32::try_from(i) {
+                        headings.push(Heading {
+                            title: text.into(),
+                            level: usize::from(level),
+                            line,
+                        });
+                    }
+                }
+            }
+            in_heading = true;
+        } else {
+            in_heading = false;
+        }
+    }
+    headings
+}
+
+/// Show the table of contents of a doc and let the user pick a section to jump to
+///
+/// Returns the line of the picked heading, or `None` if the user went back without picking one.
+fn show_table_of_contents(
+    mut w: &mut impl Write,
+    skin: &MadSkin,
+    headings: &[Heading],
+) -> anyhow::Result<Option<i32>> {
+    // Clear screen
+    w.queue(Clear(All))?;
+
+    let mut selected = 0;
+    let mut scroll = 0;
+    let picked = loop {
+        // Wait for the terminal to be big enough to render the contents in
+        if !wait_for_usable_size(&mut w)? {
+            break None;
+        }
+
+        // Create screen area
+        let mut area = Area::full_screen();
+        area.pad(1, 1);
+        area.height -= 1;
+
+        // List the headings, indented by level, with the selected one in bold. The titles are
+        // truncated to the width of the area so that every heading takes exactly one line.
+        let width = (area.width - 1) as usize;
+        let mut contents = String::from("# Contents\n\n");
+        for (i, heading) in headings.iter().enumerate() {
+            let indent = "  ".repeat(heading.level.saturating_sub(1));
+            let title = truncate_title(&heading.title, width.saturating_sub(indent.len() + 2));
+            if i == selected {
+                contents.push_str(&format!("{}**> {}**\n", indent, title));
+            } else {
+                contents.push_str(&format!("{}  {}\n", indent, title));
+            }
+        }
+
+        // Create text view
+        let fmt_text = FmtText::from(skin, &contents, Some(width));
+        let mut view = TextView::from(&area, &fmt_text);
+
+        // Keep the selected heading in view. The headings start after the title and blank line.
+        let selected_line = i32::try_from(selected + 2).unwrap_or(0);
+        let height = i32::from(area.height);
+        if selected_line < scroll {
+            scroll = selected_line;
+        } else if selected_line >= scroll + height {
+            scroll = selected_line - height + 1;
+        }
+        scroll_to_line(&mut view, scroll);
+
+        write_help_bar(
+            &mut w,
+            r#" Type "Enter" to jump to a section or "Esc" to go back "#,
+        )?;
+        view.write_on(&mut w)?;
+        w.flush()?;
+
+        match event::read() {
+            Ok(Event::Key(KeyEvent { code, .. })) => match code {
+                Home | Char('g') => selected = 0,
+                End | Char('G') => selected = headings.len().saturating_sub(1),
+                Up | Char('k') => selected = selected.saturating_sub(1),
+                Down | Char('j') => {
+                    if selected + 1 < headings.len() {
+                        selected += 1;
+                    }
+                }
+                Enter => break headings.get(selected).map(|x| x.line),
+                Esc | Char('q') | Char('t') => break None,
+                _ => (),
+            },
+            Ok(Event::Resize(_, _)) => {
+                w.queue(Clear(All))?;
+            }
+            _ => (),
+        }
+    };
+
+    // Clear screen
+    w.queue(Clear(All))?;
+
+    Ok(picked)
+}
+
+/// Shorten a heading title to at most `width` characters, ending it with `…` if it was cut
+fn truncate_title(title: &str, width: usize) -> String {
+    if title.chars().count() <= width {
+        return title.into();
+    }
+
+    let mut truncated: String = title.chars().take(width.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Wait until the terminal is big enough to render the pager in, showing a message until then
+///
+/// Returns `false` if the user quit while waiting.
+fn wait_for_usable_size(w: &mut impl Write) -> anyhow::Result<bool> {
+    loop {
+        let (width, height) = size()?;
+        if width >= MIN_PAGER_WIDTH && height >= MIN_PAGER_HEIGHT {
+            return Ok(true);
+        }
+
+        w.queue(Clear(All))?;
+        w.queue(MoveTo(0, 0))?;
+        write!(w, "Terminal too small")?;
+        w.flush()?;
+
+        match event::read()? {
+            Event::Key(KeyEvent { code, .. }) => match code {
+                Esc | Enter | Char('q') => return Ok(false),
+                _ => (),
+            },
+            // Clear the message so that it doesn't stay behind the pager
+            Event::Resize(_, _) => {
+                w.queue(Clear(All))?;
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Get the scroll position that keeps the same part of a doc at the top of the view after the doc
+/// is reflowed from `old_count` lines to `new_count` lines
+fn reflow_scroll(scroll: i32, old_count: usize, new_count: usize) -> i32 {
+    if old_count == new_count || old_count == 0 {
+        return scroll;
+    }
+
+    let old_count = i64::try_from(old_count).unwrap_or(i64::MAX);
+    let new_count = i64::try_from(new_count).unwrap_or(i64::MAX);
+    i32::try_from(i64::from(scroll).saturating_mul(new_count) / old_count).unwrap_or(scroll)
+}
+
+/// Scroll the view so that the given line is at the top, or as close to the top as it can be
+fn scroll_to_line(view: &mut TextView, line: i32) {
+    view.scroll = 0;
+    view.try_scroll_lines(line);
+}
+
 /// Prints out the raw documentation content without any formatting or colors
 fn print_raw_doc(w: &mut impl Write, cli_doc: Option<CliDoc>) -> anyhow::Result<()> {
     if let Some(cli_doc) = cli_doc {
@@ -267,12 +773,18 @@ fn print_raw_doc(w: &mut impl Write, cli_doc: Option<CliDoc>) -> anyhow::Result<
 }
 
 /// Show the pager controls help page
-fn show_pager_help(mut w: &mut impl Write) -> anyhow::Result<()> {
+fn show_pager_help(mut w: &mut impl Write, skin: &MadSkin) -> anyhow::Result<()> {
     // Clear screen
     w.queue(Clear(All))?;
 
     let mut scroll = 0;
+    let mut line_count: Option<usize> = None;
     loop {
+        // Wait for the terminal to be big enough to render the help in
+        if !wait_for_usable_size(&mut w)? {
+            break;
+        }
+
         // Create screen area
         let mut area = Area::full_screen();
         area.pad(1, 1);
@@ -280,12 +792,16 @@ fn show_pager_help(mut w: &mut impl Write) -> anyhow::Result<()> {
 
         // Create text view
         let fmt_text = FmtText::from_text(
-            &MD_SKIN,
+            skin,
             include_str!("cmdln_pager/pager_help.md").into(),
             Some((area.width - 1) as usize),
         );
         let mut view = TextView::from(&area, &fmt_text);
-        view.scroll = scroll;
+        if let Some(old_count) = line_count {
+            scroll = reflow_scroll(scroll, old_count, fmt_text.lines.len());
+        }
+        line_count = Some(fmt_text.lines.len());
+        scroll_to_line(&mut view, scroll);
 
         // Handle keyboard events
         write_help_bar(&mut w, r#" Type "Esc" to go back "#)?;
@@ -0,0 +1,158 @@
+//! User-configurable colors for the doc pager
+//!
+//! The colors of the pager can be changed with a `lucky/theme.toml` file in the user's config dir,
+//! such as `~/.config/lucky/theme.toml` on Linux, so that the docs can be made readable on light
+//! terminals or by users with colorblindness. Every setting is optional:
+//!
+//! ```toml
+//! text = "black"
+//! background = "white"
+//! headers = "dark-blue"
+//! bold = "dark-red"
+//! italic = "dark-green"
+//! code = "black"
+//! code-background = "grey"
+//! search-match = "#ffd700"
+//! ```
+//!
+//! Colors can be given by name, such as `dark-yellow`, as a hex color, such as `#ffd700`, or as an
+//! ANSI color number from `0` to `255`.
+
+use anyhow::Context;
+use crossterm::style::Color;
+use serde::Deserialize;
+use termimad::MadSkin;
+
+use std::convert::TryFrom;
+
+/// The name of the theme file in the Lucky dir of the user's config dir
+const THEME_FILE_NAME: &str = "theme.toml";
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// The colors of the doc pager that override the default skin
+pub(super) struct PagerTheme {
+    /// The color of normal text
+    text: Option<ThemeColor>,
+    /// The background color of the whole page
+    background: Option<ThemeColor>,
+    /// The color of the headers
+    headers: Option<ThemeColor>,
+    /// The color of bold text
+    bold: Option<ThemeColor>,
+    /// The color of italic text
+    italic: Option<ThemeColor>,
+    /// The color of inline code and code blocks
+    code: Option<ThemeColor>,
+    /// The background color of inline code and code blocks
+    code_background: Option<ThemeColor>,
+    /// The background color of search matches
+    search_match: Option<ThemeColor>,
+}
+
+impl PagerTheme {
+    /// Load the theme from the user's config dir, or the default theme if there is no theme file
+    pub fn load() -> anyhow::Result<Self> {
+        let path = match dirs::config_dir() {
+            Some(config_dir) => config_dir.join("lucky").join(THEME_FILE_NAME),
+            None => return Ok(Self::default()),
+        };
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .context(format!("Couldn't read pager theme: {:?}", &path))?;
+        toml::from_str(&content).context(format!("Invalid pager theme: {:?}", &path))
+    }
+
+    /// Override the colors of the skin with the colors set in the theme
+    pub fn apply(&self, skin: &mut MadSkin) {
+        // Set the background first so that the other colors can override it
+        if let Some(ThemeColor(color)) = self.background {
+            skin.set_global_bg(color);
+        }
+        if let Some(ThemeColor(color)) = self.text {
+            skin.paragraph.compound_style.set_fg(color);
+        }
+        if let Some(ThemeColor(color)) = self.headers {
+            skin.set_headers_fg(color);
+        }
+        if let Some(ThemeColor(color)) = self.bold {
+            skin.bold.set_fg(color);
+        }
+        if let Some(ThemeColor(color)) = self.italic {
+            skin.italic.set_fg(color);
+        }
+        if let Some(ThemeColor(color)) = self.code {
+            skin.inline_code.set_fg(color);
+            skin.code_block.compound_style.set_fg(color);
+        }
+        if let Some(ThemeColor(color)) = self.code_background {
+            skin.inline_code.set_bg(color);
+            skin.code_block.compound_style.set_bg(color);
+        }
+        if let Some(ThemeColor(color)) = self.search_match {
+            skin.strikeout.set_bg(color);
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(try_from = "String")]
+/// A color in the theme file
+struct ThemeColor(Color);
+
+impl TryFrom<String> for ThemeColor {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        use Color::*;
+
+        let color = match name.to_lowercase().replace('_', "-").as_str() {
+            "reset" | "default" => Reset,
+            "black" => Black,
+            "dark-grey" | "dark-gray" => DarkGrey,
+            "red" => Red,
+            "dark-red" => DarkRed,
+            "green" => Green,
+            "dark-green" => DarkGreen,
+            "yellow" => Yellow,
+            "dark-yellow" => DarkYellow,
+            "blue" => Blue,
+            "dark-blue" => DarkBlue,
+            "magenta" => Magenta,
+            "dark-magenta" => DarkMagenta,
+            "cyan" => Cyan,
+            "dark-cyan" => DarkCyan,
+            "white" => White,
+            "grey" | "gray" => Grey,
+            hex if hex.starts_with('#') => parse_hex_color(hex).ok_or_else(|| {
+                format!("Invalid hex color, expected a color like `#ffd700`: {}", name)
+            })?,
+            number => number.parse::<u8>().map(AnsiValue).map_err(|_| {
+                format!(
+                    "Invalid color, expected a color name, a hex color, or an ANSI color number: {}",
+                    name
+                )
+            })?,
+        };
+
+        Ok(ThemeColor(color))
+    }
+}
+
+/// Parse a hex color in the `#rrggbb` format
+fn parse_hex_color(hex: &str) -> Option<Color> {
+    if hex.len() != 7 {
+        return None;
+    }
+    let component = |range: std::ops::Range<usize>| u8::from_str_radix(hex.get(range)?, 16).ok();
+
+    Some(Color::Rgb {
+        r: component(1..3)?,
+        g: component(3..5)?,
+        b: component(5..7)?,
+    })
+}
@@ -0,0 +1,60 @@
+use clap::{App, Arg, ArgMatches};
+
+use std::path::Path;
+
+use crate::cli::*;
+
+pub(super) struct GenerateSubcommand;
+
+impl<'a> CliCommand<'a> for GenerateSubcommand {
+    fn get_name(&self) -> &'static str {
+        "generate"
+    }
+
+    #[rustfmt::skip]
+    fn get_app(&self) -> App<'a> {
+        self.get_base_app()
+            .about("Generate the documentation of every Lucky command")
+            .arg(Arg::with_name("format")
+                .help("The format to generate the documentation in")
+                .long("format")
+                .short('f')
+                .possible_values(&["man", "html", "md", "mdbook"])
+                .default_value("man"))
+            .arg(Arg::with_name("out_dir")
+                .help("The directory to write the documentation to")
+                .long("out-dir")
+                .short('o')
+                .takes_value(true)
+                .value_name("dir")
+                .required(true))
+    }
+
+    fn get_subcommands(&self) -> Vec<Box<dyn CliCommand<'a>>> {
+        vec![]
+    }
+
+    fn get_doc(&self) -> Option<CliDoc> {
+        Some(CliDoc {
+            name: "lucky_doc_generate",
+            content: include_str!("generate/generate.md"),
+        })
+    }
+
+    fn execute_command(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<CliData> {
+        let out_dir = Path::new(
+            args.value_of("out_dir")
+                .expect("Missing required argument: out_dir"),
+        );
+
+        let cli = LuckyCli;
+        match args.value_of("format") {
+            Some("html") => crate::cli::doc::site::generate_html_site(&cli, out_dir)?,
+            Some("md") => crate::cli::doc::site::generate_markdown_site(&cli, out_dir)?,
+            Some("mdbook") => crate::cli::doc::mdbook::generate_docs(&cli, out_dir)?,
+            _ => crate::cli::doc::man::generate_man_pages(&cli, out_dir)?,
+        }
+
+        Ok(data)
+    }
+}
@@ -0,0 +1,372 @@
+//! Module for rendering the Lucky CLI documentation as troff man pages
+//!
+//! Every command in the CLI gets its own page in section 1, named after its path in the command
+//! tree, such as `lucky-charm-build.1`. The pages are made from the command's clap help and its
+//! markdown doc page, which is converted to troff with a simple line-based converter that handles
+//! the subset of markdown that the doc pages use.
+
+use clap::ArgSettings;
+use regex::{Captures, Regex};
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use crate::cli::*;
+
+/// The man page section that the CLI pages go in
+const MAN_SECTION: &str = "1";
+
+lazy_static::lazy_static! {
+    /// Matches an inline code snippet
+    static ref MD_INLINE_CODE: Regex =
+        Regex::new(r"`(?P<text>[^`]+)`").expect("Coud not compile regex");
+
+    /// Matches bold text
+    static ref MD_BOLD: Regex =
+        Regex::new(r"\*\*(?P<text>.+?)\*\*").expect("Coud not compile regex");
+
+    /// Matches italic text
+    static ref MD_ITALIC: Regex =
+        Regex::new(r"\*(?P<text>[^*\s][^*]*)\*").expect("Coud not compile regex");
+
+    /// Matches a markdown link that starts with `http(s)://`
+    static ref MD_EXTERNAL_LINK: Regex =
+        Regex::new(r"\[(?P<link_text>.*?)\]\((?P<link_ref>https?://.*?)\)")
+            .expect("Coud not compile regex");
+
+    /// Matches any markdown link
+    static ref MD_LINK: Regex =
+        Regex::new(r"\[(?P<link_text>.*?)\]\((?P<link_ref>.*?)\)")
+            .expect("Coud not compile regex");
+}
+
+/// Generate a man page for every command in the CLI in the given directory
+pub(crate) fn generate_man_pages<'a>(
+    command: &impl CliCommand<'a>,
+    outpath: &Path,
+) -> anyhow::Result<()> {
+    std::fs::create_dir_all(outpath)?;
+    recurse_gen_man_page(command, &[], outpath)
+}
+
+/// Recurse through the CLI and generate a man page for each command
+///
+/// `parents` are the names of the commands above this command, starting with `lucky`.
+fn recurse_gen_man_page<'a>(
+    command: &dyn CliCommand<'a>,
+    parents: &[&str],
+    outpath: &Path,
+) -> anyhow::Result<()> {
+    let mut path: Vec<&str> = parents.to_vec();
+    path.push(command.get_name());
+    let page_name = path.join("-");
+
+    // Open the man page file
+    let mut outfile = OpenOptions::new()
+        .truncate(true)
+        .write(true)
+        .create(true)
+        .open(outpath.join(format!("{}.{}", page_name, MAN_SECTION)))?;
+    outfile.write_all(render_man_page(command, &page_name, parents).as_bytes())?;
+
+    for subcommand in command.get_subcommands() {
+        recurse_gen_man_page(&*subcommand, &path, outpath)?;
+    }
+
+    Ok(())
+}
+
+/// Render the man page of a command
+fn render_man_page<'a>(command: &dyn CliCommand<'a>, page_name: &str, parents: &[&str]) -> String {
+    let mut app = command.get_cli();
+    let about = app
+        .about
+        .unwrap_or_else(|| app.long_about.unwrap_or(""))
+        .trim_end_matches('.')
+        .to_owned();
+
+    let mut page = String::new();
+
+    // Add the title and the name of the command
+    page.push_str(&format!(
+        ".TH \"{}\" \"{}\" \"\" \"lucky {}\" \"Lucky Manual\"\n",
+        escape(&page_name.to_uppercase()),
+        MAN_SECTION,
+        escape(crate::LUCKY_VERSION)
+    ));
+    page.push_str(".SH NAME\n");
+    page.push_str(&format!("{} \\- {}\n", escape(page_name), escape(&about)));
+
+    // Add the usage. We have to parse out the `USAGE:\n` from `generate_usage()`.
+    let usage = app.generate_usage();
+    if let Some(usage) = usage.split('\n').nth(1) {
+        page.push_str(".SH SYNOPSIS\n");
+        page.push_str(&format!("\\fB{}\\fR\n", escape(usage.trim())));
+    }
+
+    // Add the doc page, or the long help if there isn't one
+    page.push_str(".SH DESCRIPTION\n");
+    match command.get_doc() {
+        Some(doc) => page.push_str(&markdown_to_troff(doc.content)),
+        None => page.push_str(&format!(
+            "{}\n",
+            format_inline(app.long_about.unwrap_or_else(|| app.about.unwrap_or("")))
+        )),
+    }
+
+    page.push_str(&render_args(&app));
+
+    // Add the subcommands
+    let subcommands = command.get_subcommands();
+    if !subcommands.is_empty() {
+        page.push_str(".SH COMMANDS\n");
+        for subcommand in &subcommands {
+            let sub_app = subcommand.get_app();
+            page.push_str(&format!(
+                ".TP\n\\fB{}\\fR\n{}\n",
+                escape(subcommand.get_name()),
+                format_inline(
+                    sub_app
+                        .about
+                        .unwrap_or_else(|| sub_app.long_about.unwrap_or(""))
+                )
+            ));
+        }
+    }
+
+    // Link to the parent command and the subcommands
+    let mut see_also: Vec<String> = Vec::new();
+    if !parents.is_empty() {
+        see_also.push(parents.join("-"));
+    }
+    for subcommand in &subcommands {
+        see_also.push(format!("{}-{}", page_name, subcommand.get_name()));
+    }
+    if !see_also.is_empty() {
+        page.push_str(".SH \"SEE ALSO\"\n");
+        page.push_str(
+            &see_also
+                .iter()
+                .map(|x| format!("\\fB{}\\fR({})", escape(x), MAN_SECTION))
+                .collect::<Vec<_>>()
+                .join(",\n"),
+        );
+        page.push('\n');
+    }
+
+    page
+}
+
+/// Render the arguments of a command as an `OPTIONS` section
+fn render_args(app: &clap::App) -> String {
+    let mut options = String::new();
+
+    for arg in &app.args.args {
+        // Skip the args that every command has
+        if GLOBAL_ARGS.contains(&arg.name) {
+            continue;
+        }
+
+        // Add short and long flags if present
+        let mut flags = vec![];
+        if let Some(short) = arg.short {
+            flags.push(format!("\\fB\\-{}\\fR", short));
+        }
+        if let Some(long) = arg.long {
+            flags.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+        }
+
+        let mut tag = if flags.is_empty() {
+            // Positional arguments are shown by name
+            format!("\\fI<{}>\\fR", escape(arg.name))
+        } else {
+            flags.join(", ")
+        };
+
+        // Add value names if the arg takes a value
+        if !flags.is_empty() && arg.is_set(ArgSettings::TakesValue) {
+            let value_names = arg.val_names.as_ref().map_or_else(
+                || vec![arg.name],
+                |x| x.values().copied().collect::<Vec<&str>>(),
+            );
+            for value_name in value_names {
+                tag.push_str(&format!(" \\fI<{}>\\fR", escape(value_name)));
+            }
+        }
+
+        let mut description =
+            format_inline(arg.long_help.unwrap_or_else(|| arg.help.unwrap_or("")));
+        if let Some((env, _)) = arg.env {
+            description.push_str(&format!(
+                "\n.br\nEnvironment variable: \\fB{}\\fR",
+                escape(&env.to_string_lossy())
+            ));
+        }
+        if let Some(vals) = &arg.default_vals {
+            description.push_str(&format!(
+                "\n.br\nDefault: \\fB{}\\fR",
+                escape(
+                    &vals
+                        .iter()
+                        .map(|x| x.to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            ));
+        }
+        if let Some(vals) = &arg.possible_vals {
+            description.push_str(&format!(
+                "\n.br\nPossible values: \\fB{}\\fR",
+                escape(&vals.join(", "))
+            ));
+        }
+
+        options.push_str(&format!(".TP\n{}\n{}\n", tag, description));
+    }
+
+    if options.is_empty() {
+        options
+    } else {
+        format!(".SH OPTIONS\n{}", options)
+    }
+}
+
+/// Convert a markdown doc page to troff
+///
+/// The title of the page is skipped because it is in the `NAME` section, and the
+/// `${help_message}` placeholder is skipped because the arguments get an `OPTIONS` section of
+/// their own.
+fn markdown_to_troff(markdown: &str) -> String {
+    let mut troff = String::new();
+    // Whether or not we are in a fenced code block
+    let mut in_fence = false;
+    // Whether or not we are in an indented code block or a table, which are not filled
+    let mut in_no_fill = false;
+    // Whether or not the last line was blank, so that indented lines start a code block
+    let mut after_blank = true;
+
+    for line in markdown.lines() {
+        let trimmed = line.trim();
+
+        // Fenced code blocks are copied as they are
+        if trimmed.starts_with("```") {
+            troff.push_str(if in_fence {
+                ".fi\n.RE\n"
+            } else {
+                ".PP\n.RS 4\n.nf\n"
+            });
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            troff.push_str(&escape_line(&escape(line)));
+            troff.push('\n');
+            continue;
+        }
+
+        // Indented code blocks and tables are copied as they are
+        let is_no_fill =
+            (line.starts_with("    ") && (after_blank || in_no_fill)) || trimmed.starts_with('|');
+        if is_no_fill {
+            if !in_no_fill {
+                troff.push_str(".PP\n.RS 4\n.nf\n");
+                in_no_fill = true;
+            }
+            let line = line.get(4..).filter(|_| !trimmed.starts_with('|'));
+            troff.push_str(&escape_line(&escape(line.unwrap_or(trimmed))));
+            troff.push('\n');
+            after_blank = false;
+            continue;
+        } else if in_no_fill {
+            troff.push_str(".fi\n.RE\n");
+            in_no_fill = false;
+        }
+
+        after_blank = trimmed.is_empty();
+        if trimmed.is_empty() {
+            if !troff.is_empty() && !troff.ends_with(".PP\n") {
+                troff.push_str(".PP\n");
+            }
+        } else if trimmed == "${help_message}" || trimmed.starts_with("# ") {
+            continue;
+        } else if trimmed.starts_with("## ") {
+            let title = trimmed.trim_start_matches('#').trim();
+            troff.push_str(&format!(".SH \"{}\"\n", escape(&title.to_uppercase())));
+        } else if trimmed.starts_with("###") {
+            let title = trimmed.trim_start_matches('#').trim();
+            troff.push_str(&format!(".SS \"{}\"\n", escape(title)));
+        } else if trimmed.starts_with("- ") || trimmed.starts_with("* ") {
+            let item = trimmed.get(2..).unwrap_or("");
+            troff.push_str(&format!(".IP \\(bu 2\n{}\n", format_inline(item)));
+        } else if trimmed.starts_with("> ") {
+            let quote = trimmed.get(2..).unwrap_or("");
+            troff.push_str(&format!(".RS 4\n{}\n.RE\n", format_inline(quote)));
+        } else {
+            troff.push_str(&format_inline(trimmed));
+            troff.push('\n');
+        }
+    }
+
+    // Close any block left open at the end of the doc
+    if in_fence || in_no_fill {
+        troff.push_str(".fi\n.RE\n");
+    }
+
+    troff
+}
+
+/// Convert the inline markdown of a line of text to troff
+fn format_inline(text: &str) -> String {
+    // Reformat external links and remove the rest, which will not work in a man page
+    let text = MD_EXTERNAL_LINK.replace_all(text, "$link_text ($link_ref)");
+    let text = MD_LINK.replace_all(&text, "$link_text");
+    let text = escape(&text);
+
+    // Format the code snippets separately so that their contents aren't formatted
+    let mut formatted = String::new();
+    let mut last_end = 0;
+    for code in MD_INLINE_CODE.captures_iter(&text) {
+        let whole = code.get(0).expect("Missing regex match");
+        formatted.push_str(&format_emphasis(
+            text.get(last_end..whole.start()).unwrap_or(""),
+        ));
+        formatted.push_str(&format!(
+            "\\fB{}\\fR",
+            code.name("text").map_or("", |x| x.as_str())
+        ));
+        last_end = whole.end();
+    }
+    formatted.push_str(&format_emphasis(text.get(last_end..).unwrap_or("")));
+
+    escape_line(&formatted)
+}
+
+/// Convert markdown bold and italic text to troff
+fn format_emphasis(text: &str) -> String {
+    let wrap = |font: &'static str| {
+        move |caps: &Captures| {
+            format!(
+                "\\f{}{}\\fR",
+                font,
+                caps.name("text").map_or("", |x| x.as_str())
+            )
+        }
+    };
+    let text = MD_BOLD.replace_all(text, wrap("B"));
+    MD_ITALIC.replace_all(&text, wrap("I")).into_owned()
+}
+
+/// Escape text so that troff doesn't interpret it
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e").replace('-', "\\-")
+}
+
+/// Keep troff from reading a line that starts with a `.` or a `'` as a request
+fn escape_line(line: &str) -> String {
+    if line.starts_with('.') || line.starts_with('\'') {
+        format!("\\&{}", line)
+    } else {
+        line.to_owned()
+    }
+}
@@ -5,7 +5,7 @@ use regex::Regex;
 
 use std::fs::OpenOptions;
 use std::io::{Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use crate::cli::*;
 
@@ -32,18 +32,34 @@ pub(crate) fn generate_docs<'a>(
     command: &impl CliCommand<'a>,
     outpath: &Path,
 ) -> anyhow::Result<()> {
-    // The mdbook index for all of the CLI commands. Goes in the SUMMARY.md file
-    let mut summary_index = String::from("\n\n---\n");
-
-    // Generate the CLI doc files
-    recurse_gen_cli_doc(command, &mut summary_index, outpath, outpath, 0)?;
-
     // Get the path to the SUMMARY.md file
     let summary_path = outpath.join("SUMMARY.md");
     if !summary_path.exists() {
         anyhow::bail!("SUMMARY.md not found in output directory");
     }
 
+    // Generate the CLI doc files
+    let pages = collect_doc_pages(command);
+    write_doc_pages(&pages, outpath)?;
+
+    // The mdbook index for all of the CLI commands. Goes in the SUMMARY.md file
+    let mut summary_index = String::from("\n\n---\n");
+    for page in &pages {
+        summary_index.push_str(&format!(
+            "\n{}- [{}](./{})",
+            // Indent the link acording to depth
+            "  ".repeat(page.depth),
+            // Link name
+            if page.depth == 0 {
+                "Lucky CLI"
+            } else {
+                page.name.as_str()
+            },
+            // File path
+            page.path.to_string_lossy()
+        ));
+    }
+
     // Open the SUMMARY.md file
     let mut summary_file = OpenOptions::new()
         .read(true)
@@ -71,52 +87,59 @@ pub(crate) fn generate_docs<'a>(
     Ok(())
 }
 
-/// Recurse through CLI and generate doc pages for each command
+/// The markdown doc page of a CLI command
+pub(crate) struct DocPage {
+    /// The name of the command
+    pub name: String,
+    /// The path to the page, relative to the output dir, such as `cli/lucky/charm.md`
+    pub path: PathBuf,
+    /// How deep the command is in the command tree. The top level app is at depth 0.
+    pub depth: usize,
+    /// The markdown content of the page
+    pub content: String,
+}
+
+/// Get the doc pages of the command and all of its subcommands, in the order of the command tree
 ///
-/// `index` will have all of the links added to it for the mdbook SUMMARY.md
-fn recurse_gen_cli_doc<'a>(
-    command: &dyn CliCommand<'a>,
-    mut index: &mut String,
-    base_path: &Path,
-    outpath: &Path,
-    depth: usize,
-) -> anyhow::Result<()> {
-    let mut outpath = outpath.to_owned();
-    let cli = command.get_app();
+/// The pages link to their subcommands with relative links, so they can be written to any dir.
+pub(crate) fn collect_doc_pages<'a>(command: &impl CliCommand<'a>) -> Vec<DocPage> {
+    let mut pages = Vec::new();
+    recurse_collect_doc_pages(command, &mut pages, Path::new("cli"), 0);
+    pages
+}
 
-    // If this is the top level app
-    if depth == 0 {
-        // Add the index record
-        index.push_str("\n- [Lucky CLI](./cli/lucky.md)");
-        // Set the output path to the `cli` dir
-        outpath = outpath.join("cli");
-        std::fs::create_dir_all(&outpath)?;
-    } else {
-        // Add the index record
-        index.push_str(
-            format!(
-                "\n{}- [{}](./{})",
-                // Indent the link acording to depth
-                "  ".repeat(depth),
-                // Link name
-                cli.name,
-                // File path
-                outpath
-                    .strip_prefix(base_path)
-                    .expect("Target path not subdir of base path")
-                    .join(format!("{}.md", cli.name))
-                    .to_string_lossy()
-            )
-            .as_str(),
-        );
+/// Write the doc pages to the output dir
+pub(crate) fn write_doc_pages(pages: &[DocPage], outpath: &Path) -> anyhow::Result<()> {
+    for page in pages {
+        let path = outpath.join(&page.path);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        // Open markdown file
+        let mut outfile = OpenOptions::new()
+            .truncate(true)
+            .write(true)
+            .create(true)
+            .open(path)?;
+
+        // Write doc to file
+        outfile.write_all(page.content.as_bytes())?;
     }
 
-    // Open markdown file
-    let mut outfile = OpenOptions::new()
-        .truncate(true)
-        .write(true)
-        .create(true)
-        .open(outpath.join(format!("{}.md", cli.name)))?;
+    Ok(())
+}
+
+/// Recurse through CLI and collect the doc pages for each command
+///
+/// `dir` is the dir that the command's page goes in, relative to the output dir.
+fn recurse_collect_doc_pages<'a>(
+    command: &dyn CliCommand<'a>,
+    pages: &mut Vec<DocPage>,
+    dir: &Path,
+    depth: usize,
+) {
+    let cli = command.get_app();
 
     let mut content = String::new();
     // If the command has a doc page
@@ -141,23 +164,19 @@ fn recurse_gen_cli_doc<'a>(
         content.push_str(&get_app_usage_md(command));
     }
 
-    // Write doc to file
-    outfile.write_all(content.as_bytes())?;
-
-    let subcommands = command.get_subcommands();
-    // If there are subcommands
-    if !subcommands.is_empty() {
-        // Create subdirectory for subcommands
-        let subdir = outpath.join(cli.name);
-        std::fs::create_dir_all(&subdir)?;
-
-        // Create documentation for the subcommands
-        for subcommand in command.get_subcommands() {
-            recurse_gen_cli_doc(&*subcommand, &mut index, base_path, &subdir, depth + 1)?;
-        }
+    pages.push(DocPage {
+        name: cli.name.to_string(),
+        path: dir.join(format!("{}.md", cli.name)),
+        depth,
+        content,
+    });
+
+    // Collect the documentation for the subcommands, which go in a subdirectory named after this
+    // command
+    let subdir = dir.join(&cli.name);
+    for subcommand in command.get_subcommands() {
+        recurse_collect_doc_pages(&*subcommand, pages, &subdir, depth + 1);
     }
-
-    Ok(())
 }
 
 // Get the app usage markdown
@@ -191,8 +210,8 @@ fn get_app_usage_md<'a>(command: &dyn CliCommand<'a>) -> String {
     // For each argument
     let arg_map = &app.args;
     for arg in &arg_map.args {
-        // Skip help args
-        if arg.name == "version" || arg.name == "help" || arg.name == "doc" {
+        // Skip the args that every command has
+        if GLOBAL_ARGS.contains(&arg.name) {
             continue;
         }
 
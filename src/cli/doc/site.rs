@@ -0,0 +1,125 @@
+//! Module for exporting the Lucky CLI documentation as a static markdown or HTML site
+//!
+//! The site has the same layout as the CLI docs in the mdbook: the page of every command is in the
+//! `cli` dir, with the pages of its subcommands in a dir named after it. An index page at the root
+//! of the site links to every page.
+
+use pulldown_cmark::{html, Options, Parser};
+use regex::Regex;
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use crate::cli::doc::mdbook::{collect_doc_pages, write_doc_pages, DocPage};
+use crate::cli::*;
+
+lazy_static::lazy_static! {
+    /// Matches a relative markdown link to another markdown page
+    static ref MD_PAGE_LINK: Regex =
+        Regex::new(r"\]\((?P<path>[^):\s]+?)\.md(?P<anchor>#[^)\s]*)?\)")
+            .expect("Coud not compile regex");
+}
+
+/// Write the doc pages as markdown, with a `README.md` index
+pub(crate) fn generate_markdown_site<'a>(
+    command: &impl CliCommand<'a>,
+    outpath: &Path,
+) -> anyhow::Result<()> {
+    let pages = collect_doc_pages(command);
+    write_doc_pages(&pages, outpath)?;
+
+    let mut index = String::from("# Lucky CLI\n\n");
+    for page in &pages {
+        index.push_str(&format!(
+            "{}- [{}](./{})\n",
+            "  ".repeat(page.depth),
+            page.name,
+            page.path.to_string_lossy()
+        ));
+    }
+    write_file(&outpath.join("README.md"), &index)
+}
+
+/// Render the doc pages to HTML, with an `index.html` index
+pub(crate) fn generate_html_site<'a>(
+    command: &impl CliCommand<'a>,
+    outpath: &Path,
+) -> anyhow::Result<()> {
+    let pages = collect_doc_pages(command);
+
+    for page in &pages {
+        // Get the relative path from the page back to the root of the site
+        let root = "../".repeat(page.path.components().count().saturating_sub(1));
+        let html = render_html_page(&page.name, &page.content, &pages, &root);
+
+        let path = outpath.join(page.path.with_extension("html"));
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        write_file(&path, &html)?;
+    }
+
+    let mut index = String::from("# Lucky CLI\n\n");
+    index.push_str("The documentation of every command in the Lucky CLI.\n");
+    write_file(
+        &outpath.join("index.html"),
+        &render_html_page("Lucky CLI", &index, &pages, ""),
+    )
+}
+
+/// Render a markdown page to a standalone HTML page with a navigation sidebar
+///
+/// `root` is the relative path from the page to the root of the site, such as `../../`.
+fn render_html_page(title: &str, markdown: &str, pages: &[DocPage], root: &str) -> String {
+    // Point the links to other pages at their HTML files
+    let markdown = MD_PAGE_LINK.replace_all(markdown, "]($path.html$anchor)");
+
+    let mut body = String::new();
+    html::push_html(
+        &mut body,
+        Parser::new_ext(&markdown, Options::ENABLE_TABLES),
+    );
+
+    let mut nav = String::new();
+    nav.push_str(&format!(
+        "<li><a href=\"{}index.html\">Lucky CLI</a></li>\n",
+        root
+    ));
+    for page in pages {
+        nav.push_str(&format!(
+            "<li style=\"padding-left: {}em\"><a href=\"{}{}\">{}</a></li>\n",
+            page.depth,
+            root,
+            page.path.with_extension("html").to_string_lossy(),
+            escape_html(&page.name)
+        ));
+    }
+
+    format!(
+        include_str!("site/page.html"),
+        title = escape_html(title),
+        nav = nav,
+        body = body
+    )
+}
+
+/// Escape text for use in HTML
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Write the contents of a file, replacing it if it exists
+fn write_file(path: &Path, content: &str) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new()
+        .truncate(true)
+        .write(true)
+        .create(true)
+        .open(path)?;
+    file.write_all(content.as_bytes())?;
+
+    Ok(())
+}
@@ -5,7 +5,7 @@ use thiserror::Error;
 use std::any::Any;
 use std::collections::HashMap;
 
-use crate::cli::doc::cmdln_pager::show_doc_page;
+use crate::cli::doc::cmdln_pager::{show_doc_page, PagerKind};
 
 #[derive(Error, Debug)]
 /// Lucky CLI error variants
@@ -17,6 +17,11 @@ pub(crate) enum CliError {
 
 pub(crate) type CliData = HashMap<String, Box<dyn Any>>;
 
+/// The names of the args that every command has, which are left out of the generated docs
+pub(crate) const GLOBAL_ARGS: &[&str] = &[
+    "version", "help", "doc", "pager", "no_color", "verbose", "quiet",
+];
+
 /// Trait for Lucky commands and subcommands
 ///
 /// Commands in the Lucky CLI should implement this trait
@@ -64,7 +69,8 @@ pub(crate) trait CliCommandExt<'a> {
     fn get_cli(&self) -> App<'a>;
     /// Run the command arbitrary data can be passed in the `data` argument
     fn run(&self, args: &ArgMatches, data: CliData) -> anyhow::Result<()>;
-    /// Check for the `--doc` or `-H` flag and show docs if present
+    /// Check for the `--doc` or `-H` flag and show docs if present, in the pager given by a
+    /// following `--pager` flag
     fn handle_doc_flags(&self, args: std::slice::Iter<String>) -> anyhow::Result<()>;
     /// Creates a clap app with our default settings. This should be used by implementors to
     /// create a base app when implementing `get_command()`.
@@ -126,7 +132,7 @@ impl<'a, C: CliCommand<'a>> CliCommandExt<'a> for C {
         // If the arg is a help flag
         if next_arg == "--doc" || next_arg == "-H" {
             // show the help
-            show_doc_page(self).context("Could not show doc page")?;
+            show_doc_page(self, get_pager_arg(args)?).context("Could not show doc page")?;
 
         // If the arg isn't a help flag
         } else {
@@ -149,7 +155,11 @@ impl<'a, C: CliCommand<'a>> CliCommandExt<'a> for C {
     #[rustfmt::skip]
     fn get_base_app(&self) -> App<'a> {
         let mut app = App::new(self.get_name())
-            .setting(AppSettings::ColoredHelp)
+            .setting(if crate::log::color_enabled(atty::Stream::Stdout) {
+                AppSettings::ColoredHelp
+            } else {
+                AppSettings::ColorNever
+            })
             .setting(AppSettings::VersionlessSubcommands)
             .setting(AppSettings::ArgRequiredElseHelp)
             .setting(AppSettings::DisableHelpSubcommand)
@@ -164,7 +174,27 @@ impl<'a, C: CliCommand<'a>> CliCommandExt<'a> for C {
                     None => "This command does not have a doc page: shows long help message instead"
                 })
                 .long("doc")
-                .short('H'));
+                .short('H'))
+            .arg(Arg::with_name("pager")
+                .help("The pager to show the doc page in: `external` uses your `$PAGER`")
+                .long("pager")
+                .takes_value(true)
+                .value_name("pager")
+                .possible_values(&["builtin", "external"])
+                .requires("doc"))
+            .arg(Arg::with_name("no_color")
+                .help("Disable colored output. Also disabled by setting the `NO_COLOR` env var")
+                .long("no-color"))
+            .arg(Arg::with_name("verbose")
+                .help("Show debug logs, or trace logs if given twice")
+                .long("verbose")
+                .short('v')
+                .multiple(true)
+                .conflicts_with("quiet"))
+            .arg(Arg::with_name("quiet")
+                .help("Only show error logs")
+                .long("quiet")
+                .short('q'));
 
         // If the proceess stdout is a terminal
         if atty::is(atty::Stream::Stdout) {
@@ -183,7 +213,24 @@ impl<'a, C: CliCommand<'a>> CliCommandExt<'a> for C {
     }
 }
 
-#[derive(Debug)]
+/// Get the value of the `--pager` flag from the args after the `--doc` flag
+fn get_pager_arg(mut args: std::slice::Iter<String>) -> anyhow::Result<Option<PagerKind>> {
+    while let Some(arg) = args.next() {
+        if arg == "--pager" {
+            let value = args.next().context("The --pager flag requires a value")?;
+            return value.parse().map(Some);
+        }
+
+        let mut parts = arg.splitn(2, '=');
+        if let (Some("--pager"), Some(value)) = (parts.next(), parts.next()) {
+            return value.parse().map(Some);
+        }
+    }
+
+    Ok(None)
+}
+
+#[derive(Debug, Clone, Copy)]
 /// The documentation for a CLI command
 pub struct CliDoc {
     /// The name of the doc page, used to store the scrolled location in the doc
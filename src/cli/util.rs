@@ -1,10 +1,13 @@
 //! Various utilities for the CLI
 
-use anyhow::format_err;
+use anyhow::{format_err, Context};
+use crossterm::style::{style, Color};
 use lazy_static::lazy_static;
 use regex::Regex;
+use subprocess::{Exec, Redirection};
 
 use std::collections::HashMap;
+use std::io::Write;
 
 lazy_static! {
     /// The regular expression for a key-value pair
@@ -44,3 +47,107 @@ where
 
     Ok(data)
 }
+
+/// A change to a named field, as displayed by `write_field_diff()`
+pub(crate) struct FieldChange<'a> {
+    pub field: &'a str,
+    /// The old value, or `None` if the field was added
+    pub old: Option<&'a str>,
+    /// The new value, or `None` if the field was removed
+    pub new: Option<&'a str>,
+}
+
+/// Color the string if stdout is a tty and color hasn't been disabled
+pub(crate) fn color_stdout(s: &str, color: Color) -> String {
+    if crate::log::color_enabled(atty::Stream::Stdout) {
+        style(s).with(color).to_string()
+    } else {
+        s.to_string()
+    }
+}
+
+/// Write a diff of the changed fields, one field per line with the values aligned
+///
+/// Added fields are marked with a green `+`, removed fields with a red `-`, and changed fields with
+/// a yellow `~`.
+pub(crate) fn write_field_diff(
+    w: &mut impl Write,
+    indent: usize,
+    changes: &[FieldChange],
+) -> std::io::Result<()> {
+    let width = changes.iter().map(|x| x.field.len()).max().unwrap_or(0);
+
+    for change in changes {
+        let field = format!("{:width$}", change.field, width = width);
+        let (marker, value) = match (change.old, change.new) {
+            (None, Some(new)) => (
+                color_stdout("+", Color::Green),
+                color_stdout(new, Color::Green),
+            ),
+            (Some(old), None) => (color_stdout("-", Color::Red), color_stdout(old, Color::Red)),
+            (Some(old), Some(new)) => (
+                color_stdout("~", Color::Yellow),
+                format!(
+                    "{} -> {}",
+                    color_stdout(old, Color::Red),
+                    color_stdout(new, Color::Green)
+                ),
+            ),
+            (None, None) => continue,
+        };
+
+        writeln!(
+            w,
+            "{:indent$}{} {}  {}",
+            "",
+            marker,
+            field,
+            value,
+            indent = indent
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Run a `juju` command and return its output, including stderr, failing if it exits non-zero
+pub(crate) fn juju(args: &[&str]) -> anyhow::Result<String> {
+    let capture = Exec::cmd("juju")
+        .args(args)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .context("Could not run juju")?;
+
+    if capture.success() {
+        Ok(capture.stdout_str())
+    } else {
+        Err(format_err!(
+            "juju {} failed: {}",
+            args.first().unwrap_or(&""),
+            capture.stdout_str().trim()
+        ))
+    }
+}
+
+/// Run a `juju` command and return only its stdout, failing if it exits non-zero
+///
+/// This is used for output that is parsed, which warnings that Juju prints to stderr would break.
+pub(crate) fn juju_stdout(args: &[&str]) -> anyhow::Result<String> {
+    let capture = Exec::cmd("juju")
+        .args(args)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .context("Could not run juju")?;
+
+    if capture.success() {
+        Ok(capture.stdout_str())
+    } else {
+        Err(format_err!(
+            "juju {} failed: {}",
+            args.first().unwrap_or(&""),
+            capture.stderr_str().trim()
+        ))
+    }
+}
@@ -1,10 +1,85 @@
 //! Contains utilities for loading configuration
 
 use anyhow::{format_err, Context};
+use serde::Deserialize;
 
 use std::fs;
 use std::path::{Path, PathBuf};
 
+/// The name of the Lucky config file in the Lucky dir of the user's config dir and in the charm dir
+const LUCKY_CONFIG_FILE_NAME: &str = "lucky.toml";
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// Default settings loaded from the `lucky.toml` files
+///
+/// Every setting is the default for an environment variable, so environment variables and
+/// commandline flags take precedence over the config files.
+pub(crate) struct LuckyConfig {
+    /// The path to the daemon socket, the default for `LUCKY_DAEMON_SOCKET`
+    socket_path: Option<String>,
+    /// The log filter, such as `debug` or `warn,daemon=debug`, the default for `LUCKY_LOG`
+    log_level: Option<String>,
+    /// The pager to show doc pages in, the default for `LUCKY_PAGER`
+    pager: Option<String>,
+    /// The container engine to use on machine clouds, the default for `LUCKY_CONTAINER_ENGINE`
+    container_engine: Option<String>,
+}
+
+impl LuckyConfig {
+    /// Load the `lucky.toml` in the user's config dir, such as `~/.config/lucky/lucky.toml`, and
+    /// the `lucky.toml` in the charm dir, which overrides it
+    ///
+    /// The charm dir is `JUJU_CHARM_DIR` if it is set, or the current directory otherwise.
+    pub fn load() -> anyhow::Result<Self> {
+        let user_path = dirs::config_dir().map(|x| x.join("lucky").join(LUCKY_CONFIG_FILE_NAME));
+        let charm_path = std::env::var_os("JUJU_CHARM_DIR")
+            .map(PathBuf::from)
+            .or_else(|| std::env::current_dir().ok())
+            .map(|x| x.join(LUCKY_CONFIG_FILE_NAME));
+
+        let mut config = Self::default();
+        for path in user_path.into_iter().chain(charm_path) {
+            if path.exists() {
+                let content = fs::read_to_string(&path)
+                    .context(format!("Could not read Lucky config: {:?}", path))?;
+                let file_config: Self = toml::from_str(&content)
+                    .context(format!("Could not parse Lucky config: {:?}", path))?;
+                config.merge(file_config);
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Override the settings with the ones that are set in `other`
+    fn merge(&mut self, other: Self) {
+        self.socket_path = other.socket_path.or_else(|| self.socket_path.take());
+        self.log_level = other.log_level.or_else(|| self.log_level.take());
+        self.pager = other.pager.or_else(|| self.pager.take());
+        self.container_engine = other
+            .container_engine
+            .or_else(|| self.container_engine.take());
+    }
+
+    /// Set the environment variables of the settings, unless they are already set
+    pub fn apply_to_env(&self) {
+        for (var, value) in &[
+            ("LUCKY_DAEMON_SOCKET", &self.socket_path),
+            ("LUCKY_LOG", &self.log_level),
+            ("LUCKY_PAGER", &self.pager),
+            ("LUCKY_CONTAINER_ENGINE", &self.container_engine),
+        ] {
+            if let Some(value) = value {
+                if std::env::var_os(var).is_none() {
+                    std::env::set_var(var, value);
+                }
+            }
+        }
+    }
+}
+
 #[cfg(feature = "daemon")]
 /// Gets the charm directory path
 pub(crate) fn get_charm_dir() -> anyhow::Result<PathBuf> {
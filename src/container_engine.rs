@@ -0,0 +1,715 @@
+//! Contains the container engines that the daemon can run containers with
+//!
+//! The daemon manages containers through the `ContainerEngine` trait so that charms can run their
+//! containers on hosts where Docker is unavailable or undesired. Docker and Podman both serve the
+//! Docker API, so they share the same engine implementation and only differ in how they are
+//! installed and connected to. containerd is driven through its own command line tool in the
+//! `containerd` module.
+use anyhow::Context;
+use futures::prelude::*;
+use serde_json::Value as JsonValue;
+use shiplift::{builder::ExecContainerOptions, Docker, PullOptions};
+use subprocess::{Exec, Redirection};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::docker::{ContainerConfig, EnvFile};
+use crate::rt::block_on;
+use crate::types::{ContainerEngineKind, DockerChannel, LUCKY_EXIT_CODE_HELPER_PREFIX};
+
+/// The credentials used to pull an image from a private registry
+pub(crate) struct RegistryCredentials {
+    pub username: String,
+    pub password: String,
+    /// The address of the registry, if it isn't the default registry
+    pub server_address: Option<String>,
+}
+
+/// The paths on the host that are needed to create a container
+pub(crate) struct ContainerContext<'a> {
+    /// The charm directory, which the container scripts are mounted from
+    pub charm_dir: &'a Path,
+    /// The Lucky data directory, which the container volumes are kept in
+    pub lucky_data_dir: &'a Path,
+    /// The path to the Lucky daemon socket, which is mounted into the container
+    pub socket_path: &'a Path,
+    /// The env file to load the container environment from instead of setting it inline
+    pub env_file: Option<&'a EnvFile>,
+    /// The locations of the attached Juju storage, keyed by storage name
+    pub storage_locations: &'a HashMap<String, PathBuf>,
+    /// The name of the container in Lucky, which the other containers on the shared network can
+    /// reach it at
+    pub container_name: &'a str,
+    /// The name of the unit that the container is created for, with the `/` replaced by `_`
+    pub unit_name: &'a str,
+    /// The network shared between the charm's containers, if the container should be attached to
+    /// it. The network must already exist.
+    pub shared_network: Option<&'a str>,
+}
+
+/// The entrypoint and command that a container image runs by default
+pub(crate) struct ImageCommand {
+    pub entrypoint: Vec<String>,
+    pub command: Vec<String>,
+    /// The user that the image runs its command as, in the `user[:group]` format. It is empty if
+    /// the image runs as root.
+    pub user: String,
+}
+
+/// A container exiting, as reported by the event stream of a container engine
+pub(crate) struct ContainerExit {
+    pub container_id: String,
+    /// The exit code of the container, if the engine reported it
+    pub exit_code: Option<i32>,
+}
+
+/// A container found by its labels
+pub(crate) struct LabeledContainer {
+    pub id: String,
+    pub labels: HashMap<String, String>,
+    /// Whether or not the container is running
+    pub running: bool,
+}
+
+/// A function that is given the output of a command run in a container as it is written
+pub(crate) type OutputHandler = Box<dyn FnMut(&str) + Send>;
+
+/// A container engine that the daemon can run containers with
+pub(crate) trait ContainerEngine: Send + Sync {
+    /// The name of the engine, for use in log messages
+    fn name(&self) -> &'static str;
+
+    /// Get the command line tool of the engine, set up to talk to the same engine as the daemon
+    ///
+    /// The tool must support the `logs` and `run` subcommands of the Docker CLI.
+    fn cli_command(&self) -> Exec;
+
+    /// Get a command that follows the event stream of the engine, printing each event as a line of
+    /// JSON. Container exits are picked out of the stream with `parse_exit_event()`.
+    fn events_command(&self) -> Exec;
+
+    /// Check whether or not a container exists
+    fn container_exists(&self, id: &str) -> anyhow::Result<bool>;
+
+    /// Pull an image, authenticating with the registry if credentials are given
+    fn pull_image(
+        &self,
+        image: &str,
+        credentials: Option<RegistryCredentials>,
+    ) -> anyhow::Result<()>;
+
+    /// Load the images in an image archive, such as one saved with `docker save`
+    fn load_image(&self, archive: &Path) -> anyhow::Result<()>;
+
+    /// Get the entrypoint and command that an image runs by default
+    fn image_command(&self, image: &str) -> anyhow::Result<ImageCommand>;
+
+    /// Get the registry digests of a local image, such as `sha256:...`. Images that weren't pulled
+    /// from a registry don't have any digests.
+    fn image_digests(&self, image: &str) -> anyhow::Result<Vec<String>>;
+
+    /// Get the registry digests of the image that a container was created from
+    fn container_image_digests(&self, id: &str) -> anyhow::Result<Vec<String>>;
+
+    /// Create a container with the given name and config, returning the ID of the container
+    fn create_container(
+        &self,
+        name: &str,
+        config: &ContainerConfig,
+        context: &ContainerContext,
+    ) -> anyhow::Result<String>;
+
+    /// Start a container. Starting a container that is already running does nothing.
+    fn start_container(&self, id: &str) -> anyhow::Result<()>;
+
+    /// Stop a container, waiting `timeout` for it to exit before killing it. Stopping a container
+    /// that is already stopped does nothing.
+    fn stop_container(&self, id: &str, timeout: Option<Duration>) -> anyhow::Result<()>;
+
+    /// Restart a container, waiting `timeout` for it to exit before killing it
+    fn restart_container(&self, id: &str, timeout: Option<Duration>) -> anyhow::Result<()>;
+
+    /// Remove a stopped container
+    fn remove_container(&self, id: &str) -> anyhow::Result<()>;
+
+    /// Create a bridge network if it doesn't exist already
+    fn ensure_network(&self, name: &str) -> anyhow::Result<()>;
+
+    /// Remove a network if it exists. The network must not have any containers attached.
+    fn remove_network(&self, name: &str) -> anyhow::Result<()>;
+
+    /// Run a command in a container with the given `KEY=value` environment variables and return
+    /// its exit code, or `None` if the exit code could not be determined
+    fn exec(
+        &self,
+        id: &str,
+        command: &[String],
+        env: &[String],
+        on_output: OutputHandler,
+    ) -> anyhow::Result<Option<i32>>;
+}
+
+/// Get the digest that an image reference is pinned to, if it is in the `name@digest` format
+pub(crate) fn pinned_digest(image: &str) -> Option<&str> {
+    image.splitn(2, '@').nth(1)
+}
+
+/// Get the digests out of a list of repo digests in the `name@digest` format
+pub(crate) fn parse_repo_digests(repo_digests: Vec<String>) -> Vec<String> {
+    let mut digests: Vec<String> = repo_digests
+        .iter()
+        .filter_map(|x| pinned_digest(x))
+        .map(String::from)
+        .collect();
+    digests.sort();
+    digests.dedup();
+    digests
+}
+
+/// Parse a line from the event stream of a container engine, returning the container exit that
+/// it reports, or `None` if it is a different kind of event
+///
+/// This understands the events of Docker, Podman, and nerdctl.
+pub(crate) fn parse_exit_event(line: &str) -> Option<ContainerExit> {
+    let event: JsonValue = serde_json::from_str(line).ok()?;
+    let get_str = |value: &JsonValue, key: &str| -> Option<String> {
+        value.get(key).and_then(JsonValue::as_str).map(String::from)
+    };
+    let get_code = |value: &JsonValue, key: &str| {
+        value
+            .get(key)
+            .and_then(JsonValue::as_i64)
+            .and_then(|x| i32::try_from(x).ok())
+    };
+
+    // Docker
+    if get_str(&event, "Action").as_deref() == Some("die") {
+        return Some(ContainerExit {
+            container_id: get_str(&event, "id")?,
+            exit_code: event
+                .get("Actor")
+                .and_then(|x| x.get("Attributes"))
+                .and_then(|x| get_str(x, "exitCode"))
+                .and_then(|x| x.parse().ok()),
+        });
+    }
+
+    // Podman
+    if get_str(&event, "Status").as_deref() == Some("died") {
+        return Some(ContainerExit {
+            container_id: get_str(&event, "ID")?,
+            exit_code: get_code(&event, "ContainerExitCode"),
+        });
+    }
+
+    // nerdctl reports the containerd task events, which have the event data encoded as JSON
+    if get_str(&event, "Topic").as_deref() == Some("/tasks/exit") {
+        let task: JsonValue = match event.get("Event")? {
+            JsonValue::String(task) => serde_json::from_str(task).ok()?,
+            task => task.clone(),
+        };
+        let container_id = get_str(&task, "container_id")?;
+
+        // Skip the exits of commands run in the container with `exec`
+        if get_str(&task, "id").map_or(false, |id| id != container_id) {
+            return None;
+        }
+
+        return Some(ContainerExit {
+            container_id,
+            exit_code: get_code(&task, "exit_status"),
+        });
+    }
+
+    None
+}
+
+/// Get the numeric ID of an image's user, as given in the `user[:group]` format of
+/// `ImageCommand::user`. Root and an empty user are ID `0`.
+///
+/// User names only exist inside of the image, so they are looked up by running `id` in a throwaway
+/// container with the command line tool of the engine.
+pub(crate) fn image_user_id(
+    engine: &dyn ContainerEngine,
+    image: &str,
+    user: &str,
+) -> anyhow::Result<u32> {
+    let name = user.splitn(2, ':').next().unwrap_or_default();
+    if name.is_empty() || name == "root" {
+        return Ok(0);
+    }
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+
+    let capture = engine
+        .cli_command()
+        .args(&["run", "--rm", "--entrypoint", "id", image, "-u", name])
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Pipe)
+        .capture()
+        .context(format!(
+            "Could not run the {} command line tool",
+            engine.name()
+        ))?;
+    if !capture.success() {
+        anyhow::bail!(
+            "Could not look up user {} in image {} ( {:?} ): {}",
+            name,
+            image,
+            capture.exit_status,
+            capture.stderr_str().trim()
+        );
+    }
+
+    capture.stdout_str().trim().parse().context(format!(
+        "Could not parse ID of user {} in image {}",
+        name, image
+    ))
+}
+
+/// List the containers, running or not, that have the given label in the `key=value` format
+///
+/// This uses the command line tool of the engine, which supports the same filters and inspect
+/// format for Docker, Podman, and nerdctl.
+pub(crate) fn list_labeled_containers(
+    engine: &dyn ContainerEngine,
+    label: &str,
+) -> anyhow::Result<Vec<LabeledContainer>> {
+    let run = |args: &[&str]| -> anyhow::Result<String> {
+        let capture = engine
+            .cli_command()
+            .args(args)
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe)
+            .capture()
+            .context(format!(
+                "Could not run the {} command line tool",
+                engine.name()
+            ))?;
+        if !capture.success() {
+            anyhow::bail!(
+                "{} {} failed ( {:?} ): {}",
+                engine.name(),
+                args.first().unwrap_or(&""),
+                capture.exit_status,
+                capture.stderr_str().trim()
+            );
+        }
+        Ok(capture.stdout_str())
+    };
+
+    let ids = run(&[
+        "ps",
+        "--all",
+        "--quiet",
+        "--no-trunc",
+        "--filter",
+        &format!("label={}", label),
+    ])?;
+
+    let mut containers = Vec::new();
+    for id in ids.lines().map(str::trim).filter(|x| !x.is_empty()) {
+        let inspect = run(&[
+            "container",
+            "inspect",
+            "--format",
+            "{{json .Config.Labels}} {{.State.Running}}",
+            id,
+        ])
+        .context(format!("Could not inspect container: {}", id))?;
+        let mut parts = inspect.trim().rsplitn(2, ' ');
+        let running = parts.next() == Some("true");
+        let labels: Option<HashMap<String, String>> =
+            serde_json::from_str(parts.next().unwrap_or("null"))
+                .context(format!("Could not parse labels of container: {}", id))?;
+
+        containers.push(LabeledContainer {
+            id: id.into(),
+            labels: labels.unwrap_or_default(),
+            running,
+        });
+    }
+
+    Ok(containers)
+}
+
+/// Make sure the given container engine is installed on the host
+///
+/// `podman_user` is the user to run Podman rootless as, if any, and `docker_channel` is where
+/// Docker is installed from. `on_progress` is called with a description of each installation step.
+pub(crate) fn install(
+    kind: ContainerEngineKind,
+    podman_user: Option<&str>,
+    docker_channel: DockerChannel,
+    on_progress: &mut dyn FnMut(&str) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    match kind {
+        ContainerEngineKind::Docker => crate::docker::ensure_docker(docker_channel, on_progress),
+        ContainerEngineKind::Podman => {
+            on_progress("Installing Podman")?;
+            crate::podman::ensure_podman(podman_user)
+        }
+        ContainerEngineKind::Containerd => {
+            on_progress("Installing containerd")?;
+            crate::containerd::ensure_containerd()
+        }
+    }
+}
+
+/// Connect to the given container engine
+///
+/// `podman_user` is the user to run Podman rootless as, if any.
+pub(crate) fn connect(
+    kind: ContainerEngineKind,
+    podman_user: Option<&str>,
+) -> anyhow::Result<Arc<dyn ContainerEngine>> {
+    let engine = match kind {
+        ContainerEngineKind::Docker => DockerApiEngine {
+            name: "Docker",
+            docker: Mutex::new(Docker::new()),
+            cli: vec!["docker".into()],
+            events_args: &[
+                "events",
+                "--filter",
+                "type=container",
+                "--filter",
+                "event=die",
+                "--format",
+                "{{json .}}",
+            ],
+        },
+        ContainerEngineKind::Podman => {
+            let socket_path = crate::podman::socket_path(podman_user)?;
+            DockerApiEngine {
+                name: "Podman",
+                docker: Mutex::new(Docker::unix(socket_path.to_string_lossy().into_owned())),
+                cli: vec![
+                    "podman".into(),
+                    "--remote".into(),
+                    "--url".into(),
+                    format!("unix://{}", socket_path.to_string_lossy()),
+                ],
+                events_args: &[
+                    "events",
+                    "--filter",
+                    "type=container",
+                    "--filter",
+                    "event=died",
+                    "--format",
+                    "json",
+                ],
+            }
+        }
+        ContainerEngineKind::Containerd => {
+            return Ok(Arc::new(crate::containerd::ContainerdEngine::connect()?));
+        }
+    };
+
+    // Test getting the engine info
+    log::debug!("Connecting to {}", engine.name);
+    {
+        let docker = engine.docker.lock().unwrap();
+        let info =
+            block_on(docker.info()).context(format!("Could not connect to {}", engine.name))?;
+        log::trace!("{} info: {:?}", engine.name, info);
+    }
+
+    Ok(Arc::new(engine))
+}
+
+/// A container engine that serves the Docker API, such as Docker or Podman
+///
+/// Networks are managed with the command line tool because the network API of older Podman
+/// versions isn't compatible with Docker's. Images are also loaded with the command line tool.
+struct DockerApiEngine {
+    name: &'static str,
+    docker: Mutex<Docker>,
+    /// The command line tool of the engine followed by the arguments needed to connect to it
+    cli: Vec<String>,
+    /// The arguments to the command line tool that follow the container exit events as JSON
+    events_args: &'static [&'static str],
+}
+
+impl DockerApiEngine {
+    /// Run the command line tool with the given args and return its output, failing if it exits
+    /// non-zero
+    ///
+    /// The `env` is set in the environment of the tool.
+    fn run_cli(&self, args: &[&str], env: &[(&str, &str)]) -> anyhow::Result<String> {
+        let capture = self
+            .cli_command()
+            .args(args)
+            .env_extend(env)
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe)
+            .capture()
+            .context(format!("Could not run the {} command line tool", self.name))?;
+
+        if capture.success() {
+            Ok(capture.stdout_str())
+        } else {
+            anyhow::bail!(
+                "{} {} failed ( {:?} ): {}",
+                self.name,
+                args.join(" "),
+                capture.exit_status,
+                capture.stderr_str().trim()
+            );
+        }
+    }
+}
+
+impl ContainerEngine for DockerApiEngine {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn cli_command(&self) -> Exec {
+        let (program, args) = self
+            .cli
+            .split_first()
+            .expect("Missing container engine command");
+        Exec::cmd(program).args(args)
+    }
+
+    fn events_command(&self) -> Exec {
+        self.cli_command().args(self.events_args)
+    }
+
+    fn container_exists(&self, id: &str) -> anyhow::Result<bool> {
+        let docker = self.docker.lock().unwrap();
+        match block_on(docker.containers().get(id).inspect()) {
+            Ok(_) => Ok(true),
+            Err(shiplift::Error::Fault { code, .. }) if code.as_u16() == 404 => Ok(false),
+            Err(e) => Err(e).context(format!("Could not inspect container: {}", id)),
+        }
+    }
+
+    fn pull_image(
+        &self,
+        image: &str,
+        credentials: Option<RegistryCredentials>,
+    ) -> anyhow::Result<()> {
+        let mut pull_options = PullOptions::builder();
+        pull_options.image(image);
+
+        if let Some(credentials) = credentials {
+            let mut registry_auth = shiplift::RegistryAuth::builder();
+            registry_auth
+                .username(credentials.username)
+                .password(credentials.password);
+            if let Some(server_address) = &credentials.server_address {
+                registry_auth.server_address(server_address.as_str());
+            }
+            pull_options.auth(registry_auth.build());
+        }
+
+        let docker = self.docker.lock().unwrap();
+        block_on(docker.images().pull(&pull_options.build()).collect())?;
+
+        Ok(())
+    }
+
+    fn load_image(&self, archive: &Path) -> anyhow::Result<()> {
+        // The command line tool streams the archive to the engine so that it doesn't have to be
+        // read into memory
+        self.run_cli(&["load", "--input", &archive.to_string_lossy()], &[])?;
+
+        Ok(())
+    }
+
+    fn image_command(&self, image: &str) -> anyhow::Result<ImageCommand> {
+        let docker = self.docker.lock().unwrap();
+        let image_config = block_on(docker.images().get(image).inspect())
+            .context(format!("Could not inspect image: {}", image))?
+            .config;
+
+        Ok(ImageCommand {
+            entrypoint: image_config.entrypoint.unwrap_or_default(),
+            command: image_config.cmd.unwrap_or_default(),
+            user: image_config.user,
+        })
+    }
+
+    fn image_digests(&self, image: &str) -> anyhow::Result<Vec<String>> {
+        let docker = self.docker.lock().unwrap();
+        let repo_digests = block_on(docker.images().get(image).inspect())
+            .context(format!("Could not inspect image: {}", image))?
+            .repo_digests;
+
+        Ok(parse_repo_digests(repo_digests.unwrap_or_default()))
+    }
+
+    fn container_image_digests(&self, id: &str) -> anyhow::Result<Vec<String>> {
+        let image_id = {
+            let docker = self.docker.lock().unwrap();
+            block_on(docker.containers().get(id).inspect())
+                .context(format!("Could not inspect container: {}", id))?
+                .image
+        };
+
+        self.image_digests(&image_id)
+    }
+
+    fn create_container(
+        &self,
+        name: &str,
+        config: &ContainerConfig,
+        context: &ContainerContext,
+    ) -> anyhow::Result<String> {
+        let settings = config.to_run_settings(context)?;
+
+        let id = if settings.requires_cli() {
+            // shiplift can't set the DNS servers, devices, or GPUs, so the container is created
+            // with the command line tool instead
+            let (args, env) = settings.to_cli_create_args(name, vec![]);
+            let args: Vec<&str> = args.iter().map(AsRef::as_ref).collect();
+            self.run_cli(&args, &env)?.trim().into()
+        } else {
+            let mut options = settings.to_container_options();
+            options.name = Some(name.into());
+
+            log::trace!("Creating container with options: {:#?}", options);
+            let docker = self.docker.lock().unwrap();
+            block_on(docker.containers().create(&options))?.id
+        };
+
+        // Attach the container to the shared network under its Lucky name
+        if let Some(network) = context.shared_network {
+            self.run_cli(
+                &[
+                    "network",
+                    "connect",
+                    "--alias",
+                    context.container_name,
+                    network,
+                    &id,
+                ],
+                &[],
+            )
+            .context(format!(
+                "Could not attach container to network: {}",
+                network
+            ))?;
+        }
+
+        Ok(id)
+    }
+
+    fn start_container(&self, id: &str) -> anyhow::Result<()> {
+        let docker = self.docker.lock().unwrap();
+        Ok(ignore_not_modified(block_on(
+            docker.containers().get(id).start(),
+        ))?)
+    }
+
+    fn stop_container(&self, id: &str, timeout: Option<Duration>) -> anyhow::Result<()> {
+        let docker = self.docker.lock().unwrap();
+        Ok(ignore_not_modified(block_on(
+            docker.containers().get(id).stop(timeout),
+        ))?)
+    }
+
+    fn restart_container(&self, id: &str, timeout: Option<Duration>) -> anyhow::Result<()> {
+        let docker = self.docker.lock().unwrap();
+        Ok(block_on(docker.containers().get(id).restart(timeout))?)
+    }
+
+    fn remove_container(&self, id: &str) -> anyhow::Result<()> {
+        let docker = self.docker.lock().unwrap();
+        Ok(block_on(docker.containers().get(id).delete())?)
+    }
+
+    fn ensure_network(&self, name: &str) -> anyhow::Result<()> {
+        if self.run_cli(&["network", "inspect", name], &[]).is_err() {
+            log::debug!("Creating network: {}", name);
+            self.run_cli(&["network", "create", "--driver", "bridge", name], &[])?;
+        }
+
+        Ok(())
+    }
+
+    fn remove_network(&self, name: &str) -> anyhow::Result<()> {
+        if self.run_cli(&["network", "inspect", name], &[]).is_ok() {
+            log::debug!("Removing network: {}", name);
+            self.run_cli(&["network", "rm", name], &[])?;
+        }
+
+        Ok(())
+    }
+
+    fn exec(
+        &self,
+        id: &str,
+        command: &[String],
+        env: &[String],
+        mut on_output: OutputHandler,
+    ) -> anyhow::Result<Option<i32>> {
+        // TODO: https://github.com/softprops/shiplift/issues/219
+        // The Docker API doesn't give us the exit code of the command, so we run it with the
+        // `lucky exit-code-helper` wrapper, which prints the exit code after a special prefix. The
+        // exit code helper only runs in the daemon context.
+        let mut cmd = vec!["lucky", "exit-code-helper"];
+        cmd.extend(command.iter().map(String::as_str));
+        let mut env: Vec<&str> = env.iter().map(String::as_str).collect();
+        env.push("LUCKY_CONTEXT=daemon");
+
+        let exec_options = ExecContainerOptions::builder()
+            .attach_stderr(true)
+            .attach_stdout(true)
+            .env(env)
+            .cmd(cmd)
+            .build();
+
+        let exit_code: Arc<Mutex<Option<i32>>> = Arc::new(Mutex::new(None));
+        let exit_code_ = exit_code.clone();
+
+        let docker = self.docker.lock().unwrap();
+        block_on(
+            docker
+                .containers()
+                .get(id)
+                .exec(&exec_options)
+                .for_each(move |chunk| {
+                    let chunk_str = chunk.as_string_lossy();
+
+                    // If the line starts with the exit code indication prefix
+                    if chunk_str.starts_with(LUCKY_EXIT_CODE_HELPER_PREFIX) {
+                        *exit_code_.lock().unwrap() = Some(
+                            chunk_str
+                                .trim()
+                                .trim_start_matches(LUCKY_EXIT_CODE_HELPER_PREFIX)
+                                .parse()
+                                .map_err(|e| {
+                                    shiplift::Error::InvalidResponse(format!(
+                                        "Could not parse exit code: {}",
+                                        e
+                                    ))
+                                })?,
+                        );
+                    } else {
+                        on_output(&chunk_str);
+                    }
+
+                    Ok(())
+                }),
+        )?;
+
+        let exit_code = *exit_code.lock().unwrap();
+        Ok(exit_code)
+    }
+}
+
+/// Treat the "not modified" response, which is returned when starting a container that is already
+/// running or stopping one that is already stopped, as a success
+fn ignore_not_modified(result: Result<(), shiplift::Error>) -> Result<(), shiplift::Error> {
+    match result {
+        Err(shiplift::Error::Fault { code, .. }) if code.as_u16() == 304 => Ok(()),
+        other => other,
+    }
+}
@@ -0,0 +1,357 @@
+//! Contains tools for installing containerd and running containers with it
+//!
+//! containerd doesn't serve the Docker API, so containers are managed with `nerdctl`, the
+//! Docker-compatible command line tool for containerd. Lucky's containers are kept in their own
+//! containerd namespace so that they don't get mixed up with the containers of other tools using the
+//! same containerd, such as a Kubernetes node.
+use anyhow::{bail, format_err, Context};
+use serde_json::Value as JsonValue;
+use subprocess::{Exec, ExitStatus, Redirection};
+
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::container_engine::{
+    parse_repo_digests, ContainerContext, ContainerEngine, ImageCommand, OutputHandler,
+    RegistryCredentials,
+};
+use crate::docker::{cli_env_args, ContainerConfig};
+use crate::process::{cmd_exists, run_cmd, run_cmd_with_retries};
+
+/// The containerd namespace that Lucky's containers are created in
+const NAMESPACE: &str = "lucky";
+
+/// Make sure containerd and nerdctl are installed and that containerd is running
+///
+/// An existing containerd, such as the one of a Kubernetes node, will be used if there is one.
+pub(crate) fn ensure_containerd() -> anyhow::Result<()> {
+    let mut packages = vec![];
+    if !cmd_exists("containerd", &["--version"])? {
+        packages.push("containerd");
+    }
+    if !cmd_exists("nerdctl", &["--version"])? {
+        packages.extend(&["nerdctl", "containernetworking-plugins"]);
+    }
+
+    // Skip if everything is already installed
+    if packages.is_empty() {
+        return Ok(());
+    }
+
+    let mut args = vec!["install", "-y"];
+    args.extend(&packages);
+    run_cmd_with_retries("apt-get", &args, &Default::default())?;
+
+    // Make sure nerdctl is installed
+    if !cmd_exists("nerdctl", &["--version"])? {
+        bail!("Could not install nerdctl");
+    }
+
+    // Start containerd if we installed it
+    if packages.contains(&"containerd") {
+        run_cmd("systemctl", &["enable", "--now", "containerd"])?;
+    }
+
+    Ok(())
+}
+
+/// The container engine that runs containers with containerd
+pub(crate) struct ContainerdEngine;
+
+impl ContainerdEngine {
+    /// Connect to containerd, making sure that it is running
+    pub fn connect() -> anyhow::Result<Self> {
+        log::debug!("Connecting to containerd");
+        let engine = ContainerdEngine;
+        let info = engine
+            .run(&["info"], &[])
+            .context("Could not connect to containerd")?;
+        log::trace!("containerd info: {}", info);
+
+        Ok(engine)
+    }
+
+    /// Run nerdctl with the given args and return its output, failing if it exits non-zero
+    ///
+    /// The `env` is set in the environment of nerdctl. This is used to pass environment variables
+    /// to containers without putting their values on the command line.
+    fn run(&self, args: &[&str], env: &[(&str, &str)]) -> anyhow::Result<String> {
+        let capture = self
+            .cli_command()
+            .args(args)
+            .env_extend(env)
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe)
+            .capture()
+            .context("Could not run nerdctl")?;
+
+        if capture.success() {
+            Ok(capture.stdout_str())
+        } else {
+            bail!(
+                "nerdctl {} failed ( {:?} ): {}",
+                args.first().unwrap_or(&""),
+                capture.exit_status,
+                capture.stderr_str().trim()
+            );
+        }
+    }
+
+    /// Check whether or not a container is running
+    fn is_running(&self, id: &str) -> anyhow::Result<bool> {
+        let running = self.run(
+            &["container", "inspect", "--format", "{{.State.Running}}", id],
+            &[],
+        )?;
+        Ok(running.trim() == "true")
+    }
+}
+
+impl ContainerEngine for ContainerdEngine {
+    fn name(&self) -> &'static str {
+        "containerd"
+    }
+
+    fn cli_command(&self) -> Exec {
+        Exec::cmd("nerdctl").args(&["--namespace", NAMESPACE])
+    }
+
+    fn events_command(&self) -> Exec {
+        self.cli_command()
+            .args(&["events", "--format", "{{json .}}"])
+    }
+
+    fn container_exists(&self, id: &str) -> anyhow::Result<bool> {
+        match self.run(&["container", "inspect", "--format", "{{.ID}}", id], &[]) {
+            Ok(_) => Ok(true),
+            Err(e) if e.to_string().to_lowercase().contains("no such") => Ok(false),
+            Err(e) => Err(e).context(format!("Could not inspect container: {}", id)),
+        }
+    }
+
+    fn pull_image(
+        &self,
+        image: &str,
+        credentials: Option<RegistryCredentials>,
+    ) -> anyhow::Result<()> {
+        // Log in to the registry, passing the password over stdin so that it isn't on the
+        // command line
+        if let Some(credentials) = credentials {
+            let mut args = vec![
+                "login",
+                "--username",
+                credentials.username.as_str(),
+                "--password-stdin",
+            ];
+            if let Some(server_address) = &credentials.server_address {
+                args.push(server_address);
+            }
+            let capture = self
+                .cli_command()
+                .args(&args)
+                .stdin(credentials.password.as_str())
+                .stdout(Redirection::Pipe)
+                .stderr(Redirection::Merge)
+                .capture()
+                .context("Could not run nerdctl")?;
+            if !capture.success() {
+                bail!(
+                    "Could not log in to registry: {}",
+                    capture.stdout_str().trim()
+                );
+            }
+        }
+
+        self.run(&["pull", "--quiet", image], &[])?;
+
+        Ok(())
+    }
+
+    fn load_image(&self, archive: &Path) -> anyhow::Result<()> {
+        self.run(&["load", "--input", &archive.to_string_lossy()], &[])?;
+
+        Ok(())
+    }
+
+    fn image_command(&self, image: &str) -> anyhow::Result<ImageCommand> {
+        let config = self
+            .run(
+                &["image", "inspect", "--format", "{{json .Config}}", image],
+                &[],
+            )
+            .context(format!("Could not inspect image: {}", image))?;
+        let config: JsonValue = serde_json::from_str(config.trim())
+            .map_err(|e| format_err!("Could not parse config of image {}: {}", image, e))?;
+
+        // Get a list of strings from the image config
+        let get_strings = |key: &str| -> Vec<String> {
+            config
+                .get(key)
+                .and_then(JsonValue::as_array)
+                .map(|x| {
+                    x.iter()
+                        .filter_map(|x| x.as_str().map(Into::into))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        Ok(ImageCommand {
+            entrypoint: get_strings("Entrypoint"),
+            command: get_strings("Cmd"),
+            user: config
+                .get("User")
+                .and_then(JsonValue::as_str)
+                .unwrap_or_default()
+                .into(),
+        })
+    }
+
+    fn image_digests(&self, image: &str) -> anyhow::Result<Vec<String>> {
+        let repo_digests = self
+            .run(
+                &[
+                    "image",
+                    "inspect",
+                    "--format",
+                    "{{json .RepoDigests}}",
+                    image,
+                ],
+                &[],
+            )
+            .context(format!("Could not inspect image: {}", image))?;
+        let repo_digests: Option<Vec<String>> = serde_json::from_str(repo_digests.trim())
+            .map_err(|e| format_err!("Could not parse digests of image {}: {}", image, e))?;
+
+        Ok(parse_repo_digests(repo_digests.unwrap_or_default()))
+    }
+
+    fn container_image_digests(&self, id: &str) -> anyhow::Result<Vec<String>> {
+        let image = self
+            .run(&["container", "inspect", "--format", "{{.Image}}", id], &[])
+            .context(format!("Could not inspect container: {}", id))?;
+
+        self.image_digests(image.trim())
+    }
+
+    fn create_container(
+        &self,
+        name: &str,
+        config: &ContainerConfig,
+        context: &ContainerContext,
+    ) -> anyhow::Result<String> {
+        let settings = config.to_run_settings(context)?;
+
+        // nerdctl can't give a container network aliases, so the container is given its Lucky name
+        // as its hostname, which the other containers on the network can resolve it by
+        let mut extra_args = vec![];
+        if let Some(network) = context.shared_network {
+            extra_args.extend(vec![
+                "--network".into(),
+                network.into(),
+                "--hostname".into(),
+                context.container_name.into(),
+            ]);
+        }
+
+        // The environment is passed through nerdctl's environment so that secrets don't end up on
+        // the command line
+        let (args, env) = settings.to_cli_create_args(name, extra_args);
+
+        let args: Vec<&str> = args.iter().map(AsRef::as_ref).collect();
+        Ok(self.run(&args, &env)?.trim().into())
+    }
+
+    fn start_container(&self, id: &str) -> anyhow::Result<()> {
+        if !self.is_running(id)? {
+            self.run(&["start", id], &[])?;
+        }
+
+        Ok(())
+    }
+
+    fn stop_container(&self, id: &str, timeout: Option<Duration>) -> anyhow::Result<()> {
+        if self.is_running(id)? {
+            let timeout = timeout.map(|x| x.as_secs().to_string());
+            let mut args = vec!["stop"];
+            if let Some(timeout) = &timeout {
+                args.extend(&["--time", timeout.as_str()]);
+            }
+            args.push(id);
+            self.run(&args, &[])?;
+        }
+
+        Ok(())
+    }
+
+    fn restart_container(&self, id: &str, timeout: Option<Duration>) -> anyhow::Result<()> {
+        let timeout = timeout.map(|x| x.as_secs().to_string());
+        let mut args = vec!["restart"];
+        if let Some(timeout) = &timeout {
+            args.extend(&["--time", timeout.as_str()]);
+        }
+        args.push(id);
+        self.run(&args, &[])?;
+
+        Ok(())
+    }
+
+    fn remove_container(&self, id: &str) -> anyhow::Result<()> {
+        self.run(&["rm", id], &[])?;
+
+        Ok(())
+    }
+
+    fn ensure_network(&self, name: &str) -> anyhow::Result<()> {
+        if self.run(&["network", "inspect", name], &[]).is_err() {
+            log::debug!("Creating network: {}", name);
+            self.run(&["network", "create", "--driver", "bridge", name], &[])?;
+        }
+
+        Ok(())
+    }
+
+    fn remove_network(&self, name: &str) -> anyhow::Result<()> {
+        if self.run(&["network", "inspect", name], &[]).is_ok() {
+            log::debug!("Removing network: {}", name);
+            self.run(&["network", "rm", name], &[])?;
+        }
+
+        Ok(())
+    }
+
+    fn exec(
+        &self,
+        id: &str,
+        command: &[String],
+        env: &[String],
+        mut on_output: OutputHandler,
+    ) -> anyhow::Result<Option<i32>> {
+        let (env, env_args) = cli_env_args(env);
+        let mut args = vec!["exec".to_string()];
+        args.extend(env_args);
+        args.push(id.into());
+        args.extend(command.iter().cloned());
+
+        let mut process = self
+            .cli_command()
+            .args(&args)
+            .env_extend(&env)
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Merge)
+            .popen()
+            .context("Could not run nerdctl")?;
+
+        // Pass on the output as it is written
+        let output = BufReader::new(process.stdout.take().expect("Stdout not opened"));
+        for line in output.lines() {
+            on_output(&format!("{}\n", line?));
+        }
+
+        Ok(match process.wait()? {
+            ExitStatus::Exited(code) => Some(code as i32),
+            _ => None,
+        })
+    }
+}
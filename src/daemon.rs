@@ -1,32 +1,77 @@
 //! Contains the Lucky Daemon and RPC implementaiton used for client->daemon communication.
 use anyhow::Context;
-use chrono::{DateTime, Local};
+use chrono::{DateTime, Local, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
-use shiplift::Docker;
 
 use crossbeam::{channel::unbounded as unbounded_channel, scope as thread_scope};
 
-use std::collections::HashMap;
-use std::convert::TryInto;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::convert::{TryFrom, TryInto};
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc, Mutex, RwLock,
 };
+use std::time::{Duration, Instant};
 
-use crate::docker::{ContainerInfo, PortBinding, VolumeSource, VolumeTarget};
-use crate::juju;
+use crate::container_engine::{self, ContainerEngine};
+use crate::docker::{
+    self, ContainerInfo, ContainerUpdateSettings, EnvMode, PortBinding, UpdateHook,
+    UpdateHookStage, VolumeSource, VolumeTarget,
+};
+use crate::juju::{self, JujuBackend};
 use crate::rpc;
-use crate::types::{LuckyMetadata, ScriptStatus};
+use crate::systemd::{HostServiceInfo, RestartPolicy};
+use crate::trace;
+use crate::types::{
+    hook_key_id,
+    juju::{JujuFeature, JujuVersion},
+    kv_key_matches, script_dependencies, CharmScript, ContainerEngineKind, ContainerRestartPolicy,
+    JobSchedule, LuckyMetadata, Platform, ScriptState, ScriptStatus, UpdateStrategy,
+    DEFAULT_CONTAINER_NAME, GLOBAL_KV_NAMESPACE,
+};
 
 use crate::VOLUME_DIR;
 
 /// Void type
 enum Void {}
 
+/// The number of status changes of each script that are kept in the status history
+const STATUS_HISTORY_LEN: usize = 20;
+/// How often a script waiting for a named lock checks whether the lock has been released
+const LOCK_WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Container volume backups
+mod backup;
+/// Container log streaming and forwarding
+mod container_logs;
+/// Container crash loop detection
+mod crash_monitor;
+/// Built-in `docker-registry` relation support
+mod docker_registry;
+/// The shared download cache
+mod download;
+/// Templated container environment variables and files
+mod env_template;
+/// Container health checks
+mod health;
+/// Mapping of Juju hooks onto `lucky.yaml` hooks
+mod hook_mapping;
+/// Periodic reconciliation in the `update-status` hook
+mod reconcile;
+/// Relation data and key-value store synchronization
+mod relation_kv;
+/// Running scripts in the order of their dependencies
+mod script_graph;
+/// Script output log files
+mod script_logs;
+/// Unit-local encrypted secret store
+mod secrets;
+/// Long-running service scripts
+mod service_scripts;
 /// Daemon tools
 mod tools;
 // Built-in daemon hook handlers
@@ -44,13 +89,264 @@ struct DaemonState {
     script_statuses: HashMap<String, ScriptStatus>,
     // TODO: Key-value store implementation is not currently sufficient for detecting changes for
     // reactive.
-    /// The unit-local key-value store
+    /// The unit-local key-value store for the global namespace
     kv: HashMap<String, Cd<String>>,
+    /// The unit-local key-value stores of the other namespaces, keyed by namespace
+    #[serde(default)]
+    kv_namespaces: HashMap<String, HashMap<String, Cd<String>>>,
+    /// The times that the expiring key-value store keys expire, as Unix timestamps, keyed by
+    /// namespace and then by key. The global namespace is `global`.
+    #[serde(default)]
+    kv_expirations: HashMap<String, HashMap<String, i64>>,
+    /// The keys whose values are encrypted with the secret store key, keyed by namespace. The
+    /// global namespace is `global`.
+    #[serde(default)]
+    kv_secrets: HashMap<String, HashSet<String>>,
     default_container: Option<Cd<ContainerInfo>>,
     /// Other containers that the daemon is supervising
     named_containers: HashMap<String, Cd<ContainerInfo>>,
+    /// The systemd services that the daemon is supervising on the host, keyed by service name
+    #[serde(default)]
+    host_services: HashMap<String, Cd<HostServiceInfo>>,
     /// The cached charm config obtained from Juju's `config-get` hook tool
     charm_config: HashMap<String, Cd<JsonValue>>,
+    /// The ports that have been opened for this unit, in the `port-or-range/protocol` format
+    #[serde(default)]
+    opened_ports: HashSet<String>,
+    /// The ports in `opened_ports` that were opened automatically for the containers' port
+    /// bindings, and will be closed when the bindings are removed
+    #[serde(default)]
+    container_opened_ports: HashSet<String>,
+    /// The peer store data as of the last peer relation hook. This is used to tell scripts which
+    /// keys have changed.
+    #[serde(default)]
+    peer_store: HashMap<String, String>,
+    /// The container engine that was chosen when the charm was installed
+    #[serde(default)]
+    container_engine: Option<ContainerEngineKind>,
+    /// How each container is updated, keyed by container name
+    #[serde(default)]
+    container_updates: HashMap<String, ContainerUpdateSettings>,
+    /// The registry that the charm is related to over the `docker-registry` interface, if any
+    #[serde(default)]
+    docker_registry: Option<docker_registry::RegistryRelation>,
+    /// The key-value store values that have been published to each relation, keyed by relation ID
+    #[serde(default)]
+    relation_kv_published: HashMap<String, HashMap<String, String>>,
+    /// The name of the last Juju hook that was triggered. This is read by `lucky dev watch` to
+    /// re-run the hook after syncing changes to the charm.
+    #[serde(default)]
+    last_hook: Option<String>,
+    /// The version of the charm's `migrations` that the unit's data has been migrated to. This is
+    /// `None` for units that were installed before the charm had any migrations.
+    #[serde(default)]
+    state_version: Option<u32>,
+    /// Whether or not the service scripts should be running. They are enabled by the `start` hook
+    /// and disabled by the `stop` hook.
+    #[serde(default)]
+    service_scripts_enabled: bool,
+    /// The statuses that are cleared when the script that set them exits successfully, mapped to
+    /// the ID of that script
+    #[serde(default)]
+    transient_statuses: HashMap<String, String>,
+    /// The last `STATUS_HISTORY_LEN` changes of each script's status, oldest first, keyed by script
+    /// ID
+    #[serde(default)]
+    status_history: HashMap<String, VecDeque<StatusTransition>>,
+    /// The named locks that are held by scripts, mapped to the run ID of the script holding them.
+    /// These aren't persisted because the scripts that hold them don't outlive the daemon.
+    #[serde(skip)]
+    locks: HashMap<String, String>,
+}
+
+impl DaemonState {
+    /// Record a change of a script's status in the status history
+    fn record_status_change(
+        &mut self,
+        script_id: &str,
+        old: Option<ScriptStatus>,
+        new: Option<ScriptStatus>,
+    ) {
+        if old == new {
+            return;
+        }
+
+        let history = self.status_history.entry(script_id.into()).or_default();
+        history.push_back(StatusTransition {
+            time: Utc::now().timestamp_millis(),
+            old,
+            new,
+        });
+        while history.len() > STATUS_HISTORY_LEN {
+            history.pop_front();
+        }
+    }
+
+    /// Clear a script's status, recording the change in the status history. This doesn't update
+    /// the Juju status.
+    fn remove_script_status(&mut self, script_id: &str) -> Option<ScriptStatus> {
+        let old = self.script_statuses.remove(script_id);
+        if old.is_some() {
+            self.record_status_change(script_id, old.clone(), None);
+        }
+        old
+    }
+
+    /// Get the key-value store of a namespace, or the global store if the namespace is `None`
+    fn kv_store(&self, namespace: Option<&str>) -> Option<&HashMap<String, Cd<String>>> {
+        match namespace {
+            Some(namespace) => self.kv_namespaces.get(namespace),
+            None => Some(&self.kv),
+        }
+    }
+
+    /// Get the key-value store of a namespace mutably, creating it if it doesn't exist
+    fn kv_store_mut(&mut self, namespace: Option<&str>) -> &mut HashMap<String, Cd<String>> {
+        match namespace {
+            Some(namespace) => self.kv_namespaces.entry(namespace.into()).or_default(),
+            None => &mut self.kv,
+        }
+    }
+
+    /// Release all of the named locks held by a run of a script
+    fn release_locks(&mut self, run_id: &str) {
+        self.locks.retain(|name, holder| {
+            if holder == run_id {
+                log::debug!("Releasing lock {} held by script run {}", name, run_id);
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    /// Remove the namespaces that no longer have any keys, and the expirations of deleted keys
+    fn prune_kv_namespaces(&mut self) {
+        self.kv_namespaces.retain(|_, store| !store.is_empty());
+
+        let kv = &self.kv;
+        let kv_namespaces = &self.kv_namespaces;
+        let key_exists = |namespace: &String, key: &String| {
+            if namespace == GLOBAL_KV_NAMESPACE {
+                kv.contains_key(key)
+            } else {
+                kv_namespaces
+                    .get(namespace)
+                    .map_or(false, |x| x.contains_key(key))
+            }
+        };
+        self.kv_expirations.retain(|namespace, expirations| {
+            expirations.retain(|key, _| key_exists(namespace, key));
+            !expirations.is_empty()
+        });
+        self.kv_secrets.retain(|namespace, keys| {
+            keys.retain(|key| key_exists(namespace, key));
+            !keys.is_empty()
+        });
+    }
+
+    /// Check whether or not a key's value is encrypted
+    fn kv_key_secret(&self, namespace: Option<&str>, key: &str) -> bool {
+        self.kv_secrets
+            .get(namespace.unwrap_or(GLOBAL_KV_NAMESPACE))
+            .map_or(false, |x| x.contains(key))
+    }
+
+    /// Set whether or not a key's value is encrypted
+    fn set_kv_secret(&mut self, namespace: Option<&str>, key: &str, secret: bool) {
+        let namespace = namespace.unwrap_or(GLOBAL_KV_NAMESPACE);
+        if secret {
+            self.kv_secrets
+                .entry(namespace.into())
+                .or_default()
+                .insert(key.into());
+        } else if let Some(keys) = self.kv_secrets.get_mut(namespace) {
+            keys.remove(key);
+        }
+    }
+
+    /// Check whether or not a key has expired but hasn't been removed from the store yet
+    fn kv_key_expired(&self, namespace: Option<&str>, key: &str) -> bool {
+        self.kv_expirations
+            .get(namespace.unwrap_or(GLOBAL_KV_NAMESPACE))
+            .and_then(|x| x.get(key))
+            .map_or(false, |expires| *expires <= Utc::now().timestamp())
+    }
+
+    /// Set the number of seconds until a key expires, or make it permanent if `ttl` is `None`
+    fn set_kv_ttl(&mut self, namespace: Option<&str>, key: &str, ttl: Option<i64>) {
+        let namespace = namespace.unwrap_or(GLOBAL_KV_NAMESPACE);
+        if let Some(ttl) = ttl {
+            self.kv_expirations
+                .entry(namespace.into())
+                .or_default()
+                .insert(key.into(), Utc::now().timestamp().saturating_add(ttl));
+        } else if let Some(expirations) = self.kv_expirations.get_mut(namespace) {
+            expirations.remove(key);
+        }
+    }
+
+    /// Remove the keys that have expired from the store, returning the removed keys keyed by
+    /// namespace
+    fn remove_expired_kv(&mut self) -> HashMap<String, Vec<String>> {
+        let now = Utc::now().timestamp();
+        let expired: HashMap<String, Vec<String>> = self
+            .kv_expirations
+            .iter()
+            .map(|(namespace, expirations)| {
+                let mut keys: Vec<String> = expirations
+                    .iter()
+                    .filter(|(_, expires)| **expires <= now)
+                    .map(|(key, _)| key.clone())
+                    .collect();
+                keys.sort();
+                (namespace.clone(), keys)
+            })
+            .filter(|(_, keys)| !keys.is_empty())
+            .collect();
+
+        for (namespace, keys) in &expired {
+            let namespace = Some(namespace.as_str()).filter(|x| *x != GLOBAL_KV_NAMESPACE);
+            let store = self.kv_store_mut(namespace);
+            for key in keys {
+                log::debug!("Key-Value expired: {}", key);
+                store.remove(key);
+            }
+        }
+        self.prune_kv_namespaces();
+
+        expired
+    }
+
+    /// Get the key-value pairs of every namespace, keyed by namespace, leaving out secret keys
+    fn export_kv(&self) -> HashMap<String, HashMap<String, String>> {
+        // Get the pairs of a namespace that haven't expired and aren't secret
+        let to_pairs = |namespace: &str, store: &HashMap<String, Cd<String>>| {
+            let namespace = Some(namespace).filter(|x| *x != GLOBAL_KV_NAMESPACE);
+            store
+                .iter()
+                .filter(|(k, _)| {
+                    !self.kv_key_expired(namespace, k) && !self.kv_key_secret(namespace, k)
+                })
+                .map(|(k, v)| (k.clone(), (**v).clone()))
+                .collect::<HashMap<String, String>>()
+        };
+
+        let mut namespaces: HashMap<String, HashMap<String, String>> = self
+            .kv_namespaces
+            .iter()
+            .map(|(namespace, store)| (namespace.clone(), to_pairs(namespace, store)))
+            .collect();
+        if !self.kv.is_empty() {
+            namespaces.insert(
+                GLOBAL_KV_NAMESPACE.into(),
+                to_pairs(GLOBAL_KV_NAMESPACE, &self.kv),
+            );
+        }
+        namespaces.retain(|_, pairs| !pairs.is_empty());
+
+        namespaces
+    }
 }
 
 /// The Lucky Daemon RPC service
@@ -71,8 +367,48 @@ struct LuckyDaemon {
     state: Arc<RwLock<DaemonState>>,
     /// The last time that the cron tick was run
     last_cron_tick: Arc<Mutex<DateTime<Local>>>,
-    /// The docker daemon connection if it has been loaded
-    docker_conn: Arc<Mutex<Option<Arc<Mutex<Docker>>>>>,
+    /// The kind of container engine that the containers are run with
+    container_engine_kind: RwLock<ContainerEngineKind>,
+    /// The container engine connection if it has been loaded
+    container_engine: Mutex<Option<Arc<dyn ContainerEngine>>>,
+    /// Whether or not the `opened_ports` in the daemon state have been synchronized with Juju since
+    /// the daemon started. The persisted state may be out of date after a daemon restart so we
+    /// make sure to re-load it from Juju before we trust it.
+    opened_ports_synced: AtomicBool,
+    /// Cache of the Juju secrets that have been retrieved, keyed by secret ID or label. This is
+    /// intentionally kept out of the `DaemonState` so that secrets are never written to disk.
+    secret_cache: Arc<Mutex<HashMap<String, HashMap<String, String>>>>,
+    /// Lock used to enforce the `ConcurrencyClass` of each RPC method. Hook, cron, and event
+    /// triggers do not take a class because the scripts that they run need to call back into the
+    /// daemon.
+    concurrency_lock: RwLock<()>,
+    /// Lock held by the hook, action, and cron triggers so that they run one at a time. They set
+    /// the Juju environment variables for the whole daemon process, which the hook tools called
+    /// by their scripts rely on.
+    trigger_lock: Mutex<()>,
+    /// The version of the Juju agent, if it could be detected when the daemon started
+    juju_version: Option<JujuVersion>,
+    /// The backend used to run Juju hook tools
+    juju: Arc<dyn JujuBackend>,
+    /// The unit's encrypted secret store. This is loaded the first time it is needed.
+    secret_store: Mutex<Option<secrets::SecretStore>>,
+    /// The cancel handles of the actions that are currently running, keyed by action ID
+    running_actions: Mutex<HashMap<String, CancelHandle>>,
+    /// The platform that the charm is running on. This is never `Platform::Auto`.
+    platform: Platform,
+    /// The names of the workload containers if this is a Kubernetes sidecar charm
+    sidecar_containers: Vec<String>,
+    /// Maps the hooks run by Juju onto the hooks in the `lucky.yaml`
+    hook_mapping: Box<dyn hook_mapping::HookMapping>,
+    /// The results of the container health checks. This is not persisted, so the health of the
+    /// containers will be re-checked when the daemon restarts.
+    container_health: Mutex<health::HealthMap>,
+    /// The background processes that forward container logs to the Juju log
+    log_forwarders: Mutex<container_logs::LogForwarders>,
+    /// The background process that watches the containers for crash loops
+    crash_monitor: Mutex<crash_monitor::CrashMonitor>,
+    /// The long-running service scripts that the daemon is supervising
+    service_scripts: Mutex<service_scripts::ServiceSupervisor>,
 }
 
 pub(crate) struct LuckyDaemonOptions {
@@ -81,6 +417,7 @@ pub(crate) struct LuckyDaemonOptions {
     pub data_dir: PathBuf,
     pub socket_path: PathBuf,
     pub stop_listening: Arc<AtomicBool>,
+    pub juju: Arc<dyn JujuBackend>,
 }
 
 // TODO: set juju status upon errors
@@ -90,7 +427,8 @@ macro_rules! handle_err {
         match $expr {
             Ok(v) => v,
             Err(e) => {
-                let e = format!("{:?}", e);
+                // Make sure no secrets are leaked in the error response
+                let e = crate::log::redact(&format!("{:?}", e));
                 log::error!("{}", e);
                 return $call.reply_error(e);
             }
@@ -104,6 +442,11 @@ impl LuckyDaemon {
     /// `stop_listening` will be set to `true` by the daemon if it recieves a `StopDaemon` RPC. The
     /// actual stopping of the server itself is not handled by the daemon.
     fn new(options: LuckyDaemonOptions) -> Self {
+        let sidecar_containers = tools::get_sidecar_containers(&options.charm_dir);
+        let platform =
+            tools::resolve_platform(options.lucky_metadata.platform, &sidecar_containers);
+        let container_engine_kind = options.lucky_metadata.container_engine;
+
         let daemon = LuckyDaemon {
             lucky_metadata: options.lucky_metadata,
             charm_dir: options.charm_dir,
@@ -112,16 +455,62 @@ impl LuckyDaemon {
             stop_listening: options.stop_listening,
             state: Default::default(),
             last_cron_tick: Arc::new(Mutex::new(Local::now())),
-            docker_conn: Arc::new(Mutex::new(None)),
+            container_engine_kind: RwLock::new(container_engine_kind),
+            container_engine: Mutex::new(None),
+            opened_ports_synced: AtomicBool::new(false),
+            secret_cache: Default::default(),
+            concurrency_lock: RwLock::new(()),
+            trigger_lock: Mutex::new(()),
+            juju_version: tools::detect_juju_version(&*options.juju),
+            juju: options.juju,
+            secret_store: Mutex::new(None),
+            running_actions: Default::default(),
+            platform,
+            hook_mapping: hook_mapping::for_platform(platform, sidecar_containers.clone()),
+            sidecar_containers,
+            container_health: Default::default(),
+            log_forwarders: Default::default(),
+            crash_monitor: Default::default(),
+            service_scripts: Default::default(),
         };
 
+        // Load the secret store so that the secrets will be redacted from the logs
+        daemon
+            .with_secret_store(|_| Ok(()))
+            .context("Could not load secret store")
+            .unwrap_or_else(|e| log::error!("{:?}", e));
+
+        // Write the shell helper library for the host scripts
+        tools::write_lucky_env(&daemon)
+            .context("Could not write shell helper library")
+            .unwrap_or_else(|e| log::error!("{:?}", e));
+
         // Load daemon state
         tools::load_state(&daemon)
             .context("Could not load daemon state from filesystem")
             .unwrap_or_else(|e| log::error!("{:?}", e));
 
+        // Keep using the container engine that the charm was installed with
+        if let Some(kind) = daemon.state.read().unwrap().container_engine {
+            *daemon.container_engine_kind.write().unwrap() = kind;
+        }
+
+        // Sync the container config with the containers declared in the lucky.yaml
+        tools::reconcile_declared_containers(&daemon)
+            .context("Could not reconcile the containers declared in the lucky.yaml")
+            .unwrap_or_else(|e| log::error!("{:?}", e));
+
+        // Reattach to the containers left running by the last daemon and clean up orphans
+        if daemon.docker_enabled() {
+            tools::reattach_containers(&daemon)
+                .context("Could not reattach to existing containers")
+                .unwrap_or_else(|e| log::error!("{:?}", e));
+        }
+
         // Update the Juju status
-        crate::juju::set_status(tools::get_juju_status(&daemon.state.read().unwrap()))
+        daemon
+            .juju
+            .set_status(tools::get_juju_status(&daemon.state.read().unwrap()))
             .unwrap_or_else(|e| {
                 log::warn!("{:?}", e.context("Could not set juju status"));
             });
@@ -131,28 +520,78 @@ impl LuckyDaemon {
         daemon
     }
 
-    /// Gets a handle to the daemon's Docker connection, creating a new one if one doesn't already
-    /// exist.
-    fn get_docker_conn(&self) -> anyhow::Result<Arc<Mutex<Docker>>> {
-        let mut docker_conn = self.docker_conn.lock().unwrap();
+    /// Gets a handle to the daemon's container engine connection, creating a new one if one doesn't
+    /// already exist.
+    fn get_container_engine(&self) -> anyhow::Result<Arc<dyn ContainerEngine>> {
+        let mut engine = self.container_engine.lock().unwrap();
 
         // If we have a connection already, return it
-        if let Some(docker_conn) = &*docker_conn {
-            Ok(docker_conn.clone())
+        if let Some(engine) = &*engine {
+            Ok(engine.clone())
         // If there is no connection
         } else {
-            // Connect to docker
-            log::debug!("Connecting to Docker");
-            let conn = Docker::new();
+            let conn = container_engine::connect(
+                *self.container_engine_kind.read().unwrap(),
+                self.lucky_metadata.podman_user.as_deref(),
+            )?;
+            *engine = Some(conn.clone());
+            Ok(conn)
+        }
+    }
 
-            // Test getting Docker info
-            log::trace!("Docker info: {:?}", crate::rt::block_on(conn.info())?);
+    /// Whether or not containers are run with a container engine such as Docker
+    ///
+    /// Container engines are never used on Kubernetes, where the workload containers are managed by
+    /// Pebble.
+    fn docker_enabled(&self) -> bool {
+        self.lucky_metadata.use_docker && self.platform == Platform::Machine
+    }
 
-            // Return connection
-            let conn = Arc::new(Mutex::new(conn));
-            *docker_conn = Some(conn.clone());
-            Ok(conn)
+    /// Acquire the given concurrency class for the duration of an RPC method call
+    ///
+    /// Returns a guard that will release the class when dropped.
+    fn concurrency_guard(&self, class: ConcurrencyClass) -> ConcurrencyGuard {
+        log::trace!("Acquiring {:?} concurrency class", class);
+        ConcurrencyGuard::acquire(&self.concurrency_lock, class)
+    }
+
+    /// Get the value of a key in the unit key-value store, decrypting it if it is secret
+    fn get_kv_value(
+        &self,
+        state: &DaemonState,
+        namespace: Option<&str>,
+        key: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let value = match state
+            .kv_store(namespace)
+            .and_then(|store| store.get(key))
+            .filter(|_| !state.kv_key_expired(namespace, key))
+        {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        if state.kv_key_secret(namespace, key) {
+            self.with_secret_store(|store| store.decrypt_value(value))
+                .map(Some)
+                .context(format!("Could not decrypt key-value store key: {}", key))
+        } else {
+            Ok(Some((**value).clone()))
+        }
+    }
+
+    /// Run the given function with the unit's secret store, loading it if it has not been loaded
+    fn with_secret_store<F, T>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce(&mut secrets::SecretStore) -> anyhow::Result<T>,
+    {
+        let mut secret_store = self.secret_store.lock().unwrap();
+
+        if secret_store.is_none() {
+            *secret_store = Some(secrets::SecretStore::open(&self.lucky_data_dir)?);
         }
+
+        f(secret_store.as_mut().expect("Secret store not loaded"))
     }
 
     #[allow(clippy::needless_pass_by_value)]
@@ -173,17 +612,71 @@ impl LuckyDaemon {
 
         // Add LUCKY_HOOK environment variable
         environment.insert("LUCKY_HOOK".into(), hook_name.into());
+
+        // Let scripts know which peer store keys have changed if this is a peer relation hook. A
+        // failure to read the peer data shouldn't fail the hook, which may not use the peer store.
+        match tools::update_peer_store(self, hook_name) {
+            Ok(Some(changed_keys)) => {
+                environment.insert("LUCKY_PEER_CHANGED_KEYS".into(), changed_keys.join(" "));
+            }
+            Ok(None) => (),
+            Err(e) => log::error!("{:?}", e.context("Could not update the peer store")),
+        }
+
+        // Get the lucky.yaml hooks to run for this Juju hook
+        let mapped_hook = self.hook_mapping.map_hook(hook_name);
+        environment.extend(mapped_hook.environment);
+
         // Make environment a reference so it can be used in threads
         let environment = &environment;
 
         // Create a thread scope so script threads will be able to use references
         thread_scope(|s| -> anyhow::Result<()> {
-            // Run hook scripts
-            if let Some(hook_scripts) = self.lucky_metadata.hooks.get(hook_name) {
+            // Run hook scripts, including the scripts of the hook patterns that match the hook. A
+            // pattern may match more than one of the mapped hooks, but its scripts only run once.
+            let mut run_keys = HashSet::new();
+            let matching_hooks = mapped_hook
+                .hooks
+                .iter()
+                .flat_map(|hook| {
+                    self.lucky_metadata
+                        .matching_hooks(hook)
+                        .into_iter()
+                        .map(move |(key, scripts)| (hook.as_str(), key, scripts))
+                })
+                .filter(|(_, key, _)| run_keys.insert(*key));
+
+            // The `pre-hook` and `post-hook` scripts run before and after the scripts of every hook
+            let pre_hook = (hook_name, "pre-hook", &self.lucky_metadata.pre_hook);
+            let post_hook = (hook_name, "post-hook", &self.lucky_metadata.post_hook);
+
+            for (hook_name, hook_key, hook_scripts) in std::iter::once(pre_hook)
+                .chain(matching_hooks)
+                .chain(std::iter::once(post_hook))
+            {
+                let hook_id = hook_key_id(hook_key);
+
+                // Run the scripts in the order of their dependencies if they have any
+                if let Some(dependencies) = script_dependencies(hook_scripts)? {
+                    script_graph::run_script_graph(
+                        self,
+                        hook_name,
+                        hook_scripts,
+                        &dependencies,
+                        environment,
+                        &hook_id,
+                    )?;
+                    continue;
+                }
+
                 let mut async_handles = Vec::new();
 
                 // Execute all scripts registered for this hook
                 for (i, hook_script) in hook_scripts.iter().enumerate() {
+                    // Add hook index to the script id to make it unique
+                    // TODO: Fix clugy script id override
+                    let script_id = format!("{}_{}", hook_id, i);
+
                     // Helper to run script
                     macro_rules! run_script {
                         () => {
@@ -192,15 +685,8 @@ impl LuckyDaemon {
                                 hook_name,
                                 hook_script,
                                 environment,
-                                // Add hook index as script_id override to make it unique
-                                // TODO: Fix clugy script id override
-                                Some(&format!("{}_{}", hook_name, i)),
+                                Some(&script_id),
                             )?;
-
-                            // If docker is enabled, update container configuration
-                            if self.lucky_metadata.use_docker {
-                                tools::apply_container_updates(self)?;
-                            }
                         };
                     }
 
@@ -231,173 +717,732 @@ impl LuckyDaemon {
         })
         .expect("Scoped thread paniced")?;
 
+        // Apply the container and host service configuration updates made by the hook's scripts
+        // all at once, so that a container changed by several scripts is only replaced once. This
+        // also makes sure the containers declared in the lucky.yaml are running, that the workload
+        // is added back when a Kubernetes workload container restarts, that templated env vars are
+        // up to date, and that attached storage is mounted, even if no scripts ran.
+        let ran_scripts = mapped_hook
+            .hooks
+            .iter()
+            .any(|x| !self.lucky_metadata.matching_hooks(x).is_empty())
+            || !self.lucky_metadata.pre_hook.is_empty()
+            || !self.lucky_metadata.post_hook.is_empty();
+        if (ran_scripts
+            || !self.lucky_metadata.containers.is_empty()
+            || self.platform == Platform::Kubernetes
+            || tools::get_storage_hook_name(hook_name).is_some()
+            || tools::has_env_templates(&self.state.read().unwrap()))
+            && hook_name != "stop"
+        {
+            tools::apply_workload_updates(self)?;
+        }
+
         // Run post-script hook handlers
         hook_handlers::handle_post_hook(&self, &hook_name).context(format!(
             r#"Error running internal hook handler for hook "{}""#,
             hook_name
         ))?;
 
+        // Correct any drift from the daemon state
+        if hook_name == reconcile::RECONCILE_HOOK {
+            reconcile::reconcile(self).context("Error reconciling the unit")?;
+        }
+
+        // Write any forwarded container logs to the Juju log while we have a Juju context
+        container_logs::flush_forwarded_logs(self);
+
+        // Report any crash looping containers in the Juju status
+        crash_monitor::update_crash_status(self)?;
+
+        // Start or stop the service scripts and report any that have exited in the Juju status
+        service_scripts::update_services(self, Some(hook_name))?;
+
         // Reply empty
         call.reply()?;
 
         Ok(())
     }
-}
 
-impl rpc::VarlinkInterface for LuckyDaemon {
-    /// Stop the Lucky daemon
-    fn stop_daemon(&self, call: &mut dyn rpc::Call_StopDaemon) -> varlink::Result<()> {
-        log::info!("Shutting down server");
-        // Set the stop_listening=true.
-        self.stop_listening.store(true, Ordering::SeqCst);
+    /// Update the configuration of an existing host service
+    fn update_host_service<F>(&self, service_name: &str, f: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut HostServiceInfo),
+    {
+        let mut state = self.state.write().unwrap();
+
+        let service = state.host_services.get_mut(service_name).ok_or_else(|| {
+            anyhow::format_err!(
+                r#"Host service "{}" does not exist. Set its command to create it."#,
+                service_name
+            )
+        })?;
+        service.update(f);
 
-        // Reply and exit
-        call.reply()?;
         Ok(())
     }
 
-    /// Handle the cron tick and run scheduled cron jobs
-    fn cron_tick(
+    /// Start, stop, or restart the given container
+    fn change_container_lifecycle(
         &self,
-        call: &mut dyn rpc::Call_CronTick,
-        juju_context_id: String,
-    ) -> varlink::Result<()> {
-        // Set the Juju context
-        std::env::set_var("JUJU_CONTEXT_ID", &juju_context_id);
+        container_name: Option<&str>,
+        action: &tools::ContainerLifecycleAction,
+    ) -> anyhow::Result<()> {
+        if !self.docker_enabled() && self.platform != Platform::Kubernetes {
+            anyhow::bail!("Docker is not enabled for this charm");
+        }
 
-        log::trace!("Cron tick");
+        let mut state = self.state.write().unwrap();
 
-        // Create environment map
-        let mut environment: HashMap<String, String> = HashMap::new();
-        environment.insert("JUJU_CONTEXT_ID".into(), juju_context_id);
-        // Make environment a reference ( so it can be used in threads )
-        let environment = &environment;
+        // Get the config for the requested container
+        let container = match container_name {
+            Some(container_name) => state.named_containers.get_mut(container_name),
+            None => state.default_container.as_mut(),
+        }
+        .ok_or_else(|| {
+            anyhow::format_err!(
+                r#"Container "{}" does not exist"#,
+                container_name.unwrap_or("default")
+            )
+        })?;
 
-        // Get the last cron tick time and the current time
-        let mut last_cron_tick = self.last_cron_tick.lock().unwrap();
-        let now = Local::now();
+        tools::change_container_lifecycle(self, container_name, container, action)
+    }
 
-        // Create a channel used to transefer our job results from their threads
-        let (job_sender, job_receiver) = unbounded_channel();
-        let job_sender_ref = &job_sender;
+    fn _trigger_action(
+        &self,
+        action_name: &str,
+        environment: &HashMap<String, String>,
+        client_pid: Option<i64>,
+    ) -> anyhow::Result<()> {
+        let action = self
+            .lucky_metadata
+            .actions
+            .get(action_name)
+            .ok_or_else(|| {
+                anyhow::format_err!(
+                    r#"Action "{}" is not defined in the lucky.yaml"#,
+                    action_name
+                )
+            })?;
 
-        // Create a thread scope allowing us to use references inside of the job threads
-        thread_scope(|s| {
-            // Loop through cron jobs and run them if necessary
-            for (job_index, (schedule_str, scripts)) in
-                self.lucky_metadata.cron_jobs.iter().enumerate()
-            {
-                let schedule: cron::Schedule = handle_err!(schedule_str.parse(), call);
+        // Identify the action by the UUID that Juju gives it
+        let action_id = environment
+            .get("JUJU_ACTION_UUID")
+            .cloned()
+            .unwrap_or_else(|| action_name.into());
 
-                // If this job should be run
-                if let Some(date) = schedule.after(&last_cron_tick).next() {
-                    if date < now {
-                        log::info!("Triggering cron job with schedule: {}", schedule_str);
-                        // Spawn thread to run the job
-                        s.spawn(move |ss| {
-                            // For every script in the job
-                            for (script_index, script) in scripts.iter().enumerate() {
-                                let hook_name = "cron";
+        // Create a mutable clone of the recieved environment
+        let mut environment = environment.clone();
 
-                                // helper to send error results over channel
-                                macro_rules! send_if_error {
-                                    ($result:expr) => {
-                                        if let Err(e) = $result {
-                                            job_sender_ref
-                                                .send(Err(e))
-                                                .expect("Channel dropped prematuresly");
-                                            return Ok(());
-                                        }
-                                    };
-                                }
+        // Add the Lucky action environment variables
+        environment.insert("LUCKY_ACTION".into(), action_name.into());
+        environment.insert(tools::ACTION_ID_ENV_VAR.into(), action_id.clone());
+        // Make environment a reference so it can be used in threads
+        let environment = &environment;
 
-                                // helper to run the script
-                                macro_rules! run_script {
-                                    () => {
-                                        let run_result = tools::run_charm_script(
-                                            &self,
-                                            hook_name,
-                                            &script,
-                                            environment,
-                                            // Add job and script index to script id override to
-                                            // make sure script id is unique
-                                            Some(&format!(
-                                                "{}_{}_{}",
-                                                hook_name, job_index, script_index
-                                            )),
-                                        );
+        // Register the action so that it can be cancelled
+        let cancel_handle = CancelHandle::default();
+        self.running_actions
+            .lock()
+            .unwrap()
+            .insert(action_id.clone(), cancel_handle.clone());
+
+        // Cancel the action if it times out or if Juju cancels it
+        let done = Arc::new(AtomicBool::new(false));
+        let watchdog = tools::spawn_action_watchdog(
+            cancel_handle.clone(),
+            done.clone(),
+            action.timeout.map(std::time::Duration::from_secs),
+            client_pid,
+        );
 
-                                        send_if_error!(run_result);
+        // Create a thread scope so script threads will be able to use references
+        let result = thread_scope(|s| -> anyhow::Result<()> {
+            // Run the scripts in the order of their dependencies if they have any
+            if let Some(dependencies) = script_dependencies(&action.scripts)? {
+                script_graph::run_script_graph(
+                    self,
+                    action_name,
+                    &action.scripts,
+                    &dependencies,
+                    environment,
+                    &format!("action_{}", action_name),
+                )?;
+                return tools::apply_workload_updates(self);
+            }
 
-                                        // If docker is enabled, update container configuration
-                                        if self.lucky_metadata.use_docker {
-                                            send_if_error!(tools::apply_container_updates(self));
-                                        }
-                                    };
-                                }
+            let mut async_handles = Vec::new();
 
-                                // If the script is asynchronous
-                                if script.is_async {
-                                    // Spawn it in another thread
-                                    ss.spawn(move |_| {
-                                        log::trace!(
-                                            "Running async cron job script for schedule[{}]: {:#?}",
-                                            schedule_str,
-                                            script
-                                        );
-                                        run_script!();
-                                        Ok::<(), Void>(())
-                                    });
+            // Execute all scripts registered for this action
+            for (i, action_script) in action.scripts.iter().enumerate() {
+                // Don't start any more scripts if the action has been cancelled
+                if cancel_handle.cancel_reason().is_some() {
+                    break;
+                }
 
-                                // If the script is synchronous
-                                } else {
-                                    log::trace!(
-                                        "Running cron job script for schedule[{}]: {:#?}",
-                                        schedule_str,
-                                        script
-                                    );
-                                    // Run it in place
-                                    run_script!();
-                                }
-                            }
+                // Helper to run script
+                macro_rules! run_script {
+                    () => {
+                        tools::run_charm_script(
+                            &self,
+                            action_name,
+                            action_script,
+                            environment,
+                            Some(&format!("action_{}_{}", action_name, i)),
+                        )?;
+                    };
+                }
 
-                            Ok::<(), Void>(())
-                        });
-                    }
+                // If the script is asynchronous
+                if action_script.is_async {
+                    log::trace!("Running async action script: {:#?}", action_script);
+                    // Spawn it in another thread
+                    async_handles.push(s.spawn(move |_| -> anyhow::Result<()> {
+                        run_script!();
+                        Ok(())
+                    }));
+
+                // If the script is synchronous
+                } else {
+                    log::trace!("Running action script: {:#?}", action_script);
+                    // Run it in place
+                    run_script!();
                 }
             }
 
-            Ok(())
+            // Join and handle any errors from async scripts
+            for async_handle in async_handles {
+                async_handle.join().expect("Scoped thread paniced")?;
+            }
+
+            // Apply the container and host service configuration updates made by the action's
+            // scripts all at once
+            tools::apply_workload_updates(self)
         })
-        .expect("Panic in scoped thread")?;
+        .expect("Scoped thread paniced");
 
-        // Close the channel
-        drop(job_sender);
+        // Stop the watchdog and unregister the action
+        done.store(true, Ordering::SeqCst);
+        watchdog.join().expect("Action watchdog thread paniced");
+        self.running_actions.lock().unwrap().remove(&action_id);
 
-        // Loop through job results
-        for job_result in job_receiver.iter() {
-            // Handle any errors
-            handle_err!(job_result, call);
+        // Report the cancellation instead of the error from the terminated script
+        if let Some(reason) = cancel_handle.cancel_reason() {
+            anyhow::bail!(r#"Action "{}" was cancelled: {}"#, action_name, reason);
         }
 
-        // Update the last cron tick
-        *last_cron_tick = Local::now();
+        result
+    }
 
-        // Unset the Juju context as it will be invalid when the cron tick command exits
-        std::env::remove_var("JUJU_CONTEXT_ID");
+    fn _emit(&self, event_name: &str, payload: Option<&str>, depth: usize) -> anyhow::Result<()> {
+        let scripts = match self.lucky_metadata.events.get(event_name) {
+            Some(scripts) => scripts,
+            None => {
+                log::debug!(r#"No scripts are subscribed to event "{}""#, event_name);
+                return Ok(());
+            }
+        };
 
-        // Reply empty
-        call.reply()
+        // Add the event environment variables
+        let mut environment = HashMap::new();
+        environment.insert("LUCKY_EVENT".into(), event_name.into());
+        environment.insert("LUCKY_EVENT_DEPTH".into(), depth.to_string());
+        if let Some(payload) = payload {
+            environment.insert("LUCKY_EVENT_PAYLOAD".into(), payload.into());
+        }
+
+        self._run_scripts(
+            event_name,
+            scripts,
+            &environment,
+            &format!("event_{}", event_name),
+        )
     }
 
-    /// Trigger a Juju hook
-    fn trigger_hook(
+    /// Set a blocked status for each cron job that failed, keyed by job index, and clear the
+    /// status of the jobs that failed before but have now succeeded
+    fn report_cron_job_results(
         &self,
-        call: &mut dyn rpc::Call_TriggerHook,
-        hook_name: String,
-        environment: HashMap<String, String>,
-    ) -> varlink::Result<()> {
-        // Set the hook environment variables
+        job_results: BTreeMap<usize, anyhow::Result<()>>,
+    ) -> anyhow::Result<()> {
+        let mut state = self.state.write().unwrap();
+        for (job_index, result) in job_results {
+            let script_id = format!("cron_{}", job_index);
+            let schedule = self
+                .lucky_metadata
+                .cron_jobs
+                .get_index(job_index)
+                .map_or("", |(schedule, _)| schedule.as_str());
+
+            match result {
+                Err(e) => {
+                    log::error!("Cron job with schedule {} failed: {:?}", schedule, e);
+                    tools::set_script_status(
+                        &*self.juju,
+                        &mut state,
+                        &script_id,
+                        ScriptStatus {
+                            state: ScriptState::Blocked,
+                            message: Some(format!("Cron job {} failed", schedule)),
+                        },
+                    )?;
+                }
+                Ok(()) => {
+                    let failed_before = state
+                        .script_statuses
+                        .get(&script_id)
+                        .map_or(false, |x| x.state == ScriptState::Blocked);
+                    if failed_before {
+                        tools::set_script_status(
+                            &*self.juju,
+                            &mut state,
+                            &script_id,
+                            ScriptStatus {
+                                state: ScriptState::Active,
+                                message: None,
+                            },
+                        )?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove the keys that have expired from the unit key-value store, running the `kv-watches`
+    /// scripts of the removed keys if `kv-expiry-triggers-watches` is enabled
+    ///
+    /// `depth` is the event depth of the script that caused the keys to be expired, if any.
+    fn expire_kv_keys(&self, depth: usize) -> anyhow::Result<()> {
+        let expired = {
+            let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+            let expired = self.state.write().unwrap().remove_expired_kv();
+            if expired.is_empty() {
+                return Ok(());
+            }
+            tools::flush_state(self)?;
+
+            expired
+        };
+
+        if self.lucky_metadata.kv_expiry_triggers_watches {
+            for (namespace, keys) in expired {
+                let namespace = Some(namespace.as_str()).filter(|x| *x != GLOBAL_KV_NAMESPACE);
+                self.run_kv_watches(namespace, &keys, depth)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the `kv-watches` scripts of the keys that have changed in the unit key-value store
+    ///
+    /// `depth` is the event depth of the script that changed the keys, which is `0` for scripts
+    /// that weren't run for an event or a kv watch.
+    fn run_kv_watches(
+        &self,
+        namespace: Option<&str>,
+        changed_keys: &[String],
+        depth: usize,
+    ) -> anyhow::Result<()> {
+        if changed_keys.is_empty() {
+            return Ok(());
+        }
+
+        // Keep watch scripts that change the keys that they watch from looping forever
+        if depth >= tools::MAX_EVENT_DEPTH {
+            anyhow::bail!(
+                "kv watch scripts changed the keys that they watch more than {} levels deep",
+                tools::MAX_EVENT_DEPTH
+            );
+        }
+
+        self._run_kv_watches(namespace, changed_keys, depth + 1)
+    }
+
+    fn _run_kv_watches(
+        &self,
+        namespace: Option<&str>,
+        changed_keys: &[String],
+        depth: usize,
+    ) -> anyhow::Result<()> {
+        for (watch_index, (pattern, scripts)) in self.lucky_metadata.kv_watches.iter().enumerate() {
+            let matched_keys: Vec<&str> = changed_keys
+                .iter()
+                .filter(|key| kv_key_matches(pattern, key))
+                .map(String::as_str)
+                .collect();
+            if matched_keys.is_empty() {
+                continue;
+            }
+
+            log::info!("Triggering kv watch: {}", pattern);
+
+            let _span = trace::Span::start(&format!("kv watch {}", pattern))
+                .with_attr("lucky.kv_watch", pattern);
+
+            // Add the kv watch environment variables
+            let mut environment = HashMap::new();
+            environment.insert("LUCKY_KV_WATCH".into(), pattern.clone());
+            environment.insert("LUCKY_KV_CHANGED_KEYS".into(), matched_keys.join(" "));
+            environment.insert("LUCKY_EVENT_DEPTH".into(), depth.to_string());
+            environment.insert(
+                "LUCKY_KV_NAMESPACE".into(),
+                namespace.unwrap_or(GLOBAL_KV_NAMESPACE).into(),
+            );
+
+            self._run_scripts(
+                "kv-watch",
+                scripts,
+                &environment,
+                &format!("kv_watch_{}", watch_index),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Run a list of scripts for an event or kv watch, waiting for the async ones to finish
+    fn _run_scripts(
+        &self,
+        hook_name: &str,
+        scripts: &[CharmScript],
+        environment: &HashMap<String, String>,
+        script_id_prefix: &str,
+    ) -> anyhow::Result<()> {
+        // Run the scripts in the order of their dependencies if they have any
+        if let Some(dependencies) = script_dependencies(scripts)? {
+            return script_graph::run_script_graph(
+                self,
+                hook_name,
+                scripts,
+                &dependencies,
+                environment,
+                script_id_prefix,
+            );
+        }
+
+        // Create a thread scope so script threads will be able to use references
+        thread_scope(|s| -> anyhow::Result<()> {
+            let mut async_handles = Vec::new();
+
+            // Execute all of the scripts
+            for (i, script) in scripts.iter().enumerate() {
+                // Helper to run script
+                macro_rules! run_script {
+                    () => {
+                        tools::run_charm_script(
+                            &self,
+                            hook_name,
+                            script,
+                            environment,
+                            Some(&format!("{}_{}", script_id_prefix, i)),
+                        )?;
+                    };
+                }
+
+                // If the script is asynchronous
+                if script.is_async {
+                    log::trace!("Running async {} script: {:#?}", hook_name, script);
+                    // Spawn it in another thread
+                    async_handles.push(s.spawn(move |_| -> anyhow::Result<()> {
+                        run_script!();
+                        Ok(())
+                    }));
+
+                // If the script is synchronous
+                } else {
+                    log::trace!("Running {} script: {:#?}", hook_name, script);
+                    // Run it in place
+                    run_script!();
+                }
+            }
+
+            // Join and handle any errors from async scripts
+            for async_handle in async_handles {
+                async_handle.join().expect("Scoped thread paniced")?;
+            }
+
+            Ok(())
+        })
+        .expect("Scoped thread paniced")
+    }
+}
+
+impl rpc::VarlinkInterface for LuckyDaemon {
+    /// Stop the Lucky daemon
+    fn stop_daemon(&self, call: &mut dyn rpc::Call_StopDaemon) -> varlink::Result<()> {
+        log::info!("Shutting down server");
+        // Set the stop_listening=true.
+        self.stop_listening.store(true, Ordering::SeqCst);
+
+        // Reply and exit
+        call.reply()?;
+        Ok(())
+    }
+
+    /// Clear selected parts of the daemon state
+    fn daemon_reset(
+        &self,
+        call: &mut dyn rpc::Call_DaemonReset,
+        statuses: bool,
+        queue: bool,
+        containers: bool,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let mut cleared = vec![];
+
+        // Clear the pending cron jobs by pretending that the cron tick just ran
+        if queue {
+            log::warn!("Resetting the pending cron job queue");
+            *self.last_cron_tick.lock().unwrap() = Local::now();
+            cleared.push("queue");
+        }
+
+        let mut state = self.state.write().unwrap();
+
+        // Forget all container configuration
+        if containers {
+            log::warn!("Resetting the container configuration");
+            state.default_container = None;
+            state.named_containers.clear();
+            cleared.push("containers");
+        }
+
+        // Clear script statuses
+        if statuses {
+            log::warn!("Resetting the script statuses");
+            let script_ids: Vec<String> = state.script_statuses.keys().cloned().collect();
+            for script_id in script_ids {
+                state.remove_script_status(&script_id);
+            }
+            handle_err!(self.juju.set_status(tools::get_juju_status(&state)), call);
+            cleared.push("statuses");
+        }
+
+        drop(state);
+
+        // Record the reset in the audit log
+        handle_err!(
+            tools::write_audit_entry(self, &format!("Reset daemon state: {}", cleared.join(", "))),
+            call
+        );
+
+        // Persist the updated state
+        handle_err!(tools::flush_state(self), call);
+
+        // Reply empty
+        call.reply()
+    }
+
+    /// Handle the cron tick and run scheduled cron jobs
+    fn cron_tick(
+        &self,
+        call: &mut dyn rpc::Call_CronTick,
+        juju_context_id: String,
+    ) -> varlink::Result<()> {
+        let _trigger = self.trigger_lock.lock().unwrap();
+
+        // Set the Juju context
+        std::env::set_var("JUJU_CONTEXT_ID", &juju_context_id);
+
+        log::trace!("Cron tick");
+
+        let span = trace::Span::start_root("cron tick");
+
+        // Create environment map
+        let mut environment: HashMap<String, String> = HashMap::new();
+        environment.insert("JUJU_CONTEXT_ID".into(), juju_context_id);
+        // Make environment a reference ( so it can be used in threads )
+        let environment = &environment;
+
+        // Remove the key-value store keys that have expired since the last tick
+        handle_err!(self.expire_kv_keys(0), call);
+
+        // Get the last cron tick time and the current time
+        let mut last_cron_tick = self.last_cron_tick.lock().unwrap();
+        let now = Local::now();
+
+        // Create a channel used to transefer our job results from their threads
+        let (job_sender, job_receiver) = unbounded_channel();
+        let job_sender_ref = &job_sender;
+
+        // Create a thread scope allowing us to use references inside of the job threads
+        thread_scope(|s| {
+            // Loop through cron jobs and run them if necessary
+            for (job_index, (schedule_str, scripts)) in
+                self.lucky_metadata.cron_jobs.iter().enumerate()
+            {
+                let schedule: JobSchedule = handle_err!(schedule_str.parse(), call);
+
+                // If this job should be run
+                if let Some(date) = schedule.after(&last_cron_tick) {
+                    if date < now {
+                        log::info!("Triggering cron job with schedule: {}", schedule_str);
+                        // Spawn thread to run the job
+                        s.spawn(move |ss| {
+                            let hook_name = "cron";
+
+                            // Run the scripts in the order of their dependencies if they have any
+                            let dependencies = match script_dependencies(scripts) {
+                                Ok(dependencies) => dependencies,
+                                Err(e) => {
+                                    job_sender_ref
+                                        .send((job_index, Err(e.into())))
+                                        .expect("Channel dropped prematuresly");
+                                    return Ok(());
+                                }
+                            };
+                            if let Some(dependencies) = dependencies {
+                                let result = script_graph::run_script_graph(
+                                    &self,
+                                    hook_name,
+                                    scripts,
+                                    &dependencies,
+                                    environment,
+                                    &format!("{}_{}", hook_name, job_index),
+                                );
+                                job_sender_ref
+                                    .send((job_index, result))
+                                    .expect("Channel dropped prematuresly");
+                                return Ok(());
+                            }
+
+                            // For every script in the job
+                            for (script_index, script) in scripts.iter().enumerate() {
+                                // helper to send error results over channel
+                                macro_rules! send_if_error {
+                                    ($result:expr) => {
+                                        if let Err(e) = $result {
+                                            job_sender_ref
+                                                .send((job_index, Err(e)))
+                                                .expect("Channel dropped prematuresly");
+                                            return Ok(());
+                                        }
+                                    };
+                                }
+
+                                // helper to run the script
+                                macro_rules! run_script {
+                                    () => {
+                                        let run_result = tools::run_charm_script(
+                                            &self,
+                                            hook_name,
+                                            &script,
+                                            environment,
+                                            // Add job and script index to script id override to
+                                            // make sure script id is unique
+                                            Some(&format!(
+                                                "{}_{}_{}",
+                                                hook_name, job_index, script_index
+                                            )),
+                                        );
+
+                                        send_if_error!(run_result);
+                                    };
+                                }
+
+                                // If the script is asynchronous
+                                if script.is_async {
+                                    // Spawn it in another thread
+                                    ss.spawn(move |_| {
+                                        log::trace!(
+                                            "Running async cron job script for schedule[{}]: {:#?}",
+                                            schedule_str,
+                                            script
+                                        );
+                                        run_script!();
+                                        Ok::<(), Void>(())
+                                    });
+
+                                // If the script is synchronous
+                                } else {
+                                    log::trace!(
+                                        "Running cron job script for schedule[{}]: {:#?}",
+                                        schedule_str,
+                                        script
+                                    );
+                                    // Run it in place
+                                    run_script!();
+                                }
+                            }
+
+                            job_sender_ref
+                                .send((job_index, Ok(())))
+                                .expect("Channel dropped prematuresly");
+                            Ok::<(), Void>(())
+                        });
+                    }
+                }
+            }
+
+            Ok(())
+        })
+        .expect("Panic in scoped thread")?;
+
+        // Close the channel
+        drop(job_sender);
+
+        // Collect the job results, keeping the first error of each job
+        let mut job_results: BTreeMap<usize, anyhow::Result<()>> = BTreeMap::new();
+        for (job_index, job_result) in job_receiver.iter() {
+            let result = job_results.entry(job_index).or_insert(Ok(()));
+            if result.is_ok() {
+                *result = job_result;
+            }
+        }
+
+        // Report failed jobs in the script statuses instead of failing the tick, so that one broken
+        // job doesn't stop the others or the rest of the tick from running
+        handle_err!(self.report_cron_job_results(job_results), call);
+
+        // Apply the container and host service configuration updates made by the jobs all at once
+        handle_err!(tools::apply_workload_updates(self), call);
+
+        // Publish the key-value store values changed by the jobs to the synchronized relations
+        handle_err!(relation_kv::publish_relation_data(self, None), call);
+
+        // Run any container health checks that are due
+        handle_err!(health::run_health_checks(self), call);
+
+        // Write any forwarded container logs to the Juju log while we have a Juju context
+        container_logs::flush_forwarded_logs(self);
+
+        // Report any crash looping containers in the Juju status
+        handle_err!(crash_monitor::update_crash_status(self), call);
+
+        // Restart the service scripts if the daemon has restarted and report any that have exited
+        // in the Juju status
+        handle_err!(service_scripts::update_services(self, None), call);
+
+        // Update the last cron tick
+        *last_cron_tick = Local::now();
+
+        // Export the cron tick's traces
+        drop(span);
+        trace::flush();
+
+        // Unset the Juju context as it will be invalid when the cron tick command exits
+        std::env::remove_var("JUJU_CONTEXT_ID");
+
+        // Reply empty
+        call.reply()
+    }
+
+    /// Trigger a Juju hook
+    fn trigger_hook(
+        &self,
+        call: &mut dyn rpc::Call_TriggerHook,
+        hook_name: String,
+        environment: HashMap<String, String>,
+    ) -> varlink::Result<()> {
+        let _trigger = self.trigger_lock.lock().unwrap();
+
+        // Set the hook environment variables
         for (var, value) in &environment {
             std::env::set_var(var, value);
         }
@@ -405,86 +1450,607 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         log::info!("Triggering hook: {}", hook_name);
 
         // Trigger hook
-        handle_err!(self._trigger_hook(call, &hook_name, &environment), call);
+        let mut span = trace::Span::start_root(&format!("hook {}", hook_name))
+            .with_attr("juju.hook", &hook_name);
+        let result = self._trigger_hook(call, &hook_name, &environment);
+        span.record_result(&result);
+        drop(span);
+
+        // Export the hook's traces
+        trace::flush();
+
+        // Save the last hook so that it can be found by `lucky dev watch`, even if it failed
+        self.state.write().unwrap().last_hook = Some(hook_name.clone());
+        let flushed = tools::flush_state(self);
+
+        handle_err!(result, call);
+        handle_err!(flushed, call);
+
+        // Unset the hook environment variables as they will be invalid when the hook exits
+        for var in environment.keys() {
+            std::env::remove_var(var);
+        }
+
+        log::info!("Done triggering hook: {}", hook_name);
+
+        Ok(())
+    }
+
+    /// Trigger a Juju action
+    fn trigger_action(
+        &self,
+        call: &mut dyn rpc::Call_TriggerAction,
+        action_name: String,
+        environment: HashMap<String, String>,
+        client_pid: Option<i64>,
+    ) -> varlink::Result<()> {
+        let _trigger = self.trigger_lock.lock().unwrap();
+
+        // Set the action environment variables
+        for (var, value) in &environment {
+            std::env::set_var(var, value);
+        }
+
+        log::info!("Triggering action: {}", action_name);
+
+        // Trigger action
+        let mut span = trace::Span::start_root(&format!("action {}", action_name))
+            .with_attr("juju.action", &action_name);
+        let result = self._trigger_action(&action_name, &environment, client_pid);
+        span.record_result(&result);
+        drop(span);
+
+        // Export the action's traces
+        trace::flush();
+
+        // Let Juju know why the action failed
+        if let Err(e) = &result {
+            self.juju
+                .action_fail(&crate::log::redact(&format!("{:#}", e)))
+                .unwrap_or_else(|e| {
+                    log::warn!("{:?}", e.context("Could not set action failure message"));
+                });
+        }
+
+        handle_err!(result, call);
+
+        // Unset the action environment variables as they will be invalid when the action exits
+        for var in environment.keys() {
+            std::env::remove_var(var);
+        }
+
+        log::info!("Done triggering action: {}", action_name);
+
+        call.reply()
+    }
+
+    /// Emit a custom event
+    fn emit(
+        &self,
+        call: &mut dyn rpc::Call_Emit,
+        event_name: String,
+        payload: Option<String>,
+        event_depth: Option<i64>,
+    ) -> varlink::Result<()> {
+        if event_name.is_empty() {
+            return call.reply_error("The event name cannot be empty".into());
+        }
+
+        // Keep events that emit each other from looping forever
+        let depth = tools::event_depth(event_depth);
+        if depth >= tools::MAX_EVENT_DEPTH {
+            return call.reply_error(format!(
+                r#"Could not emit event "{}": events were emitted more than {} levels deep"#,
+                event_name,
+                tools::MAX_EVENT_DEPTH
+            ));
+        }
+
+        log::info!("Emitting event: {}", event_name);
+
+        let mut span = trace::Span::start(&format!("event {}", event_name))
+            .with_attr("lucky.event", &event_name);
+        let result = self._emit(&event_name, payload.as_deref(), depth + 1);
+        span.record_result(&result);
+        drop(span);
+
+        handle_err!(result, call);
+
+        log::info!("Done emitting event: {}", event_name);
+
+        call.reply()
+    }
+
+    /// Cancel a running action
+    fn cancel_action(
+        &self,
+        call: &mut dyn rpc::Call_CancelAction,
+        action_id: String,
+    ) -> varlink::Result<()> {
+        let cancel_handle = self
+            .running_actions
+            .lock()
+            .unwrap()
+            .get(&action_id)
+            .cloned();
+
+        if let Some(cancel_handle) = &cancel_handle {
+            cancel_handle.cancel("The action was cancelled by Juju");
+        } else {
+            log::debug!("Not cancelling action {}: action is not running", action_id);
+        }
+
+        call.reply(cancel_handle.is_some())
+    }
+
+    /// Set a script's status
+    fn set_status(
+        &self,
+        call: &mut dyn rpc::Call_SetStatus,
+        script_id: String,
+        status: rpc::ScriptStatus,
+        transient_for: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        // Add status to script statuses
+        let status: ScriptStatus = status.into();
+        let mut state = self.state.write().unwrap();
+
+        // Keep track of the script that clears the status if it is transient
+        match transient_for {
+            Some(owner) => state.transient_statuses.insert(script_id.clone(), owner),
+            None => state.transient_statuses.remove(&script_id),
+        };
+
+        handle_err!(
+            tools::set_script_status(&*self.juju, &mut state, &script_id, status),
+            call
+        );
+
+        // Reply
+        call.reply()
+    }
+
+    fn lock_acquire(
+        &self,
+        call: &mut dyn rpc::Call_LockAcquire,
+        name: String,
+        holder: String,
+        wait: Option<i64>,
+    ) -> varlink::Result<()> {
+        let wait = Duration::from_secs(wait.and_then(|x| u64::try_from(x).ok()).unwrap_or(0));
+        let waiting = Instant::now();
+
+        loop {
+            // The concurrency class is only held while the lock is checked so that the script that
+            // holds the lock can release it while we wait
+            {
+                let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+                let mut state = self.state.write().unwrap();
+
+                let lock_holder = state
+                    .locks
+                    .entry(name.clone())
+                    .or_insert_with(|| holder.clone());
+                if *lock_holder == holder {
+                    log::debug!("Script run {} acquired lock {}", holder, name);
+                    return call.reply(true);
+                }
+            }
+
+            if waiting.elapsed() >= wait {
+                return call.reply(false);
+            }
+            std::thread::sleep(LOCK_WAIT_INTERVAL);
+        }
+    }
+
+    fn lock_release(
+        &self,
+        call: &mut dyn rpc::Call_LockRelease,
+        name: String,
+        holder: String,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let mut state = self.state.write().unwrap();
+        match state.locks.get(&name).cloned() {
+            Some(lock_holder) if lock_holder == holder => {
+                log::debug!("Script run {} released lock {}", holder, name);
+                state.locks.remove(&name);
+            }
+            Some(lock_holder) => {
+                let e = format!("Lock {} is held by script run {}", name, lock_holder);
+                log::error!("{}", e);
+                return call.reply_error(e);
+            }
+            // Releasing a lock that isn't held does nothing
+            None => (),
+        }
+
+        call.reply()
+    }
+
+    fn status_history(
+        &self,
+        call: &mut dyn rpc::Call_StatusHistory,
+        script_id: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        let state = self.state.read().unwrap();
+
+        // Merge the histories of the scripts into one timeline
+        let mut transitions: Vec<rpc::StatusTransition> = state
+            .status_history
+            .iter()
+            .filter(|(id, _)| script_id.as_ref().map_or(true, |x| x == *id))
+            .flat_map(|(id, history)| {
+                history.iter().map(move |transition| rpc::StatusTransition {
+                    script_id: id.clone(),
+                    time: transition.time,
+                    old: transition.old.clone().map(Into::into),
+                    new: transition.new.clone().map(Into::into),
+                })
+            })
+            .collect();
+        transitions.sort_by_key(|x| x.time);
+
+        call.reply(transitions)
+    }
+
+    // The run numbers always fit in an i64 because they are counted up from 1
+    #[allow(clippy::cast_possible_wrap)]
+    fn script_logs(
+        &self,
+        call: &mut dyn rpc::Call_ScriptLogs,
+        script_id: String,
+        run: Option<i64>,
+    ) -> varlink::Result<()> {
+        // Reading the logs doesn't take a concurrency class because it doesn't touch the daemon
+        // state
+
+        let run: Option<u64> = handle_err!(
+            run.map(|x| x.try_into().context("Invalid run number"))
+                .transpose(),
+            call
+        );
+        let (run, runs, log) =
+            handle_err!(script_logs::read_script_log(self, &script_id, run), call);
+
+        call.reply(
+            run as i64,
+            runs.into_iter().map(|x| x as i64).collect(),
+            log,
+        )
+    }
+
+    /// Get a value in the unit local key-value store
+    fn unit_kv_get(
+        &self,
+        call: &mut dyn rpc::Call_UnitKvGet,
+        key: String,
+        namespace: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        // Get with key
+        let state = self.state.read().unwrap();
+        let value = handle_err!(self.get_kv_value(&state, namespace.as_deref(), &key), call);
+
+        // Reply with value
+        call.reply(value)
+    }
+
+    /// Set a value in the unit local key-value store
+    fn unit_kv_set(
+        &self,
+        call: &mut dyn rpc::Call_UnitKvSet,
+        data: HashMap<String, Option<String>>,
+        namespace: Option<String>,
+        ttl: Option<i64>,
+        secret: bool,
+        event_depth: Option<i64>,
+    ) -> varlink::Result<()> {
+        if ttl.map_or(false, |x| x <= 0) {
+            return call.reply_error("The TTL must be a positive number of seconds".into());
+        }
+        if ttl.map_or(false, |x| Utc::now().timestamp().checked_add(x).is_none()) {
+            return call.reply_error("The TTL is too long".into());
+        }
+        let depth = tools::event_depth(event_depth);
+
+        // Remove the keys that have expired first so that setting them again counts as a change
+        handle_err!(self.expire_kv_keys(depth), call);
+
+        let mut changed_keys = Vec::new();
+        {
+            let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+            let mut state = self.state.write().unwrap();
+            let namespace = namespace.as_deref();
+
+            for (key, value) in data {
+                // If a value has been provided
+                if let Some(value) = value {
+                    if secret {
+                        crate::log::add_redacted_value(&value);
+                        log::debug!("Key-Value set secret: {}", key);
+                    } else {
+                        log::debug!("Key-Value set: {} = {}", key, value);
+                    }
+                    let old_value = handle_err!(self.get_kv_value(&state, namespace, &key), call);
+                    if old_value.as_ref() != Some(&value) {
+                        changed_keys.push(key.clone());
+                    }
+
+                    // Encrypt secret values
+                    let value = if secret {
+                        handle_err!(
+                            self.with_secret_store(|store| store.encrypt_value(&value)),
+                            call
+                        )
+                    } else {
+                        value
+                    };
+
+                    // Set key to value
+                    state
+                        .kv_store_mut(namespace)
+                        .insert(key.clone(), value.into());
+                    state.set_kv_ttl(namespace, &key, ttl);
+                    state.set_kv_secret(namespace, &key, secret);
+                } else {
+                    log::debug!("Key-Value delete: {}", key);
+                    // Erase key
+                    if state.kv_store_mut(namespace).remove(&key).is_some() {
+                        changed_keys.push(key);
+                    }
+                }
+            }
+            state.prune_kv_namespaces();
+            drop(state);
+
+            // Save the store right away so that the values aren't lost if the daemon crashes
+            handle_err!(tools::flush_state(self), call);
+        }
+        changed_keys.sort();
+
+        // Run the scripts watching the changed keys once the write is done so that they can read
+        // the new values
+        handle_err!(
+            self.run_kv_watches(namespace.as_deref(), &changed_keys, depth),
+            call
+        );
+
+        // Reply empty
+        call.reply()
+    }
+
+    /// Delete keys from the unit local key-value store
+    fn unit_kv_delete(
+        &self,
+        call: &mut dyn rpc::Call_UnitKvDelete,
+        keys: Vec<String>,
+        namespace: Option<String>,
+        event_depth: Option<i64>,
+    ) -> varlink::Result<()> {
+        let depth = tools::event_depth(event_depth);
+
+        // Remove the keys that have expired first so that deleting them doesn't count as a change
+        handle_err!(self.expire_kv_keys(depth), call);
+
+        let mut changed_keys = Vec::new();
+        {
+            let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+            let mut state = self.state.write().unwrap();
+            let store = state.kv_store_mut(namespace.as_deref());
+            for key in keys {
+                log::debug!("Key-Value delete: {}", key);
+                if store.remove(&key).is_some() {
+                    changed_keys.push(key);
+                }
+            }
+            state.prune_kv_namespaces();
+            drop(state);
+
+            // Save the store right away so that the deleted values don't come back if the daemon
+            // crashes
+            handle_err!(tools::flush_state(self), call);
+        }
+        changed_keys.sort();
+        changed_keys.dedup();
+
+        // Run the scripts watching the deleted keys
+        handle_err!(
+            self.run_kv_watches(namespace.as_deref(), &changed_keys, depth),
+            call
+        );
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn unit_kv_get_all(
+        &self,
+        call: &mut dyn rpc::Call_UnitKvGetAll,
+        namespace: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        let state = self.state.read().unwrap();
 
-        // Unset the hook environment variables as they will be invalid when the hook exits
-        for var in environment.keys() {
-            std::env::remove_var(var);
-        }
+        // Reply with pairs
+        call.reply(
+            state
+                .kv_store(namespace.as_deref())
+                .into_iter()
+                .flatten()
+                .filter(|(k, _)| !state.kv_key_expired(namespace.as_deref(), k))
+                .map(|(k, v)| rpc::UnitKvGetAll_Reply_pairs {
+                    key: k.clone(),
+                    // Never send the values of secret keys in bulk
+                    value: if state.kv_key_secret(namespace.as_deref(), k) {
+                        crate::log::REDACTED_PLACEHOLDER.into()
+                    } else {
+                        v.clone().into_inner()
+                    },
+                })
+                .collect(),
+        )
+    }
 
-        log::info!("Done triggering hook: {}", hook_name);
+    /// Get the key-value pairs of every namespace of the unit local key-value store
+    fn unit_kv_export(&self, call: &mut dyn rpc::Call_UnitKvExport) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
 
-        Ok(())
+        call.reply(self.state.read().unwrap().export_kv())
     }
 
-    /// Set a script's status
-    fn set_status(
+    /// Load key-value pairs into the unit local key-value store
+    fn unit_kv_import(
         &self,
-        call: &mut dyn rpc::Call_SetStatus,
-        script_id: String,
-        status: rpc::ScriptStatus,
+        call: &mut dyn rpc::Call_UnitKvImport,
+        namespaces: HashMap<String, HashMap<String, String>>,
+        replace: bool,
+        event_depth: Option<i64>,
     ) -> varlink::Result<()> {
-        // Add status to script statuses
-        let status: ScriptStatus = status.into();
+        let depth = tools::event_depth(event_depth);
 
-        handle_err!(
-            tools::set_script_status(&mut self.state.write().unwrap(), &script_id, status),
-            call
-        );
+        let (old, new) = {
+            let _guard = self.concurrency_guard(ConcurrencyClass::Write);
 
-        // Reply
+            let mut state = self.state.write().unwrap();
+            let old = state.export_kv();
+
+            if replace {
+                log::debug!("Key-Value clear");
+                state.kv.clear();
+                state.kv_namespaces.clear();
+                state.kv_expirations.clear();
+                state.kv_secrets.clear();
+            }
+            for (namespace, pairs) in namespaces {
+                let namespace = Some(namespace).filter(|x| x != GLOBAL_KV_NAMESPACE);
+                for (key, value) in pairs {
+                    log::debug!("Key-Value set: {} = {}", key, value);
+                    state.set_kv_ttl(namespace.as_deref(), &key, None);
+                    state.set_kv_secret(namespace.as_deref(), &key, false);
+                    state
+                        .kv_store_mut(namespace.as_deref())
+                        .insert(key, value.into());
+                }
+            }
+            state.prune_kv_namespaces();
+            let new = state.export_kv();
+            drop(state);
+
+            // Save the store right away so that the values aren't lost if the daemon crashes
+            handle_err!(tools::flush_state(self), call);
+
+            (old, new)
+        };
+
+        // Run the scripts watching the keys that were changed or deleted in each namespace
+        let empty = HashMap::new();
+        let namespace_names: HashSet<&String> = old.keys().chain(new.keys()).collect();
+        for namespace in namespace_names {
+            let old_pairs = old.get(namespace).unwrap_or(&empty);
+            let new_pairs = new.get(namespace).unwrap_or(&empty);
+            let mut changed_keys: Vec<String> = old_pairs
+                .keys()
+                .chain(new_pairs.keys())
+                .filter(|key| old_pairs.get(*key) != new_pairs.get(*key))
+                .cloned()
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+            changed_keys.sort();
+
+            let namespace = Some(namespace.as_str()).filter(|x| *x != GLOBAL_KV_NAMESPACE);
+            handle_err!(self.run_kv_watches(namespace, &changed_keys, depth), call);
+        }
+
+        // Reply empty
         call.reply()
     }
 
-    /// Get a value in the unit local key-value store
-    fn unit_kv_get(&self, call: &mut dyn rpc::Call_UnitKvGet, key: String) -> varlink::Result<()> {
-        // Get with key
-        let state = self.state.read().unwrap();
-        let value = state.kv.get(&key);
+    /// Get a value from the unit's encrypted secret store
+    fn unit_secret_get(
+        &self,
+        call: &mut dyn rpc::Call_UnitSecretGet,
+        key: String,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
 
-        // Reply with value
-        call.reply(value.map(|x| x.clone().into_inner()))
+        let value = handle_err!(
+            self.with_secret_store(|store| Ok(store.get(&key).cloned())),
+            call
+        );
+
+        call.reply(value)
     }
 
-    /// Set a value in the unit local key-value store
-    fn unit_kv_set(
+    /// Set values in the unit's encrypted secret store
+    fn unit_secret_set(
         &self,
-        call: &mut dyn rpc::Call_UnitKvSet,
+        call: &mut dyn rpc::Call_UnitSecretSet,
         data: HashMap<String, Option<String>>,
     ) -> varlink::Result<()> {
-        let mut state = self.state.write().unwrap();
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
 
-        for (key, value) in data {
-            // If a value has been provided
-            if let Some(value) = value {
-                log::debug!("Key-Value set: {} = {}", key, value);
-                // Set key to value
-                state.kv.insert(key, value.into());
-            } else {
-                log::debug!("Key-Value delete: {}", key);
-                // Erase key
-                state.kv.remove(&key);
-            }
-        }
+        handle_err!(
+            self.with_secret_store(|store| {
+                for (key, value) in data {
+                    log::debug!("Secret store set: {}", key);
+                    store.set(key, value);
+                }
+
+                store.flush()
+            }),
+            call
+        );
 
         // Reply empty
         call.reply()
     }
 
-    fn unit_kv_get_all(&self, call: &mut dyn rpc::Call_UnitKvGetAll) -> varlink::Result<()> {
-        let state = self.state.read().unwrap();
+    /// Get a value from the unit's encrypted secret store, generating it if it is not set
+    fn unit_secret_generate(
+        &self,
+        call: &mut dyn rpc::Call_UnitSecretGenerate,
+        key: String,
+        length: i64,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
 
-        // Reply with pairs
-        call.reply(
-            state
-                .kv
-                .iter()
-                .map(|(k, v)| rpc::UnitKvGetAll_Reply_pairs {
-                    key: k.clone(),
-                    value: v.clone().into_inner(),
-                })
-                .collect(),
-        )
+        let length: usize = handle_err!(
+            length
+                .try_into()
+                .context(format!("Invalid secret length: {}", length)),
+            call
+        );
+
+        let value = handle_err!(
+            self.with_secret_store(|store| {
+                // Return the existing value if there is one
+                if let Some(value) = store.get(&key) {
+                    return Ok(value.clone());
+                }
+
+                log::debug!("Secret store generate: {}", key);
+                let value = tools::generate_password(length);
+                store.set(key, Some(value.clone()));
+                store.flush()?;
+
+                Ok(value)
+            }),
+            call
+        );
+
+        call.reply(value)
     }
 
     fn relation_set(
@@ -494,7 +2060,9 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         relation_id: Option<String>,
         app: bool,
     ) -> varlink::Result<()> {
-        handle_err!(juju::relation_set(data, relation_id, app), call);
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        handle_err!(self.juju.relation_set(data, relation_id, app), call);
 
         // Reply empty
         call.reply()
@@ -506,8 +2074,10 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         relation: Option<rpc::RelationGet_Args_relation>,
         app: bool,
     ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
         call.reply(handle_err!(
-            juju::relation_get(
+            self.juju.relation_get(
                 relation.map(|r| {
                     juju::SpecificRelation {
                         relation_id: r.relation_id,
@@ -525,7 +2095,9 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         call: &mut dyn rpc::Call_RelationList,
         relation_id: Option<String>,
     ) -> varlink::Result<()> {
-        call.reply(handle_err!(juju::relation_list(relation_id), call))
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        call.reply(handle_err!(self.juju.relation_list(relation_id), call))
     }
 
     fn relation_ids(
@@ -533,11 +2105,15 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         call: &mut dyn rpc::Call_RelationIds,
         relation_name: String,
     ) -> varlink::Result<()> {
-        call.reply(handle_err!(juju::relation_ids(&relation_name), call))
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        call.reply(handle_err!(self.juju.relation_ids(&relation_name), call))
     }
 
     fn leader_is_leader(&self, call: &mut dyn rpc::Call_LeaderIsLeader) -> varlink::Result<()> {
-        call.reply(handle_err!(juju::is_leader(), call))
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        call.reply(handle_err!(self.juju.is_leader(), call))
     }
 
     fn leader_set(
@@ -545,17 +2121,158 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         call: &mut dyn rpc::Call_LeaderSet,
         data: HashMap<String, String>,
     ) -> varlink::Result<()> {
-        handle_err!(juju::leader_set(data), call);
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        handle_err!(self.juju.leader_set(data), call);
 
         // Reply empty
         call.reply()
     }
 
     fn leader_get(&self, call: &mut dyn rpc::Call_LeaderGet) -> varlink::Result<()> {
-        call.reply(handle_err!(juju::leader_get(), call))
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        call.reply(handle_err!(self.juju.leader_get(), call))
+    }
+
+    fn peer_set(
+        &self,
+        call: &mut dyn rpc::Call_PeerSet,
+        data: HashMap<String, Option<String>>,
+        relation_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        // Only the leader is allowed to write to the application data
+        if !handle_err!(self.juju.is_leader(), call) {
+            return call
+                .reply_error("Only the leader unit may set values in the peer store".into());
+        }
+
+        let relation_id = handle_err!(
+            tools::get_peer_relation_id(self, relation_name.as_deref()),
+            call
+        );
+
+        // Map `None`s to empty strings, which will erase the values
+        let data = data
+            .into_iter()
+            .map(|(k, v)| {
+                log::debug!("Peer store set: {} = {:?}", k, v);
+                (k, v.unwrap_or_default())
+            })
+            .collect();
+
+        handle_err!(self.juju.relation_set(data, Some(relation_id), true), call);
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn peer_get(
+        &self,
+        call: &mut dyn rpc::Call_PeerGet,
+        key: String,
+        relation_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        let mut data = handle_err!(tools::get_peer_store(self, relation_name.as_deref()), call);
+
+        call.reply(data.remove(&key))
+    }
+
+    fn peer_get_all(
+        &self,
+        call: &mut dyn rpc::Call_PeerGetAll,
+        relation_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        call.reply(handle_err!(
+            tools::get_peer_store(self, relation_name.as_deref()),
+            call
+        ))
+    }
+
+    fn secret_get(
+        &self,
+        call: &mut dyn rpc::Call_SecretGet,
+        secret_id: Option<String>,
+        label: Option<String>,
+        refresh: bool,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        handle_err!(
+            tools::require_juju_feature(self, JujuFeature::Secrets),
+            call
+        );
+
+        // The key to cache the secret under
+        let cache_key = match (&secret_id, &label) {
+            (Some(secret_id), _) => secret_id.clone(),
+            (None, Some(label)) => format!("label:{}", label),
+            (None, None) => {
+                return call.reply_error("A secret ID or label must be specified".into());
+            }
+        };
+
+        let mut secret_cache = self.secret_cache.lock().unwrap();
+
+        // Return the cached secret if we have it and we don't need to refresh it
+        if !refresh {
+            if let Some(data) = secret_cache.get(&cache_key) {
+                log::debug!("Secret get ( cached ): {}", cache_key);
+                return call.reply(data.clone());
+            }
+        }
+
+        log::debug!("Secret get: {}", cache_key);
+        let data = handle_err!(self.juju.secret_get(secret_id, label, refresh), call);
+
+        // Make sure the secret values never show up in the logs
+        for value in data.values() {
+            crate::log::add_redacted_value(value);
+        }
+
+        // Cache the secret
+        secret_cache.insert(cache_key, data.clone());
+
+        call.reply(data)
+    }
+
+    fn secret_set(
+        &self,
+        call: &mut dyn rpc::Call_SecretSet,
+        secret_id: String,
+        data: HashMap<String, String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        handle_err!(
+            tools::require_juju_feature(self, JujuFeature::Secrets),
+            call
+        );
+
+        // Redact the new secret values before they can be logged
+        for value in data.values() {
+            crate::log::add_redacted_value(value);
+        }
+
+        log::debug!("Secret set: {}", secret_id);
+        handle_err!(self.juju.secret_set(&secret_id, data.clone()), call);
+
+        // Update the cached secret
+        self.secret_cache.lock().unwrap().insert(secret_id, data);
+
+        // Reply empty
+        call.reply()
     }
 
     fn get_config(&self, call: &mut dyn rpc::Call_GetConfig) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
         let state = self.state.read().unwrap();
 
         // Return all of the key-value config pairs
@@ -572,69 +2289,284 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         )
     }
 
-    fn get_resource(
+    fn render_template(
+        &self,
+        call: &mut dyn rpc::Call_RenderTemplate,
+        template: String,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        // Templates can only use the global, non-secret keys of the key-value store
+        let kv: HashMap<String, String> = {
+            let state = self.state.read().unwrap();
+            state
+                .kv
+                .iter()
+                .filter(|(key, _)| {
+                    !state.kv_key_secret(None, key) && !state.kv_key_expired(None, key)
+                })
+                .map(|(key, value)| (key.clone(), (**value).clone()))
+                .collect()
+        };
+
+        call.reply(handle_err!(
+            env_template::render_file_template(&*self.juju, &kv, "template", &template),
+            call
+        ))
+    }
+
+    fn get_resource(
+        &self,
+        call: &mut dyn rpc::Call_GetResource,
+        resource_name: String,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        // Reply with path to resource
+        call.reply(handle_err!(self.juju.resource_get(&resource_name), call))
+    }
+
+    fn fetch(
+        &self,
+        call: &mut dyn rpc::Call_Fetch,
+        url: String,
+        sha256: Option<String>,
+    ) -> varlink::Result<()> {
+        // Fetching doesn't take a concurrency class because it doesn't touch the daemon state and
+        // downloads can take a long time
+
+        let (path, sha256) = handle_err!(
+            trace::in_span(
+                trace::Span::start("fetch").with_attr("http.url", &url),
+                || download::fetch(&url, sha256.as_deref())
+            ),
+            call
+        );
+
+        call.reply(path.to_string_lossy().into(), sha256)
+    }
+
+    fn port_open(&self, call: &mut dyn rpc::Call_PortOpen, port: String) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        handle_err!(tools::sync_opened_ports(self), call);
+        let port = juju::normalize_port(&port);
+        let mut state = self.state.write().unwrap();
+
+        // Skip ports that are already open
+        if state.opened_ports.contains(&port) {
+            log::debug!("Port already open: {}", port);
+            return call.reply();
+        }
+
+        log::debug!("Opening port: {}", port);
+
+        // Open the port
+        handle_err!(self.juju.open_port(&port), call);
+        state.opened_ports.insert(port);
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn port_close(&self, call: &mut dyn rpc::Call_PortClose, port: String) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        handle_err!(tools::sync_opened_ports(self), call);
+        let port = juju::normalize_port(&port);
+        let mut state = self.state.write().unwrap();
+
+        // Skip ports that are not open
+        if !state.opened_ports.contains(&port) {
+            log::debug!("Port already closed: {}", port);
+            return call.reply();
+        }
+
+        log::debug!("Closing port: {}", port);
+
+        // Close the port
+        handle_err!(self.juju.close_port(&port), call);
+        state.opened_ports.remove(&port);
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn port_close_all(&self, call: &mut dyn rpc::Call_PortCloseAll) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        handle_err!(tools::sync_opened_ports(self), call);
+        let mut state = self.state.write().unwrap();
+
+        // For each opened port
+        for port in state.opened_ports.clone() {
+            log::debug!("Closing port: {}", port);
+
+            // Close the port
+            handle_err!(self.juju.close_port(&port), call);
+            state.opened_ports.remove(&port);
+        }
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn port_get_opened(&self, call: &mut dyn rpc::Call_PortGetOpened) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        handle_err!(tools::sync_opened_ports(self), call);
+        let state = self.state.read().unwrap();
+
+        // Reply with port list
+        let mut ports: Vec<String> = state.opened_ports.iter().cloned().collect();
+        ports.sort();
+        call.reply(ports)
+    }
+
+    fn get_private_address(
+        &self,
+        call: &mut dyn rpc::Call_GetPrivateAddress,
+        binding: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        let address = if let Some(binding) = binding {
+            handle_err!(
+                tools::require_juju_feature(self, JujuFeature::NetworkGetBindAddress),
+                call
+            );
+            handle_err!(self.juju.network_get_bind_address(&binding), call)
+        } else {
+            handle_err!(self.juju.unit_get_private_address(), call)
+        };
+
+        call.reply(address)
+    }
+
+    fn get_public_address(&self, call: &mut dyn rpc::Call_GetPublicAddress) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        call.reply(handle_err!(self.juju.unit_get_public_address(), call))
+    }
+
+    fn model_info(&self, call: &mut dyn rpc::Call_ModelInfo) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        let status = handle_err!(tools::get_model_status(), call);
+
+        call.reply(status.name, status.uuid)
+    }
+
+    fn model_applications(
         &self,
-        call: &mut dyn rpc::Call_GetResource,
-        resource_name: String,
+        call: &mut dyn rpc::Call_ModelApplications,
     ) -> varlink::Result<()> {
-        // Reply with path to resource
-        call.reply(handle_err!(juju::resource_get(&resource_name), call))
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        let status = handle_err!(tools::get_model_status(), call);
+
+        call.reply(status.applications)
     }
 
-    fn port_open(&self, call: &mut dyn rpc::Call_PortOpen, port: String) -> varlink::Result<()> {
-        log::debug!("Opening port: {}", port);
+    fn model_offers(&self, call: &mut dyn rpc::Call_ModelOffers) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
 
-        // Open the port
-        handle_err!(juju::open_port(&port), call);
+        let status = handle_err!(tools::get_model_status(), call);
 
-        // Reply empty
-        call.reply()
+        call.reply(
+            status
+                .offers
+                .into_iter()
+                .map(|offer| rpc::ModelOffers_Reply_offers {
+                    name: offer.name,
+                    application: offer.application,
+                    endpoints: offer.endpoints,
+                })
+                .collect(),
+        )
     }
 
-    fn port_close(&self, call: &mut dyn rpc::Call_PortClose, port: String) -> varlink::Result<()> {
-        log::debug!("Closing port: {}", port);
+    fn docker_ensure(&self, call: &mut dyn rpc::Call_DockerEnsure) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
 
-        // Close the port
-        handle_err!(juju::close_port(&port), call);
+        if !self.docker_enabled() {
+            return call.reply_error("Docker is not enabled for this charm".into());
+        }
+
+        let mut state = self.state.write().unwrap();
+        handle_err!(tools::ensure_container_engine(self, &mut state), call);
 
-        // Reply empty
         call.reply()
     }
 
-    fn port_close_all(&self, call: &mut dyn rpc::Call_PortCloseAll) -> varlink::Result<()> {
-        // For each opened port
-        for port in handle_err!(juju::opened_ports(), call) {
-            log::debug!("Closing port: {}", port);
-
-            // Close the port
-            handle_err!(juju::close_port(&port), call);
+    fn container_apply(&self, call: &mut dyn rpc::Call_ContainerApply) -> varlink::Result<()> {
+        if self.docker_enabled() {
+            // The guard is released while the container update hooks run
+            let guard = || self.concurrency_guard(ConcurrencyClass::Write);
+            handle_err!(tools::apply_container_updates(self, guard), call);
+        } else if self.platform == Platform::Kubernetes {
+            let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+            handle_err!(tools::apply_pebble_updates(self), call);
         }
 
-        // Reply empty
         call.reply()
     }
 
-    fn port_get_opened(&self, call: &mut dyn rpc::Call_PortGetOpened) -> varlink::Result<()> {
-        // Reply with port list
-        call.reply(handle_err!(juju::opened_ports(), call))
+    fn container_apply_dry_run(
+        &self,
+        call: &mut dyn rpc::Call_ContainerApplyDryRun,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        call.reply(handle_err!(
+            tools::get_pending_container_changes(self),
+            call
+        ))
     }
 
-    fn get_private_address(
+    fn container_backup(
         &self,
-        call: &mut dyn rpc::Call_GetPrivateAddress,
+        call: &mut dyn rpc::Call_ContainerBackup,
+        container_name: Option<String>,
+        storage_name: Option<String>,
+        dir: Option<String>,
     ) -> varlink::Result<()> {
-        call.reply(handle_err!(juju::unit_get_private_address(), call))
-    }
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
 
-    fn get_public_address(&self, call: &mut dyn rpc::Call_GetPublicAddress) -> varlink::Result<()> {
-        call.reply(handle_err!(juju::unit_get_public_address(), call))
+        if !self.docker_enabled() {
+            return call.reply_error("Docker is not enabled for this charm".into());
+        }
+
+        let path = handle_err!(
+            backup::backup_container(
+                self,
+                container_name.as_deref(),
+                storage_name.as_deref(),
+                dir.as_deref()
+            ),
+            call
+        );
+
+        call.reply(path.to_string_lossy().into())
     }
 
-    fn container_apply(&self, call: &mut dyn rpc::Call_ContainerApply) -> varlink::Result<()> {
-        if self.lucky_metadata.use_docker {
-            handle_err!(tools::apply_container_updates(self), call);
+    fn container_restore(
+        &self,
+        call: &mut dyn rpc::Call_ContainerRestore,
+        path: String,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        if !self.docker_enabled() {
+            return call.reply_error("Docker is not enabled for this charm".into());
         }
 
+        handle_err!(
+            backup::restore_container(self, container_name.as_deref(), Path::new(&path)),
+            call
+        );
+
         call.reply()
     }
 
@@ -643,6 +2575,8 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         call: &mut dyn rpc::Call_ContainerDelete,
         container_name: Option<String>,
     ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
         let mut state = self.state.write().unwrap();
 
         // Get the config for the requested container
@@ -660,12 +2594,111 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         call.reply()
     }
 
+    fn container_start(
+        &self,
+        call: &mut dyn rpc::Call_ContainerStart,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        handle_err!(
+            self.change_container_lifecycle(
+                container_name.as_deref(),
+                &tools::ContainerLifecycleAction::Start
+            ),
+            call
+        );
+
+        call.reply()
+    }
+
+    fn container_stop(
+        &self,
+        call: &mut dyn rpc::Call_ContainerStop,
+        timeout: Option<i64>,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let timeout = handle_err!(tools::parse_timeout(timeout), call);
+        handle_err!(
+            self.change_container_lifecycle(
+                container_name.as_deref(),
+                &tools::ContainerLifecycleAction::Stop(timeout)
+            ),
+            call
+        );
+
+        call.reply()
+    }
+
+    fn container_restart(
+        &self,
+        call: &mut dyn rpc::Call_ContainerRestart,
+        timeout: Option<i64>,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let timeout = handle_err!(tools::parse_timeout(timeout), call);
+        handle_err!(
+            self.change_container_lifecycle(
+                container_name.as_deref(),
+                &tools::ContainerLifecycleAction::Restart(timeout)
+            ),
+            call
+        );
+
+        call.reply()
+    }
+
+    fn container_logs(
+        &self,
+        call: &mut dyn rpc::Call_ContainerLogs,
+        follow: bool,
+        tail: Option<i64>,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        // This call must be called with more
+        if !call.wants_more() {
+            call.reply_requires_more()?;
+            return Ok(());
+        }
+
+        // Following the logs may never finish, so this doesn't hold the concurrency lock
+        let tail: Option<u64> = handle_err!(
+            tail.map(|x| x.try_into().context("Invalid tail"))
+                .transpose(),
+            call
+        );
+        let (_process, lines) = handle_err!(
+            container_logs::start_container_logs(self, container_name.as_deref(), follow, tail),
+            call
+        );
+
+        // Send each line in its own reply. The logs process is killed when we return, such as when
+        // the client disconnects.
+        call.set_continues(true);
+        for (stream, line) in lines {
+            call.reply(Some(rpc::ContainerLogLine {
+                stream: stream.name().into(),
+                line,
+            }))?;
+        }
+
+        // Send the last reply
+        call.set_continues(false);
+        call.reply(None)
+    }
+
     fn container_set_entrypoint(
         &self,
         call: &mut dyn rpc::Call_ContainerSetEntrypoint,
         entrypoint: Option<String>,
         container_name: Option<String>,
     ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
         let mut state = self.state.write().unwrap();
 
         // If a container was specified
@@ -703,6 +2736,8 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         command: Option<Vec<String>>,
         container_name: Option<String>,
     ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
         let mut state = self.state.write().unwrap();
 
         // If a container was specified
@@ -735,6 +2770,76 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         call.reply()
     }
 
+    fn container_reset_entrypoint(
+        &self,
+        call: &mut dyn rpc::Call_ContainerResetEntrypoint,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let mut state = self.state.write().unwrap();
+
+        // Get the config for the requested container
+        let container = match &container_name {
+            Some(container_name) => state.named_containers.get_mut(container_name),
+            None => state.default_container.as_mut(),
+        };
+
+        if let Some(container) = container {
+            // Go back to the declared entrypoint
+            let entrypoint = container
+                .declared_spec
+                .as_ref()
+                .and_then(|x| x.entrypoint.clone());
+            log::debug!(
+                "Reset Docker entrypoint{}: {}",
+                container_name.map_or("".into(), |x| format!(" [{}]", x)),
+                entrypoint.as_ref().unwrap_or(&"unset".to_string())
+            );
+
+            if container.config.entrypoint != entrypoint {
+                container.update(|c| c.config.entrypoint = entrypoint);
+            }
+        }
+
+        call.reply()
+    }
+
+    fn container_reset_command(
+        &self,
+        call: &mut dyn rpc::Call_ContainerResetCommand,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let mut state = self.state.write().unwrap();
+
+        // Get the config for the requested container
+        let container = match &container_name {
+            Some(container_name) => state.named_containers.get_mut(container_name),
+            None => state.default_container.as_mut(),
+        };
+
+        if let Some(container) = container {
+            // Go back to the declared command
+            let command = container
+                .declared_spec
+                .as_ref()
+                .and_then(|x| x.command.clone());
+            log::debug!(
+                "Reset Docker command{}: {}",
+                container_name.map_or("".into(), |x| format!(" [{}]", x)),
+                command.as_ref().map_or("unset".into(), |x| x.join(" "))
+            );
+
+            if container.config.command != command {
+                container.update(|c| c.config.command = command);
+            }
+        }
+
+        call.reply()
+    }
+
     // The uncollapsed if is easier to understand in this case
     #[allow(clippy::collapsible_if)]
     fn container_image_set(
@@ -744,6 +2849,8 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         container_name: Option<String>,
         no_pull: bool,
     ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
         let mut state = self.state.write().unwrap();
 
         // If this is for a named container
@@ -793,6 +2900,8 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         call: &mut dyn rpc::Call_ContainerImageGet,
         container_name: Option<String>,
     ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
         let state = self.state.read().unwrap();
 
         // If this is for a named container
@@ -812,12 +2921,77 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         }
     }
 
+    fn container_check_images(
+        &self,
+        call: &mut dyn rpc::Call_ContainerCheckImages,
+        pull: bool,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        call.reply(handle_err!(tools::check_container_images(self, pull), call))
+    }
+
+    fn container_image_set_auth(
+        &self,
+        call: &mut dyn rpc::Call_ContainerImageSetAuth,
+        auth: Option<rpc::RegistryAuth>,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        // Convert the RPC type to the container config type
+        let auth = match auth {
+            Some(auth) => Some(crate::docker::RegistryAuth {
+                source: match (auth.relation_id, auth.remote_unit) {
+                    (Some(relation_id), Some(remote_unit)) => {
+                        crate::docker::RegistryAuthSource::Relation {
+                            relation_id,
+                            remote_unit,
+                            app: auth.app,
+                        }
+                    }
+                    (None, None) => crate::docker::RegistryAuthSource::Config,
+                    _ => {
+                        return call.reply_error(
+                            "The relation ID and the remote unit must be set together".into(),
+                        )
+                    }
+                },
+                username_key: auth.username_key,
+                password_key: auth.password_key,
+                server_address: auth.server_address,
+            }),
+            None => None,
+        };
+
+        let mut state = self.state.write().unwrap();
+
+        // Get the config for the requested container
+        let container = match &container_name {
+            Some(container_name) => state.named_containers.get_mut(container_name),
+            None => state.default_container.as_mut(),
+        };
+
+        if let Some(container) = container {
+            container.update(|c| c.registry_auth = auth);
+        } else {
+            return call.reply_error(format!(
+                r#"Container "{}" does not exist"#,
+                container_name.as_deref().unwrap_or("default")
+            ));
+        }
+
+        call.reply()
+    }
+
     fn container_env_get(
         &self,
         call: &mut dyn rpc::Call_ContainerEnvGet,
         key: String,
         container_name: Option<String>,
     ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
         let state = self.state.read().unwrap();
 
         // Get the config for the requested container
@@ -841,6 +3015,8 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         call: &mut dyn rpc::Call_ContainerEnvGetAll,
         container_name: Option<String>,
     ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
         let state = self.state.read().unwrap();
 
         // This call must be called with more
@@ -871,20 +3047,115 @@ impl rpc::VarlinkInterface for LuckyDaemon {
             // Reply with pairs
             call.reply(pairs)
 
-        // If the container doesn't exist
+        // If the container doesn't exist
+        } else {
+            // Reply with empty array
+            call.reply(vec![])
+        }
+    }
+
+    /// Set a value in the unit local key-value store
+    fn container_env_set(
+        &self,
+        call: &mut dyn rpc::Call_ContainerEnvSet,
+        vars: HashMap<String, Option<String>>,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let mut state = self.state.write().unwrap();
+
+        // Get the config for the requested container
+        let mut container = match &container_name {
+            Some(container_name) => state.named_containers.get_mut(container_name),
+            None => state.default_container.as_mut(),
+        };
+
+        if let Some(container) = &mut container {
+            for (key, value) in vars {
+                // If a value has been provided
+                if let Some(value) = value {
+                    log::debug!("Container env set: {} = {}", key, value);
+                    // Set key to value, replacing any template for it
+                    container.update(|c| {
+                        c.config.env_templates.remove(&key);
+                        c.config.env_vars.insert(key, value);
+                    });
+                } else {
+                    log::debug!("Container env deleted: {}", key);
+                    // Erase key
+                    container.update(|c| {
+                        c.config.env_templates.remove(&key);
+                        c.config.env_vars.remove(&key);
+                    });
+                }
+            }
+        }
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn container_env_set_template(
+        &self,
+        call: &mut dyn rpc::Call_ContainerEnvSetTemplate,
+        vars: HashMap<String, Option<String>>,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let mut state = self.state.write().unwrap();
+
+        // Get the config for the requested container
+        let container = match &container_name {
+            Some(container_name) => state.named_containers.get_mut(container_name),
+            None => state.default_container.as_mut(),
+        };
+
+        let container = if let Some(container) = container {
+            container
         } else {
-            // Reply with empty array
-            call.reply(vec![])
+            return call.reply_error(format!(
+                r#"Container "{}" does not exist"#,
+                container_name.as_deref().unwrap_or("default")
+            ));
+        };
+
+        for (key, template) in vars {
+            // If a template has been provided
+            if let Some(template) = template {
+                log::debug!("Container env template set: {} = {}", key, template);
+                // The value will be rendered when the container config is applied
+                container.update(|c| {
+                    c.config.env_templates.insert(key, template);
+                });
+            } else {
+                log::debug!("Container env template deleted: {}", key);
+                container.update(|c| {
+                    c.config.env_templates.remove(&key);
+                    c.config.env_vars.remove(&key);
+                });
+            }
         }
+
+        // Reply empty
+        call.reply()
     }
 
-    /// Set a value in the unit local key-value store
-    fn container_env_set(
+    fn container_env_mode_set(
         &self,
-        call: &mut dyn rpc::Call_ContainerEnvSet,
-        vars: HashMap<String, Option<String>>,
+        call: &mut dyn rpc::Call_ContainerEnvModeSet,
+        mode: String,
         container_name: Option<String>,
     ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let mode: EnvMode = handle_err!(
+            mode.parse()
+                .map_err(|_| anyhow::format_err!("Invalid env mode: {}", mode)),
+            call
+        );
+
         let mut state = self.state.write().unwrap();
 
         // Get the config for the requested container
@@ -894,28 +3165,32 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         };
 
         if let Some(container) = &mut container {
-            for (key, value) in vars {
-                // If a value has been provided
-                if let Some(value) = value {
-                    log::debug!("Container env set: {} = {}", key, value);
-                    // Set key to value
-                    container.update(|c| {
-                        c.config.env_vars.insert(key, value);
-                    });
-                } else {
-                    log::debug!("Container env deleted: {}", key);
-                    // Erase key
-                    container.update(|c| {
-                        c.config.env_vars.remove(&key);
-                    });
-                }
-            }
+            log::debug!("Container env mode set: {}", mode.as_ref());
+            container.update(|c| c.config.env_mode = mode);
         }
 
         // Reply empty
         call.reply()
     }
 
+    fn container_env_mode_get(
+        &self,
+        call: &mut dyn rpc::Call_ContainerEnvModeGet,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        let state = self.state.read().unwrap();
+
+        // Get the config for the requested container
+        let container = match &container_name {
+            Some(container_name) => state.named_containers.get(container_name),
+            None => state.default_container.as_ref(),
+        };
+
+        call.reply(container.map(|x| x.config.env_mode.as_ref().to_owned()))
+    }
+
     fn container_volume_add(
         &self,
         call: &mut dyn rpc::Call_ContainerVolumeAdd,
@@ -923,6 +3198,8 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         target: String,
         container_name: Option<String>,
     ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
         let mut state = self.state.write().unwrap();
 
         // Get the config for the requested container
@@ -944,6 +3221,9 @@ impl rpc::VarlinkInterface for LuckyDaemon {
             );
             // Add volume to container config
             container.update(|c| {
+                c.config
+                    .read_only_volumes
+                    .remove(&VolumeTarget(target.clone()));
                 c.config
                     .volumes
                     .insert(VolumeTarget(target), VolumeSource(source));
@@ -961,6 +3241,8 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         delete_data: bool,
         container_name: Option<String>,
     ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
         let mut state = self.state.write().unwrap();
 
         // Get the config for the requested container
@@ -983,6 +3265,10 @@ impl rpc::VarlinkInterface for LuckyDaemon {
 
             // Remove the container volume
             container.update(|container| {
+                container
+                    .config
+                    .read_only_volumes
+                    .remove(&VolumeTarget(target.clone()));
                 let volumes = &mut container.config.volumes;
 
                 // Get source and remove from volume list
@@ -990,8 +3276,12 @@ impl rpc::VarlinkInterface for LuckyDaemon {
 
                 // If there is a volume for the given target path
                 if let Some(source) = source {
+                    // Juju owns the data in storage volumes, so it is never deleted here
+                    if delete_data && source.storage_name().is_some() {
+                        log::warn!("Not deleting data of Juju storage volume: {}", &*source);
+
                     // If we should delete the source data
-                    if delete_data {
+                    } else if delete_data {
                         // If there are no other volumes with the same source
                         if volumes.values().find(|&x| *x == source).is_none() {
                             log::debug!("Deleting volume data source: {}", &*source);
@@ -1028,16 +3318,18 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         call: &mut dyn rpc::Call_ContainerVolumeGetAll,
         container_name: Option<String>,
     ) -> varlink::Result<()> {
-        let mut state = self.state.write().unwrap();
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        let state = self.state.read().unwrap();
 
         // Get the config for the requested container
-        let mut container = match &container_name {
-            Some(container_name) => state.named_containers.get_mut(container_name),
-            None => state.default_container.as_mut(),
+        let container = match &container_name {
+            Some(container_name) => state.named_containers.get(container_name),
+            None => state.default_container.as_ref(),
         };
 
         // If the container exists
-        if let Some(container) = &mut container {
+        if let Some(container) = container {
             // Reply wth volumes
             call.reply(
                 container
@@ -1064,8 +3356,11 @@ impl rpc::VarlinkInterface for LuckyDaemon {
         host_port: i64,
         container_port: i64,
         protocol: String,
+        no_open: bool,
         container_name: Option<String>,
     ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
         let mut state = self.state.write().unwrap();
 
         // Get the config for the requested container
@@ -1123,23 +3418,229 @@ impl rpc::VarlinkInterface for LuckyDaemon {
                 return Ok(());
             }
 
-            container.update(|c| {
-                c.config.ports.insert(port_binding);
-            });
+            container.update(|c| {
+                if no_open {
+                    c.config.private_ports.insert(port_binding.clone());
+                } else {
+                    c.config.private_ports.remove(&port_binding);
+                }
+                c.config.ports.insert(port_binding);
+            });
+        }
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn container_port_remove(
+        &self,
+        call: &mut dyn rpc::Call_ContainerPortRemove,
+        host_port: i64,
+        container_port: i64,
+        protocol: String,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let mut state = self.state.write().unwrap();
+
+        // Get the config for the requested container
+        let mut container_log_name = None;
+        let mut container = match &container_name {
+            Some(container_name) => {
+                container_log_name = Some(container_name.clone());
+                state.named_containers.get_mut(container_name)
+            }
+            None => state.default_container.as_mut(),
+        };
+
+        if let Some(container) = &mut container {
+            log::debug!(
+                "Removing port from container{}: {}:{}/{}",
+                container_log_name.map_or("".into(), |x| format!("[{}]", x)),
+                host_port,
+                container_port,
+                protocol
+            );
+
+            container.update(|c| {
+                let port_binding = PortBinding {
+                    host_port: handle_err!(
+                        host_port.try_into().context("Invalid port number"),
+                        call
+                    ),
+                    container_port: handle_err!(
+                        container_port.try_into().context("Invalid port number"),
+                        call
+                    ),
+                    protocol,
+                };
+                c.config.ports.remove(&port_binding);
+                c.config.private_ports.remove(&port_binding);
+
+                Ok(())
+            })?;
+        }
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn container_port_remove_all(
+        &self,
+        call: &mut dyn rpc::Call_ContainerPortRemoveAll,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let mut state = self.state.write().unwrap();
+
+        // Get the config for the requested container
+        let mut container_log_name = None;
+        let container = match &container_name {
+            Some(container_name) => {
+                container_log_name = Some(container_name.clone());
+                state.named_containers.get_mut(container_name)
+            }
+            None => state.default_container.as_mut(),
+        };
+
+        if let Some(container) = container {
+            // For each port
+            for port_binding in &container.config.ports.clone() {
+                log::debug!(
+                    "Removing port from container{}: {}:{}/{}",
+                    container_log_name
+                        .as_ref()
+                        .map_or("".into(), |x| format!("[{}]", x)),
+                    port_binding.host_port,
+                    port_binding.container_port,
+                    port_binding.protocol
+                );
+
+                // Remove the port
+                container.update(|c| {
+                    c.config.ports.remove(&port_binding);
+                    c.config.private_ports.remove(&port_binding);
+                });
+            }
+        }
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn container_port_get_all(
+        &self,
+        call: &mut dyn rpc::Call_ContainerPortGetAll,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        let state = self.state.read().unwrap();
+
+        // Get the config for the requested container
+        let mut container = match &container_name {
+            Some(container_name) => state.named_containers.get(container_name),
+            None => state.default_container.as_ref(),
+        };
+
+        if let Some(container) = &mut container {
+            call.reply(
+                container
+                    .config
+                    .ports
+                    .iter()
+                    .map(|port| rpc::ContainerPortGetAll_Reply_ports {
+                        container_port: port.container_port.into(),
+                        host_port: port.host_port.into(),
+                        protocol: port.protocol.clone(),
+                    })
+                    .collect(),
+            )
+        } else {
+            // Reply empty
+            call.reply(vec![])
+        }
+    }
+
+    fn container_network_set(
+        &self,
+        call: &mut dyn rpc::Call_ContainerNetworkSet,
+        network_name: Option<String>,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let mut state = self.state.write().unwrap();
+
+        // Get the config for the requested container
+        let mut container_log_name = None;
+        let mut container = match &container_name {
+            Some(container_name) => {
+                container_log_name = Some(container_name.clone());
+                state.named_containers.get_mut(container_name)
+            }
+            None => state.default_container.as_mut(),
+        };
+
+        if let Some(container) = &mut container {
+            log::debug!(
+                "Setting container network{}: {}",
+                container_log_name.map_or("".into(), |x| format!("[{}]", x)),
+                network_name.as_ref().unwrap_or(&"unset".to_string()),
+            );
+
+            container.update(|c| c.config.network = network_name);
+        }
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn container_dns_set(
+        &self,
+        call: &mut dyn rpc::Call_ContainerDnsSet,
+        servers: Vec<String>,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let mut state = self.state.write().unwrap();
+
+        // Get the config for the requested container
+        let mut container_log_name = None;
+        let mut container = match &container_name {
+            Some(container_name) => {
+                container_log_name = Some(container_name.clone());
+                state.named_containers.get_mut(container_name)
+            }
+            None => state.default_container.as_mut(),
+        };
+
+        if let Some(container) = &mut container {
+            log::debug!(
+                "Setting container DNS servers{}: {:?}",
+                container_log_name.map_or("".into(), |x| format!("[{}]", x)),
+                servers,
+            );
+
+            container.update(|c| c.config.dns = servers);
         }
 
         // Reply empty
         call.reply()
     }
 
-    fn container_port_remove(
+    fn container_extra_host_set(
         &self,
-        call: &mut dyn rpc::Call_ContainerPortRemove,
-        host_port: i64,
-        container_port: i64,
-        protocol: String,
+        call: &mut dyn rpc::Call_ContainerExtraHostSet,
+        hostname: String,
+        address: Option<String>,
         container_name: Option<String>,
     ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
         let mut state = self.state.write().unwrap();
 
         // Get the config for the requested container
@@ -1154,44 +3655,44 @@ impl rpc::VarlinkInterface for LuckyDaemon {
 
         if let Some(container) = &mut container {
             log::debug!(
-                "Removing port from container{}: {}:{}/{}",
+                "Setting container extra host{}: {} = {}",
                 container_log_name.map_or("".into(), |x| format!("[{}]", x)),
-                host_port,
-                container_port,
-                protocol
+                hostname,
+                address.as_ref().unwrap_or(&"unset".to_string()),
             );
 
             container.update(|c| {
-                c.config.ports.remove(&PortBinding {
-                    host_port: handle_err!(
-                        host_port.try_into().context("Invalid port number"),
-                        call
-                    ),
-                    container_port: handle_err!(
-                        container_port.try_into().context("Invalid port number"),
-                        call
-                    ),
-                    protocol,
-                });
-
-                Ok(())
-            })?;
+                if let Some(address) = address {
+                    c.config.extra_hosts.insert(hostname, address);
+                } else {
+                    c.config.extra_hosts.remove(&hostname);
+                }
+            });
         }
 
         // Reply empty
         call.reply()
     }
 
-    fn container_port_remove_all(
+    fn container_devices_set(
         &self,
-        call: &mut dyn rpc::Call_ContainerPortRemoveAll,
+        call: &mut dyn rpc::Call_ContainerDevicesSet,
+        devices: Vec<String>,
         container_name: Option<String>,
     ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        // Validate the devices. Whether they exist on the host is checked when the container is
+        // created.
+        for device in &devices {
+            handle_err!(docker::device_host_path(device), call);
+        }
+
         let mut state = self.state.write().unwrap();
 
         // Get the config for the requested container
         let mut container_log_name = None;
-        let container = match &container_name {
+        let mut container = match &container_name {
             Some(container_name) => {
                 container_log_name = Some(container_name.clone());
                 state.named_containers.get_mut(container_name)
@@ -1199,93 +3700,372 @@ impl rpc::VarlinkInterface for LuckyDaemon {
             None => state.default_container.as_mut(),
         };
 
-        if let Some(container) = container {
-            // For each port
-            for port_binding in &container.config.ports.clone() {
-                log::debug!(
-                    "Removing port from container{}: {}:{}/{}",
-                    container_log_name
-                        .as_ref()
-                        .map_or("".into(), |x| format!("[{}]", x)),
-                    port_binding.host_port,
-                    port_binding.container_port,
-                    port_binding.protocol
-                );
+        if let Some(container) = &mut container {
+            log::debug!(
+                "Setting container devices{}: {:?}",
+                container_log_name.map_or("".into(), |x| format!("[{}]", x)),
+                devices,
+            );
 
-                // Remove the port
-                container.update(|c| {
-                    c.config.ports.remove(&port_binding);
-                });
-            }
+            container.update(|c| c.config.devices = devices);
         }
 
         // Reply empty
         call.reply()
     }
 
-    fn container_port_get_all(
+    fn container_gpus_set(
         &self,
-        call: &mut dyn rpc::Call_ContainerPortGetAll,
+        call: &mut dyn rpc::Call_ContainerGpusSet,
+        gpus: Option<String>,
         container_name: Option<String>,
     ) -> varlink::Result<()> {
-        let state = self.state.read().unwrap();
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        // Validate the GPUs. Whether they exist on the host is checked when the container is
+        // created.
+        if let Some(gpus) = &gpus {
+            handle_err!(gpus.parse::<docker::GpuRequest>(), call);
+        }
+
+        let mut state = self.state.write().unwrap();
 
         // Get the config for the requested container
+        let mut container_log_name = None;
         let mut container = match &container_name {
-            Some(container_name) => state.named_containers.get(container_name),
-            None => state.default_container.as_ref(),
+            Some(container_name) => {
+                container_log_name = Some(container_name.clone());
+                state.named_containers.get_mut(container_name)
+            }
+            None => state.default_container.as_mut(),
         };
 
         if let Some(container) = &mut container {
-            call.reply(
-                container
-                    .config
-                    .ports
-                    .iter()
-                    .map(|port| rpc::ContainerPortGetAll_Reply_ports {
-                        container_port: port.container_port.into(),
-                        host_port: port.host_port.into(),
-                        protocol: port.protocol.clone(),
-                    })
-                    .collect(),
-            )
-        } else {
-            // Reply empty
-            call.reply(vec![])
+            log::debug!(
+                "Setting container GPUs{}: {}",
+                container_log_name.map_or("".into(), |x| format!("[{}]", x)),
+                gpus.as_ref().unwrap_or(&"unset".to_string()),
+            );
+
+            container.update(|c| c.config.gpus = gpus);
         }
+
+        // Reply empty
+        call.reply()
     }
 
-    fn container_network_set(
+    fn container_restart_policy_set(
         &self,
-        call: &mut dyn rpc::Call_ContainerNetworkSet,
-        network_name: Option<String>,
+        call: &mut dyn rpc::Call_ContainerRestartPolicySet,
+        policy: String,
         container_name: Option<String>,
     ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let policy: ContainerRestartPolicy = handle_err!(
+            policy
+                .parse()
+                .map_err(|_| anyhow::format_err!("Invalid restart policy: {}", policy)),
+            call
+        );
+
         let mut state = self.state.write().unwrap();
 
         // Get the config for the requested container
-        let mut container_log_name = None;
         let mut container = match &container_name {
-            Some(container_name) => {
-                container_log_name = Some(container_name.clone());
-                state.named_containers.get_mut(container_name)
-            }
+            Some(container_name) => state.named_containers.get_mut(container_name),
             None => state.default_container.as_mut(),
         };
 
         if let Some(container) = &mut container {
-            log::debug!(
-                "Setting container network{}: {}",
-                container_log_name.map_or("".into(), |x| format!("[{}]", x)),
-                network_name.as_ref().unwrap_or(&"unset".to_string()),
-            );
+            log::debug!("Container restart policy set: {}", policy.as_ref());
+            container.update(|c| c.config.restart_policy = policy);
+        }
 
-            container.update(|c| c.config.network = network_name);
+        // Reply empty
+        call.reply()
+    }
+
+    fn container_update_strategy_set(
+        &self,
+        call: &mut dyn rpc::Call_ContainerUpdateStrategySet,
+        strategy: String,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let strategy: UpdateStrategy = handle_err!(
+            strategy
+                .parse()
+                .map_err(|_| anyhow::format_err!("Invalid update strategy: {}", strategy)),
+            call
+        );
+
+        let mut state = self.state.write().unwrap();
+
+        log::debug!("Container update strategy set: {}", strategy.as_ref());
+        state
+            .container_updates
+            .entry(container_name.unwrap_or_else(|| DEFAULT_CONTAINER_NAME.into()))
+            .or_default()
+            .strategy = strategy;
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn container_update_hook_add(
+        &self,
+        call: &mut dyn rpc::Call_ContainerUpdateHookAdd,
+        stage: String,
+        script: String,
+        args: Vec<String>,
+        in_container: bool,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let stage: UpdateHookStage = handle_err!(
+            stage
+                .parse()
+                .map_err(|_| anyhow::format_err!("Invalid update hook stage: {}", stage)),
+            call
+        );
+
+        let mut state = self.state.write().unwrap();
+
+        log::debug!("Container {} hook added: {}", stage.as_ref(), script);
+        let hooks = state
+            .container_updates
+            .entry(container_name.unwrap_or_else(|| DEFAULT_CONTAINER_NAME.into()))
+            .or_default()
+            .hooks_mut(stage);
+        let hook = UpdateHook {
+            script,
+            args,
+            in_container,
+        };
+        // Replace the hook if the script is already registered
+        if let Some(existing) = hooks.iter_mut().find(|x| x.script == hook.script) {
+            *existing = hook;
+        } else {
+            hooks.push(hook);
+        }
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn container_update_hook_remove(
+        &self,
+        call: &mut dyn rpc::Call_ContainerUpdateHookRemove,
+        stage: String,
+        script: String,
+        container_name: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let stage: UpdateHookStage = handle_err!(
+            stage
+                .parse()
+                .map_err(|_| anyhow::format_err!("Invalid update hook stage: {}", stage)),
+            call
+        );
+
+        let mut state = self.state.write().unwrap();
+
+        if let Some(settings) = state
+            .container_updates
+            .get_mut(container_name.as_deref().unwrap_or(DEFAULT_CONTAINER_NAME))
+        {
+            log::debug!("Container {} hook removed: {}", stage.as_ref(), script);
+            settings.hooks_mut(stage).retain(|x| x.script != script);
+        }
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn host_service_set_command(
+        &self,
+        call: &mut dyn rpc::Call_HostServiceSetCommand,
+        service_name: String,
+        command: Vec<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        handle_err!(crate::systemd::validate_service_name(&service_name), call);
+        if command.is_empty() {
+            return call.reply_error("The host service command cannot be empty".into());
+        }
+
+        let mut state = self.state.write().unwrap();
+
+        log::debug!(
+            "Setting host service command[{}]: {:?}",
+            service_name,
+            command
+        );
+        if let Some(service) = state.host_services.get_mut(&service_name) {
+            service.update(|s| s.config.command = command);
+        } else {
+            state
+                .host_services
+                .insert(service_name, HostServiceInfo::new(command).into());
+        }
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn host_service_set_template(
+        &self,
+        call: &mut dyn rpc::Call_HostServiceSetTemplate,
+        service_name: String,
+        template: Option<String>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        // Make sure the template exists now instead of failing when the service is applied
+        if let Some(template) = &template {
+            if !self.charm_dir.join(template).is_file() {
+                return call.reply_error(format!("Unit file template not found: {}", template));
+            }
+        }
+
+        handle_err!(
+            self.update_host_service(&service_name, |s| s.config.unit_template = template),
+            call
+        );
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn host_service_set_restart_policy(
+        &self,
+        call: &mut dyn rpc::Call_HostServiceSetRestartPolicy,
+        service_name: String,
+        policy: String,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let policy: RestartPolicy = handle_err!(
+            policy
+                .parse()
+                .map_err(|_| anyhow::format_err!("Invalid restart policy: {}", policy)),
+            call
+        );
+
+        handle_err!(
+            self.update_host_service(&service_name, |s| s.config.restart_policy = policy),
+            call
+        );
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn host_service_env_set(
+        &self,
+        call: &mut dyn rpc::Call_HostServiceEnvSet,
+        service_name: String,
+        vars: HashMap<String, Option<String>>,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        handle_err!(
+            self.update_host_service(&service_name, |s| {
+                for (key, value) in vars {
+                    // Setting a variable to null removes it
+                    if let Some(value) = value {
+                        s.config.env_vars.insert(key, value);
+                    } else {
+                        s.config.env_vars.remove(&key);
+                    }
+                }
+            }),
+            call
+        );
+
+        // Reply empty
+        call.reply()
+    }
+
+    fn host_service_env_get_all(
+        &self,
+        call: &mut dyn rpc::Call_HostServiceEnvGetAll,
+        service_name: String,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        let state = self.state.read().unwrap();
+
+        let pairs = state
+            .host_services
+            .get(&service_name)
+            .map(|service| {
+                service
+                    .config
+                    .env_vars
+                    .iter()
+                    .map(|(k, v)| rpc::HostServiceEnvGetAll_Reply_pairs {
+                        key: k.clone(),
+                        value: v.clone(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        call.reply(pairs)
+    }
+
+    fn host_service_delete(
+        &self,
+        call: &mut dyn rpc::Call_HostServiceDelete,
+        service_name: String,
+    ) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        let mut state = self.state.write().unwrap();
+
+        if let Some(service) = state.host_services.get_mut(&service_name) {
+            // Mark service as needing removal
+            service.update(|s| s.pending_removal = true);
         }
 
         // Reply empty
         call.reply()
     }
+
+    fn host_service_list(&self, call: &mut dyn rpc::Call_HostServiceList) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Read);
+
+        let state = self.state.read().unwrap();
+
+        let mut services: Vec<rpc::HostServiceList_Reply_services> = state
+            .host_services
+            .iter()
+            .filter(|(_, service)| !service.pending_removal)
+            .map(|(name, service)| rpc::HostServiceList_Reply_services {
+                name: name.clone(),
+                command: service.config.command.clone(),
+                restart_policy: service.config.restart_policy.as_ref().into(),
+                template: service.config.unit_template.clone(),
+            })
+            .collect();
+        services.sort_by(|a, b| a.name.cmp(&b.name));
+
+        call.reply(services)
+    }
+
+    fn host_service_apply(&self, call: &mut dyn rpc::Call_HostServiceApply) -> varlink::Result<()> {
+        let _guard = self.concurrency_guard(ConcurrencyClass::Write);
+
+        handle_err!(tools::apply_host_service_updates(self), call);
+
+        call.reply()
+    }
 }
 
 impl Drop for LuckyDaemon {
@@ -0,0 +1,195 @@
+//! Container volume backups
+//!
+//! A backup is a gzipped tarball of the volumes of a container that Lucky manages, which are the
+//! volumes with a relative source that are kept in the Lucky data dir. Volumes that are mounted
+//! from a path on the host or from Juju storage are not included. The container is stopped while
+//! the backup is taken or restored so that its data doesn't change in the middle of it.
+
+use anyhow::Context;
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::*;
+use crate::process::run_cmd;
+
+/// How long to wait for the container to stop before killing it
+const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Get the container with the given name, or the default container if no name is given
+fn get_container<'a>(
+    state: &'a DaemonState,
+    container_name: Option<&str>,
+) -> anyhow::Result<&'a ContainerInfo> {
+    match container_name {
+        Some(name) => state.named_containers.get(name),
+        None => state.default_container.as_ref(),
+    }
+    .map(|x| &**x)
+    .ok_or_else(|| {
+        anyhow::format_err!(
+            r#"Container "{}" does not exist"#,
+            container_name.unwrap_or(DEFAULT_CONTAINER_NAME)
+        )
+    })
+}
+
+/// Get the sources of the Lucky managed volumes of the container, relative to the volume dir
+fn managed_volume_sources(container: &ContainerInfo) -> Vec<String> {
+    let mut sources: Vec<String> = container
+        .config
+        .volumes
+        .values()
+        .filter(|source| !source.starts_with('/') && source.storage_name().is_none())
+        .map(|source| source.0.clone())
+        .collect();
+    sources.sort();
+    sources.dedup();
+    sources
+}
+
+/// Get the directory to write a backup to
+///
+/// This is the location of the given Juju storage if `storage_name` is set, `dir` if that is set,
+/// or the `backups` dir in the Lucky data dir otherwise.
+fn backup_dir(
+    daemon: &LuckyDaemon,
+    storage_name: Option<&str>,
+    dir: Option<&str>,
+) -> anyhow::Result<PathBuf> {
+    if let Some(storage_name) = storage_name {
+        let storage_id = daemon
+            .juju
+            .storage_list(storage_name)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::format_err!("Storage {} is not attached", storage_name))?;
+        Ok(daemon.juju.storage_get_location(&storage_id)?.into())
+    } else if let Some(dir) = dir {
+        Ok(dir.into())
+    } else {
+        Ok(daemon.lucky_data_dir.join("backups"))
+    }
+}
+
+/// Run `f` with the container stopped, starting it again afterwards if it was running
+fn with_container_stopped<T>(
+    daemon: &LuckyDaemon,
+    container: &ContainerInfo,
+    f: impl FnOnce() -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let id = match &container.id {
+        Some(id) if !container.stopped => id,
+        _ => return f(),
+    };
+
+    let engine = daemon.get_container_engine()?;
+    crash_monitor::expect_exit(daemon, id);
+    engine.stop_container(id, Some(STOP_TIMEOUT))?;
+
+    let result = f();
+
+    engine
+        .start_container(id)
+        .context("Could not start container after backup")?;
+
+    result
+}
+
+/// Back up the Lucky managed volumes of the container, returning the path to the backup
+pub(super) fn backup_container(
+    daemon: &LuckyDaemon,
+    container_name: Option<&str>,
+    storage_name: Option<&str>,
+    dir: Option<&str>,
+) -> anyhow::Result<PathBuf> {
+    let state = daemon.state.read().unwrap();
+    let container = get_container(&state, container_name)?;
+    let sources = managed_volume_sources(container);
+    if sources.is_empty() {
+        anyhow::bail!(
+            "Container {} doesn't have any volumes managed by Lucky to back up",
+            container_name.unwrap_or(DEFAULT_CONTAINER_NAME)
+        );
+    }
+
+    let backup_dir = backup_dir(daemon, storage_name, dir)?;
+    std::fs::create_dir_all(&backup_dir)
+        .context(format!("Could not create backup dir: {:?}", backup_dir))?;
+    let backup_path = backup_dir.join(format!(
+        "{}-{}.tar.gz",
+        container_name.unwrap_or(DEFAULT_CONTAINER_NAME),
+        chrono::Utc::now().format("%Y%m%dT%H%M%SZ")
+    ));
+
+    let volume_dir = daemon.lucky_data_dir.join(VOLUME_DIR);
+    let backup_path_str = backup_path.to_string_lossy();
+    let volume_dir_str = volume_dir.to_string_lossy();
+    let mut args = vec!["-czf", &backup_path_str, "-C", &volume_dir_str, "--"];
+    args.extend(sources.iter().map(String::as_str));
+
+    log::info!("Backing up container volumes to {:?}", backup_path);
+    with_container_stopped(daemon, container, || {
+        // Make sure the volume dirs exist so that containers that haven't been run can be backed up
+        for source in &sources {
+            std::fs::create_dir_all(volume_dir.join(source))?;
+        }
+        run_cmd("tar", &args).context("Could not create backup")
+    })?;
+
+    Ok(backup_path)
+}
+
+/// Restore the Lucky managed volumes of the container from a backup, replacing their data
+///
+/// Only the volumes that are both in the backup and still used by the container are restored.
+pub(super) fn restore_container(
+    daemon: &LuckyDaemon,
+    container_name: Option<&str>,
+    backup_path: &Path,
+) -> anyhow::Result<()> {
+    if !backup_path.is_file() {
+        anyhow::bail!("Backup does not exist: {:?}", backup_path);
+    }
+
+    let state = daemon.state.read().unwrap();
+    let container = get_container(&state, container_name)?;
+
+    // Get the volumes that are in the backup
+    let backup_path_str = backup_path.to_string_lossy();
+    let members = run_cmd("tar", &["-tzf", &backup_path_str]).context("Could not read backup")?;
+    let sources: Vec<String> = managed_volume_sources(container)
+        .into_iter()
+        .filter(|source| {
+            members
+                .lines()
+                .any(|x| x.trim_end_matches('/') == source.as_str())
+        })
+        .collect();
+    if sources.is_empty() {
+        anyhow::bail!(
+            "The backup doesn't contain any of the volumes of container {}",
+            container_name.unwrap_or(DEFAULT_CONTAINER_NAME)
+        );
+    }
+
+    let volume_dir = daemon.lucky_data_dir.join(VOLUME_DIR);
+    let volume_dir_str = volume_dir.to_string_lossy();
+    let mut args = vec!["-xzf", &backup_path_str, "-C", &volume_dir_str, "--"];
+    args.extend(sources.iter().map(String::as_str));
+
+    log::info!("Restoring container volumes from {:?}", backup_path);
+    with_container_stopped(daemon, container, || {
+        // Clear the volumes so that files created since the backup don't stay around
+        for source in &sources {
+            let path = volume_dir.join(source);
+            if path.exists() {
+                std::fs::remove_dir_all(&path)
+                    .context(format!("Could not clear volume: {:?}", path))?;
+            }
+        }
+        run_cmd("tar", &args).context("Could not restore backup")
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,353 @@
+//! Container log streaming and forwarding to the Juju debug log
+//!
+//! Containers declared with `forward-logs: true` in the `lucky.yaml` have their logs followed in
+//! the background with the `logs` command of the container engine's command line tool. Because `juju-log` only works inside of a Juju context, the
+//! lines are buffered and written to the Juju log at the end of every hook and cron tick. Each line
+//! is logged at the level that it appears to be logged at by the container, and lines that come in
+//! faster than the rate limit are dropped so that a noisy container can't flood the debug log.
+
+use anyhow::{bail, format_err, Context};
+use crossbeam::channel::{unbounded, Receiver};
+use subprocess::{Popen, Redirection};
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use super::*;
+use crate::juju::juju_log_with_level;
+use crate::types::DEFAULT_CONTAINER_NAME;
+
+/// The maximum number of lines per second that will be forwarded from each container
+const MAX_LINES_PER_SECOND: u32 = 20;
+/// The maximum number of lines that will be buffered for each container between flushes
+const MAX_BUFFERED_LINES: usize = 1000;
+
+/// The stream that a container log line was written to
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(super) enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    /// The name of the stream, as given to clients
+    pub fn name(self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+        }
+    }
+}
+
+/// A process following the logs of a container
+///
+/// The process is killed when this is dropped.
+pub(super) struct LogProcess {
+    process: Popen,
+}
+
+impl LogProcess {
+    /// Start following the logs of the given container, returning the process and a channel that
+    /// receives the lines written by the container, tagged with their stream
+    ///
+    /// `since` is a Unix timestamp to get the logs since and `tail` is the number of lines to get
+    /// from the end of the existing logs. If `follow` is `true` new lines will keep coming until
+    /// the container is removed.
+    pub fn start(
+        engine: &dyn ContainerEngine,
+        container_id: &str,
+        follow: bool,
+        since: Option<u64>,
+        tail: Option<u64>,
+    ) -> anyhow::Result<(Self, Receiver<(LogStream, String)>)> {
+        let mut args = vec!["logs".to_string()];
+        if follow {
+            args.push("--follow".into());
+        }
+        if let Some(since) = since {
+            args.push(format!("--since={}", since));
+        }
+        if let Some(tail) = tail {
+            args.push(format!("--tail={}", tail));
+        }
+        args.push(container_id.into());
+
+        let mut process = engine
+            .cli_command()
+            .args(&args)
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe)
+            .popen()
+            .context(format!("Could not get {} container logs", engine.name()))?;
+
+        // Read the stdout and stderr of the container in separate threads
+        let (sender, lines) = unbounded();
+        let pipes = vec![
+            (LogStream::Stdout, process.stdout.take()),
+            (LogStream::Stderr, process.stderr.take()),
+        ];
+        for (stream, pipe) in pipes {
+            let pipe = pipe.ok_or_else(|| format_err!("Could not read container logs output"))?;
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                for line in BufReader::new(pipe).lines() {
+                    match line {
+                        // Stop reading if the receiver has gone away
+                        Ok(line) => {
+                            if sender.send((stream, line)).is_err() {
+                                break;
+                            }
+                        }
+                        Err(_) => break,
+                    }
+                }
+            });
+        }
+
+        Ok((LogProcess { process }, lines))
+    }
+}
+
+impl Drop for LogProcess {
+    fn drop(&mut self) {
+        if self.process.poll().is_none() {
+            self.process.kill().ok();
+            self.process.wait().ok();
+        }
+    }
+}
+
+/// Start following the logs of a container, returning the process and the channel that receives
+/// its lines
+pub(super) fn start_container_logs(
+    daemon: &LuckyDaemon,
+    container_name: Option<&str>,
+    follow: bool,
+    tail: Option<u64>,
+) -> anyhow::Result<(LogProcess, Receiver<(LogStream, String)>)> {
+    if !daemon.docker_enabled() {
+        bail!("Container logs can only be read when containers are enabled for the charm");
+    }
+
+    // Get the ID of the container
+    let container_id = {
+        let state = daemon.state.read().unwrap();
+        let container = match container_name {
+            Some(name) => state.named_containers.get(name),
+            None => state.default_container.as_ref(),
+        }
+        .ok_or_else(|| {
+            format_err!(
+                r#"Container "{}" does not exist"#,
+                container_name.unwrap_or("default")
+            )
+        })?;
+        container.id.clone().ok_or_else(|| {
+            format_err!(
+                r#"Container "{}" has not been created yet"#,
+                container_name.unwrap_or("default")
+            )
+        })?
+    };
+
+    LogProcess::start(
+        &*daemon.get_container_engine()?,
+        &container_id,
+        follow,
+        None,
+        tail,
+    )
+}
+
+/// The log forwarders of the containers, keyed by container name
+pub(super) struct LogForwarders {
+    /// The Unix time that the daemon started. Forwarders only forward logs from after this time so
+    /// that lines aren't forwarded again when the daemon restarts.
+    started: u64,
+    forwarders: HashMap<String, LogForwarder>,
+}
+
+impl Default for LogForwarders {
+    fn default() -> Self {
+        LogForwarders {
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or_default(),
+            forwarders: HashMap::new(),
+        }
+    }
+}
+
+/// Forwards the logs of one container
+struct LogForwarder {
+    /// The ID of the container being followed
+    container_id: String,
+    /// The lines waiting to be written to the Juju log
+    buffer: Arc<Mutex<LogBuffer>>,
+    /// Kept so that the logs process is killed when the forwarder is removed
+    _process: LogProcess,
+}
+
+/// The lines of a container's log that are waiting to be written to the Juju log
+#[derive(Default)]
+struct LogBuffer {
+    lines: VecDeque<(&'static str, String)>,
+    /// The number of lines that have been dropped since the last flush
+    dropped: usize,
+    /// The start of the current rate limit window
+    window_start: Option<Instant>,
+    /// The number of lines received in the current rate limit window
+    window_lines: u32,
+}
+
+impl LogBuffer {
+    /// Add a line to the buffer, dropping it if the container is over the rate limit
+    fn push(&mut self, stream: LogStream, line: String) {
+        let now = Instant::now();
+        match self.window_start {
+            Some(start) if now.duration_since(start).as_secs() < 1 => (),
+            _ => {
+                self.window_start = Some(now);
+                self.window_lines = 0;
+            }
+        }
+        self.window_lines += 1;
+
+        if self.window_lines > MAX_LINES_PER_SECOND || self.lines.len() >= MAX_BUFFERED_LINES {
+            self.dropped += 1;
+        } else {
+            self.lines.push_back((detect_level(stream, &line), line));
+        }
+    }
+}
+
+/// Guess the Juju log level of a line from its content, defaulting to `WARNING` for stderr and
+/// `INFO` for stdout
+fn detect_level(stream: LogStream, line: &str) -> &'static str {
+    let upper = line.to_uppercase();
+    if ["ERROR", "FATAL", "CRITICAL", "PANIC"]
+        .iter()
+        .any(|x| upper.contains(x))
+    {
+        "ERROR"
+    } else if upper.contains("WARN") {
+        "WARNING"
+    } else if upper.contains("DEBUG") || upper.contains("TRACE") {
+        "DEBUG"
+    } else if upper.contains("INFO") {
+        "INFO"
+    } else if stream == LogStream::Stderr {
+        "WARNING"
+    } else {
+        "INFO"
+    }
+}
+
+/// Start forwarding the logs of the containers that have `forward-logs` enabled, and stop
+/// forwarding the logs of containers that have been removed, re-created, or no longer have it
+/// enabled
+pub(super) fn sync_forwarders(daemon: &LuckyDaemon, state: &DaemonState) {
+    let mut forwarders = daemon.log_forwarders.lock().unwrap();
+    let since = forwarders.started;
+
+    // Get the IDs of the containers that should have their logs forwarded
+    let mut wanted = HashMap::new();
+    for (name, spec) in &daemon.lucky_metadata.containers {
+        if !spec.forward_logs {
+            continue;
+        }
+        let container = if name == DEFAULT_CONTAINER_NAME {
+            state.default_container.as_ref()
+        } else {
+            state.named_containers.get(name)
+        };
+        if let Some(id) = container.and_then(|x| x.id.clone()) {
+            wanted.insert(name.clone(), id);
+        }
+    }
+
+    // Stop the forwarders that are no longer wanted, keeping their buffered lines
+    let mut previous_buffers = HashMap::new();
+    forwarders.forwarders.retain(|name, forwarder| {
+        if wanted.get(name) == Some(&forwarder.container_id) {
+            true
+        } else {
+            log::debug!("Stopping log forwarding for container {}", name);
+            previous_buffers.insert(name.clone(), forwarder.buffer.clone());
+            false
+        }
+    });
+
+    for (name, id) in wanted {
+        if forwarders.forwarders.contains_key(&name) {
+            continue;
+        }
+
+        log::debug!("Forwarding logs for container {}", name);
+        let started = daemon
+            .get_container_engine()
+            .and_then(|engine| LogProcess::start(&*engine, &id, true, Some(since), None));
+        let (process, lines) = match started {
+            Ok(started) => started,
+            Err(e) => {
+                log::warn!("Could not forward logs of container {}: {:?}", name, e);
+                continue;
+            }
+        };
+
+        // Buffer the lines from the container in a background thread
+        let buffer = previous_buffers.remove(&name).unwrap_or_default();
+        let buffer_ = buffer.clone();
+        std::thread::spawn(move || {
+            // This ends when the process is killed or the container is removed
+            for (stream, line) in lines {
+                buffer_.lock().unwrap().push(stream, line);
+            }
+        });
+
+        forwarders.forwarders.insert(
+            name,
+            LogForwarder {
+                container_id: id,
+                buffer,
+                _process: process,
+            },
+        );
+    }
+}
+
+/// Write the buffered container log lines to the Juju log
+///
+/// This must be run in a Juju context, such as during a hook or cron tick.
+pub(super) fn flush_forwarded_logs(daemon: &LuckyDaemon) {
+    let forwarders = daemon.log_forwarders.lock().unwrap();
+
+    for (name, forwarder) in &forwarders.forwarders {
+        let (lines, dropped) = {
+            let mut buffer = forwarder.buffer.lock().unwrap();
+            let dropped = buffer.dropped;
+            buffer.dropped = 0;
+            (buffer.lines.drain(..).collect::<Vec<_>>(), dropped)
+        };
+
+        for (level, line) in lines {
+            juju_log_with_level(
+                &crate::log::redact(&format!("[container {}] {}", name, line)),
+                level,
+            );
+        }
+        if dropped > 0 {
+            juju_log_with_level(
+                &format!(
+                    "[container {}] Dropped {} log lines to stay under the rate limit of {} lines \
+                    per second",
+                    name, dropped, MAX_LINES_PER_SECOND
+                ),
+                "WARNING",
+            );
+        }
+    }
+}
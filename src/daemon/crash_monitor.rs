@@ -0,0 +1,278 @@
+//! Container crash loop detection
+//!
+//! The daemon follows the event stream of the container engine in the background and records every
+//! time a container exits without being stopped by Lucky. A container that exits too many times in
+//! a short period is crash looping, and gets its own script status with its restart count so that
+//! it shows up in the Juju status. Because the status can only be set inside of a Juju context, the
+//! statuses are updated at the end of every hook and cron tick.
+
+use anyhow::Context;
+use subprocess::{Popen, Redirection};
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::*;
+use crate::container_engine::parse_exit_event;
+use crate::types::{ScriptState, ScriptStatus, DEFAULT_CONTAINER_NAME};
+
+/// The number of exits within the `CRASH_LOOP_WINDOW` that make a container crash looping
+const CRASH_LOOP_EXITS: usize = 3;
+/// The period that a container has to exit `CRASH_LOOP_EXITS` times in to be crash looping. A
+/// crash looping container recovers once it stays up for this long.
+const CRASH_LOOP_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// How long after Lucky stops a container that the container's exit is ignored
+const EXPECTED_EXIT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Follows the container exit events and keeps track of which containers are crash looping
+#[derive(Default)]
+pub(super) struct CrashMonitor {
+    /// The process following the container engine events, if it has been started
+    process: Option<Popen>,
+    /// The exits recorded from the event stream
+    exits: Arc<Mutex<ExitRecords>>,
+    /// Whether or not each container was crash looping as of the last status update, keyed by
+    /// container name
+    crash_looping: HashMap<String, bool>,
+}
+
+impl Drop for CrashMonitor {
+    fn drop(&mut self) {
+        if let Some(process) = &mut self.process {
+            if process.poll().is_none() {
+                process.kill().ok();
+                process.wait().ok();
+            }
+        }
+    }
+}
+
+/// The container exits recorded from the container engine event stream
+#[derive(Default)]
+struct ExitRecords {
+    /// The exits of each container, keyed by container ID
+    containers: HashMap<String, ContainerExits>,
+    /// The containers that Lucky is stopping, keyed by container ID, with the time that they were
+    /// stopped
+    expected: HashMap<String, Instant>,
+}
+
+/// The exits of one container
+#[derive(Default)]
+struct ContainerExits {
+    /// The number of times that the container has exited since it was created
+    restarts: u32,
+    /// The times of the exits within the last `CRASH_LOOP_WINDOW`
+    recent: VecDeque<Instant>,
+    /// The exit code of the last exit, if the container engine reported it
+    last_exit_code: Option<i32>,
+}
+
+/// Get the script ID used for a container's crash loop status
+fn status_id(container_name: &str) -> String {
+    format!("__lucky::container_crash::{}__", container_name)
+}
+
+/// Start following the container exit events if they aren't being followed already, such as
+/// when the daemon has just started or the event process has died
+pub(super) fn ensure_monitoring(daemon: &LuckyDaemon) {
+    let mut monitor = daemon.crash_monitor.lock().unwrap();
+    if let Some(process) = &mut monitor.process {
+        if process.poll().is_none() {
+            return;
+        }
+    }
+
+    log::debug!("Following container exit events");
+    let started = daemon.get_container_engine().and_then(|engine| {
+        engine
+            .events_command()
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Merge)
+            .popen()
+            .context(format!("Could not follow {} events", engine.name()))
+    });
+    let mut process = match started {
+        Ok(process) => process,
+        Err(e) => {
+            log::warn!("Could not monitor containers for crashes: {:?}", e);
+            return;
+        }
+    };
+
+    // Record the exits in a background thread. This ends when the process is killed.
+    let stdout = process.stdout.take().expect("Stdout not opened");
+    let exits = monitor.exits.clone();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            let exit = match parse_exit_event(&line) {
+                Some(exit) => exit,
+                None => continue,
+            };
+
+            let mut exits = exits.lock().unwrap();
+            // Skip exits caused by Lucky stopping the container
+            if let Some(stopped) = exits.expected.remove(&exit.container_id) {
+                if stopped.elapsed() < EXPECTED_EXIT_TIMEOUT {
+                    continue;
+                }
+            }
+
+            log::debug!(
+                "Container {} exited with code {:?}",
+                exit.container_id,
+                exit.exit_code
+            );
+            let container = exits.containers.entry(exit.container_id).or_default();
+            container.restarts += 1;
+            container.recent.push_back(Instant::now());
+            container.last_exit_code = exit.exit_code;
+        }
+    });
+
+    monitor.process = Some(process);
+}
+
+/// Mark a container as being stopped by Lucky so that its exit isn't counted as a crash
+pub(super) fn expect_exit(daemon: &LuckyDaemon, container_id: &str) {
+    let monitor = daemon.crash_monitor.lock().unwrap();
+    let mut exits = monitor.exits.lock().unwrap();
+    exits
+        .expected
+        .insert(container_id.to_string(), Instant::now());
+}
+
+/// Update the crash loop statuses of the containers
+///
+/// This must be run in a Juju context, such as during a hook or cron tick.
+pub(super) fn update_crash_status(daemon: &LuckyDaemon) -> anyhow::Result<()> {
+    if !daemon.docker_enabled() {
+        return Ok(());
+    }
+
+    // Get the IDs of the containers
+    let containers: HashMap<String, String> = {
+        let state = daemon.state.read().unwrap();
+        state
+            .named_containers
+            .iter()
+            .map(|(name, container)| (name.clone(), container))
+            .chain(
+                state
+                    .default_container
+                    .iter()
+                    .map(|x| (DEFAULT_CONTAINER_NAME.to_string(), x)),
+            )
+            .filter_map(|(name, container)| container.id.clone().map(|id| (name, id)))
+            .collect()
+    };
+
+    // Start following the exit events if the daemon has restarted since the containers were
+    // applied
+    if !containers.is_empty() {
+        ensure_monitoring(daemon);
+    }
+
+    // Check which containers are crash looping and get the statuses that have changed. The
+    // monitor isn't kept locked while the statuses are set so that it can't deadlock with the
+    // daemon state.
+    let changes: Vec<(String, Option<String>)> = {
+        let monitor = daemon.crash_monitor.lock().unwrap();
+        let mut exits = monitor.exits.lock().unwrap();
+
+        // Forget the containers that have been removed
+        exits
+            .containers
+            .retain(|id, _| containers.values().any(|x| x == id));
+        exits
+            .expected
+            .retain(|_, stopped| stopped.elapsed() < EXPECTED_EXIT_TIMEOUT);
+
+        let mut changes = Vec::new();
+        for (name, id) in &containers {
+            let message = exits.containers.get_mut(id).and_then(|container| {
+                while container
+                    .recent
+                    .front()
+                    .map_or(false, |x| x.elapsed() > CRASH_LOOP_WINDOW)
+                {
+                    container.recent.pop_front();
+                }
+
+                if container.recent.len() >= CRASH_LOOP_EXITS {
+                    Some(format!(
+                        "{} container is crash looping: restarted {} times, last exit code {}",
+                        name,
+                        container.restarts,
+                        container
+                            .last_exit_code
+                            .map_or_else(|| "unknown".into(), |x| x.to_string())
+                    ))
+                } else {
+                    None
+                }
+            });
+
+            let was_crash_looping = monitor.crash_looping.get(name).copied().unwrap_or(false);
+            if message.is_some() != was_crash_looping {
+                changes.push((name.clone(), message));
+            }
+        }
+
+        // Clear the statuses of containers that have been removed
+        for (name, crash_looping) in &monitor.crash_looping {
+            if *crash_looping && !containers.contains_key(name) {
+                changes.push((name.clone(), None));
+            }
+        }
+
+        changes
+    };
+
+    for (name, message) in changes {
+        let crash_looping = message.is_some();
+        if let Some(message) = &message {
+            log::warn!("{}", message);
+        } else {
+            log::info!("Container {} is no longer crash looping", name);
+        }
+        set_crash_status(daemon, &name, message)?;
+
+        let mut monitor = daemon.crash_monitor.lock().unwrap();
+        if crash_looping || containers.contains_key(&name) {
+            monitor.crash_looping.insert(name, crash_looping);
+        } else {
+            monitor.crash_looping.remove(&name);
+        }
+    }
+
+    Ok(())
+}
+
+/// Set the crash loop status of a container, clearing it if `message` is `None`
+fn set_crash_status(
+    daemon: &LuckyDaemon,
+    container_name: &str,
+    message: Option<String>,
+) -> anyhow::Result<()> {
+    let mut state = daemon.state.write().unwrap();
+    tools::set_script_status(
+        &*daemon.juju,
+        &mut state,
+        &status_id(container_name),
+        ScriptStatus {
+            state: if message.is_some() {
+                ScriptState::Blocked
+            } else {
+                ScriptState::Active
+            },
+            message,
+        },
+    )
+}
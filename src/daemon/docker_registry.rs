@@ -0,0 +1,219 @@
+//! Built-in support for the `docker-registry` relation interface
+//!
+//! When a charm requires a relation with the `docker-registry` interface, Lucky reads the address
+//! and credentials of the related registry from the relation data and uses them to pull the images
+//! that are hosted on it. Like other registry credentials, the username and password are never
+//! stored: only the relation that they come from is kept in the daemon state, and they are read
+//! from the relation data every time an image is pulled, so new credentials are used as soon as
+//! the registry changes them.
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::PathBuf;
+
+use super::*;
+use crate::docker::{RegistryAuth, RegistryAuthSource};
+use crate::types::juju::CharmMetadata;
+
+/// The name of the relation interface served by registry charms
+const DOCKER_REGISTRY_INTERFACE: &str = "docker-registry";
+/// The relation key with the `host:port` address of the registry
+const NETLOC_KEY: &str = "registry_netloc";
+/// The relation key with the URL of the registry, used if the address isn't set
+const URL_KEY: &str = "registry_url";
+/// The relation key with the username for the registry, if it requires authentication
+const USERNAME_KEY: &str = "basic_user";
+/// The relation key with the password for the registry, if it requires authentication
+const PASSWORD_KEY: &str = "basic_password";
+/// The relation key with the certificate of the CA that signed the registry's TLS certificate
+const TLS_CA_KEY: &str = "tls_ca";
+
+/// The registry that the charm is related to over the `docker-registry` interface
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(super) struct RegistryRelation {
+    /// The ID of the relation to the registry
+    pub relation_id: String,
+    /// The registry unit that the relation data is read from
+    pub remote_unit: String,
+    /// The `host:port` address of the registry, as used in image names
+    pub netloc: String,
+    /// Whether or not the registry requires a username and password
+    pub authenticated: bool,
+}
+
+impl RegistryRelation {
+    /// Get the registry auth that reads the credentials from the relation data, if the registry
+    /// requires authentication
+    fn auth(&self) -> Option<RegistryAuth> {
+        if !self.authenticated {
+            return None;
+        }
+
+        Some(RegistryAuth {
+            source: RegistryAuthSource::Relation {
+                relation_id: self.relation_id.clone(),
+                remote_unit: self.remote_unit.clone(),
+                app: false,
+            },
+            username_key: USERNAME_KEY.into(),
+            password_key: PASSWORD_KEY.into(),
+            server_address: Some(self.netloc.clone()),
+        })
+    }
+}
+
+/// Get the auth used to pull a container's image: the container's own registry auth if it has
+/// one, or the auth of the related registry if the image is hosted on it
+pub(super) fn image_registry_auth(
+    container: &ContainerInfo,
+    registry: Option<&RegistryRelation>,
+) -> Option<RegistryAuth> {
+    if container.registry_auth.is_some() {
+        return container.registry_auth.clone();
+    }
+
+    registry
+        .filter(|registry| {
+            image_registry(&container.config.image) == Some(registry.netloc.as_str())
+        })
+        .and_then(RegistryRelation::auth)
+}
+
+/// Update the related registry from the relation data in a `docker-registry` relation hook
+///
+/// This does nothing for the hooks of other relations.
+pub(super) fn handle_relation_hook(daemon: &LuckyDaemon, hook_name: &str) -> anyhow::Result<()> {
+    let relation_name = match std::env::var("JUJU_RELATION") {
+        Ok(name) => name,
+        Err(_) => return Ok(()),
+    };
+    if !registry_relation_names(daemon)?.contains(&relation_name) {
+        return Ok(());
+    }
+    let relation_id =
+        std::env::var("JUJU_RELATION_ID").context("Env var JUJU_RELATION_ID not readable!")?;
+
+    // Forget the registry when it leaves the relation
+    if hook_name.ends_with("-relation-departed") || hook_name.ends_with("-relation-broken") {
+        let remote_unit = std::env::var("JUJU_REMOTE_UNIT").ok();
+        let mut state = daemon.state.write().unwrap();
+        let departed = state.docker_registry.as_ref().map_or(false, |registry| {
+            registry.relation_id == relation_id
+                && (hook_name.ends_with("-relation-broken")
+                    || remote_unit.as_ref() == Some(&registry.remote_unit))
+        });
+        if departed {
+            log::info!("No longer using the related docker registry");
+            state.docker_registry = None;
+        }
+
+        return Ok(());
+    }
+
+    if !hook_name.ends_with("-relation-joined") && !hook_name.ends_with("-relation-changed") {
+        return Ok(());
+    }
+    let remote_unit =
+        std::env::var("JUJU_REMOTE_UNIT").context("Env var JUJU_REMOTE_UNIT not readable!")?;
+    let data = daemon.juju.relation_get(None, false)?;
+    let get_value = |key: &str| data.get(key).filter(|x| !x.is_empty());
+
+    // Wait for the registry to send its address
+    let netloc = match get_value(NETLOC_KEY)
+        .cloned()
+        .or_else(|| get_value(URL_KEY).map(String::as_str).and_then(url_netloc))
+    {
+        Some(netloc) => netloc,
+        None => {
+            log::debug!(
+                "Waiting for the docker registry on relation {} to send its address",
+                relation_id
+            );
+            return Ok(());
+        }
+    };
+
+    // Trust the CA of the registry's certificate
+    if let Some(ca) = get_value(TLS_CA_KEY) {
+        write_registry_ca(daemon, &netloc, ca)?;
+    }
+
+    let registry = RegistryRelation {
+        relation_id,
+        remote_unit,
+        netloc,
+        authenticated: get_value(USERNAME_KEY).is_some() && get_value(PASSWORD_KEY).is_some(),
+    };
+    let mut state = daemon.state.write().unwrap();
+    if state.docker_registry.as_ref() != Some(&registry) {
+        log::info!(
+            "Using the related docker registry for images from {}",
+            registry.netloc
+        );
+        state.docker_registry = Some(registry);
+    }
+
+    Ok(())
+}
+
+//
+// Helpers
+//
+
+/// Get the names of the relations in the charm's metadata.yaml that require a docker registry
+fn registry_relation_names(daemon: &LuckyDaemon) -> anyhow::Result<Vec<String>> {
+    let metadata: CharmMetadata = crate::config::load_yaml(&daemon.charm_dir, "metadata")
+        .context("Could not load charm metadata")?;
+
+    Ok(metadata
+        .requires
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(_, relation)| relation.interface == DOCKER_REGISTRY_INTERFACE)
+        .map(|(name, _)| name)
+        .collect())
+}
+
+/// Get the registry that an image is hosted on, such as `10.0.0.5:5000` for
+/// `10.0.0.5:5000/my-app:latest`, or `None` for images on Docker Hub
+fn image_registry(image: &str) -> Option<&str> {
+    let mut parts = image.splitn(2, '/');
+    match (parts.next(), parts.next()) {
+        (Some(host), Some(_)) if host.contains(|c| c == '.' || c == ':') || host == "localhost" => {
+            Some(host)
+        }
+        _ => None,
+    }
+}
+
+/// Get the `host:port` part of a URL such as `https://10.0.0.5:5000/v2`
+fn url_netloc(url: &str) -> Option<String> {
+    let rest = url.splitn(2, "://").last()?;
+    rest.split('/')
+        .next()
+        .filter(|x| !x.is_empty())
+        .map(Into::into)
+}
+
+/// Write the CA certificate of a registry to where the container engine looks for it
+fn write_registry_ca(daemon: &LuckyDaemon, netloc: &str, ca: &str) -> anyhow::Result<()> {
+    let certs_dir = match *daemon.container_engine_kind.read().unwrap() {
+        ContainerEngineKind::Podman => "/etc/containers/certs.d",
+        // nerdctl reads the Docker certificate directory too
+        ContainerEngineKind::Docker | ContainerEngineKind::Containerd => "/etc/docker/certs.d",
+    };
+    let dir = PathBuf::from(certs_dir).join(netloc);
+    let path = dir.join("ca.crt");
+
+    if fs::read_to_string(&path).ok().as_deref() == Some(ca) {
+        return Ok(());
+    }
+
+    log::debug!("Writing CA certificate for docker registry {}", netloc);
+    fs::create_dir_all(&dir).context(format!("Could not create dir: {:?}", dir))?;
+    fs::write(&path, ca).context(format!("Could not write CA certificate: {:?}", path))?;
+
+    Ok(())
+}
@@ -0,0 +1,214 @@
+//! The download cache used by `lucky fetch`
+//!
+//! Downloads are kept in a cache directory that is shared by all of the units on the machine. Each
+//! download is keyed by its URL and expected SHA-256 hash, so a charm that fetches the same file on
+//! every retry or upgrade only downloads it once. Interrupted downloads are resumed the next time
+//! the file is fetched.
+
+use anyhow::{bail, Context};
+use sha2::{Digest, Sha256};
+use subprocess::{Exec, ExitStatus, Redirection};
+use thiserror::Error;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The directory that downloads are cached in
+const DOWNLOAD_CACHE_DIR: &str = "/var/lib/lucky/download_cache";
+/// The name of the completed download in its cache dir
+const DOWNLOAD_FILE_NAME: &str = "download";
+/// The name of the file that the download is written to until it is complete
+const PARTIAL_FILE_NAME: &str = "download.partial";
+/// The name of the file that the SHA-256 hash of the completed download is stored in
+const HASH_FILE_NAME: &str = "download.sha256";
+/// The name of the lock file that makes sure only one unit downloads the file at a time
+const LOCK_FILE_NAME: &str = "lock";
+/// How long to wait before checking a held lock again
+const LOCK_WAIT_INTERVAL: Duration = Duration::from_millis(500);
+/// The curl exit code for when the server can't resume a download
+const CURL_CANNOT_RESUME: u32 = 33;
+
+/// Download the file at the given URL to the cache, if it is not already cached, and return the
+/// path to the file and its SHA-256 hash
+///
+/// If `expected_sha256` is given, the download will fail if its hash does not match.
+pub(super) fn fetch(url: &str, expected_sha256: Option<&str>) -> anyhow::Result<(PathBuf, String)> {
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        bail!("Only http and https URLs can be fetched: {}", url);
+    }
+    let expected_sha256 = expected_sha256.map(str::to_lowercase);
+    if let Some(hash) = &expected_sha256 {
+        if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("Invalid SHA-256 hash: {}", hash);
+        }
+    }
+
+    // Get the cache dir for the download
+    let key = hex_digest(Sha256::digest(
+        format!("{}\n{}", url, expected_sha256.as_deref().unwrap_or("")).as_bytes(),
+    ));
+    let cache_dir = Path::new(DOWNLOAD_CACHE_DIR).join(key);
+    fs::create_dir_all(&cache_dir).context(format!(
+        "Could not create download cache dir: {:?}",
+        cache_dir
+    ))?;
+    let download_path = cache_dir.join(DOWNLOAD_FILE_NAME);
+    let partial_path = cache_dir.join(PARTIAL_FILE_NAME);
+    let hash_path = cache_dir.join(HASH_FILE_NAME);
+
+    // Make sure that no other unit is downloading the file
+    let _lock = CacheLock::acquire(&cache_dir.join(LOCK_FILE_NAME))?;
+
+    // Return the cached file if it has already been downloaded
+    if download_path.exists() {
+        if let Ok(hash) = fs::read_to_string(&hash_path) {
+            log::debug!("Using cached download of {}", url);
+            return Ok((download_path, hash.trim().into()));
+        }
+    }
+
+    log::info!("Downloading {}", url);
+    if let Err(e) = curl(url, &partial_path, true) {
+        // Start over if the server doesn't support resuming the download
+        if e.downcast_ref::<CannotResume>().is_some() {
+            log::debug!("Server cannot resume download, starting over: {}", url);
+            fs::remove_file(&partial_path)?;
+            curl(url, &partial_path, false)?;
+        } else {
+            return Err(e);
+        }
+    }
+
+    // Verify the download
+    let hash = hash_file(&partial_path)?;
+    if let Some(expected) = &expected_sha256 {
+        if &hash != expected {
+            // Remove the download so that it isn't resumed the next time it is fetched
+            fs::remove_file(&partial_path)?;
+            bail!(
+                "Download of {} has SHA-256 hash {} but {} was expected",
+                url,
+                hash,
+                expected
+            );
+        }
+    }
+
+    // Mark the download complete
+    fs::write(&hash_path, &hash)?;
+    fs::rename(&partial_path, &download_path)?;
+
+    Ok((download_path, hash))
+}
+
+/// The error returned by `curl()` when the server can't resume the download
+#[derive(Error, Debug)]
+#[error("The server cannot resume the download")]
+struct CannotResume;
+
+/// Download a file with curl, resuming the download if the output file already exists and
+/// `resume` is `true`
+fn curl(url: &str, output: &Path, resume: bool) -> anyhow::Result<()> {
+    let mut args = vec![
+        "--fail",
+        "--location",
+        "--silent",
+        "--show-error",
+        "--retry",
+        "3",
+    ];
+    if resume {
+        args.extend(&["--continue-at", "-"]);
+    }
+    args.push("--output");
+    let output = output.to_string_lossy();
+    args.push(&output);
+    args.push(url);
+
+    let capture = Exec::cmd("curl")
+        .args(&args)
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .context("Could not run curl")?;
+
+    match capture.exit_status {
+        ExitStatus::Exited(0) => Ok(()),
+        ExitStatus::Exited(CURL_CANNOT_RESUME) => Err(CannotResume.into()),
+        status => bail!(
+            "Could not download {} ( {:?} ): {}",
+            url,
+            status,
+            capture.stdout_str().trim()
+        ),
+    }
+}
+
+/// Get the SHA-256 hash of a file as a hex string
+fn hash_file(path: &Path) -> anyhow::Result<String> {
+    let mut file = File::open(path).context(format!("Could not open file: {:?}", path))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let count = file.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        hasher.update(buffer.get(..count).expect("Read more than the buffer size"));
+    }
+
+    Ok(hex_digest(hasher.finalize()))
+}
+
+/// Format a digest as a hex string
+fn hex_digest(digest: impl std::fmt::LowerHex) -> String {
+    format!("{:x}", digest)
+}
+
+/// A lock on a download in the cache. The lock is released when it is dropped.
+///
+/// The lock file contains the PID of the process holding the lock so that locks left behind by
+/// processes that have died can be cleared.
+struct CacheLock {
+    path: PathBuf,
+}
+
+impl CacheLock {
+    /// Acquire the lock, waiting for other processes to release it
+    fn acquire(path: &Path) -> anyhow::Result<Self> {
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(path) {
+                Ok(mut file) => {
+                    write!(file, "{}", std::process::id())?;
+                    return Ok(CacheLock { path: path.into() });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    // Clear the lock if the process that held it is gone
+                    let holder = fs::read_to_string(path).unwrap_or_default();
+                    let holder = holder.trim();
+                    if !holder.is_empty() && !Path::new("/proc").join(holder).exists() {
+                        log::debug!("Clearing stale download lock: {:?}", path);
+                        fs::remove_file(path).ok();
+                        continue;
+                    }
+
+                    log::debug!("Waiting for another unit to finish downloading");
+                    std::thread::sleep(LOCK_WAIT_INTERVAL);
+                }
+                Err(e) => {
+                    return Err(e).context(format!("Could not create download lock: {:?}", path))
+                }
+            }
+        }
+    }
+}
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).unwrap_or_else(|e| {
+            log::warn!("Could not remove download lock {:?}: {}", self.path, e);
+        });
+    }
+}
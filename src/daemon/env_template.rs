@@ -0,0 +1,286 @@
+//! Rendering of templated container environment variables and files
+//!
+//! Templated environment variables and files are Handlebars templates that can reference the charm
+//! config and relation data with the `config` and `relation` helpers:
+//!
+//! * `{{config "key"}}` is the value of the `key` charm config option
+//! * `{{relation "db" "key"}}` is the value of `key` in the relation data of the first remote unit
+//!   of the first `db` relation. The relation can also be given by ID, such as `db:3`.
+//! * `{{relation "db:3" "postgresql/0" "key"}}` is the value of `key` in the relation data of the
+//!   given remote unit
+//!
+//! * `{{network "private-address"}}` is the unit's private address, and
+//!   `{{network "public-address"}}` is its public address
+//!
+//! Relation data that isn't available yet, such as before the relation is joined, renders as an
+//! empty string.
+//!
+//! Templated files, script `env` vars, and templates rendered with `lucky render-template`, can also
+//! reference the unit key-value store with `{{kv "key"}}`, which renders as an empty string if the
+//! key isn't set.
+
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext, RenderError,
+};
+use serde_json::Value as JsonValue;
+
+use std::collections::HashMap;
+
+use crate::juju::{JujuBackend, SpecificRelation};
+
+/// Render the given templates to get the values of the environment variables
+pub(super) fn render_env_templates(
+    juju: &dyn JujuBackend,
+    templates: &HashMap<String, String>,
+) -> anyhow::Result<HashMap<String, String>> {
+    let config = juju.config_get()?;
+    let handlebars = new_registry(juju, &config);
+
+    render_env_values(&handlebars, templates)
+}
+
+/// Render the templated `env` of a script, with access to the given unit key-value store
+pub(super) fn render_script_env(
+    juju: &dyn JujuBackend,
+    kv: &HashMap<String, String>,
+    templates: &HashMap<String, String>,
+) -> anyhow::Result<HashMap<String, String>> {
+    let config = juju.config_get()?;
+    let mut handlebars = new_registry(juju, &config);
+    handlebars.register_helper("kv", Box::new(KvHelper { kv }));
+
+    render_env_values(&handlebars, templates)
+}
+
+/// Render a templated file, with access to the given unit key-value store
+pub(super) fn render_file_template(
+    juju: &dyn JujuBackend,
+    kv: &HashMap<String, String>,
+    template_name: &str,
+    template: &str,
+) -> anyhow::Result<String> {
+    let config = juju.config_get()?;
+    let mut handlebars = new_registry(juju, &config);
+    handlebars.register_helper("kv", Box::new(KvHelper { kv }));
+
+    handlebars
+        .render_template(template, &())
+        .map_err(|e| anyhow::format_err!("Could not render template {}: {}", template_name, e))
+}
+
+/// Render the environment variable templates with the given registry
+fn render_env_values(
+    handlebars: &Handlebars,
+    templates: &HashMap<String, String>,
+) -> anyhow::Result<HashMap<String, String>> {
+    templates
+        .iter()
+        .map(|(key, template)| {
+            let value = handlebars.render_template(template, &()).map_err(|e| {
+                anyhow::format_err!("Could not render template for env var {}: {}", key, e)
+            })?;
+            Ok((key.clone(), value))
+        })
+        .collect()
+}
+
+/// Create a template registry with the helpers that every template can use
+fn new_registry<'a>(
+    juju: &'a dyn JujuBackend,
+    config: &'a HashMap<String, JsonValue>,
+) -> Handlebars<'a> {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(handlebars::no_escape);
+    handlebars.set_strict_mode(true);
+    handlebars.register_helper("config", Box::new(ConfigHelper { config }));
+    handlebars.register_helper("relation", Box::new(RelationHelper { juju }));
+    handlebars.register_helper("network", Box::new(NetworkHelper { juju }));
+
+    handlebars
+}
+
+/// Get a string parameter of a helper
+fn string_param<'a>(h: &'a Helper, index: usize) -> Result<&'a str, RenderError> {
+    h.param(index)
+        .and_then(|x| x.value().as_str())
+        .ok_or_else(|| {
+            RenderError::new(format!(
+                "The {} helper requires string parameter {}",
+                h.name(),
+                index + 1
+            ))
+        })
+}
+
+/// The `config` template helper
+struct ConfigHelper<'a> {
+    config: &'a HashMap<String, JsonValue>,
+}
+
+impl HelperDef for ConfigHelper<'_> {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let key = string_param(h, 0)?;
+
+        match self.config.get(key) {
+            Some(JsonValue::String(value)) => out.write(value)?,
+            // Unset config options have no value
+            Some(JsonValue::Null) => (),
+            Some(value) => out.write(&value.to_string())?,
+            None => {
+                return Err(RenderError::new(format!(
+                    "Config option {:?} does not exist",
+                    key
+                )))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The `relation` template helper
+struct RelationHelper<'a> {
+    juju: &'a dyn JujuBackend,
+}
+
+impl RelationHelper<'_> {
+    /// Get a value from the relation data, returning `None` if the relation or remote unit doesn't
+    /// exist
+    fn get_value(
+        &self,
+        relation: &str,
+        remote_unit: Option<&str>,
+        key: &str,
+    ) -> anyhow::Result<Option<String>> {
+        // Get the relation ID from the relation name if necessary
+        let relation_id = if relation.contains(':') {
+            relation.to_owned()
+        } else {
+            match self.juju.relation_ids(relation)?.into_iter().next() {
+                Some(relation_id) => relation_id,
+                None => return Ok(None),
+            }
+        };
+
+        // Use the first remote unit if one wasn't given
+        let remote_unit = match remote_unit {
+            Some(remote_unit) => remote_unit.to_owned(),
+            None => match self
+                .juju
+                .relation_list(Some(relation_id.clone()))?
+                .into_iter()
+                .next()
+            {
+                Some(remote_unit) => remote_unit,
+                None => return Ok(None),
+            },
+        };
+
+        let mut data = self.juju.relation_get(
+            Some(SpecificRelation {
+                relation_id,
+                remote_unit,
+            }),
+            false,
+        )?;
+
+        Ok(data.remove(key))
+    }
+}
+
+impl HelperDef for RelationHelper<'_> {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let relation = string_param(h, 0)?;
+        let (remote_unit, key) = if h.params().len() > 2 {
+            (Some(string_param(h, 1)?), string_param(h, 2)?)
+        } else {
+            (None, string_param(h, 1)?)
+        };
+
+        let value = self
+            .get_value(relation, remote_unit, key)
+            .map_err(|e| RenderError::new(format!("Could not get relation data: {:?}", e)))?;
+        if let Some(value) = value {
+            out.write(&value)?;
+        } else {
+            log::debug!(
+                "Relation data {:?} is not available from relation {:?}",
+                key,
+                relation
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// The `kv` template helper
+struct KvHelper<'a> {
+    kv: &'a HashMap<String, String>,
+}
+
+impl HelperDef for KvHelper<'_> {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let key = string_param(h, 0)?;
+
+        if let Some(value) = self.kv.get(key) {
+            out.write(value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The `network` template helper
+struct NetworkHelper<'a> {
+    juju: &'a dyn JujuBackend,
+}
+
+impl HelperDef for NetworkHelper<'_> {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let name = string_param(h, 0)?;
+
+        let address = match name {
+            "private-address" => self.juju.unit_get_private_address(),
+            "public-address" => self.juju.unit_get_public_address(),
+            _ => {
+                return Err(RenderError::new(format!(
+                    "Unknown network value {:?}, expected private-address or public-address",
+                    name
+                )))
+            }
+        }
+        .map_err(|e| RenderError::new(format!("Could not get {}: {:?}", name, e)))?;
+        out.write(&address)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+//! Container health checks
+//!
+//! Containers declared in the `lucky.yaml` can have a health check that the daemon runs every time
+//! the cron tick comes around after the check's interval has passed. Each container with a health
+//! check gets its own script status, so an unhealthy workload shows up in the Juju status as soon
+//! as it fails more checks in a row than it is allowed to.
+
+use anyhow::bail;
+use subprocess::{Exec, ExitStatus, Redirection};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::*;
+use crate::trace::{self, Span};
+use crate::types::{HealthCheckSpec, ScriptState, ScriptStatus, DEFAULT_CONTAINER_NAME};
+
+/// How long to wait for an HTTP health check to respond
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The health of the containers, keyed by container name
+pub(super) type HealthMap = HashMap<String, ContainerHealth>;
+
+/// The health of one container, as of its last check
+#[derive(Default)]
+pub(super) struct ContainerHealth {
+    /// When the container was last checked
+    last_check: Option<Instant>,
+    /// The number of checks in a row that have failed
+    failures: u32,
+    /// Whether or not the container was healthy as of the last status update, or `None` if its
+    /// status has not been set yet
+    healthy: Option<bool>,
+}
+
+/// Get the script ID used for a container's health status
+fn status_id(container_name: &str) -> String {
+    format!("__lucky::container_health::{}__", container_name)
+}
+
+/// Run the health checks of the containers that are due for a check and update their status
+pub(super) fn run_health_checks(daemon: &LuckyDaemon) -> anyhow::Result<()> {
+    let mut health = daemon.container_health.lock().unwrap();
+
+    for (name, spec) in &daemon.lucky_metadata.containers {
+        let check = match &spec.healthcheck {
+            Some(check) => check,
+            None => continue,
+        };
+        let health = health.entry(name.clone()).or_default();
+
+        // Skip the check if its interval hasn't passed yet
+        if let Some(last_check) = health.last_check {
+            if last_check.elapsed() < Duration::from_secs(check.interval) {
+                continue;
+            }
+        }
+        health.last_check = Some(Instant::now());
+
+        let result = trace::in_span(
+            Span::start("health check").with_attr("container.name", name),
+            || run_health_check(daemon, name, check),
+        )?;
+
+        match result {
+            Ok(()) => health.failures = 0,
+            Err(reason) => {
+                health.failures += 1;
+                log::debug!(
+                    "Health check for container {} failed ( {}/{} ): {}",
+                    name,
+                    health.failures,
+                    check.retries,
+                    reason
+                );
+
+                // Only mark the container unhealthy once it has used up its retries
+                if health.failures >= check.retries.max(1) && health.healthy != Some(false) {
+                    log::warn!("Container {} is unhealthy: {}", name, reason);
+                    set_health_status(
+                        daemon,
+                        name,
+                        ScriptStatus {
+                            state: ScriptState::Blocked,
+                            message: Some(format!("{} container unhealthy: {}", name, reason)),
+                        },
+                    )?;
+                    health.healthy = Some(false);
+                }
+                continue;
+            }
+        }
+
+        // Only update the status when the container becomes healthy
+        if health.healthy != Some(true) {
+            log::info!("Container {} is healthy", name);
+            set_health_status(
+                daemon,
+                name,
+                ScriptStatus {
+                    state: ScriptState::Active,
+                    message: None,
+                },
+            )?;
+            health.healthy = Some(true);
+        }
+    }
+
+    Ok(())
+}
+
+/// Make every health check due, so that they all run the next time the checks are run
+pub(super) fn expire_health_checks(daemon: &LuckyDaemon) {
+    for health in daemon.container_health.lock().unwrap().values_mut() {
+        health.last_check = None;
+    }
+}
+
+/// Set the health status of a container
+fn set_health_status(
+    daemon: &LuckyDaemon,
+    container_name: &str,
+    status: ScriptStatus,
+) -> anyhow::Result<()> {
+    let mut state = daemon.state.write().unwrap();
+    tools::set_script_status(
+        &*daemon.juju,
+        &mut state,
+        &status_id(container_name),
+        status,
+    )
+}
+
+/// Run a container's health check
+///
+/// The outer result is an error if the check could not be run at all. The inner result is an error
+/// with the reason if the check ran and failed.
+fn run_health_check(
+    daemon: &LuckyDaemon,
+    container_name: &str,
+    check: &HealthCheckSpec,
+) -> anyhow::Result<Result<(), String>> {
+    match (&check.exec, &check.http) {
+        (Some(command), None) => exec_check(daemon, container_name, command),
+        (None, Some(url)) => Ok(http_check(url)),
+        _ => bail!(
+            "The health check for container {} must have exactly one of `exec` or `http`",
+            container_name
+        ),
+    }
+}
+
+/// Check that a URL responds with a success status
+fn http_check(url: &str) -> Result<(), String> {
+    let capture = Exec::cmd("curl")
+        .args(&[
+            "--fail",
+            "--silent",
+            "--show-error",
+            "--output",
+            "/dev/null",
+            "--max-time",
+            &HTTP_TIMEOUT.as_secs().to_string(),
+            url,
+        ])
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .capture()
+        .map_err(|e| format!("could not run curl: {}", e))?;
+
+    match capture.exit_status {
+        ExitStatus::Exited(0) => Ok(()),
+        _ => Err(capture.stdout_str().trim().to_string()),
+    }
+}
+
+/// Check that a command exits zero in the container
+fn exec_check(
+    daemon: &LuckyDaemon,
+    container_name: &str,
+    command: &[String],
+) -> anyhow::Result<Result<(), String>> {
+    if !daemon.docker_enabled() {
+        bail!(
+            "Command health checks require a container engine: use an `http` health check for \
+            container {}",
+            container_name
+        );
+    }
+
+    // Get the ID of the container
+    let container_id = {
+        let state = daemon.state.read().unwrap();
+        let container = if container_name == DEFAULT_CONTAINER_NAME {
+            state.default_container.as_ref()
+        } else {
+            state.named_containers.get(container_name)
+        };
+        match container.and_then(|x| x.id.clone()) {
+            Some(id) => id,
+            None => return Ok(Err("container is not running".into())),
+        }
+    };
+
+    // Run the command, collecting its output
+    let output: Arc<Mutex<String>> = Arc::new(Mutex::new(String::new()));
+    let output_ = output.clone();
+    let result = daemon.get_container_engine()?.exec(
+        &container_id,
+        command,
+        &[],
+        Box::new(move |chunk| output_.lock().unwrap().push_str(chunk)),
+    );
+    let exit_code = match result {
+        Ok(exit_code) => exit_code,
+        Err(e) => return Ok(Err(format!("could not run command: {}", e))),
+    };
+
+    let output = output.lock().unwrap();
+    Ok(match exit_code {
+        Some(0) => Ok(()),
+        Some(code) => Err(format!(
+            "command exited {}{}",
+            code,
+            // Include the last line of output, which is usually the error
+            output
+                .lines()
+                .rev()
+                .find(|x| !x.trim().is_empty())
+                .map_or_else(String::new, |x| format!(": {}", x.trim()))
+        )),
+        None => Err("could not get the exit code of the command".into()),
+    })
+}
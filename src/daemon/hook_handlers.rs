@@ -1,43 +1,66 @@
 //! Built-in handlers for Juju hooks that are executed by the daemon
 
+use std::ops::Bound;
 use std::time::Duration;
 
 use super::*;
 use crate::docker::ContainerInfo;
-use crate::rt::block_on;
-use crate::types::{ScriptState, ScriptStatus};
+use crate::trace::{self, Span};
+use crate::types::{CharmScript, Platform, ScriptState, ScriptStatus, DEFAULT_CONTAINER_NAME};
 
 pub(super) fn handle_pre_hook(daemon: &LuckyDaemon, hook_name: &str) -> anyhow::Result<()> {
     match hook_name {
         "install" => handle_pre_install(daemon),
         "config-changed" => handle_pre_config_changed(daemon),
         "upgrade-charm" => handle_pre_upgrade_charm(daemon),
-        _ => Ok(()),
+        _ if daemon.platform == Platform::Kubernetes && hook_name.ends_with("-pebble-ready") => {
+            handle_pre_pebble_ready(daemon, hook_name.trim_end_matches("-pebble-ready"))
+        }
+        _ => match tools::get_storage_hook_name(hook_name) {
+            Some(storage_name) => handle_pre_storage_hook(daemon, storage_name),
+            None => {
+                docker_registry::handle_relation_hook(daemon, hook_name)?;
+                relation_kv::handle_relation_hook(daemon, hook_name)
+            }
+        },
     }
 }
 
 pub(super) fn handle_post_hook(daemon: &LuckyDaemon, hook_name: &str) -> anyhow::Result<()> {
     match hook_name {
-        "stop" => handle_post_stop(daemon),
-        _ => Ok(()),
+        "stop" => {
+            handle_post_stop_host_services(daemon)?;
+            if daemon.docker_enabled() {
+                handle_post_stop(daemon)
+            } else {
+                Ok(())
+            }
+        }
+        _ => relation_kv::publish_relation_data(daemon, Some(hook_name)),
     }
 }
 
-#[function_name::named]
 fn handle_pre_install(daemon: &LuckyDaemon) -> anyhow::Result<()> {
     let mut state = daemon.state.write().unwrap();
 
     // Update the config cache
-    update_config_cache(&mut state)?;
+    update_config_cache(daemon, &mut state)?;
 
-    // If Docker support is enabled
-    if daemon.lucky_metadata.use_docker {
-        daemon_set_status!(&mut state, ScriptState::Maintenance, "Installing docker");
+    // A new unit doesn't have any data to migrate
+    if state.state_version.is_none() {
+        state.state_version = daemon.lucky_metadata.migrations.keys().next_back().copied();
+    }
 
-        // Make sure Docker is installed
-        crate::docker::ensure_docker()?;
+    // If container support is enabled
+    if daemon.docker_enabled() {
+        // Choose the container engine. It is recorded in the state so that it doesn't change while
+        // there are containers running on it.
+        let kind = tools::get_configured_container_engine(daemon)?;
+        state.container_engine = Some(kind);
+        *daemon.container_engine_kind.write().unwrap() = kind;
 
-        daemon_set_status!(&mut state, ScriptState::Active);
+        // Make sure the container engine is installed
+        tools::ensure_container_engine(daemon, &mut state)?;
     }
 
     Ok(())
@@ -47,7 +70,19 @@ fn handle_pre_config_changed(daemon: &LuckyDaemon) -> anyhow::Result<()> {
     let mut state = daemon.state.write().unwrap();
 
     // Update the configuration cache
-    update_config_cache(&mut state)?;
+    update_config_cache(daemon, &mut state)?;
+
+    // Warn if the charm has been configured to use a different container engine
+    if daemon.docker_enabled() {
+        let kind = *daemon.container_engine_kind.read().unwrap();
+        let configured = tools::get_configured_container_engine(daemon)?;
+        if configured != kind {
+            log::warn!(
+                "The container engine can't be changed after the charm is installed: still using {}",
+                kind.as_ref()
+            );
+        }
+    }
 
     Ok(())
 }
@@ -55,33 +90,129 @@ fn handle_pre_config_changed(daemon: &LuckyDaemon) -> anyhow::Result<()> {
 #[function_name::named]
 fn handle_post_stop(daemon: &LuckyDaemon) -> anyhow::Result<()> {
     let mut state = daemon.state.write().unwrap();
-    let docker_conn = daemon.get_docker_conn()?;
-    let docker_conn = docker_conn.lock().unwrap();
+    let engine = daemon.get_container_engine()?;
 
-    daemon_set_status!(&mut state, ScriptState::Maintenance, "Removing containers");
+    daemon_set_status!(
+        daemon,
+        &mut state,
+        ScriptState::Maintenance,
+        "Removing containers"
+    );
 
-    for mut container_info in state.named_containers.values_mut() {
-        remove_container(&docker_conn, &mut container_info)?;
+    // Remove the containers in the reverse of the order that they were started in so that
+    // containers are removed before the containers that they depend on
+    let order = match tools::container_start_order(daemon, &state) {
+        Ok(order) => order,
+        Err(e) => {
+            log::warn!("Could not order containers for removal: {:?}", e);
+            state
+                .named_containers
+                .keys()
+                .cloned()
+                .chain(Some(DEFAULT_CONTAINER_NAME.into()))
+                .collect()
+        }
+    };
+    for name in order.iter().rev() {
+        let container_info = if name == DEFAULT_CONTAINER_NAME {
+            state.default_container.as_mut()
+        } else {
+            state.named_containers.get_mut(name)
+        };
+        if let Some(container_info) = container_info {
+            remove_container(&*engine, container_info)?;
+        }
     }
 
     // Erase container config
     state.named_containers.clear();
+    state.default_container = None;
 
-    if let Some(container_info) = &mut state.default_container {
-        remove_container(&docker_conn, container_info)?;
+    // Remove the network shared between the containers
+    engine.remove_network(&tools::shared_network_name()?)?;
+
+    // Remove the data of the Lucky managed volumes so that it isn't left behind on the machine.
+    // Volumes that are mounted from a path on the host or from Juju storage are not touched.
+    let volume_dir = daemon.lucky_data_dir.join(VOLUME_DIR);
+    if tools::get_configured_preserve_volumes(daemon)? {
+        log::info!("Preserving volume data in {:?}", volume_dir);
+    } else if volume_dir.exists() {
+        daemon_set_status!(
+            daemon,
+            &mut state,
+            ScriptState::Maintenance,
+            "Removing volumes"
+        );
+        log::debug!("Removing volume data in {:?}", volume_dir);
+        std::fs::remove_dir_all(&volume_dir)
+            .context(format!("Could not remove volume data in {:?}", volume_dir))?;
     }
 
-    // Erase container config
-    state.default_container = None;
+    daemon_set_status!(daemon, &mut state, ScriptState::Active);
+    Ok(())
+}
+
+#[function_name::named]
+fn handle_post_stop_host_services(daemon: &LuckyDaemon) -> anyhow::Result<()> {
+    let mut state = daemon.state.write().unwrap();
+
+    if state.host_services.is_empty() {
+        return Ok(());
+    }
+
+    daemon_set_status!(
+        daemon,
+        &mut state,
+        ScriptState::Maintenance,
+        "Removing host services"
+    );
+
+    let unit_name =
+        std::env::var("JUJU_UNIT_NAME").context("Env var JUJU_UNIT_NAME not readable!")?;
+    for service_name in state.host_services.keys() {
+        let systemd_unit = crate::systemd::unit_name(&unit_name, service_name);
+        tools::remove_host_service(
+            &systemd_unit,
+            &PathBuf::from(crate::systemd::SYSTEMD_UNIT_DIR).join(&systemd_unit),
+            &daemon
+                .lucky_data_dir
+                .join(tools::HOST_SERVICE_ENV_DIR)
+                .join(format!("{}.env", service_name)),
+        )?;
+    }
+    crate::systemd::systemctl(&["daemon-reload"])?;
+
+    // Erase host service config
+    state.host_services.clear();
 
-    daemon_set_status!(&mut state, ScriptState::Active);
+    daemon_set_status!(daemon, &mut state, ScriptState::Active);
     Ok(())
 }
 
 #[function_name::named]
 fn handle_pre_upgrade_charm(daemon: &LuckyDaemon) -> anyhow::Result<()> {
+    // Migrate the unit's data before anything else uses it
+    let current_version = daemon.state.read().unwrap().state_version.unwrap_or(0);
+    let migrations = daemon
+        .lucky_metadata
+        .migrations
+        .range((Bound::Excluded(current_version), Bound::Unbounded));
+    for (version, scripts) in migrations {
+        let mut state = daemon.state.write().unwrap();
+        daemon_set_status!(
+            daemon,
+            &mut state,
+            ScriptState::Maintenance,
+            format!("Migrating unit data to version {}", version)
+        );
+        drop(state);
+
+        run_migration(daemon, *version, scripts)?;
+    }
+
     let mut state = daemon.state.write().unwrap();
     daemon_set_status!(
+        daemon,
         &mut state,
         ScriptState::Maintenance,
         "Updating containers after charm upgrade"
@@ -95,15 +226,93 @@ fn handle_pre_upgrade_charm(daemon: &LuckyDaemon) -> anyhow::Result<()> {
         container.mark_dirty();
     }
 
+    // Mark any host services as dirty because their unit file templates may have changed
+    for service in state.host_services.values_mut() {
+        service.mark_dirty();
+    }
+
     // Drop state while we apply container updates
     drop(state);
 
-    tools::apply_container_updates(&daemon)
-        .context("Could not apply container updates during charm upgrade")?;
+    tools::apply_workload_updates(&daemon)
+        .context("Could not apply container and host service updates during charm upgrade")?;
 
     // Set status to active
     let mut state = daemon.state.write().unwrap();
-    daemon_set_status!(&mut state, ScriptState::Active);
+    daemon_set_status!(daemon, &mut state, ScriptState::Active);
+    Ok(())
+}
+
+/// Run the scripts of a migration and record that the unit's data is at its version
+///
+/// The state is flushed after every migration so that, if a later migration fails, the migrations
+/// that succeeded are not run again when the hook is retried.
+fn run_migration(
+    daemon: &LuckyDaemon,
+    version: u32,
+    scripts: &[CharmScript],
+) -> anyhow::Result<()> {
+    let from_version = daemon.state.read().unwrap().state_version.unwrap_or(0);
+    log::info!(
+        "Migrating unit data from version {} to version {}",
+        from_version,
+        version
+    );
+
+    let _span = Span::start(&format!("migration {}", version))
+        .with_attr("lucky.migration", &version.to_string());
+
+    let mut environment = HashMap::new();
+    environment.insert("LUCKY_MIGRATION_FROM".into(), from_version.to_string());
+    environment.insert("LUCKY_MIGRATION_TO".into(), version.to_string());
+    daemon
+        ._run_scripts(
+            "upgrade-charm",
+            scripts,
+            &environment,
+            &format!("migration_{}", version),
+        )
+        .context(format!("Migration to version {} failed", version))?;
+
+    daemon.state.write().unwrap().state_version = Some(version);
+    tools::flush_state(daemon)
+}
+
+/// Mark the containers that run in a workload container as dirty when its Pebble becomes ready
+///
+/// Pebble doesn't keep its layers when the workload container is restarted, so the service has to
+/// be added again. The update is applied at the end of the hook.
+fn handle_pre_pebble_ready(daemon: &LuckyDaemon, sidecar_container: &str) -> anyhow::Result<()> {
+    let mut state = daemon.state.write().unwrap();
+    let use_default = daemon.sidecar_containers.len() == 1;
+
+    if let Some(container) = state.named_containers.get_mut(sidecar_container) {
+        container.mark_dirty();
+    }
+    if let Some(container) = state.default_container.as_mut().filter(|_| use_default) {
+        container.mark_dirty();
+    }
+
+    Ok(())
+}
+
+/// Mark the containers that mount the given Juju storage as dirty when it is attached or detached
+///
+/// The containers are re-created at the end of the hook so that the storage is mounted into them,
+/// or unmounted before Juju detaches it.
+fn handle_pre_storage_hook(daemon: &LuckyDaemon, storage_name: &str) -> anyhow::Result<()> {
+    let mut state = daemon.state.write().unwrap();
+    let state = &mut *state;
+
+    for container in state
+        .named_containers
+        .values_mut()
+        .chain(state.default_container.iter_mut())
+        .filter(|x| x.config.storage_names().contains(storage_name))
+    {
+        container.mark_dirty();
+    }
+
     Ok(())
 }
 
@@ -112,12 +321,12 @@ fn handle_pre_upgrade_charm(daemon: &LuckyDaemon) -> anyhow::Result<()> {
 //
 
 /// Update the daemons charm configuration cache with the valu
-fn update_config_cache(state: &mut DaemonState) -> anyhow::Result<()> {
+fn update_config_cache(daemon: &LuckyDaemon, state: &mut DaemonState) -> anyhow::Result<()> {
     log::debug!("Updating config cache");
     let charm_config = &mut state.charm_config;
 
     // Get updated charm config
-    let latest_config = juju::config_get()?;
+    let latest_config = daemon.juju.config_get()?;
 
     // Loop through config
     for (k, v) in latest_config {
@@ -137,20 +346,24 @@ fn update_config_cache(state: &mut DaemonState) -> anyhow::Result<()> {
 
 /// Helper to remove a given container
 fn remove_container(
-    docker_conn: &shiplift::Docker,
+    engine: &dyn ContainerEngine,
     container_info: &mut Cd<ContainerInfo>,
 ) -> anyhow::Result<()> {
     // If container has an ID
     if let Some(id) = &container_info.id {
-        let container = docker_conn.containers().get(id);
-
         // Stop the container
         log::debug!("Stopping container: {}", id);
-        block_on(container.stop(Some(Duration::from_secs(10))))?;
+        trace::in_span(
+            Span::start("container stop").with_attr("container.id", id),
+            || engine.stop_container(id, Some(Duration::from_secs(10))),
+        )?;
 
         // Remove the container
         log::debug!("Removing container: {}", id);
-        block_on(container.delete())?;
+        trace::in_span(
+            Span::start("container delete").with_attr("container.id", id),
+            || engine.remove_container(id),
+        )?;
 
         // Unset the container id
         container_info.update(|info| info.id = None);
@@ -0,0 +1,90 @@
+//! Mapping of the hooks that Juju runs onto the hooks in the `lucky.yaml`
+//!
+//! Machine charms get the same hooks from Juju that are used in the `lucky.yaml`, but Kubernetes
+//! sidecar charms get extra hooks for their workload containers. Each platform has a
+//! `HookMapping` that decides which `lucky.yaml` hooks to run for a Juju hook, so that the same
+//! `lucky.yaml` can be used on both kinds of clouds.
+
+use std::collections::HashMap;
+
+use crate::types::{juju::JUJU_PEBBLE_HOOKS, Platform};
+
+/// The environment variable used to tell scripts which workload container a hook is for
+const CONTAINER_ENV_VAR: &str = "LUCKY_CONTAINER";
+
+/// The `lucky.yaml` hooks to run for a Juju hook
+pub(super) struct MappedHook {
+    /// The names of the `lucky.yaml` hooks to run, in order
+    pub hooks: Vec<String>,
+    /// Extra environment variables to pass to the hook scripts
+    pub environment: HashMap<String, String>,
+}
+
+impl MappedHook {
+    /// A mapped hook that only runs the `lucky.yaml` hook with the same name
+    fn identity(hook_name: &str) -> Self {
+        MappedHook {
+            hooks: vec![hook_name.into()],
+            environment: HashMap::new(),
+        }
+    }
+}
+
+/// Maps Juju hooks onto `lucky.yaml` hooks
+pub(super) trait HookMapping: Send + Sync {
+    /// Get the `lucky.yaml` hooks to run for the given Juju hook
+    fn map_hook(&self, hook_name: &str) -> MappedHook;
+}
+
+/// Create the hook mapping for the given platform
+///
+/// `containers` is the list of workload containers in the charm's `metadata.yaml`.
+pub(super) fn for_platform(platform: Platform, containers: Vec<String>) -> Box<dyn HookMapping> {
+    match platform {
+        Platform::Kubernetes => Box::new(SidecarHookMapping { containers }),
+        Platform::Auto | Platform::Machine => Box::new(MachineHookMapping),
+    }
+}
+
+/// The hook mapping for machine charms, which runs hooks unchanged
+struct MachineHookMapping;
+
+impl HookMapping for MachineHookMapping {
+    fn map_hook(&self, hook_name: &str) -> MappedHook {
+        MappedHook::identity(hook_name)
+    }
+}
+
+/// The hook mapping for Kubernetes sidecar charms
+///
+/// Workload container hooks such as `<container>-pebble-ready` are run as themselves and then as
+/// the generic `container-ready`, `container-custom-notice`, `container-check-failed`, and
+/// `container-check-recovered` hooks, with the container name in the `LUCKY_CONTAINER`
+/// environment variable.
+struct SidecarHookMapping {
+    /// The names of the workload containers
+    containers: Vec<String>,
+}
+
+impl HookMapping for SidecarHookMapping {
+    fn map_hook(&self, hook_name: &str) -> MappedHook {
+        for container in &self.containers {
+            for template in JUJU_PEBBLE_HOOKS {
+                if template.replace("{}", container) != hook_name {
+                    continue;
+                }
+
+                let generic_hook = template.replace("{}-pebble", "container");
+                let mut environment = HashMap::new();
+                environment.insert(CONTAINER_ENV_VAR.into(), container.clone());
+
+                return MappedHook {
+                    hooks: vec![hook_name.into(), generic_hook],
+                    environment,
+                };
+            }
+        }
+
+        MappedHook::identity(hook_name)
+    }
+}
@@ -0,0 +1,119 @@
+# Lucky shell helpers
+#
+# Source this file at the top of a Bash host script to get functions that wrap the `lucky`
+# commands:
+#
+#     . "$LUCKY_ENV"
+#
+# The functions quote their arguments for you, and print their usage and fail with exit code 2 when
+# they are called with the wrong arguments. They don't change any shell options, so use `set -e` to
+# exit when one of them fails.
+
+# Print an error to stderr and fail
+#
+# Usage: lucky::error <message>
+lucky::error() {
+    echo "lucky: $*" >&2
+    return 1
+}
+
+# Check the number of arguments passed to a helper
+lucky::_check_args() {
+    local name="$1" usage="$2" min="$3" max="$4" count="$5"
+    if [ "$count" -lt "$min" ] || [ "$count" -gt "$max" ]; then
+        lucky::error "Usage: $name $usage"
+        return 2
+    fi
+}
+
+# Check that a key can be set with a `key=value` pair
+lucky::_check_key() {
+    case "$1" in
+        "" | [!a-zA-Z]* | [a-zA-Z]*[!a-zA-Z0-9_-]*)
+            lucky::error "Invalid key, keys must start with a letter and contain only letters," \
+                "numbers, \`_\`, and \`-\`: $1"
+            return 2
+            ;;
+    esac
+}
+
+# Set the status of the script
+#
+# Usage: lucky::set-status <active|waiting|maintenance|blocked> [message]
+lucky::set-status() {
+    lucky::_check_args lucky::set-status "<active|waiting|maintenance|blocked> [message]" \
+        1 2 $# || return
+    lucky set-status -- "$@"
+}
+
+# Check whether or not the unit is the leader, for use in `if` statements
+#
+# Usage: lucky::is-leader
+lucky::is-leader() {
+    lucky::_check_args lucky::is-leader "" 0 0 $# || return
+    local leader
+    leader="$(lucky leader is-leader)" || return
+    [ "$leader" = "true" ]
+}
+
+# Print a charm config option, or the default if the option isn't set
+#
+# Usage: lucky::config-get <option> [default]
+lucky::config-get() {
+    lucky::_check_args lucky::config-get "<option> [default]" 1 2 $# || return
+    local value
+    value="$(lucky get-config -- "$1")" || return
+    printf '%s\n' "${value:-${2:-}}"
+}
+
+# Print a value from the unit key-value store, or the default if the key isn't set
+#
+# Usage: lucky::kv-get <key> [default]
+lucky::kv-get() {
+    lucky::_check_args lucky::kv-get "<key> [default]" 1 2 $# || return
+    local value
+    value="$(lucky kv get -- "$1")" || return
+    printf '%s\n' "${value:-${2:-}}"
+}
+
+# Set a value in the unit key-value store. Setting an empty value deletes the key.
+#
+# Usage: lucky::kv-set <key> <value>
+lucky::kv-set() {
+    lucky::_check_args lucky::kv-set "<key> <value>" 2 2 $# || return
+    lucky::_check_key "$1" || return
+    lucky kv set -- "$1=$2"
+}
+
+# Delete keys from the unit key-value store
+#
+# Usage: lucky::kv-delete <key>...
+lucky::kv-delete() {
+    if [ $# -eq 0 ]; then
+        lucky::error "Usage: lucky::kv-delete <key>..."
+        return 2
+    fi
+    lucky kv delete -- "$@"
+}
+
+# Print an environment variable of a container, or set it if a value is given. Setting an empty
+# value removes the variable. The default container is used unless a container is given with `-c`.
+#
+# Usage: lucky::container-env [-c <container>] <var> [value]
+lucky::container-env() {
+    local usage="[-c <container>] <var> [value]"
+    local container_args=()
+    if [ "${1:-}" = "-c" ]; then
+        lucky::_check_args lucky::container-env "$usage" 2 4 $# || return
+        container_args=(--container "$2")
+        shift 2
+    fi
+    lucky::_check_args lucky::container-env "$usage" 1 2 $# || return
+
+    if [ $# -eq 1 ]; then
+        lucky container env get ${container_args[@]+"${container_args[@]}"} -- "$1"
+    else
+        lucky::_check_key "$1" || return
+        lucky container env set ${container_args[@]+"${container_args[@]}"} -- "$1=$2"
+    fi
+}
\ No newline at end of file
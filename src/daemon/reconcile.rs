@@ -0,0 +1,80 @@
+//! Periodic reconciliation
+//!
+//! Juju runs the `update-status` hook every few minutes. After the hook's scripts have run, the
+//! daemon reconciles the unit with its state, so that drift on the machine is corrected without
+//! an operator having to step in: containers that were removed are re-created, containers that were
+//! stopped outside of Lucky are started, ports that were closed outside of the charm are opened,
+//! every health check is run, and the Juju status is set again from the script statuses.
+
+use super::*;
+
+/// The Juju hook that the reconciliation runs after
+pub(super) const RECONCILE_HOOK: &str = "update-status";
+
+/// Reconcile the unit with the daemon state, unless reconciliation is disabled in the `lucky.yaml`
+pub(super) fn reconcile(daemon: &LuckyDaemon) -> anyhow::Result<()> {
+    if !daemon.lucky_metadata.reconcile {
+        return Ok(());
+    }
+    log::debug!("Reconciling the unit");
+
+    // Load the open ports from Juju again so that the container ports closed outside of the daemon
+    // are opened when the container configuration is applied
+    daemon.opened_ports_synced.store(false, Ordering::SeqCst);
+
+    // Re-create the containers that were removed and make sure the host services are up to date
+    tools::apply_workload_updates(daemon)?;
+
+    // Start the containers that were stopped outside of Lucky
+    if daemon.docker_enabled() {
+        start_stopped_containers(daemon)?;
+    }
+
+    // Run every health check now instead of waiting for its interval
+    health::expire_health_checks(daemon);
+    health::run_health_checks(daemon)?;
+
+    // Set the Juju status again in case it was changed outside of the daemon
+    let state = daemon.state.read().unwrap();
+    daemon.juju.set_status(tools::get_juju_status(&state))?;
+
+    Ok(())
+}
+
+/// Start the containers that should be running but aren't
+///
+/// Containers that were stopped by Lucky are left stopped, and so are containers with the `no` or
+/// `on-failure` restart policies, which may have exited on purpose. Starting a container that is
+/// already running does nothing.
+fn start_stopped_containers(daemon: &LuckyDaemon) -> anyhow::Result<()> {
+    let state = daemon.state.read().unwrap();
+    let engine = daemon.get_container_engine()?;
+
+    let containers = state
+        .named_containers
+        .iter()
+        .map(|(name, container)| (name.as_str(), container))
+        .chain(
+            state
+                .default_container
+                .iter()
+                .map(|container| (DEFAULT_CONTAINER_NAME, container)),
+        );
+    for (name, container) in containers {
+        let id = match &container.id {
+            Some(id) if !container.stopped && !container.pending_removal => id,
+            _ => continue,
+        };
+        match container.config.restart_policy {
+            ContainerRestartPolicy::Always | ContainerRestartPolicy::UnlessStopped => (),
+            ContainerRestartPolicy::No | ContainerRestartPolicy::OnFailure => continue,
+        }
+
+        log::trace!("Making sure container {} is running", name);
+        if let Err(e) = engine.start_container(id) {
+            log::warn!("Could not start container {}: {:#}", name, e);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,161 @@
+//! Synchronization of relation data with the unit key-value store
+//!
+//! The `relation-kv` section of the `lucky.yaml` lists the relation data keys that are mirrored
+//! into the `global` namespace of the unit key-value store, and the key-value store keys that are
+//! published in this unit's relation data. Remote data is imported before the scripts of the
+//! relation's hooks run, and changed values are published at the end of every hook and cron tick,
+//! so scripts only ever have to deal with the key-value store.
+
+use anyhow::Context;
+
+use super::*;
+
+/// Copy the remote unit's relation data into the key-value store in a synchronized relation's hook
+///
+/// This does nothing for the hooks of relations that aren't in the `relation-kv` section.
+pub(super) fn handle_relation_hook(daemon: &LuckyDaemon, hook_name: &str) -> anyhow::Result<()> {
+    let relation_name = match std::env::var("JUJU_RELATION") {
+        Ok(name) => name,
+        Err(_) => return Ok(()),
+    };
+    let sync = match daemon.lucky_metadata.relation_kv.get(&relation_name) {
+        Some(sync) if !sync.import.is_empty() => sync,
+        _ => return Ok(()),
+    };
+
+    // Get the new values of the imported keys
+    let updates: HashMap<&String, Option<String>> = if hook_name.ends_with("-relation-broken") {
+        // Forget the imported values when the relation is removed
+        sync.import.values().map(|kv_key| (kv_key, None)).collect()
+    } else if hook_name.ends_with("-relation-joined") || hook_name.ends_with("-relation-changed") {
+        let data = daemon
+            .juju
+            .relation_get(None, false)
+            .context(format!("Could not get relation data for {}", relation_name))?;
+        sync.import
+            .iter()
+            .map(|(relation_key, kv_key)| {
+                (
+                    kv_key,
+                    data.get(relation_key).filter(|x| !x.is_empty()).cloned(),
+                )
+            })
+            .collect()
+    } else {
+        return Ok(());
+    };
+
+    let mut changed_keys = Vec::new();
+    let mut state = daemon.state.write().unwrap();
+    for (kv_key, value) in updates {
+        let changed = match value {
+            Some(value) => {
+                let changed = state.kv.get(kv_key).map_or(true, |x| **x != value);
+                state.kv.insert(kv_key.clone(), value.into());
+                state.set_kv_ttl(None, kv_key, None);
+                state.set_kv_secret(None, kv_key, false);
+                changed
+            }
+            None => state.kv.remove(kv_key).is_some(),
+        };
+        if changed {
+            log::debug!(
+                "Key-Value synced from relation {}: {}",
+                relation_name,
+                kv_key
+            );
+            changed_keys.push(kv_key.clone());
+        }
+    }
+    drop(state);
+    changed_keys.sort();
+
+    daemon.run_kv_watches(None, &changed_keys)
+}
+
+/// Publish the key-value store values that have changed since they were last published to the
+/// relation data of every synchronized relation
+///
+/// The `hook_name` is the name of the hook being run, if any, so that nothing is published to a
+/// relation that is being removed.
+pub(super) fn publish_relation_data(
+    daemon: &LuckyDaemon,
+    hook_name: Option<&str>,
+) -> anyhow::Result<()> {
+    // Relation data can't be set on a relation that is being removed
+    let broken_relation_id = hook_name
+        .filter(|x| x.ends_with("-relation-broken"))
+        .and_then(|_| std::env::var("JUJU_RELATION_ID").ok());
+
+    let mut relation_ids = HashSet::new();
+    for (relation_name, sync) in &daemon.lucky_metadata.relation_kv {
+        if sync.export.is_empty() {
+            continue;
+        }
+
+        for relation_id in daemon.juju.relation_ids(relation_name)? {
+            if Some(&relation_id) == broken_relation_id.as_ref() {
+                continue;
+            }
+            relation_ids.insert(relation_id.clone());
+
+            let mut state = daemon.state.write().unwrap();
+
+            // Get the values to publish, with deleted keys published as empty to remove them
+            let data: HashMap<String, String> = sync
+                .export
+                .iter()
+                .map(|(kv_key, relation_key)| {
+                    (
+                        relation_key.clone(),
+                        state
+                            .kv
+                            .get(kv_key)
+                            .filter(|_| !state.kv_key_secret(None, kv_key))
+                            .map(|x| (**x).clone())
+                            .unwrap_or_default(),
+                    )
+                })
+                .collect();
+
+            // Only set the values that have changed since they were last published
+            let published = state
+                .relation_kv_published
+                .entry(relation_id.clone())
+                .or_default();
+            let changed: HashMap<String, String> = data
+                .iter()
+                .filter(|(key, value)| {
+                    published
+                        .get(*key)
+                        .map_or(!value.is_empty(), |x| x != *value)
+                })
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            if changed.is_empty() {
+                continue;
+            }
+
+            log::debug!(
+                "Publishing key-value store values to relation {}: {:?}",
+                relation_id,
+                changed.keys().collect::<Vec<_>>()
+            );
+            daemon
+                .juju
+                .relation_set(changed, Some(relation_id.clone()), false)
+                .context(format!("Could not set relation data for {}", relation_id))?;
+            *published = data;
+        }
+    }
+
+    // Forget what was published to relations that have been removed
+    daemon
+        .state
+        .write()
+        .unwrap()
+        .relation_kv_published
+        .retain(|relation_id, _| relation_ids.contains(relation_id));
+
+    Ok(())
+}
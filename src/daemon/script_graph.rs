@@ -0,0 +1,132 @@
+//! Running lists of scripts that depend on each other
+//!
+//! Scripts can name the scripts in the same list that they run `after`, or that they `requires` to
+//! succeed. When any script in a list has dependencies the list is run as a graph instead of in
+//! order: every script starts as soon as the scripts it depends on have finished, so independent
+//! scripts run in parallel, and the scripts that require a script that failed are skipped.
+
+use super::*;
+use crate::types::ScriptDependency;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The progress of a script in the graph
+enum ScriptRun {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+impl ScriptRun {
+    /// Whether or not the script has finished, successfully or not
+    fn is_finished(self) -> bool {
+        match self {
+            ScriptRun::Pending | ScriptRun::Running => false,
+            ScriptRun::Succeeded | ScriptRun::Failed | ScriptRun::Skipped => true,
+        }
+    }
+}
+
+/// Run a list of scripts in the order of their dependencies, as returned by
+/// `types::script_dependencies`
+///
+/// Every script is run, except for the ones that require a script that didn't succeed. The error
+/// of the first script that failed is returned once all of the scripts have finished.
+pub(super) fn run_script_graph(
+    daemon: &LuckyDaemon,
+    hook_name: &str,
+    scripts: &[CharmScript],
+    dependencies: &[Vec<ScriptDependency>],
+    environment: &HashMap<String, String>,
+    script_id_prefix: &str,
+) -> anyhow::Result<()> {
+    let mut runs = vec![ScriptRun::Pending; scripts.len()];
+    let mut first_error = None;
+    let (result_sender, result_receiver) = unbounded_channel();
+
+    thread_scope(|s| {
+        let mut running = 0;
+        loop {
+            // Start or skip every pending script whose dependencies have finished. Skipping a
+            // script may make the scripts that depend on it ready, so repeat until nothing changes.
+            let mut changed = true;
+            while changed {
+                changed = false;
+                for (i, script) in scripts.iter().enumerate() {
+                    if runs.get(i) != Some(&ScriptRun::Pending) {
+                        continue;
+                    }
+
+                    let script_dependencies = dependencies.get(i).map_or(&[][..], Vec::as_slice);
+                    let ready = script_dependencies
+                        .iter()
+                        .all(|x| runs.get(x.index).map_or(true, |x| x.is_finished()));
+                    if !ready {
+                        continue;
+                    }
+
+                    let skip = script_dependencies.iter().any(|x| {
+                        x.required
+                            && matches!(
+                                runs.get(x.index),
+                                Some(ScriptRun::Failed) | Some(ScriptRun::Skipped)
+                            )
+                    });
+                    let run = if skip {
+                        log::warn!(
+                            "Skipping {} script {} because a script that it requires did not \
+                            succeed",
+                            hook_name,
+                            script.id_or_name()
+                        );
+                        ScriptRun::Skipped
+                    } else {
+                        log::trace!("Running {} script: {:#?}", hook_name, script);
+                        let result_sender = result_sender.clone();
+                        let script_id = format!("{}_{}", script_id_prefix, i);
+                        s.spawn(move |_| {
+                            let result = tools::run_charm_script(
+                                daemon,
+                                hook_name,
+                                script,
+                                environment,
+                                Some(&script_id),
+                            );
+                            result_sender
+                                .send((i, result))
+                                .expect("Channel dropped prematurely");
+                        });
+                        running += 1;
+                        ScriptRun::Running
+                    };
+                    if let Some(x) = runs.get_mut(i) {
+                        *x = run;
+                    }
+                    changed = true;
+                }
+            }
+
+            if running == 0 {
+                break;
+            }
+
+            // Wait for a script to finish
+            let (i, result) = result_receiver.recv().expect("Channel closed prematurely");
+            running -= 1;
+            let run = match result {
+                Ok(()) => ScriptRun::Succeeded,
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                    ScriptRun::Failed
+                }
+            };
+            if let Some(x) = runs.get_mut(i) {
+                *x = run;
+            }
+        }
+    })
+    .expect("Scoped thread paniced");
+
+    first_error.map_or(Ok(()), Err)
+}
@@ -0,0 +1,191 @@
+//! Capturing the output of script runs to log files
+//!
+//! The output of every script run is written to `script_logs/<script-id>/<run>.log` in the Lucky
+//! data dir, where the runs of each script are numbered from 1. When a run finishes, the oldest
+//! logs of the script are removed according to the `script-logs` retention policy in the
+//! `lucky.yaml`. The logs can be read with `lucky script logs`.
+
+use std::fs::File;
+use std::time::{Duration, SystemTime};
+
+use super::*;
+use crate::types::ScriptLogRetention;
+
+/// The log file that the output of a script run is written to
+///
+/// Failing to write the log never fails the script, so errors are only logged. The retention
+/// policy is applied when this is dropped.
+pub(super) struct ScriptLog {
+    dir: PathBuf,
+    file: Option<File>,
+    retention: ScriptLogRetention,
+}
+
+impl ScriptLog {
+    /// Create the log file for a new run of the given script
+    pub(super) fn create(daemon: &LuckyDaemon, script_id: &str) -> Self {
+        Self::create_in(
+            script_log_dir(daemon, script_id),
+            daemon.lucky_metadata.script_logs.clone(),
+        )
+    }
+
+    /// Create the log file for a new run in the given script log dir. This is used by threads that
+    /// don't have access to the daemon.
+    pub(super) fn create_in(dir: PathBuf, retention: ScriptLogRetention) -> Self {
+        // A retention of zero runs disables the logs
+        let file = if retention.max_runs == 0 {
+            None
+        } else {
+            match create_run_file(&dir) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    log::warn!("Could not create script log in {:?}: {:?}", dir, e);
+                    None
+                }
+            }
+        };
+
+        ScriptLog {
+            dir,
+            file,
+            retention,
+        }
+    }
+
+    /// Write a line of script output to the log
+    pub(super) fn write_line(&mut self, line: &str) {
+        if let Some(file) = &mut self.file {
+            if let Err(e) = writeln!(file, "{}", line) {
+                log::warn!("Could not write script log in {:?}: {}", self.dir, e);
+                self.file = None;
+            }
+        }
+    }
+}
+
+impl Drop for ScriptLog {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            if let Err(e) = apply_retention(&self.dir, &self.retention) {
+                log::warn!(
+                    "Could not remove old script logs in {:?}: {:?}",
+                    self.dir,
+                    e
+                );
+            }
+        }
+    }
+}
+
+/// Get the log of a script run, returning the run number, the numbers of all of the runs that have
+/// logs, and the log. Gets the latest run if `run` is `None`.
+pub(super) fn read_script_log(
+    daemon: &LuckyDaemon,
+    script_id: &str,
+    run: Option<u64>,
+) -> anyhow::Result<(u64, Vec<u64>, String)> {
+    let runs: Vec<u64> = list_runs(&script_log_dir(daemon, script_id))?
+        .into_iter()
+        .map(|(run, _)| run)
+        .collect();
+
+    let run = match run {
+        Some(run) => run,
+        None => *runs
+            .last()
+            .ok_or_else(|| anyhow::format_err!("There are no logs for script {}", script_id))?,
+    };
+    if !runs.contains(&run) {
+        anyhow::bail!("There is no log for run {} of script {}", run, script_id);
+    }
+
+    let path = script_log_dir(daemon, script_id).join(format!("{}.log", run));
+    let log =
+        std::fs::read_to_string(&path).context(format!("Could not read script log: {:?}", path))?;
+
+    Ok((run, runs, log))
+}
+
+/// Get the directory with the logs of the given script
+pub(super) fn script_log_dir(daemon: &LuckyDaemon, script_id: &str) -> PathBuf {
+    // Script IDs may contain the names of scripts in sub-directories
+    daemon
+        .lucky_data_dir
+        .join("script_logs")
+        .join(script_id.replace('/', "_"))
+}
+
+/// Create the log file for the next run in the given script log dir
+fn create_run_file(dir: &Path) -> anyhow::Result<File> {
+    std::fs::create_dir_all(dir).context(format!("Could not create script log dir: {:?}", dir))?;
+
+    // Another run of the same script may create its file first, so retry with the next number
+    let mut run = list_runs(dir)?.last().map_or(1, |(run, _)| run + 1);
+    loop {
+        let path = dir.join(format!("{}.log", run));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok(file),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => run += 1,
+            Err(e) => {
+                return Err(e).context(format!("Could not create script log file: {:?}", path))
+            }
+        }
+    }
+}
+
+/// List the runs that have logs in the given script log dir, oldest first
+fn list_runs(dir: &Path) -> anyhow::Result<Vec<(u64, PathBuf)>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut runs = vec![];
+    for entry in std::fs::read_dir(dir).context(format!("Could not read dir: {:?}", dir))? {
+        let path = entry?.path();
+        let run = path
+            .file_name()
+            .and_then(|x| x.to_str())
+            .and_then(|x| x.strip_suffix(".log"))
+            .and_then(|x| x.parse::<u64>().ok());
+        if let Some(run) = run {
+            runs.push((run, path));
+        }
+    }
+    runs.sort();
+
+    Ok(runs)
+}
+
+/// Remove the oldest logs in the given script log dir that are past the retention policy. The
+/// latest log is always kept.
+fn apply_retention(dir: &Path, retention: &ScriptLogRetention) -> anyhow::Result<()> {
+    let mut runs = vec![];
+    for (_, path) in list_runs(dir)? {
+        let metadata = std::fs::metadata(&path)?;
+        runs.push((path, metadata.len(), metadata.modified()?));
+    }
+    let mut total_size: u64 = runs.iter().map(|(_, size, _)| size).sum();
+    // Keep the latest run
+    runs.pop();
+
+    let now = SystemTime::now();
+    let mut kept_runs = runs.len() + 1;
+    for (path, size, modified) in runs {
+        let too_many = kept_runs > retention.max_runs;
+        let too_big = retention.max_size.map_or(false, |max| total_size > max);
+        let too_old = retention.max_age.map_or(false, |max| {
+            now.duration_since(modified)
+                .map_or(false, |age| age > Duration::from_secs(max))
+        });
+        if !too_many && !too_big && !too_old {
+            continue;
+        }
+
+        std::fs::remove_file(&path).context(format!("Could not remove script log: {:?}", path))?;
+        total_size -= size;
+        kept_runs -= 1;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,211 @@
+//! The unit-local secret store
+//!
+//! Secrets are stored in the unit's data directory, encrypted with ChaCha20-Poly1305. The
+//! encryption key is derived from a randomly generated keyfile that is only readable by the user
+//! running the daemon.
+
+use anyhow::{format_err, Context};
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use rand::{thread_rng, RngCore};
+use sha2::Sha256;
+
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// The name of the keyfile in the unit's data directory
+const KEYFILE_NAME: &str = "secret.key";
+/// The name of the encrypted secret store in the unit's data directory
+const STORE_FILE_NAME: &str = "secrets.enc";
+/// The length of the keyfile in bytes
+const KEYFILE_LEN: usize = 32;
+/// The length of the nonce that is prepended to the encrypted store
+const NONCE_LEN: usize = 12;
+/// The HKDF info used to derive the store encryption key from the keyfile
+const KEY_DERIVATION_INFO: &[u8] = b"lucky secret store v1";
+
+/// The unit's encrypted secret store
+pub(super) struct SecretStore {
+    /// The path to the encrypted store file
+    path: PathBuf,
+    /// The cipher used to encrypt and decrypt the store
+    cipher: ChaCha20Poly1305,
+    /// The decrypted secrets
+    secrets: HashMap<String, String>,
+}
+
+impl SecretStore {
+    /// Open the secret store in the given data directory, creating the keyfile if it doesn't exist
+    ///
+    /// All of the secrets in the store will be added to the log redaction list.
+    pub fn open(data_dir: &Path) -> anyhow::Result<Self> {
+        let keyfile = load_or_create_keyfile(&data_dir.join(KEYFILE_NAME))?;
+
+        // Derive the encryption key from the keyfile
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(None, &keyfile)
+            .expand(KEY_DERIVATION_INFO, &mut key)
+            .map_err(|_| format_err!("Could not derive secret store key"))?;
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+
+        let path = data_dir.join(STORE_FILE_NAME);
+        let secrets = if path.exists() {
+            let encrypted =
+                fs::read(&path).context(format!("Could not read secret store: {:?}", path))?;
+            if encrypted.len() < NONCE_LEN {
+                anyhow::bail!("Secret store is corrupt: {:?}", path);
+            }
+            let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+            let plaintext = cipher
+                .decrypt(Nonce::from_slice(nonce), ciphertext)
+                .map_err(|_| {
+                    format_err!(
+                        "Could not decrypt secret store {:?}: the keyfile may have changed",
+                        path
+                    )
+                })?;
+
+            serde_json::from_slice(&plaintext).context("Could not parse secret store")?
+        } else {
+            HashMap::new()
+        };
+
+        // Make sure none of the secrets end up in the logs
+        for value in secrets.values() {
+            crate::log::add_redacted_value(value);
+        }
+
+        Ok(SecretStore {
+            path,
+            cipher,
+            secrets,
+        })
+    }
+
+    /// Get a secret from the store
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.secrets.get(key)
+    }
+
+    /// Set a secret in the store. Setting a secret to `None` removes it.
+    ///
+    /// Changes are not persisted until `flush()` is called.
+    pub fn set(&mut self, key: String, value: Option<String>) {
+        if let Some(value) = value {
+            crate::log::add_redacted_value(&value);
+            self.secrets.insert(key, value);
+        } else {
+            self.secrets.remove(&key);
+        }
+    }
+
+    /// Encrypt a single value with the store's key, such as a secret value in the key-value store
+    ///
+    /// The value is returned hex encoded, with its nonce in front.
+    pub fn encrypt_value(&self, value: &str) -> anyhow::Result<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), value.as_bytes())
+            .map_err(|_| format_err!("Could not encrypt value"))?;
+
+        Ok(nonce
+            .iter()
+            .chain(ciphertext.iter())
+            .map(|byte| format!("{:02x}", byte))
+            .collect())
+    }
+
+    /// Decrypt a value that was encrypted with `encrypt_value`
+    ///
+    /// The value will be added to the log redaction list.
+    pub fn decrypt_value(&self, encrypted: &str) -> anyhow::Result<String> {
+        let encrypted =
+            decode_hex(encrypted).ok_or_else(|| format_err!("Encrypted value is corrupt"))?;
+        if encrypted.len() < NONCE_LEN {
+            anyhow::bail!("Encrypted value is corrupt");
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| format_err!("Could not decrypt value: the keyfile may have changed"))?;
+        let value = String::from_utf8(plaintext).context("Decrypted value is not valid UTF-8")?;
+
+        crate::log::add_redacted_value(&value);
+
+        Ok(value)
+    }
+
+    /// Encrypt the store and write it to disk
+    pub fn flush(&self) -> anyhow::Result<()> {
+        let plaintext = serde_json::to_vec(&self.secrets)?;
+
+        // Use a fresh nonce every time the store is written
+        let mut nonce = [0u8; NONCE_LEN];
+        thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
+            .map_err(|_| format_err!("Could not encrypt secret store"))?;
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&self.path)
+            .context(format!("Could not open secret store: {:?}", self.path))?;
+        file.write_all(&nonce)?;
+        file.write_all(&ciphertext)?;
+
+        Ok(())
+    }
+}
+
+/// Decode a hex string, returning `None` if it isn't valid hex
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+        })
+        .collect()
+}
+
+/// Load the keyfile at the given path, generating a new one if it doesn't exist
+fn load_or_create_keyfile(path: &Path) -> anyhow::Result<Vec<u8>> {
+    if path.exists() {
+        let keyfile = fs::read(path).context(format!("Could not read keyfile: {:?}", path))?;
+        if keyfile.len() < KEYFILE_LEN {
+            anyhow::bail!("Keyfile is too short: {:?}", path);
+        }
+
+        Ok(keyfile)
+    } else {
+        log::debug!("Generating secret store keyfile: {:?}", path);
+        let mut keyfile = vec![0u8; KEYFILE_LEN];
+        thread_rng().fill_bytes(&mut keyfile);
+
+        // Only allow the daemon user to read the keyfile
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)
+            .and_then(|mut file| file.write_all(&keyfile))
+            .context(format!("Could not write keyfile: {:?}", path))?;
+
+        Ok(keyfile)
+    }
+}
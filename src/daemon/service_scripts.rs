@@ -0,0 +1,424 @@
+//! Long-running service scripts supervised by the daemon
+//!
+//! The `service-scripts` in the `lucky.yaml` are host scripts that keep running in the background,
+//! for workloads that are plain processes instead of containers. The daemon starts them at the end
+//! of the `start` hook and stops them in the `stop` hook. Each service is run in its own process
+//! group by a supervisor thread that restarts it according to its `restart` policy when it exits,
+//! waiting longer between restarts while it keeps exiting soon after it starts. When the daemon
+//! restarts, the services are started again at the end of the next hook or cron tick.
+//!
+//! A service that is waiting to be restarted or that has failed for good gets its own script
+//! status so that it shows up in the Juju status. Because the status can only be set inside of a
+//! Juju context, the statuses are updated at the end of every hook and cron tick.
+
+use subprocess::{Exec, ExitStatus, Redirection};
+
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use super::*;
+use crate::types::{ScriptLogRetention, ServiceRestartPolicy, ServiceScript};
+
+/// How long a service has to stay up for its restart delay to go back to its `restart-delay`
+const SERVICE_STABLE_PERIOD: Duration = Duration::from_secs(60);
+/// The longest that the restart delay of a service is doubled to, in seconds
+const MAX_SERVICE_RESTART_DELAY: u64 = 300;
+/// How long a service has to exit after it is sent SIGTERM before it is sent SIGKILL
+const SERVICE_STOP_GRACE_PERIOD: Duration = Duration::from_secs(10);
+/// How often the supervisor threads check whether they have been stopped
+const SERVICE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// The prefix of the script IDs of the service statuses
+const STATUS_ID_PREFIX: &str = "__lucky::service::";
+
+/// The service scripts that the daemon is supervising
+#[derive(Default)]
+pub(super) struct ServiceSupervisor {
+    /// The supervised services, keyed by service name
+    services: HashMap<String, SupervisedService>,
+}
+
+impl Drop for ServiceSupervisor {
+    fn drop(&mut self) {
+        for (name, service) in self.services.drain() {
+            service.stop(&name);
+        }
+    }
+}
+
+/// A service script and the thread supervising it
+struct SupervisedService {
+    /// Set to make the supervisor thread stop the service and exit
+    stop: Arc<AtomicBool>,
+    progress: Arc<Mutex<ServiceProgress>>,
+    thread: JoinHandle<()>,
+}
+
+impl SupervisedService {
+    /// Stop the service, sending it SIGKILL if it doesn't exit within the
+    /// `SERVICE_STOP_GRACE_PERIOD` after SIGTERM
+    fn stop(self, name: &str) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        let pgid = self.progress.lock().unwrap().pgid;
+        if let Some(pgid) = pgid {
+            log::info!("Stopping service script: {}", name);
+            tools::signal_process_group(pgid, libc::SIGTERM);
+
+            let stopping = Instant::now();
+            while self.progress.lock().unwrap().pgid.is_some() {
+                if stopping.elapsed() > SERVICE_STOP_GRACE_PERIOD {
+                    log::warn!(
+                        "Service script {} did not exit {} seconds after SIGTERM, sending SIGKILL",
+                        name,
+                        SERVICE_STOP_GRACE_PERIOD.as_secs()
+                    );
+                    tools::signal_process_group(pgid, libc::SIGKILL);
+                    break;
+                }
+                std::thread::sleep(SERVICE_POLL_INTERVAL);
+            }
+        }
+
+        if self.thread.join().is_err() {
+            log::error!("Supervisor thread of service script {} paniced", name);
+        }
+    }
+}
+
+/// The progress of a supervised service, shared with its supervisor thread
+#[derive(Default)]
+struct ServiceProgress {
+    /// The process group ID of the service while it is running
+    pgid: Option<u32>,
+    /// The number of times that the service has been restarted
+    restarts: u32,
+    /// How the service last exited, such as `exit code 1`
+    last_exit: Option<String>,
+    /// Whether or not the last exit was a failure
+    failed: bool,
+    /// Whether or not the service has exited and won't be restarted
+    finished: bool,
+}
+
+/// Everything the supervisor thread needs to run a service script
+struct ServiceCommand {
+    name: String,
+    script_id: String,
+    script: ServiceScript,
+    /// The path to the script in the `host_scripts` dir
+    path: PathBuf,
+    /// The `PATH` that the script is run with
+    path_env: OsString,
+    /// The path to the shell helper library
+    lucky_env: PathBuf,
+    log_dir: PathBuf,
+    log_retention: ScriptLogRetention,
+    /// The daemon state, used to release the locks that the service holds when it exits
+    state: Arc<RwLock<DaemonState>>,
+}
+
+/// Get the script ID used for a service's status
+fn status_id(service_name: &str) -> String {
+    format!("{}{}__", STATUS_ID_PREFIX, service_name)
+}
+
+/// Start or stop the service scripts and update their statuses
+///
+/// The services are enabled by the `start` hook and disabled by the `stop` hook. This must be run
+/// in a Juju context, such as at the end of a hook or cron tick. `hook_name` is `None` for cron
+/// ticks.
+pub(super) fn update_services(daemon: &LuckyDaemon, hook_name: Option<&str>) -> anyhow::Result<()> {
+    if daemon.lucky_metadata.service_scripts.is_empty() {
+        return Ok(());
+    }
+
+    let enabled = {
+        let mut state = daemon.state.write().unwrap();
+        match hook_name {
+            Some("start") => state.service_scripts_enabled = true,
+            Some("stop") => state.service_scripts_enabled = false,
+            _ => (),
+        }
+        state.service_scripts_enabled
+    };
+
+    if enabled {
+        start_services(daemon)?;
+    } else {
+        stop_services(daemon);
+    }
+
+    update_service_status(daemon)
+}
+
+/// Start the supervisor threads of the services that aren't being supervised yet
+fn start_services(daemon: &LuckyDaemon) -> anyhow::Result<()> {
+    let mut supervisor = daemon.service_scripts.lock().unwrap();
+
+    for (name, script) in &daemon.lucky_metadata.service_scripts {
+        if supervisor.services.contains_key(name) {
+            continue;
+        }
+
+        let script_id = format!("service/{}", name);
+        let command = ServiceCommand {
+            name: name.clone(),
+            script: script.clone(),
+            path: daemon
+                .charm_dir
+                .join("host_scripts")
+                .join(&script.host_script),
+            path_env: std::env::join_paths(tools::script_path_dirs(daemon)?)
+                .context("Path contains invalid character")?,
+            lucky_env: tools::lucky_env_path(daemon),
+            log_dir: script_logs::script_log_dir(daemon, &script_id),
+            log_retention: daemon.lucky_metadata.script_logs.clone(),
+            state: daemon.state.clone(),
+            script_id,
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let progress = Arc::new(Mutex::new(ServiceProgress::default()));
+        let thread = {
+            let stop = stop.clone();
+            let progress = progress.clone();
+            std::thread::spawn(move || supervise(&command, &stop, &progress))
+        };
+
+        supervisor.services.insert(
+            name.clone(),
+            SupervisedService {
+                stop,
+                progress,
+                thread,
+            },
+        );
+    }
+
+    Ok(())
+}
+
+/// Stop all of the services
+fn stop_services(daemon: &LuckyDaemon) {
+    // The supervisor isn't kept locked while the services exit
+    let services: Vec<(String, SupervisedService)> = daemon
+        .service_scripts
+        .lock()
+        .unwrap()
+        .services
+        .drain()
+        .collect();
+
+    for (name, service) in services {
+        service.stop(&name);
+    }
+}
+
+/// Run a service, restarting it according to its restart policy, until it is stopped or finishes
+fn supervise(command: &ServiceCommand, stop: &AtomicBool, progress: &Mutex<ServiceProgress>) {
+    let mut delay = command.script.restart_delay;
+
+    loop {
+        let started = Instant::now();
+        let run_id = tools::script_run_id(&command.script_id);
+        let exit = run_service(command, &run_id, stop, progress);
+        command.state.write().unwrap().release_locks(&run_id);
+        if stop.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let (failed, description) = match exit {
+            Ok(ExitStatus::Exited(0)) => (false, "exit code 0".to_string()),
+            Ok(ExitStatus::Exited(code)) => (true, format!("exit code {}", code)),
+            Ok(ExitStatus::Signaled(signal)) => (true, format!("signal {}", signal)),
+            Ok(status) => (true, format!("{:?}", status)),
+            Err(e) => {
+                log::error!("Could not run service script {}: {:?}", command.name, e);
+                (true, "an error".to_string())
+            }
+        };
+        let restart = match command.script.restart {
+            ServiceRestartPolicy::Always => true,
+            ServiceRestartPolicy::OnFailure => failed,
+            ServiceRestartPolicy::No => false,
+        };
+
+        let message = format!(
+            "Service script {} exited with {}{}",
+            command.name,
+            description,
+            if restart { ", restarting" } else { "" }
+        );
+        if failed {
+            log::warn!("{}", message);
+        } else {
+            log::info!("{}", message);
+        }
+
+        {
+            let mut progress = progress.lock().unwrap();
+            progress.last_exit = Some(description);
+            progress.failed = failed;
+            progress.finished = !restart;
+        }
+        if !restart {
+            return;
+        }
+
+        // Wait longer between restarts while the service keeps exiting soon after it starts
+        if started.elapsed() > SERVICE_STABLE_PERIOD {
+            delay = command.script.restart_delay;
+        }
+        let waiting = Instant::now();
+        while waiting.elapsed() < Duration::from_secs(delay) {
+            if stop.load(Ordering::SeqCst) {
+                return;
+            }
+            std::thread::sleep(SERVICE_POLL_INTERVAL);
+        }
+        delay = delay
+            .saturating_mul(2)
+            .min(MAX_SERVICE_RESTART_DELAY.max(command.script.restart_delay));
+
+        progress.lock().unwrap().restarts += 1;
+    }
+}
+
+/// Run a service until it exits, writing its output to the debug log and the script log
+fn run_service(
+    command: &ServiceCommand,
+    run_id: &str,
+    stop: &AtomicBool,
+    progress: &Mutex<ServiceProgress>,
+) -> anyhow::Result<ExitStatus> {
+    log::info!("Starting service script: {}", command.name);
+
+    // Run the service in its own process group so that the processes that it starts are stopped
+    // with it
+    let mut exec = Exec::cmd("setsid")
+        .arg(&command.path)
+        .args(command.script.args.as_slice())
+        .stdout(Redirection::Pipe)
+        .stderr(Redirection::Merge)
+        .env("PATH", &command.path_env)
+        .env("LUCKY_CONTEXT", "client")
+        .env("LUCKY_ENV", &command.lucky_env)
+        .env("LUCKY_SCRIPT_ID", &command.script_id)
+        .env("LUCKY_SCRIPT_RUN_ID", run_id)
+        // The service outlives the hook or cron tick that starts it, so it can't use its context
+        .env_remove("JUJU_CONTEXT_ID");
+    for (k, v) in &command.script.env {
+        exec = exec.env(k, v);
+    }
+
+    // Record the process group while the progress is locked so that the service is stopped even if
+    // it is stopped while it is starting
+    let mut process = {
+        let mut progress = progress.lock().unwrap();
+        let process = exec
+            .popen()
+            .context(format!("Error executing script: {:?}", command.path))?;
+        progress.pgid = process.pid();
+        if stop.load(Ordering::SeqCst) {
+            if let Some(pgid) = progress.pgid {
+                tools::signal_process_group(pgid, libc::SIGTERM);
+            }
+        }
+        process
+    };
+
+    let stdout = process.stdout.take().expect("Stdout not opened");
+    let mut script_log =
+        script_logs::ScriptLog::create_in(command.log_dir.clone(), command.log_retention.clone());
+    for line in BufReader::new(stdout).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        log::debug!("{} output: {}", command.name, line);
+        script_log.write_line(&line);
+    }
+
+    let exit_status = process.wait();
+    progress.lock().unwrap().pgid = None;
+
+    exit_status.context(format!("Error waiting for script: {:?}", command.path))
+}
+
+/// Update the statuses of the services
+///
+/// This must be run in a Juju context, such as during a hook or cron tick.
+fn update_service_status(daemon: &LuckyDaemon) -> anyhow::Result<()> {
+    // Get the status of each service. The supervisor isn't kept locked while the statuses are set
+    // so that it can't deadlock with the daemon state.
+    let statuses: HashMap<String, Option<ScriptStatus>> = {
+        let supervisor = daemon.service_scripts.lock().unwrap();
+        daemon
+            .lucky_metadata
+            .service_scripts
+            .keys()
+            .map(|name| {
+                let status = supervisor
+                    .services
+                    .get(name)
+                    .and_then(|service| service_status(name, &service.progress.lock().unwrap()));
+                (status_id(name), status)
+            })
+            .collect()
+    };
+
+    let mut state = daemon.state.write().unwrap();
+
+    // Clear the statuses of the services that are fine or that are no longer in the lucky.yaml
+    let cleared: Vec<String> = state
+        .script_statuses
+        .keys()
+        .filter(|id| {
+            id.starts_with(STATUS_ID_PREFIX) && statuses.get(*id).map_or(true, Option::is_none)
+        })
+        .cloned()
+        .collect();
+    for id in &cleared {
+        state.remove_script_status(id);
+    }
+    if !cleared.is_empty() {
+        daemon.juju.set_status(tools::get_juju_status(&state))?;
+    }
+
+    for (id, status) in statuses {
+        let status = match status {
+            Some(status) => status,
+            None => continue,
+        };
+        if state.script_statuses.get(&id) != Some(&status) {
+            tools::set_script_status(&*daemon.juju, &mut state, &id, status)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the status of a service, or `None` if it is running or has finished successfully
+fn service_status(name: &str, progress: &ServiceProgress) -> Option<ScriptStatus> {
+    let last_exit = progress.last_exit.as_ref()?;
+    if progress.pgid.is_some() || (progress.finished && !progress.failed) {
+        return None;
+    }
+
+    Some(if progress.finished {
+        ScriptStatus {
+            state: ScriptState::Blocked,
+            message: Some(format!("Service script {} exited with {}", name, last_exit)),
+        }
+    } else {
+        ScriptStatus {
+            state: ScriptState::Maintenance,
+            message: Some(format!(
+                "Service script {} exited with {}, restarting: restarted {} times",
+                name, last_exit, progress.restarts
+            )),
+        }
+    })
+}
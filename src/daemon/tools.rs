@@ -1,24 +1,277 @@
 use anyhow::format_err;
-use futures::prelude::*;
+use lazy_static::lazy_static;
 use rand::{seq::IteratorRandom, thread_rng};
-use shiplift::{builder::ExecContainerOptions, PullOptions};
+use regex::Regex;
 use subprocess::{Exec, ExitStatus, Redirection};
 
+use std::collections::VecDeque;
+use std::convert::TryFrom;
 use std::env;
 use std::io::{BufRead, BufReader};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::docker::ContainerInfo;
-use crate::rt::block_on;
+use crate::container_engine::{
+    image_user_id, list_labeled_containers, pinned_digest, ContainerContext, ContainerEngine,
+    ImageCommand, RegistryCredentials,
+};
+use crate::docker::{
+    self, ConfigFieldChange, ContainerConfig, ContainerInfo, ContainerUpdateSettings, EnvFile,
+    EnvMode, ImageDigest, RegistryAuth, RegistryAuthSource, UpdateHook, UpdateHookStage,
+};
+use crate::pebble;
+use crate::process::{run_cmd, run_cmd_with_retries};
+use crate::systemd;
+use crate::trace::{self, Span};
 use crate::types::{
-    CharmScript, CharmScriptType, ScriptState, ScriptStatus, LUCKY_EXIT_CODE_HELPER_PREFIX,
+    condition::{Condition, ConditionContext},
+    juju::{CharmMetadata, JUJU_STORAGE_HOOKS},
+    CharmScript, CharmScriptType, ContainerEngineKind, DockerChannel, Platform, ScriptState,
+    ScriptStatus, UpdateStrategy, DEFAULT_CONTAINER_NAME,
 };
 
 const CONTAINER_SUFFIX_CHARS: &str = "abcdefghijklmnopqrstuvwxyz0123456789";
+/// The directory in the Lucky data dir that container env files are rendered to
+const ENV_FILE_DIR: &str = "env_files";
+const PASSWORD_CHARS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+/// The directory in the Lucky data dir that host service env files are rendered to
+pub(super) const HOST_SERVICE_ENV_DIR: &str = "host_service_env_files";
+/// The maximum number of characters to put in the Juju status message
+const MAX_STATUS_MESSAGE_LEN: usize = 256;
+/// The environment variable used to pass the ID of the running action to its scripts
+pub(super) const ACTION_ID_ENV_VAR: &str = "LUCKY_ACTION_ID";
+/// The charm config option that can be used to override the container engine
+const CONTAINER_ENGINE_CONFIG_KEY: &str = "container-engine";
+/// The charm config option that can be used to override where Docker is installed from
+const DOCKER_CHANNEL_CONFIG_KEY: &str = "docker-channel";
+/// The charm config option that can be used to override whether volume data is kept when the unit
+/// is removed
+const PRESERVE_VOLUMES_CONFIG_KEY: &str = "preserve-volumes";
+/// How often the action watchdog checks the action's timeout and client process
+const ACTION_WATCHDOG_INTERVAL: Duration = Duration::from_millis(500);
+/// How long to wait for a host script to exit before checking again
+const SCRIPT_WAIT_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a host script that has timed out has to exit after `SIGTERM` before it is sent
+/// `SIGKILL`
+const SCRIPT_KILL_GRACE_PERIOD: Duration = Duration::from_secs(10);
+/// The number of lines at the end of a script's output that are kept for errors and `on-failure`
+/// scripts
+const SCRIPT_OUTPUT_TAIL_LINES: usize = 50;
+/// The longest that the delay between the attempts of a script can grow to
+const MAX_SCRIPT_RETRY_DELAY: Duration = Duration::from_secs(300);
+/// How many custom events can be emitted from inside of each other before giving up. This keeps
+/// events that emit each other from looping forever.
+///
+/// The depth is tracked separately for each chain of events by passing it to the scripts of an
+/// event in `LUCKY_EVENT_DEPTH`, which the client sends back when a script emits another event.
+pub(super) const MAX_EVENT_DEPTH: usize = 8;
+/// The file in the Lucky data dir that the shell helper library is written to
+const LUCKY_ENV_FILE: &str = "lucky-env.sh";
+
+lazy_static! {
+    /// Matches ANSI escape sequences such as color codes
+    static ref ANSI_ESCAPE_RE: Regex =
+        Regex::new(r"\x1b(\[[0-9;?]*[ -/]*[@-~]|\][^\x07\x1b]*(\x07|\x1b\\)|[@-_])")
+            .expect("Could not compile regex");
+}
 
 use super::*;
 
+/// Get the event depth sent by a client, which is `0` if the calling script wasn't run for an event
+/// or a kv watch
+pub(super) fn event_depth(depth: Option<i64>) -> usize {
+    depth.and_then(|x| usize::try_from(x).ok()).unwrap_or(0)
+}
+
+/// Detect the version of Juju that is running the charm and log any features that it doesn't
+/// support
+///
+/// Returns `None` if the version could not be detected.
+pub(super) fn detect_juju_version(juju: &dyn JujuBackend) -> Option<JujuVersion> {
+    match juju.juju_version() {
+        Ok(version) => {
+            log::info!("Detected Juju version: {}", version);
+
+            let unsupported: Vec<String> = JujuFeature::ALL
+                .iter()
+                .filter(|x| !version.supports(**x))
+                .map(ToString::to_string)
+                .collect();
+            if !unsupported.is_empty() {
+                log::info!(
+                    "Features not supported by this version of Juju: {}",
+                    unsupported.join(", ")
+                );
+            }
+
+            Some(version)
+        }
+        Err(e) => {
+            log::warn!(
+                "{:?}",
+                e.context("Could not detect Juju version, assuming all features are available")
+            );
+            None
+        }
+    }
+}
+
+/// Get the names of the workload containers in the charm's metadata.yaml, sorted by name
+///
+/// Only Kubernetes sidecar charms have workload containers.
+pub(super) fn get_sidecar_containers(charm_dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> =
+        match crate::config::load_yaml::<CharmMetadata>(charm_dir, "metadata") {
+            Ok(metadata) => metadata
+                .containers
+                .map(|containers| containers.keys().cloned().collect())
+                .unwrap_or_default(),
+            Err(e) => {
+                log::warn!("{:?}", e.context("Could not load charm metadata"));
+                vec![]
+            }
+        };
+    names.sort();
+
+    names
+}
+
+/// Resolve the platform that the charm is running on
+///
+/// If the platform is `auto`, the charm is considered to be running on Kubernetes if it has
+/// workload containers and their Pebble sockets are present.
+pub(super) fn resolve_platform(platform: Platform, sidecar_containers: &[String]) -> Platform {
+    let platform = match platform {
+        Platform::Auto if !sidecar_containers.is_empty() && pebble::is_available() => {
+            Platform::Kubernetes
+        }
+        Platform::Auto => Platform::Machine,
+        platform => platform,
+    };
+    log::info!("Running on platform: {:?}", platform);
+
+    platform
+}
+
+/// Get the container engine that the charm is configured to use
+///
+/// The `container-engine` charm config option, if the charm has one and it is set, takes
+/// precedence over the `LUCKY_CONTAINER_ENGINE` environment variable, which can be set in a
+/// `lucky.toml`, and that takes precedence over the engine in the `lucky.yaml`.
+pub(super) fn get_configured_container_engine(
+    daemon: &LuckyDaemon,
+) -> anyhow::Result<ContainerEngineKind> {
+    match daemon.juju.config_get()?.get(CONTAINER_ENGINE_CONFIG_KEY) {
+        Some(JsonValue::String(engine)) if !engine.is_empty() => engine
+            .parse()
+            .map_err(|_| format_err!("Invalid container engine in charm config: {}", engine)),
+        _ => match std::env::var("LUCKY_CONTAINER_ENGINE") {
+            Ok(engine) if !engine.is_empty() => engine.parse().map_err(|_| {
+                format_err!(
+                    "Invalid container engine in LUCKY_CONTAINER_ENGINE: {}",
+                    engine
+                )
+            }),
+            _ => Ok(daemon.lucky_metadata.container_engine),
+        },
+    }
+}
+
+/// Get the channel that Docker is installed from if it isn't already installed
+///
+/// The `docker-channel` charm config option, if the charm has one and it is set, takes precedence
+/// over the channel in the `lucky.yaml`.
+pub(super) fn get_configured_docker_channel(daemon: &LuckyDaemon) -> anyhow::Result<DockerChannel> {
+    match daemon.juju.config_get()?.get(DOCKER_CHANNEL_CONFIG_KEY) {
+        Some(JsonValue::String(channel)) if !channel.is_empty() => channel
+            .parse()
+            .map_err(|_| format_err!("Invalid Docker channel in charm config: {}", channel)),
+        _ => Ok(daemon.lucky_metadata.docker_channel),
+    }
+}
+
+/// Get whether or not the data of the container volumes should be kept when the unit is removed
+///
+/// The `preserve-volumes` charm config option, if the charm has one, takes precedence over the
+/// setting in the `lucky.yaml`.
+pub(super) fn get_configured_preserve_volumes(daemon: &LuckyDaemon) -> anyhow::Result<bool> {
+    match daemon.juju.config_get()?.get(PRESERVE_VOLUMES_CONFIG_KEY) {
+        Some(JsonValue::Bool(preserve)) => Ok(*preserve),
+        _ => Ok(daemon.lucky_metadata.preserve_volumes),
+    }
+}
+
+/// Make sure that the daemon's container engine is installed and running, installing it if it
+/// isn't
+///
+/// The progress is reported in the unit status.
+#[function_name::named]
+pub(super) fn ensure_container_engine(
+    daemon: &LuckyDaemon,
+    state: &mut DaemonState,
+) -> anyhow::Result<()> {
+    let kind = *daemon.container_engine_kind.read().unwrap();
+    let docker_channel = get_configured_docker_channel(daemon)?;
+
+    let result = crate::container_engine::install(
+        kind,
+        daemon.lucky_metadata.podman_user.as_deref(),
+        docker_channel,
+        &mut |message: &str| {
+            daemon_set_status!(daemon, state, ScriptState::Maintenance, message);
+            Ok(())
+        },
+    );
+    if let Err(e) = result {
+        daemon_set_status!(
+            daemon,
+            state,
+            ScriptState::Blocked,
+            format!("Could not install {}", kind.as_ref())
+        );
+        return Err(e);
+    }
+
+    daemon_set_status!(daemon, state, ScriptState::Active);
+
+    Ok(())
+}
+
+/// Return an error if the detected Juju version does not support the given feature
+///
+/// If the Juju version could not be detected, the feature is assumed to be available.
+pub(super) fn require_juju_feature(
+    daemon: &LuckyDaemon,
+    feature: JujuFeature,
+) -> anyhow::Result<()> {
+    if let Some(version) = daemon.juju_version {
+        if !version.supports(feature) {
+            anyhow::bail!(
+                "The {} feature requires Juju {} or newer, but this unit is running Juju {}",
+                feature,
+                feature.min_version(),
+                version
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the shell helper library that host scripts can source from `$LUCKY_ENV` to the Lucky data
+/// dir. It is re-written every time the daemon starts so that it matches the Lucky version.
+pub(super) fn write_lucky_env(daemon: &LuckyDaemon) -> anyhow::Result<()> {
+    let path = lucky_env_path(daemon);
+    std::fs::write(&path, include_str!("lucky-env.sh"))
+        .context(format!("Could not write shell helper library: {:?}", path))
+}
+
+/// Get the path to the shell helper library that is passed to host scripts in `$LUCKY_ENV`
+pub(super) fn lucky_env_path(daemon: &LuckyDaemon) -> PathBuf {
+    daemon.lucky_data_dir.join(LUCKY_ENV_FILE)
+}
+
 /// Load the daemon state from the filesystem
 pub(super) fn load_state(daemon: &LuckyDaemon) -> anyhow::Result<()> {
     let state_file_path = daemon.lucky_data_dir.join("state.yaml");
@@ -40,7 +293,174 @@ pub(super) fn load_state(daemon: &LuckyDaemon) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Update the container configuration in the daemon state to match the containers declared in the
+/// `lucky.yaml`
+///
+/// Declared containers that don't exist yet are added, and changes to the declaration of existing
+/// containers are merged into their config without overwriting changes made by scripts.
+/// Containers that were declared but have been removed from the `lucky.yaml` are marked for
+/// removal. The changes are applied to Docker the next time the container configuration is
+/// applied.
+pub(super) fn reconcile_declared_containers(daemon: &LuckyDaemon) -> anyhow::Result<()> {
+    let mut state_guard = daemon.state.write().unwrap();
+    // Reborrow the state so that we can borrow its fields separately
+    let state = &mut *state_guard;
+    let declared = &daemon.lucky_metadata.containers;
+
+    for (name, spec) in declared {
+        let is_default = name == DEFAULT_CONTAINER_NAME;
+
+        // Take the container out of the state while we update it
+        let existing = if is_default {
+            state.default_container.take()
+        } else {
+            state.named_containers.remove(name)
+        };
+
+        let mut container = existing.unwrap_or_else(|| {
+            log::debug!("Adding declared container: {}", name);
+            ContainerInfo::new(&spec.image).into()
+        });
+
+        // Apply the declared update strategy if it has changed
+        let old_strategy = container
+            .declared_spec
+            .as_ref()
+            .and_then(|x| x.update_strategy);
+        if old_strategy != spec.update_strategy {
+            state
+                .container_updates
+                .entry(name.clone())
+                .or_default()
+                .strategy = spec.update_strategy.unwrap_or_default();
+        }
+
+        // Apply the changes since the last time the container was declared
+        let result = if container.declared_spec.as_ref() == Some(spec) {
+            Ok(())
+        } else {
+            container.update(|info| -> anyhow::Result<()> {
+                let old_spec = info.declared_spec.clone().unwrap_or_default();
+                info.config
+                    .apply_spec_changes(&old_spec, spec)
+                    .context(format!("Invalid spec for container: {}", name))?;
+                if old_spec.image_resource != spec.image_resource {
+                    info.image_resource = spec.image_resource.clone();
+                    info.loaded_image_resource = None;
+                }
+                info.declared_spec = Some(spec.clone());
+                Ok(())
+            })
+        };
+
+        // Put the container back before handling any errors
+        if is_default {
+            state.default_container = Some(container);
+        } else {
+            state.named_containers.insert(name.clone(), container);
+        }
+        result?;
+    }
+
+    // Remove containers that are no longer declared
+    let removed_containers = state
+        .named_containers
+        .iter_mut()
+        .filter(|(name, _)| !declared.contains_key(*name))
+        .map(|(_, container)| container)
+        .chain(
+            state
+                .default_container
+                .as_mut()
+                .filter(|_| !declared.contains_key(DEFAULT_CONTAINER_NAME)),
+        );
+    for container in removed_containers {
+        if container.declared_spec.is_some() {
+            container.update(|info| info.pending_removal = true);
+        }
+    }
+
+    Ok(())
+}
+
 /// Write out the daemon state to fileystem
+/// Find the containers that were created for this unit by an earlier run of the daemon and make
+/// sure that the daemon state matches them
+///
+/// The containers are found by their `lucky.unit` label. A container that the state no longer
+/// knows about, because the daemon exited before it could save its state, is reattached to if it
+/// was created for one of the Lucky containers with its current config, so that it isn't
+/// re-created. Reattached containers are started again unless they have been stopped. Any other
+/// containers are orphans and are removed.
+pub(super) fn reattach_containers(daemon: &LuckyDaemon) -> anyhow::Result<()> {
+    // Nothing has been created if the container engine hasn't been chosen yet
+    if daemon.state.read().unwrap().container_engine.is_none() {
+        return Ok(());
+    }
+
+    let engine = daemon.get_container_engine()?;
+    let found = list_labeled_containers(
+        &*engine,
+        &format!("{}={}", docker::UNIT_LABEL, get_unit_name_for_engine()?),
+    )?;
+
+    let mut state_guard = daemon.state.write().unwrap();
+    // Reborrow the state so that we can borrow its fields separately
+    let state = &mut *state_guard;
+
+    let mut known: HashSet<String> = HashSet::new();
+    let containers = state
+        .named_containers
+        .iter_mut()
+        .map(|(name, container)| (name.as_str(), container))
+        .chain(
+            state
+                .default_container
+                .iter_mut()
+                .map(|container| (DEFAULT_CONTAINER_NAME, container)),
+        );
+    for (name, container) in containers {
+        if let Some(id) = &container.id {
+            if found.iter().any(|x| &x.id == id) {
+                known.insert(id.clone());
+                continue;
+            }
+        }
+        if container.pending_removal {
+            continue;
+        }
+
+        let fingerprint = container.config.fingerprint();
+        let matching = found.iter().find(|x| {
+            !known.contains(&x.id)
+                && x.labels.get(docker::CONTAINER_LABEL).map(String::as_str) == Some(name)
+                && x.labels.get(docker::CONFIG_LABEL) == Some(&fingerprint)
+        });
+        if let Some(matching) = matching {
+            log::info!("Reattaching to container {}: {}", name, matching.id);
+            let was_clean = container.is_clean();
+            container.update(|info| info.id = Some(matching.id.clone()));
+            if was_clean {
+                container.clean();
+            }
+            known.insert(matching.id.clone());
+
+            if !matching.running && !container.stopped {
+                log::debug!("Starting reattached container: {}", matching.id);
+                engine.start_container(&matching.id)?;
+            }
+        }
+    }
+
+    // Remove the containers that don't belong to any of the Lucky containers
+    for orphan in found.iter().filter(|x| !known.contains(&x.id)) {
+        log::info!("Removing orphaned container: {}", orphan.id);
+        remove_old_container(daemon, &*engine, &orphan.id)?;
+    }
+
+    Ok(())
+}
+
 pub(super) fn flush_state(daemon: &LuckyDaemon) -> anyhow::Result<()> {
     log::debug!("Flushing daemon state to disk");
     let state_file_path = daemon.lucky_data_dir.join("state.yaml");
@@ -69,8 +489,203 @@ pub(super) fn flush_state(daemon: &LuckyDaemon) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Load the currently opened ports from Juju into the daemon state
+///
+/// This only queries Juju the first time it is called after the daemon starts. After that the
+/// daemon keeps track of the ports that it opens and closes itself.
+pub(super) fn sync_opened_ports(daemon: &LuckyDaemon) -> anyhow::Result<()> {
+    // Skip if we have already synchronized the ports
+    if daemon.opened_ports_synced.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+
+    log::debug!("Loading opened ports from Juju");
+    let opened_ports = daemon.juju.opened_ports()?;
+    let mut state = daemon.state.write().unwrap();
+
+    // Log any ports that were out of sync with our persisted state
+    for port in opened_ports
+        .iter()
+        .filter(|&x| !state.opened_ports.contains(x))
+    {
+        log::debug!("Found port opened outside of the daemon: {}", port);
+    }
+    for port in state
+        .opened_ports
+        .iter()
+        .filter(|&x| !opened_ports.contains(x))
+    {
+        log::debug!("Forgetting port that is no longer open: {}", port);
+    }
+
+    state.opened_ports = opened_ports.into_iter().collect();
+    daemon.opened_ports_synced.store(true, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// Get the ID of the peer relation used for the peer store
+///
+/// If `relation_name` is `None`, the first peer relation in the charm's metadata.yaml is used.
+pub(super) fn get_peer_relation_id(
+    daemon: &LuckyDaemon,
+    relation_name: Option<&str>,
+) -> anyhow::Result<String> {
+    let relation_name = match relation_name {
+        Some(name) => name.to_string(),
+        None => get_peer_relation_names(daemon)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| format_err!("The charm does not define any peer relations"))?,
+    };
+
+    daemon
+        .juju
+        .relation_ids(&relation_name)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| {
+            format_err!(
+                "The peer relation \"{}\" has not been established yet",
+                relation_name
+            )
+        })
+}
+
+/// Get the names of the peer relations defined in the charm's metadata.yaml
+fn get_peer_relation_names(daemon: &LuckyDaemon) -> anyhow::Result<Vec<String>> {
+    let metadata: CharmMetadata = crate::config::load_yaml(&daemon.charm_dir, "metadata")
+        .context("Could not load charm metadata")?;
+
+    let mut names: Vec<String> = metadata
+        .peers
+        .map(|peers| peers.keys().cloned().collect())
+        .unwrap_or_default();
+    // Sort the names so that the default peer relation is consistent
+    names.sort();
+
+    Ok(names)
+}
+
+/// Get all of the data in the application's peer store
+pub(super) fn get_peer_store(
+    daemon: &LuckyDaemon,
+    relation_name: Option<&str>,
+) -> anyhow::Result<HashMap<String, String>> {
+    let relation_id = get_peer_relation_id(daemon, relation_name)?;
+    let unit_name =
+        std::env::var("JUJU_UNIT_NAME").context("Env var JUJU_UNIT_NAME not readable!")?;
+
+    // All units can read the application data of a peer relation by asking for the data of
+    // their own application
+    daemon.juju.relation_get(
+        Some(juju::SpecificRelation {
+            relation_id,
+            remote_unit: unit_name,
+        }),
+        true,
+    )
+}
+
+/// Update the daemon's copy of the peer store if the given hook is a peer relation hook
+///
+/// Returns the keys that have changed since the last peer relation hook, or `None` if the hook
+/// is not a `-relation-changed` hook for a peer relation.
+pub(super) fn update_peer_store(
+    daemon: &LuckyDaemon,
+    hook_name: &str,
+) -> anyhow::Result<Option<Vec<String>>> {
+    let relation_name = match hook_name.strip_suffix("-relation-changed") {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    if !get_peer_relation_names(daemon)?
+        .iter()
+        .any(|x| x == relation_name)
+    {
+        return Ok(None);
+    }
+
+    let peer_store = get_peer_store(daemon, Some(relation_name))?;
+    let mut state = daemon.state.write().unwrap();
+
+    // Find keys that have been added, changed, or removed
+    let mut changed_keys: Vec<String> = peer_store
+        .iter()
+        .filter(|(k, v)| state.peer_store.get(*k) != Some(v))
+        .map(|(k, _)| k.clone())
+        .chain(
+            state
+                .peer_store
+                .keys()
+                .filter(|k| !peer_store.contains_key(*k))
+                .cloned(),
+        )
+        .collect();
+    changed_keys.sort();
+
+    log::debug!("Changed peer store keys: {:?}", changed_keys);
+    state.peer_store = peer_store;
+
+    Ok(Some(changed_keys))
+}
+
+/// Get the status of the Juju model from the controller API
+#[cfg(feature = "juju-api")]
+pub(super) fn get_model_status() -> anyhow::Result<juju::ModelStatus> {
+    trace::in_span(Span::start("juju api model status"), || {
+        juju::JujuApiClient::connect()?.model_status()
+    })
+}
+
+/// Get the status of the Juju model from the controller API
+#[cfg(not(feature = "juju-api"))]
+pub(super) fn get_model_status() -> anyhow::Result<juju::ModelStatus> {
+    anyhow::bail!(concat!(
+        "Lucky was built without support for the Juju controller API. Lucky must be built with ",
+        "the `juju-api` feature to use the `lucky model` commands."
+    ))
+}
+
+/// Generate a random password of the given length
+pub(super) fn generate_password(length: usize) -> String {
+    let mut rng = thread_rng();
+    let mut password = String::with_capacity(length);
+    for _ in 0..length {
+        password.push(
+            PASSWORD_CHARS
+                .chars()
+                .choose(&mut rng)
+                .expect("Empty password chars"),
+        );
+    }
+
+    password
+}
+
+/// Append an entry to the daemon's audit log
+///
+/// The audit log keeps track of operator actions, such as resetting the daemon state, that are
+/// important to know about when debugging a unit.
+pub(super) fn write_audit_entry(daemon: &LuckyDaemon, message: &str) -> anyhow::Result<()> {
+    let audit_file_path = daemon.lucky_data_dir.join("audit.log");
+    let mut audit_file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&audit_file_path)
+        .context(format!("Could not open audit log: {:?}", audit_file_path))?;
+
+    writeln!(audit_file, "[{}]: {}", Local::now().to_rfc3339(), message).context(format!(
+        "Failed writing to audit log: {:?}",
+        audit_file_path
+    ))?;
+
+    Ok(())
+}
+
 /// Set the status of a script
 pub(super) fn set_script_status(
+    juju: &dyn JujuBackend,
     state: &mut DaemonState,
     script_id: &str,
     status: ScriptStatus,
@@ -90,11 +705,20 @@ pub(super) fn set_script_status(
         status
     );
 
+    // Make sure no secrets end up in the Juju status
+    let status = ScriptStatus {
+        message: status.message.map(|x| crate::log::redact(&x)),
+        ..status
+    };
+
     // Insert script status
-    state.script_statuses.insert(script_id.into(), status);
+    let old = state
+        .script_statuses
+        .insert(script_id.into(), status.clone());
+    state.record_status_change(script_id, old, Some(status));
 
     // Set the Juju status to the consolidated script statuses
-    crate::juju::set_status(tools::get_juju_status(state))?;
+    juju.set_status(tools::get_juju_status(state))?;
 
     Ok(())
 }
@@ -103,10 +727,14 @@ pub(super) fn set_script_status(
 pub(super) fn get_juju_status(state: &DaemonState) -> ScriptStatus {
     // The resulting Juju state
     let mut juju_state = ScriptState::default();
-    // The resulting Juju status message
-    let mut juju_message = None;
+    // The messages of each of the scripts
+    let mut messages = vec![];
+
+    // Sort the statuses by script ID so that the message order is stable between hooks
+    let mut statuses: Vec<_> = state.script_statuses.iter().collect();
+    statuses.sort_by_key(|(script_id, _)| *script_id);
 
-    for status in state.script_statuses.values() {
+    for (_, status) in statuses {
         // If this script state has a higher precedence
         if status.state > juju_state {
             // Set the Juju state to the more precedent state
@@ -115,12 +743,9 @@ pub(super) fn get_juju_status(state: &DaemonState) -> ScriptStatus {
 
         // If there is a message with the status
         if let Some(message) = &status.message {
-            if let Some(current) = juju_message {
-                // Add this message to Juju message
-                juju_message = Some([current, message.clone()].join(", "));
-            } else {
-                // Set Juju message to this message
-                juju_message = Some(message.clone());
+            let message = sanitize_status_message(message);
+            if !message.is_empty() {
+                messages.push(message);
             }
         }
     }
@@ -128,7 +753,68 @@ pub(super) fn get_juju_status(state: &DaemonState) -> ScriptStatus {
     // Return Juju status
     ScriptStatus {
         state: juju_state,
-        message: juju_message,
+        message: join_status_messages(&messages),
+    }
+}
+
+/// Strip ANSI escape sequences from a status message and collapse newlines and other control
+/// characters into single spaces
+fn sanitize_status_message(message: &str) -> String {
+    ANSI_ESCAPE_RE
+        .replace_all(message, "")
+        .split(|c: char| c.is_whitespace() || c.is_control())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Join script status messages into one message that is no longer than `MAX_STATUS_MESSAGE_LEN`
+///
+/// Messages that don't fit are left off and replaced with a count of how many scripts were elided,
+/// such as `first message, second message ... (+3 more)`. If the first message doesn't fit on its
+/// own it is truncated and all of the others are elided.
+fn join_status_messages(messages: &[String]) -> Option<String> {
+    let elided_suffix = |count: usize| format!(" ... (+{} more)", count);
+
+    let mut joined = String::new();
+    let mut joined_len = 0;
+    for (i, message) in messages.iter().enumerate() {
+        let separator = if joined.is_empty() { "" } else { ", " };
+        let message_len = message.chars().count();
+
+        // Leave room to say how many messages were elided in case the next message doesn't fit
+        let remaining = messages.len() - i - 1;
+        let reserved = if remaining == 0 {
+            0
+        } else {
+            elided_suffix(remaining).len()
+        };
+        let available =
+            MAX_STATUS_MESSAGE_LEN.saturating_sub(joined_len + separator.len() + reserved);
+
+        if message_len <= available {
+            joined.push_str(separator);
+            joined.push_str(message);
+            joined_len += separator.len() + message_len;
+        } else if joined.is_empty() {
+            // If the first message doesn't fit on its own, truncate it and elide the rest
+            let truncated: String = message.chars().take(available.saturating_sub(3)).collect();
+            joined.push_str(&truncated);
+            joined.push_str("...");
+            if remaining > 0 {
+                joined.push_str(&elided_suffix(remaining));
+            }
+            break;
+        } else {
+            joined.push_str(&elided_suffix(messages.len() - i));
+            break;
+        }
+    }
+
+    if joined.is_empty() {
+        None
+    } else {
+        Some(joined)
     }
 }
 
@@ -162,7 +848,168 @@ pub(super) fn run_charm_script(
     // statuses.
     script_id_override: Option<&str>,
 ) -> anyhow::Result<()> {
-    match &script.script_type {
+    // Skip the script if its condition is false
+    if let Some(when) = &script.when {
+        let condition: Condition = when
+            .parse()
+            .context(format!("Invalid condition for script {}", script.name()))?;
+        let script_id = charm_script_id(hook_name, script, script_id_override);
+        let context = ScriptConditionContext {
+            daemon,
+            hook_name,
+            script_id: &script_id,
+        };
+        if !condition.evaluate(&context).context(format!(
+            "Could not evaluate condition for script {}",
+            script.name()
+        ))? {
+            log::debug!(
+                "Skipping script {} because its condition is false: {}",
+                script.name(),
+                when
+            );
+            return Ok(());
+        }
+    }
+
+    // Add the script's own templated environment variables
+    let script_environment;
+    let environment = if script.env.is_empty() {
+        environment
+    } else {
+        let mut env = environment.clone();
+        env.extend(render_script_env(
+            daemon,
+            script,
+            &charm_script_id(hook_name, script, script_id_override),
+        )?);
+        script_environment = env;
+        &script_environment
+    };
+
+    // Make sure that the interpreter of a host script is installed
+    let interpreter = match (&script.interpreter, &script.script_type) {
+        (Some(interpreter), CharmScriptType::Host { .. })
+        | (Some(interpreter), CharmScriptType::InlineHost { .. }) => Some(resolve_interpreter(
+            daemon,
+            interpreter,
+            script.interpreter_package.as_deref(),
+        )?),
+        _ => None,
+    };
+
+    // Run the script, retrying it if it fails and has retries left
+    let mut retry_delay = Duration::from_secs(script.retry_delay).min(MAX_SCRIPT_RETRY_DELAY);
+    let mut attempt = 1;
+    loop {
+        let mut attempt_environment = environment.clone();
+        attempt_environment.insert("LUCKY_SCRIPT_ATTEMPT".into(), attempt.to_string());
+
+        let result = run_charm_script_attempt(
+            daemon,
+            hook_name,
+            script,
+            &attempt_environment,
+            script_id_override,
+            interpreter.as_deref(),
+        );
+        let cancelled = get_action_cancel_handle(daemon, environment)
+            .map_or(false, |x| x.cancel_reason().is_some());
+        match result {
+            Err(e) if attempt <= script.retries && !cancelled => {
+                log::warn!(
+                    "Script {} failed on attempt {} of {}, retrying in {} seconds: {:?}",
+                    script.name(),
+                    attempt,
+                    script.retries + 1,
+                    retry_delay.as_secs(),
+                    e
+                );
+                std::thread::sleep(retry_delay);
+                retry_delay = retry_delay
+                    .checked_mul(2)
+                    .unwrap_or(MAX_SCRIPT_RETRY_DELAY)
+                    .min(MAX_SCRIPT_RETRY_DELAY);
+                attempt += 1;
+            }
+            Err(e) => {
+                if let Some(on_failure) = &script.on_failure {
+                    run_on_failure_script(
+                        daemon,
+                        hook_name,
+                        script,
+                        on_failure,
+                        environment,
+                        script_id_override,
+                        &e,
+                    );
+                }
+                return Err(e);
+            }
+            Ok(()) => return Ok(()),
+        }
+    }
+}
+
+/// Run the `on-failure` script of a script that failed. The failure of the `on-failure` script is
+/// only logged so that the error of the original script is the one that is reported.
+fn run_on_failure_script(
+    daemon: &LuckyDaemon,
+    hook_name: &str,
+    script: &CharmScript,
+    on_failure: &CharmScript,
+    environment: &HashMap<String, String>,
+    script_id_override: Option<&str>,
+    error: &anyhow::Error,
+) {
+    let script_id = script_id_override.map_or_else(|| script.name(), ToOwned::to_owned);
+
+    let mut environment = environment.clone();
+    environment.insert("LUCKY_FAILED_SCRIPT_ID".into(), script_id.clone());
+    environment.insert("LUCKY_FAILED_ERROR".into(), format!("{:#}", error));
+    if let Some(failure) = error.downcast_ref::<ScriptFailure>() {
+        environment.insert("LUCKY_FAILED_EXIT_CODE".into(), failure.code.to_string());
+        environment.insert("LUCKY_FAILED_OUTPUT".into(), failure.output.clone());
+    }
+
+    log::info!("Running on-failure script for script {}", script_id);
+    if let Err(e) = run_charm_script(
+        daemon,
+        hook_name,
+        on_failure,
+        &environment,
+        Some(&format!("{}_on_failure", script_id)),
+    ) {
+        log::error!("On-failure script for script {} failed: {:?}", script_id, e);
+    }
+}
+
+/// Run a charm script once
+fn run_charm_script_attempt(
+    daemon: &LuckyDaemon,
+    hook_name: &str,
+    script: &CharmScript,
+    environment: &HashMap<String, String>,
+    script_id_override: Option<&str>,
+    interpreter: Option<&Path>,
+) -> anyhow::Result<()> {
+    // Give the run its own ID so that concurrent runs of the same script hold separate locks
+    let run_id =
+        script_run_id(&script_id_override.map_or_else(|| script.name(), ToOwned::to_owned));
+    let mut environment = environment.clone();
+    environment.insert("LUCKY_SCRIPT_RUN_ID".into(), run_id.clone());
+
+    let span = Span::start(&format!("script {}", script.name()))
+        .with_attr("juju.hook", hook_name)
+        .with_attr("lucky.script.async", &script.is_async.to_string())
+        .with_attr(
+            "lucky.script.attempt",
+            environment
+                .get("LUCKY_SCRIPT_ATTEMPT")
+                .map_or("1", String::as_str),
+        );
+
+    let exit = trace::in_span(span, || match &script.script_type {
         // Run named host script
         CharmScriptType::Host { host_script, args } => run_host_script(
             daemon,
@@ -173,6 +1020,8 @@ pub(super) fn run_charm_script(
             hook_name,
             &environment,
             script_id_override,
+            script,
+            interpreter,
         ),
         // Run inline host script
         CharmScriptType::InlineHost {
@@ -187,6 +1036,8 @@ pub(super) fn run_charm_script(
             hook_name,
             &environment,
             script_id_override,
+            script,
+            interpreter,
         ),
         // Run named container script
         CharmScriptType::Container {
@@ -224,49 +1075,478 @@ pub(super) fn run_charm_script(
             &environment,
             script_id_override,
         ),
+    });
+
+    // Release the locks that the script didn't release itself
+    daemon.state.write().unwrap().release_locks(&run_id);
+
+    match exit? {
+        Some(exit) => check_script_exit(daemon, script, exit),
+        // The script was skipped
+        None => Ok(()),
     }
 }
 
-/// Run one of the charm's host scripts
-fn run_host_script(
-    daemon: &LuckyDaemon,
-    script_type: ScriptType,
-    hook_name: &str,
-    environment: &HashMap<String, String>,
-    script_id_override: Option<&str>, // Optional override for script id
-) -> anyhow::Result<()> {
-    // Create script name based on script type
-    let script_name = match &script_type {
-        ScriptType::Inline { .. } => format!("{}_inline", hook_name),
-        ScriptType::Named { name, .. } => name.clone(),
-    };
+/// The values that the `when` conditions of scripts can reference
+struct ScriptConditionContext<'a> {
+    daemon: &'a LuckyDaemon,
+    hook_name: &'a str,
+    /// The ID of the script, which is the namespace of the key-value store keys that it sets
+    script_id: &'a str,
+}
 
-    log::info!("Running host script: {}", script_name);
+impl ConditionContext for ScriptConditionContext<'_> {
+    fn is_leader(&self) -> anyhow::Result<bool> {
+        self.daemon.juju.is_leader()
+    }
 
-    // Add bin dirs to the PATH
-    let path_env = {
-        // Get initial PATH if set
-        let mut paths = if let Some(path) = std::env::var_os("PATH") {
-            env::split_paths(&path).collect::<Vec<_>>()
-        } else {
-            vec![]
-        };
+    fn hook(&self) -> &str {
+        self.hook_name
+    }
 
-        // Add the charm's bin dir
-        paths.push(daemon.charm_dir.join("bin"));
+    fn config(&self, key: &str) -> anyhow::Result<JsonValue> {
+        Ok(self
+            .daemon
+            .juju
+            .config_get()?
+            .remove(key)
+            .unwrap_or(JsonValue::Null))
+    }
 
-        // Add the directory containing the Lucky executable
-        if let Some(path) = std::env::current_exe()?.parent() {
-            paths.push(path.to_owned());
-        };
+    fn kv(&self, key: &str) -> anyhow::Result<Option<String>> {
+        let state = self.daemon.state.read().unwrap();
+        get_script_kv_value(self.daemon, &state, self.script_id, key)
+    }
+}
 
-        env::join_paths(paths).context("Path contains invalid character")?
-    };
+/// Get a new ID for one run of the script with the given ID, which is its `LUCKY_SCRIPT_RUN_ID`
+pub(super) fn script_run_id(script_id: &str) -> String {
+    format!("{}-{:08x}", script_id, rand::random::<u32>())
+}
 
-    // Build the command to run
+/// Get the ID that a charm script runs with, which is its `LUCKY_SCRIPT_ID`
+fn charm_script_id(
+    hook_name: &str,
+    script: &CharmScript,
+    script_id_override: Option<&str>,
+) -> String {
+    if let Some(script_id) = script_id_override {
+        return script_id.into();
+    }
+
+    match &script.script_type {
+        CharmScriptType::Host { host_script, .. } => host_script.clone(),
+        CharmScriptType::Container {
+            container_script, ..
+        } => container_script.clone(),
+        CharmScriptType::InlineHost { .. } | CharmScriptType::InlineContainer { .. } => {
+            format!("{}_inline", hook_name)
+        }
+    }
+}
+
+/// Get a key-value store value as a script would see it: from the script's own namespace, which
+/// `lucky kv set` uses by default, or from the global namespace if it isn't set there
+fn get_script_kv_value(
+    daemon: &LuckyDaemon,
+    state: &DaemonState,
+    script_id: &str,
+    key: &str,
+) -> anyhow::Result<Option<String>> {
+    match daemon.get_kv_value(state, Some(script_id), key)? {
+        Some(value) => Ok(Some(value)),
+        None => daemon.get_kv_value(state, None, key),
+    }
+}
+
+/// Get the path to a script interpreter, installing its package with apt if it isn't installed
+fn resolve_interpreter(
+    daemon: &LuckyDaemon,
+    interpreter: &str,
+    package: Option<&str>,
+) -> anyhow::Result<PathBuf> {
+    if let Some(path) = find_interpreter(daemon, interpreter)? {
+        return Ok(path);
+    }
+
+    let package = package.ok_or_else(|| {
+        format_err!(
+            "Script interpreter {} is not installed and no interpreter-package was given",
+            interpreter
+        )
+    })?;
+    log::info!(
+        "Installing package {} for script interpreter {}",
+        package,
+        interpreter
+    );
+    // Update the package lists first, which are empty on fresh machines
+    run_cmd_with_retries("apt-get", &["update"], &Default::default())
+        .context("Could not update the apt package lists")?;
+    run_cmd_with_retries("apt-get", &["install", "-y", package], &Default::default())
+        .context(format!("Could not install package {}", package))?;
+
+    find_interpreter(daemon, interpreter)?.ok_or_else(|| {
+        format_err!(
+            "Script interpreter {} was not found after installing package {}",
+            interpreter,
+            package
+        )
+    })
+}
+
+/// Find a script interpreter by path, relative to the charm dir, or by name in the script `PATH`
+fn find_interpreter(daemon: &LuckyDaemon, interpreter: &str) -> anyhow::Result<Option<PathBuf>> {
+    if interpreter.contains('/') {
+        let path = daemon.charm_dir.join(interpreter);
+        return Ok(Some(path).filter(|x| x.is_file()));
+    }
+
+    Ok(script_path_dirs(daemon)?
+        .into_iter()
+        .map(|dir| dir.join(interpreter))
+        .find(|x| x.is_file()))
+}
+
+/// Get the directories in the `PATH` of the host scripts: the daemon's `PATH`, the charm's bin dir,
+/// and the directory containing the Lucky executable
+pub(super) fn script_path_dirs(daemon: &LuckyDaemon) -> anyhow::Result<Vec<PathBuf>> {
+    // Get initial PATH if set
+    let mut paths = if let Some(path) = std::env::var_os("PATH") {
+        env::split_paths(&path).collect::<Vec<_>>()
+    } else {
+        vec![]
+    };
+
+    // Add the charm's bin dir
+    paths.push(daemon.charm_dir.join("bin"));
+
+    // Add the directory containing the Lucky executable
+    if let Some(path) = std::env::current_exe()?.parent() {
+        paths.push(path.to_owned());
+    };
+
+    Ok(paths)
+}
+
+/// The exit code of a script that ran to completion
+struct ScriptExit {
+    /// The script for use in errors, such as `Host script "install.sh"`
+    description: String,
+    script_id: String,
+    code: i32,
+    output_tail: OutputTail,
+}
+
+/// The last lines of the output of a script
+#[derive(Default)]
+struct OutputTail(VecDeque<String>);
+
+impl OutputTail {
+    fn push(&mut self, line: &str) {
+        if self.0.len() == SCRIPT_OUTPUT_TAIL_LINES {
+            self.0.pop_front();
+        }
+        self.0.push_back(line.into());
+    }
+
+    /// Get the last line of output that isn't empty
+    fn last_line(&self) -> Option<&str> {
+        self.0
+            .iter()
+            .rev()
+            .map(String::as_str)
+            .find(|x| !x.trim().is_empty())
+    }
+
+    /// Get the lines joined by newlines
+    fn joined(&self) -> String {
+        self.0
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+/// The error of a script that exited non-zero
+struct ScriptFailure {
+    message: String,
+    code: i32,
+    /// The last lines of the script's output
+    output: String,
+}
+
+/// Check the exit code of a script, setting the script's status if the script maps its exit codes
+/// to statuses
+fn check_script_exit(
+    daemon: &LuckyDaemon,
+    script: &CharmScript,
+    exit: ScriptExit,
+) -> anyhow::Result<()> {
+    if exit.code == 0 {
+        clear_transient_statuses(daemon, &exit.script_id)?;
+    }
+
+    if let Some(state) = script.exit_codes.as_ref().and_then(|x| x.state(exit.code)) {
+        return set_script_status(
+            &*daemon.juju,
+            &mut daemon.state.write().unwrap(),
+            &exit.script_id,
+            ScriptStatus {
+                state,
+                message: exit.output_tail.last_line().map(ToOwned::to_owned),
+            },
+        );
+    }
+
+    let message = match (exit.code, &script.exit_codes, exit.output_tail.last_line()) {
+        (0, _, _) => return Ok(()),
+        // Scripts that use exit codes put the reason that they failed in the last line of output
+        (code, Some(_), Some(last_line)) => format!(
+            "{} exited non-zero ({}): {}",
+            exit.description, code, last_line
+        ),
+        (code, _, _) => format!("{} exited non-zero ({})", exit.description, code),
+    };
+    Err(ScriptFailure {
+        message,
+        code: exit.code,
+        output: exit.output_tail.joined(),
+    }
+    .into())
+}
+
+/// Clear the transient statuses set by a script that has exited successfully
+fn clear_transient_statuses(daemon: &LuckyDaemon, script_id: &str) -> anyhow::Result<()> {
+    let mut state = daemon.state.write().unwrap();
+
+    let status_ids: Vec<String> = state
+        .transient_statuses
+        .iter()
+        .filter(|(_, owner)| *owner == script_id)
+        .map(|(status_id, _)| status_id.clone())
+        .collect();
+    if status_ids.is_empty() {
+        return Ok(());
+    }
+
+    for status_id in &status_ids {
+        log::debug!("Clearing transient status: {}", status_id);
+        state.transient_statuses.remove(status_id);
+        state.remove_script_status(status_id);
+    }
+    daemon.juju.set_status(get_juju_status(&state))?;
+
+    Ok(())
+}
+
+/// Render the templated `env` of a script
+///
+/// Unlike file templates, the templates can reference secret key-value store keys, because the
+/// script could read them with `lucky kv get` anyway. The keys of the script's own namespace take
+/// precedence over the keys of the global namespace.
+fn render_script_env(
+    daemon: &LuckyDaemon,
+    script: &CharmScript,
+    script_id: &str,
+) -> anyhow::Result<HashMap<String, String>> {
+    let kv = {
+        let state = daemon.state.read().unwrap();
+        let mut kv = HashMap::new();
+        for namespace in &[None, Some(script_id)] {
+            let keys = state
+                .kv_store(*namespace)
+                .map_or_else(Vec::new, |store| store.keys().cloned().collect());
+            for key in keys {
+                if let Some(value) = daemon.get_kv_value(&state, *namespace, &key)? {
+                    kv.insert(key, value);
+                }
+            }
+        }
+        kv
+    };
+
+    env_template::render_script_env(&*daemon.juju, &kv, &script.env)
+        .context(format!("Could not render env for script {}", script.name()))
+}
+
+/// Get the cancel handle of the action that the given script environment belongs to, if any
+fn get_action_cancel_handle(
+    daemon: &LuckyDaemon,
+    environment: &HashMap<String, String>,
+) -> Option<CancelHandle> {
+    let action_id = environment.get(ACTION_ID_ENV_VAR)?;
+    daemon
+        .running_actions
+        .lock()
+        .unwrap()
+        .get(action_id)
+        .cloned()
+}
+
+/// Write a line of script output to the action log
+///
+/// Failing to log is not fatal to the action, so errors are only logged.
+fn log_action_output(juju: &dyn JujuBackend, line: &str) {
+    let line = crate::log::redact(line.trim_end());
+    if line.is_empty() {
+        return;
+    }
+
+    juju.action_log(&line).unwrap_or_else(|e| {
+        log::warn!("{:?}", e.context("Could not write to the action log"));
+    });
+}
+
+/// Spawn a thread that cancels an action if it runs past its timeout or if the client process
+/// that triggered it exits
+///
+/// Juju cancels a running action by killing the process that it started for the action, which
+/// is the client that triggered it, so the client exiting early means that the action has been
+/// cancelled. The thread exits once `done` is set.
+pub(super) fn spawn_action_watchdog(
+    cancel_handle: CancelHandle,
+    done: Arc<AtomicBool>,
+    timeout: Option<Duration>,
+    client_pid: Option<i64>,
+) -> std::thread::JoinHandle<()> {
+    let start = std::time::Instant::now();
+
+    std::thread::spawn(move || {
+        while !done.load(Ordering::SeqCst) {
+            if let Some(timeout) = timeout {
+                if start.elapsed() >= timeout {
+                    cancel_handle.cancel(&format!("Timed out after {} seconds", timeout.as_secs()));
+                    break;
+                }
+            }
+
+            if let Some(pid) = client_pid {
+                if !PathBuf::from(format!("/proc/{}", pid)).exists() {
+                    cancel_handle.cancel("The action was cancelled by Juju");
+                    break;
+                }
+            }
+
+            std::thread::sleep(ACTION_WATCHDOG_INTERVAL);
+        }
+    })
+}
+
+/// Spawn a thread that kills the process group of a host script if it runs past its timeout
+///
+/// The process group is sent `SIGTERM`, and then `SIGKILL` if it is still running after
+/// `SCRIPT_KILL_GRACE_PERIOD`. `timed_out` is set when the script times out, and the thread exits
+/// once `done` is set.
+fn spawn_script_watchdog(
+    pgid: u32,
+    timeout: Duration,
+    done: Arc<AtomicBool>,
+    timed_out: Arc<AtomicBool>,
+) -> std::thread::JoinHandle<()> {
+    let start = Instant::now();
+
+    std::thread::spawn(move || {
+        // Wait for the script to exit or time out
+        while start.elapsed() < timeout {
+            if done.load(Ordering::SeqCst) {
+                return;
+            }
+            std::thread::sleep(SCRIPT_WAIT_INTERVAL);
+        }
+
+        timed_out.store(true, Ordering::SeqCst);
+        log::warn!(
+            "Script timed out after {} seconds, sending SIGTERM",
+            timeout.as_secs()
+        );
+        signal_process_group(pgid, libc::SIGTERM);
+
+        // Give the script a chance to clean up before killing it
+        let terminated = Instant::now();
+        while terminated.elapsed() < SCRIPT_KILL_GRACE_PERIOD {
+            if done.load(Ordering::SeqCst) {
+                return;
+            }
+            std::thread::sleep(SCRIPT_WAIT_INTERVAL);
+        }
+
+        log::warn!(
+            "Script did not exit {} seconds after SIGTERM, sending SIGKILL",
+            SCRIPT_KILL_GRACE_PERIOD.as_secs()
+        );
+        signal_process_group(pgid, libc::SIGKILL);
+    })
+}
+
+/// Send a signal to every process in a process group, logging any errors
+pub(super) fn signal_process_group(pgid: u32, signal: libc::c_int) {
+    let pgid = match libc::pid_t::try_from(pgid) {
+        Ok(pgid) => pgid,
+        Err(_) => return,
+    };
+
+    // Safe because `kill` doesn't touch any of our memory
+    if unsafe { libc::kill(-pgid, signal) } != 0 {
+        log::warn!(
+            "Could not signal script process group {}: {}",
+            pgid,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+/// Run one of the charm's host scripts
+///
+/// Scripts with a `timeout` are run in their own process group with `setsid` so that the processes
+/// that they start are killed with them when they time out.
+fn run_host_script(
+    daemon: &LuckyDaemon,
+    script_type: ScriptType,
+    hook_name: &str,
+    environment: &HashMap<String, String>,
+    script_id_override: Option<&str>, // Optional override for script id
+    script: &CharmScript,
+    interpreter: Option<&Path>,
+) -> anyhow::Result<Option<ScriptExit>> {
+    let timeout = script.timeout.map(Duration::from_secs);
+
+    // Create script name based on script type
+    let script_name = match &script_type {
+        ScriptType::Inline { .. } => format!("{}_inline", hook_name),
+        ScriptType::Named { name, .. } => name.clone(),
+    };
+
+    log::info!("Running host script: {}", script_name);
+
+    // Add bin dirs to the PATH
+    let path_env =
+        env::join_paths(script_path_dirs(daemon)?).context("Path contains invalid character")?;
+
+    // Build the command to run
     let mut args: Vec<String> = vec![];
-    let command_path;
+    let mut command_path;
+    // The file that an inline script with an interpreter is written to. It is removed when this is
+    // dropped.
+    let _inline_script_file;
     match script_type {
+        // Run an inline script with an interpreter or an interpreter line from a file so that the
+        // interpreter is used
+        ScriptType::Inline { content, .. }
+            if interpreter.is_some() || content.starts_with("#!") =>
+        {
+            let file = InlineScriptFile::create(
+                daemon,
+                script_id_override.unwrap_or(&script_name.as_str()),
+                &content,
+                script.run_as.as_deref(),
+            )?;
+            command_path = file.0.clone();
+            _inline_script_file = file;
+        }
         // Run an inline script with the specified shell
         ScriptType::Inline { shell, content } => {
             let mut shell_iter = shell.iter();
@@ -297,50 +1577,136 @@ fn run_host_script(
         }
     };
 
+    // Run the script with its interpreter
+    if let Some(interpreter) = interpreter {
+        args.insert(0, command_path.to_string_lossy().into_owned());
+        command_path = interpreter.to_owned();
+    }
+
+    // Run the script as its user and with its resource limits
+    let (program, args) = sandbox_host_script(script, &command_path, args)?;
+
     // Creat the command
-    let mut command = Exec::cmd(&command_path)
-        .stdout(Redirection::Pipe)
-        .stderr(Redirection::Merge)
-        .args(args.as_slice())
-        .env("PATH", path_env)
-        .env("LUCKY_CONTEXT", "client")
-        .env(
-            "LUCKY_SCRIPT_ID",
-            script_id_override.unwrap_or(&script_name.as_str()),
-        );
+    let mut command = if timeout.is_some() {
+        Exec::cmd("setsid").arg(&program)
+    } else {
+        Exec::cmd(&program)
+    }
+    .stdout(Redirection::Pipe)
+    .stderr(Redirection::Merge)
+    .args(args.as_slice())
+    .env("PATH", path_env)
+    .env("LUCKY_CONTEXT", "client")
+    .env("LUCKY_ENV", lucky_env_path(daemon))
+    .env(
+        "LUCKY_SCRIPT_ID",
+        script_id_override.unwrap_or(&script_name.as_str()),
+    );
 
     // Set environment for hook exececution
     for (k, v) in environment.iter() {
         command = command.env(k, v);
     }
 
+    // Get the cancel handle for the action if this script is being run for one
+    let cancel_handle = get_action_cancel_handle(daemon, environment);
+
     // Run script process
     let mut process = command
         .popen()
         .context(format!("Error executing script: {:?}", command_path))?;
 
     // Get script output buffer
-    let output_buffer = BufReader::new(process.stdout.as_ref().expect("Stdout not opened"));
+    let output_buffer = BufReader::new(process.stdout.take().expect("Stdout not opened"));
+    let mut script_log =
+        script_logs::ScriptLog::create(daemon, script_id_override.unwrap_or(&script_name.as_str()));
+
+    // Kill the script if it runs past its timeout
+    let done = Arc::new(AtomicBool::new(false));
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let watchdog = match (timeout, process.pid()) {
+        (Some(timeout), Some(pid)) => Some(spawn_script_watchdog(
+            pid,
+            timeout,
+            done.clone(),
+            timed_out.clone(),
+        )),
+        _ => None,
+    };
+
+    // Register the process with the action so that it will be terminated if the action is
+    // cancelled
+    let process = Arc::new(Mutex::new(process));
+    if let Some(cancel_handle) = &cancel_handle {
+        cancel_handle.register_process(process.clone());
+    }
 
     // Loop through lines of output
+    let mut output_tail = OutputTail::default();
     for line in output_buffer.lines() {
         let line = line?;
         // Print output to debug log
         log::debug!("output: {}", line);
+        script_log.write_line(&line);
+        output_tail.push(&line);
+
+        // Stream the output to the action log
+        if cancel_handle.is_some() {
+            log_action_output(&*daemon.juju, &line);
+        }
+    }
+
+    // Wait for script to exit. The process lock is released between checks so that the process
+    // can still be terminated if the action is cancelled.
+    let exit_status = loop {
+        if let Some(status) = process.lock().unwrap().wait_timeout(SCRIPT_WAIT_INTERVAL)? {
+            break status;
+        }
+    };
+
+    // Stop the watchdog
+    done.store(true, Ordering::SeqCst);
+    if let Some(watchdog) = watchdog {
+        watchdog.join().expect("Script watchdog thread paniced");
     }
 
-    // Wait for script to exit
-    let exit_status = process.wait()?;
+    // Report timeouts in a status of their own so that the script's own status is left alone. The
+    // status is cleared the next time the script finishes in time.
+    if let Some(timeout) = timeout {
+        let status_id = format!(
+            "{}_timeout",
+            script_id_override.unwrap_or(&script_name.as_str())
+        );
+        let mut state = daemon.state.write().unwrap();
+        if timed_out.load(Ordering::SeqCst) && !exit_status.success() {
+            let message = format!(
+                r#"Host script "{}" timed out after {} seconds"#,
+                script_name,
+                timeout.as_secs()
+            );
+            set_script_status(
+                &*daemon.juju,
+                &mut state,
+                &status_id,
+                ScriptStatus {
+                    state: ScriptState::Blocked,
+                    message: Some(message.clone()),
+                },
+            )?;
+            anyhow::bail!(message);
+        } else if state.remove_script_status(&status_id).is_some() {
+            daemon.juju.set_status(get_juju_status(&state))?;
+        }
+    }
 
     match exit_status {
         // If the command exited with a code, return the code
-        ExitStatus::Exited(0) => Ok(()),
-        // If process had an abnormal exit code just exit 1
-        ExitStatus::Exited(n) => Err(format_err!(
-            r#"Host script "{}" exited non-zero ({})"#,
-            script_name,
-            n
-        )),
+        ExitStatus::Exited(code) => Ok(Some(ScriptExit {
+            description: format!(r#"Host script "{}""#, script_name),
+            script_id: script_id_override.unwrap_or(&script_name).into(),
+            code: i32::try_from(code).unwrap_or(i32::MAX),
+            output_tail,
+        })),
         ExitStatus::Signaled(signum) => Err(format_err!(
             r#"Host script "{}" terminated by signal ({})"#,
             script_name,
@@ -354,6 +1720,135 @@ fn run_host_script(
     }
 }
 
+/// Wrap the command of a host script so that it runs as the script's `run-as` user and with the
+/// script's resource limits. Returns the program to run and its arguments.
+///
+/// The memory, CPU, and process limits are set on a transient systemd scope, the open file limit is
+/// set with `prlimit`, and `runuser` switches to the user, so the script itself is run last.
+fn sandbox_host_script(
+    script: &CharmScript,
+    command_path: &Path,
+    args: Vec<String>,
+) -> anyhow::Result<(PathBuf, Vec<String>)> {
+    let mut wrapper: Vec<String> = Vec::new();
+
+    if let Some(limits) = &script.limits {
+        let properties = limits.scope_properties();
+        if !properties.is_empty() {
+            wrapper.extend(vec![
+                "systemd-run".into(),
+                "--scope".into(),
+                "--quiet".into(),
+                "--collect".into(),
+            ]);
+            for property in properties {
+                wrapper.push("--property".into());
+                wrapper.push(property);
+            }
+            wrapper.push("--".into());
+        }
+
+        if let Some(max_open_files) = limits.max_open_files {
+            wrapper.extend(vec![
+                "prlimit".into(),
+                format!("--nofile={}", max_open_files),
+                "--".into(),
+            ]);
+        }
+    }
+
+    if let Some(user) = &script.run_as {
+        ensure_script_user(user)?;
+        wrapper.extend(vec![
+            "runuser".into(),
+            "-u".into(),
+            user.clone(),
+            "--".into(),
+        ]);
+    }
+
+    let mut wrapper = wrapper.into_iter();
+    Ok(match wrapper.next() {
+        Some(program) => (
+            PathBuf::from(program),
+            wrapper
+                .chain(std::iter::once(command_path.to_string_lossy().into_owned()))
+                .chain(args)
+                .collect(),
+        ),
+        None => (command_path.to_owned(), args),
+    })
+}
+
+/// Create the user that a script runs as if it doesn't exist. The user is created as a system user
+/// without a home dir or a login shell.
+fn ensure_script_user(user: &str) -> anyhow::Result<()> {
+    if run_cmd("id", &["-u", user]).is_err() {
+        log::info!("Creating user for scripts: {}", user);
+        run_cmd(
+            "useradd",
+            &[
+                "--system",
+                "--no-create-home",
+                "--shell",
+                "/usr/sbin/nologin",
+                user,
+            ],
+        )
+        .context(format!("Could not create user for scripts: {}", user))?;
+    }
+
+    Ok(())
+}
+
+/// An inline script written to a file in the Lucky data dir so that it can be executed. The file is
+/// removed when this is dropped.
+struct InlineScriptFile(PathBuf);
+
+impl InlineScriptFile {
+    /// Write an inline script to an executable file. The file is owned by the given user, if any,
+    /// so that a script that is run as that user can read it.
+    fn create(
+        daemon: &LuckyDaemon,
+        script_id: &str,
+        content: &str,
+        owner: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let dir = daemon.lucky_data_dir.join("inline_scripts");
+        std::fs::create_dir_all(&dir)
+            .context(format!("Could not create inline script dir: {:?}", dir))?;
+
+        // Scripts with the same ID may run at the same time so add a random suffix
+        let path = dir.join(format!("{}-{:08x}", script_id, rand::random::<u32>()));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o700)
+            .open(&path)
+            .context(format!("Could not create inline script file: {:?}", path))?;
+        file.write_all(content.as_bytes())
+            .context(format!("Could not write inline script file: {:?}", path))?;
+        if let Some(owner) = owner {
+            run_cmd("chown", &[owner, &path.to_string_lossy()]).context(format!(
+                "Could not change owner of inline script file: {:?}",
+                path
+            ))?;
+        }
+
+        Ok(InlineScriptFile(path))
+    }
+}
+
+impl Drop for InlineScriptFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            log::warn!("Could not remove inline script file {:?}: {}", self.0, e);
+        }
+    }
+}
+
 fn run_container_script(
     daemon: &LuckyDaemon,
     script_type: ScriptType,
@@ -362,7 +1857,7 @@ fn run_container_script(
     ignore_missing_container: bool,
     environment: &HashMap<String, String>,
     script_id_override: Option<&str>,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Option<ScriptExit>> {
     // Create script name based on script type
     let script_name = match &script_type {
         ScriptType::Inline { .. } => format!("{}_inline", hook_name),
@@ -371,6 +1866,13 @@ fn run_container_script(
 
     log::info!("Running container script: {}", script_name);
 
+    if daemon.platform == Platform::Kubernetes {
+        anyhow::bail!(
+            "Container scripts are not supported on Kubernetes, could not run: {}",
+            script_name
+        );
+    }
+
     // Get the container ID. This must be scoped to limit the time that we lock the daemon state
     // otherwise any script attempting to access the daemon state will deadlock.
     let container_id;
@@ -396,7 +1898,7 @@ fn run_container_script(
                         container_name.as_ref().unwrap_or(&"default".into()),
                         script_name
                     );
-                    return Ok(());
+                    return Ok(None);
                 // If we don't ignore missing containers
                 } else {
                     // Exit with error
@@ -425,7 +1927,7 @@ fn run_container_script(
                         container_name.as_ref().unwrap_or(&"default".into()),
                         script_name
                     );
-                    return Ok(());
+                    return Ok(None);
                 // If we don't ignore missing containers
                 } else {
                     // Exit with error
@@ -443,12 +1945,6 @@ fn run_container_script(
         };
     }
 
-    // Get the docker connection
-    let docker_conn = daemon.get_docker_conn()?;
-    let docker_conn = docker_conn.lock().unwrap();
-    let containers = docker_conn.containers();
-    let container = containers.get(&container_id);
-
     // The command environment
     let mut env: Vec<String> = environment
         .iter()
@@ -460,32 +1956,20 @@ fn run_container_script(
         "LUCKY_SCRIPT_ID={}",
         script_id_override.unwrap_or(&script_name.as_str())
     ));
-    // TODO: https://github.com/softprops/shiplift/issues/219
-    // We currently set the context to "daemon" so we can call `lucky exit-code-helper` to help
-    // us get the exit code of the container script.
-    env.push("LUCKY_CONTEXT=daemon".into());
 
     // Build the command
     let mut cmd: Vec<String>;
     match script_type {
         ScriptType::Inline { content, mut shell } => {
-            // Set command to the lucky exit code helper ( see comment above )
-            cmd = vec!["lucky".into(), "exit-code-helper".into()];
-
             // Add shell command
-            cmd.extend(shell.drain(0..));
+            cmd = shell.drain(0..).collect();
 
             // Add inline script as last arg
             cmd.push(content);
         }
         ScriptType::Named { name, mut args } => {
-            // Set command to the lucky exit code helper ( see comment above )
-            cmd = vec![
-                "lucky".into(),
-                "exit-code-helper".into(),
-                // Add container script
-                format!("/lucky/container_scripts/{}", name),
-            ];
+            // Add container script
+            cmd = vec![format!("/lucky/container_scripts/{}", name)];
 
             // Add script args
             cmd.extend(args.drain(0..));
@@ -498,162 +1982,774 @@ fn run_container_script(
         cmd
     );
 
-    // Build exec options
-    let exec_options = ExecContainerOptions::builder()
-        .attach_stderr(true)
-        .attach_stdout(true)
-        .env(env.iter().map(AsRef::as_ref).collect())
-        .cmd(cmd.iter().map(AsRef::as_ref).collect())
-        .build();
-
-    // Instantiate exit code
-    let exit_code: Arc<Mutex<Option<i32>>> = Arc::new(Mutex::new(None));
-    let exit_code_ = exit_code.clone();
+    // Get the Juju backend to stream the output to if the script is being run for an action
+    let action_juju = get_action_cancel_handle(daemon, environment).map(|_| daemon.juju.clone());
+    let mut script_log =
+        script_logs::ScriptLog::create(daemon, script_id_override.unwrap_or(&script_name.as_str()));
+    let output_tail = Arc::new(Mutex::new(OutputTail::default()));
+    let exec_output_tail = output_tail.clone();
 
     // Exec script and log output
-    block_on(container.exec(&exec_options).for_each(move |chunk| {
-        let exit_code = &exit_code_;
-        let chunk_str = chunk.as_string_lossy();
-
-        // TODO: https://github.com/softprops/shiplift/issues/219
-        // This hack looks for a special prefix for a line of text that will tell us the exit
-        // code. This output provided by our `lucky daemon exit-code-helper` wrapper command.
-
-        // If the line starts with the exit code indication prefix
-        if chunk_str.starts_with(LUCKY_EXIT_CODE_HELPER_PREFIX) {
-            // Trim output string
-            let chunk_str = chunk_str.trim();
-
-            // Set the exit code
-            *exit_code.lock().unwrap() = Some(
-                chunk_str
-                    .trim_start_matches(LUCKY_EXIT_CODE_HELPER_PREFIX)
-                    .parse()
-                    .map_err(|e| {
-                        shiplift::Error::InvalidResponse(format!(
-                            "Could not parse container script exit code: {}",
-                            e
-                        ))
-                    })?,
-            );
+    let exit_code = daemon
+        .get_container_engine()?
+        .exec(
+            &container_id,
+            &cmd,
+            &env,
+            Box::new(move |output| {
+                // Log the output
+                log::debug!("output: {}", output);
+                for line in output.lines() {
+                    script_log.write_line(line);
+                    exec_output_tail.lock().unwrap().push(line);
+                }
 
-        // If line doesn't start with exit-code prefix
-        } else {
-            // Log the output
-            log::debug!("output: {}", chunk.as_string_lossy());
-        }
-        Ok(())
-    }))
-    .context(format!(
-        r#"failed to exec script "{}" for container "{}""#,
-        script_name,
-        container_name.as_ref().unwrap_or(&"default".into())
-    ))?;
+                // Stream the output to the action log
+                if let Some(juju) = &action_juju {
+                    for line in output.lines() {
+                        log_action_output(&**juju, line);
+                    }
+                }
+            }),
+        )
+        .context(format!(
+            r#"failed to exec script "{}" for container "{}""#,
+            script_name,
+            container_name.as_ref().unwrap_or(&"default".into())
+        ))?;
 
     // Match exit code and exit accordingly
-    let exit_code = exit_code.lock().unwrap();
-    match *exit_code {
-        Some(0) => Ok(()),
-        Some(code) => Err(format_err!(
-            r#"Container script "{}" exited non-zero: {}"#,
-            script_name,
-            code
-        )),
+    match exit_code {
+        Some(code) => Ok(Some(ScriptExit {
+            description: format!(r#"Container script "{}""#, script_name),
+            script_id: script_id_override.unwrap_or(&script_name).into(),
+            code,
+            output_tail: std::mem::take(&mut *output_tail.lock().unwrap()),
+        })),
         None => Err(format_err!(
             "Error getting exit code from container script: assuming something went wrong."
         )),
     }
 }
 
-#[function_name::named]
-/// Apply any updates to container configuration for the charm by running
-pub(super) fn apply_container_updates(daemon: &LuckyDaemon) -> anyhow::Result<()> {
-    log::debug!("Applying container configuration");
-    let mut state = daemon.state.write().unwrap();
-    daemon_set_status!(
-        &mut state,
-        ScriptState::Maintenance,
-        "Applying Docker configuration updates"
-    );
+/// Apply any updates to container configuration for the charm, running the update hooks of the
+/// containers that are replaced
+///
+/// The update hooks run scripts that call back into the daemon, so `guard` is only held while the
+/// changes are made and not while the hooks are running. It should acquire the concurrency class
+/// of the caller, if it has one.
+pub(super) fn apply_container_updates<G>(
+    daemon: &LuckyDaemon,
+    guard: impl Fn() -> G,
+) -> anyhow::Result<()> {
+    // Get the update settings of the containers that are going to be replaced
+    let replacing = {
+        let _guard = guard();
+        get_replaced_containers(daemon)?
+    };
 
-    // Apply changes for any updated named containers
-    for mut container in state.named_containers.values_mut() {
-        apply_updates(daemon, &mut container)?;
+    for (name, settings) in &replacing {
+        run_update_hooks(
+            daemon,
+            name,
+            UpdateHookStage::PreUpdate,
+            &settings.pre_update,
+        )?;
     }
 
-    // Remove named containers that are pending removal
-    state
-        .named_containers
-        .retain(|_name, container| !container.pending_removal);
-
-    // Apply changes for the default container
-    if let Some(container) = &mut state.default_container {
-        apply_updates(daemon, container)?;
+    let replaced = {
+        let _guard = guard();
+        let result = apply_container_changes(daemon);
+        // Save the IDs of the new containers right away so that they aren't lost if the daemon
+        // exits without saving its state
+        flush_state(daemon)?;
+        result?
+    };
 
-        // Remove container if pending removal
-        if container.pending_removal {
-            state.default_container = None;
+    for (name, settings) in &replacing {
+        if replaced.contains(name) {
+            run_update_hooks(
+                daemon,
+                name,
+                UpdateHookStage::PostUpdate,
+                &settings.post_update,
+            )?;
         }
     }
 
-    daemon_set_status!(&mut state, ScriptState::Active);
     Ok(())
 }
 
-fn apply_updates(
+/// Get the update settings of the existing containers that will be replaced when the container
+/// configuration is applied, keyed by container name. Containers without update hooks are skipped.
+fn get_replaced_containers(
     daemon: &LuckyDaemon,
-    container_info: &mut Cd<ContainerInfo>,
-) -> anyhow::Result<()> {
-    // Skip apply if container config is unchanged since last apply
-    if container_info.is_clean() {
-        return Ok(());
-    }
+) -> anyhow::Result<Vec<(String, ContainerUpdateSettings)>> {
+    let mut state_guard = daemon.state.write().unwrap();
+    // Reborrow the state so that we can borrow its fields separately
+    let state = &mut *state_guard;
 
-    // Get the docker connection
-    let docker_conn = daemon.get_docker_conn()?;
-    let docker_conn = docker_conn.lock().unwrap();
-    let containers = docker_conn.containers();
-    let images = docker_conn.images();
+    let containers = state
+        .named_containers
+        .iter_mut()
+        .map(|(name, container)| (name.as_str(), container))
+        .chain(
+            state
+                .default_container
+                .iter_mut()
+                .map(|container| (DEFAULT_CONTAINER_NAME, container)),
+        );
 
-    // If the container has already been deployed
-    if let Some(id) = &container_info.id {
-        // Remove the container
-        let container = containers.get(&id);
+    let mut replaced = Vec::new();
+    for (name, container) in containers {
+        let settings = match state.container_updates.get(name) {
+            Some(settings)
+                if !settings.pre_update.is_empty() || !settings.post_update.is_empty() =>
+            {
+                settings
+            }
+            _ => continue,
+        };
 
-        // TODO: handle NOT MODIFIED error response
-        log::debug!("Stopping container: {}", id);
-        block_on(container.stop(Some(Duration::from_secs(10))))?;
-        log::debug!("Removing container: {}", id);
-        block_on(container.delete())?;
+        // Render the env templates now so that changes to them are seen
+        render_env_templates(daemon, container)?;
 
-        // Clear the containers ID
-        container_info.update(|info| info.id = None);
+        if container.id.is_some() && !container.pending_removal && !container.is_clean() {
+            replaced.push((name.to_string(), settings.clone()));
+        }
     }
 
-    // If this contianer was not meant to be removed
-    if !container_info.pending_removal {
-        let image_name = container_info.config.image.clone();
-
-        if container_info.pull_image {
-            // Pull the image
-            log::debug!("Pulling container image: {}", image_name);
-            block_on(
-                images
-                    .pull(&PullOptions::builder().image(image_name).build())
-                    .collect(),
-            )?;
-        }
+    Ok(replaced)
+}
 
-        // Create the container
-        let mut docker_options = container_info.config.to_container_options(
-            &daemon.charm_dir,
-            &daemon.lucky_data_dir,
-            &daemon.socket_path,
+/// Run the update hooks of a container for the given stage
+fn run_update_hooks(
+    daemon: &LuckyDaemon,
+    container_name: &str,
+    stage: UpdateHookStage,
+    hooks: &[UpdateHook],
+) -> anyhow::Result<()> {
+    let mut environment = HashMap::new();
+    environment.insert(
+        "LUCKY_CONTAINER_NAME".to_string(),
+        container_name.to_string(),
+    );
+    environment.insert("LUCKY_UPDATE_STAGE".to_string(), stage.as_ref().to_string());
+
+    for (i, hook) in hooks.iter().enumerate() {
+        log::info!(
+            "Running {} hook of container {}: {}",
+            stage.as_ref(),
+            container_name,
+            hook.script
+        );
+        run_charm_script(
+            daemon,
+            stage.as_ref(),
+            &hook.to_charm_script(container_name),
+            &environment,
+            Some(&format!("{}_{}_{}", stage.as_ref(), container_name, i)),
+        )
+        .context(format!(
+            "The {} hook of container {} failed",
+            stage.as_ref(),
+            container_name
+        ))?;
+    }
+
+    Ok(())
+}
+
+#[function_name::named]
+/// Apply the changes to the container configuration, returning the names of the containers that
+/// replaced an existing container
+fn apply_container_changes(daemon: &LuckyDaemon) -> anyhow::Result<HashSet<String>> {
+    log::debug!("Applying container configuration");
+    sync_opened_ports(daemon)?;
+    let mut state = daemon.state.write().unwrap();
+    daemon_set_status!(
+        daemon,
+        &mut state,
+        ScriptState::Maintenance,
+        "Applying Docker configuration updates"
+    );
+
+    // Get the unit key-value store for rendering the templated container files
+    let kv: HashMap<String, String> = state
+        .kv
+        .iter()
+        .filter(|(key, _)| !state.kv_key_secret(None, key))
+        .map(|(key, value)| (key.clone(), (**value).clone()))
+        .collect();
+    // Get the related docker registry that images may be pulled from
+    let registry = state.docker_registry.clone();
+
+    // Apply changes to the containers in dependency order, keeping track of the containers that
+    // were re-created or restarted so that the containers depending on them can be restarted
+    let mut restarted = HashSet::new();
+    let mut replaced = HashSet::new();
+    for name in container_start_order(daemon, &state)? {
+        let is_default = name == DEFAULT_CONTAINER_NAME;
+        let strategy = state
+            .container_updates
+            .get(&name)
+            .map(|x| x.strategy)
+            .unwrap_or_default();
+        let container = if is_default {
+            state.default_container.as_mut()
+        } else {
+            state.named_containers.get_mut(&name)
+        };
+        let container = match container {
+            Some(container) => container,
+            None => continue,
+        };
+
+        // Render the templated files before the container is created so that they can be mounted
+        let files_changed = if container.pending_removal {
+            false
+        } else {
+            render_container_files(daemon, &name, container, &kv)?
+        };
+
+        let old_id = container.id.clone();
+        apply_updates(
+            daemon,
+            if is_default { None } else { Some(&name) },
+            container,
+            strategy,
+            registry.as_ref(),
         )?;
-        let unit_name = std::env::var("JUJU_UNIT_NAME")
-            .context("Env var JUJU_UNIT_NAME not readable!")?
-            .replace("/", "_");
-        docker_options.name = Some(format!("lucky_{}_{}", unit_name, {
+
+        if container.id.is_some() && container.id != old_id {
+            if old_id.is_some() {
+                replaced.insert(name.clone());
+            }
+            restarted.insert(name);
+        } else if let Some(id) = &container.id {
+            let dependencies_restarted =
+                daemon
+                    .lucky_metadata
+                    .containers
+                    .get(&name)
+                    .map_or(false, |spec| {
+                        spec.restart_with_dependencies
+                            && spec.depends_on.iter().any(|x| restarted.contains(x))
+                    });
+
+            // Restart the container to load its changed files or to reconnect to its dependencies
+            if !container.stopped && (files_changed || dependencies_restarted) {
+                log::debug!(
+                    "Restarting container {} because its {} changed",
+                    name,
+                    if files_changed {
+                        "files have"
+                    } else {
+                        "dependencies have"
+                    }
+                );
+                let engine = daemon.get_container_engine()?;
+                crash_monitor::expect_exit(daemon, id);
+                trace::in_span(
+                    Span::start("container restart").with_attr("container.id", id),
+                    || engine.restart_container(id, Some(Duration::from_secs(10))),
+                )?;
+                restarted.insert(name);
+            }
+        }
+    }
+
+    // Remove named containers that are pending removal
+    let mut removed: Vec<String> = state
+        .named_containers
+        .iter()
+        .filter(|(_, container)| container.pending_removal)
+        .map(|(name, _)| name.clone())
+        .collect();
+    state
+        .named_containers
+        .retain(|_name, container| !container.pending_removal);
+
+    // Remove the default container if it is pending removal
+    if state
+        .default_container
+        .as_ref()
+        .map_or(false, |x| x.pending_removal)
+    {
+        state.default_container = None;
+        removed.push(DEFAULT_CONTAINER_NAME.into());
+    }
+
+    // Forget the update settings and delete the rendered files of the removed containers
+    for name in &removed {
+        state.container_updates.remove(name);
+
+        let files_dir = docker::rendered_files_dir(&daemon.lucky_data_dir, name);
+        if files_dir.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&files_dir) {
+                log::warn!("Could not remove container files {:?}: {}", files_dir, e);
+            }
+        }
+    }
+
+    open_container_ports(daemon, &mut state)?;
+
+    // Make sure the logs of the new containers are being forwarded
+    container_logs::sync_forwarders(daemon, &state);
+
+    // Make sure the containers are being watched for crashes
+    crash_monitor::ensure_monitoring(daemon);
+
+    daemon_set_status!(daemon, &mut state, ScriptState::Active);
+    Ok(replaced)
+}
+
+/// Get a fingerprint of a file that changes when the file is replaced, without reading the whole
+/// file
+fn file_fingerprint(path: &Path) -> anyhow::Result<String> {
+    let metadata = std::fs::metadata(path).context(format!("Could not read file: {:?}", path))?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|x| x.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |x| x.as_secs());
+
+    Ok(format!(
+        "{}:{}:{}",
+        path.to_string_lossy(),
+        metadata.len(),
+        modified
+    ))
+}
+
+/// Get the names of the containers in the order that they should be started in, so that every
+/// container comes after the containers that it depends on
+///
+/// The default container is named `DEFAULT_CONTAINER_NAME`. Containers that don't depend on each
+/// other are ordered by name, with the default container last.
+pub(super) fn container_start_order(
+    daemon: &LuckyDaemon,
+    state: &DaemonState,
+) -> anyhow::Result<Vec<String>> {
+    let mut names: Vec<&str> = state.named_containers.keys().map(String::as_str).collect();
+    names.sort();
+    if state.default_container.is_some() {
+        names.push(DEFAULT_CONTAINER_NAME);
+    }
+
+    // Get the dependencies of each container from its declaration
+    let dependencies: HashMap<&str, &[String]> = names
+        .iter()
+        .map(|&name| {
+            (
+                name,
+                daemon
+                    .lucky_metadata
+                    .containers
+                    .get(name)
+                    .map(|spec| spec.depends_on.as_slice())
+                    .unwrap_or_default(),
+            )
+        })
+        .collect();
+
+    /// Add a container to the order after its dependencies
+    fn visit<'a>(
+        name: &'a str,
+        dependencies: &HashMap<&'a str, &'a [String]>,
+        path: &mut Vec<&'a str>,
+        order: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        if order.iter().any(|x| x == name) {
+            return Ok(());
+        }
+        if let Some(start) = path.iter().position(|&x| x == name) {
+            let cycle: Vec<&str> = path.iter().skip(start).copied().chain(Some(name)).collect();
+            anyhow::bail!(
+                "Container dependencies form a cycle: {}",
+                cycle.join(" -> ")
+            );
+        }
+
+        path.push(name);
+        for dependency in dependencies.get(name).copied().unwrap_or_default() {
+            if !dependencies.contains_key(dependency.as_str()) {
+                anyhow::bail!(
+                    "Container {} depends on container {}, which does not exist",
+                    name,
+                    dependency
+                );
+            }
+            visit(dependency, dependencies, path, order)?;
+        }
+        path.pop();
+
+        order.push(name.into());
+        Ok(())
+    }
+
+    let mut order = Vec::with_capacity(names.len());
+    for name in names {
+        visit(name, &dependencies, &mut vec![], &mut order)?;
+    }
+
+    Ok(order)
+}
+
+/// Get the name of the network shared between the unit's containers
+pub(super) fn shared_network_name() -> anyhow::Result<String> {
+    Ok(format!("lucky_{}", get_unit_name_for_engine()?))
+}
+
+/// Get the name of the unit in a form that can be used in container and network names
+fn get_unit_name_for_engine() -> anyhow::Result<String> {
+    Ok(std::env::var("JUJU_UNIT_NAME")
+        .context("Env var JUJU_UNIT_NAME not readable!")?
+        .replace("/", "_"))
+}
+
+/// Open the host ports of the containers' port bindings in Juju, and close the ports that were
+/// opened for bindings that have since been removed
+///
+/// Ports that were already open, such as ports opened by a script with `lucky port open`, are left
+/// open when the binding is removed.
+fn open_container_ports(daemon: &LuckyDaemon, state: &mut DaemonState) -> anyhow::Result<()> {
+    let wanted_ports: HashSet<String> = state
+        .named_containers
+        .values()
+        .chain(state.default_container.iter())
+        .flat_map(|container| container.config.juju_ports())
+        .collect();
+
+    for port in &wanted_ports {
+        if !state.opened_ports.contains(port) {
+            log::debug!("Opening port for container port binding: {}", port);
+            daemon.juju.open_port(port)?;
+            state.opened_ports.insert(port.clone());
+            state.container_opened_ports.insert(port.clone());
+        }
+    }
+
+    for port in state.container_opened_ports.clone() {
+        if wanted_ports.contains(&port) {
+            continue;
+        }
+
+        if state.opened_ports.contains(&port) {
+            log::debug!("Closing port for removed container port binding: {}", port);
+            daemon.juju.close_port(&port)?;
+            state.opened_ports.remove(&port);
+        }
+        state.container_opened_ports.remove(&port);
+    }
+
+    Ok(())
+}
+
+/// Get the storage name from the name of a `<name>-storage-attached` or
+/// `<name>-storage-detaching` hook
+pub(super) fn get_storage_hook_name(hook_name: &str) -> Option<&str> {
+    JUJU_STORAGE_HOOKS
+        .iter()
+        .filter_map(|template| {
+            let suffix = template.trim_start_matches("{}");
+            hook_name.strip_suffix(suffix)
+        })
+        .find(|x| !x.is_empty())
+}
+
+/// Get the locations of the attached Juju storage that is mounted into the container, keyed by
+/// storage name
+///
+/// Storage that is being detached in the current `<name>-storage-detaching` hook is left out so
+/// that the container is re-created without it before Juju detaches it.
+fn get_storage_locations(
+    daemon: &LuckyDaemon,
+    config: &ContainerConfig,
+) -> anyhow::Result<HashMap<String, PathBuf>> {
+    let detaching_storage = env::var("JUJU_HOOK_NAME")
+        .ok()
+        .filter(|hook| hook.ends_with("-storage-detaching"))
+        .and_then(|_| env::var("JUJU_STORAGE_ID").ok());
+
+    let mut locations = HashMap::new();
+    for storage_name in config.storage_names() {
+        let storage_id = daemon
+            .juju
+            .storage_list(storage_name)?
+            .into_iter()
+            .find(|id| Some(id) != detaching_storage.as_ref());
+
+        if let Some(storage_id) = storage_id {
+            let location = daemon.juju.storage_get_location(&storage_id)?;
+            locations.insert(storage_name.to_owned(), PathBuf::from(location));
+        }
+    }
+
+    Ok(locations)
+}
+
+/// Whether or not any of the containers have templated environment variables
+pub(super) fn has_env_templates(state: &DaemonState) -> bool {
+    state
+        .named_containers
+        .values()
+        .chain(state.default_container.iter())
+        .any(|container| !container.config.env_templates.is_empty())
+}
+
+/// Render the container's templated environment variables, marking the container as dirty if
+/// any of their values have changed
+fn render_env_templates(
+    daemon: &LuckyDaemon,
+    container_info: &mut Cd<ContainerInfo>,
+) -> anyhow::Result<()> {
+    if container_info.config.env_templates.is_empty() || container_info.pending_removal {
+        return Ok(());
+    }
+
+    let rendered =
+        env_template::render_env_templates(&*daemon.juju, &container_info.config.env_templates)?;
+
+    // Only update the container if a value changed so that it isn't re-created needlessly
+    if rendered
+        .iter()
+        .any(|(key, value)| container_info.config.env_vars.get(key) != Some(value))
+    {
+        container_info.update(|info| info.config.env_vars.extend(rendered));
+    }
+
+    Ok(())
+}
+
+/// Render the container's templated files to the Lucky data dir, returning whether or not any of
+/// their content changed
+///
+/// The files are written in place instead of being replaced so that the bind mounts of a running
+/// container see the new content.
+fn render_container_files(
+    daemon: &LuckyDaemon,
+    container_name: &str,
+    container_info: &ContainerInfo,
+    kv: &HashMap<String, String>,
+) -> anyhow::Result<bool> {
+    let mut changed = false;
+    for (target, template_path) in &container_info.config.files {
+        let template_path = daemon.charm_dir.join(template_path);
+        let template = std::fs::read_to_string(&template_path).context(format!(
+            "Could not read container file template: {:?}",
+            template_path
+        ))?;
+        let rendered = env_template::render_file_template(
+            &*daemon.juju,
+            kv,
+            &template_path.to_string_lossy(),
+            &template,
+        )?;
+
+        // Skip files that haven't changed
+        let path = docker::rendered_file_path(&daemon.lucky_data_dir, container_name, target)?;
+        if std::fs::read_to_string(&path).ok().as_ref() == Some(&rendered) {
+            continue;
+        }
+
+        log::debug!("Rendering file {} of container {}", target, container_name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .context(format!("Could not create dir: {:?}", parent))?;
+        }
+        std::fs::write(&path, rendered)
+            .context(format!("Could not write container file: {:?}", path))?;
+        changed = true;
+    }
+
+    Ok(changed)
+}
+
+fn apply_updates(
+    daemon: &LuckyDaemon,
+    container_name: Option<&str>,
+    container_info: &mut Cd<ContainerInfo>,
+    strategy: UpdateStrategy,
+    registry: Option<&docker_registry::RegistryRelation>,
+) -> anyhow::Result<()> {
+    // Update the templated environment variables
+    render_env_templates(daemon, container_info)?;
+
+    // Get the container engine
+    let engine = daemon.get_container_engine()?;
+
+    // Load the image from its Juju resource if the resource has changed since it was loaded. This
+    // marks the container as changed so that it is re-created with the new image.
+    if !container_info.pending_removal {
+        if let Some(resource) = &container_info.image_resource {
+            let path = daemon
+                .juju
+                .resource_get(resource)
+                .context(format!("Could not get image resource: {}", resource))?;
+            let fingerprint = file_fingerprint(Path::new(&path))?;
+
+            if container_info.loaded_image_resource.as_ref() != Some(&fingerprint) {
+                log::info!("Loading container image from resource: {}", resource);
+                trace::in_span(
+                    Span::start("container load").with_attr("juju.resource", resource),
+                    || engine.load_image(Path::new(&path)),
+                )
+                .context(format!("Could not load image from resource: {}", resource))?;
+                container_info.update(|info| info.loaded_image_resource = Some(fingerprint));
+            }
+        }
+    }
+
+    // Skip apply if container config is unchanged since last apply, unless the container has been
+    // removed from the container engine behind our back
+    if container_info.is_clean() {
+        match &container_info.id {
+            Some(id) if !container_exists(&*engine, id)? => {
+                log::warn!("Container {} no longer exists, re-creating it", id);
+                container_info.update(|info| info.id = None);
+            }
+            _ => return Ok(()),
+        }
+    }
+
+    // Containers can't bind the same host ports as each other, so the new container can't be
+    // started next to the old one if it has port bindings
+    let strategy =
+        if strategy == UpdateStrategy::BlueGreen && !container_info.config.ports.is_empty() {
+            log::warn!(
+            "Updating container with the stop-start strategy instead of blue-green because it has \
+            port bindings"
+        );
+            UpdateStrategy::StopStart
+        } else {
+            strategy
+        };
+
+    // If the container has already been deployed, remove it now unless it is going to be replaced
+    // after the new container is created
+    if let Some(id) = &container_info.id {
+        if container_info.pending_removal || strategy == UpdateStrategy::Recreate {
+            remove_old_container(daemon, &*engine, id)?;
+
+            // Clear the containers ID
+            container_info.update(|info| info.id = None);
+        }
+    }
+
+    // Remove the old env file, if any, so that removed containers don't leave their environment
+    // behind
+    let env_file_path = daemon
+        .lucky_data_dir
+        .join(ENV_FILE_DIR)
+        .join(match container_name {
+            Some(name) => format!("named-{}.env", name),
+            None => "default.env".into(),
+        });
+    if env_file_path.exists() {
+        std::fs::remove_file(&env_file_path)
+            .context(format!("Could not remove env file: {:?}", env_file_path))?;
+    }
+
+    // If this contianer was not meant to be removed
+    if !container_info.pending_removal {
+        let image_name = container_info.config.image.clone();
+
+        // Images loaded from a resource are never pulled so that the charm works without access
+        // to a registry
+        if container_info.pull_image && container_info.image_resource.is_none() {
+            // Authenticate with the registry if the image is private. The credentials must not be
+            // logged or added to the trace.
+            let credentials = match docker_registry::image_registry_auth(container_info, registry) {
+                Some(auth) => Some(get_registry_auth(daemon, &auth).context(format!(
+                    "Could not get registry credentials for {}",
+                    image_name
+                ))?),
+                None => None,
+            };
+
+            // Pull the image
+            log::debug!("Pulling container image: {}", image_name);
+            trace::in_span(
+                Span::start("container pull").with_attr("container.image", &image_name),
+                || engine.pull_image(&image_name, credentials),
+            )?;
+        }
+
+        // Record the digest that the image resolved to
+        let image_digest = resolve_image_digest(&*engine, &image_name);
+        if let (Some(previous), Some(digest)) = (&container_info.image_digest, &image_digest) {
+            if previous.image == image_name && previous.digest != *digest {
+                log::warn!(
+                    "Image {} has changed in the registry since it was last pulled: it was {} and \
+                    is now {}",
+                    image_name,
+                    previous.digest,
+                    digest
+                );
+            }
+        }
+        container_info.update(|info| {
+            info.image_digest = image_digest.map(|digest| ImageDigest {
+                image: image_name.clone(),
+                digest,
+            })
+        });
+
+        // Render the env file if the environment should not be set inline
+        let env_file = if container_info.config.env_mode == EnvMode::File {
+            let image = &container_info.config.image;
+            let image_command = trace::in_span(
+                Span::start("container inspect").with_attr("container.image", image),
+                || engine.image_command(image),
+            )?;
+
+            // The env file is only readable by its owner, so it is owned by the user that the
+            // image runs as for Lucky to be able to load it inside of the container
+            container_info.config.write_env_file(&env_file_path)?;
+            let user_id = image_user_id(&*engine, image, &image_command.user)?;
+            run_cmd(
+                "chown",
+                &[&user_id.to_string(), &env_file_path.to_string_lossy()],
+            )
+            .context(format!(
+                "Could not change owner of env file: {:?}",
+                env_file_path
+            ))?;
+
+            Some(EnvFile {
+                command: get_container_command(&container_info.config, image_command)?,
+                path: env_file_path,
+            })
+        } else {
+            None
+        };
+
+        // Create the shared network if the container is attached to it
+        let shared_network = if container_info.config.shared_network {
+            let network = shared_network_name()?;
+            engine
+                .ensure_network(&network)
+                .context(format!("Could not create network: {}", network))?;
+            Some(network)
+        } else {
+            None
+        };
+
+        // Create the container
+        let storage_locations = get_storage_locations(daemon, &container_info.config)?;
+        let unit_name = get_unit_name_for_engine()?;
+        let context = ContainerContext {
+            charm_dir: &daemon.charm_dir,
+            lucky_data_dir: &daemon.lucky_data_dir,
+            socket_path: &daemon.socket_path,
+            env_file: env_file.as_ref(),
+            storage_locations: &storage_locations,
+            container_name: container_name.unwrap_or(DEFAULT_CONTAINER_NAME),
+            unit_name: &unit_name,
+            shared_network: shared_network.as_deref(),
+        };
+        let name = format!("lucky_{}_{}", unit_name, {
             // Generate random suffix
             let mut rng = thread_rng();
             let mut buffer = String::with_capacity(8);
@@ -666,20 +2762,645 @@ fn apply_updates(
                 );
             }
             buffer
-        }));
+        });
+
+        log::debug!("Creating container with {}: {}", engine.name(), name);
+        let id = trace::in_span(
+            Span::start("container create").with_attr("container.image", &image_name),
+            || engine.create_container(&name, &container_info.config, &context),
+        )?;
 
-        log::trace!("Creating container with options: {:#?}", docker_options);
-        let create_info = block_on(containers.create(&docker_options))?;
+        // Start the container unless it has been stopped
+        let start = || -> anyhow::Result<()> {
+            if container_info.stopped {
+                log::debug!("Not starting stopped container: {}", id);
+            } else {
+                log::debug!("Starting container: {}", id);
+                trace::in_span(
+                    Span::start("container start").with_attr("container.id", &id),
+                    || engine.start_container(&id),
+                )?;
+            }
+            Ok(())
+        };
 
-        // Start the container
-        log::debug!("Starting container: {}", create_info.id);
-        let container = containers.get(&create_info.id);
-        block_on(container.start())?;
+        // Swap the new container in for the old one
+        match &container_info.id {
+            Some(old_id) if strategy == UpdateStrategy::BlueGreen => {
+                start()?;
+                remove_old_container(daemon, &*engine, old_id)?;
+            }
+            Some(old_id) => {
+                remove_old_container(daemon, &*engine, old_id)?;
+                start()?;
+            }
+            None => start()?,
+        }
 
         // Mark container_info as "clean" and up-to-date with the system config
-        container_info.update(|info| info.id = Some(create_info.id));
+        container_info.update(|info| info.id = Some(id));
         container_info.clean();
     }
 
     Ok(())
 }
+
+/// Stop and remove a container that is being replaced or removed
+fn remove_old_container(
+    daemon: &LuckyDaemon,
+    engine: &dyn ContainerEngine,
+    id: &str,
+) -> anyhow::Result<()> {
+    log::debug!("Stopping container: {}", id);
+    crash_monitor::expect_exit(daemon, id);
+    trace::in_span(
+        Span::start("container stop").with_attr("container.id", id),
+        || engine.stop_container(id, Some(Duration::from_secs(10))),
+    )?;
+    log::debug!("Removing container: {}", id);
+    trace::in_span(
+        Span::start("container delete").with_attr("container.id", id),
+        || engine.remove_container(id),
+    )?;
+
+    Ok(())
+}
+
+/// Apply any updates to the container and host service configuration
+///
+/// Container updates are applied with Docker if it is enabled for the charm, or with Pebble on
+/// Kubernetes.
+pub(super) fn apply_workload_updates(daemon: &LuckyDaemon) -> anyhow::Result<()> {
+    if daemon.docker_enabled() {
+        apply_container_updates(daemon, || ())?;
+    } else if daemon.platform == Platform::Kubernetes {
+        apply_pebble_updates(daemon)?;
+    }
+
+    apply_host_service_updates(daemon)
+}
+
+/// Get the name of the sidecar workload container that a Lucky container runs in
+///
+/// Named containers use the workload container with the same name. The default container uses
+/// the charm's only workload container.
+fn get_sidecar_container<'a>(
+    daemon: &'a LuckyDaemon,
+    container_name: Option<&'a str>,
+) -> anyhow::Result<&'a str> {
+    match container_name {
+        Some(name) if daemon.sidecar_containers.iter().any(|x| x == name) => Ok(name),
+        Some(name) => Err(format_err!(
+            "Container {:?} is not a workload container in the charm's metadata.yaml",
+            name
+        )),
+        None => match daemon.sidecar_containers.as_slice() {
+            [name] => Ok(name),
+            _ => Err(format_err!(
+                "The default container can only be used if the charm has exactly one workload \
+                container, use a named container instead"
+            )),
+        },
+    }
+}
+
+#[function_name::named]
+/// Apply any updates to the container configuration by replacing the Pebble services in the
+/// workload containers of a Kubernetes sidecar charm
+pub(super) fn apply_pebble_updates(daemon: &LuckyDaemon) -> anyhow::Result<()> {
+    let mut state = daemon.state.write().unwrap();
+
+    // Skip if there are no changes to apply
+    if !has_env_templates(&state)
+        && state
+            .named_containers
+            .values()
+            .chain(state.default_container.iter())
+            .all(|container| container.is_clean())
+    {
+        return Ok(());
+    }
+
+    log::debug!("Applying container configuration with Pebble");
+    daemon_set_status!(
+        daemon,
+        &mut state,
+        ScriptState::Maintenance,
+        "Applying container configuration updates"
+    );
+
+    // Reborrow the state so that we can borrow its fields separately
+    let state_ref = &mut *state;
+    let containers = state_ref
+        .named_containers
+        .iter_mut()
+        .map(|(name, container)| (Some(name.as_str()), container))
+        .chain(state_ref.default_container.iter_mut().map(|x| (None, x)));
+    for (container_name, container) in containers {
+        render_env_templates(daemon, container)?;
+        if container.is_clean() {
+            continue;
+        }
+
+        let pebble = pebble::PebbleClient::new(get_sidecar_container(daemon, container_name)?);
+        // Wait for the `pebble-ready` hook if the workload container hasn't started yet
+        if !pebble.is_ready() {
+            log::debug!("Pebble is not ready, deferring container update");
+            continue;
+        }
+
+        trace::in_span(Span::start("pebble apply"), || {
+            if container.pending_removal {
+                // The service may never have been added, so don't let this block the removal
+                pebble.change_service("stop").or_else(|e| {
+                    log::warn!("{:?}", e.context("Could not stop removed container"));
+                    Ok(())
+                })
+            } else {
+                pebble.apply_config(&container.config, !container.stopped)
+            }
+        })?;
+        container.clean();
+    }
+
+    // Remove containers that are pending removal
+    state
+        .named_containers
+        .retain(|_name, container| !container.pending_removal);
+    if state
+        .default_container
+        .as_ref()
+        .map_or(false, |x| x.pending_removal)
+    {
+        state.default_container = None;
+    }
+
+    daemon_set_status!(daemon, &mut state, ScriptState::Active);
+    Ok(())
+}
+
+/// Get the changes that applying the container configuration would make, without applying them
+pub(super) fn get_pending_container_changes(
+    daemon: &LuckyDaemon,
+) -> anyhow::Result<Vec<rpc::ContainerChange>> {
+    let state = daemon.state.read().unwrap();
+
+    let containers = state
+        .named_containers
+        .iter()
+        .map(|(name, container)| (Some(name.clone()), container))
+        .chain(state.default_container.iter().map(|x| (None, x)));
+    let mut changes = Vec::new();
+    for (container_name, container) in containers {
+        // Render the templated environment variables on a copy of the container so that the
+        // daemon state is left untouched
+        let mut container = container.clone();
+        render_env_templates(daemon, &mut container)?;
+        if container.is_clean() {
+            continue;
+        }
+
+        let (action, fields) = if container.pending_removal {
+            (
+                "remove",
+                container
+                    .config
+                    .diff(None)
+                    .into_iter()
+                    .map(|x| ConfigFieldChange {
+                        old: x.new,
+                        new: None,
+                        ..x
+                    })
+                    .collect(),
+            )
+        } else if daemon.docker_enabled() && container.id.is_none() {
+            ("create", container.config.diff(None))
+        } else {
+            (
+                "update",
+                container.config.diff(Some(&container.original().config)),
+            )
+        };
+
+        changes.push(rpc::ContainerChange {
+            container_name,
+            action: action.into(),
+            fields: fields
+                .into_iter()
+                .map(|x| rpc::ContainerFieldChange {
+                    field: x.field,
+                    old: x.old,
+                    new: x.new,
+                })
+                .collect(),
+        });
+    }
+
+    Ok(changes)
+}
+
+#[function_name::named]
+/// Apply any updates to the host service configuration by installing and restarting the changed
+/// systemd services
+pub(super) fn apply_host_service_updates(daemon: &LuckyDaemon) -> anyhow::Result<()> {
+    let mut state = daemon.state.write().unwrap();
+
+    // Skip if there are no changes to apply
+    if state
+        .host_services
+        .values()
+        .all(|service| service.is_clean())
+    {
+        return Ok(());
+    }
+
+    log::debug!("Applying host service configuration");
+    daemon_set_status!(
+        daemon,
+        &mut state,
+        ScriptState::Maintenance,
+        "Applying host service updates"
+    );
+
+    let unit_name =
+        std::env::var("JUJU_UNIT_NAME").context("Env var JUJU_UNIT_NAME not readable!")?;
+    let mut reload_needed = false;
+
+    for (service_name, service) in &mut state.host_services {
+        if service.is_clean() {
+            continue;
+        }
+
+        let systemd_unit = systemd::unit_name(&unit_name, service_name);
+        let unit_path = PathBuf::from(systemd::SYSTEMD_UNIT_DIR).join(&systemd_unit);
+        let env_file_path = daemon
+            .lucky_data_dir
+            .join(HOST_SERVICE_ENV_DIR)
+            .join(format!("{}.env", service_name));
+
+        if service.pending_removal {
+            remove_host_service(&systemd_unit, &unit_path, &env_file_path)?;
+            reload_needed = true;
+            continue;
+        }
+
+        // Write out the environment and unit files
+        service.config.write_env_file(&env_file_path)?;
+        let unit_file = service.config.render_unit_file(
+            service_name,
+            &unit_name,
+            &daemon.charm_dir,
+            &env_file_path,
+        )?;
+        std::fs::write(&unit_path, unit_file)
+            .context(format!("Could not write unit file: {:?}", unit_path))?;
+
+        // Restart the service with the new configuration
+        trace::in_span(
+            Span::start("systemctl restart").with_attr("systemd.unit", &systemd_unit),
+            || -> anyhow::Result<()> {
+                systemd::systemctl(&["daemon-reload"])?;
+                log::debug!("Restarting host service: {}", systemd_unit);
+                systemd::systemctl(&["enable", &systemd_unit])?;
+                systemd::systemctl(&["restart", &systemd_unit])
+            },
+        )?;
+
+        // Mark the service as up-to-date with the system config
+        service.clean();
+    }
+
+    // Forget services that have been removed
+    state
+        .host_services
+        .retain(|_name, service| !service.pending_removal);
+
+    if reload_needed {
+        systemd::systemctl(&["daemon-reload"])?;
+    }
+
+    daemon_set_status!(daemon, &mut state, ScriptState::Active);
+    Ok(())
+}
+
+/// Stop and disable a host service and remove its unit and environment files
+pub(super) fn remove_host_service(
+    systemd_unit: &str,
+    unit_path: &Path,
+    env_file_path: &Path,
+) -> anyhow::Result<()> {
+    // Services that have never been applied won't have a unit file
+    if unit_path.exists() {
+        log::debug!("Removing host service: {}", systemd_unit);
+        trace::in_span(
+            Span::start("systemctl disable").with_attr("systemd.unit", systemd_unit),
+            || systemd::systemctl(&["disable", "--now", systemd_unit]),
+        )?;
+        std::fs::remove_file(unit_path)
+            .context(format!("Could not remove unit file: {:?}", unit_path))?;
+    }
+
+    if env_file_path.exists() {
+        std::fs::remove_file(env_file_path)
+            .context(format!("Could not remove env file: {:?}", env_file_path))?;
+    }
+
+    Ok(())
+}
+
+/// Read the credentials used to pull an image from a private registry
+///
+/// The password is added to the log redaction list so that it can't end up in the logs.
+fn get_registry_auth(
+    daemon: &LuckyDaemon,
+    auth: &RegistryAuth,
+) -> anyhow::Result<RegistryCredentials> {
+    let data: HashMap<String, String> = match &auth.source {
+        RegistryAuthSource::Config => daemon
+            .juju
+            .config_get()?
+            .into_iter()
+            .filter_map(|(key, value)| match value {
+                JsonValue::String(value) => Some((key, value)),
+                _ => None,
+            })
+            .collect(),
+        RegistryAuthSource::Relation {
+            relation_id,
+            remote_unit,
+            app,
+        } => daemon.juju.relation_get(
+            Some(juju::SpecificRelation {
+                relation_id: relation_id.clone(),
+                remote_unit: remote_unit.clone(),
+            }),
+            *app,
+        )?,
+    };
+
+    let get_value = |key: &str| {
+        data.get(key)
+            .filter(|value| !value.is_empty())
+            .cloned()
+            .ok_or_else(|| format_err!("Registry credential {:?} is not set", key))
+    };
+    let username = get_value(&auth.username_key)?;
+    let password = get_value(&auth.password_key)?;
+    crate::log::add_redacted_value(&password);
+
+    Ok(RegistryCredentials {
+        username,
+        password,
+        server_address: auth.server_address.clone(),
+    })
+}
+
+/// Get the digest that an image resolves to, which is the digest that it is pinned to if it has
+/// one, or the registry digest of the local image otherwise
+///
+/// This returns `None` if the image doesn't have a registry digest, such as when it was built
+/// locally.
+fn resolve_image_digest(engine: &dyn ContainerEngine, image: &str) -> Option<String> {
+    if let Some(digest) = pinned_digest(image) {
+        return Some(digest.into());
+    }
+
+    let digests = trace::in_span(
+        Span::start("container inspect").with_attr("container.image", image),
+        || engine.image_digests(image),
+    );
+    match digests {
+        Ok(digests) => digests.into_iter().next(),
+        Err(e) => {
+            log::warn!("Could not get the digest of image {}: {:?}", image, e);
+            None
+        }
+    }
+}
+
+/// Check that the containers are running the image digests that they are pinned to
+///
+/// If `pull` is `true` the images that are referenced by tag are pulled again to get the digest
+/// that the tag resolves to in the registry now.
+pub(super) fn check_container_images(
+    daemon: &LuckyDaemon,
+    pull: bool,
+) -> anyhow::Result<Vec<rpc::ContainerImageCheck>> {
+    if !daemon.docker_enabled() {
+        anyhow::bail!(
+            "Container images can only be checked when containers are enabled for the charm"
+        );
+    }
+    let engine = daemon.get_container_engine()?;
+
+    // Copy the containers so that the state isn't locked while images are pulled
+    let (containers, registry): (Vec<(Option<String>, Cd<ContainerInfo>)>, _) = {
+        let state = daemon.state.read().unwrap();
+        (
+            state
+                .named_containers
+                .iter()
+                .map(|(name, container)| (Some(name.clone()), container.clone()))
+                .chain(state.default_container.iter().map(|x| (None, x.clone())))
+                .collect(),
+            state.docker_registry.clone(),
+        )
+    };
+
+    let mut checks = Vec::new();
+    for (container_name, container) in containers {
+        // Check the container as it was last applied
+        let container = container.original();
+        let id = match &container.id {
+            Some(id) => id,
+            None => continue,
+        };
+        let image = container.config.image.clone();
+
+        let pinned_digest = pinned_digest(&image).map(String::from).or_else(|| {
+            container
+                .image_digest
+                .as_ref()
+                .filter(|x| x.image == image)
+                .map(|x| x.digest.clone())
+        });
+        let running_digests = trace::in_span(
+            Span::start("container inspect").with_attr("container.id", id),
+            || engine.container_image_digests(id),
+        )?;
+
+        // Pull the image tag to see what it resolves to now
+        let remote_digest = if pull && pinned_digest(&image).is_none() {
+            let credentials =
+                match docker_registry::image_registry_auth(container, registry.as_ref()) {
+                    Some(auth) => Some(
+                        get_registry_auth(daemon, &auth)
+                            .context(format!("Could not get registry credentials for {}", image))?,
+                    ),
+                    None => None,
+                };
+            log::debug!("Pulling container image: {}", image);
+            trace::in_span(
+                Span::start("container pull").with_attr("container.image", &image),
+                || engine.pull_image(&image, credentials),
+            )?;
+            resolve_image_digest(&*engine, &image)
+        } else {
+            None
+        };
+
+        checks.push(rpc::ContainerImageCheck {
+            container_name,
+            image,
+            pinned_digest,
+            running_digests,
+            remote_digest,
+        });
+    }
+
+    Ok(checks)
+}
+
+/// Check whether or not a container exists in the container engine
+fn container_exists(engine: &dyn ContainerEngine, id: &str) -> anyhow::Result<bool> {
+    trace::in_span(
+        Span::start("container inspect").with_attr("container.id", id),
+        || engine.container_exists(id),
+    )
+}
+
+/// A change to the run state of a container
+pub(super) enum ContainerLifecycleAction {
+    Start,
+    /// Stop the container, waiting the given amount of time before killing it
+    Stop(Option<Duration>),
+    /// Restart the container, waiting the given amount of time before killing it
+    Restart(Option<Duration>),
+}
+
+/// Start, stop, or restart a container
+///
+/// Whether or not the container should be stopped is recorded in the container info so that a
+/// stopped container will stay stopped when it is re-created by a configuration update. If the
+/// container has not been created yet, or it has configuration updates that haven't been applied,
+/// the change will take effect the next time the container configuration is applied.
+pub(super) fn change_container_lifecycle(
+    daemon: &LuckyDaemon,
+    container_name: Option<&str>,
+    container_info: &mut Cd<ContainerInfo>,
+    action: &ContainerLifecycleAction,
+) -> anyhow::Result<()> {
+    let stopped = match action {
+        ContainerLifecycleAction::Stop(_) => true,
+        ContainerLifecycleAction::Start | ContainerLifecycleAction::Restart(_) => false,
+    };
+
+    // Record the run state. Changing the run state alone doesn't require the container to be
+    // re-created, so we keep the container clean if it was clean before.
+    if container_info.stopped != stopped {
+        let was_clean = container_info.is_clean();
+        container_info.update(|info| info.stopped = stopped);
+        if was_clean {
+            container_info.clean();
+        }
+    }
+
+    // On Kubernetes the container's Pebble service is started or stopped instead
+    if daemon.platform == Platform::Kubernetes {
+        if !container_info.is_clean() {
+            log::debug!("Container has pending updates, deferring change until next apply");
+            return Ok(());
+        }
+
+        let pebble = pebble::PebbleClient::new(get_sidecar_container(daemon, container_name)?);
+        let service_action = match action {
+            ContainerLifecycleAction::Start => "start",
+            ContainerLifecycleAction::Stop(_) => "stop",
+            ContainerLifecycleAction::Restart(_) => "restart",
+        };
+        return trace::in_span(
+            Span::start("pebble service change").with_attr("action", service_action),
+            || pebble.change_service(service_action),
+        );
+    }
+
+    // Skip containers that will be started or stopped when the config is applied
+    let id = match &container_info.id {
+        Some(id) if container_info.is_clean() => id.clone(),
+        _ => {
+            log::debug!("Container has pending updates, deferring change until next apply");
+            return Ok(());
+        }
+    };
+
+    // Get the container engine
+    let engine = daemon.get_container_engine()?;
+
+    match action {
+        ContainerLifecycleAction::Start => {
+            log::debug!("Starting container: {}", id);
+            trace::in_span(
+                Span::start("container start").with_attr("container.id", &id),
+                || engine.start_container(&id),
+            )?;
+        }
+        ContainerLifecycleAction::Stop(wait) => {
+            log::debug!("Stopping container: {}", id);
+            crash_monitor::expect_exit(daemon, &id);
+            trace::in_span(
+                Span::start("container stop").with_attr("container.id", &id),
+                || engine.stop_container(&id, *wait),
+            )?;
+        }
+        ContainerLifecycleAction::Restart(wait) => {
+            log::debug!("Restarting container: {}", id);
+            crash_monitor::expect_exit(daemon, &id);
+            trace::in_span(
+                Span::start("container restart").with_attr("container.id", &id),
+                || engine.restart_container(&id, *wait),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Convert a timeout in seconds recieved over RPC to a `Duration`
+pub(super) fn parse_timeout(timeout: Option<i64>) -> anyhow::Result<Option<Duration>> {
+    timeout
+        .map(|seconds| {
+            seconds
+                .try_into()
+                .map(Duration::from_secs)
+                .map_err(|_| format_err!("Invalid timeout: {}", seconds))
+        })
+        .transpose()
+}
+
+/// Get the full command that a container will run: the entrypoint followed by the command
+///
+/// Any parts that are not set in the container config are taken from the `image_command`.
+fn get_container_command(
+    config: &ContainerConfig,
+    image_command: ImageCommand,
+) -> anyhow::Result<Vec<String>> {
+    let mut command = vec![];
+    if let Some(entrypoint) = &config.entrypoint {
+        // The image's command is not used when the entrypoint is overridden
+        command.push(entrypoint.clone());
+        command.extend(config.command.clone().unwrap_or_default());
+    } else {
+        command.extend(image_command.entrypoint);
+        command.extend(config.command.clone().unwrap_or(image_command.command));
+    }
+
+    if command.is_empty() {
+        anyhow::bail!(
+            "Could not determine the command to run for image: {}",
+            config.image
+        );
+    }
+
+    Ok(command)
+}
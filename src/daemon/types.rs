@@ -1,5 +1,20 @@
 use serde::{Deserialize, Serialize};
 use std::ops::Deref;
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use subprocess::Popen;
+
+use crate::types::ScriptStatus;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A change of a script's status, recorded in the status history
+pub(crate) struct StatusTransition {
+    /// When the status changed, as a Unix timestamp in milliseconds
+    pub time: i64,
+    /// The status before the change, or `None` if it wasn't set
+    pub old: Option<ScriptStatus>,
+    /// The status after the change, or `None` if it was cleared
+    pub new: Option<ScriptStatus>,
+}
 
 #[derive(Serialize, Deserialize, Clone)]
 /// A change detecting container for other types
@@ -14,7 +29,7 @@ use std::ops::Deref;
 /// version and will return `true` if the clone and the original are the same. This means that if
 /// you modify the type and the set it back to what it was previously, `is_clean()` will still
 /// return `true`.
-/// 
+///
 /// > **Note:** Newly created `Cd`'s start off "dirty" and `is_clean()` will return false. You
 /// > can also force any `Cd` to register as dirty, regardless of whether or not the inner type has
 /// > changed by running `mark_dirty()`.
@@ -47,9 +62,11 @@ impl<T: Clone + PartialEq> Cd<T> {
             std::mem::swap(&mut self.inner, new_inner);
             // And delete the old value ( now stored in `new_inner` )
             self.new_inner = None;
-            // Clear the force_dirty flag
-            self.force_dirty = false;
         }
+
+        // Clear the force_dirty flag, which is also set on new and `mark_dirty()`ed types that
+        // haven't been updated
+        self.force_dirty = false;
     }
 
     /// Returns `true` if the inner type has **not** been modified since the last run of
@@ -76,6 +93,11 @@ impl<T: Clone + PartialEq> Cd<T> {
         self.force_dirty = true;
     }
 
+    /// Get the inner type as of the last run of `clean()`, ignoring any updates made since then
+    pub fn original(&self) -> &T {
+        &self.inner
+    }
+
     /// Consumes the `Cd` and converts to the inner type
     pub fn into_inner(self) -> T {
         // Return the latest updated inner type if it exists
@@ -140,3 +162,108 @@ impl<T: Clone + PartialEq + std::fmt::Debug> std::fmt::Debug for Cd<T> {
         write!(f, "{:?}", self.inner)
     }
 }
+
+/// The concurrency class of a daemon RPC method
+///
+/// Methods in the `Read` class only query state and may run at the same time as any other `Read`
+/// method, such as when multiple scripts in a parallel script group are querying the daemon at
+/// once. Methods in the `Write` class are run exclusively, with no other `Read` or `Write` methods
+/// running at the same time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ConcurrencyClass {
+    /// Read-only methods that may run concurrently with each other
+    Read,
+    /// Methods that modify state and must be serialized
+    Write,
+}
+
+/// A guard that is held for the duration of an RPC method call to enforce its `ConcurrencyClass`
+///
+/// The class is released when the guard is dropped.
+pub(crate) enum ConcurrencyGuard<'a> {
+    Read(RwLockReadGuard<'a, ()>),
+    Write(RwLockWriteGuard<'a, ()>),
+}
+
+impl<'a> ConcurrencyGuard<'a> {
+    /// Acquire the given concurrency class on the lock, blocking until it is available
+    pub fn acquire(lock: &'a RwLock<()>, class: ConcurrencyClass) -> Self {
+        // The lock only guards `()` so it is safe to continue using it even if another thread
+        // panicked while holding it.
+        match class {
+            ConcurrencyClass::Read => {
+                ConcurrencyGuard::Read(lock.read().unwrap_or_else(PoisonError::into_inner))
+            }
+            ConcurrencyClass::Write => {
+                ConcurrencyGuard::Write(lock.write().unwrap_or_else(PoisonError::into_inner))
+            }
+        }
+    }
+}
+
+/// A handle that can be used to cancel a running action
+///
+/// Host script processes started for the action are registered with the handle so that they can
+/// be terminated when the action is cancelled. Cloning the handle gives another handle to the same
+/// action.
+#[derive(Clone, Default)]
+pub(crate) struct CancelHandle {
+    /// The reason that the action was cancelled, if it has been cancelled
+    reason: Arc<Mutex<Option<String>>>,
+    /// The script processes that are running for the action
+    processes: Arc<Mutex<Vec<Arc<Mutex<Popen>>>>>,
+}
+
+impl CancelHandle {
+    /// Cancel the action, terminating all of its running script processes
+    ///
+    /// Only the first cancellation reason is kept. Cancelling an action that has already been
+    /// cancelled does nothing.
+    pub fn cancel(&self, reason: &str) {
+        {
+            let mut current_reason = self.reason.lock().unwrap();
+            if current_reason.is_some() {
+                return;
+            }
+            *current_reason = Some(reason.into());
+        }
+
+        log::warn!("Cancelling action: {}", reason);
+
+        for process in self.processes.lock().unwrap().iter() {
+            terminate_process(process);
+        }
+    }
+
+    /// Get the reason that the action was cancelled, or `None` if it hasn't been cancelled
+    pub fn cancel_reason(&self) -> Option<String> {
+        self.reason.lock().unwrap().clone()
+    }
+
+    /// Register a script process with the action so that it will be terminated if the action is
+    /// cancelled
+    ///
+    /// If the action has already been cancelled the process is terminated immediately.
+    pub fn register_process(&self, process: Arc<Mutex<Popen>>) {
+        // Hold the reason lock so that the action can't be cancelled while we register
+        let reason = self.reason.lock().unwrap();
+        if reason.is_some() {
+            terminate_process(&process);
+        }
+        self.processes.lock().unwrap().push(process);
+    }
+}
+
+/// Send `SIGTERM` to a script process, logging any errors
+fn terminate_process(process: &Mutex<Popen>) {
+    let mut process = process.lock().unwrap();
+
+    // Skip processes that have already exited
+    if process.poll().is_some() {
+        return;
+    }
+
+    if let Err(e) = process.terminate() {
+        log::warn!("Could not terminate action script: {}", e);
+    }
+}
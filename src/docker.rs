@@ -2,20 +2,41 @@
 use anyhow::{bail, Context};
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use shiplift::builder::ContainerOptions;
 use shrinkwraprs::Shrinkwrap;
+use strum_macros::{AsRefStr, EnumString, EnumVariantNames};
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 use std::fs;
 use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
+use crate::container_engine::ContainerContext;
 use crate::process::{cmd_exists, run_cmd, run_cmd_with_retries};
+use crate::types::{
+    CharmScript, CharmScriptType, ContainerRestartPolicy, ContainerSpec, DockerChannel, PortSpec,
+    UpdateStrategy, DEFAULT_CONTAINER_NAME,
+};
 
 use crate::VOLUME_DIR;
 
+/// The prefix of volume sources that refer to Juju storage
+const STORAGE_VOLUME_PREFIX: &str = "storage:";
+/// The path that env files are mounted to inside of the container
+const CONTAINER_ENV_FILE_PATH: &str = "/run/lucky/container.env";
+/// The directory in the Lucky data dir that templated container files are rendered to
+const CONTAINER_FILE_DIR: &str = "container_files";
+/// The label that containers are given with the name of the unit that created them
+pub(crate) const UNIT_LABEL: &str = "lucky.unit";
+/// The label that containers are given with their name in Lucky
+pub(crate) const CONTAINER_LABEL: &str = "lucky.container";
+/// The label that containers are given with the fingerprint of the config they were created with
+pub(crate) const CONFIG_LABEL: &str = "lucky.config";
+
 /// A struct made of a container definition and the container id
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub(crate) struct ContainerInfo {
@@ -25,6 +46,29 @@ pub(crate) struct ContainerInfo {
     pub pending_removal: bool,
     /// Whether or not to pull the Docker image before running it
     pub pull_image: bool,
+    /// Whether or not the container has been stopped. Stopped containers are still created when
+    /// the container configuration is applied, but they are not started.
+    #[serde(default)]
+    pub stopped: bool,
+    /// The spec that the container was last declared with in the `lucky.yaml`, if it was declared
+    /// there. This is used to tell which parts of the config were changed by the spec and which
+    /// were overridden by scripts.
+    #[serde(default)]
+    pub declared_spec: Option<ContainerSpec>,
+    /// Where to read the credentials used to pull the image from a private registry
+    #[serde(default)]
+    pub registry_auth: Option<RegistryAuth>,
+    /// The digest that the image resolved to when the container was last created. Containers
+    /// are checked against this digest by `lucky container check-images`.
+    #[serde(default)]
+    pub image_digest: Option<ImageDigest>,
+    /// The Juju resource that the image is loaded from instead of being pulled, if any
+    #[serde(default)]
+    pub image_resource: Option<String>,
+    /// A fingerprint of the resource file that the image was last loaded from. The image is
+    /// loaded again when the fingerprint changes, such as when a new resource is attached.
+    #[serde(default)]
+    pub loaded_image_resource: Option<String>,
     /// The definition for the desired state of the container. This should match the actual state
     /// of the container if `dirty` is `false`.
     pub config: ContainerConfig,
@@ -36,17 +80,156 @@ impl ContainerInfo {
             id: None,
             pending_removal: false,
             pull_image: true,
+            stopped: false,
+            declared_spec: None,
+            registry_auth: None,
+            image_digest: None,
+            image_resource: None,
+            loaded_image_resource: None,
             config: ContainerConfig::new(image),
         }
     }
 }
 
+/// How a container is swapped for a new one when its configuration changes, and the scripts that
+/// are run around the swap
+///
+/// This is kept separately from the `ContainerInfo` so that changing it doesn't re-create the
+/// container.
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub(crate) struct ContainerUpdateSettings {
+    #[serde(default)]
+    pub strategy: UpdateStrategy,
+    /// The scripts to run before the container is updated
+    #[serde(default)]
+    pub pre_update: Vec<UpdateHook>,
+    /// The scripts to run after the new container has replaced the old one
+    #[serde(default)]
+    pub post_update: Vec<UpdateHook>,
+}
+
+impl ContainerUpdateSettings {
+    /// Get the hooks for the given stage
+    pub fn hooks_mut(&mut self, stage: UpdateHookStage) -> &mut Vec<UpdateHook> {
+        match stage {
+            UpdateHookStage::PreUpdate => &mut self.pre_update,
+            UpdateHookStage::PostUpdate => &mut self.post_update,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, AsRefStr, EnumString, EnumVariantNames)]
+#[strum(serialize_all = "kebab-case")]
+/// When an update hook is run
+pub(crate) enum UpdateHookStage {
+    PreUpdate,
+    PostUpdate,
+}
+
+/// A script that is run before or after a container is updated
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct UpdateHook {
+    /// The name of the script in the `host_scripts` dir, or in the `container_scripts` dir if
+    /// `in_container` is `true`
+    pub script: String,
+    pub args: Vec<String>,
+    /// Whether or not to run the script in the container. Pre-update hooks run in the old
+    /// container and post-update hooks run in the new one.
+    pub in_container: bool,
+}
+
+impl UpdateHook {
+    /// Get the charm script that runs the hook for the given container
+    pub fn to_charm_script(&self, container_name: &str) -> CharmScript {
+        CharmScript {
+            is_async: false,
+            id: None,
+            after: vec![],
+            requires: vec![],
+            timeout: None,
+            env: HashMap::new(),
+            exit_codes: None,
+            interpreter: None,
+            interpreter_package: None,
+            on_failure: None,
+            when: None,
+            retries: 0,
+            retry_delay: 5,
+            run_as: None,
+            limits: None,
+            script_type: if self.in_container {
+                CharmScriptType::Container {
+                    container_script: self.script.clone(),
+                    container_name: Some(container_name)
+                        .filter(|&x| x != DEFAULT_CONTAINER_NAME)
+                        .map(Into::into),
+                    args: self.args.clone(),
+                    ignore_missing_container: false,
+                }
+            } else {
+                CharmScriptType::Host {
+                    host_script: self.script.clone(),
+                    args: self.args.clone(),
+                }
+            },
+        }
+    }
+}
+
+/// The digest that an image reference resolved to
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct ImageDigest {
+    /// The image reference, including its tag or digest
+    pub image: String,
+    /// The digest of the image, such as `sha256:...`
+    pub digest: String,
+}
+
+/// Where to read the credentials used to pull a container image from a private registry
+///
+/// Only the location of the credentials is stored in the daemon state. The credentials themselves
+/// are read every time the image is pulled so that they are never written to disk.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct RegistryAuth {
+    pub source: RegistryAuthSource,
+    /// The key that the username is stored under
+    pub username_key: String,
+    /// The key that the password is stored under
+    pub password_key: String,
+    /// The address of the registry. If not set, Docker will use the registry from the image name.
+    pub server_address: Option<String>,
+}
+
+/// The place to read registry credentials from
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum RegistryAuthSource {
+    /// The charm config
+    Config,
+    /// The relation data of a remote unit, or of the remote application if `app` is `true`
+    Relation {
+        relation_id: String,
+        remote_unit: String,
+        app: bool,
+    },
+}
+
 #[derive(Shrinkwrap, Serialize, Deserialize, PartialEq, Eq, Hash, Default, Clone, Debug)]
 #[shrinkwrap(mutable)]
 #[serde(transparent)]
 /// A volume source path wrapper type to make it more difficult to mix-up sources and targets
 pub struct VolumeSource(pub String);
 
+impl VolumeSource {
+    /// Get the name of the Juju storage that the volume is mounted from, if the source is in the
+    /// `storage:<name>` format
+    pub fn storage_name(&self) -> Option<&str> {
+        self.0
+            .strip_prefix(STORAGE_VOLUME_PREFIX)
+            .filter(|x| !x.is_empty())
+    }
+}
+
 #[derive(Shrinkwrap, Serialize, Deserialize, PartialEq, Eq, Hash, Default, Clone, Debug)]
 #[shrinkwrap(mutable)]
 #[serde(transparent)]
@@ -102,6 +285,209 @@ impl FromStr for PortBinding {
     }
 }
 
+#[derive(
+    Serialize,
+    Deserialize,
+    PartialEq,
+    Eq,
+    Clone,
+    Copy,
+    Debug,
+    AsRefStr,
+    EnumString,
+    EnumVariantNames,
+)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+/// How the environment variables are passed to a container
+pub(crate) enum EnvMode {
+    /// Set the variables in the container config, where they are visible in `docker inspect`
+    Inline,
+    /// Render the variables to an env file that is mounted into the container and loaded by Lucky
+    /// before running the container's command
+    File,
+}
+
+impl Default for EnvMode {
+    fn default() -> Self {
+        EnvMode::Inline
+    }
+}
+
+/// An env file that a container's environment is loaded from instead of being set inline
+pub(crate) struct EnvFile {
+    /// The path to the env file on the host
+    pub path: PathBuf,
+    /// The command that should be run after loading the env file: the container's entrypoint
+    /// followed by its command
+    pub command: Vec<String>,
+}
+
+/// The engine-independent settings used to run a container
+pub(crate) struct RunSettings {
+    pub image: String,
+    /// The bind mounts in the `source:target[:options]` format
+    pub volumes: Vec<String>,
+    /// The environment variables in the `KEY=value` format
+    pub env: Vec<String>,
+    pub entrypoint: Option<String>,
+    pub command: Option<Vec<String>>,
+    pub ports: Vec<PortBinding>,
+    pub network: Option<String>,
+    pub restart_policy: ContainerRestartPolicy,
+    /// The DNS servers to use instead of the engine's defaults
+    pub dns: Vec<String>,
+    /// Extra entries for the container's `/etc/hosts` in the `hostname:address` format
+    pub extra_hosts: Vec<String>,
+    /// The host devices to add to the container in the
+    /// `host_path[:container_path][:permissions]` format
+    pub devices: Vec<String>,
+    /// The GPUs to add to the container in the format of the `--gpus` command line option
+    pub gpus: Option<String>,
+    /// The labels that Lucky uses to find the container again
+    pub labels: BTreeMap<String, String>,
+}
+
+impl RunSettings {
+    /// Whether or not the container has settings that shiplift can't set, which means that it
+    /// has to be created with the command line tool
+    pub fn requires_cli(&self) -> bool {
+        !self.dns.is_empty() || !self.devices.is_empty() || self.gpus.is_some()
+    }
+
+    /// Get a `ContainerOptions` struct that can be given to shiplift to run the container
+    ///
+    /// shiplift can't set the DNS servers, devices, or GPUs of a container, so they are left out.
+    pub fn to_container_options(&self) -> ContainerOptions {
+        let mut options = ContainerOptions::builder(&self.image);
+
+        // Add entrypoint
+        if let Some(entrypoint) = &self.entrypoint {
+            options.entrypoint(entrypoint);
+        }
+
+        // Add command
+        if let Some(cmd) = &self.command {
+            options.cmd(cmd.iter().map(AsRef::as_ref).collect());
+        }
+
+        // Add ports
+        for PortBinding {
+            container_port,
+            protocol,
+            host_port,
+        } in &self.ports
+        {
+            options.expose(*container_port, protocol, *host_port);
+        }
+
+        // Set network
+        if let Some(network) = &self.network {
+            options.network_mode(network);
+        }
+        if !self.extra_hosts.is_empty() {
+            options.extra_hosts(self.extra_hosts.iter().map(AsRef::as_ref).collect());
+        }
+
+        // Add volumes
+        options.volumes(self.volumes.iter().map(AsRef::as_ref).collect());
+        // Add environment
+        options.env(self.env.iter().map(AsRef::as_ref).collect());
+        // Add labels
+        options.labels(
+            &self
+                .labels
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect(),
+        );
+
+        // Set the restart policy
+        options.restart_policy(
+            self.restart_policy.as_ref(),
+            0, /* Maximum retry count */
+        );
+
+        // Build options
+        options.build()
+    }
+
+    /// Get the arguments to the `create` subcommand of a Docker-compatible command line tool that
+    /// create the container with the given name
+    ///
+    /// `extra_args` are added before the image. The environment variables are returned separately
+    /// as pairs that must be set in the environment of the tool, so that their values don't end
+    /// up on the command line.
+    pub fn to_cli_create_args(
+        &self,
+        name: &str,
+        extra_args: Vec<String>,
+    ) -> (Vec<String>, Vec<(&str, &str)>) {
+        let mut args: Vec<String> = vec![
+            "create".into(),
+            "--name".into(),
+            name.into(),
+            "--restart".into(),
+            self.restart_policy.as_ref().into(),
+        ];
+
+        for volume in &self.volumes {
+            args.extend(vec!["--volume".into(), volume.clone()]);
+        }
+        for port in &self.ports {
+            args.extend(vec!["--publish".into(), port.to_string()]);
+        }
+        if let Some(network) = &self.network {
+            args.extend(vec!["--network".into(), network.clone()]);
+        }
+        for server in &self.dns {
+            args.extend(vec!["--dns".into(), server.clone()]);
+        }
+        for host in &self.extra_hosts {
+            args.extend(vec!["--add-host".into(), host.clone()]);
+        }
+        for device in &self.devices {
+            args.extend(vec!["--device".into(), device.clone()]);
+        }
+        if let Some(gpus) = &self.gpus {
+            args.extend(vec!["--gpus".into(), gpus.clone()]);
+        }
+        for (key, value) in &self.labels {
+            args.extend(vec!["--label".into(), format!("{}={}", key, value)]);
+        }
+        if let Some(entrypoint) = &self.entrypoint {
+            args.extend(vec!["--entrypoint".into(), entrypoint.clone()]);
+        }
+
+        let (env, env_args) = cli_env_args(&self.env);
+        args.extend(env_args);
+        args.extend(extra_args);
+
+        args.push(self.image.clone());
+        args.extend(self.command.clone().unwrap_or_default());
+
+        (args, env)
+    }
+}
+
+/// Split `KEY=value` environment variables into pairs, and get the `--env` args that pass the
+/// variables through from the environment of a Docker-compatible command line tool
+pub(crate) fn cli_env_args(env: &[String]) -> (Vec<(&str, &str)>, Vec<String>) {
+    let pairs: Vec<(&str, &str)> = env
+        .iter()
+        .map(|var| {
+            let mut parts = var.splitn(2, '=');
+            (parts.next().unwrap_or(""), parts.next().unwrap_or(""))
+        })
+        .collect();
+    let args = pairs
+        .iter()
+        .flat_map(|(key, _)| vec!["--env".to_string(), (*key).to_string()])
+        .collect();
+
+    (pairs, args)
+}
+
 /// The container configuration options such as image, volumes, ports, etc.
 #[derive(Serialize, Deserialize, Default, PartialEq, Clone, Debug)]
 pub(crate) struct ContainerConfig {
@@ -111,9 +497,48 @@ pub(crate) struct ContainerConfig {
     pub command: Option<Vec<String>>,
     /// Volume mapping from target to source
     pub volumes: HashMap<VolumeTarget, VolumeSource>,
+    /// The volume targets that are mounted read-only
+    #[serde(default)]
+    pub read_only_volumes: HashSet<VolumeTarget>,
     // The port bindings
     pub ports: HashSet<PortBinding>,
+    /// The port bindings whose host port should not be opened in Juju
+    #[serde(default)]
+    pub private_ports: HashSet<PortBinding>,
     pub network: Option<String>,
+    /// How the environment variables are passed to the container
+    #[serde(default)]
+    pub env_mode: EnvMode,
+    /// Templates for environment variables that are rendered from the charm config and relation
+    /// data every time the container config is applied. The rendered values are kept in
+    /// `env_vars`.
+    #[serde(default)]
+    pub env_templates: HashMap<String, String>,
+    /// When the container engine should restart the container after it exits
+    #[serde(default)]
+    pub restart_policy: ContainerRestartPolicy,
+    /// Whether or not the container is attached to the network shared between the charm's
+    /// containers
+    #[serde(default)]
+    pub shared_network: bool,
+    /// The DNS servers to use instead of the engine's defaults
+    #[serde(default)]
+    pub dns: Vec<String>,
+    /// Extra entries for the container's `/etc/hosts`, mapping hostname to address
+    #[serde(default)]
+    pub extra_hosts: HashMap<String, String>,
+    /// Templated files mapping the path in the container to the template path, relative to the
+    /// charm directory. The rendered files are mounted read-only into the container.
+    #[serde(default)]
+    pub files: HashMap<String, String>,
+    /// The host devices to add to the container in the
+    /// `host_path[:container_path][:permissions]` format
+    #[serde(default)]
+    pub devices: Vec<String>,
+    /// The GPUs to add to the container: `all`, a number of GPUs, or `device=` followed by a comma
+    /// separated list of GPU indexes or UUIDs
+    #[serde(default)]
+    pub gpus: Option<String>,
 }
 
 impl ContainerConfig {
@@ -124,19 +549,59 @@ impl ContainerConfig {
         }
     }
 
-    /// Get a `ContainerOptions` struct that can be given to shiplift to run the container
+    /// Get the engine-independent settings used to run the container
     ///
-    /// The `charm_dir` is used as reference when mounting the container scripts into the container
-    /// and the `socket_path` is used to mount the Lucky Daemon socket inside the container.
-    pub fn to_container_options(
-        &self,
-        charm_dir: &Path,
-        lucky_data_dir: &Path,
-        socket_path: &Path,
-    ) -> anyhow::Result<ContainerOptions> {
-        let mut options = ContainerOptions::builder(&self.image);
+    /// The `charm_dir` of the context is used as reference when mounting the container scripts
+    /// into the container and the `socket_path` is used to mount the Lucky Daemon socket inside the
+    /// container. If an `env_file` is given, the environment variables will be loaded from it
+    /// instead of being set in the settings. Volumes from Juju storage are mounted from their
+    /// location in `storage_locations`, keyed by storage name, and are skipped if the storage isn't
+    /// attached.
+    pub fn to_run_settings(&self, context: &ContainerContext) -> anyhow::Result<RunSettings> {
+        let ContainerContext {
+            charm_dir,
+            lucky_data_dir,
+            socket_path,
+            env_file,
+            storage_locations,
+            ..
+        } = *context;
         let mut volumes: Vec<String> = vec![];
         let mut env: Vec<String> = vec![];
+        let entrypoint;
+        let command;
+
+        // Containers that share the network stack of the host, or have none, can't join another
+        // network
+        match self.network.as_deref() {
+            Some(network) if self.shared_network && (network == "host" || network == "none") => {
+                bail!(
+                    "Containers using the {} network can't be attached to the shared network",
+                    network
+                );
+            }
+            Some("host") if !self.dns.is_empty() => {
+                bail!("DNS servers can't be set for containers using the host network");
+            }
+            _ => (),
+        }
+
+        // Make sure that the devices and GPUs exist on the host, so that the container doesn't
+        // fail to start with a less helpful error from the container engine
+        for device in &self.devices {
+            let host_path = device_host_path(device)?;
+            if !Path::new(host_path).exists() {
+                bail!("Device {} does not exist on the host", host_path);
+            }
+        }
+        let gpus = match &self.gpus {
+            Some(gpus) => {
+                let gpus: GpuRequest = gpus.parse()?;
+                gpus.check_available()?;
+                Some(gpus.to_string())
+            }
+            None => None,
+        };
 
         // Mount container scripts into the container
         volumes.push(format!(
@@ -166,23 +631,62 @@ impl ContainerConfig {
         // Set lucky context to client
         env.push("LUCKY_CONTEXT=client".into());
 
-        // Add the rest of the environment variables
-        for (var, value) in &self.env_vars {
-            env.push(format!("{}={}", var, value));
-        }
+        // If the environment should be loaded from an env file
+        if let Some(env_file) = env_file {
+            // Mount the env file into the container
+            volumes.push(format!(
+                "{}:{}:ro",
+                env_file.path.to_string_lossy(),
+                CONTAINER_ENV_FILE_PATH
+            ));
+            env.push(format!("LUCKY_ENV_FILE={}", CONTAINER_ENV_FILE_PATH));
 
-        // Add entrypoint
-        if let Some(entrypoint) = &self.entrypoint {
-            options.entrypoint(&entrypoint);
+            // Wrap the container command with Lucky so that it can load the env file first
+            entrypoint = Some("/usr/bin/lucky".into());
+            let mut cmd: Vec<String> = vec!["container", "env", "exec", "--"]
+                .into_iter()
+                .map(Into::into)
+                .collect();
+            cmd.extend(env_file.command.iter().cloned());
+            command = Some(cmd);
+        } else {
+            // Add the rest of the environment variables
+            for (var, value) in &self.env_vars {
+                env.push(format!("{}={}", var, value));
+            }
+
+            // Add entrypoint and command
+            entrypoint = self.entrypoint.clone();
+            command = self.command.clone();
         }
 
-        // Add command
-        if let Some(cmd) = &self.command {
-            options.cmd(cmd.iter().map(AsRef::as_ref).collect());
+        // Mount the rendered templated files
+        for target in self.files.keys() {
+            let host_path = rendered_file_path(lucky_data_dir, context.container_name, target)?;
+            volumes.push(format!("{}:{}:ro", host_path.to_string_lossy(), target));
         }
 
         // Add other specified volumes
         for (target, source) in &self.volumes {
+            // Mount Juju storage from wherever Juju has attached it
+            if let Some(storage_name) = source.storage_name() {
+                if let Some(location) = storage_locations.get(storage_name) {
+                    volumes.push(format!(
+                        "{}:{}{}",
+                        location.to_string_lossy(),
+                        &**target,
+                        self.volume_mode_suffix(target)
+                    ));
+                } else {
+                    log::debug!(
+                        "Not mounting storage {} to {}: the storage is not attached",
+                        storage_name,
+                        &**target
+                    );
+                }
+                continue;
+            }
+
             let host_path = if source.starts_with('/') {
                 PathBuf::from(&**source)
             } else {
@@ -198,54 +702,585 @@ impl ContainerConfig {
             }
 
             // Add volume to container
-            volumes.push(format!("{}:{}", host_path.to_string_lossy(), &**target));
+            volumes.push(format!(
+                "{}:{}{}",
+                host_path.to_string_lossy(),
+                &**target,
+                self.volume_mode_suffix(target)
+            ));
         }
 
-        // Add ports
-        for PortBinding {
-            container_port,
-            protocol,
-            host_port,
-        } in &self.ports
-        {
-            options.expose(*container_port, protocol, *host_port);
+        Ok(RunSettings {
+            image: self.image.clone(),
+            volumes,
+            env,
+            entrypoint,
+            command,
+            ports: self.ports.iter().cloned().collect(),
+            network: self.network.clone(),
+            restart_policy: self.restart_policy,
+            dns: self.dns.clone(),
+            extra_hosts: {
+                let mut hosts: Vec<String> = self
+                    .extra_hosts
+                    .iter()
+                    .map(|(hostname, address)| format!("{}:{}", hostname, address))
+                    .collect();
+                hosts.sort();
+                hosts
+            },
+            devices: self.devices.clone(),
+            gpus,
+            labels: vec![
+                (UNIT_LABEL, context.unit_name.to_string()),
+                (CONTAINER_LABEL, context.container_name.to_string()),
+                (CONFIG_LABEL, self.fingerprint()),
+            ]
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+        })
+    }
+
+    /// Render the container's environment variables to an env file that is only readable by the
+    /// user running the daemon
+    pub fn write_env_file(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context(format!(
+                "Could not create dir: {}",
+                parent.to_string_lossy()
+            ))?;
         }
 
-        // Set network
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)
+            .context(format!("Could not open env file: {:?}", path))?;
+        // Make sure the permissions are correct even if the file already existed
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(crate::types::env_file::render(&self.env_vars).as_bytes())
+            .context(format!("Could not write env file: {:?}", path))?;
+
+        Ok(())
+    }
+
+    /// Update the config with the changes between two versions of the container's declared spec
+    ///
+    /// Only the parts of the spec that have changed are applied so that any changes made to the
+    /// config by scripts are kept.
+    pub fn apply_spec_changes(
+        &mut self,
+        old: &ContainerSpec,
+        new: &ContainerSpec,
+    ) -> anyhow::Result<()> {
+        if old.image != new.image {
+            self.image = new.image.clone();
+        }
+        if old.entrypoint != new.entrypoint {
+            self.entrypoint = new.entrypoint.clone();
+        }
+        if old.command != new.command {
+            self.command = new.command.clone();
+        }
+        if old.network != new.network {
+            self.network = new.network.clone();
+        }
+        if old.restart != new.restart {
+            self.restart_policy = new.restart.unwrap_or_default();
+        }
+        if old.shared_network != new.shared_network {
+            self.shared_network = new.shared_network;
+        }
+        if old.dns != new.dns {
+            self.dns = new.dns.clone();
+        }
+        if old.devices != new.devices {
+            self.devices = new.devices.clone();
+        }
+        if old.gpus != new.gpus {
+            self.gpus = new.gpus.clone();
+        }
+
+        // Update extra hosts
+        for hostname in old.extra_hosts.keys() {
+            if !new.extra_hosts.contains_key(hostname) {
+                self.extra_hosts.remove(hostname);
+            }
+        }
+        for (hostname, address) in &new.extra_hosts {
+            if old.extra_hosts.get(hostname) != Some(address) {
+                self.extra_hosts.insert(hostname.clone(), address.clone());
+            }
+        }
+
+        // Update environment variables
+        for key in old.env.keys() {
+            if !new.env.contains_key(key) {
+                self.env_vars.remove(key);
+            }
+        }
+        for (key, value) in &new.env {
+            if old.env.get(key) != Some(value) {
+                self.env_vars.insert(key.clone(), value.clone());
+            }
+        }
+
+        // Update port bindings
+        let parse_ports = |ports: &[PortSpec]| -> anyhow::Result<HashMap<PortBinding, bool>> {
+            ports
+                .iter()
+                .map(|port| {
+                    let binding = port.binding().parse().context(format!(
+                        "Invalid port binding in container spec: {}",
+                        port.binding()
+                    ))?;
+                    Ok((binding, port.open()))
+                })
+                .collect()
+        };
+        let old_ports = parse_ports(&old.ports)?;
+        let new_ports = parse_ports(&new.ports)?;
+        for port in old_ports.keys() {
+            if !new_ports.contains_key(port) {
+                self.ports.remove(port);
+                self.private_ports.remove(port);
+            }
+        }
+        for (port, open) in new_ports {
+            if old_ports.get(&port) != Some(&open) {
+                if open {
+                    self.private_ports.remove(&port);
+                } else {
+                    self.private_ports.insert(port.clone());
+                }
+                self.ports.insert(port);
+            }
+        }
+
+        // Update templated files
+        for file in &old.files {
+            if !new.files.iter().any(|x| x.target == file.target) {
+                self.files.remove(&file.target);
+            }
+        }
+        for file in &new.files {
+            if !old.files.contains(file) {
+                self.files
+                    .insert(file.target.clone(), file.template.clone());
+            }
+        }
+
+        // Update volumes
+        let old_volumes = parse_volumes(&old.volumes)?;
+        let new_volumes = parse_volumes(&new.volumes)?;
+        for target in old_volumes.keys() {
+            if !new_volumes.contains_key(target) {
+                self.volumes.remove(target);
+                self.read_only_volumes.remove(target);
+            }
+        }
+        for (target, volume) in new_volumes {
+            if old_volumes.get(&target) != Some(&volume) {
+                let (source, read_only) = volume;
+                if read_only {
+                    self.read_only_volumes.insert(target.clone());
+                } else {
+                    self.read_only_volumes.remove(&target);
+                }
+                self.volumes.insert(target, source);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the host ports that should be opened in Juju, in the `port/protocol` format
+    pub fn juju_ports(&self) -> HashSet<String> {
+        self.ports
+            .difference(&self.private_ports)
+            .map(|port| format!("{}/{}", port.host_port, port.protocol))
+            .collect()
+    }
+
+    /// Get the `:ro` mount option for the volume if it is mounted read-only
+    fn volume_mode_suffix(&self, target: &VolumeTarget) -> &'static str {
+        if self.read_only_volumes.contains(target) {
+            ":ro"
+        } else {
+            ""
+        }
+    }
+
+    /// Get the names of the Juju storage that is mounted into the container
+    pub fn storage_names(&self) -> HashSet<&str> {
+        self.volumes
+            .values()
+            .filter_map(VolumeSource::storage_name)
+            .collect()
+    }
+
+    /// Get a hash of the config that changes whenever any of its fields change
+    ///
+    /// This is used to tell whether an existing container was created with this config.
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        for (field, value) in self.fields() {
+            hasher.update(field.as_bytes());
+            hasher.update(b"\0");
+            hasher.update(value.as_bytes());
+            hasher.update(b"\0");
+        }
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Get the fields that differ between `old` and this config, sorted by field name
+    ///
+    /// If `old` is `None` every field is reported as added. Values that are registered as secrets
+    /// are redacted.
+    pub fn diff(&self, old: Option<&ContainerConfig>) -> Vec<ConfigFieldChange> {
+        let new_fields = self.fields();
+        let old_fields = old.map(ContainerConfig::fields).unwrap_or_default();
+
+        let mut changes: Vec<ConfigFieldChange> = old_fields
+            .iter()
+            .filter(|(field, value)| new_fields.get(*field) != Some(value))
+            .map(|(field, value)| ConfigFieldChange {
+                field: field.clone(),
+                old: Some(crate::log::redact(value)),
+                new: new_fields.get(field).map(|x| crate::log::redact(x)),
+            })
+            .collect();
+        changes.extend(
+            new_fields
+                .into_iter()
+                .filter(|(field, _)| !old_fields.contains_key(field))
+                .map(|(field, value)| ConfigFieldChange {
+                    field,
+                    old: None,
+                    new: Some(crate::log::redact(&value)),
+                }),
+        );
+        changes.sort_by(|a, b| a.field.cmp(&b.field));
+
+        changes
+    }
+
+    /// Flatten the config into a map of field names to their values for diffing
+    fn fields(&self) -> BTreeMap<String, String> {
+        let mut fields = BTreeMap::new();
+
+        fields.insert("image".into(), self.image.clone());
+        if let Some(entrypoint) = &self.entrypoint {
+            fields.insert("entrypoint".into(), entrypoint.clone());
+        }
+        if let Some(command) = &self.command {
+            fields.insert("command".into(), command.join(" "));
+        }
         if let Some(network) = &self.network {
-            options.network_mode(network);
+            fields.insert("network".into(), network.clone());
+        }
+        if self.shared_network {
+            fields.insert("shared-network".into(), "true".into());
+        }
+        if !self.dns.is_empty() {
+            fields.insert("dns".into(), self.dns.join(" "));
+        }
+        for (hostname, address) in &self.extra_hosts {
+            fields.insert(format!("extra-host.{}", hostname), address.clone());
+        }
+        if !self.devices.is_empty() {
+            fields.insert("devices".into(), self.devices.join(" "));
+        }
+        if let Some(gpus) = &self.gpus {
+            fields.insert("gpus".into(), gpus.clone());
+        }
+        fields.insert("env-mode".into(), self.env_mode.as_ref().into());
+        fields.insert("restart-policy".into(), self.restart_policy.as_ref().into());
+        for (key, value) in &self.env_vars {
+            fields.insert(format!("env.{}", key), value.clone());
+        }
+        for (key, template) in &self.env_templates {
+            fields.insert(format!("env-template.{}", key), template.clone());
+        }
+        for (target, source) in &self.volumes {
+            fields.insert(
+                format!("volume.{}", target.0),
+                format!("{}{}", source.0, self.volume_mode_suffix(target)),
+            );
+        }
+        for (target, template) in &self.files {
+            fields.insert(format!("file.{}", target), template.clone());
+        }
+        for port in &self.ports {
+            fields.insert(
+                format!("port.{}/{}", port.host_port, port.protocol),
+                if self.private_ports.contains(port) {
+                    format!("{} (not opened)", port.container_port)
+                } else {
+                    port.container_port.to_string()
+                },
+            );
         }
 
-        // Add volumes
-        options.volumes(volumes.iter().map(AsRef::as_ref).collect());
-        // Add environment
-        options.env(env.iter().map(AsRef::as_ref).collect());
+        fields
+    }
+}
 
-        // TODO: Right now we will always add the "restart unless-stopped" flag, but we should
-        // parameterize this later.
-        options.restart_policy("unless-stopped", 0 /* Maximum retry count */);
+/// A change to one field of a container config
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ConfigFieldChange {
+    /// The name of the field, such as `image` or `env.PASSWORD`
+    pub field: String,
+    /// The old value of the field, or `None` if it was added
+    pub old: Option<String>,
+    /// The new value of the field, or `None` if it was removed
+    pub new: Option<String>,
+}
 
-        // Build options
-        Ok(options.build())
+/// Get the path on the host that a container's templated file is rendered to
+///
+/// The files of each container are kept in their own directory in the Lucky data dir, at the same
+/// path that they are mounted to in the container.
+pub(crate) fn rendered_file_path(
+    lucky_data_dir: &Path,
+    container_name: &str,
+    target: &str,
+) -> anyhow::Result<PathBuf> {
+    let target_path = Path::new(target);
+    if !target_path.is_absolute()
+        || target_path
+            .components()
+            .any(|x| x == std::path::Component::ParentDir)
+    {
+        bail!(
+            "The target of a container file must be an absolute path without `..`: {}",
+            target
+        );
+    }
+
+    Ok(rendered_files_dir(lucky_data_dir, container_name).join(target.trim_start_matches('/')))
+}
+
+/// Get the directory on the host that a container's templated files are rendered to
+pub(crate) fn rendered_files_dir(lucky_data_dir: &Path, container_name: &str) -> PathBuf {
+    lucky_data_dir.join(CONTAINER_FILE_DIR).join(container_name)
+}
+
+/// Get the path on the host of a device in the `host_path[:container_path][:permissions]` format,
+/// checking that the rest of the device is valid
+pub(crate) fn device_host_path(device: &str) -> anyhow::Result<&str> {
+    let parts: Vec<&str> = device.split(':').collect();
+    let is_permissions = |x: &str| !x.is_empty() && x.chars().all(|c| "rwm".contains(c));
+    let valid = match parts.as_slice() {
+        [host_path] => host_path.starts_with('/'),
+        [host_path, other] => {
+            host_path.starts_with('/') && (other.starts_with('/') || is_permissions(other))
+        }
+        [host_path, container_path, permissions] => {
+            host_path.starts_with('/')
+                && container_path.starts_with('/')
+                && is_permissions(permissions)
+        }
+        _ => false,
+    };
+    if !valid {
+        bail!(
+            "Invalid device {}: expected `host_path[:container_path][:permissions]` with absolute \
+            paths and permissions made of `r`, `w`, and `m`",
+            device
+        );
+    }
+
+    Ok(parts.first().copied().unwrap_or(""))
+}
+
+/// The GPUs to add to a container
+///
+/// Only NVIDIA GPUs can be added to containers, through the NVIDIA container toolkit.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum GpuRequest {
+    /// All of the GPUs on the host
+    All,
+    /// The given number of GPUs
+    Count(usize),
+    /// The GPUs with the given indexes or UUIDs
+    Devices(Vec<String>),
+}
+
+impl FromStr for GpuRequest {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().trim_matches('"');
+        if s == "all" {
+            Ok(GpuRequest::All)
+        } else if let Ok(count) = s.parse() {
+            if count == 0 {
+                bail!("The number of GPUs must be greater than 0");
+            }
+            Ok(GpuRequest::Count(count))
+        } else if s.starts_with("device=") {
+            let devices: Vec<String> = s
+                .trim_start_matches("device=")
+                .split(',')
+                .filter(|x| !x.is_empty())
+                .map(Into::into)
+                .collect();
+            if devices.is_empty() {
+                bail!("No GPU devices given in: {}", s);
+            }
+            Ok(GpuRequest::Devices(devices))
+        } else {
+            bail!(
+                "Invalid GPUs {}: expected `all`, a number of GPUs, or `device=` followed by a \
+                list of GPU indexes or UUIDs",
+                s
+            );
+        }
     }
 }
 
-/// Make sure Docker is installed an available
-pub(crate) fn ensure_docker() -> anyhow::Result<()> {
-    // Skip if docker is already installed
+impl fmt::Display for GpuRequest {
+    /// Format the request for the `--gpus` command line option
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GpuRequest::All => write!(f, "all"),
+            GpuRequest::Count(count) => write!(f, "{}", count),
+            // The list is quoted so that its commas aren't read as separate options
+            GpuRequest::Devices(devices) => write!(f, "\"device={}\"", devices.join(",")),
+        }
+    }
+}
+
+impl GpuRequest {
+    /// Make sure that the requested GPUs exist on the host
+    ///
+    /// GPUs given by UUID can't be looked up without the NVIDIA driver tools, so only GPUs given
+    /// by index are checked.
+    pub fn check_available(&self) -> anyhow::Result<()> {
+        if !Path::new("/dev/nvidiactl").exists() {
+            bail!("GPUs were requested but no NVIDIA GPUs were found on the host");
+        }
+
+        // Get the indexes of the GPUs from their device files, such as `/dev/nvidia0`
+        let indexes: HashSet<String> = fs::read_dir("/dev")
+            .context("Could not list devices")?
+            .filter_map(Result::ok)
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let index = name.trim_start_matches("nvidia");
+                if index.len() < name.len()
+                    && !index.is_empty()
+                    && index.chars().all(|c| c.is_ascii_digit())
+                {
+                    Some(index.to_string())
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        match self {
+            GpuRequest::All => (),
+            GpuRequest::Count(count) => {
+                if indexes.len() < *count {
+                    bail!(
+                        "{} GPUs were requested but the host only has {}",
+                        count,
+                        indexes.len()
+                    );
+                }
+            }
+            GpuRequest::Devices(devices) => {
+                for device in devices {
+                    if device.chars().all(|c| c.is_ascii_digit()) && !indexes.contains(device) {
+                        bail!("GPU {} does not exist on the host", device);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse volumes in the `source:target[:ro|:rw]` format into the source and whether the volume is
+/// read-only, keyed by target
+///
+/// The mode suffix is removed first and the rest is split at the last `:` so that
+/// `storage:<name>` sources can be used.
+fn parse_volumes(
+    volumes: &[String],
+) -> anyhow::Result<HashMap<VolumeTarget, (VolumeSource, bool)>> {
+    volumes
+        .iter()
+        .map(|volume| {
+            let (volume_path, read_only) = if let Some(path) = volume.strip_suffix(":ro") {
+                (path, true)
+            } else if let Some(path) = volume.strip_suffix(":rw") {
+                (path, false)
+            } else {
+                (volume.as_str(), false)
+            };
+            let mut parts = volume_path.rsplitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(target), Some(source)) if !source.is_empty() && !target.is_empty() => Ok((
+                    VolumeTarget(target.into()),
+                    (VolumeSource(source.into()), read_only),
+                )),
+                _ => bail!("Invalid volume in container spec: {}", volume),
+            }
+        })
+        .collect()
+}
+
+/// The oldest Docker API version that Lucky can use
+const MIN_DOCKER_API_VERSION: (u32, u32) = (1, 25);
+
+/// Make sure that a compatible Docker is installed and running
+///
+/// An existing Docker installation is used if there is one, otherwise Docker is installed from the
+/// given `channel`. `on_progress` is called with a description of each step so that it can be
+/// shown in the unit status.
+pub(crate) fn ensure_docker(
+    channel: DockerChannel,
+    on_progress: &mut dyn FnMut(&str) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    on_progress("Detecting Docker")?;
     if cmd_exists("docker", &["--version"])? {
+        // Start the Docker daemon if it isn't running
+        if docker_api_version().is_err() {
+            on_progress("Starting Docker")?;
+            let installed_channel = if run_cmd("snap", &["list", "docker"]).is_ok() {
+                DockerChannel::Snap
+            } else {
+                DockerChannel::Apt
+            };
+            run_cmd(
+                "systemctl",
+                &["start", docker_service_name(installed_channel)],
+            )?;
+        }
+        check_docker_api_version()?;
+
         return Ok(());
     };
 
     // Install Docker
-    // TODO: We need to figure out whether or not to use the Docker snap, which doesn't work on
-    // LXD. We will also want to support Centos, but we might just have to install different
-    // packages depending on the system.
-    run_cmd_with_retries(
-        "apt-get",
-        &["install", "-y", "docker.io"],
-        &Default::default(),
-    )?;
+    on_progress(&format!("Installing Docker from {}", channel.as_ref()))?;
+    match channel {
+        DockerChannel::Apt => run_cmd_with_retries(
+            "apt-get",
+            &["install", "-y", "docker.io"],
+            &Default::default(),
+        )?,
+        DockerChannel::Snap => {
+            run_cmd_with_retries("snap", &["install", "docker"], &Default::default())?
+        }
+    };
     // Make sure docker is installed
     if !cmd_exists("docker", &["--version"])? {
         bail!("Could not install Docker");
@@ -264,19 +1299,20 @@ pub(crate) fn ensure_docker() -> anyhow::Result<()> {
     // If there are any proxy settings
     if proxy_settings != "" {
         // Create the Docker service drop-in dir
-        let dropin_dir = "/etc/systemd/system/docker.service.d/";
-        fs::create_dir_all(dropin_dir).context(format!(
+        let dropin_dir = Path::new("/etc/systemd/system")
+            .join(format!("{}.service.d", docker_service_name(channel)));
+        fs::create_dir_all(&dropin_dir).context(format!(
             "Could not create docker service drop-in config dir: {:?}",
             dropin_dir
         ))?;
 
         // Open the drop-in file
-        let file_path = "/etc/systemd/system/docker.service.d/http-proxy.conf";
+        let file_path = dropin_dir.join("http-proxy.conf");
         let mut file = fs::OpenOptions::new()
             .write(true)
             .truncate(true)
             .create(true)
-            .open(file_path)
+            .open(&file_path)
             .context(format!(
                 "Could not open Docker service dropin file: {:?}",
                 file_path
@@ -289,8 +1325,49 @@ pub(crate) fn ensure_docker() -> anyhow::Result<()> {
         drop(file);
 
         // Reload Docker config
+        on_progress("Restarting Docker")?;
         run_cmd("systemctl", &["daemon-reload"])?;
-        run_cmd("systemctl", &["restart", "docker"])?;
+        run_cmd("systemctl", &["restart", docker_service_name(channel)])?;
+    }
+
+    check_docker_api_version()
+}
+
+/// Get the name of the systemd service that runs the Docker daemon when it is installed from
+/// the given channel
+fn docker_service_name(channel: DockerChannel) -> &'static str {
+    match channel {
+        DockerChannel::Apt => "docker",
+        DockerChannel::Snap => "snap.docker.dockerd",
+    }
+}
+
+/// Get the API version of the running Docker daemon
+fn docker_api_version() -> anyhow::Result<String> {
+    Ok(
+        run_cmd("docker", &["version", "--format", "{{.Server.APIVersion}}"])?
+            .trim()
+            .into(),
+    )
+}
+
+/// Make sure that the Docker daemon is running and that its API is new enough for Lucky
+fn check_docker_api_version() -> anyhow::Result<()> {
+    let version = docker_api_version().context("The Docker daemon is not responding")?;
+
+    let mut parts = version.split('.').map(str::parse::<u32>);
+    match (parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor))) => {
+            if (major, minor) < MIN_DOCKER_API_VERSION {
+                bail!(
+                    "The Docker API version {} is too old, Lucky requires version {}.{} or newer",
+                    version,
+                    MIN_DOCKER_API_VERSION.0,
+                    MIN_DOCKER_API_VERSION.1
+                );
+            }
+        }
+        _ => log::warn!("Could not parse the Docker API version: {}", version),
     }
 
     Ok(())
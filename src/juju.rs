@@ -7,198 +7,414 @@ use std::collections::HashMap;
 use std::io::Write;
 use std::process::Command;
 
-use crate::process::run_cmd;
-use crate::types::ScriptStatus;
-
-/// Set the Juju status
-///
-/// Returns the command output
-pub(crate) fn set_status(status: ScriptStatus) -> anyhow::Result<()> {
-    run_cmd(
-        "status-set",
-        &[
-            status.state.as_ref(),
-            &status.message.unwrap_or_else(|| "".into()),
-        ],
-    )?;
-
-    Ok(())
+use crate::process;
+use crate::types::{juju::JujuVersion, ScriptStatus};
+
+/// Juju controller API client
+#[cfg(feature = "juju-api")]
+mod api;
+#[cfg(feature = "juju-api")]
+pub(crate) use api::JujuApiClient;
+
+/// In-memory Juju backend used for testing charms against the daemon
+mod mock;
+pub(crate) use mock::{MockJujuBackend, MockJujuState, MockRelation};
+
+/// The status of the Juju model, as returned by the controller API
+#[cfg_attr(not(feature = "juju-api"), allow(dead_code))]
+pub(crate) struct ModelStatus {
+    pub name: String,
+    pub uuid: String,
+    /// The names of the applications in the model
+    pub applications: Vec<String>,
+    /// The application offers made from the model for cross-model relations
+    pub offers: Vec<ModelOffer>,
 }
 
-pub(crate) fn unit_get_private_address() -> anyhow::Result<String> {
-    Ok(run_cmd("unit-get", &["private-address"])?)
+/// An application offer for cross-model relations
+#[cfg_attr(not(feature = "juju-api"), allow(dead_code))]
+pub(crate) struct ModelOffer {
+    pub name: String,
+    /// The name of the offered application
+    pub application: String,
+    /// The names of the offered relation endpoints
+    pub endpoints: Vec<String>,
 }
 
-pub(crate) fn unit_get_public_address() -> anyhow::Result<String> {
-    Ok(run_cmd("unit-get", &["public-address"])?)
+pub(crate) struct SpecificRelation {
+    pub relation_id: String,
+    pub remote_unit: String,
 }
 
-pub(crate) fn config_get() -> anyhow::Result<HashMap<String, serde_json::Value>> {
-    let config_json = run_cmd("config-get", &["--format", "json", "--all"])?;
-    let config = serde_json::from_str(&config_json).context("Could not parse config json")?;
+/// The interface that the daemon uses to interact with Juju
+///
+/// All hook tool invocations go through this trait. The `ExecJujuBackend` runs the real hook
+/// tools, while the `MockJujuBackend` keeps everything in memory so that the daemon can be
+/// exercised without a live Juju environment.
+pub(crate) trait JujuBackend: Send + Sync {
+    /// Set the Juju status
+    fn set_status(&self, status: ScriptStatus) -> anyhow::Result<()>;
 
-    Ok(config)
-}
+    /// Detect the version of the Juju agent
+    fn juju_version(&self) -> anyhow::Result<JujuVersion>;
+
+    fn unit_get_private_address(&self) -> anyhow::Result<String>;
+
+    fn unit_get_public_address(&self) -> anyhow::Result<String>;
+
+    /// Get the address that the unit should bind to for the given endpoint binding
+    fn network_get_bind_address(&self, binding: &str) -> anyhow::Result<String>;
+
+    fn config_get(&self) -> anyhow::Result<HashMap<String, serde_json::Value>>;
+
+    fn open_port(&self, port_def: &str) -> anyhow::Result<()>;
+
+    fn close_port(&self, port_def: &str) -> anyhow::Result<()>;
+
+    /// Get the ports that Juju has recorded as opened for this unit
+    ///
+    /// The returned ports are normalized with `normalize_port`.
+    fn opened_ports(&self) -> anyhow::Result<Vec<String>>;
+
+    fn relation_set(
+        &self,
+        data: HashMap<String, String>,
+        relation_id: Option<String>,
+        app: bool,
+    ) -> anyhow::Result<()>;
 
-pub(crate) fn open_port(port_def: &str) -> anyhow::Result<String> {
-    Ok(run_cmd("open-port", &[port_def])?)
+    fn relation_get(
+        &self,
+        relation: Option<SpecificRelation>,
+        app: bool,
+    ) -> anyhow::Result<HashMap<String, String>>;
+
+    fn relation_list(&self, relation_id: Option<String>) -> anyhow::Result<Vec<String>>;
+
+    fn relation_ids(&self, relation_name: &str) -> anyhow::Result<Vec<String>>;
+
+    fn is_leader(&self) -> anyhow::Result<bool>;
+
+    fn leader_set(&self, data: HashMap<String, String>) -> anyhow::Result<()>;
+
+    fn leader_get(&self) -> anyhow::Result<HashMap<String, String>>;
+
+    fn resource_get(&self, resource_name: &str) -> anyhow::Result<String>;
+
+    /// Get the IDs of the attached storage instances with the given storage name
+    fn storage_list(&self, storage_name: &str) -> anyhow::Result<Vec<String>>;
+
+    /// Get the path that the given storage instance is mounted to on the host
+    fn storage_get_location(&self, storage_id: &str) -> anyhow::Result<String>;
+
+    /// Get the content of a Juju secret by its ID or label
+    ///
+    /// If `refresh` is `true`, Juju will be told to start tracking the latest revision of the
+    /// secret.
+    fn secret_get(
+        &self,
+        secret_id: Option<String>,
+        label: Option<String>,
+        refresh: bool,
+    ) -> anyhow::Result<HashMap<String, String>>;
+
+    /// Update the content of a Juju secret owned by this charm
+    fn secret_set(&self, secret_id: &str, data: HashMap<String, String>) -> anyhow::Result<()>;
+
+    /// Record a progress message for the running action
+    fn action_log(&self, message: &str) -> anyhow::Result<()>;
+
+    /// Mark the running action as failed with the given message
+    fn action_fail(&self, message: &str) -> anyhow::Result<()>;
 }
 
-pub(crate) fn close_port(port_def: &str) -> anyhow::Result<String> {
-    Ok(run_cmd("close-port", &[port_def])?)
+/// A `JujuBackend` that runs the Juju hook tools
+pub(crate) struct ExecJujuBackend;
+
+/// Run a Juju hook tool, recording the call in a trace span
+fn run_cmd(command: &str, args: &[&str]) -> anyhow::Result<String> {
+    crate::trace::in_span(
+        crate::trace::Span::start(command).with_attr("juju.hook_tool", command),
+        || process::run_cmd(command, args),
+    )
 }
 
-pub(crate) fn opened_ports() -> anyhow::Result<Vec<String>> {
-    Ok(
-        serde_json::from_str(&run_cmd("opened-ports", &["--format", "json"])?)
-            .context("Could not parse json output of `opened-ports` command")?,
+/// Run a Juju hook tool with a list of owned arguments
+fn run_cmd_owned(command: &str, args: &[String]) -> anyhow::Result<String> {
+    run_cmd(
+        command,
+        args.iter()
+            .map(AsRef::as_ref)
+            .collect::<Vec<&str>>()
+            .as_slice(),
     )
 }
 
-pub(crate) fn relation_set(
-    data: HashMap<String, String>,
-    relation_id: Option<String>,
-    app: bool,
-) -> anyhow::Result<()> {
-    let mut args: Vec<String> = vec![];
+impl JujuBackend for ExecJujuBackend {
+    fn set_status(&self, status: ScriptStatus) -> anyhow::Result<()> {
+        run_cmd(
+            "status-set",
+            &[
+                status.state.as_ref(),
+                &status.message.unwrap_or_else(|| "".into()),
+            ],
+        )?;
+
+        Ok(())
+    }
 
-    if app {
-        args.push("--app".into());
+    /// The version is taken from the `JUJU_VERSION` environment variable that Juju sets in the
+    /// hook environment. If that is not set, we fall back to running `jujud --version`.
+    fn juju_version(&self) -> anyhow::Result<JujuVersion> {
+        let version = if let Ok(version) = std::env::var("JUJU_VERSION") {
+            version
+        } else {
+            process::run_cmd("jujud", &["--version"])
+                .context("Could not get the version of jujud")?
+        };
+
+        Ok(version.parse()?)
     }
 
-    // Add relation option if specified
-    if let Some(relation_id) = relation_id {
-        args.push("-r".into());
-        args.push(relation_id);
+    fn unit_get_private_address(&self) -> anyhow::Result<String> {
+        Ok(run_cmd("unit-get", &["private-address"])?)
     }
 
-    // Add data
-    for (k, v) in data {
-        args.push(format!("{}={}", k, v));
+    fn unit_get_public_address(&self) -> anyhow::Result<String> {
+        Ok(run_cmd("unit-get", &["public-address"])?)
     }
 
-    run_cmd(
-        "relation-set",
-        args.iter()
-            .map(AsRef::as_ref)
-            .collect::<Vec<&str>>()
-            .as_slice(),
-    )?;
+    fn network_get_bind_address(&self, binding: &str) -> anyhow::Result<String> {
+        Ok(run_cmd("network-get", &[binding, "--bind-address"])?)
+    }
 
-    Ok(())
-}
+    fn config_get(&self) -> anyhow::Result<HashMap<String, serde_json::Value>> {
+        let config_json = run_cmd("config-get", &["--format", "json", "--all"])?;
+        let config = serde_json::from_str(&config_json).context("Could not parse config json")?;
 
-pub(crate) struct SpecificRelation {
-    pub relation_id: String,
-    pub remote_unit: String,
-}
+        Ok(config)
+    }
 
-pub(crate) fn relation_get(
-    relation: Option<SpecificRelation>,
-    app: bool,
-) -> anyhow::Result<HashMap<String, String>> {
-    let mut args: Vec<String> = vec!["--format".into(), "json".into()];
+    fn open_port(&self, port_def: &str) -> anyhow::Result<()> {
+        run_cmd("open-port", &[port_def])?;
 
-    if app {
-        args.push("--app".into());
+        Ok(())
     }
 
-    // Add relation id
-    if let Some(relation) = relation {
-        args.append(&mut vec![
-            "-r".into(),
-            relation.relation_id,
-            "-".into(),
-            relation.remote_unit,
-        ]);
+    fn close_port(&self, port_def: &str) -> anyhow::Result<()> {
+        run_cmd("close-port", &[port_def])?;
+
+        Ok(())
     }
 
-    // Run command
-    let output = run_cmd(
-        "relation-get",
-        args.iter()
-            .map(AsRef::as_ref)
-            .collect::<Vec<&str>>()
-            .as_slice(),
-    )?;
+    fn opened_ports(&self) -> anyhow::Result<Vec<String>> {
+        let ports: Vec<String> =
+            serde_json::from_str(&run_cmd("opened-ports", &["--format", "json"])?)
+                .context("Could not parse json output of `opened-ports` command")?;
 
-    // Parse output
-    Ok(serde_json::from_str(&output).context("Could not parse JSON response")?)
-}
+        Ok(ports.iter().map(|x| normalize_port(x)).collect())
+    }
 
-pub(crate) fn relation_list(relation_id: Option<String>) -> anyhow::Result<Vec<String>> {
-    let mut args: Vec<String> = vec!["--format".into(), "json".into()];
+    fn relation_set(
+        &self,
+        data: HashMap<String, String>,
+        relation_id: Option<String>,
+        app: bool,
+    ) -> anyhow::Result<()> {
+        let mut args: Vec<String> = vec![];
 
-    // Add relation id
-    if let Some(relation_id) = relation_id {
-        args.append(&mut vec!["-r".into(), relation_id]);
+        if app {
+            args.push("--app".into());
+        }
+
+        // Add relation option if specified
+        if let Some(relation_id) = relation_id {
+            args.push("-r".into());
+            args.push(relation_id);
+        }
+
+        // Add data
+        for (k, v) in data {
+            args.push(format!("{}={}", k, v));
+        }
+
+        run_cmd_owned("relation-set", &args)?;
+
+        Ok(())
     }
 
-    // Run command
-    let output = run_cmd(
-        "relation-list",
-        args.iter()
-            .map(AsRef::as_ref)
-            .collect::<Vec<&str>>()
-            .as_slice(),
-    )?;
+    fn relation_get(
+        &self,
+        relation: Option<SpecificRelation>,
+        app: bool,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        let mut args: Vec<String> = vec!["--format".into(), "json".into()];
 
-    // Parse output
-    Ok(serde_json::from_str(&output).context("Could not parse JSON")?)
-}
+        if app {
+            args.push("--app".into());
+        }
+
+        // Add relation id
+        if let Some(relation) = relation {
+            args.append(&mut vec![
+                "-r".into(),
+                relation.relation_id,
+                "-".into(),
+                relation.remote_unit,
+            ]);
+        }
 
-pub(crate) fn relation_ids(relation_name: &str) -> anyhow::Result<Vec<String>> {
-    // Run command
-    let output = run_cmd("relation-ids", &["--format", "json", relation_name])?;
+        // Run command
+        let output = run_cmd_owned("relation-get", &args)?;
 
-    // Parse output
-    Ok(serde_json::from_str(&output).context("Could not parse JSON")?)
-}
+        // Parse output
+        Ok(serde_json::from_str(&output).context("Could not parse JSON response")?)
+    }
+
+    fn relation_list(&self, relation_id: Option<String>) -> anyhow::Result<Vec<String>> {
+        let mut args: Vec<String> = vec!["--format".into(), "json".into()];
 
-pub(crate) fn is_leader() -> anyhow::Result<bool> {
-    // Run command
-    let output = run_cmd("is-leader", &[])?;
-
-    // Parse output
-    match output.trim() {
-        "True" => Ok(true),
-        "False" => Ok(false),
-        other => {
-            Err(format_err!("Unexpected response: {}", other)
-                .context("Error running `is-leader` tool"))
+        // Add relation id
+        if let Some(relation_id) = relation_id {
+            args.append(&mut vec!["-r".into(), relation_id]);
         }
+
+        // Run command
+        let output = run_cmd_owned("relation-list", &args)?;
+
+        // Parse output
+        Ok(serde_json::from_str(&output).context("Could not parse JSON")?)
     }
-}
 
-pub(crate) fn leader_set(data: HashMap<String, String>) -> anyhow::Result<()> {
-    let mut args: Vec<String> = vec![];
+    fn relation_ids(&self, relation_name: &str) -> anyhow::Result<Vec<String>> {
+        // Run command
+        let output = run_cmd("relation-ids", &["--format", "json", relation_name])?;
 
-    // Add data
-    for (k, v) in data {
-        args.push(format!("{}={}", k, v));
+        // Parse output
+        Ok(serde_json::from_str(&output).context("Could not parse JSON")?)
     }
 
-    run_cmd(
-        "leader-set",
-        args.iter()
-            .map(AsRef::as_ref)
-            .collect::<Vec<&str>>()
-            .as_slice(),
-    )?;
+    fn is_leader(&self) -> anyhow::Result<bool> {
+        // Run command
+        let output = run_cmd("is-leader", &[])?;
 
-    Ok(())
-}
+        // Parse output
+        match output.trim() {
+            "True" => Ok(true),
+            "False" => Ok(false),
+            other => Err(format_err!("Unexpected response: {}", other)
+                .context("Error running `is-leader` tool")),
+        }
+    }
+
+    fn leader_set(&self, data: HashMap<String, String>) -> anyhow::Result<()> {
+        let mut args: Vec<String> = vec![];
+
+        // Add data
+        for (k, v) in data {
+            args.push(format!("{}={}", k, v));
+        }
+
+        run_cmd_owned("leader-set", &args)?;
+
+        Ok(())
+    }
+
+    fn leader_get(&self) -> anyhow::Result<HashMap<String, String>> {
+        // Run command
+        let output = run_cmd("leader-get", &["--format", "json"])?;
+
+        // Parse output
+        Ok(serde_json::from_str(&output).context("Could not parse JSON response")?)
+    }
+
+    fn resource_get(&self, resource_name: &str) -> anyhow::Result<String> {
+        Ok(run_cmd("resource-get", &[resource_name])?.trim().into())
+    }
 
-pub(crate) fn leader_get() -> anyhow::Result<HashMap<String, String>> {
-    // Run command
-    let output = run_cmd("leader-get", &["--format", "json"])?;
+    fn storage_list(&self, storage_name: &str) -> anyhow::Result<Vec<String>> {
+        // Run command
+        let output = run_cmd("storage-list", &["--format", "json", storage_name])?;
 
-    // Parse output
-    Ok(serde_json::from_str(&output).context("Could not parse JSON response")?)
+        // Parse output
+        Ok(serde_json::from_str(&output).context("Could not parse JSON")?)
+    }
+
+    fn storage_get_location(&self, storage_id: &str) -> anyhow::Result<String> {
+        Ok(run_cmd("storage-get", &["-s", storage_id, "location"])?
+            .trim()
+            .into())
+    }
+
+    fn secret_get(
+        &self,
+        secret_id: Option<String>,
+        label: Option<String>,
+        refresh: bool,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        let mut args: Vec<String> = vec!["--format".into(), "json".into()];
+
+        if refresh {
+            args.push("--refresh".into());
+        }
+
+        // Add the secret label if specified
+        if let Some(label) = label {
+            args.push("--label".into());
+            args.push(label);
+        }
+
+        // Add the secret id if specified
+        if let Some(secret_id) = secret_id {
+            args.push(secret_id);
+        }
+
+        // Run command
+        let output = run_cmd_owned("secret-get", &args)?;
+
+        // Parse output
+        Ok(serde_json::from_str(&output).context("Could not parse JSON response")?)
+    }
+
+    fn secret_set(&self, secret_id: &str, data: HashMap<String, String>) -> anyhow::Result<()> {
+        let mut args: Vec<String> = vec![secret_id.into()];
+
+        // Add data
+        for (k, v) in data {
+            args.push(format!("{}={}", k, v));
+        }
+
+        run_cmd_owned("secret-set", &args)?;
+
+        Ok(())
+    }
+
+    fn action_log(&self, message: &str) -> anyhow::Result<()> {
+        run_cmd("action-log", &[message])?;
+
+        Ok(())
+    }
+
+    fn action_fail(&self, message: &str) -> anyhow::Result<()> {
+        run_cmd("action-fail", &[message])?;
+
+        Ok(())
+    }
 }
 
-pub(crate) fn resource_get(resource_name: &str) -> anyhow::Result<String> {
-    Ok(run_cmd("resource-get", &[resource_name])?.trim().into())
+/// Normalize a port definition such as `80` or `8000-9000/UDP` to the `port-or-range/protocol`
+/// format that is returned by `opened-ports`
+///
+/// Ports without a protocol are assumed to be TCP, which matches the behavior of `open-port`.
+/// `icmp` has no port and is returned as it is.
+pub(crate) fn normalize_port(port_def: &str) -> String {
+    let port_def = port_def.trim().to_lowercase();
+
+    if port_def.contains('/') || port_def == "icmp" {
+        port_def
+    } else {
+        format!("{}/tcp", port_def)
+    }
 }
 
 /// Write out a message to the Juju Log. Setting `debug` to `true` will tell Juju the log is a
@@ -210,11 +426,17 @@ pub(crate) fn resource_get(resource_name: &str) -> anyhow::Result<String> {
 ///
 /// This function blocks until the command exits.
 pub(crate) fn juju_log(message: &str, debug: bool) {
+    juju_log_with_level(message, if debug { "DEBUG" } else { "INFO" });
+}
+
+/// Write out a message to the Juju Log with the given level: `DEBUG`, `INFO`, `WARNING`, or
+/// `ERROR`.
+///
+/// Errors are handled the same way as they are by `juju_log()`.
+pub(crate) fn juju_log_with_level(message: &str, level: &str) {
     // build the juju-log command
     let mut cmd = Command::new("juju-log");
-    if debug {
-        cmd.arg("--debug");
-    }
+    cmd.args(&["--log-level", level]);
     cmd.arg(&message);
 
     // Run command and awit for it to exit
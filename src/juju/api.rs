@@ -0,0 +1,246 @@
+//! A minimal client for the Juju controller API
+//!
+//! The controller API is a JSON RPC protocol served over a TLS websocket. The client logs in with
+//! the unit agent's credentials from its `agent.conf`, which limits it to the facades that the
+//! unit agent is allowed to call.
+
+use anyhow::{format_err, Context};
+use native_tls::{Certificate, TlsConnector, TlsStream};
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use tungstenite::{Message, WebSocket};
+
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::{ModelOffer, ModelStatus};
+
+/// The amount of time to wait when connecting to a controller
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The server name in the certificates generated by Juju for the API server
+const API_SERVER_NAME: &str = "juju-apiserver";
+
+/// The parts of a Juju agent's `agent.conf` file needed to connect to the controller
+#[derive(Deserialize)]
+struct AgentConf {
+    /// The agent tag, such as `unit-my-app-0`
+    tag: String,
+    /// The model tag, such as `model-<uuid>`
+    model: String,
+    /// The `host:port` addresses of the controller API servers
+    apiaddresses: Vec<String>,
+    /// The agent's password for the API
+    apipassword: String,
+    /// The PEM encoded CA certificate for the controller
+    cacert: String,
+    /// The nonce that machine agents have to provide when logging in
+    #[serde(default)]
+    nonce: Option<String>,
+}
+
+/// A logged in connection to the Juju controller API
+pub(crate) struct JujuApiClient {
+    socket: WebSocket<TlsStream<TcpStream>>,
+    model_uuid: String,
+    request_id: u64,
+}
+
+impl JujuApiClient {
+    /// Connect to the controller and log in using the credentials of the current unit's agent
+    pub fn connect() -> anyhow::Result<Self> {
+        let unit_name =
+            std::env::var("JUJU_UNIT_NAME").context("Env var JUJU_UNIT_NAME not readable!")?;
+        let agent_conf_path = PathBuf::from("/var/lib/juju/agents")
+            .join(format!("unit-{}", unit_name.replace("/", "-")))
+            .join("agent.conf");
+        let agent_conf: AgentConf = serde_yaml::from_str(
+            &std::fs::read_to_string(&agent_conf_path)
+                .context(format!("Could not read agent conf: {:?}", agent_conf_path))?,
+        )
+        .context(format!("Could not parse agent conf: {:?}", agent_conf_path))?;
+
+        let model_uuid = agent_conf
+            .model
+            .strip_prefix("model-")
+            .unwrap_or(&agent_conf.model)
+            .to_owned();
+
+        let connector = TlsConnector::builder()
+            .add_root_certificate(
+                Certificate::from_pem(agent_conf.cacert.as_bytes())
+                    .context("Could not parse controller CA certificate")?,
+            )
+            .build()?;
+
+        // Try each of the API addresses until one of them connects
+        let mut last_error = format_err!("No API addresses in agent conf");
+        for address in &agent_conf.apiaddresses {
+            match connect_websocket(&connector, address, &model_uuid) {
+                Ok(socket) => {
+                    let mut client = JujuApiClient {
+                        socket,
+                        model_uuid,
+                        request_id: 0,
+                    };
+                    client.login(&agent_conf)?;
+
+                    return Ok(client);
+                }
+                Err(e) => {
+                    log::debug!("Could not connect to Juju API at {}: {:?}", address, e);
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(last_error.context("Could not connect to the Juju controller API"))
+    }
+
+    /// Log in with the agent credentials
+    fn login(&mut self, agent_conf: &AgentConf) -> anyhow::Result<()> {
+        // Keep the agent password out of the logs
+        crate::log::add_redacted_value(&agent_conf.apipassword);
+
+        let mut params = json!({
+            "auth-tag": agent_conf.tag,
+            "credentials": agent_conf.apipassword,
+        });
+        if let (Some(nonce), Some(fields)) = (&agent_conf.nonce, params.as_object_mut()) {
+            fields.insert("nonce".into(), nonce.as_str().into());
+        }
+
+        self.call("Admin", 3, "Login", params)
+            .context("Could not log in to the Juju controller API")?;
+
+        Ok(())
+    }
+
+    /// Call a method on the API and return the response
+    fn call(
+        &mut self,
+        facade: &str,
+        version: u32,
+        method: &str,
+        params: JsonValue,
+    ) -> anyhow::Result<JsonValue> {
+        self.request_id += 1;
+        let request = json!({
+            "request-id": self.request_id,
+            "type": facade,
+            "version": version,
+            "request": method,
+            "params": params,
+        });
+
+        self.socket
+            .write_message(Message::Text(request.to_string()))
+            .context("Could not send request to Juju API")?;
+
+        loop {
+            let message = self
+                .socket
+                .read_message()
+                .context("Could not read response from Juju API")?;
+
+            // Ignore ping and pong messages
+            let text = match message {
+                Message::Text(text) => text,
+                Message::Close(_) => anyhow::bail!("Juju API closed the connection"),
+                _ => continue,
+            };
+
+            let mut response: JsonValue =
+                serde_json::from_str(&text).context("Could not parse Juju API response")?;
+
+            if response.get("request-id").and_then(JsonValue::as_u64) != Some(self.request_id) {
+                continue;
+            }
+
+            if let Some(error) = response.get("error").and_then(JsonValue::as_str) {
+                anyhow::bail!("Juju API error calling {}.{}: {}", facade, method, error);
+            }
+
+            return Ok(response
+                .get_mut("response")
+                .map(JsonValue::take)
+                .unwrap_or_default());
+        }
+    }
+
+    /// Get the status of the model
+    pub fn model_status(&mut self) -> anyhow::Result<ModelStatus> {
+        let status = self.call("Client", 2, "FullStatus", json!({ "patterns": [] }))?;
+
+        let mut applications: Vec<String> = status
+            .get("applications")
+            .and_then(JsonValue::as_object)
+            .map(|x| x.keys().cloned().collect())
+            .unwrap_or_default();
+        applications.sort();
+
+        let mut offers: Vec<ModelOffer> = status
+            .get("offers")
+            .and_then(JsonValue::as_object)
+            .map(|offers| {
+                offers
+                    .values()
+                    .map(|offer| ModelOffer {
+                        name: str_field(offer, "offer-name").into(),
+                        application: str_field(offer, "application-name").into(),
+                        endpoints: offer
+                            .get("endpoints")
+                            .and_then(JsonValue::as_object)
+                            .map(|x| x.keys().cloned().collect())
+                            .unwrap_or_default(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        offers.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(ModelStatus {
+            name: status
+                .get("model")
+                .map_or("", |model| str_field(model, "name"))
+                .into(),
+            uuid: self.model_uuid.clone(),
+            applications,
+            offers,
+        })
+    }
+}
+
+/// Open the API websocket for the model on the given controller address
+fn connect_websocket(
+    connector: &TlsConnector,
+    address: &str,
+    model_uuid: &str,
+) -> anyhow::Result<WebSocket<TlsStream<TcpStream>>> {
+    let socket_address = address
+        .to_socket_addrs()
+        .context(format!("Could not resolve address: {}", address))?
+        .next()
+        .ok_or_else(|| format_err!("Could not resolve address: {}", address))?;
+
+    let stream = TcpStream::connect_timeout(&socket_address, CONNECT_TIMEOUT)
+        .context(format!("Could not connect to {}", address))?;
+    let stream = connector
+        .connect(API_SERVER_NAME, stream)
+        .map_err(|e| format_err!("TLS handshake failed: {}", e))?;
+
+    let url = format!("wss://{}/model/{}/api", address, model_uuid);
+    let (socket, _) = tungstenite::client(url.as_str(), stream)
+        .map_err(|e| format_err!("Websocket handshake failed: {}", e))?;
+
+    Ok(socket)
+}
+
+/// Get a string field of a JSON object, or an empty string if it is missing
+fn str_field<'a>(value: &'a JsonValue, key: &str) -> &'a str {
+    value
+        .get(key)
+        .and_then(JsonValue::as_str)
+        .unwrap_or_default()
+}
@@ -0,0 +1,367 @@
+//! An in-memory `JujuBackend` that can be used to run the daemon without a live Juju environment
+
+use anyhow::format_err;
+use serde_json::Value as JsonValue;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+
+use super::{normalize_port, JujuBackend, SpecificRelation};
+use crate::types::{juju::JujuVersion, ScriptStatus};
+
+/// The data for one relation in the `MockJujuBackend`
+#[derive(Default, Debug, Clone)]
+pub(crate) struct MockRelation {
+    /// The relation name, such as `db`
+    pub name: String,
+    /// The relation data for each of the remote units
+    pub remote_units: HashMap<String, HashMap<String, String>>,
+    /// The remote application's relation data
+    pub remote_app_data: HashMap<String, String>,
+    /// The relation data set by this unit
+    pub local_unit_data: HashMap<String, String>,
+    /// The application relation data set by this unit
+    pub local_app_data: HashMap<String, String>,
+}
+
+/// The state of the mock Juju environment
+#[derive(Debug)]
+pub(crate) struct MockJujuState {
+    pub version: JujuVersion,
+    /// The last status that was set
+    pub status: Option<ScriptStatus>,
+    pub private_address: String,
+    pub public_address: String,
+    pub config: HashMap<String, JsonValue>,
+    pub opened_ports: HashSet<String>,
+    /// The relations, keyed by relation ID such as `db:1`
+    pub relations: HashMap<String, MockRelation>,
+    pub is_leader: bool,
+    pub leader_data: HashMap<String, String>,
+    /// The paths to the resources, keyed by resource name
+    pub resources: HashMap<String, String>,
+    /// The locations of the attached storage instances, keyed by storage ID such as `data/0`
+    pub storage: HashMap<String, String>,
+    /// The secret contents, keyed by secret ID
+    pub secrets: HashMap<String, HashMap<String, String>>,
+    /// The secret IDs, keyed by secret label
+    pub secret_labels: HashMap<String, String>,
+    /// The messages logged by the running action, in order
+    pub action_logs: Vec<String>,
+    /// The failure message of the running action, if it has failed
+    pub action_failure: Option<String>,
+    /// The names of every hook tool that has been called, in order
+    pub calls: Vec<String>,
+}
+
+impl Default for MockJujuState {
+    fn default() -> Self {
+        MockJujuState {
+            version: JujuVersion::new(3, 1, 0),
+            status: None,
+            private_address: "10.0.0.2".into(),
+            public_address: "10.0.0.2".into(),
+            config: Default::default(),
+            opened_ports: Default::default(),
+            relations: Default::default(),
+            is_leader: true,
+            leader_data: Default::default(),
+            resources: Default::default(),
+            storage: Default::default(),
+            secrets: Default::default(),
+            secret_labels: Default::default(),
+            action_logs: Default::default(),
+            action_failure: None,
+            calls: Default::default(),
+        }
+    }
+}
+
+/// A `JujuBackend` that keeps all of its data in memory instead of calling the Juju hook tools
+///
+/// The mock environment can be set up and inspected through `state()`.
+#[derive(Default)]
+pub(crate) struct MockJujuBackend {
+    state: Mutex<MockJujuState>,
+}
+
+impl MockJujuBackend {
+    /// Create a mock backend with the given initial state
+    pub fn new(state: MockJujuState) -> Self {
+        MockJujuBackend {
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Get a handle to the state of the mock Juju environment
+    pub fn state(&self) -> MutexGuard<MockJujuState> {
+        self.state.lock().unwrap()
+    }
+
+    /// Lock the state and record a call to the given hook tool
+    fn call(&self, hook_tool: &str) -> MutexGuard<MockJujuState> {
+        let mut state = self.state();
+        state.calls.push(hook_tool.into());
+        state
+    }
+}
+
+/// Get the ID of the relation that the current hook is running for
+fn current_relation_id() -> anyhow::Result<String> {
+    std::env::var("JUJU_RELATION_ID").map_err(|_| format_err!("No relation ID specified"))
+}
+
+impl JujuBackend for MockJujuBackend {
+    fn set_status(&self, status: ScriptStatus) -> anyhow::Result<()> {
+        self.call("status-set").status = Some(status);
+        Ok(())
+    }
+
+    fn juju_version(&self) -> anyhow::Result<JujuVersion> {
+        Ok(self.state().version)
+    }
+
+    fn unit_get_private_address(&self) -> anyhow::Result<String> {
+        Ok(self.call("unit-get").private_address.clone())
+    }
+
+    fn unit_get_public_address(&self) -> anyhow::Result<String> {
+        Ok(self.call("unit-get").public_address.clone())
+    }
+
+    fn network_get_bind_address(&self, _binding: &str) -> anyhow::Result<String> {
+        Ok(self.call("network-get").private_address.clone())
+    }
+
+    fn config_get(&self) -> anyhow::Result<HashMap<String, JsonValue>> {
+        Ok(self.call("config-get").config.clone())
+    }
+
+    fn open_port(&self, port_def: &str) -> anyhow::Result<()> {
+        self.call("open-port")
+            .opened_ports
+            .insert(normalize_port(port_def));
+        Ok(())
+    }
+
+    fn close_port(&self, port_def: &str) -> anyhow::Result<()> {
+        self.call("close-port")
+            .opened_ports
+            .remove(&normalize_port(port_def));
+        Ok(())
+    }
+
+    fn opened_ports(&self) -> anyhow::Result<Vec<String>> {
+        Ok(self
+            .call("opened-ports")
+            .opened_ports
+            .iter()
+            .cloned()
+            .collect())
+    }
+
+    fn relation_set(
+        &self,
+        data: HashMap<String, String>,
+        relation_id: Option<String>,
+        app: bool,
+    ) -> anyhow::Result<()> {
+        let mut state = self.call("relation-set");
+        let relation_id = relation_id.map_or_else(current_relation_id, Ok)?;
+
+        if app && !state.is_leader {
+            anyhow::bail!("Cannot write application relation data: unit is not the leader");
+        }
+
+        let relation = state
+            .relations
+            .get_mut(&relation_id)
+            .ok_or_else(|| format_err!("Relation not found: {}", relation_id))?;
+        let relation_data = if app {
+            &mut relation.local_app_data
+        } else {
+            &mut relation.local_unit_data
+        };
+
+        for (k, v) in data {
+            // Setting a value to an empty string removes it
+            if v.is_empty() {
+                relation_data.remove(&k);
+            } else {
+                relation_data.insert(k, v);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn relation_get(
+        &self,
+        relation: Option<SpecificRelation>,
+        app: bool,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        let state = self.call("relation-get");
+        let (relation_id, remote_unit) = match relation {
+            Some(relation) => (relation.relation_id, relation.remote_unit),
+            None => (
+                current_relation_id()?,
+                std::env::var("JUJU_REMOTE_UNIT")
+                    .map_err(|_| format_err!("No remote unit specified"))?,
+            ),
+        };
+
+        let relation = state
+            .relations
+            .get(&relation_id)
+            .ok_or_else(|| format_err!("Relation not found: {}", relation_id))?;
+
+        if app {
+            // Units can read their own application's data on peer relations
+            let is_own_app = std::env::var("JUJU_UNIT_NAME")
+                .map(|unit| unit.split('/').next() == remote_unit.split('/').next())
+                .unwrap_or(false);
+
+            if is_own_app {
+                Ok(relation.local_app_data.clone())
+            } else {
+                Ok(relation.remote_app_data.clone())
+            }
+        } else {
+            relation
+                .remote_units
+                .get(&remote_unit)
+                .cloned()
+                .ok_or_else(|| format_err!("Unit not found in relation: {}", remote_unit))
+        }
+    }
+
+    fn relation_list(&self, relation_id: Option<String>) -> anyhow::Result<Vec<String>> {
+        let state = self.call("relation-list");
+        let relation_id = relation_id.map_or_else(current_relation_id, Ok)?;
+
+        let mut units: Vec<String> = state
+            .relations
+            .get(&relation_id)
+            .ok_or_else(|| format_err!("Relation not found: {}", relation_id))?
+            .remote_units
+            .keys()
+            .cloned()
+            .collect();
+        units.sort();
+
+        Ok(units)
+    }
+
+    fn relation_ids(&self, relation_name: &str) -> anyhow::Result<Vec<String>> {
+        let mut ids: Vec<String> = self
+            .call("relation-ids")
+            .relations
+            .iter()
+            .filter(|(_, relation)| relation.name == relation_name)
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.sort();
+
+        Ok(ids)
+    }
+
+    fn is_leader(&self) -> anyhow::Result<bool> {
+        Ok(self.call("is-leader").is_leader)
+    }
+
+    fn leader_set(&self, data: HashMap<String, String>) -> anyhow::Result<()> {
+        let mut state = self.call("leader-set");
+
+        if !state.is_leader {
+            anyhow::bail!("Cannot write leader settings: unit is not the leader");
+        }
+
+        for (k, v) in data {
+            // Setting a value to an empty string removes it
+            if v.is_empty() {
+                state.leader_data.remove(&k);
+            } else {
+                state.leader_data.insert(k, v);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn leader_get(&self) -> anyhow::Result<HashMap<String, String>> {
+        Ok(self.call("leader-get").leader_data.clone())
+    }
+
+    fn resource_get(&self, resource_name: &str) -> anyhow::Result<String> {
+        self.call("resource-get")
+            .resources
+            .get(resource_name)
+            .cloned()
+            .ok_or_else(|| format_err!("Resource not found: {}", resource_name))
+    }
+
+    fn storage_list(&self, storage_name: &str) -> anyhow::Result<Vec<String>> {
+        let prefix = format!("{}/", storage_name);
+        let mut ids: Vec<String> = self
+            .call("storage-list")
+            .storage
+            .keys()
+            .filter(|id| id.starts_with(&prefix))
+            .cloned()
+            .collect();
+        ids.sort();
+
+        Ok(ids)
+    }
+
+    fn storage_get_location(&self, storage_id: &str) -> anyhow::Result<String> {
+        self.call("storage-get")
+            .storage
+            .get(storage_id)
+            .cloned()
+            .ok_or_else(|| format_err!("Storage not found: {}", storage_id))
+    }
+
+    fn secret_get(
+        &self,
+        secret_id: Option<String>,
+        label: Option<String>,
+        _refresh: bool,
+    ) -> anyhow::Result<HashMap<String, String>> {
+        let state = self.call("secret-get");
+
+        let secret_id = match (secret_id, label) {
+            (Some(secret_id), _) => secret_id,
+            (None, Some(label)) => state
+                .secret_labels
+                .get(&label)
+                .cloned()
+                .ok_or_else(|| format_err!("Secret not found with label: {}", label))?,
+            (None, None) => anyhow::bail!("A secret ID or label must be specified"),
+        };
+
+        state
+            .secrets
+            .get(&secret_id)
+            .cloned()
+            .ok_or_else(|| format_err!("Secret not found: {}", secret_id))
+    }
+
+    fn secret_set(&self, secret_id: &str, data: HashMap<String, String>) -> anyhow::Result<()> {
+        self.call("secret-set")
+            .secrets
+            .insert(secret_id.into(), data);
+
+        Ok(())
+    }
+
+    fn action_log(&self, message: &str) -> anyhow::Result<()> {
+        self.call("action-log").action_logs.push(message.into());
+        Ok(())
+    }
+
+    fn action_fail(&self, message: &str) -> anyhow::Result<()> {
+        self.call("action-fail").action_failure = Some(message.into());
+        Ok(())
+    }
+}
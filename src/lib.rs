@@ -27,15 +27,27 @@ pub(crate) mod types;
 
 // Daemon only modules
 #[cfg(feature = "daemon")]
+pub(crate) mod container_engine;
+#[cfg(feature = "daemon")]
+pub(crate) mod containerd;
+#[cfg(feature = "daemon")]
 pub(crate) mod daemon;
 #[cfg(feature = "daemon")]
 pub(crate) mod docker;
 #[cfg(feature = "daemon")]
 pub(crate) mod juju;
 #[cfg(feature = "daemon")]
+pub(crate) mod pebble;
+#[cfg(feature = "daemon")]
+pub(crate) mod podman;
+#[cfg(feature = "daemon")]
 pub(crate) mod process;
 #[cfg(feature = "daemon")]
 pub(crate) mod rt;
+#[cfg(feature = "daemon")]
+pub(crate) mod systemd;
+#[cfg(feature = "daemon")]
+pub(crate) mod trace;
 
 /// Lucky version from environment var
 ///
@@ -2,6 +2,7 @@
 use lazy_static::lazy_static;
 use log::{Level, LevelFilter, Metadata, Record};
 
+use std::collections::HashSet;
 use std::fmt::Write as FmtWrite;
 use std::fs::File;
 use std::io::Write;
@@ -15,12 +16,22 @@ use crate::juju::juju_log;
 /// This logger uses different output styles in the CLI and Daemon logging modes. The default mode
 /// is CLI, but the mode can be changed with `set_log_mode`.
 ///
-/// The daemon log level can be independently controlled from the CLI log level by using the
-/// `LUCKY_DAEMON_LOG_LEVEL` and `LUCKY_CLI_LOG_LEVEL` environment variables. The `LUCKY_LOG_LEVEL`
-/// environment variable can be used to set a global default log level.
+/// Messages are filtered by the first of these that applies:
+///
+/// - A module directive in the `LUCKY_LOG` environment variable, such as `daemon::tools=trace`
+/// - The `-v`, `-vv`, and `-q` flags, set with `set_verbosity`
+/// - A bare level in the `LUCKY_LOG` environment variable, such as `debug`
+/// - The `LUCKY_DAEMON_LOG_LEVEL` or `LUCKY_CLI_LOG_LEVEL` environment variable for the log mode
+/// - The `LUCKY_LOG_LEVEL` environment variable
+/// - The default level, `info`
+///
+/// Any values registered with `add_redacted_value` will be replaced with `[REDACTED]` in all log
+/// output.
 pub(crate) struct LuckyLogger {
     log_mode: Arc<RwLock<LogMode>>,
     log_file: Arc<RwLock<Option<File>>>,
+    redacted_values: Arc<RwLock<HashSet<String>>>,
+    log_filter: Arc<RwLock<LogFilter>>,
 }
 
 impl LuckyLogger {
@@ -28,9 +39,23 @@ impl LuckyLogger {
         LuckyLogger {
             log_mode: Arc::new(RwLock::new(LogMode::Cli)),
             log_file: Arc::new(RwLock::new(None)),
+            redacted_values: Arc::new(RwLock::new(HashSet::new())),
+            log_filter: Arc::new(RwLock::new(LogFilter::default())),
         }
     }
 
+    fn set_log_filter(&self, filter: LogFilter) {
+        let mut log_filter = self.log_filter.write().unwrap();
+
+        *log_filter = filter;
+    }
+
+    fn set_verbosity(&self, level: LevelFilter) {
+        let mut log_filter = self.log_filter.write().unwrap();
+
+        log_filter.verbosity = Some(level);
+    }
+
     fn set_log_mode(&self, mode: LogMode) {
         let mut log_mode = self.log_mode.write().unwrap();
 
@@ -42,6 +67,104 @@ impl LuckyLogger {
 
         *log_file = Some(file);
     }
+
+    fn add_redacted_value(&self, value: &str) {
+        let mut redacted_values = self.redacted_values.write().unwrap();
+
+        redacted_values.insert(value.into());
+    }
+
+    fn redact(&self, message: &str) -> String {
+        let redacted_values = self.redacted_values.read().unwrap();
+
+        let mut message = message.to_string();
+        for value in redacted_values.iter() {
+            message = message.replace(value.as_str(), REDACTED_PLACEHOLDER);
+        }
+
+        message
+    }
+}
+
+/// The text that redacted values are replaced with in the logs
+pub(crate) const REDACTED_PLACEHOLDER: &str = "[REDACTED]";
+
+/// The minimum length of a value that will be redacted. This prevents very short values such as
+/// `1` or `no` from mangling unrelated log output.
+const MIN_REDACTED_LEN: usize = 4;
+
+#[derive(Default)]
+/// The log levels set by the `LUCKY_LOG` environment variable and the verbosity flags
+struct LogFilter {
+    /// The levels of specific modules, such as `lucky::daemon`
+    modules: Vec<(String, LevelFilter)>,
+    /// The level set by the `-v` or `-q` flags
+    verbosity: Option<LevelFilter>,
+    /// The level set by a directive without a module
+    level: Option<LevelFilter>,
+    /// The level set by the `LUCKY_LOG_LEVEL` environment variable
+    base_level: Option<LevelFilter>,
+}
+
+impl LogFilter {
+    /// Parse a comma separated list of `module=level`, `module`, or `level` directives, such as
+    /// `warn,daemon=debug`
+    ///
+    /// Module paths are relative to the `lucky` crate, and a module without a level gets every
+    /// message. Invalid levels are treated as `trace`.
+    fn parse(spec: &str) -> Self {
+        let mut filter = Self::default();
+        for directive in spec.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            let (module, level) = match (parts.next(), parts.next()) {
+                (Some(module), Some(level)) => (
+                    module.trim(),
+                    level.trim().parse().unwrap_or(LevelFilter::Trace),
+                ),
+                (Some(directive), None) => match directive.parse() {
+                    Ok(level) => {
+                        filter.level = Some(level);
+                        continue;
+                    }
+                    Err(_) => (directive, LevelFilter::Trace),
+                },
+                _ => continue,
+            };
+
+            let module = if module == "lucky" || module.starts_with("lucky::") {
+                module.to_string()
+            } else {
+                format!("lucky::{}", module)
+            };
+            filter.modules.push((module, level));
+        }
+
+        filter
+    }
+
+    /// Get the level of the most specific module directive that matches the target
+    fn module_level(&self, target: &str) -> Option<LevelFilter> {
+        self.modules
+            .iter()
+            .filter(|(module, _)| target == module || target.starts_with(&format!("{}::", module)))
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+    }
+
+    /// Get the filter as a `LUCKY_LOG` value, including the verbosity level
+    fn to_spec(&self) -> String {
+        let mut directives: Vec<String> = self
+            .verbosity
+            .or(self.level)
+            .map(|x| x.to_string().to_lowercase())
+            .into_iter()
+            .collect();
+        for (module, level) in &self.modules {
+            directives.push(format!("{}={}", module, level.to_string().to_lowercase()));
+        }
+
+        directives.join(",")
+    }
 }
 
 /// The logging output mode to use
@@ -62,26 +185,25 @@ impl log::Log for LuckyLogger {
             return false;
         }
 
+        // Filter based on the module directives and verbosity flags
+        let log_filter = self.log_filter.read().unwrap();
+        if let Some(level) = log_filter
+            .module_level(metadata.target())
+            .or(log_filter.verbosity)
+            .or(log_filter.level)
+        {
+            return metadata.level() <= level;
+        }
+
         // Filter based on specific log level environment variables
-        match *log_mode {
-            // Daemon logs
-            LogMode::Daemon => {
-                if let Ok(level) = std::env::var("LUCKY_DAEMON_LOG_LEVEL") {
-                    metadata.level() <= level.parse().unwrap_or(LevelFilter::Trace)
-                } else {
-                    // Modified by the `LUCKY_LOG_LEVEL` env var
-                    metadata.level() <= log::max_level()
-                }
-            }
-            // CLI logs
-            LogMode::Cli => {
-                if let Ok(level) = std::env::var("LUCKY_CLI_LOG_LEVEL") {
-                    metadata.level() <= level.parse().unwrap_or(LevelFilter::Trace)
-                } else {
-                    // Modified by the `LUCKY_LOG_LEVEL` env var
-                    metadata.level() <= log::max_level()
-                }
-            }
+        let mode_level_var = match *log_mode {
+            LogMode::Daemon => "LUCKY_DAEMON_LOG_LEVEL",
+            LogMode::Cli => "LUCKY_CLI_LOG_LEVEL",
+        };
+        if let Ok(level) = std::env::var(mode_level_var) {
+            metadata.level() <= level.parse().unwrap_or(LevelFilter::Trace)
+        } else {
+            metadata.level() <= log_filter.base_level.unwrap_or(LevelFilter::Info)
         }
     }
 
@@ -92,6 +214,8 @@ impl log::Log for LuckyLogger {
 
         let buffer_error = "Could not write to internal string buffer";
         let log_mode = self.log_mode.read().unwrap();
+        // Format the log message, removing any secret values
+        let args = self.redact(&record.args().to_string());
         match *log_mode {
             // Daemon logs
             LogMode::Daemon => {
@@ -120,7 +244,7 @@ impl log::Log for LuckyLogger {
                 }
 
                 // Write message
-                write!(message, ": {}", record.args()).expect(buffer_error);
+                write!(message, ": {}", args).expect(buffer_error);
                 // Log to stderr
                 log_to_stderr(&message);
                 // Log to file
@@ -138,16 +262,15 @@ impl log::Log for LuckyLogger {
                 match record.level() {
                     // Print errors with newline and red `Error:` prefix
                     Level::Error => {
-                        write!(message, "{} {}", red("Error:"), record.args()).expect(buffer_error);
+                        write!(message, "{} {}", red("Error:"), args).expect(buffer_error);
                     }
                     // Print warnings with yellow `Warning:` prefix
                     Level::Warn => {
-                        write!(message, "{} {}", yellow("Warning:"), record.args())
-                            .expect(buffer_error);
+                        write!(message, "{} {}", yellow("Warning:"), args).expect(buffer_error);
                     }
                     // Print info without decoration ( might want to change that, needs thought )
                     Level::Info => {
-                        write!(message, "{}", record.args()).expect(buffer_error);
+                        write!(message, "{}", args).expect(buffer_error);
                     }
                     // Print debug with dark blue `Debug:` prefix
                     Level::Debug => {
@@ -169,8 +292,7 @@ impl log::Log for LuckyLogger {
                         }
 
                         // Add message
-                        write!(message, "{} {}", dark_blue(":"), record.args())
-                            .expect(buffer_error);
+                        write!(message, "{} {}", dark_blue(":"), args).expect(buffer_error);
                     }
                     // Print trace with grey `Trace:` prefix
                     Level::Trace => {
@@ -192,8 +314,7 @@ impl log::Log for LuckyLogger {
                         }
 
                         // Add message
-                        write!(message, "{} {}", dark_grey(":"), record.args())
-                            .expect(buffer_error);
+                        write!(message, "{} {}", dark_grey(":"), args).expect(buffer_error);
                     }
                 }
 
@@ -224,20 +345,42 @@ lazy_static! {
     static ref LUCKY_LOGGER: LuckyLogger = LuckyLogger::new();
 }
 
-/// Initialize the logger and set the max log level from the `LUCKY_LOG_LEVEL` environment variable
+/// Initialize the logger with the log filter from the `LUCKY_LOG` and `LUCKY_LOG_LEVEL`
+/// environment variables
 pub(crate) fn init_logger() {
     match log::set_logger(&*LUCKY_LOGGER) {
         Ok(()) => {
-            if let Ok(level) = std::env::var("LUCKY_LOG_LEVEL") {
-                log::set_max_level(level.parse().unwrap_or(log::LevelFilter::Debug));
-            } else {
-                log::set_max_level(log::LevelFilter::Info);
-            }
+            reload_log_filter();
+
+            // All filtering is done by the logger because the levels can differ by module
+            log::set_max_level(LevelFilter::Trace);
         }
         Err(e) => panic!("Could not set logger: {}", e),
     }
 }
 
+/// Load the log filter from the `LUCKY_LOG` and `LUCKY_LOG_LEVEL` environment variables again,
+/// such as after they are set from the Lucky config
+///
+/// This clears the level set with `set_verbosity`.
+pub(crate) fn reload_log_filter() {
+    let mut filter = LogFilter::parse(&std::env::var("LUCKY_LOG").unwrap_or_default());
+    filter.base_level = std::env::var("LUCKY_LOG_LEVEL")
+        .ok()
+        .map(|level| level.parse().unwrap_or(LevelFilter::Debug));
+    LUCKY_LOGGER.set_log_filter(filter);
+}
+
+/// Override the log level for the `-v`, `-vv`, and `-q` flags
+pub(crate) fn set_verbosity(level: LevelFilter) {
+    LUCKY_LOGGER.set_verbosity(level);
+}
+
+/// Get the log filter as a `LUCKY_LOG` value so that it can be passed on to child processes
+pub(crate) fn log_filter_spec() -> String {
+    LUCKY_LOGGER.log_filter.read().unwrap().to_spec()
+}
+
 /// Set the logging mode for Lucky
 pub(crate) fn set_log_mode(mode: LogMode) {
     LUCKY_LOGGER.set_log_mode(mode);
@@ -248,17 +391,51 @@ pub(crate) fn set_log_file(file: File) {
     LUCKY_LOGGER.set_log_file(file);
 }
 
+/// Register a secret value that should never show up in the logs
+///
+/// Values shorter than 4 characters are ignored.
+pub(crate) fn add_redacted_value(value: &str) {
+    if value.len() >= MIN_REDACTED_LEN {
+        LUCKY_LOGGER.add_redacted_value(value);
+    }
+}
+
+/// Replace any registered secret values in the given string with `[REDACTED]`
+pub(crate) fn redact(message: &str) -> String {
+    LUCKY_LOGGER.redact(message)
+}
+
 //
 // Color helpers
 //
-// These functions add color to the output if stderr is a tty
+// These functions add color to the output if stderr is a tty and color hasn't been disabled
 //
 
 use atty::Stream::Stderr;
 use crossterm::style::{style, Color};
 
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether color has been disabled with the `--no-color` flag
+static COLOR_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Disable colored output everywhere, such as for the `--no-color` flag
+pub(crate) fn disable_color() {
+    COLOR_DISABLED.store(true, Ordering::SeqCst);
+}
+
+/// Get whether output to the given stream should be colored
+///
+/// Output is only colored if the stream is a tty, color hasn't been disabled with
+/// `disable_color()`, and the `NO_COLOR` environment variable isn't set.
+pub(crate) fn color_enabled(stream: atty::Stream) -> bool {
+    !COLOR_DISABLED.load(Ordering::SeqCst)
+        && std::env::var_os("NO_COLOR").is_none()
+        && atty::is(stream)
+}
+
 fn red(s: &str) -> String {
-    if atty::is(Stderr) {
+    if color_enabled(Stderr) {
         style(s).with(Color::Red).to_string()
     } else {
         s.to_string()
@@ -266,7 +443,7 @@ fn red(s: &str) -> String {
 }
 
 fn yellow(s: &str) -> String {
-    if atty::is(Stderr) {
+    if color_enabled(Stderr) {
         style(s).with(Color::Yellow).to_string()
     } else {
         s.to_string()
@@ -274,7 +451,7 @@ fn yellow(s: &str) -> String {
 }
 
 fn dark_blue(s: &str) -> String {
-    if atty::is(Stderr) {
+    if color_enabled(Stderr) {
         style(s).with(Color::DarkBlue).to_string()
     } else {
         s.to_string()
@@ -282,7 +459,7 @@ fn dark_blue(s: &str) -> String {
 }
 
 fn dark_grey(s: &str) -> String {
-    if atty::is(Stderr) {
+    if color_enabled(Stderr) {
         style(s).with(Color::DarkGrey).to_string()
     } else {
         s.to_string()
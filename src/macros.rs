@@ -19,16 +19,17 @@
 ///     let state = daemon.state.write().unwrap();
 ///
 ///     // Set the status
-///     daemon_set_status!(&mut state, ScriptState::Maintenance, "Doing something");
+///     daemon_set_status!(daemon, &mut state, ScriptState::Maintenance, "Doing something");
 ///
 ///     // Do stuff
 ///
 ///     // Clear the status
-///     daemon_set_status!(&mut state, ScriptState::Active);
+///     daemon_set_status!(daemon, &mut state, ScriptState::Active);
 /// }
 macro_rules! daemon_set_status {
-    ($daemon_state:expr, $script_state:expr) => {
+    ($daemon:expr, $daemon_state:expr, $script_state:expr) => {
         crate::daemon::tools::set_script_status(
+            &*$daemon.juju,
             $daemon_state,
             function_path!(),
             ScriptStatus {
@@ -37,8 +38,9 @@ macro_rules! daemon_set_status {
             },
         )?;
     };
-    ($daemon_state:expr, $script_state:expr, $message:expr) => {
+    ($daemon:expr, $daemon_state:expr, $script_state:expr, $message:expr) => {
         crate::daemon::tools::set_script_status(
+            &*$daemon.juju,
             $daemon_state,
             function_path!(),
             ScriptStatus {
@@ -0,0 +1,251 @@
+//! Contains tools for managing the workload containers of Kubernetes sidecar charms through Pebble
+//!
+//! Each workload container of a sidecar charm runs Pebble, which exposes its API on a socket that
+//! is mounted into the charm container. Lucky runs the container's workload as a Pebble service
+//! that is named after the container.
+use anyhow::{bail, format_err, Context};
+use serde::Serialize;
+use serde_json::{json, Value as JsonValue};
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::docker::ContainerConfig;
+
+/// The directory that the workload container Pebble sockets are mounted to in the charm container
+pub(crate) const PEBBLE_CONTAINER_DIR: &str = "/charm/containers";
+/// The label of the Pebble layer that Lucky manages
+const LAYER_LABEL: &str = "lucky";
+/// How long to wait for Pebble to finish changing a service
+const CHANGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether or not the Pebble sockets of the workload containers are present
+pub(crate) fn is_available() -> bool {
+    Path::new(PEBBLE_CONTAINER_DIR).is_dir()
+}
+
+/// A Pebble API client for one workload container
+pub(crate) struct PebbleClient {
+    /// The name of the workload container
+    container_name: String,
+    /// The path to the container's Pebble socket
+    socket_path: PathBuf,
+}
+
+/// A Pebble layer
+#[derive(Serialize)]
+struct Layer<'a> {
+    summary: String,
+    services: HashMap<&'a str, LayerService>,
+}
+
+/// A service in a Pebble layer
+#[derive(Serialize)]
+struct LayerService {
+    #[serde(rename = "override")]
+    override_mode: &'static str,
+    summary: String,
+    command: String,
+    startup: &'static str,
+    environment: HashMap<String, String>,
+}
+
+impl PebbleClient {
+    /// Create a client for the given workload container
+    pub fn new(container_name: &str) -> Self {
+        PebbleClient {
+            container_name: container_name.into(),
+            socket_path: Path::new(PEBBLE_CONTAINER_DIR)
+                .join(container_name)
+                .join("pebble.socket"),
+        }
+    }
+
+    /// Whether or not Pebble is responding in the container
+    pub fn is_ready(&self) -> bool {
+        self.request("GET", "/v1/system-info", None).is_ok()
+    }
+
+    /// Replace the container's workload service with one that runs the given container config
+    ///
+    /// The image of the config is ignored because the image of a sidecar container is set by the
+    /// charm's `metadata.yaml`. Because Pebble can't read the image's entrypoint, the config must
+    /// have an entrypoint or a command.
+    pub fn apply_config(&self, config: &ContainerConfig, start: bool) -> anyhow::Result<()> {
+        if !config.ports.is_empty() || !config.volumes.is_empty() || config.network.is_some() {
+            log::warn!(
+                "Ignoring the ports, volumes, and network of container {}: these are managed by \
+                Kubernetes for sidecar charms",
+                self.container_name
+            );
+        }
+
+        let args: Vec<&str> = config
+            .entrypoint
+            .iter()
+            .map(String::as_str)
+            .chain(config.command.iter().flatten().map(String::as_str))
+            .collect();
+        if args.is_empty() {
+            bail!(
+                "Container {} must have an entrypoint or a command to run it with Pebble",
+                self.container_name
+            );
+        }
+
+        let mut services = HashMap::new();
+        services.insert(
+            self.container_name.as_str(),
+            LayerService {
+                override_mode: "replace",
+                summary: format!("Lucky workload for {}", self.container_name),
+                command: args
+                    .iter()
+                    .map(|x| quote_arg(x))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                startup: if start { "enabled" } else { "disabled" },
+                environment: config.env_vars.clone(),
+            },
+        );
+        let layer = serde_yaml::to_string(&Layer {
+            summary: "Lucky managed workload".into(),
+            services,
+        })?;
+
+        self.request(
+            "POST",
+            "/v1/layers",
+            Some(&json!({
+                "action": "add",
+                "combine": true,
+                "label": LAYER_LABEL,
+                "format": "yaml",
+                "layer": layer,
+            })),
+        )
+        .context(format!(
+            "Could not add Pebble layer to container {}",
+            self.container_name
+        ))?;
+
+        if start {
+            // Restart the service so that it picks up the new config
+            self.change_service("restart")
+        } else {
+            self.change_service("stop")
+        }
+    }
+
+    /// Run the given action, such as `start`, `stop`, or `restart`, on the workload service and
+    /// wait for it to finish
+    pub fn change_service(&self, action: &str) -> anyhow::Result<()> {
+        let response = self
+            .request(
+                "POST",
+                "/v1/services",
+                Some(&json!({ "action": action, "services": [&self.container_name] })),
+            )
+            .context(format!(
+                "Could not {} the service in container {}",
+                action, self.container_name
+            ))?;
+
+        let change_id = response
+            .get("change")
+            .and_then(JsonValue::as_str)
+            .ok_or_else(|| format_err!("Pebble did not return a change ID"))?;
+        self.wait_change(change_id)
+    }
+
+    /// Wait for a Pebble change to finish
+    fn wait_change(&self, change_id: &str) -> anyhow::Result<()> {
+        let response = self.request(
+            "GET",
+            &format!(
+                "/v1/changes/{}/wait?timeout={}s",
+                change_id,
+                CHANGE_TIMEOUT.as_secs()
+            ),
+            None,
+        )?;
+        let result = response.get("result").unwrap_or(&JsonValue::Null);
+
+        match result.get("status").and_then(JsonValue::as_str) {
+            Some("Done") => Ok(()),
+            status => bail!(
+                "Pebble change {} in container {} did not succeed ( status: {} ): {}",
+                change_id,
+                self.container_name,
+                status.unwrap_or("unknown"),
+                result
+                    .get("err")
+                    .and_then(JsonValue::as_str)
+                    .unwrap_or("no error message")
+            ),
+        }
+    }
+
+    /// Make a request to the Pebble API and return the response body
+    ///
+    /// HTTP/1.0 is used so that Pebble closes the connection after the response instead of using
+    /// chunked encoding.
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<&JsonValue>,
+    ) -> anyhow::Result<JsonValue> {
+        log::trace!(
+            "Pebble request to {}: {} {}",
+            self.container_name,
+            method,
+            path
+        );
+        let body = body.map(ToString::to_string).unwrap_or_default();
+
+        let mut stream = UnixStream::connect(&self.socket_path).context(format!(
+            "Could not connect to Pebble socket: {:?}",
+            self.socket_path
+        ))?;
+        stream.set_read_timeout(Some(CHANGE_TIMEOUT + Duration::from_secs(5)))?;
+        write!(
+            stream,
+            "{} {} HTTP/1.0\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+            Content-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        let response_body = response
+            .splitn(2, "\r\n\r\n")
+            .nth(1)
+            .ok_or_else(|| format_err!("Invalid response from Pebble"))?;
+        let response: JsonValue =
+            serde_json::from_str(response_body).context("Could not parse Pebble response")?;
+
+        if response.get("type").and_then(JsonValue::as_str) == Some("error") {
+            bail!(
+                "Pebble error: {}",
+                response
+                    .pointer("/result/message")
+                    .and_then(JsonValue::as_str)
+                    .unwrap_or("unknown error")
+            );
+        }
+
+        Ok(response)
+    }
+}
+
+/// Quote an argument for use in a Pebble service command, which is split like a shell command
+fn quote_arg(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r#"'"'"'"#))
+}
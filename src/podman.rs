@@ -0,0 +1,66 @@
+//! Contains tools for installing Podman and connecting to its Docker-compatible API
+//!
+//! Lucky talks to Podman through the Docker-compatible API that is served by the `podman.socket`
+//! systemd unit. When Podman is run rootless, the socket is started in the systemd user instance of
+//! the Podman user instead of the system instance.
+use anyhow::bail;
+
+use std::path::{Path, PathBuf};
+
+use crate::process::{cmd_exists, run_cmd, run_cmd_with_retries};
+
+/// The path to the API socket of the system Podman service
+const ROOT_SOCKET_PATH: &str = "/run/podman/podman.sock";
+
+/// Make sure Podman is installed and that its API socket is listening
+///
+/// If a `user` is given the socket is started for that user so that Podman runs rootless.
+pub(crate) fn ensure_podman(user: Option<&str>) -> anyhow::Result<()> {
+    // Install Podman if it isn't already installed
+    if !cmd_exists("podman", &["--version"])? {
+        run_cmd_with_retries("apt-get", &["install", "-y", "podman"], &Default::default())?;
+
+        // Make sure podman is installed
+        if !cmd_exists("podman", &["--version"])? {
+            bail!("Could not install Podman");
+        }
+    }
+
+    // Start the API socket
+    match user {
+        Some(user) => {
+            // Keep the user's systemd instance running when the user isn't logged in
+            run_cmd("loginctl", &["enable-linger", user])?;
+            run_cmd(
+                "systemctl",
+                &[
+                    "--user",
+                    "--machine",
+                    &format!("{}@", user),
+                    "enable",
+                    "--now",
+                    "podman.socket",
+                ],
+            )?;
+        }
+        None => {
+            run_cmd("systemctl", &["enable", "--now", "podman.socket"])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the path to the Podman API socket for the given user, or for root if no user is given
+pub(crate) fn socket_path(user: Option<&str>) -> anyhow::Result<PathBuf> {
+    match user {
+        Some(user) => {
+            let uid = run_cmd("id", &["-u", user])?;
+            Ok(Path::new("/run/user")
+                .join(uid.trim())
+                .join("podman")
+                .join("podman.sock"))
+        }
+        None => Ok(ROOT_SOCKET_PATH.into()),
+    }
+}
@@ -0,0 +1,262 @@
+//! Contains tools for running parts of a charm's workload as systemd services on the host
+use anyhow::{bail, Context};
+use handlebars::Handlebars;
+use serde::{Deserialize, Serialize};
+use strum_macros::{AsRefStr, EnumString, EnumVariantNames};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+use crate::process::run_cmd;
+
+/// The directory that host service unit files are installed to
+pub(crate) const SYSTEMD_UNIT_DIR: &str = "/etc/systemd/system";
+
+/// A struct made of a host service definition and its removal state
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct HostServiceInfo {
+    /// Marks this service as pending removal
+    pub pending_removal: bool,
+    /// The definition for the desired state of the service
+    pub config: HostServiceConfig,
+}
+
+impl HostServiceInfo {
+    pub fn new(command: Vec<String>) -> Self {
+        HostServiceInfo {
+            pending_removal: false,
+            config: HostServiceConfig {
+                command,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[derive(
+    Serialize,
+    Deserialize,
+    PartialEq,
+    Eq,
+    Clone,
+    Copy,
+    Debug,
+    AsRefStr,
+    EnumString,
+    EnumVariantNames,
+)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+/// When systemd should restart a host service. These map to the systemd `Restart=` setting.
+pub(crate) enum RestartPolicy {
+    /// Never restart the service
+    No,
+    /// Restart the service if it exits non-zero or is killed
+    OnFailure,
+    /// Always restart the service when it exits
+    Always,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy::OnFailure
+    }
+}
+
+/// The host service configuration options such as command, environment, and restart policy
+#[derive(Serialize, Deserialize, Default, PartialEq, Clone, Debug)]
+pub(crate) struct HostServiceConfig {
+    /// The command to run, starting with the program path
+    pub command: Vec<String>,
+    pub env_vars: HashMap<String, String>,
+    pub restart_policy: RestartPolicy,
+    /// The path, relative to the charm dir, to a template to use instead of the default unit file
+    pub unit_template: Option<String>,
+}
+
+/// The data that is made available to unit file templates
+#[derive(Serialize)]
+struct UnitTemplateData<'a> {
+    /// The name of the host service
+    name: &'a str,
+    /// The Juju unit that the service belongs to
+    unit: &'a str,
+    /// The command, quoted for use in `ExecStart=`
+    command: String,
+    /// The path to the service's environment file
+    env_file: String,
+    /// The systemd `Restart=` value
+    restart: &'a str,
+    /// The path to the charm dir
+    charm_dir: String,
+}
+
+/// The unit file used for host services that don't have their own template
+const DEFAULT_UNIT_TEMPLATE: &str = "[Unit]
+Description=Lucky host service {{name}} for {{unit}}
+After=network.target
+
+[Service]
+EnvironmentFile={{env_file}}
+ExecStart={{command}}
+Restart={{restart}}
+
+[Install]
+WantedBy=multi-user.target
+";
+
+/// Get the name of the systemd unit for a host service
+pub(crate) fn unit_name(unit_name: &str, service_name: &str) -> String {
+    format!(
+        "lucky_{}_{}.service",
+        unit_name.replace("/", "_"),
+        service_name
+    )
+}
+
+/// Make sure a host service name can be used in a unit file name
+pub(crate) fn validate_service_name(service_name: &str) -> anyhow::Result<()> {
+    if service_name.is_empty()
+        || !service_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!(
+            "Invalid host service name {:?}: names may only contain letters, numbers, `-`, and `_`",
+            service_name
+        );
+    }
+
+    Ok(())
+}
+
+impl HostServiceConfig {
+    /// Render the systemd unit file for the service
+    pub fn render_unit_file(
+        &self,
+        service_name: &str,
+        unit_name: &str,
+        charm_dir: &Path,
+        env_file: &Path,
+    ) -> anyhow::Result<String> {
+        if self.command.is_empty() {
+            bail!("Host service {:?} does not have a command", service_name);
+        }
+
+        let template = match &self.unit_template {
+            Some(template) => {
+                let path = charm_dir.join(template);
+                fs::read_to_string(&path)
+                    .context(format!("Could not read unit file template: {:?}", path))?
+            }
+            None => DEFAULT_UNIT_TEMPLATE.into(),
+        };
+
+        let data = UnitTemplateData {
+            name: service_name,
+            unit: unit_name,
+            command: self
+                .command
+                .iter()
+                .map(|arg| quote_arg(arg))
+                .collect::<Vec<_>>()
+                .join(" "),
+            env_file: env_file.to_string_lossy().into(),
+            restart: self.restart_policy.as_ref(),
+            charm_dir: charm_dir.to_string_lossy().into(),
+        };
+
+        let mut handlebars = Handlebars::new();
+        handlebars.register_escape_fn(handlebars::no_escape);
+        Ok(handlebars
+            .render_template(&template, &data)
+            .context(format!(
+                "Could not render unit file for host service: {}",
+                service_name
+            ))?)
+    }
+
+    /// Render the service's environment variables to a systemd environment file that is only
+    /// readable by the user running the daemon
+    pub fn write_env_file(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context(format!(
+                "Could not create dir: {}",
+                parent.to_string_lossy()
+            ))?;
+        }
+
+        // Sort the variables so that the output is stable
+        let mut vars: Vec<_> = self.env_vars.iter().collect();
+        vars.sort();
+
+        let mut content = String::new();
+        for (key, value) in vars {
+            if value.contains('\n') {
+                bail!(
+                    "Host service environment variable {:?} cannot contain a newline",
+                    key
+                );
+            }
+            content.push_str(&format!("{}={}\n", key, quote_env_value(value)));
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)
+            .context(format!("Could not open env file: {:?}", path))?;
+        // Make sure the permissions are correct even if the file already existed
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(content.as_bytes())
+            .context(format!("Could not write env file: {:?}", path))?;
+
+        Ok(())
+    }
+}
+
+/// Quote a command argument for use in a unit file `ExecStart=` setting
+fn quote_arg(arg: &str) -> String {
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    for c in arg.chars() {
+        match c {
+            '\\' | '"' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            // Systemd expands variables and specifiers such as `$HOME` and `%n` in commands
+            '$' => quoted.push_str("$$"),
+            '%' => quoted.push_str("%%"),
+            _ => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Quote a value for use in a systemd environment file
+fn quote_env_value(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if let '\\' | '"' | '$' | '`' = c {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Run `systemctl` with the given args
+pub(crate) fn systemctl(args: &[&str]) -> anyhow::Result<()> {
+    run_cmd("systemctl", args)?;
+
+    Ok(())
+}
@@ -0,0 +1,369 @@
+//! OpenTelemetry trace export
+//!
+//! Lucky can optionally export spans for hook executions, scripts, hook tool calls, and Docker
+//! operations to an OpenTelemetry collector using the OTLP/HTTP protocol with JSON encoding.
+//! Tracing is disabled unless an OTLP endpoint has been configured with `init`.
+//!
+//! Spans are queued in memory as they finish and are exported in a batch whenever `flush` is
+//! called, which the daemon does at the end of every hook and cron tick.
+
+use anyhow::{format_err, Context};
+use lazy_static::lazy_static;
+use rand::{thread_rng, Rng};
+use serde_json::json;
+
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The amount of time to wait on the collector before giving up on an export
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The OTLP span status code for errors
+const STATUS_CODE_ERROR: u8 = 2;
+
+lazy_static! {
+    /// The trace exporter, if tracing has been enabled
+    static ref EXPORTER: RwLock<Option<Exporter>> = RwLock::new(None);
+    /// The spans that have finished but have not been exported yet
+    static ref FINISHED_SPANS: Mutex<Vec<SpanData>> = Mutex::new(vec![]);
+    /// The context of the current root span. This is used as the parent for spans that are
+    /// started on threads that don't have a span of their own, such as the RPC worker threads that
+    /// handle the hook tool calls made by scripts.
+    static ref ROOT_CONTEXT: Mutex<Option<SpanContext>> = Mutex::new(None);
+}
+
+thread_local! {
+    /// The stack of spans that are active on the current thread
+    static SPAN_STACK: RefCell<Vec<SpanContext>> = RefCell::new(vec![]);
+}
+
+/// The destination for exported spans
+struct Exporter {
+    /// The `host:port` of the collector
+    host: String,
+    /// The HTTP path to post the traces to
+    path: String,
+    /// The name of the service to report the spans under
+    service_name: String,
+}
+
+/// The IDs that identify a span and its trace
+#[derive(Clone, Copy, PartialEq)]
+struct SpanContext {
+    trace_id: u128,
+    span_id: u64,
+}
+
+/// A span that has finished and is ready to export
+struct SpanData {
+    name: String,
+    context: SpanContext,
+    parent_span_id: Option<u64>,
+    start_time: u128,
+    end_time: u128,
+    attributes: Vec<(String, String)>,
+    error: Option<String>,
+}
+
+/// Enable trace export to the given OTLP/HTTP endpoint
+///
+/// The endpoint should be in the format `http://host:port`. Spans will be posted to the
+/// `/v1/traces` path on that endpoint unless the endpoint includes its own path.
+pub(crate) fn init(endpoint: &str, service_name: &str) -> anyhow::Result<()> {
+    let address = endpoint.strip_prefix("http://").ok_or_else(|| {
+        format_err!(
+            "Unsupported OTLP endpoint {:?}: only http:// endpoints are supported",
+            endpoint
+        )
+    })?;
+
+    let (host, path) = match address.find('/') {
+        Some(index) if index + 1 < address.len() => address.split_at(index),
+        Some(index) => (address.split_at(index).0, "/v1/traces"),
+        None => (address, "/v1/traces"),
+    };
+
+    *EXPORTER.write().unwrap() = Some(Exporter {
+        host: host.into(),
+        path: path.into(),
+        service_name: service_name.into(),
+    });
+
+    log::info!("Exporting traces to {}", endpoint);
+
+    Ok(())
+}
+
+/// Returns whether or not trace export is enabled
+fn is_enabled() -> bool {
+    EXPORTER.read().unwrap().is_some()
+}
+
+/// Get the current time in nanoseconds since the Unix epoch
+fn now_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_nanos())
+        .unwrap_or(0)
+}
+
+/// An in-progress span
+///
+/// The span will be ended and queued for export when it is dropped. If tracing is not enabled,
+/// the span does nothing.
+pub(crate) struct Span {
+    inner: Option<SpanData>,
+    /// Whether or not this span is the root span for the daemon
+    is_root: bool,
+}
+
+impl Span {
+    /// Start a new span
+    ///
+    /// The span will be a child of the span that is currently active on this thread, or of the
+    /// current root span if there is no span active on this thread.
+    pub fn start(name: &str) -> Self {
+        if !is_enabled() {
+            return Span {
+                inner: None,
+                is_root: false,
+            };
+        }
+
+        let parent = SPAN_STACK
+            .with(|stack| stack.borrow().last().copied())
+            .or_else(|| *ROOT_CONTEXT.lock().unwrap());
+
+        Span::start_with_parent(name, parent, false)
+    }
+
+    /// Start a new trace with this span as the root
+    ///
+    /// Spans started on other threads will be made children of this span until it is dropped.
+    pub fn start_root(name: &str) -> Self {
+        if !is_enabled() {
+            return Span {
+                inner: None,
+                is_root: false,
+            };
+        }
+
+        let span = Span::start_with_parent(name, None, true);
+
+        if let Some(data) = &span.inner {
+            *ROOT_CONTEXT.lock().unwrap() = Some(data.context);
+        }
+
+        span
+    }
+
+    fn start_with_parent(name: &str, parent: Option<SpanContext>, is_root: bool) -> Self {
+        let mut rng = thread_rng();
+        let context = SpanContext {
+            trace_id: parent.map_or_else(|| rng.gen(), |x| x.trace_id),
+            span_id: rng.gen(),
+        };
+
+        // Make this the active span for the thread
+        SPAN_STACK.with(|stack| stack.borrow_mut().push(context));
+
+        Span {
+            inner: Some(SpanData {
+                name: name.into(),
+                context,
+                parent_span_id: parent.map(|x| x.span_id),
+                start_time: now_nanos(),
+                end_time: 0,
+                attributes: vec![],
+                error: None,
+            }),
+            is_root,
+        }
+    }
+
+    /// Add an attribute to the span
+    pub fn with_attr(mut self, key: &str, value: &str) -> Self {
+        self.set_attr(key, value);
+        self
+    }
+
+    /// Add an attribute to the span
+    pub fn set_attr(&mut self, key: &str, value: &str) {
+        if let Some(data) = &mut self.inner {
+            data.attributes.push((key.into(), value.into()));
+        }
+    }
+
+    /// Mark the span as failed if the given result is an error
+    pub fn record_result<T, E: std::fmt::Debug>(&mut self, result: &Result<T, E>) {
+        if let (Some(data), Err(e)) = (&mut self.inner, result) {
+            data.error = Some(crate::log::redact(&format!("{:?}", e)));
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let Some(mut data) = self.inner.take() {
+            data.end_time = now_nanos();
+
+            // Remove this span from the thread's active spans
+            SPAN_STACK.with(|stack| stack.borrow_mut().retain(|x| x != &data.context));
+
+            // Clear the root context if this was the root span
+            if self.is_root {
+                let mut root_context = ROOT_CONTEXT.lock().unwrap();
+                if *root_context == Some(data.context) {
+                    *root_context = None;
+                }
+            }
+
+            FINISHED_SPANS.lock().unwrap().push(data);
+        }
+    }
+}
+
+/// Run the given function inside of the span, marking the span as failed if the function returns
+/// an error
+pub(crate) fn in_span<T, E, F>(mut span: Span, f: F) -> Result<T, E>
+where
+    E: std::fmt::Debug,
+    F: FnOnce() -> Result<T, E>,
+{
+    let result = f();
+    span.record_result(&result);
+    result
+}
+
+/// Export all of the finished spans to the collector
+///
+/// Errors are logged instead of returned because a tracing failure should never cause a hook to
+/// fail.
+pub(crate) fn flush() {
+    let exporter = EXPORTER.read().unwrap();
+    let exporter = match &*exporter {
+        Some(exporter) => exporter,
+        None => return,
+    };
+
+    let spans: Vec<SpanData> = FINISHED_SPANS.lock().unwrap().drain(..).collect();
+    if spans.is_empty() {
+        return;
+    }
+
+    log::trace!("Exporting {} spans", spans.len());
+    if let Err(e) = export(exporter, &spans) {
+        log::warn!("{:?}", e.context("Could not export traces"));
+    }
+}
+
+/// Build the OTLP JSON request body for the given spans
+fn build_request_body(exporter: &Exporter, spans: &[SpanData]) -> serde_json::Value {
+    let attribute =
+        |key: &str, value: &str| json!({ "key": key, "value": { "stringValue": value } });
+
+    let mut resource_attributes = vec![attribute("service.name", &exporter.service_name)];
+    if let Ok(unit_name) = std::env::var("JUJU_UNIT_NAME") {
+        resource_attributes.push(attribute("juju.unit", &unit_name));
+    }
+
+    json!({
+        "resourceSpans": [{
+            "resource": { "attributes": resource_attributes },
+            "scopeSpans": [{
+                "scope": { "name": "lucky", "version": crate::LUCKY_VERSION },
+                "spans": spans.iter().map(|span| {
+                    let mut value = json!({
+                        "traceId": format!("{:032x}", span.context.trace_id),
+                        "spanId": format!("{:016x}", span.context.span_id),
+                        "name": span.name,
+                        // Internal span kind
+                        "kind": 1,
+                        "startTimeUnixNano": span.start_time.to_string(),
+                        "endTimeUnixNano": span.end_time.to_string(),
+                        "attributes": span.attributes
+                            .iter()
+                            .map(|(k, v)| attribute(k, v))
+                            .collect::<Vec<_>>(),
+                    });
+
+                    if let Some(fields) = value.as_object_mut() {
+                        if let Some(parent_span_id) = span.parent_span_id {
+                            fields.insert(
+                                "parentSpanId".into(),
+                                format!("{:016x}", parent_span_id).into(),
+                            );
+                        }
+
+                        if let Some(error) = &span.error {
+                            fields.insert(
+                                "status".into(),
+                                json!({ "code": STATUS_CODE_ERROR, "message": error }),
+                            );
+                        }
+                    }
+
+                    value
+                }).collect::<Vec<_>>(),
+            }],
+        }],
+    })
+}
+
+/// Post the spans to the collector
+fn export(exporter: &Exporter, spans: &[SpanData]) -> anyhow::Result<()> {
+    let body = build_request_body(exporter, spans).to_string();
+
+    let address = exporter
+        .host
+        .to_socket_addrs()
+        .context(format!("Could not resolve address: {}", exporter.host))?
+        .next()
+        .ok_or_else(|| format_err!("Could not resolve address: {}", exporter.host))?;
+
+    let mut stream = TcpStream::connect_timeout(&address, EXPORT_TIMEOUT)
+        .context(format!("Could not connect to collector: {}", exporter.host))?;
+    stream.set_read_timeout(Some(EXPORT_TIMEOUT))?;
+    stream.set_write_timeout(Some(EXPORT_TIMEOUT))?;
+
+    write!(
+        stream,
+        concat!(
+            "POST {} HTTP/1.1\r\n",
+            "Host: {}\r\n",
+            "Content-Type: application/json\r\n",
+            "Content-Length: {}\r\n",
+            "Connection: close\r\n",
+            "\r\n",
+            "{}"
+        ),
+        exporter.path,
+        exporter.host,
+        body.len(),
+        body
+    )
+    .context("Could not send traces to collector")?;
+
+    // Read the response and check the status code
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .context("Could not read response from collector")?;
+    let status_line = response.lines().next().unwrap_or("");
+    let status_ok = status_line
+        .split_whitespace()
+        .nth(1)
+        .map_or(false, |code| code.starts_with('2'));
+
+    if status_ok {
+        Ok(())
+    } else {
+        Err(format_err!(
+            "Collector responded with an error: {}",
+            status_line
+        ))
+    }
+}
@@ -1,10 +1,14 @@
 //! Types specific to Lucky that are used throughout the app
 
+use anyhow::format_err;
+use chrono::{DateTime, Local, TimeZone};
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
 
 use strum_macros::{AsRefStr, EnumString, EnumVariantNames};
+use thiserror::Error;
 
 use crate::rpc::ScriptStatus as RpcScriptStatus;
 use crate::rpc::ScriptStatus_state as RpcScriptState;
@@ -14,6 +18,10 @@ use crate::rpc::ScriptStatus_state as RpcScriptState;
 /// See `lucky::cli::daemon::exit_code_helper`.
 pub(crate) const LUCKY_EXIT_CODE_HELPER_PREFIX: &str = "__LUCKY_CMD_EXIT_CODE__:";
 
+/// The `when` conditions of scripts
+pub(crate) mod condition;
+/// Container env file rendering and parsing
+pub(crate) mod env_file;
 /// Juju related types
 pub(crate) mod juju;
 
@@ -76,7 +84,7 @@ impl Into<RpcScriptState> for ScriptState {
     }
 }
 
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 /// Encapsulates the scripts state and an optional message
 pub(crate) struct ScriptStatus {
     pub state: ScriptState,
@@ -115,20 +123,630 @@ impl Into<RpcScriptStatus> for ScriptStatus {
 // Config files
 //
 
+/// The latest version of the `lucky.yaml` schema
+///
+/// This has to be bumped, along with the JSON Schema printed by `lucky charm validate --schema`,
+/// whenever a change to the `lucky.yaml` format would break existing files.
+pub(crate) const LUCKY_YAML_SCHEMA_VERSION: u32 = 1;
+
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "kebab-case")]
 #[serde(deny_unknown_fields)]
 /// The struct definition for the `lucky.yaml` file
 pub(crate) struct LuckyMetadata {
+    /// The version of the `lucky.yaml` schema that the file is written for. Defaults to the latest
+    /// version, `LUCKY_YAML_SCHEMA_VERSION`.
+    #[serde(default)]
+    pub version: Option<u32>,
     #[serde(default = "default_true")]
     /// Specifies whether or not to install Docker on the host and enable Docker-based features
     pub use_docker: bool,
-    /// The hooks for the charm
+    /// The kind of cloud that the charm is running on
+    #[serde(default)]
+    pub platform: Platform,
+    /// The container engine used to run the containers on machine clouds. This can be overridden
+    /// by the `container-engine` charm config option, if the charm has one.
+    #[serde(default)]
+    pub container_engine: ContainerEngineKind,
+    /// Where to install Docker from if it isn't already installed. This can be overridden by the
+    /// `docker-channel` charm config option, if the charm has one.
+    #[serde(default)]
+    pub docker_channel: DockerChannel,
+    /// The user to run Podman as. If this is set Podman is run rootless as the given user,
+    /// otherwise it is run as root.
+    #[serde(default)]
+    pub podman_user: Option<String>,
+    /// Whether or not to keep the data of the container volumes in the Lucky data dir when the
+    /// unit is removed. This can be overridden by the `preserve-volumes` charm config option, if
+    /// the charm has one.
+    #[serde(default)]
+    pub preserve_volumes: bool,
+    /// Whether or not to correct drift from the daemon state in the `update-status` hook
+    #[serde(default = "default_true")]
+    pub reconcile: bool,
+    /// The hooks for the charm. The keys are hook patterns, see `hook_key_matches`.
     #[serde(default)]
     pub hooks: HashMap<String, Vec<CharmScript>>,
-    /// The cron jobs for the charm
+    /// The scripts that run before the scripts of every hook
+    #[serde(default)]
+    pub pre_hook: Vec<CharmScript>,
+    /// The scripts that run after the scripts of every hook, if they succeed
+    #[serde(default)]
+    pub post_hook: Vec<CharmScript>,
+    /// The cron jobs for the charm, keyed by a cron schedule or an `@every` interval. See
+    /// `JobSchedule`.
     #[serde(default)]
     pub cron_jobs: IndexMap<String, Vec<CharmScript>>, // Use an IndexMap to preserve order
+    /// The Juju actions for the charm
+    #[serde(default)]
+    pub actions: HashMap<String, CharmAction>,
+    /// The scripts to run when a custom event is emitted with `lucky emit`, keyed by event name
+    #[serde(default)]
+    pub events: HashMap<String, Vec<CharmScript>>,
+    /// The scripts to run when keys in the unit key-value store change, keyed by the key to
+    /// watch. The key may contain `*` wildcards to watch every key that matches it.
+    #[serde(default)]
+    pub kv_watches: IndexMap<String, Vec<CharmScript>>,
+    /// Whether or not keys in the unit key-value store that expire trigger the `kv-watches`
+    /// scripts that watch them
+    #[serde(default)]
+    pub kv_expiry_triggers_watches: bool,
+    /// The relation data keys that are kept in sync with the unit key-value store, keyed by
+    /// relation name
+    #[serde(default)]
+    pub relation_kv: HashMap<String, RelationKvSync>,
+    /// The containers declared for the charm, keyed by container name. The container named
+    /// `default` is the default container.
+    #[serde(default)]
+    pub containers: HashMap<String, ContainerSpec>,
+    /// The scripts that migrate the unit's data to a new state version, keyed by version. When the
+    /// charm is upgraded, the migrations newer than the unit's state version are run in order.
+    #[serde(default)]
+    pub migrations: BTreeMap<u32, Vec<CharmScript>>,
+    /// How many logs of the output of each script are kept
+    #[serde(default)]
+    pub script_logs: ScriptLogRetention,
+    /// The long-running host scripts that the daemon keeps running in the background, keyed by
+    /// service name
+    #[serde(default)]
+    pub service_scripts: HashMap<String, ServiceScript>,
+}
+
+impl LuckyMetadata {
+    /// Get the `hooks` that run for the given hook: the hook with the exact name first, followed by
+    /// the other hooks whose keys match it, ordered by key
+    pub fn matching_hooks(&self, hook_name: &str) -> Vec<(&str, &Vec<CharmScript>)> {
+        let mut hooks: Vec<(&str, &Vec<CharmScript>)> = self
+            .hooks
+            .iter()
+            .filter(|(key, _)| key.as_str() != hook_name && hook_key_matches(key, hook_name))
+            .map(|(key, scripts)| (key.as_str(), scripts))
+            .collect();
+        hooks.sort_by_key(|(key, _)| *key);
+
+        if let Some(scripts) = self.hooks.get(hook_name) {
+            hooks.insert(0, (hook_name, scripts));
+        }
+
+        hooks
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// The retention policy of the logs of the output of each script. The oldest logs of a script are
+/// removed when any of the limits is exceeded, but the latest log is always kept.
+pub(crate) struct ScriptLogRetention {
+    /// The number of runs of each script to keep the logs of. Setting this to `0` disables the
+    /// logs.
+    #[serde(default = "default_script_log_max_runs")]
+    pub max_runs: usize,
+    /// The maximum total size, in bytes, of the logs of each script
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    /// The maximum age, in seconds, of the logs
+    #[serde(default)]
+    pub max_age: Option<u64>,
+}
+
+impl Default for ScriptLogRetention {
+    fn default() -> Self {
+        ScriptLogRetention {
+            max_runs: default_script_log_max_runs(),
+            max_size: None,
+            max_age: None,
+        }
+    }
+}
+
+fn default_script_log_max_runs() -> usize {
+    10
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// A long-running host script that the daemon starts and supervises, for workloads that are plain
+/// processes instead of containers
+pub(crate) struct ServiceScript {
+    /// The name of the script in the charm's `host_scripts` dir
+    pub host_script: String,
+    /// The arguments to run the script with
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// The environment variables that are set for the script
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// When the script is restarted after it exits
+    #[serde(default)]
+    pub restart: ServiceRestartPolicy,
+    /// The number of seconds to wait before restarting the script. The delay doubles every time
+    /// the script exits soon after it was restarted.
+    #[serde(default = "default_retry_delay")]
+    pub restart_delay: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+/// When the daemon should restart a service script after it exits
+pub(crate) enum ServiceRestartPolicy {
+    /// Never restart the script
+    No,
+    /// Restart the script when it exits with a non-zero exit code or is killed
+    OnFailure,
+    /// Always restart the script when it exits
+    Always,
+}
+
+impl Default for ServiceRestartPolicy {
+    fn default() -> Self {
+        ServiceRestartPolicy::Always
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+/// The kind of cloud that the charm is running on
+pub(crate) enum Platform {
+    /// Detect the platform when the daemon starts
+    Auto,
+    /// A machine cloud, where containers are run with Docker
+    Machine,
+    /// A Kubernetes cloud, where the charm is a sidecar charm and containers are managed by Pebble
+    Kubernetes,
+}
+
+impl Default for Platform {
+    fn default() -> Self {
+        Platform::Auto
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, EnumString, AsRefStr)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab_case")]
+/// The container engine used to run containers on machine clouds
+pub(crate) enum ContainerEngineKind {
+    Docker,
+    Podman,
+    Containerd,
+}
+
+impl Default for ContainerEngineKind {
+    fn default() -> Self {
+        ContainerEngineKind::Docker
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, EnumString, AsRefStr)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab_case")]
+/// Where Docker is installed from when it isn't already installed
+pub(crate) enum DockerChannel {
+    /// The `docker.io` package from the Ubuntu archive
+    Apt,
+    /// The `docker` snap
+    Snap,
+}
+
+impl Default for DockerChannel {
+    fn default() -> Self {
+        DockerChannel::Apt
+    }
+}
+
+/// The name used for the default container in the `lucky.yaml` file
+pub(crate) const DEFAULT_CONTAINER_NAME: &str = "default";
+
+/// The name of the unit key-value store namespace that is shared by all scripts
+pub(crate) const GLOBAL_KV_NAMESPACE: &str = "global";
+
+/// Check whether or not a key in the unit key-value store matches a key pattern, such as the keys
+/// of the `kv-watches`, where `*` matches any number of characters
+pub(crate) fn kv_key_matches(pattern: &str, key: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let mut rest = match key.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+
+    // Without a wildcard the key has to match exactly
+    let mut parts = parts.peekable();
+    if parts.peek().is_none() {
+        return rest.is_empty();
+    }
+
+    while let Some(part) = parts.next() {
+        // The last part has to be at the end of the key
+        if parts.peek().is_none() {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(index) => rest = rest.get(index + part.len()..).unwrap_or(""),
+            None => return false,
+        }
+    }
+
+    true
+}
+
+/// Check whether or not a hook matches a key of the `hooks` in the `lucky.yaml`
+///
+/// The key is a comma-separated list of hook names that may contain `*` wildcards, such as
+/// `install, upgrade-charm` or `*-relation-changed`. The key `*` matches every hook.
+pub(crate) fn hook_key_matches(key: &str, hook_name: &str) -> bool {
+    key.split(',')
+        .any(|pattern| kv_key_matches(pattern.trim(), hook_name))
+}
+
+/// Get the ID of a key of the `hooks` in the `lucky.yaml` for use in script IDs, which end up in
+/// file names and key-value store namespaces
+///
+/// The hooks in the key are joined with `+` and `*` wildcards are replaced with `any`, so
+/// `install, upgrade-charm` becomes `install+upgrade-charm` and `*-relation-changed` becomes
+/// `any-relation-changed`. Keys that are a single hook name are left as they are.
+pub(crate) fn hook_key_id(key: &str) -> String {
+    key.split(',')
+        .map(|pattern| {
+            pattern
+                .trim()
+                .replace('*', "any")
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("+")
+}
+
+/// The schedule of a job in the `cron-jobs` section of the `lucky.yaml`
+///
+/// A schedule is either a cron expression, such as `0 */5 * * * *`, or an interval, such as
+/// `@every 5m`. Intervals are lined up with the Unix epoch, so `@every 1h` runs at the top of every
+/// hour.
+#[derive(Debug, Clone)]
+pub(crate) enum JobSchedule {
+    Cron(cron::Schedule),
+    Interval(std::time::Duration),
+}
+
+impl JobSchedule {
+    /// Get the first time that the job is scheduled after the given time
+    pub fn after(&self, time: &DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            JobSchedule::Cron(schedule) => schedule.after(time).next(),
+            JobSchedule::Interval(interval) => {
+                let interval = i64::try_from(interval.as_secs()).ok()?.max(1);
+                let next = (time.timestamp() / interval + 1).checked_mul(interval)?;
+                Local.timestamp_opt(next, 0).single()
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for JobSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let interval = match s.trim().strip_prefix("@every") {
+            Some(interval) => interval.trim(),
+            None => {
+                return s
+                    .parse()
+                    .map(JobSchedule::Cron)
+                    .map_err(|e| format_err!("Could not parse cron schedule {:?}: {}", s, e))
+            }
+        };
+
+        // Parse intervals such as `90s` or `1h30m`
+        let mut seconds = 0u64;
+        let mut number = String::new();
+        for c in interval.chars().filter(|c| !c.is_whitespace()) {
+            if c.is_ascii_digit() {
+                number.push(c);
+                continue;
+            }
+
+            let unit = match c {
+                's' => 1,
+                'm' => 60,
+                'h' => 60 * 60,
+                'd' => 60 * 60 * 24,
+                _ => anyhow::bail!("Invalid unit in interval {:?}: {}", s, c),
+            };
+            let value: u64 = number
+                .parse()
+                .map_err(|_| format_err!("Missing number before unit in interval: {:?}", s))?;
+            seconds = value
+                .checked_mul(unit)
+                .and_then(|x| seconds.checked_add(x))
+                .ok_or_else(|| format_err!("Interval is too long: {:?}", s))?;
+            number.clear();
+        }
+        if !number.is_empty() {
+            anyhow::bail!("Missing unit after number in interval: {:?}", s);
+        }
+        if seconds == 0 {
+            anyhow::bail!("Interval must be at least one second: {:?}", s);
+        }
+
+        // Make sure that the times of the schedule can be represented
+        let schedule = JobSchedule::Interval(std::time::Duration::from_secs(seconds));
+        if schedule.after(&Local::now()).is_none() {
+            anyhow::bail!("Interval is too long: {:?}", s);
+        }
+
+        Ok(schedule)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// A container declared in the `lucky.yaml` file
+pub(crate) struct ContainerSpec {
+    /// The image to run, including its tag or digest
+    pub image: String,
+    /// The Juju resource to load the image from instead of pulling it. The resource must be an
+    /// image archive, such as one saved with `docker save`, that contains `image`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_resource: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entrypoint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// Port bindings in the `host_port:container_port/protocol` format
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<PortSpec>,
+    /// Volumes in the `source:target` format, optionally followed by `:ro` to mount the volume
+    /// read-only or `:rw` for the default read-write mount
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes: Vec<String>,
+    /// Templated files that are rendered on the host and mounted read-only into the container
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<FileSpec>,
+    /// Host devices to add to the container in the `host_path[:container_path][:permissions]`
+    /// format
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<String>,
+    /// The NVIDIA GPUs to add to the container: `all`, a number of GPUs, or `device=` followed by a
+    /// comma separated list of GPU indexes or UUIDs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpus: Option<String>,
+    /// The network mode of the container: `bridge`, `host`, `none`, or the name of an existing
+    /// network
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+    /// The DNS servers to use instead of the container engine's defaults
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dns: Vec<String>,
+    /// Extra entries for the container's `/etc/hosts`, mapping hostname to IP address
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub extra_hosts: HashMap<String, String>,
+    /// Whether or not to attach the container to the network that Lucky shares between the
+    /// charm's containers, where the other containers can reach it by its container name
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub shared_network: bool,
+    /// The names of the containers that have to be started before this container
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /// Whether or not to restart the container when one of the containers that it depends on is
+    /// re-created or restarted
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub restart_with_dependencies: bool,
+    /// A health check that is run periodically to report the health of the container in the Juju
+    /// status
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub healthcheck: Option<HealthCheckSpec>,
+    /// When the container engine should restart the container after it exits. Defaults to
+    /// `unless-stopped`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub restart: Option<ContainerRestartPolicy>,
+    /// How the container is swapped for a new one when its configuration changes. Defaults to
+    /// `recreate`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update_strategy: Option<UpdateStrategy>,
+    /// Whether or not to forward the container's logs to the Juju debug log
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub forward_logs: bool,
+}
+
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    AsRefStr,
+    EnumString,
+    EnumVariantNames,
+)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+/// When the container engine should restart a container after it exits
+pub(crate) enum ContainerRestartPolicy {
+    /// Never restart the container
+    No,
+    /// Restart the container when it exits with a non-zero exit code
+    OnFailure,
+    /// Always restart the container. A container that was stopped by Lucky is started again when
+    /// the container engine restarts.
+    Always,
+    /// Always restart the container unless it was stopped by Lucky
+    UnlessStopped,
+}
+
+impl Default for ContainerRestartPolicy {
+    fn default() -> Self {
+        ContainerRestartPolicy::UnlessStopped
+    }
+}
+
+#[derive(
+    Deserialize,
+    Serialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    AsRefStr,
+    EnumString,
+    EnumVariantNames,
+)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+/// How a container is swapped for a new one when its configuration changes
+pub(crate) enum UpdateStrategy {
+    /// Remove the old container before pulling the image and creating the new container
+    Recreate,
+    /// Pull the image and create the new container, then stop the old container and start the new
+    /// one
+    StopStart,
+    /// Start the new container next to the old one before stopping the old container. Containers
+    /// with port bindings are updated with `stop-start` instead because the old and new container
+    /// can't bind the same host ports.
+    BlueGreen,
+}
+
+impl Default for UpdateStrategy {
+    fn default() -> Self {
+        UpdateStrategy::Recreate
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// A templated file for a container declared in the `lucky.yaml` file
+pub(crate) struct FileSpec {
+    /// The path to the template, relative to the charm directory
+    pub template: String,
+    /// The path in the container to mount the rendered file to
+    pub target: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// A health check for a container declared in the `lucky.yaml` file. Exactly one of `exec` or
+/// `http` must be set.
+pub(crate) struct HealthCheckSpec {
+    /// A command to run in the container. The check passes if the command exits zero.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exec: Option<Vec<String>>,
+    /// A URL to request from the host. The check passes if the response has a success status.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<String>,
+    /// The number of seconds between checks
+    #[serde(default = "default_health_check_interval")]
+    pub interval: u64,
+    /// The number of checks in a row that have to fail before the container is unhealthy
+    #[serde(default = "default_health_check_retries")]
+    pub retries: u32,
+}
+
+impl Default for HealthCheckSpec {
+    fn default() -> Self {
+        HealthCheckSpec {
+            exec: None,
+            http: None,
+            interval: default_health_check_interval(),
+            retries: default_health_check_retries(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+/// A port binding of a container declared in the `lucky.yaml` file
+pub(crate) enum PortSpec {
+    /// A port binding in the `host_port:container_port/protocol` format
+    Binding(String),
+    /// A port binding with options
+    Detailed {
+        /// The port binding in the `host_port:container_port/protocol` format
+        port: String,
+        /// Whether or not to open the host port in Juju. Defaults to `true`.
+        #[serde(default = "default_true")]
+        open: bool,
+    },
+}
+
+impl PortSpec {
+    /// Get the port binding
+    pub fn binding(&self) -> &str {
+        match self {
+            PortSpec::Binding(port) | PortSpec::Detailed { port, .. } => port,
+        }
+    }
+
+    /// Whether or not the host port should be opened in Juju
+    pub fn open(&self) -> bool {
+        match self {
+            PortSpec::Binding(_) => true,
+            PortSpec::Detailed { open, .. } => *open,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// A Juju action defined in the `lucky.yaml` file
+pub(crate) struct CharmAction {
+    /// The number of seconds that the action may run before it is cancelled. Optional.
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    /// The scripts to run for the action
+    pub scripts: Vec<CharmScript>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// The relation data keys of a relation that are kept in sync with the unit key-value store
+pub(crate) struct RelationKvSync {
+    /// The keys of the remote units' relation data to copy into the key-value store, mapped to the
+    /// key-value store key to copy them to
+    #[serde(default)]
+    pub import: HashMap<String, String>,
+    /// The key-value store keys to publish in this unit's relation data, mapped to the relation
+    /// data key to publish them as
+    #[serde(default)]
+    pub export: HashMap<String, String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -137,10 +755,145 @@ pub(crate) struct CharmScript {
     #[serde(rename = "async")]
     #[serde(default = "default_false")]
     pub is_async: bool,
+    /// The ID that other scripts in the same list use to depend on this script
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// The IDs of the scripts that this script runs after, whether or not they succeed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub after: Vec<String>,
+    /// The IDs of the scripts that this script runs after and that have to succeed for this script
+    /// to run
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub requires: Vec<String>,
+    /// The number of seconds that a host script may run before it is killed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    /// Templated environment variables that are set for this script
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// The statuses that the exit codes of the script set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_codes: Option<ExitCodeStatuses>,
+    /// The interpreter that runs a host script, such as `python3`, by name or by path
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interpreter: Option<String>,
+    /// The apt package that is installed if the interpreter isn't installed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interpreter_package: Option<String>,
+    /// A script that is run when this script fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_failure: Option<Box<CharmScript>>,
+    /// A condition that has to be true for the script to run. See `condition::Condition`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+    /// The number of times to retry the script if it fails
+    #[serde(default)]
+    pub retries: u32,
+    /// The number of seconds to wait before the first retry. The delay doubles after every retry.
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay: u64,
+    /// The user to run a host script as
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_as: Option<String>,
+    /// The resource limits of a host script
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limits: Option<ScriptLimits>,
     #[serde(flatten)]
     pub script_type: CharmScriptType,
 }
 
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+/// The resource limits of a host script
+///
+/// The memory, CPU, and process limits are set on a systemd scope that the script is run in, so
+/// that they apply to all of the script's processes together. The open file limit is a resource
+/// limit of each process.
+pub(crate) struct ScriptLimits {
+    /// The maximum memory, in bytes or with a `K`, `M`, `G`, or `T` suffix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<String>,
+    /// The percent of one CPU that the script may use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_percent: Option<u32>,
+    /// The maximum number of processes and threads that the script may run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_processes: Option<u64>,
+    /// The maximum number of files that each process of the script may have open
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_open_files: Option<u64>,
+}
+
+impl ScriptLimits {
+    /// Get the systemd unit properties that set the limits of the scope that the script runs in
+    pub fn scope_properties(&self) -> Vec<String> {
+        let mut properties = Vec::new();
+        if let Some(memory) = &self.memory {
+            properties.push(format!("MemoryMax={}", memory));
+        }
+        if let Some(cpu_percent) = self.cpu_percent {
+            properties.push(format!("CPUQuota={}%", cpu_percent));
+        }
+        if let Some(max_processes) = self.max_processes {
+            properties.push(format!("TasksMax={}", max_processes));
+        }
+        properties
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+/// The statuses that the exit codes of a script set, so that the script doesn't have to set its
+/// status itself
+///
+/// When a script exits with one of the exit codes, its status is set to the exit code's status,
+/// with the last line of its output as the message, and the script doesn't fail. Any other non-zero
+/// exit code fails the script, with the last line of its output in the error.
+pub(crate) enum ExitCodeStatuses {
+    /// Whether to use the default exit codes: `0` is active, `10` is waiting, and `20` is blocked
+    Default(bool),
+    /// The statuses of the given exit codes
+    Custom(BTreeMap<i32, ExitCodeState>),
+}
+
+impl ExitCodeStatuses {
+    /// Get the state that the given exit code sets, if any
+    pub fn state(&self, exit_code: i32) -> Option<ScriptState> {
+        match self {
+            ExitCodeStatuses::Default(false) => None,
+            ExitCodeStatuses::Default(true) => match exit_code {
+                0 => Some(ScriptState::Active),
+                10 => Some(ScriptState::Waiting),
+                20 => Some(ScriptState::Blocked),
+                _ => None,
+            },
+            ExitCodeStatuses::Custom(states) => states.get(&exit_code).map(|&x| x.into()),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+/// A status that an exit code of a script sets
+pub(crate) enum ExitCodeState {
+    Active,
+    Waiting,
+    Maintenance,
+    Blocked,
+}
+
+impl From<ExitCodeState> for ScriptState {
+    fn from(state: ExitCodeState) -> Self {
+        match state {
+            ExitCodeState::Active => ScriptState::Active,
+            ExitCodeState::Waiting => ScriptState::Waiting,
+            ExitCodeState::Maintenance => ScriptState::Maintenance,
+            ExitCodeState::Blocked => ScriptState::Blocked,
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(untagged)]
 #[serde(rename_all = "kebab-case")]
@@ -185,10 +938,172 @@ pub(crate) enum CharmScriptType {
     },
 }
 
+impl CharmScript {
+    /// Iterate over this script followed by its `on-failure` scripts
+    pub fn with_on_failure(&self) -> impl Iterator<Item = &CharmScript> {
+        std::iter::successors(Some(self), |x| x.on_failure.as_deref())
+    }
+
+    /// Get a human readable name for the script, such as `host_scripts/configure.sh`
+    pub fn name(&self) -> String {
+        match &self.script_type {
+            CharmScriptType::Host { host_script, .. } => format!("host_scripts/{}", host_script),
+            CharmScriptType::InlineHost { .. } => "inline host script".into(),
+            CharmScriptType::Container {
+                container_script, ..
+            } => format!("container_scripts/{}", container_script),
+            CharmScriptType::InlineContainer { .. } => "inline container script".into(),
+        }
+    }
+
+    /// Get the ID of the script, or its name if it doesn't have one
+    pub fn id_or_name(&self) -> String {
+        self.id.clone().unwrap_or_else(|| self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A dependency of a script on another script in the same list of scripts
+pub(crate) struct ScriptDependency {
+    /// The index of the script that is depended on
+    pub index: usize,
+    /// Whether the dependent script is skipped when this one fails, which is the case for
+    /// `requires` but not `after`
+    pub required: bool,
+}
+
+#[derive(Error, Debug, Clone, PartialEq)]
+/// A problem with the dependencies between the scripts in a list of scripts
+pub(crate) enum ScriptDependencyError {
+    #[error("Script {script} depends on script {dependency}, which is not in the same list")]
+    UnknownDependency { script: String, dependency: String },
+    #[error("More than one script has the ID {0}")]
+    DuplicateId(String),
+    #[error("Scripts depend on each other in a cycle: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+}
+
+/// Get the dependencies of each script in a list of scripts, checking that the scripts depended
+/// on exist and that there are no cycles
+///
+/// Returns `None` if none of the scripts have dependencies, in which case they are run in order.
+pub(crate) fn script_dependencies(
+    scripts: &[CharmScript],
+) -> Result<Option<Vec<Vec<ScriptDependency>>>, ScriptDependencyError> {
+    if scripts
+        .iter()
+        .all(|x| x.after.is_empty() && x.requires.is_empty())
+    {
+        return Ok(None);
+    }
+
+    let mut ids = HashMap::new();
+    for (i, script) in scripts.iter().enumerate() {
+        if let Some(id) = &script.id {
+            if ids.insert(id.as_str(), i).is_some() {
+                return Err(ScriptDependencyError::DuplicateId(id.clone()));
+            }
+        }
+    }
+
+    let mut dependencies = Vec::with_capacity(scripts.len());
+    for script in scripts {
+        let mut script_dependencies = Vec::new();
+        let all_dependencies = script
+            .after
+            .iter()
+            .map(|x| (x, false))
+            .chain(script.requires.iter().map(|x| (x, true)));
+        for (dependency, required) in all_dependencies {
+            let index = *ids.get(dependency.as_str()).ok_or_else(|| {
+                ScriptDependencyError::UnknownDependency {
+                    script: script.id_or_name(),
+                    dependency: dependency.clone(),
+                }
+            })?;
+            script_dependencies.push(ScriptDependency { index, required });
+        }
+        dependencies.push(script_dependencies);
+    }
+
+    let mut marks = vec![VisitMark::New; scripts.len()];
+    let mut path = Vec::new();
+    for i in 0..scripts.len() {
+        if let Some(cycle) = find_dependency_cycle(i, &dependencies, &mut marks, &mut path) {
+            return Err(ScriptDependencyError::Cycle(
+                cycle
+                    .into_iter()
+                    .filter_map(|x| scripts.get(x))
+                    .map(CharmScript::id_or_name)
+                    .collect(),
+            ));
+        }
+    }
+
+    Ok(Some(dependencies))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// The state of a script in the depth-first search for dependency cycles
+enum VisitMark {
+    New,
+    Visiting,
+    Done,
+}
+
+/// Search the dependencies of a script for a cycle, returning the indexes of the scripts in the
+/// cycle if there is one
+fn find_dependency_cycle(
+    index: usize,
+    dependencies: &[Vec<ScriptDependency>],
+    marks: &mut [VisitMark],
+    path: &mut Vec<usize>,
+) -> Option<Vec<usize>> {
+    match marks.get(index) {
+        Some(VisitMark::New) => (),
+        Some(VisitMark::Visiting) => {
+            // The cycle is the part of the path since this script was first visited
+            let start = path.iter().position(|x| *x == index).unwrap_or(0);
+            let mut cycle: Vec<usize> = path.iter().skip(start).copied().collect();
+            cycle.push(index);
+            return Some(cycle);
+        }
+        Some(VisitMark::Done) | None => return None,
+    }
+
+    if let Some(mark) = marks.get_mut(index) {
+        *mark = VisitMark::Visiting;
+    }
+    path.push(index);
+    for dependency in dependencies.get(index).into_iter().flatten() {
+        if let Some(cycle) = find_dependency_cycle(dependency.index, dependencies, marks, path) {
+            return Some(cycle);
+        }
+    }
+    path.pop();
+    if let Some(mark) = marks.get_mut(index) {
+        *mark = VisitMark::Done;
+    }
+
+    None
+}
+
 //
 // Helpers
 //
 
+fn default_health_check_interval() -> u64 {
+    30
+}
+
+fn default_health_check_retries() -> u32 {
+    3
+}
+
+fn default_retry_delay() -> u64 {
+    5
+}
+
 fn default_true() -> bool {
     true
 }
@@ -0,0 +1,305 @@
+//! The `when` conditions of the scripts in the `lucky.yaml`
+//!
+//! A condition is an expression that decides whether or not a script runs, such as:
+//!
+//! * `is-leader`
+//! * `config.enable_feature == true`
+//! * `kv.installed != "true" && !is-leader`
+//! * `hook == "config-changed" || (config.port != 80 && kv.configured == "true")`
+//!
+//! The values that can be referenced are `is-leader`, `hook` (the name of the hook that the script
+//! is run for), `config.<option>` (a charm config option, which is `null` if unset), and
+//! `kv.<key>` (a key in the unit key-value store, which is `null` if unset). Values are compared
+//! with `==` and `!=` to other values or to string, number, boolean, and `null` literals. A value
+//! that isn't compared is true unless it is `false`, `null`, `0`, or an empty string. Conditions
+//! can be combined with `&&`, `||`, `!`, and parentheses.
+
+use anyhow::{bail, format_err};
+use serde_json::Value as JsonValue;
+
+use std::str::FromStr;
+
+/// The values that a condition can reference, which are looked up as they are needed
+pub(crate) trait ConditionContext {
+    /// Whether or not the unit is the leader
+    fn is_leader(&self) -> anyhow::Result<bool>;
+    /// The name of the hook that the script is run for
+    fn hook(&self) -> &str;
+    /// Get a charm config option, or `null` if it isn't set
+    fn config(&self, key: &str) -> anyhow::Result<JsonValue>;
+    /// Get a key in the unit key-value store
+    fn kv(&self, key: &str) -> anyhow::Result<Option<String>>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A parsed script condition
+pub(crate) enum Condition {
+    /// A value that is checked for truthiness
+    Value(Operand),
+    Eq(Operand, Operand),
+    Ne(Operand, Operand),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A value referenced in a condition
+pub(crate) enum Operand {
+    IsLeader,
+    Hook,
+    Config(String),
+    Kv(String),
+    Literal(JsonValue),
+}
+
+impl Condition {
+    /// Evaluate the condition
+    pub fn evaluate(&self, context: &dyn ConditionContext) -> anyhow::Result<bool> {
+        Ok(match self {
+            Condition::Value(operand) => is_truthy(&operand.evaluate(context)?),
+            Condition::Eq(left, right) => left.evaluate(context)? == right.evaluate(context)?,
+            Condition::Ne(left, right) => left.evaluate(context)? != right.evaluate(context)?,
+            Condition::Not(condition) => !condition.evaluate(context)?,
+            // The right side is only evaluated if it is needed
+            Condition::And(left, right) => left.evaluate(context)? && right.evaluate(context)?,
+            Condition::Or(left, right) => left.evaluate(context)? || right.evaluate(context)?,
+        })
+    }
+}
+
+impl Operand {
+    fn evaluate(&self, context: &dyn ConditionContext) -> anyhow::Result<JsonValue> {
+        Ok(match self {
+            Operand::IsLeader => JsonValue::Bool(context.is_leader()?),
+            Operand::Hook => JsonValue::String(context.hook().into()),
+            Operand::Config(key) => context.config(key)?,
+            Operand::Kv(key) => context.kv(key)?.map_or(JsonValue::Null, JsonValue::String),
+            Operand::Literal(value) => value.clone(),
+        })
+    }
+}
+
+/// Whether or not a value that isn't compared to anything counts as true
+fn is_truthy(value: &JsonValue) -> bool {
+    match value {
+        JsonValue::Null => false,
+        JsonValue::Bool(x) => *x,
+        JsonValue::Number(x) => x.as_f64().map_or(true, |x| x.abs() > 0.0),
+        JsonValue::String(x) => !x.is_empty(),
+        JsonValue::Array(_) | JsonValue::Object(_) => true,
+    }
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+        };
+        let condition = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            bail!("Unexpected {} in condition: {}", token, s);
+        }
+
+        Ok(condition)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LeftParen,
+    RightParen,
+    And,
+    Or,
+    Not,
+    Eq,
+    Ne,
+    Str(String),
+    Number(JsonValue),
+    Ident(String),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::LeftParen => write!(f, "`(`"),
+            Token::RightParen => write!(f, "`)`"),
+            Token::And => write!(f, "`&&`"),
+            Token::Or => write!(f, "`||`"),
+            Token::Not => write!(f, "`!`"),
+            Token::Eq => write!(f, "`==`"),
+            Token::Ne => write!(f, "`!=`"),
+            Token::Str(x) => write!(f, "string {:?}", x),
+            Token::Number(x) => write!(f, "number {}", x),
+            Token::Ident(x) => write!(f, "`{}`", x),
+        }
+    }
+}
+
+/// Split a condition into tokens
+fn tokenize(s: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        // Get the operator made of this character and the next one, if any
+        let operator = match (c, chars.peek()) {
+            ('&', Some('&')) => Some(Token::And),
+            ('|', Some('|')) => Some(Token::Or),
+            ('=', Some('=')) => Some(Token::Eq),
+            ('!', Some('=')) => Some(Token::Ne),
+            _ => None,
+        };
+        if let Some(operator) = operator {
+            chars.next();
+            tokens.push(operator);
+            continue;
+        }
+
+        let token =
+            match c {
+                c if c.is_whitespace() => continue,
+                '(' => Token::LeftParen,
+                ')' => Token::RightParen,
+                '!' => Token::Not,
+                '"' | '\'' => {
+                    let quote = c;
+                    let mut string = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('\\') => string.extend(chars.next()),
+                            Some(c) if c == quote => break,
+                            Some(c) => string.push(c),
+                            None => bail!("Unterminated string in condition: {}", s),
+                        }
+                    }
+                    Token::Str(string)
+                }
+                c if c.is_ascii_digit() || c == '-' => {
+                    let mut number = c.to_string();
+                    while let Some(&c) = chars.peek().filter(|x| x.is_ascii_digit() || **x == '.') {
+                        number.push(c);
+                        chars.next();
+                    }
+                    Token::Number(serde_json::from_str(&number).map_err(|_| {
+                        format_err!("Invalid number {} in condition: {}", number, s)
+                    })?)
+                }
+                c if c.is_alphabetic() || c == '_' => {
+                    let mut ident = c.to_string();
+                    while let Some(&c) = chars
+                        .peek()
+                        .filter(|x| x.is_alphanumeric() || "_-.".contains(**x))
+                    {
+                        ident.push(c);
+                        chars.next();
+                    }
+                    Token::Ident(ident)
+                }
+                c => bail!("Unexpected character {:?} in condition: {}", c, s),
+            };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// A recursive descent parser for conditions
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        token
+    }
+
+    /// Consume the next token if it is the given token
+    fn consume(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_or(&mut self) -> anyhow::Result<Condition> {
+        let mut condition = self.parse_and()?;
+        while self.consume(&Token::Or) {
+            condition = Condition::Or(Box::new(condition), Box::new(self.parse_and()?));
+        }
+        Ok(condition)
+    }
+
+    fn parse_and(&mut self) -> anyhow::Result<Condition> {
+        let mut condition = self.parse_not()?;
+        while self.consume(&Token::And) {
+            condition = Condition::And(Box::new(condition), Box::new(self.parse_not()?));
+        }
+        Ok(condition)
+    }
+
+    fn parse_not(&mut self) -> anyhow::Result<Condition> {
+        if self.consume(&Token::Not) {
+            return Ok(Condition::Not(Box::new(self.parse_not()?)));
+        }
+
+        if self.consume(&Token::LeftParen) {
+            let condition = self.parse_or()?;
+            if !self.consume(&Token::RightParen) {
+                bail!("Missing `)` in condition");
+            }
+            return Ok(condition);
+        }
+
+        let left = self.parse_operand()?;
+        if self.consume(&Token::Eq) {
+            Ok(Condition::Eq(left, self.parse_operand()?))
+        } else if self.consume(&Token::Ne) {
+            Ok(Condition::Ne(left, self.parse_operand()?))
+        } else {
+            Ok(Condition::Value(left))
+        }
+    }
+
+    fn parse_operand(&mut self) -> anyhow::Result<Operand> {
+        Ok(match self.next() {
+            Some(Token::Str(x)) => Operand::Literal(JsonValue::String(x.clone())),
+            Some(Token::Number(x)) => Operand::Literal(x.clone()),
+            Some(Token::Ident(ident)) => match ident.as_str() {
+                "is-leader" => Operand::IsLeader,
+                "hook" => Operand::Hook,
+                "true" => Operand::Literal(JsonValue::Bool(true)),
+                "false" => Operand::Literal(JsonValue::Bool(false)),
+                "null" => Operand::Literal(JsonValue::Null),
+                ident => {
+                    if let Some(key) = ident.strip_prefix("config.").filter(|x| !x.is_empty()) {
+                        Operand::Config(key.into())
+                    } else if let Some(key) = ident.strip_prefix("kv.").filter(|x| !x.is_empty()) {
+                        Operand::Kv(key.into())
+                    } else {
+                        bail!(
+                            "Unknown value `{}` in condition, expected `is-leader`, `hook`, \
+                            `config.<option>`, or `kv.<key>`",
+                            ident
+                        )
+                    }
+                }
+            },
+            Some(token) => bail!("Expected a value in condition but found {}", token),
+            None => bail!("Condition ended where a value was expected"),
+        })
+    }
+}
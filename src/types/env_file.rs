@@ -0,0 +1,70 @@
+//! Rendering and parsing of the env files that container environments can be loaded from
+//!
+//! The format is the same `KEY=value` per line format that `docker --env-file` uses, except that
+//! backslashes and newlines in values are escaped as `\\` and `\n` so that values can span
+//! multiple lines.
+
+use anyhow::format_err;
+
+use std::collections::HashMap;
+
+/// Render environment variables to the contents of an env file
+///
+/// The variables are sorted by name so that the output is stable.
+pub(crate) fn render(vars: &HashMap<String, String>) -> String {
+    let mut vars: Vec<_> = vars.iter().collect();
+    vars.sort();
+
+    let mut content = String::new();
+    for (key, value) in vars {
+        content.push_str(key);
+        content.push('=');
+        content.push_str(&value.replace('\\', "\\\\").replace('\n', "\\n"));
+        content.push('\n');
+    }
+
+    content
+}
+
+/// Parse the contents of an env file into a list of variables
+///
+/// Empty lines and lines starting with `#` are ignored.
+pub(crate) fn parse(content: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut vars = vec![];
+
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap_or("");
+        let value = parts
+            .next()
+            .ok_or_else(|| format_err!("Invalid env file: line {} is missing `=`", i + 1))?;
+
+        vars.push((key.to_owned(), unescape(value)));
+    }
+
+    Ok(vars)
+}
+
+/// Reverse the escaping done by `render`
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => unescaped.push('\n'),
+                Some(other) => unescaped.push(other),
+                None => unescaped.push('\\'),
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+
+    unescaped
+}
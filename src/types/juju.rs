@@ -1,5 +1,8 @@
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
 
 /// The list of the normal Juju hook names
 pub(crate) const JUJU_NORMAL_HOOKS: &[&str] = &[
@@ -23,10 +26,122 @@ pub(crate) const JUJU_RELATION_HOOKS: &[&str] = &[
     "{}-relation-broken",
 ];
 
+/// The list of the Juju workload container hooks for Kubernetes sidecar charms with the `{}` where
+/// the container name should be
+pub(crate) const JUJU_PEBBLE_HOOKS: &[&str] = &[
+    "{}-pebble-ready",
+    "{}-pebble-custom-notice",
+    "{}-pebble-check-failed",
+    "{}-pebble-check-recovered",
+];
+
 #[allow(dead_code)]
 /// The list of the Juju storage hooks with the `{}` where the storage name should be
 pub(crate) const JUJU_STORAGE_HOOKS: &[&str] = &["{}-storage-attached", "{}-storage-detaching"];
 
+/// The version of the Juju agent that is running the charm
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub(crate) struct JujuVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl JujuVersion {
+    /// Create a new Juju version
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        JujuVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Whether or not this version of Juju supports the given feature
+    pub fn supports(self, feature: JujuFeature) -> bool {
+        self >= feature.min_version()
+    }
+}
+
+impl fmt::Display for JujuVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for JujuVersion {
+    type Err = anyhow::Error;
+
+    /// Parse a Juju version such as `2.7.6`, `2.7.6-bionic-amd64`, or `2.9-rc1`
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        let re = Regex::new(r"^(?P<major>[0-9]+)\.(?P<minor>[0-9]+)(\.(?P<patch>[0-9]+))?")
+            .expect("Could not compile regex");
+
+        if let Some(captures) = re.captures(version.trim()) {
+            let get_number = |name| {
+                captures
+                    .name(name)
+                    .map_or(Ok(0), |x| x.as_str().parse())
+                    .map_err(|_| anyhow::format_err!("Version number too large: {}", version))
+            };
+
+            Ok(JujuVersion {
+                major: get_number("major")?,
+                minor: get_number("minor")?,
+                patch: get_number("patch")?,
+            })
+        } else {
+            Err(anyhow::format_err!(
+                "Could not parse Juju version: {}",
+                version
+            ))
+        }
+    }
+}
+
+/// A Juju feature that is only available in certain versions of Juju
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub(crate) enum JujuFeature {
+    /// The `--bind-address` flag to the `network-get` hook tool
+    NetworkGetBindAddress,
+    /// Juju secrets and the `secret-*` hook tools
+    Secrets,
+    /// Running charms through the `dispatch` script instead of individual hook files
+    Dispatch,
+}
+
+impl JujuFeature {
+    /// All of the version dependent Juju features
+    pub const ALL: &'static [JujuFeature] = &[
+        JujuFeature::NetworkGetBindAddress,
+        JujuFeature::Secrets,
+        JujuFeature::Dispatch,
+    ];
+
+    /// The minimum version of Juju that supports this feature
+    pub fn min_version(self) -> JujuVersion {
+        match self {
+            JujuFeature::NetworkGetBindAddress => JujuVersion::new(2, 1, 0),
+            JujuFeature::Secrets => JujuVersion::new(3, 0, 2),
+            JujuFeature::Dispatch => JujuVersion::new(2, 8, 0),
+        }
+    }
+}
+
+impl fmt::Display for JujuFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                JujuFeature::NetworkGetBindAddress => "network-get --bind-address",
+                JujuFeature::Secrets => "secrets",
+                JujuFeature::Dispatch => "dispatch",
+            }
+        )
+    }
+}
+
 /// The charm metadata as defined in a charm's `metadata.yaml` file
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "kebab-case")]
@@ -71,9 +186,21 @@ pub(crate) struct CharmMetadata {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub storage: Option<HashMap<String, StorageDef>>,
+
+    /// The workload containers of a Kubernetes sidecar charm
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub containers: Option<HashMap<String, ContainerDef>>,
     // TODO: Resources, payloads, and extra bindings
 }
 
+/// The definition of a workload container of a Kubernetes sidecar charm in the `metadata.yaml` file
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct ContainerDef {
+    /// The name of the OCI image resource used for the container
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resource: Option<String>,
+}
+
 /// The definition of a relation in the `metadata.yaml` file
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) struct RelationDef {